@@ -0,0 +1,71 @@
+// Generates the table of the files of the romfs (see `src/fs/romfs.rs`) from the `romfs` directory, every file of it
+// is built in the kernel with `include_bytes!`. The symbolic links in the directory are followed, so a file kept
+// somewhere else in the tree (like a user program) can be added without a copy
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The permissions of the nodes of the romfs, nothing can write to it
+const DIRECTORY_MODE: u16 = 0o555;
+const PROGRAM_MODE: u16 = 0o555;
+const FILE_MODE: u16 = 0o444;
+
+/// Adds the entries under `directory` to `table`, `prefix` is the path of the directory in the romfs
+fn add_entries(table: &mut String, directory: &Path, prefix: &str) {
+    let mut entries: Vec<PathBuf> = fs::read_dir(directory)
+        .unwrap_or_else(|error| panic!("Failed to read {}: {}", directory.display(), error))
+        .map(|entry| entry.unwrap().path())
+        .collect();
+
+    // The table is the same from one build to the next, whatever order the host gives the entries in
+    entries.sort();
+
+    for path in entries {
+        let name = path.file_name().unwrap().to_str().expect("The names in the romfs must be UTF-8");
+        let romfs_path = format!("{}/{}", prefix, name);
+
+        if path.is_dir() {
+            writeln!(table, "    RomEntry {{ path: {:?}, mode: 0o{:o}, content: None }},", romfs_path, DIRECTORY_MODE)
+                .unwrap();
+
+            add_entries(table, &path, &romfs_path);
+            continue;
+        }
+
+        // The programs are the only files that can be executed, they're told apart by the magic number of ELF
+        let content = fs::read(&path).unwrap_or_else(|error| panic!("Failed to read {}: {}", path.display(), error));
+        let mode = if content.starts_with(b"\x7FELF") { PROGRAM_MODE } else { FILE_MODE };
+        let absolute = fs::canonicalize(&path).unwrap();
+
+        // Cargo only looks for changes in the directory itself, not in the targets of the links
+        println!("cargo:rerun-if-changed={}", absolute.display());
+
+        writeln!(
+            table,
+            "    RomEntry {{ path: {:?}, mode: 0o{:o}, content: Some(include_bytes!({:?})) }},",
+            romfs_path,
+            mode,
+            absolute.to_str().unwrap()
+        )
+        .unwrap();
+    }
+}
+
+fn main() {
+    let source = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("romfs");
+    let output = Path::new(&env::var("OUT_DIR").unwrap()).join("romfs.rs");
+
+    let mut table = String::from("static ENTRIES: &[RomEntry] = &[\n");
+
+    if source.is_dir() {
+        add_entries(&mut table, &source, "");
+    }
+
+    table.push_str("];\n");
+
+    fs::write(&output, table).unwrap();
+
+    println!("cargo:rerun-if-changed=romfs");
+    println!("cargo:rerun-if-changed=build.rs");
+}
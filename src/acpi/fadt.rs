@@ -0,0 +1,121 @@
+use x86_64::PhysAddr;
+use super::SdtHeader;
+
+/// The offsets of the fields of the FADT used by the kernel, from the start of the table
+const OFFSET_DSDT: usize = 40;
+const OFFSET_SCI_INTERRUPT: usize = 46;
+const OFFSET_SMI_COMMAND: usize = 48;
+const OFFSET_ACPI_ENABLE: usize = 52;
+const OFFSET_ACPI_DISABLE: usize = 53;
+const OFFSET_PM1A_EVENT_BLOCK: usize = 56;
+const OFFSET_PM1B_EVENT_BLOCK: usize = 60;
+const OFFSET_PM1A_CONTROL_BLOCK: usize = 64;
+const OFFSET_PM1B_CONTROL_BLOCK: usize = 68;
+const OFFSET_PM_TIMER_BLOCK: usize = 76;
+const OFFSET_PM1_CONTROL_LENGTH: usize = 89;
+const OFFSET_CENTURY: usize = 108;
+const OFFSET_BOOT_FLAGS: usize = 109;
+const OFFSET_FLAGS: usize = 112;
+const OFFSET_RESET_REGISTER: usize = 116;
+const OFFSET_RESET_VALUE: usize = 128;
+const OFFSET_X_DSDT: usize = 140;
+
+/// Set in the flags when the reset register can be used to reset the machine
+const FLAG_RESET_REGISTER: u32 = 1 << 10;
+
+/// Set in the boot flags when the machine has an 8042 PS/2 controller, see [`crate::ps2`]
+const BOOT_FLAG_8042: u16 = 1 << 1;
+
+/// A Generic Address Structure, the way ACPI points at a register in any address space
+#[derive(Debug, Copy, Clone)]
+#[repr(C, packed)]
+pub struct GenericAddress {
+    /// 0 for memory, 1 for I/O ports
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64
+}
+
+/// The FADT (Fixed ACPI Description Table), with the fixed registers of the power management hardware
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone)]
+pub struct Fadt {
+    /// The DSDT, the table with the AML code of the machine (like the `\_S5` object needed to power it off)
+    pub dsdt: PhysAddr,
+    /// The legacy IRQ the SCI (System Control Interrupt) is connected to
+    pub sci_interrupt: u16,
+    /// The port where [`Fadt::acpi_enable`] is written to take control of the power management from
+    /// the firmware, 0 if the machine is always in ACPI mode
+    pub smi_command_port: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    /// The I/O ports of the registers of the power management, the B blocks are 0 when they don't exist
+    pub pm1a_event_block: u32,
+    pub pm1b_event_block: u32,
+    pub pm1a_control_block: u32,
+    pub pm1b_control_block: u32,
+    pub pm1_control_length: u8,
+    /// The I/O port of the ACPI power management timer, 0 if there's none
+    pub pm_timer_block: u32,
+    /// The index of the century in the CMOS, 0 if there's none
+    pub century: u8,
+    /// The register written with [`Fadt::reset_value`] to reset the machine, if it can be used
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+    /// The IA-PC boot architecture flags, 0 on ACPI 1.0
+    pub boot_flags: u16
+}
+
+impl Fadt {
+    /// Whatever the machine has an 8042 PS/2 controller. The flag only exists since ACPI 2.0, so
+    /// the older machines are assumed to have one
+    #[allow(dead_code)]
+    pub fn has_8042(&self) -> bool {
+        self.boot_flags == 0 || self.boot_flags & BOOT_FLAG_8042 != 0
+    }
+
+    /// Parses the FADT at `table`, the fields newer than the revision of the table are left at 0
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that `table` is a valid FADT
+    pub(super) unsafe fn parse(table: PhysAddr) -> Self {
+        let header: SdtHeader = super::read_table(table, 0);
+        let length = header.length as usize;
+
+        // Reads a field only if the table is long enough to have it
+        let field = |offset: usize, size: usize| offset + size <= length;
+
+        let flags: u32 = if field(OFFSET_FLAGS, 4) { super::read_table(table, OFFSET_FLAGS) } else { 0 };
+
+        // The 64 bits address of the DSDT replaces the 32 bits one when it's there
+        let mut dsdt = super::read_table::<u32>(table, OFFSET_DSDT) as u64;
+
+        if field(OFFSET_X_DSDT, 8) && super::read_table::<u64>(table, OFFSET_X_DSDT) != 0 {
+            dsdt = super::read_table(table, OFFSET_X_DSDT);
+        }
+
+        let reset_register = (flags & FLAG_RESET_REGISTER != 0 && field(OFFSET_RESET_VALUE, 1))
+            .then(|| super::read_table::<GenericAddress>(table, OFFSET_RESET_REGISTER));
+
+        Fadt {
+            dsdt: PhysAddr::new(dsdt),
+            sci_interrupt: super::read_table(table, OFFSET_SCI_INTERRUPT),
+            smi_command_port: super::read_table(table, OFFSET_SMI_COMMAND),
+            acpi_enable: super::read_table(table, OFFSET_ACPI_ENABLE),
+            acpi_disable: super::read_table(table, OFFSET_ACPI_DISABLE),
+            pm1a_event_block: super::read_table(table, OFFSET_PM1A_EVENT_BLOCK),
+            pm1b_event_block: super::read_table(table, OFFSET_PM1B_EVENT_BLOCK),
+            pm1a_control_block: super::read_table(table, OFFSET_PM1A_CONTROL_BLOCK),
+            pm1b_control_block: super::read_table(table, OFFSET_PM1B_CONTROL_BLOCK),
+            pm1_control_length: super::read_table(table, OFFSET_PM1_CONTROL_LENGTH),
+            pm_timer_block: super::read_table(table, OFFSET_PM_TIMER_BLOCK),
+            century: if field(OFFSET_CENTURY, 1) { super::read_table(table, OFFSET_CENTURY) } else { 0 },
+            reset_register,
+            reset_value: if reset_register.is_some() { super::read_table(table, OFFSET_RESET_VALUE) } else { 0 },
+            boot_flags: if field(OFFSET_BOOT_FLAGS, 2) && header.revision >= 2 { super::read_table(table, OFFSET_BOOT_FLAGS) } else { 0 }
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use core::mem::size_of;
+use x86_64::PhysAddr;
+use super::fadt::GenericAddress;
+use super::SdtHeader;
+
+/// The bits of the event timer block ID: the index of the last comparator (bits 8 to 12), whatever the counter
+/// has 64 bits and whatever the HPET can replace the PIT and the RTC (legacy replacement)
+const ID_LAST_COMPARATOR_SHIFT: u32 = 8;
+const ID_COUNTER_64BIT: u32 = 1 << 13;
+const ID_LEGACY_REPLACEMENT: u32 = 1 << 15;
+
+/// The HPET (High Precision Event Timer) table
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone)]
+pub struct Hpet {
+    /// Where the registers of the HPET are, always in memory
+    pub address: PhysAddr,
+    /// The sequence number of this HPET, the machine may have more than one
+    pub number: u8,
+    /// How many comparators (timers) the HPET has
+    pub comparators: u8,
+    pub counter_64bit: bool,
+    pub legacy_replacement: bool,
+    /// The smallest period (in ticks of the main counter) that can be set in periodic mode without losing interrupts
+    pub minimum_tick: u16
+}
+
+impl Hpet {
+    /// Parses the HPET table at `table`
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that `table` is a valid HPET table
+    pub(super) unsafe fn parse(table: PhysAddr) -> Self {
+        let start = size_of::<SdtHeader>();
+        let id: u32 = super::read_table(table, start);
+        let address: GenericAddress = super::read_table(table, start + 4);
+
+        Hpet {
+            address: PhysAddr::new(address.address),
+            number: super::read_table(table, start + 16),
+            comparators: ((id >> ID_LAST_COMPARATOR_SHIFT) & 0x1F) as u8 + 1,
+            counter_64bit: id & ID_COUNTER_64BIT != 0,
+            legacy_replacement: id & ID_LEGACY_REPLACEMENT != 0,
+            minimum_tick: super::read_table(table, start + 17)
+        }
+    }
+}
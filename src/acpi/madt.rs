@@ -0,0 +1,176 @@
+use alloc::vec::Vec;
+use core::mem::size_of;
+use x86_64::PhysAddr;
+use super::SdtHeader;
+
+/// The types of the entries of the MADT used by the kernel, the others are skipped
+const ENTRY_LOCAL_APIC: u8 = 0;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_APIC_NMI: u8 = 4;
+const ENTRY_LOCAL_APIC_ADDRESS: u8 = 5;
+const ENTRY_LOCAL_X2APIC: u8 = 9;
+
+/// Set in the flags of the MADT when the machine also has the two legacy PICs
+const FLAG_LEGACY_PICS: u32 = 1 << 0;
+
+/// The bits of the flags of a processor: it can be used right away, or it can be brought online later
+const PROCESSOR_ENABLED: u32 = 1 << 0;
+const PROCESSOR_ONLINE_CAPABLE: u32 = 1 << 1;
+
+/// The processor ID of an NMI entry that applies to every processor
+const ALL_PROCESSORS: u8 = 0xFF;
+
+/// A processor and its local APIC
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone)]
+pub struct LocalApic {
+    /// The ID the ACPI namespace uses for the processor
+    pub processor_id: u32,
+    pub apic_id: u32,
+    /// Whatever the processor can be used
+    pub enabled: bool,
+    /// Whatever a processor that isn't enabled can be brought online by the kernel
+    pub online_capable: bool
+}
+
+/// An I/O APIC, which routes the interrupts of the devices to the local APICs
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: PhysAddr,
+    /// The first global system interrupt handled by this I/O APIC
+    pub gsi_base: u32
+}
+
+/// An ISA interrupt connected to a different global system interrupt than its IRQ number, or with a
+/// different polarity or trigger mode than the ISA default
+#[derive(Debug, Copy, Clone)]
+pub struct InterruptOverride {
+    /// The IRQ on the ISA bus
+    pub source: u8,
+    pub gsi: u32,
+    /// The polarity (bits 0 and 1) and the trigger mode (bits 2 and 3), 0 means the default of the bus
+    pub flags: u16
+}
+
+/// A LINT pin of a local APIC connected to the NMI
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone)]
+pub struct LocalApicNmi {
+    /// The processor whose local APIC has the NMI connected, `None` for all of them
+    pub processor_id: Option<u8>,
+    /// Which LINT pin, 0 or 1
+    pub lint: u8,
+    pub flags: u16
+}
+
+/// The MADT (Multiple APIC Description Table), which describes the processors and the interrupt controllers
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct Madt {
+    /// Where the local APIC of every processor is, each one only sees its own
+    pub local_apic_address: PhysAddr,
+    /// Whatever the two legacy PICs are there too, they have to be masked before using the APICs
+    pub has_legacy_pics: bool,
+    pub processors: Vec<LocalApic>,
+    pub io_apics: Vec<IoApic>,
+    pub overrides: Vec<InterruptOverride>,
+    pub nmis: Vec<LocalApicNmi>
+}
+
+impl Madt {
+    /// Returns the global system interrupt an ISA IRQ is connected to, together with the flags of the
+    /// override if there's one
+    #[allow(dead_code)]
+    pub fn isa_irq_to_gsi(&self, irq: u8) -> (u32, u16) {
+        match self.overrides.iter().find(|entry| entry.source == irq) {
+            Some(entry) => (entry.gsi, entry.flags),
+            None => (irq as u32, 0)
+        }
+    }
+
+    /// Parses the MADT at `table`
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that `table` is a valid MADT
+    pub(super) unsafe fn parse(table: PhysAddr) -> Self {
+        let header: SdtHeader = super::read_table(table, 0);
+        let flags: u32 = super::read_table(table, size_of::<SdtHeader>() + 4);
+
+        let mut madt = Madt {
+            local_apic_address: PhysAddr::new(super::read_table::<u32>(table, size_of::<SdtHeader>()) as u64),
+            has_legacy_pics: flags & FLAG_LEGACY_PICS != 0,
+            processors: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+            nmis: Vec::new()
+        };
+
+        // The entries start after the address of the local APICs and the flags, each one starts with its type
+        // and its length
+        let mut offset = size_of::<SdtHeader>() + 8;
+
+        while offset + 2 <= header.length as usize {
+            let kind: u8 = super::read_table(table, offset);
+            let length: u8 = super::read_table(table, offset + 1);
+
+            // A broken entry would loop forever
+            if length < 2 {
+                break;
+            }
+
+            match kind {
+                ENTRY_LOCAL_APIC => {
+                    let flags: u32 = super::read_table(table, offset + 4);
+
+                    madt.processors.push(LocalApic {
+                        processor_id: super::read_table::<u8>(table, offset + 2) as u32,
+                        apic_id: super::read_table::<u8>(table, offset + 3) as u32,
+                        enabled: flags & PROCESSOR_ENABLED != 0,
+                        online_capable: flags & PROCESSOR_ONLINE_CAPABLE != 0
+                    });
+                }
+                ENTRY_IO_APIC => madt.io_apics.push(IoApic {
+                    id: super::read_table(table, offset + 2),
+                    address: PhysAddr::new(super::read_table::<u32>(table, offset + 4) as u64),
+                    gsi_base: super::read_table(table, offset + 8)
+                }),
+                ENTRY_INTERRUPT_OVERRIDE => madt.overrides.push(InterruptOverride {
+                    source: super::read_table(table, offset + 3),
+                    gsi: super::read_table(table, offset + 4),
+                    flags: super::read_table(table, offset + 8)
+                }),
+                ENTRY_LOCAL_APIC_NMI => {
+                    let processor_id: u8 = super::read_table(table, offset + 2);
+
+                    madt.nmis.push(LocalApicNmi {
+                        processor_id: (processor_id != ALL_PROCESSORS).then_some(processor_id),
+                        lint: super::read_table(table, offset + 5),
+                        flags: super::read_table(table, offset + 3)
+                    });
+                }
+                ENTRY_LOCAL_APIC_ADDRESS => {
+                    madt.local_apic_address = PhysAddr::new(super::read_table(table, offset + 4));
+                }
+                ENTRY_LOCAL_X2APIC => {
+                    let flags: u32 = super::read_table(table, offset + 8);
+
+                    madt.processors.push(LocalApic {
+                        processor_id: super::read_table(table, offset + 12),
+                        apic_id: super::read_table(table, offset + 4),
+                        enabled: flags & PROCESSOR_ENABLED != 0,
+                        online_capable: flags & PROCESSOR_ONLINE_CAPABLE != 0
+                    });
+                }
+                _ => {}
+            }
+
+            offset += length as usize;
+        }
+
+        return madt;
+    }
+}
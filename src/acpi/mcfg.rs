@@ -0,0 +1,48 @@
+use alloc::vec::Vec;
+use core::mem::size_of;
+use x86_64::PhysAddr;
+use super::SdtHeader;
+
+/// An entry of the MCFG table as it's stored
+#[repr(C, packed)]
+struct RawEntry {
+    base: u64,
+    segment: u16,
+    start_bus: u8,
+    end_bus: u8,
+    _reserved: u32
+}
+
+/// An entry of the MCFG table, describing where the memory-mapped configuration space (ECAM) of a range of buses is
+#[derive(Debug, Copy, Clone)]
+pub struct McfgEntry {
+    /// The physical address of the configuration space of bus 0, even if the range doesn't start at bus 0
+    pub base: PhysAddr,
+    /// The PCI segment the buses belong to
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8
+}
+
+/// Parses the entries of the MCFG table at `table`
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that `table` is a valid MCFG table
+pub(super) unsafe fn parse(table: PhysAddr) -> Vec<McfgEntry> {
+    let header: SdtHeader = super::read_table(table, 0);
+
+    // The entries come after the header and 8 reserved bytes
+    let first_entry = size_of::<SdtHeader>() + 8;
+    let count = (header.length as usize).saturating_sub(first_entry) / size_of::<RawEntry>();
+
+    return (0..count)
+        .map(|index| super::read_table::<RawEntry>(table, first_entry + index * size_of::<RawEntry>()))
+        .map(|entry| McfgEntry {
+            base: PhysAddr::new(entry.base),
+            segment: entry.segment,
+            start_bus: entry.start_bus,
+            end_bus: entry.end_bus
+        })
+        .collect();
+}
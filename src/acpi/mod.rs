@@ -0,0 +1,201 @@
+mod fadt;
+mod hpet;
+mod madt;
+mod mcfg;
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr;
+use x86_64::PhysAddr;
+use crate::memory::physical_to_virtual;
+use crate::utils::Mutex;
+
+#[allow(unused_imports)]
+pub use self::fadt::{Fadt, GenericAddress};
+pub use self::hpet::Hpet;
+#[allow(unused_imports)]
+pub use self::madt::{InterruptOverride, IoApic, LocalApic, LocalApicNmi, Madt};
+pub use self::mcfg::McfgEntry;
+
+/// The signature that starts the RSDP (Root System Description Pointer), always on a 16 bytes boundary
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+/// Where the BIOS leaves the segment of the EBDA (Extended BIOS Data Area), whose first KiB may hold the RSDP
+const EBDA_SEGMENT_POINTER: u64 = 0x40E;
+
+/// The read-only memory of the BIOS, where the RSDP is when it's not in the EBDA
+const BIOS_AREA_START: u64 = 0xE0000;
+const BIOS_AREA_END: u64 = 0x100000;
+
+/// The header every ACPI table (except the RSDP) starts with
+#[derive(Debug, Copy, Clone)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    /// The size of the whole table, including this header
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32
+}
+
+/// The tables parsed by [`init`]
+static TABLES: Mutex<Tables> = Mutex::new(Tables {
+    madt: None,
+    fadt: None,
+    hpet: None,
+    mcfg: Vec::new()
+});
+
+struct Tables {
+    madt: Option<Madt>,
+    fadt: Option<Fadt>,
+    hpet: Option<Hpet>,
+    mcfg: Vec<McfgEntry>
+}
+
+/// The fields of the RSDP used by the kernel, the ones of ACPI 2.0 are only valid if `revision` is 2 or more
+#[allow(dead_code)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    length: u32,
+    xsdt_address: u64
+}
+
+/// Size of the RSDP of ACPI 1.0, which is all the checksum covers
+const RSDP_V1_LENGTH: usize = 20;
+
+/// Reads a `T` from a physical address, ACPI tables aren't aligned in any way
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that `address` points to at least `size_of::<T>()`
+/// bytes of mapped memory
+unsafe fn read_physical<T>(address: PhysAddr) -> T {
+    ptr::read_unaligned(physical_to_virtual(address).as_ptr())
+}
+
+/// Returns whatever the `length` bytes at `address` add up to 0, which is how ACPI checks its structures
+///
+/// ## Safety
+///
+/// Same as [`read_physical`]
+unsafe fn checksum_valid(address: PhysAddr, length: usize) -> bool {
+    let bytes = core::slice::from_raw_parts(physical_to_virtual(address).as_ptr::<u8>(), length);
+    return bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0;
+}
+
+/// Looks for a valid RSDP in the `length` bytes at `start`
+fn search_rsdp(start: u64, length: u64) -> Option<PhysAddr> {
+    return (start..start + length)
+        .step_by(16)
+        .map(PhysAddr::new)
+        .find(|&address| unsafe {
+            read_physical::<[u8; 8]>(address) == *RSDP_SIGNATURE && checksum_valid(address, RSDP_V1_LENGTH)
+        });
+}
+
+/// Finds the RSDP, the entry point of the ACPI tables, where the BIOS left it. The bootloader doesn't pass it
+/// along, so it's always searched for, which only works on BIOS machines
+fn find_rsdp() -> Option<PhysAddr> {
+    let ebda = unsafe { read_physical::<u16>(PhysAddr::new(EBDA_SEGMENT_POINTER)) } as u64 * 16;
+
+    if ebda != 0 {
+        if let Some(rsdp) = search_rsdp(ebda, 1024) {
+            return Some(rsdp);
+        }
+    }
+
+    return search_rsdp(BIOS_AREA_START, BIOS_AREA_END - BIOS_AREA_START);
+}
+
+/// Finds the ACPI table with the given signature (like `b"MCFG"`), returning the physical address of its
+/// [`SdtHeader`]. Tables whose checksum doesn't match are skipped.
+///
+/// This searches the tables from scratch every time, it's meant to be called once by every driver that needs a table
+pub fn find_table(signature: &[u8; 4]) -> Option<PhysAddr> {
+    let rsdp_address = find_rsdp()?;
+    let rsdp: Rsdp = unsafe { read_physical(rsdp_address) };
+
+    // The XSDT has 64 bits pointers, the RSDT of ACPI 1.0 only 32 bits ones
+    let (root, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        (PhysAddr::new(rsdp.xsdt_address), 8)
+    } else {
+        (PhysAddr::new(rsdp.rsdt_address as u64), 4)
+    };
+
+    let root_header: SdtHeader = unsafe { read_physical(root) };
+    let entries = (root_header.length as usize).saturating_sub(size_of::<SdtHeader>()) / entry_size;
+
+    for index in 0..entries {
+        let entry = root + size_of::<SdtHeader>() + index * entry_size;
+
+        let table = unsafe {
+            match entry_size {
+                8 => PhysAddr::new(read_physical::<u64>(entry)),
+                _ => PhysAddr::new(read_physical::<u32>(entry) as u64)
+            }
+        };
+
+        let header: SdtHeader = unsafe { read_physical(table) };
+
+        if header.signature == *signature && unsafe { checksum_valid(table, header.length as usize) } {
+            return Some(table);
+        }
+    }
+
+    return None;
+}
+
+/// Reads a `T` found `offset` bytes into the table at `table`
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that `table` was returned by [`find_table`] and that
+/// the value is inside the table
+pub unsafe fn read_table<T>(table: PhysAddr, offset: usize) -> T {
+    read_physical(table + offset)
+}
+
+/// Finds and parses the tables the rest of the kernel needs (MADT, FADT, HPET and MCFG), the ones that are
+/// missing are left empty. Must be called once, after the memory is initialized
+pub fn init() {
+    let madt = find_table(b"APIC").map(|table| unsafe { Madt::parse(table) });
+    let fadt = find_table(b"FACP").map(|table| unsafe { Fadt::parse(table) });
+    let hpet = find_table(b"HPET").map(|table| unsafe { Hpet::parse(table) });
+    let mcfg = find_table(b"MCFG").map(|table| unsafe { mcfg::parse(table) }).unwrap_or_default();
+
+    *TABLES.lock() = Tables { madt, fadt, hpet, mcfg };
+}
+
+/// Returns the MADT found by [`init`], with the processors and the interrupt controllers
+#[allow(dead_code)]
+pub fn madt() -> Option<Madt> {
+    TABLES.lock().madt.clone()
+}
+
+/// Returns the FADT found by [`init`], with the registers of the power management
+#[allow(dead_code)]
+pub fn fadt() -> Option<Fadt> {
+    TABLES.lock().fadt
+}
+
+/// Returns the HPET table found by [`init`]
+#[allow(dead_code)]
+pub fn hpet() -> Option<Hpet> {
+    TABLES.lock().hpet
+}
+
+/// Returns the entries of the MCFG table found by [`init`], empty if there's none
+pub fn mcfg() -> Vec<McfgEntry> {
+    TABLES.lock().mcfg.clone()
+}
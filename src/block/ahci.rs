@@ -0,0 +1,572 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::block::{self, BlockDevice, SECTOR_SIZE};
+use crate::drivers::registry::Driver;
+use crate::memory::dma::DmaBuffer;
+use crate::pci::PciDevice;
+use crate::println;
+use crate::sched::WaitQueue;
+use crate::sync::SleepMutex;
+use crate::time;
+use crate::utils::error::KernelError;
+
+/// The class, the subclass and the programming interface of an AHCI controller on the PCI bus
+const AHCI_CLASS: (u8, u8, u8) = (0x01, 0x06, 0x01);
+
+/// The register of the PCI command of a function and its bits that let the device answer to its memory,
+/// start transfers on its own and raise its interrupt (this one is set to disable it)
+const PCI_COMMAND: u16 = 0x04;
+const PCI_COMMAND_MEMORY: u32 = 1 << 1;
+const PCI_COMMAND_BUS_MASTER: u32 = 1 << 2;
+const PCI_COMMAND_INTERRUPT_DISABLE: u32 = 1 << 10;
+
+/// The BAR with the memory of the HBA (Host Bus Adapter), called ABAR
+const PCI_ABAR: u16 = 0x24;
+
+/// The register with the IRQ line the firmware routed the interrupt of the device to, in its lowest byte
+const PCI_INTERRUPT_LINE: u16 = 0x3C;
+
+/// The registers of the HBA, as offsets from the start of its memory
+const HBA_CONTROL: usize = 0x04;
+const HBA_INTERRUPT_STATUS: usize = 0x08;
+const HBA_PORTS_IMPLEMENTED: usize = 0x0C;
+
+/// The bits of the global HBA control
+const CONTROL_INTERRUPTS: u32 = 1 << 1;
+const CONTROL_AHCI_ENABLE: u32 = 1 << 31;
+
+/// The registers of the ports follow the ones of the HBA, each port has the same ones
+const PORTS_OFFSET: usize = 0x100;
+const PORT_SIZE: usize = 0x80;
+const MAX_PORTS: usize = 32;
+
+/// The size of the memory of the HBA, with every port implemented
+const HBA_SIZE: usize = PORTS_OFFSET + MAX_PORTS * PORT_SIZE;
+
+/// The registers of a port, as offsets from its first one
+const PORT_COMMAND_LIST: usize = 0x00;
+const PORT_COMMAND_LIST_UPPER: usize = 0x04;
+const PORT_RECEIVED_FIS: usize = 0x08;
+const PORT_RECEIVED_FIS_UPPER: usize = 0x0C;
+const PORT_INTERRUPT_STATUS: usize = 0x10;
+const PORT_INTERRUPT_ENABLE: usize = 0x14;
+const PORT_COMMAND: usize = 0x18;
+const PORT_TASK_FILE: usize = 0x20;
+const PORT_SIGNATURE: usize = 0x24;
+const PORT_SATA_STATUS: usize = 0x28;
+const PORT_SATA_ERROR: usize = 0x30;
+const PORT_COMMAND_ISSUE: usize = 0x38;
+
+/// The bits of the command register of a port
+const COMMAND_START: u32 = 1 << 0;
+const COMMAND_FIS_RECEIVE_ENABLE: u32 = 1 << 4;
+const COMMAND_FIS_RECEIVE_RUNNING: u32 = 1 << 14;
+const COMMAND_LIST_RUNNING: u32 = 1 << 15;
+
+/// The interrupts of a port the driver cares about: a command finishing (with or without data coming in)
+/// and the drive reporting an error
+const INTERRUPT_DEVICE_TO_HOST: u32 = 1 << 0;
+const INTERRUPT_PIO_SETUP: u32 = 1 << 1;
+const INTERRUPT_TASK_FILE_ERROR: u32 = 1 << 30;
+
+/// The bits of the status of the drive, in the lowest byte of the task file register
+const STATUS_ERROR: u32 = 1 << 0;
+const STATUS_DATA_REQUEST: u32 = 1 << 3;
+const STATUS_BUSY: u32 = 1 << 7;
+
+/// The device detection in the SATA status of a port once a drive is there and talking to the HBA
+const SATA_STATUS_PRESENT: u32 = 3;
+
+/// The signature of a port with an ATA drive, ATAPI drives (like CD-ROMs) have a different one
+const SIGNATURE_ATA: u32 = 0x0000_0101;
+
+/// Where the structures the HBA reads and writes are in the page of a port: the command list (only its first slot
+/// is used), the area the received FISes are copied to and the only command table, with its PRD table at the end
+const COMMAND_LIST_OFFSET: usize = 0x000;
+const RECEIVED_FIS_OFFSET: usize = 0x400;
+const COMMAND_TABLE_OFFSET: usize = 0x800;
+const PRDT_OFFSET: usize = COMMAND_TABLE_OFFSET + 0x80;
+
+/// The type of the FIS (Frame Information Structure) that sends a command to the drive, and its bit that
+/// tells the drive it's a command (instead of an update of the control register)
+const FIS_REGISTER_HOST_TO_DEVICE: u8 = 0x27;
+const FIS_COMMAND: u8 = 1 << 7;
+
+/// The size of a host to device register FIS, in double words, as the command header wants it
+const FIS_LENGTH: u32 = 5;
+
+/// Set in the command header when the data goes from the memory to the drive
+const HEADER_WRITE: u32 = 1 << 6;
+
+/// Selects LBA addressing in the device register
+const DEVICE_LBA: u8 = 1 << 6;
+
+const COMMAND_IDENTIFY: u8 = 0xEC;
+const COMMAND_READ_DMA: u8 = 0xC8;
+const COMMAND_READ_DMA_EXT: u8 = 0x25;
+const COMMAND_WRITE_DMA: u8 = 0xCA;
+const COMMAND_WRITE_DMA_EXT: u8 = 0x35;
+const COMMAND_FLUSH_CACHE: u8 = 0xE7;
+const COMMAND_FLUSH_CACHE_EXT: u8 = 0xEA;
+
+/// How many pages the buffer of a port has, which limits how much data a single command transfers
+const BUFFER_PAGES: usize = 16;
+
+/// The most sectors a single command transfers
+const MAX_SECTORS_PER_COMMAND: usize = BUFFER_PAGES * 4096 / SECTOR_SIZE;
+
+/// The highest sector reachable with a 28 bits LBA, plus one
+const LBA28_LIMIT: u64 = 1 << 28;
+
+/// How long the HBA can take to stop a port, or a drive can stay busy before a command is sent
+const READY_TIMEOUT_MS: u64 = 500;
+
+/// How long a command can take before it's considered failed
+const COMMAND_TIMEOUT_MS: u64 = 2000;
+
+/// What the interrupt handler shares with the thread waiting for the command of a port
+struct Completion {
+    /// The interrupts of the port that arrived since the command was issued
+    status: AtomicU32,
+    waiters: WaitQueue
+}
+
+impl Completion {
+    const fn new() -> Self {
+        Completion {
+            status: AtomicU32::new(0),
+            waiters: WaitQueue::new()
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_COMPLETION: Completion = Completion::new();
+
+/// The completions of the ports of the controller
+static COMPLETIONS: [Completion; MAX_PORTS] = [NO_COMPLETION; MAX_PORTS];
+
+/// Where the memory of the HBA is mapped, 0 until [`init`] finds one. Only the first AHCI controller is used
+static HBA: AtomicU64 = AtomicU64::new(0);
+
+/// The memory-mapped registers of the HBA or of one of its ports
+#[derive(Copy, Clone)]
+struct Registers(VirtAddr);
+
+impl Registers {
+    fn read(&self, register: usize) -> u32 {
+        unsafe { core::ptr::read_volatile((self.0 + register).as_ptr::<u32>()) }
+    }
+
+    fn write(&self, register: usize, value: u32) {
+        unsafe { core::ptr::write_volatile((self.0 + register).as_mut_ptr::<u32>(), value) };
+    }
+
+    /// Returns the registers of the port with the given index, only valid on the ones of the HBA
+    fn port(&self, index: usize) -> Registers {
+        Registers(self.0 + PORTS_OFFSET + index * PORT_SIZE)
+    }
+}
+
+/// Called by the interrupt handler of the IRQ line of the controller, it acknowledges the interrupts of
+/// every port and wakes up the threads waiting for their commands
+///
+/// ## Note
+///
+/// This function must not allocate or block since it runs inside an interrupt handler
+pub fn handle_interrupt() {
+    let base = HBA.load(Ordering::Acquire);
+
+    if base == 0 {
+        return;
+    }
+
+    let hba = Registers(VirtAddr::new(base));
+    let pending = hba.read(HBA_INTERRUPT_STATUS);
+
+    // The line may be shared with other devices
+    if pending == 0 {
+        return;
+    }
+
+    for index in (0..MAX_PORTS).filter(|&index| pending & 1 << index != 0) {
+        let port = hba.port(index);
+        let status = port.read(PORT_INTERRUPT_STATUS);
+
+        port.write(PORT_INTERRUPT_STATUS, status);
+
+        COMPLETIONS[index].status.fetch_or(status, Ordering::Release);
+        COMPLETIONS[index].waiters.wake_all();
+    }
+
+    // The interrupts of the ports have to be cleared first, otherwise the HBA raises the interrupt again
+    hba.write(HBA_INTERRUPT_STATUS, pending);
+}
+
+/// Polls `condition` until it's true, or fails with [`KernelError::Timeout`] after `ms` milliseconds
+fn wait_for(ms: u64, condition: impl Fn() -> bool) -> Result<(), KernelError> {
+    let deadline = time::ticks() + time::ms_to_ticks(ms);
+
+    while !condition() {
+        if time::ticks() > deadline {
+            return Err(KernelError::Timeout);
+        }
+
+        core::hint::spin_loop();
+    }
+
+    Ok(())
+}
+
+/// A port of the HBA with a drive, together with the memory the HBA uses for its commands. The data is copied
+/// through a buffer of its own, so the callers can use any memory (even above 4 GiB, or not physically contiguous)
+struct Port {
+    registers: Registers,
+    completion: &'static Completion,
+    /// The command list, the received FIS area and the command table, all in a single page
+    memory: DmaBuffer,
+    buffer: DmaBuffer
+}
+
+impl Port {
+    /// Sets up the port with the given index, pointing the HBA to the memory of the port and letting it run commands
+    ///
+    /// ## Errors
+    ///
+    /// Returns the error of the allocation of the memory, or [`KernelError::Timeout`] if the port doesn't stop
+    fn new(hba: Registers, index: usize) -> Result<Self, KernelError> {
+        let port = Port {
+            registers: hba.port(index),
+            completion: &COMPLETIONS[index],
+            memory: DmaBuffer::new(1)?,
+            buffer: DmaBuffer::new(BUFFER_PAGES)?
+        };
+
+        // The firmware may have left the port running with memory of its own
+        port.stop()?;
+
+        let base = port.memory.physical(0).as_u64();
+
+        port.registers.write(PORT_COMMAND_LIST, (base + COMMAND_LIST_OFFSET as u64) as u32);
+        port.registers.write(PORT_COMMAND_LIST_UPPER, (base >> 32) as u32);
+        port.registers.write(PORT_RECEIVED_FIS, (base + RECEIVED_FIS_OFFSET as u64) as u32);
+        port.registers.write(PORT_RECEIVED_FIS_UPPER, (base >> 32) as u32);
+
+        // Both registers are cleared by writing 1 to their bits
+        port.registers.write(PORT_SATA_ERROR, u32::MAX);
+        port.registers.write(PORT_INTERRUPT_STATUS, u32::MAX);
+        port.registers.write(PORT_INTERRUPT_ENABLE, INTERRUPT_DEVICE_TO_HOST | INTERRUPT_PIO_SETUP | INTERRUPT_TASK_FILE_ERROR);
+
+        port.start();
+        Ok(port)
+    }
+
+    /// Stops the port from running commands and receiving FISes, waiting for the HBA to acknowledge it
+    fn stop(&self) -> Result<(), KernelError> {
+        let command = self.registers.read(PORT_COMMAND);
+        self.registers.write(PORT_COMMAND, command & !(COMMAND_START | COMMAND_FIS_RECEIVE_ENABLE));
+
+        wait_for(READY_TIMEOUT_MS, || {
+            self.registers.read(PORT_COMMAND) & (COMMAND_LIST_RUNNING | COMMAND_FIS_RECEIVE_RUNNING) == 0
+        })
+    }
+
+    fn start(&self) {
+        let command = self.registers.read(PORT_COMMAND);
+        self.registers.write(PORT_COMMAND, command | COMMAND_FIS_RECEIVE_ENABLE);
+        self.registers.write(PORT_COMMAND, command | COMMAND_FIS_RECEIVE_ENABLE | COMMAND_START);
+    }
+
+    /// Gets the port running again after the drive reported an error, which stops the HBA from running commands
+    fn recover(&self) {
+        if self.stop().is_ok() {
+            self.registers.write(PORT_SATA_ERROR, u32::MAX);
+            self.registers.write(PORT_INTERRUPT_STATUS, u32::MAX);
+            self.start();
+        }
+    }
+
+    /// Runs a command transferring `size` bytes between the drive and the buffer of the port, to the drive
+    /// if `write` is set. The thread sleeps until the interrupt of the port tells the command is done
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Timeout`] if the drive stays busy or the command never finishes, and
+    /// [`KernelError::Io`] if the drive reports an error
+    fn execute(&self, command: u8, lba: u64, count: u16, size: usize, write: bool) -> Result<(), KernelError> {
+        wait_for(READY_TIMEOUT_MS, || self.registers.read(PORT_TASK_FILE) & (STATUS_BUSY | STATUS_DATA_REQUEST) == 0)?;
+
+        let pages = (size + 4095) / 4096;
+        let page = self.memory.page(0);
+        let table_address = self.memory.physical(0).as_u64() + COMMAND_TABLE_OFFSET as u64;
+
+        unsafe {
+            // The first slot of the command list, the HBA writes how many bytes it transferred to its second word
+            let header = page.add(COMMAND_LIST_OFFSET) as *mut u32;
+
+            core::ptr::write_volatile(header, FIS_LENGTH | if write { HEADER_WRITE } else { 0 } | (pages as u32) << 16);
+            core::ptr::write_volatile(header.add(1), 0);
+            core::ptr::write_volatile(header.add(2), table_address as u32);
+            core::ptr::write_volatile(header.add(3), (table_address >> 32) as u32);
+
+            let fis = page.add(COMMAND_TABLE_OFFSET);
+            core::ptr::write_bytes(fis, 0, PRDT_OFFSET - COMMAND_TABLE_OFFSET);
+
+            let lba = lba.to_le_bytes();
+            let bytes = [
+                FIS_REGISTER_HOST_TO_DEVICE, FIS_COMMAND, command, 0,
+                lba[0], lba[1], lba[2], DEVICE_LBA,
+                lba[3], lba[4], lba[5], 0,
+                count as u8, (count >> 8) as u8, 0, 0
+            ];
+
+            core::ptr::copy_nonoverlapping(bytes.as_ptr(), fis, bytes.len());
+
+            let prdt = page.add(PRDT_OFFSET) as *mut u32;
+
+            for index in 0..pages {
+                let address = self.buffer.physical(index).as_u64();
+                let page_size = (size - index * 4096).min(4096);
+                let entry = prdt.add(index * 4);
+
+                // The last word has the size of the piece minus one
+                core::ptr::write_volatile(entry, address as u32);
+                core::ptr::write_volatile(entry.add(1), (address >> 32) as u32);
+                core::ptr::write_volatile(entry.add(2), 0);
+                core::ptr::write_volatile(entry.add(3), page_size as u32 - 1);
+            }
+        }
+
+        let completion = self.completion;
+        completion.status.store(0, Ordering::Release);
+
+        self.registers.write(PORT_COMMAND_ISSUE, 1);
+
+        // The HBA clears the slot once the command is done, the interrupt only wakes the thread up to check it
+        let finished = completion.waiters.wait_until_timeout(COMMAND_TIMEOUT_MS, || {
+            self.registers.read(PORT_COMMAND_ISSUE) & 1 == 0
+                || completion.status.load(Ordering::Acquire) & INTERRUPT_TASK_FILE_ERROR != 0
+        });
+
+        let failed = completion.status.load(Ordering::Acquire) & INTERRUPT_TASK_FILE_ERROR != 0
+            || self.registers.read(PORT_TASK_FILE) & STATUS_ERROR != 0;
+
+        if !finished || failed {
+            self.recover();
+        }
+
+        if !finished {
+            return Err(KernelError::Timeout);
+        }
+
+        if failed {
+            return Err(KernelError::Io);
+        }
+
+        Ok(())
+    }
+
+    /// Asks the drive to describe itself, returning the 256 words of the answer
+    fn identify(&self) -> Result<[u16; 256], KernelError> {
+        self.execute(COMMAND_IDENTIFY, 0, 0, SECTOR_SIZE, false)?;
+
+        let mut bytes = [0u8; SECTOR_SIZE];
+        self.buffer.copy_out(&mut bytes);
+
+        let mut words = [0u16; 256];
+
+        for (word, bytes) in words.iter_mut().zip(bytes.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+
+        Ok(words)
+    }
+}
+
+/// A hard disk connected to a port of an AHCI controller. Every transfer uses DMA, the thread sleeps until
+/// the interrupt of the port while the HBA moves the data
+pub struct AhciDrive {
+    name: String,
+    /// The model reported by the drive
+    model: String,
+    port: SleepMutex<Port>,
+    sectors: u64,
+    /// Whatever the drive supports the 48 bits LBA commands
+    lba48: bool
+}
+
+impl AhciDrive {
+    /// Returns the model reported by the drive
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Runs a read or a write (if `write` is set) of up to [`MAX_SECTORS_PER_COMMAND`] sectors, the data is
+    /// already in the buffer of the port for a write and is left there by a read
+    fn transfer(&self, port: &Port, sector: u64, count: usize, write: bool) -> Result<(), KernelError> {
+        let extended = self.lba48 && sector + count as u64 > LBA28_LIMIT;
+
+        let command = match (write, extended) {
+            (false, false) => COMMAND_READ_DMA,
+            (false, true) => COMMAND_READ_DMA_EXT,
+            (true, false) => COMMAND_WRITE_DMA,
+            (true, true) => COMMAND_WRITE_DMA_EXT
+        };
+
+        port.execute(command, sector, count as u16, count * SECTOR_SIZE, write)?;
+
+        if write {
+            let flush = if extended { COMMAND_FLUSH_CACHE_EXT } else { COMMAND_FLUSH_CACHE };
+            port.execute(flush, 0, 0, 0, false)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl BlockDevice for AhciDrive {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read(&self, sector: u64, buffer: &mut [u8]) -> Result<(), KernelError> {
+        block::check_transfer(self, sector, buffer.len())?;
+
+        let port = self.port.lock();
+        let mut sector = sector;
+
+        for chunk in buffer.chunks_mut(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE) {
+            let count = chunk.len() / SECTOR_SIZE;
+
+            self.transfer(&port, sector, count, false)?;
+            port.buffer.copy_out(chunk);
+            sector += count as u64;
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, sector: u64, buffer: &[u8]) -> Result<(), KernelError> {
+        block::check_transfer(self, sector, buffer.len())?;
+
+        let port = self.port.lock();
+        let mut sector = sector;
+
+        for chunk in buffer.chunks(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE) {
+            let count = chunk.len() / SECTOR_SIZE;
+
+            port.buffer.copy_in(chunk);
+            self.transfer(&port, sector, count, true)?;
+            sector += count as u64;
+        }
+
+        Ok(())
+    }
+}
+
+/// Maps the memory of the HBA of `controller`, after letting it answer to its memory, start transfers on its own
+/// and raise its interrupt. Returns the registers of the HBA and the IRQ line of the controller, if it has one the
+/// PIC can deliver
+fn map_controller(controller: &PciDevice) -> Option<(Registers, Option<u8>)> {
+    // Bit 0 tells whatever the BAR has I/O ports, the memory of the HBA is always below 4 GiB
+    let bar = controller.read_config(PCI_ABAR);
+
+    if bar & 1 != 0 || bar & !0xF == 0 {
+        return None;
+    }
+
+    let command = controller.read_config(PCI_COMMAND);
+    let command = command & !PCI_COMMAND_INTERRUPT_DISABLE | PCI_COMMAND_MEMORY | PCI_COMMAND_BUS_MASTER;
+    controller.write_config(PCI_COMMAND, command);
+
+    let base = crate::memory::mmio::map(PhysAddr::new((bar & !0xF) as u64), HBA_SIZE).ok()?;
+
+    // 0xFF means the firmware didn't route the interrupt anywhere
+    let irq = controller.read_config(PCI_INTERRUPT_LINE) as u8;
+
+    return Some((Registers(base), (irq < 16).then_some(irq)));
+}
+
+/// The driver bound to the AHCI controller
+struct AhciDriver;
+
+impl Driver for AhciDriver {
+    fn name(&self) -> &'static str {
+        "ahci"
+    }
+}
+
+/// Binds to `device` if it's an AHCI controller, registering every ATA drive found on its ports as `sataN`, where
+/// N is the index of the port. Only the first controller is used
+pub fn probe(device: &PciDevice) -> Option<Box<dyn Driver>> {
+    if (device.class, device.subclass, device.prog_if) != AHCI_CLASS || HBA.load(Ordering::Acquire) != 0 {
+        return None;
+    }
+
+    let (hba, irq) = map_controller(device)?;
+
+    hba.write(HBA_CONTROL, hba.read(HBA_CONTROL) | CONTROL_AHCI_ENABLE);
+    HBA.store(hba.0.as_u64(), Ordering::Release);
+
+    // Without an interrupt the commands still finish, but the threads only notice once their wait times out
+    match irq.map(|irq| crate::interrupts::interrupt_manager::register_irq(irq, handle_interrupt)) {
+        Some(Ok(())) => {}
+        _ => println!("AHCI: no usable IRQ line, the commands will be slow")
+    }
+
+    let implemented = hba.read(HBA_PORTS_IMPLEMENTED);
+
+    for index in (0..MAX_PORTS).filter(|&index| implemented & 1 << index != 0) {
+        let registers = hba.port(index);
+
+        if registers.read(PORT_SATA_STATUS) & 0xF != SATA_STATUS_PRESENT || registers.read(PORT_SIGNATURE) != SIGNATURE_ATA {
+            continue;
+        }
+
+        let port = match Port::new(hba, index) {
+            Ok(port) => port,
+            Err(error) => {
+                println!("AHCI: failed to set up port {}: {:?}", index, error);
+                continue;
+            }
+        };
+
+        // The interrupts of the HBA are enabled once the first port is ready for them
+        hba.write(HBA_CONTROL, hba.read(HBA_CONTROL) | CONTROL_INTERRUPTS);
+
+        let identity = match port.identify() {
+            Ok(identity) => identity,
+            Err(_) => continue
+        };
+
+        // Same words as for the drives of an IDE controller
+        let lba48 = identity[83] & (1 << 10) != 0;
+
+        let sectors = if lba48 {
+            identity[100..104].iter().rev().fold(0u64, |count, &word| count << 16 | word as u64)
+        } else {
+            (identity[61] as u64) << 16 | identity[60] as u64
+        };
+
+        let drive = AhciDrive {
+            name: format!("sata{}", index),
+            model: super::ata::identify_string(&identity[27..47]),
+            port: SleepMutex::new(port),
+            sectors,
+            lba48
+        };
+
+        println!("{}: {} ({} MiB)", drive.name, drive.model(), sectors * SECTOR_SIZE as u64 / (1024 * 1024));
+        block::register(Arc::new(drive));
+    }
+
+    return Some(Box::new(AhciDriver));
+}
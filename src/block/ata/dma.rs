@@ -0,0 +1,206 @@
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU8, Ordering};
+use x86_64::instructions::port::Port;
+use crate::memory::dma::DmaBuffer;
+use crate::sched::WaitQueue;
+use crate::utils::error::KernelError;
+use super::{REGISTER_COMMAND, SECTOR_SIZE};
+
+/// The registers of the bus master of a channel, as offsets from its first port
+const BUS_MASTER_COMMAND: u16 = 0;
+const BUS_MASTER_STATUS: u16 = 2;
+const BUS_MASTER_PRDT: u16 = 4;
+
+/// Starts the transfer, it must be cleared once the transfer is done
+const COMMAND_START: u8 = 1 << 0;
+
+/// Makes the transfer go from the drive to the memory, a read
+const COMMAND_TO_MEMORY: u8 = 1 << 3;
+
+/// The bits of the status of the bus master, both are cleared by writing 1 to them
+const STATUS_ERROR: u8 = 1 << 1;
+const STATUS_INTERRUPT: u8 = 1 << 2;
+
+/// Marks the last entry of the PRD table
+const PRD_END_OF_TABLE: u16 = 1 << 15;
+
+/// How many pages the buffer of a channel has, which limits how much data a single command transfers
+const BUFFER_PAGES: usize = 16;
+
+/// The most sectors a single DMA command transfers
+pub(super) const MAX_SECTORS_PER_COMMAND: u64 = (BUFFER_PAGES * 4096 / SECTOR_SIZE) as u64;
+
+/// How long a DMA transfer can take before it's considered failed
+const TRANSFER_TIMEOUT_MS: u64 = 2000;
+
+/// An entry of the PRD (Physical Region Descriptor) table, a piece of memory the bus master transfers to or from
+#[repr(C)]
+struct Prd {
+    address: u32,
+    /// How many bytes the piece has, 0 means 64 KiB
+    size: u16,
+    flags: u16
+}
+
+/// What the interrupt handler of a channel shares with the thread waiting for a transfer of that channel
+pub(super) struct Completion {
+    /// The first port of the bus master of the channel, 0 while the channel doesn't use DMA
+    bus_master: AtomicU16,
+    /// The first port of the command block of the channel
+    io_base: AtomicU16,
+    /// Set by the interrupt handler once the drive raises its interrupt
+    done: AtomicBool,
+    /// The status of the bus master when the interrupt arrived
+    status: AtomicU8,
+    waiters: WaitQueue
+}
+
+impl Completion {
+    const fn new() -> Self {
+        Completion {
+            bus_master: AtomicU16::new(0),
+            io_base: AtomicU16::new(0),
+            done: AtomicBool::new(false),
+            status: AtomicU8::new(0),
+            waiters: WaitQueue::new()
+        }
+    }
+}
+
+/// The completions of the primary and the secondary channel
+static COMPLETIONS: [Completion; 2] = [Completion::new(), Completion::new()];
+
+/// Called by the interrupt handler of a channel, it acknowledges the interrupt and wakes up
+/// the thread waiting for the transfer of the channel
+///
+/// ## Note
+///
+/// This function must not allocate or block since it runs inside an interrupt handler
+pub fn handle_interrupt(channel: usize) {
+    let completion = &COMPLETIONS[channel];
+    let bus_master = completion.bus_master.load(Ordering::Acquire);
+
+    if bus_master == 0 {
+        return;
+    }
+
+    unsafe {
+        let mut status_port: Port<u8> = Port::new(bus_master + BUS_MASTER_STATUS);
+        let status = status_port.read();
+
+        if status & STATUS_INTERRUPT == 0 {
+            return;
+        }
+
+        status_port.write(status | STATUS_INTERRUPT | STATUS_ERROR);
+
+        // Reading the status register of the drive is what acknowledges its interrupt
+        Port::<u8>::new(completion.io_base.load(Ordering::Relaxed) + REGISTER_COMMAND).read();
+
+        completion.status.store(status, Ordering::Relaxed);
+    }
+
+    completion.done.store(true, Ordering::Release);
+    completion.waiters.wake_all();
+}
+
+/// The bus master of a channel together with the memory it transfers from and to. The data is copied through
+/// a buffer of its own, so the callers can use any memory (even above 4 GiB, or not physically contiguous)
+pub(super) struct Dma {
+    bus_master: u16,
+    completion: &'static Completion,
+    /// The page of the PRD table, which has an entry for every page of the buffer
+    prdt: DmaBuffer,
+    buffer: DmaBuffer
+}
+
+impl Dma {
+    /// Allocates the memory used for the transfers of the channel with the given index, whose bus master
+    /// starts at `bus_master`
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::OutOfMemory`] if there's not enough memory, or [`KernelError::Unsupported`] if the
+    /// memory given isn't reachable by the bus master
+    pub(super) fn new(channel: usize, bus_master: u16, io_base: u16) -> Result<Self, KernelError> {
+        let prdt = DmaBuffer::new(1)?;
+        let buffer = DmaBuffer::new(BUFFER_PAGES)?;
+
+        let completion = &COMPLETIONS[channel];
+        completion.io_base.store(io_base, Ordering::Relaxed);
+        completion.bus_master.store(bus_master, Ordering::Release);
+
+        Ok(Dma { bus_master, completion, prdt, buffer })
+    }
+
+    /// Copies `data` into the buffer, before a write
+    pub(super) fn copy_in(&self, data: &[u8]) {
+        self.buffer.copy_in(data);
+    }
+
+    /// Copies the buffer into `data`, after a read
+    pub(super) fn copy_out(&self, data: &mut [u8]) {
+        self.buffer.copy_out(data);
+    }
+
+    /// Gets the bus master ready to transfer `size` bytes, to the memory if `to_memory` is set. The transfer
+    /// starts with [`Dma::start`], after the command is sent to the drive
+    pub(super) fn prepare(&self, size: usize, to_memory: bool) {
+        let table = self.prdt.page(0) as *mut Prd;
+        let pages = (size + 4095) / 4096;
+
+        for index in 0..pages {
+            let page_size = (size - index * 4096).min(4096);
+
+            let entry = Prd {
+                address: self.buffer.physical(index).as_u64() as u32,
+                size: page_size as u16,
+                flags: if index == pages - 1 { PRD_END_OF_TABLE } else { 0 }
+            };
+
+            unsafe { core::ptr::write_volatile(table.add(index), entry) };
+        }
+
+        self.completion.done.store(false, Ordering::Release);
+
+        unsafe {
+            Port::<u8>::new(self.bus_master + BUS_MASTER_COMMAND).write(0);
+            Port::<u32>::new(self.bus_master + BUS_MASTER_PRDT).write(self.prdt.physical(0).as_u64() as u32);
+
+            // The bits of the status are cleared by writing them
+            Port::<u8>::new(self.bus_master + BUS_MASTER_STATUS).write(STATUS_INTERRUPT | STATUS_ERROR);
+            Port::<u8>::new(self.bus_master + BUS_MASTER_COMMAND).write(if to_memory { COMMAND_TO_MEMORY } else { 0 });
+        }
+    }
+
+    pub(super) fn start(&self) {
+        unsafe {
+            let mut command: Port<u8> = Port::new(self.bus_master + BUS_MASTER_COMMAND);
+            let value = command.read();
+
+            command.write(value | COMMAND_START);
+        }
+    }
+
+    /// Blocks the current thread until the drive raises its interrupt and stops the bus master
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Timeout`] if the interrupt never arrives and [`KernelError::Io`] if the bus
+    /// master reports an error
+    pub(super) fn wait(&self) -> Result<(), KernelError> {
+        let completion = self.completion;
+        let finished = completion.waiters.wait_until_timeout(TRANSFER_TIMEOUT_MS, || completion.done.load(Ordering::Acquire));
+
+        unsafe { Port::<u8>::new(self.bus_master + BUS_MASTER_COMMAND).write(0) };
+
+        if !finished {
+            return Err(KernelError::Timeout);
+        }
+
+        if completion.status.load(Ordering::Relaxed) & STATUS_ERROR != 0 {
+            return Err(KernelError::Io);
+        }
+
+        Ok(())
+    }
+}
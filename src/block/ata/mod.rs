@@ -0,0 +1,539 @@
+mod dma;
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use x86_64::instructions::port::Port;
+use crate::block::{self, BlockDevice, SECTOR_SIZE};
+use crate::drivers::registry::Driver;
+use crate::println;
+use crate::sync::SleepMutex;
+use crate::time;
+use crate::utils::error::KernelError;
+use self::dma::Dma;
+
+pub use self::dma::handle_interrupt;
+
+/// The I/O ports of the command block and the control block of the primary and the secondary channel
+const CHANNELS: [(u16, u16); 2] = [(0x1F0, 0x3F6), (0x170, 0x376)];
+
+/// The registers of the command block, as offsets from its first port
+const REGISTER_DATA: u16 = 0;
+const REGISTER_ERROR: u16 = 1;
+const REGISTER_SECTOR_COUNT: u16 = 2;
+const REGISTER_LBA_LOW: u16 = 3;
+const REGISTER_LBA_MID: u16 = 4;
+const REGISTER_LBA_HIGH: u16 = 5;
+const REGISTER_DRIVE: u16 = 6;
+const REGISTER_COMMAND: u16 = 7;
+
+/// The bits of the status register
+const STATUS_ERROR: u8 = 1 << 0;
+const STATUS_DATA_REQUEST: u8 = 1 << 3;
+const STATUS_DRIVE_FAULT: u8 = 1 << 5;
+const STATUS_BUSY: u8 = 1 << 7;
+
+/// Set in the device control register to keep the drives from raising interrupts, the channels without DMA are polled
+const CONTROL_NO_INTERRUPTS: u8 = 1 << 1;
+
+/// The IRQ line of the primary channel, the secondary channel uses the next one
+const PRIMARY_IRQ: u8 = 14;
+
+/// Selects the master drive using LBA addressing, the slave drive also has bit 4 set
+const DRIVE_LBA: u8 = 0xE0;
+
+const COMMAND_READ_SECTORS: u8 = 0x20;
+const COMMAND_READ_SECTORS_EXT: u8 = 0x24;
+const COMMAND_WRITE_SECTORS: u8 = 0x30;
+const COMMAND_WRITE_SECTORS_EXT: u8 = 0x34;
+const COMMAND_FLUSH_CACHE: u8 = 0xE7;
+const COMMAND_FLUSH_CACHE_EXT: u8 = 0xEA;
+const COMMAND_IDENTIFY: u8 = 0xEC;
+const COMMAND_READ_DMA: u8 = 0xC8;
+const COMMAND_READ_DMA_EXT: u8 = 0x25;
+const COMMAND_WRITE_DMA: u8 = 0xCA;
+const COMMAND_WRITE_DMA_EXT: u8 = 0x35;
+
+/// The class and the subclass of an IDE controller on the PCI bus
+const IDE_CLASS: (u8, u8) = (0x01, 0x01);
+
+/// The register of the PCI command of a function and the bit that lets the device start transfers on its own
+const PCI_COMMAND: u16 = 0x04;
+const PCI_COMMAND_BUS_MASTER: u32 = 1 << 2;
+
+/// The BAR (Base Address Register) with the I/O ports of the bus masters of both channels
+const PCI_BUS_MASTER_BAR: u16 = 0x20;
+
+/// The highest sector reachable with a 28 bits LBA, plus one
+const LBA28_LIMIT: u64 = 1 << 28;
+
+/// The most sectors a single PIO command transfers here, a sector count of 0 means 256 for the 28 bits commands
+const MAX_SECTORS_PER_COMMAND: u64 = 256;
+
+/// How long a drive can stay busy before a command is considered failed
+const COMMAND_TIMEOUT_MS: u64 = 1000;
+
+/// Which way the data of a command goes
+enum Transfer<'a> {
+    Read(&'a mut [u8]),
+    Write(&'a [u8])
+}
+
+impl Transfer<'_> {
+    /// Returns how many sectors are transferred
+    fn sectors(&self) -> u64 {
+        let size = match self {
+            Transfer::Read(buffer) => buffer.len(),
+            Transfer::Write(buffer) => buffer.len()
+        };
+
+        (size / SECTOR_SIZE) as u64
+    }
+}
+
+/// The ports of a channel, shared by the two drives connected to it
+struct Channel {
+    io_base: u16,
+    control_base: u16,
+    /// The bus master of the channel, if the IDE controller has one and it could be set up
+    dma: Option<Dma>
+}
+
+impl Channel {
+    fn read_register(&self, register: u16) -> u8 {
+        unsafe { Port::<u8>::new(self.io_base + register).read() }
+    }
+
+    fn write_register(&self, register: u16, value: u8) {
+        unsafe { Port::<u8>::new(self.io_base + register).write(value) };
+    }
+
+    /// Reads the status without acknowledging an interrupt, through the alternate status register
+    fn alternate_status(&self) -> u8 {
+        unsafe { Port::<u8>::new(self.control_base).read() }
+    }
+
+    fn set_control(&self, value: u8) {
+        unsafe { Port::<u8>::new(self.control_base).write(value) };
+    }
+
+    /// Waits about 400ns, the time a drive takes to update its status after a command or a drive selection,
+    /// reading the alternate status takes about 100ns
+    fn delay(&self) {
+        for _ in 0..4 {
+            self.alternate_status();
+        }
+    }
+
+    fn select(&self, slave: bool, lba_high_bits: u8) {
+        self.write_register(REGISTER_DRIVE, DRIVE_LBA | (slave as u8) << 4 | lba_high_bits & 0x0F);
+        self.delay();
+    }
+
+    /// Waits until the drive isn't busy, returning its status
+    fn wait_not_busy(&self) -> Result<u8, KernelError> {
+        let deadline = time::ticks() + time::ms_to_ticks(COMMAND_TIMEOUT_MS);
+
+        loop {
+            let status = self.alternate_status();
+
+            if status & STATUS_BUSY == 0 {
+                return Ok(status);
+            }
+
+            if time::ticks() > deadline {
+                return Err(KernelError::Timeout);
+            }
+
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Waits until the drive is ready to transfer a sector, failing if it reports an error
+    fn wait_data_request(&self) -> Result<(), KernelError> {
+        let status = self.wait_not_busy()?;
+
+        if status & (STATUS_ERROR | STATUS_DRIVE_FAULT) != 0 {
+            return Err(KernelError::Io);
+        }
+
+        if status & STATUS_DATA_REQUEST == 0 {
+            return Err(KernelError::Io);
+        }
+
+        Ok(())
+    }
+
+    /// Sends a command with its LBA and sector count, using the 48 bits registers if `extended` is set
+    fn send_command(&self, slave: bool, command: u8, lba: u64, count: u16, extended: bool) -> Result<(), KernelError> {
+        if extended {
+            self.select(slave, 0);
+            self.wait_not_busy()?;
+
+            // The registers are FIFOs of two bytes, the high bytes go first
+            self.write_register(REGISTER_SECTOR_COUNT, (count >> 8) as u8);
+            self.write_register(REGISTER_LBA_LOW, (lba >> 24) as u8);
+            self.write_register(REGISTER_LBA_MID, (lba >> 32) as u8);
+            self.write_register(REGISTER_LBA_HIGH, (lba >> 40) as u8);
+        } else {
+            self.select(slave, (lba >> 24) as u8);
+            self.wait_not_busy()?;
+        }
+
+        self.write_register(REGISTER_SECTOR_COUNT, count as u8);
+        self.write_register(REGISTER_LBA_LOW, lba as u8);
+        self.write_register(REGISTER_LBA_MID, (lba >> 8) as u8);
+        self.write_register(REGISTER_LBA_HIGH, (lba >> 16) as u8);
+        self.write_register(REGISTER_COMMAND, command);
+
+        self.delay();
+        Ok(())
+    }
+
+    /// Makes the drive write the data still in its cache
+    fn flush_cache(&self, extended: bool) -> Result<(), KernelError> {
+        let flush = if extended { COMMAND_FLUSH_CACHE_EXT } else { COMMAND_FLUSH_CACHE };
+
+        self.write_register(REGISTER_COMMAND, flush);
+        self.delay();
+
+        if self.wait_not_busy()? & (STATUS_ERROR | STATUS_DRIVE_FAULT) != 0 {
+            return Err(KernelError::Io);
+        }
+
+        Ok(())
+    }
+
+    fn read_sector(&self, buffer: &mut [u8]) {
+        let mut data: Port<u16> = Port::new(self.io_base + REGISTER_DATA);
+
+        for word in buffer.chunks_exact_mut(2) {
+            word.copy_from_slice(&unsafe { data.read() }.to_le_bytes());
+        }
+    }
+
+    fn write_sector(&self, buffer: &[u8]) {
+        let mut data: Port<u16> = Port::new(self.io_base + REGISTER_DATA);
+
+        for word in buffer.chunks_exact(2) {
+            unsafe { data.write(u16::from_le_bytes([word[0], word[1]])) };
+        }
+    }
+
+    /// Asks a drive to describe itself, returning the 256 words of the answer or `None` if there's
+    /// no ATA drive there (ATAPI drives, like CD-ROMs, don't answer this command)
+    fn identify(&self, slave: bool) -> Option<[u16; 256]> {
+        self.select(slave, 0);
+
+        self.write_register(REGISTER_SECTOR_COUNT, 0);
+        self.write_register(REGISTER_LBA_LOW, 0);
+        self.write_register(REGISTER_LBA_MID, 0);
+        self.write_register(REGISTER_LBA_HIGH, 0);
+        self.write_register(REGISTER_COMMAND, COMMAND_IDENTIFY);
+        self.delay();
+
+        // A status of 0 means there's no drive, a floating bus reads as all ones
+        let status = self.alternate_status();
+
+        if status == 0 || status == 0xFF {
+            return None;
+        }
+
+        self.wait_not_busy().ok()?;
+
+        // ATAPI and SATA drives set these registers to their signature instead of answering
+        if self.read_register(REGISTER_LBA_MID) != 0 || self.read_register(REGISTER_LBA_HIGH) != 0 {
+            return None;
+        }
+
+        self.wait_data_request().ok()?;
+
+        let mut bytes = [0u8; SECTOR_SIZE];
+        self.read_sector(&mut bytes);
+
+        let mut words = [0u16; 256];
+
+        for (word, bytes) in words.iter_mut().zip(bytes.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+
+        Some(words)
+    }
+}
+
+/// A hard disk connected to an IDE controller. The transfers use DMA (the bus master of the channel moves the data
+/// while the thread sleeps until the interrupt of the drive) when both the drive and the channel support it,
+/// otherwise PIO (the CPU moves every word of data)
+pub struct AtaDrive {
+    name: String,
+    /// The model reported by the drive
+    model: String,
+    channel: Arc<SleepMutex<Channel>>,
+    slave: bool,
+    sectors: u64,
+    /// Whatever the drive supports the 48 bits LBA commands
+    lba48: bool,
+    /// Whatever the drive supports DMA transfers
+    dma: bool
+}
+
+impl AtaDrive {
+    /// Returns the model reported by the drive
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Whatever the transfer of `count` sectors at `sector` needs the 48 bits commands, the drives without
+    /// them are never bigger than what 28 bits reach
+    fn needs_lba48(&self, sector: u64, count: u64) -> bool {
+        self.lba48 && sector + count > LBA28_LIMIT
+    }
+
+    /// Returns the bus master used for the transfers of this drive, if they use DMA
+    fn dma<'a>(&self, channel: &'a Channel) -> Option<&'a Dma> {
+        channel.dma.as_ref().filter(|_| self.dma)
+    }
+
+    /// Returns how many sectors a single command transfers at most, which depends on the kind of transfer
+    fn max_sectors(&self, channel: &Channel) -> usize {
+        match self.dma(channel) {
+            Some(_) => dma::MAX_SECTORS_PER_COMMAND as usize,
+            None => MAX_SECTORS_PER_COMMAND as usize
+        }
+    }
+
+    /// Transfers up to [`AtaDrive::max_sectors`] sectors with a single command
+    fn transfer(&self, channel: &Channel, sector: u64, transfer: Transfer) -> Result<(), KernelError> {
+        if let Some(dma) = self.dma(channel) {
+            return self.transfer_dma(channel, dma, sector, transfer);
+        }
+
+        let count = transfer.sectors();
+        let extended = self.needs_lba48(sector, count);
+
+        let command = match (&transfer, extended) {
+            (Transfer::Read(_), false) => COMMAND_READ_SECTORS,
+            (Transfer::Read(_), true) => COMMAND_READ_SECTORS_EXT,
+            (Transfer::Write(_), false) => COMMAND_WRITE_SECTORS,
+            (Transfer::Write(_), true) => COMMAND_WRITE_SECTORS_EXT
+        };
+
+        // 256 sectors is sent as 0 by the 28 bits commands, which the truncation to u8 does on its own
+        channel.send_command(self.slave, command, sector, count as u16, extended)?;
+
+        if let Transfer::Read(buffer) = transfer {
+            for chunk in buffer.chunks_exact_mut(SECTOR_SIZE) {
+                channel.wait_data_request()?;
+                channel.read_sector(chunk);
+            }
+        } else if let Transfer::Write(buffer) = transfer {
+            for chunk in buffer.chunks_exact(SECTOR_SIZE) {
+                channel.wait_data_request()?;
+                channel.write_sector(chunk);
+            }
+
+            channel.flush_cache(extended)?;
+        }
+
+        Ok(())
+    }
+
+    /// Transfers up to [`dma::MAX_SECTORS_PER_COMMAND`] sectors with a single DMA command, the thread sleeps
+    /// while the bus master moves the data
+    fn transfer_dma(&self, channel: &Channel, dma: &Dma, sector: u64, transfer: Transfer) -> Result<(), KernelError> {
+        let count = transfer.sectors();
+        let extended = self.needs_lba48(sector, count);
+
+        let command = match (&transfer, extended) {
+            (Transfer::Read(_), false) => COMMAND_READ_DMA,
+            (Transfer::Read(_), true) => COMMAND_READ_DMA_EXT,
+            (Transfer::Write(_), false) => COMMAND_WRITE_DMA,
+            (Transfer::Write(_), true) => COMMAND_WRITE_DMA_EXT
+        };
+
+        if let Transfer::Write(buffer) = &transfer {
+            dma.copy_in(buffer);
+        }
+
+        dma.prepare(count as usize * SECTOR_SIZE, matches!(transfer, Transfer::Read(_)));
+        channel.send_command(self.slave, command, sector, count as u16, extended)?;
+        dma.start();
+        dma.wait()?;
+
+        if channel.wait_not_busy()? & (STATUS_ERROR | STATUS_DRIVE_FAULT) != 0 {
+            return Err(KernelError::Io);
+        }
+
+        match transfer {
+            Transfer::Read(buffer) => dma.copy_out(buffer),
+            Transfer::Write(_) => channel.flush_cache(extended)?
+        }
+
+        Ok(())
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read(&self, sector: u64, buffer: &mut [u8]) -> Result<(), KernelError> {
+        block::check_transfer(self, sector, buffer.len())?;
+
+        let channel = self.channel.lock();
+        let mut sector = sector;
+
+        for chunk in buffer.chunks_mut(self.max_sectors(&channel) * SECTOR_SIZE) {
+            let count = (chunk.len() / SECTOR_SIZE) as u64;
+
+            self.transfer(&channel, sector, Transfer::Read(chunk))?;
+            sector += count;
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, sector: u64, buffer: &[u8]) -> Result<(), KernelError> {
+        block::check_transfer(self, sector, buffer.len())?;
+
+        let channel = self.channel.lock();
+        let mut sector = sector;
+
+        for chunk in buffer.chunks(self.max_sectors(&channel) * SECTOR_SIZE) {
+            let count = (chunk.len() / SECTOR_SIZE) as u64;
+
+            self.transfer(&channel, sector, Transfer::Write(chunk))?;
+            sector += count;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the text stored in the given words of the answer to IDENTIFY, which has the bytes of every word swapped
+pub(super) fn identify_string(words: &[u16]) -> String {
+    let mut text = String::new();
+
+    for word in words {
+        for byte in word.to_be_bytes() {
+            text.push(if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '?' });
+        }
+    }
+
+    return String::from(text.trim());
+}
+
+/// Finds the IDE controller on the PCI bus and returns the first port of the bus masters of its channels, after
+/// letting it start transfers on its own. Only the channels in compatibility mode (using the fixed ports in [`CHANNELS`]
+/// and IRQs 14 and 15) are handled, the other ones get `None`
+fn find_bus_masters() -> [Option<u16>; 2] {
+    let controller = crate::pci::devices()
+        .into_iter()
+        .find(|device| (device.class, device.subclass) == IDE_CLASS);
+
+    let controller = match controller {
+        Some(controller) => controller,
+        None => return [None, None]
+    };
+
+    // Bit 0 tells whatever the BAR has I/O ports (instead of memory)
+    let bar = controller.read_config(PCI_BUS_MASTER_BAR);
+
+    if bar & 1 == 0 || bar & 0xFFFC == 0 {
+        return [None, None];
+    }
+
+    controller.write_config(PCI_COMMAND, controller.read_config(PCI_COMMAND) | PCI_COMMAND_BUS_MASTER);
+
+    let base = (bar & 0xFFFC) as u16;
+
+    // Bits 0 and 2 of the programming interface are set for the channels in native mode
+    let primary = controller.prog_if & (1 << 0) == 0;
+    let secondary = controller.prog_if & (1 << 2) == 0;
+
+    return [primary.then_some(base), secondary.then_some(base + 8)];
+}
+
+/// The driver bound to the legacy IDE channels
+struct AtaDriver;
+
+impl Driver for AtaDriver {
+    fn name(&self) -> &'static str {
+        "ata"
+    }
+}
+
+/// Looks for drives on both channels of the IDE controller, registering every ATA drive found as `ataN`, where
+/// N is 0 and 1 for the master and the slave of the primary channel and 2 and 3 for the ones of the secondary channel.
+/// Binds as soon as one of the channels is there
+pub fn probe() -> Option<Box<dyn Driver>> {
+    let bus_masters = find_bus_masters();
+    let mut found = false;
+
+    for (index, &(io_base, control_base)) in CHANNELS.iter().enumerate() {
+        let mut channel = Channel { io_base, control_base, dma: None };
+
+        // The interrupts are only enabled (further down) once the channel turns out to support DMA
+        channel.set_control(CONTROL_NO_INTERRUPTS);
+
+        // Reading the error register of a missing channel gives a floating bus
+        if channel.alternate_status() == 0xFF && channel.read_register(REGISTER_ERROR) == 0xFF {
+            continue;
+        }
+
+        found = true;
+
+        // Without DMA the channel just keeps being polled
+        channel.dma = bus_masters[index].and_then(|bus_master| Dma::new(index, bus_master, io_base).ok());
+
+        if channel.dma.is_some() {
+            channel.set_control(0);
+            crate::interrupts::interrupt_manager::enable_irq(PRIMARY_IRQ + index as u8);
+        }
+
+        let channel = Arc::new(SleepMutex::new(channel));
+
+        for slave in [false, true] {
+            let identity = match channel.lock().identify(slave) {
+                Some(identity) => identity,
+                None => continue
+            };
+
+            // Words 83 tells whatever the 48 bits commands are supported, words 100 to 103 have the sector count for
+            // them and words 60 and 61 the count reachable with 28 bits
+            let lba48 = identity[83] & (1 << 10) != 0;
+
+            let sectors = if lba48 {
+                identity[100..104].iter().rev().fold(0u64, |count, &word| count << 16 | word as u64)
+            } else {
+                (identity[61] as u64) << 16 | identity[60] as u64
+            };
+
+            // Word 49 tells whatever the drive supports DMA
+            let dma = identity[49] & (1 << 8) != 0;
+
+            let drive = AtaDrive {
+                name: format!("ata{}", index * 2 + slave as usize),
+                model: identify_string(&identity[27..47]),
+                channel: channel.clone(),
+                slave,
+                sectors,
+                lba48,
+                dma
+            };
+
+            let mode = if drive.dma(&channel.lock()).is_some() { "DMA" } else { "PIO" };
+
+            println!("{}: {} ({} MiB, {})", drive.name, drive.model(), sectors * SECTOR_SIZE as u64 / (1024 * 1024), mode);
+            block::register(Arc::new(drive));
+        }
+    }
+
+    return found.then(|| Box::new(AtaDriver) as Box<dyn Driver>);
+}
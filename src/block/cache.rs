@@ -0,0 +1,297 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use x86_64::VirtAddr;
+use crate::block::{self, BlockDevice, SECTOR_SIZE};
+use crate::println;
+use crate::sync::SleepMutex;
+use crate::utils::error::KernelError;
+
+/// How many sectors the cache holds, shared by every device. The table of their slots is on the heap, whose largest
+/// block is 4 KiB
+const CACHE_SECTORS: usize = 96;
+
+/// How long a sector written stays only in the cache at most, before the writeback thread puts it on the device
+const WRITEBACK_INTERVAL_MS: u64 = 5000;
+
+/// The cache of every device, [`None`] until [`init`] (or if there was no memory for it), then the devices are
+/// used directly. The lock is held while the devices transfer the sectors, so the transfers through the cache
+/// happen one at a time
+static CACHE: SleepMutex<Option<Cache>> = SleepMutex::new(None);
+
+/// A sector of a device held by the cache
+struct Slot {
+    /// The device the sector is from, [`None`] while the slot is free
+    device: Option<Arc<dyn BlockDevice>>,
+    sector: u64,
+    /// Whatever the sector was written in the cache but not on the device yet
+    dirty: bool,
+    /// When the sector was last used, the one used the longest time ago is replaced first
+    last_used: u64
+}
+
+struct Cache {
+    /// Where the data of the slots is, one sector after another
+    memory: VirtAddr,
+    slots: Vec<Slot>,
+    /// Counts the uses of the slots, it only goes up
+    clock: u64
+}
+
+/// How the cache is used, see [`stats`]
+pub struct CacheStats {
+    /// How many sectors the cache holds, and how many of them weren't written to their device yet
+    pub cached: usize,
+    pub dirty: usize
+}
+
+/// Returns whatever `a` and `b` are the same device. Only their data is compared, the same device can be seen
+/// through different vtables
+fn same_device(a: &Arc<dyn BlockDevice>, b: &Arc<dyn BlockDevice>) -> bool {
+    Arc::as_ptr(a) as *const u8 == Arc::as_ptr(b) as *const u8
+}
+
+impl Slot {
+    fn belongs_to(&self, device: &Arc<dyn BlockDevice>) -> bool {
+        self.device.as_ref().map_or(false, |owner| same_device(owner, device))
+    }
+}
+
+impl Cache {
+    /// Returns the data of the slot at `index`
+    #[allow(clippy::mut_from_ref)]
+    fn data(&self, index: usize) -> &mut [u8] {
+        // The slots don't overlap, and the cache is locked while a slot is used
+        unsafe { core::slice::from_raw_parts_mut((self.memory + index * SECTOR_SIZE).as_mut_ptr(), SECTOR_SIZE) }
+    }
+
+    /// Returns the slot holding `sector` of `device`, if there's one
+    fn find(&self, device: &Arc<dyn BlockDevice>, sector: u64) -> Option<usize> {
+        self.slots.iter().position(|slot| slot.sector == sector && slot.belongs_to(device))
+    }
+
+    fn touch(&mut self, index: usize) {
+        self.clock += 1;
+        self.slots[index].last_used = self.clock;
+    }
+
+    /// Writes the sector of the slot at `index` to its device if it's dirty
+    fn write_back(&mut self, index: usize) -> Result<(), KernelError> {
+        let slot = &self.slots[index];
+
+        if let (true, Some(device)) = (slot.dirty, &slot.device) {
+            device.write(slot.sector, self.data(index))?;
+            self.slots[index].dirty = false;
+        }
+
+        Ok(())
+    }
+
+    /// Writes every dirty sector of `device` to it, or of every device for [`None`]. Goes on after a failure,
+    /// returning the first error
+    fn write_back_all(&mut self, device: Option<&Arc<dyn BlockDevice>>) -> Result<(), KernelError> {
+        let mut result = Ok(());
+
+        for index in 0..self.slots.len() {
+            let slot = &self.slots[index];
+
+            if device.map_or(slot.device.is_some(), |device| slot.belongs_to(device)) {
+                result = result.and(self.write_back(index));
+            }
+        }
+
+        return result;
+    }
+
+    /// Returns a slot to hold another sector: a free one, otherwise the one used the longest time ago, whose
+    /// sector is written to its device first if it's dirty
+    ///
+    /// ## Errors
+    ///
+    /// Returns the error of writing the sector replaced, the slot keeps it then
+    fn take_slot(&mut self) -> Result<usize, KernelError> {
+        let index = match self.slots.iter().position(|slot| slot.device.is_none()) {
+            Some(index) => index,
+            None => (0..self.slots.len()).min_by_key(|&index| self.slots[index].last_used).unwrap()
+        };
+
+        self.write_back(index)?;
+        self.slots[index].device = None;
+
+        return Ok(index);
+    }
+
+    /// Returns the slot holding `sector` of `device`, reading the sector into a slot if it isn't in the cache.
+    /// With `read` unset the sector isn't read, it's about to be overwritten anyway
+    fn load(&mut self, device: &Arc<dyn BlockDevice>, sector: u64, read: bool) -> Result<usize, KernelError> {
+        if let Some(index) = self.find(device, sector) {
+            self.touch(index);
+            return Ok(index);
+        }
+
+        let index = self.take_slot()?;
+
+        if read {
+            device.read(sector, self.data(index))?;
+        }
+
+        self.slots[index] = Slot { device: Some(device.clone()), sector, dirty: false, last_used: 0 };
+        self.touch(index);
+
+        return Ok(index);
+    }
+}
+
+/// A block device whose sectors go through the cache shared by every device, so the sectors used often (like the
+/// table and the directories of a filesystem) are only read once. The transfers of a single sector are cached and
+/// the writes are only made on the device later, by [`CachedDevice::flush`] (see [`sync`]) or when the sector
+/// has to leave the cache. The larger transfers go to the device, with what's in the cache for their sectors kept
+/// up to date
+pub struct CachedDevice {
+    device: Arc<dyn BlockDevice>
+}
+
+impl CachedDevice {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Self {
+        CachedDevice { device }
+    }
+}
+
+impl BlockDevice for CachedDevice {
+    fn name(&self) -> &str {
+        self.device.name()
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.device.sector_count()
+    }
+
+    fn sector_size(&self) -> usize {
+        self.device.sector_size()
+    }
+
+    fn read(&self, sector: u64, buffer: &mut [u8]) -> Result<(), KernelError> {
+        let mut guard = CACHE.lock();
+
+        let cache = match guard.as_mut() {
+            Some(cache) if self.device.sector_size() == SECTOR_SIZE => cache,
+            _ => return self.device.read(sector, buffer)
+        };
+
+        if buffer.len() == SECTOR_SIZE {
+            block::check_transfer(self.device.as_ref(), sector, buffer.len())?;
+
+            let index = cache.load(&self.device, sector, true)?;
+            buffer.copy_from_slice(cache.data(index));
+
+            return Ok(());
+        }
+
+        self.device.read(sector, buffer)?;
+
+        // The sectors written in the cache only are newer than the ones just read
+        let end = sector + (buffer.len() / SECTOR_SIZE) as u64;
+
+        for index in 0..cache.slots.len() {
+            let slot = &cache.slots[index];
+
+            if slot.dirty && slot.belongs_to(&self.device) && (sector..end).contains(&slot.sector) {
+                let offset = (slot.sector - sector) as usize * SECTOR_SIZE;
+                buffer[offset..offset + SECTOR_SIZE].copy_from_slice(cache.data(index));
+            }
+        }
+
+        return Ok(());
+    }
+
+    fn write(&self, sector: u64, buffer: &[u8]) -> Result<(), KernelError> {
+        let mut guard = CACHE.lock();
+
+        let cache = match guard.as_mut() {
+            Some(cache) if self.device.sector_size() == SECTOR_SIZE => cache,
+            _ => return self.device.write(sector, buffer)
+        };
+
+        if buffer.len() == SECTOR_SIZE {
+            block::check_transfer(self.device.as_ref(), sector, buffer.len())?;
+
+            let index = cache.load(&self.device, sector, false)?;
+            cache.data(index).copy_from_slice(buffer);
+            cache.slots[index].dirty = true;
+
+            return Ok(());
+        }
+
+        self.device.write(sector, buffer)?;
+
+        // The sectors in the cache now hold what's on the device
+        for (offset, data) in buffer.chunks_exact(SECTOR_SIZE).enumerate() {
+            if let Some(index) = cache.find(&self.device, sector + offset as u64) {
+                cache.data(index).copy_from_slice(data);
+                cache.slots[index].dirty = false;
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Writes the sectors of the device that are only in the cache, then flushes the device itself
+    fn flush(&self) -> Result<(), KernelError> {
+        if let Some(cache) = CACHE.lock().as_mut() {
+            cache.write_back_all(Some(&self.device))?;
+        }
+
+        return self.device.flush();
+    }
+}
+
+/// Makes sure everything written to every block device is on it, going on after a failure and returning the first
+/// error
+pub fn sync() -> Result<(), KernelError> {
+    return block::devices().iter().map(|device| device.flush()).fold(Ok(()), |result, flushed| result.and(flushed));
+}
+
+/// Returns how the cache is used, [`None`] if there's no cache
+pub fn stats() -> Option<CacheStats> {
+    CACHE.lock().as_ref().map(|cache| CacheStats {
+        cached: cache.slots.iter().filter(|slot| slot.device.is_some()).count(),
+        dirty: cache.slots.iter().filter(|slot| slot.dirty).count()
+    })
+}
+
+/// The thread that writes the sectors only in the cache to their device every [`WRITEBACK_INTERVAL_MS`]
+fn writeback_loop() {
+    loop {
+        crate::task::sleep_ms(WRITEBACK_INTERVAL_MS);
+
+        if let Err(error) = sync() {
+            println!("Block cache: failed to write back ({})", error);
+        }
+    }
+}
+
+/// Allocates the memory of the cache and starts the writeback thread, without memory the devices are used
+/// directly. Must be called once after the scheduler is initialized, before any process is created
+pub fn init() {
+    // The slots are taken first, the memory of the region is never given back
+    let mut slots = Vec::new();
+
+    if let Err(error) = slots.try_reserve_exact(CACHE_SECTORS) {
+        println!("Block cache: no memory for its slots ({})", error);
+        return;
+    }
+
+    let memory = match crate::memory::mmio::allocate(CACHE_SECTORS * SECTOR_SIZE) {
+        Ok(memory) => memory,
+        Err(error) => {
+            println!("Block cache: no memory for it ({:?})", error);
+            return;
+        }
+    };
+
+    slots.extend((0..CACHE_SECTORS).map(|_| Slot { device: None, sector: 0, dirty: false, last_used: 0 }));
+    *CACHE.lock() = Some(Cache { memory, slots, clock: 0 });
+
+    if let Err(error) = crate::task::spawn_kthread(writeback_loop, "writeback") {
+        println!("Block cache: failed to start the writeback thread ({:?})", error);
+    }
+}
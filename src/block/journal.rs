@@ -0,0 +1,308 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::block::{self, BlockDevice, SECTOR_SIZE};
+use crate::println;
+use crate::sync::SleepMutex;
+use crate::utils::error::KernelError;
+
+/// What the header of a journal starts with, the header is the last sector of the device
+const MAGIC: &[u8; 8] = b"OSJOURNL";
+
+/// The smallest journal: its header, a sector of targets and a sector of data
+const MIN_JOURNAL_SECTORS: u64 = 3;
+
+/// How many sectors a transaction holds at most, and how many it has to hold to be committed at the end of a change.
+/// The changes bigger than the difference between them can be split between two transactions
+const MAX_TRANSACTION_SECTORS: usize = 64;
+const COMMIT_THRESHOLD: usize = 32;
+
+/// How many targets (the sectors of the device the data of the journal goes to) a sector of the journal holds
+const TARGETS_PER_SECTOR: usize = SECTOR_SIZE / 8;
+
+/// The start and the prime of the FNV-1a hash, the checksum of a transaction
+const FNV_OFFSET: u32 = 0x811C_9DC5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+/// A block device whose end holds a journal, so the changes of the filesystem on it are either made completely or
+/// not at all when the machine stops in the middle of them. The writes are gathered in a transaction in memory,
+/// which is committed at the end of a change once it's big enough (see [`BlockDevice::end_change`]) or by a flush:
+/// its sectors are written to the journal first, then to where they belong. A transaction committed to the journal
+/// but not written where it belongs is written again when the device is opened, see [`open`]
+///
+/// The journal is made of the last sectors of the device: the sectors with the targets of the transaction (8 bytes
+/// each), the sectors of data, then the header. The header has [`MAGIC`], the size of the journal in sectors
+/// (4 bytes), the number of sectors of the transaction committed (4 bytes, 0 when there's none) and the checksum of
+/// the sectors of targets and data (4 bytes). Everything written goes through the journal, data included
+pub struct JournaledDevice {
+    device: Arc<dyn BlockDevice>,
+    /// The first sector of the journal, the sectors before it are the ones given to the filesystem
+    start: u64,
+    /// The size of the journal in sectors, with its header
+    sectors: u64,
+    /// The sectors written since the last commit, by sector of the device
+    transaction: SleepMutex<BTreeMap<u64, Box<[u8; SECTOR_SIZE]>>>
+}
+
+fn checksum(hash: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(hash, |hash, &byte| (hash ^ byte as u32).wrapping_mul(FNV_PRIME))
+}
+
+/// Returns how many sectors of targets a transaction of `count` sectors needs
+fn target_sectors(count: usize) -> u64 {
+    ((count + TARGETS_PER_SECTOR - 1) / TARGETS_PER_SECTOR) as u64
+}
+
+impl JournaledDevice {
+    /// How many sectors a transaction can hold, they fit in the journal with their targets
+    fn capacity(&self) -> usize {
+        let available = self.sectors - 1;
+        let fitting = available - (available + TARGETS_PER_SECTOR as u64) / (TARGETS_PER_SECTOR as u64 + 1);
+
+        return (fitting as usize).min(MAX_TRANSACTION_SECTORS);
+    }
+
+    fn header_sector(&self) -> u64 {
+        self.start + self.sectors - 1
+    }
+
+    fn write_header(&self, count: u32, hash: u32) -> Result<(), KernelError> {
+        let mut header = [0; SECTOR_SIZE];
+
+        header[0..8].copy_from_slice(MAGIC);
+        header[8..12].copy_from_slice(&(self.sectors as u32).to_le_bytes());
+        header[12..16].copy_from_slice(&count.to_le_bytes());
+        header[16..20].copy_from_slice(&hash.to_le_bytes());
+
+        return self.device.write(self.header_sector(), &header);
+    }
+
+    /// Writes the transaction to the journal, then where its sectors belong, emptying it. It stays as it is after a
+    /// failure, to be committed again later
+    fn commit(&self, transaction: &mut BTreeMap<u64, Box<[u8; SECTOR_SIZE]>>) -> Result<(), KernelError> {
+        if transaction.is_empty() {
+            return Ok(());
+        }
+
+        let targets = target_sectors(transaction.len());
+        let mut sector = [0; SECTOR_SIZE];
+        let mut hash = FNV_OFFSET;
+
+        for index in 0..targets {
+            let first = index as usize * TARGETS_PER_SECTOR;
+            sector.fill(0);
+
+            for (slot, target) in sector.chunks_exact_mut(8).zip(transaction.keys().skip(first)) {
+                slot.copy_from_slice(&target.to_le_bytes());
+            }
+
+            hash = checksum(hash, &sector);
+            self.device.write(self.start + index, &sector)?;
+        }
+
+        for (index, data) in transaction.values().enumerate() {
+            hash = checksum(hash, data.as_slice());
+            self.device.write(self.start + targets + index as u64, data.as_slice())?;
+        }
+
+        // The transaction is committed once its header is on the device, which is only written after the rest of it
+        self.device.flush()?;
+        self.write_header(transaction.len() as u32, hash)?;
+        self.device.flush()?;
+
+        for (&target, data) in transaction.iter() {
+            self.device.write(target, data.as_slice())?;
+        }
+
+        self.device.flush()?;
+        self.write_header(0, 0)?;
+
+        transaction.clear();
+        Ok(())
+    }
+
+    /// Writes the transaction committed to the journal where its sectors belong, if there's one, returning how many
+    /// sectors it had. A transaction whose checksum is wrong is left out, its header was written over a damaged one
+    fn replay(&self) -> Result<usize, KernelError> {
+        let mut header = [0; SECTOR_SIZE];
+        self.device.read(self.header_sector(), &mut header)?;
+
+        let count = u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize;
+        let expected = u32::from_le_bytes(header[16..20].try_into().unwrap());
+
+        if count == 0 {
+            return Ok(0);
+        }
+
+        if count > self.capacity() {
+            return Err(KernelError::Io);
+        }
+
+        let targets = target_sectors(count);
+        let mut sector = [0; SECTOR_SIZE];
+        let mut destinations = Vec::new();
+        let mut hash = FNV_OFFSET;
+
+        for index in 0..targets {
+            self.device.read(self.start + index, &mut sector)?;
+            hash = checksum(hash, &sector);
+
+            let first = index as usize * TARGETS_PER_SECTOR;
+
+            for target in sector.chunks_exact(8).take(count - first) {
+                destinations.push(u64::from_le_bytes(target.try_into().unwrap()));
+            }
+        }
+
+        if destinations.iter().any(|&target| target >= self.start) {
+            return Err(KernelError::Io);
+        }
+
+        for index in 0..count as u64 {
+            self.device.read(self.start + targets + index, &mut sector)?;
+            hash = checksum(hash, &sector);
+        }
+
+        if hash != expected {
+            self.write_header(0, 0)?;
+            return Ok(0);
+        }
+
+        // The transaction is written where it belongs like when it was committed, a crash now replays it again
+        for (index, &target) in destinations.iter().enumerate() {
+            self.device.read(self.start + targets + index as u64, &mut sector)?;
+            self.device.write(target, &sector)?;
+        }
+
+        self.device.flush()?;
+        self.write_header(0, 0)?;
+        self.device.flush()?;
+
+        return Ok(count);
+    }
+}
+
+impl BlockDevice for JournaledDevice {
+    fn name(&self) -> &str {
+        self.device.name()
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.start
+    }
+
+    /// The sectors of the transaction are newer than the ones of the device
+    fn read(&self, sector: u64, buffer: &mut [u8]) -> Result<(), KernelError> {
+        let count = block::check_transfer(self, sector, buffer.len())?;
+        let transaction = self.transaction.lock();
+
+        self.device.read(sector, buffer)?;
+
+        for (&target, data) in transaction.range(sector..sector + count) {
+            let offset = (target - sector) as usize * SECTOR_SIZE;
+            buffer[offset..offset + SECTOR_SIZE].copy_from_slice(data.as_slice());
+        }
+
+        Ok(())
+    }
+
+    /// The sectors are only kept in the transaction, when it's full it's committed first
+    fn write(&self, sector: u64, buffer: &[u8]) -> Result<(), KernelError> {
+        block::check_transfer(self, sector, buffer.len())?;
+
+        let mut transaction = self.transaction.lock();
+        let capacity = self.capacity();
+
+        for (index, data) in buffer.chunks_exact(SECTOR_SIZE).enumerate() {
+            let target = sector + index as u64;
+
+            if transaction.len() >= capacity && !transaction.contains_key(&target) {
+                self.commit(&mut transaction)?;
+            }
+
+            transaction.insert(target, Box::new(data.try_into().unwrap()));
+        }
+
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), KernelError> {
+        self.commit(&mut self.transaction.lock())?;
+        return self.device.flush();
+    }
+
+    /// Commits the transaction when it holds [`COMMIT_THRESHOLD`] sectors, the smaller ones wait for the next changes
+    fn end_change(&self) -> Result<(), KernelError> {
+        let mut transaction = self.transaction.lock();
+
+        if transaction.len() >= COMMIT_THRESHOLD {
+            self.commit(&mut transaction)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns `device` with its journal if it has one, after writing the transaction the journal holds where it
+/// belongs (when the machine stopped in the middle of it). A device without a journal is returned as it is
+///
+/// ## Errors
+///
+/// Returns [`KernelError::Io`] if the journal is damaged, otherwise the error of reading or writing the device
+pub fn open(device: Arc<dyn BlockDevice>) -> Result<Arc<dyn BlockDevice>, KernelError> {
+    if device.sector_size() != SECTOR_SIZE || device.sector_count() < MIN_JOURNAL_SECTORS {
+        return Ok(device);
+    }
+
+    let mut header = [0; SECTOR_SIZE];
+    device.read(device.sector_count() - 1, &mut header)?;
+
+    if &header[0..8] != MAGIC {
+        return Ok(device);
+    }
+
+    let sectors = u32::from_le_bytes(header[8..12].try_into().unwrap()) as u64;
+
+    if !(MIN_JOURNAL_SECTORS..device.sector_count()).contains(&sectors) {
+        return Err(KernelError::Io);
+    }
+
+    let journal = JournaledDevice {
+        start: device.sector_count() - sectors,
+        device,
+        sectors,
+        transaction: SleepMutex::new(BTreeMap::new())
+    };
+
+    let replayed = journal.replay()?;
+
+    if replayed != 0 {
+        println!("Journal: wrote {} sectors of an unfinished transaction to {}", replayed, journal.name());
+    }
+
+    return Ok(Arc::new(journal));
+}
+
+/// Makes the last `sectors` sectors of `device` an empty journal, which is used from the next time the device is
+/// opened. Whatever was in those sectors is lost, the filesystem on the device must end before them
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if the journal is smaller than 3 sectors or doesn't leave any sector to
+/// the filesystem, otherwise the error of writing the device
+pub fn format(device: Arc<dyn BlockDevice>, sectors: u64) -> Result<(), KernelError> {
+    if device.sector_size() != SECTOR_SIZE || !(MIN_JOURNAL_SECTORS..device.sector_count()).contains(&sectors) {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let journal = JournaledDevice {
+        start: device.sector_count() - sectors,
+        device,
+        sectors,
+        transaction: SleepMutex::new(BTreeMap::new())
+    };
+
+    journal.write_header(0, 0)?;
+    return journal.device.flush();
+}
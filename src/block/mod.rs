@@ -0,0 +1,127 @@
+pub mod ahci;
+pub mod ata;
+pub mod cache;
+pub mod journal;
+pub mod nvme;
+pub mod queue;
+pub mod ramdisk;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::drivers::registry;
+use crate::utils::error::KernelError;
+use crate::utils::Mutex;
+use crate::workqueue::WorkQueue;
+use self::cache::CachedDevice;
+use self::queue::RequestQueue;
+
+/// The size of a sector, the smallest unit a [`BlockDevice`] reads or writes
+pub const SECTOR_SIZE: usize = 512;
+
+/// The queues of every block device found by the drivers, in the order they were found
+static QUEUES: Mutex<Vec<Arc<RequestQueue>>> = Mutex::new(Vec::new());
+
+/// A device that stores data in sectors of [`SECTOR_SIZE`] bytes, like a disk
+#[allow(dead_code)]
+pub trait BlockDevice: Send + Sync {
+    /// A short name that identifies the device, like `ata0`
+    fn name(&self) -> &str;
+
+    /// How many sectors the device has
+    fn sector_count(&self) -> u64;
+
+    /// The size of a sector of the device in bytes, every transfer is made of whole sectors
+    fn sector_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    /// Reads the sectors starting at `sector` into `buffer`, as many as fit in it
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if the size of `buffer` isn't a multiple of [`SECTOR_SIZE`] or
+    /// the sectors go past the end of the device, otherwise the error reported by the device
+    fn read(&self, sector: u64, buffer: &mut [u8]) -> Result<(), KernelError>;
+
+    /// Writes `buffer` to the sectors starting at `sector`, the data is on the device once this returns
+    ///
+    /// ## Errors
+    ///
+    /// Same as [`BlockDevice::read`]
+    fn write(&self, sector: u64, buffer: &[u8]) -> Result<(), KernelError>;
+
+    /// Makes sure everything written so far is on the device. The drivers flush the cache of the device after
+    /// every write already, so by default there's nothing left to do
+    ///
+    /// ## Errors
+    ///
+    /// Returns the error reported by the device
+    fn flush(&self) -> Result<(), KernelError> {
+        Ok(())
+    }
+
+    /// Tells the device a change of the filesystem on it is over, like adding an entry to a directory. A device with
+    /// a journal (see [`journal::JournaledDevice`]) makes the whole change or none of it, the others have nothing
+    /// to do
+    ///
+    /// ## Errors
+    ///
+    /// Returns the error of writing the change, it's written again later
+    fn end_change(&self) -> Result<(), KernelError> {
+        Ok(())
+    }
+}
+
+/// Checks that a transfer of `buffer_size` bytes starting at `sector` fits in `device`, returning how
+/// many sectors it covers
+pub fn check_transfer(device: &dyn BlockDevice, sector: u64, buffer_size: usize) -> Result<u64, KernelError> {
+    if buffer_size % device.sector_size() != 0 {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let count = (buffer_size / device.sector_size()) as u64;
+
+    match sector.checked_add(count) {
+        Some(end) if end <= device.sector_count() => Ok(count),
+        _ => Err(KernelError::InvalidArgument)
+    }
+}
+
+/// Makes a device available to the rest of the kernel with a queue of its own, called by the drivers when they
+/// find one. Everything else sees the device through the cache, see [`CachedDevice`]. Must be called before any
+/// process is created, see [`RequestQueue::new`]
+pub fn register(device: Arc<dyn BlockDevice>) {
+    let device: Arc<dyn BlockDevice> = Arc::new(CachedDevice::new(device));
+    QUEUES.lock().push(Arc::new(RequestQueue::new(device)));
+}
+
+/// Returns every block device found so far
+#[allow(dead_code)]
+pub fn devices() -> Vec<Arc<dyn BlockDevice>> {
+    QUEUES.lock().iter().map(|queue| queue.device().clone()).collect()
+}
+
+/// Returns the block device with the given name
+#[allow(dead_code)]
+pub fn find(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    find_queue(name).map(|queue| queue.device().clone())
+}
+
+/// Returns the request queue of the block device with the given name
+#[allow(dead_code)]
+pub fn find_queue(name: &str) -> Option<Arc<RequestQueue>> {
+    QUEUES.lock().iter().find(|queue| queue.device().name() == name).cloned()
+}
+
+/// Creates the work queue the requests run on and the cache, and registers the probes of every block driver, the
+/// devices are found by [`crate::drivers::registry::probe_all`]. Must be called once after the scheduler is
+/// initialized
+pub fn init() {
+    WorkQueue::create(queue::WORK_QUEUE_NAME).expect("Failed to create the block work queue");
+    cache::init();
+
+    registry::register(ahci::probe);
+    registry::register(nvme::probe);
+    registry::register_platform("ide", ata::probe);
+    registry::register_platform("ram0", ramdisk::probe);
+}
@@ -0,0 +1,625 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::block::{self, BlockDevice, SECTOR_SIZE};
+use crate::drivers::registry::Driver;
+use crate::memory::dma::DmaBuffer;
+use crate::pci::PciDevice;
+use crate::println;
+use crate::sched::WaitQueue;
+use crate::sync::SleepMutex;
+use crate::time;
+use crate::utils::error::KernelError;
+
+/// The class, the subclass and the programming interface of an NVMe controller on the PCI bus
+const NVME_CLASS: (u8, u8, u8) = (0x01, 0x08, 0x02);
+
+/// The register of the PCI command of a function and its bits that let the device answer to its memory,
+/// start transfers on its own and raise its interrupt (this one is set to disable it)
+const PCI_COMMAND: u16 = 0x04;
+const PCI_COMMAND_MEMORY: u32 = 1 << 1;
+const PCI_COMMAND_BUS_MASTER: u32 = 1 << 2;
+const PCI_COMMAND_INTERRUPT_DISABLE: u32 = 1 << 10;
+
+/// The BAR with the registers of the controller, a 64 bits one takes the next BAR too
+const PCI_BAR0: u16 = 0x10;
+const PCI_BAR1: u16 = 0x14;
+
+/// The register with the IRQ line the firmware routed the interrupt of the device to, in its lowest byte
+const PCI_INTERRUPT_LINE: u16 = 0x3C;
+
+/// How much of the registers is mapped, the doorbells of the queues used here always fall inside it
+const REGISTERS_SIZE: usize = 0x4000;
+
+/// The registers of the controller, as offsets from the start of its BAR
+const REGISTER_CAPABILITIES: usize = 0x00;
+const REGISTER_INTERRUPT_MASK_SET: usize = 0x0C;
+const REGISTER_INTERRUPT_MASK_CLEAR: usize = 0x10;
+const REGISTER_CONFIGURATION: usize = 0x14;
+const REGISTER_STATUS: usize = 0x1C;
+const REGISTER_ADMIN_QUEUE_ATTRIBUTES: usize = 0x24;
+const REGISTER_ADMIN_SUBMISSION_QUEUE: usize = 0x28;
+const REGISTER_ADMIN_COMPLETION_QUEUE: usize = 0x30;
+const REGISTER_DOORBELLS: usize = 0x1000;
+
+/// The bits of the configuration: enabled, with submission entries of 64 bytes (2^6) and completion
+/// entries of 16 bytes (2^4). Everything else is left at 0, which selects the NVM command set and 4 KiB pages
+const CONFIGURATION_ENABLE: u32 = 1 << 0;
+const CONFIGURATION_ENTRY_SIZES: u32 = 6 << 16 | 4 << 20;
+
+/// The bits of the status of the controller
+const STATUS_READY: u32 = 1 << 0;
+const STATUS_FATAL: u32 = 1 << 1;
+
+/// The size of the entries of the queues, as set in the configuration
+const SUBMISSION_ENTRY_SIZE: usize = 64;
+const COMPLETION_ENTRY_SIZE: usize = 16;
+
+/// How many entries each queue has at most, both kinds of queues fit in a single page with this many
+const QUEUE_ENTRIES: u16 = 64;
+
+/// The ID of the only I/O queue pair, the admin queues always have 0
+const IO_QUEUE_ID: u16 = 1;
+
+const ADMIN_CREATE_IO_SUBMISSION_QUEUE: u8 = 0x01;
+const ADMIN_CREATE_IO_COMPLETION_QUEUE: u8 = 0x05;
+const ADMIN_IDENTIFY: u8 = 0x06;
+
+/// What the identify command describes
+const IDENTIFY_NAMESPACE: u32 = 0;
+const IDENTIFY_CONTROLLER: u32 = 1;
+
+const IO_FLUSH: u8 = 0x00;
+const IO_WRITE: u8 = 0x01;
+const IO_READ: u8 = 0x02;
+
+/// The bits of the creation of an I/O queue: its memory is physically contiguous and (for a completion queue)
+/// it raises interrupts
+const QUEUE_CONTIGUOUS: u32 = 1 << 0;
+const QUEUE_INTERRUPTS: u32 = 1 << 1;
+
+/// How many pages the buffer of the controller has, which limits how much data a single command transfers
+const BUFFER_PAGES: usize = 16;
+
+/// How many namespaces are looked for at most
+const MAX_NAMESPACES: u32 = 16;
+
+/// How long a command can take before it's considered failed
+const COMMAND_TIMEOUT_MS: u64 = 2000;
+
+/// Where the registers of the controller are mapped, 0 until [`init`] finds one. Only the first NVMe controller is used
+static REGISTERS: AtomicU64 = AtomicU64::new(0);
+
+/// Set once the interrupt of the controller is handled, otherwise the completions are polled
+static INTERRUPTS: AtomicBool = AtomicBool::new(false);
+
+/// The threads waiting for a completion of the I/O queue
+static WAITERS: WaitQueue = WaitQueue::new();
+
+/// The memory-mapped registers of the controller
+#[derive(Copy, Clone)]
+struct Registers(VirtAddr);
+
+impl Registers {
+    fn read(&self, register: usize) -> u32 {
+        unsafe { core::ptr::read_volatile((self.0 + register).as_ptr::<u32>()) }
+    }
+
+    fn write(&self, register: usize, value: u32) {
+        unsafe { core::ptr::write_volatile((self.0 + register).as_mut_ptr::<u32>(), value) };
+    }
+
+    /// The 64 bits registers are accessed as two halves, the low one first
+    fn read_u64(&self, register: usize) -> u64 {
+        (self.read(register + 4) as u64) << 32 | self.read(register) as u64
+    }
+
+    fn write_u64(&self, register: usize, value: u64) {
+        self.write(register, value as u32);
+        self.write(register + 4, (value >> 32) as u32);
+    }
+}
+
+/// Called by the interrupt handler of the IRQ line of the controller. The interrupt stays raised until the completions
+/// are consumed, which the waiting thread does, so it's masked here and unmasked again before the next command
+///
+/// ## Note
+///
+/// This function must not allocate or block since it runs inside an interrupt handler
+pub fn handle_interrupt() {
+    let base = REGISTERS.load(Ordering::Acquire);
+
+    if base == 0 {
+        return;
+    }
+
+    // The line may be shared with other devices, masking it when the controller didn't raise it does no harm
+    Registers(VirtAddr::new(base)).write(REGISTER_INTERRUPT_MASK_SET, 1);
+    WAITERS.wake_all();
+}
+
+/// Polls `condition` until it's true, or fails with [`KernelError::Timeout`] after `ms` milliseconds
+fn wait_for(ms: u64, condition: impl Fn() -> bool) -> Result<(), KernelError> {
+    let deadline = time::ticks() + time::ms_to_ticks(ms);
+
+    while !condition() {
+        if time::ticks() > deadline {
+            return Err(KernelError::Timeout);
+        }
+
+        core::hint::spin_loop();
+    }
+
+    Ok(())
+}
+
+/// A submission queue together with the completion queue its commands complete on, each in a page of its own
+struct QueuePair {
+    entries: u16,
+    submissions: DmaBuffer,
+    completions: DmaBuffer,
+    /// Where the next command goes
+    tail: u16,
+    /// Where the next completion is expected
+    head: u16,
+    /// The phase bit of the completions written in the current pass over the queue, the controller flips it every pass
+    phase: bool,
+    next_command: u16
+}
+
+impl QueuePair {
+    fn new(entries: u16) -> Result<Self, KernelError> {
+        Ok(QueuePair {
+            entries,
+            submissions: DmaBuffer::new(1)?,
+            completions: DmaBuffer::new(1)?,
+            tail: 0,
+            head: 0,
+            phase: true,
+            next_command: 0
+        })
+    }
+
+    /// Returns the status word of the completion at the head of the queue, if the controller already wrote it
+    fn peek(&self) -> Option<u32> {
+        let entry = unsafe { self.completions.page(0).add(self.head as usize * COMPLETION_ENTRY_SIZE) } as *const u32;
+        let status = unsafe { core::ptr::read_volatile(entry.add(3)) };
+
+        return (status >> 16 & 1 == self.phase as u32).then_some(status);
+    }
+}
+
+/// The registers and the queues of the controller, shared by all its namespaces
+struct Controller {
+    registers: Registers,
+    /// The distance between two doorbells
+    doorbell_stride: usize,
+    admin: QueuePair,
+    io: QueuePair,
+    /// The page with the PRP (Physical Region Page) list of the transfers of more than two pages
+    prp_list: DmaBuffer,
+    buffer: DmaBuffer
+}
+
+impl Controller {
+    fn submission_doorbell(&self, queue: u16) -> usize {
+        REGISTER_DOORBELLS + 2 * queue as usize * self.doorbell_stride
+    }
+
+    fn completion_doorbell(&self, queue: u16) -> usize {
+        REGISTER_DOORBELLS + (2 * queue as usize + 1) * self.doorbell_stride
+    }
+
+    /// Points the command at the first `size` bytes of the buffer, through the PRP list if it needs more than two pages
+    fn set_data(&self, command: &mut [u32; 16], size: usize) {
+        let pages = (size + 4095) / 4096;
+
+        let second = match pages {
+            0 | 1 => 0,
+            2 => self.buffer.physical(1).as_u64(),
+            _ => {
+                let list = self.prp_list.page(0) as *mut u64;
+
+                for index in 1..pages {
+                    unsafe { core::ptr::write_volatile(list.add(index - 1), self.buffer.physical(index).as_u64()) };
+                }
+
+                self.prp_list.physical(0).as_u64()
+            }
+        };
+
+        let first = if pages == 0 { 0 } else { self.buffer.physical(0).as_u64() };
+
+        command[6] = first as u32;
+        command[7] = (first >> 32) as u32;
+        command[8] = second as u32;
+        command[9] = (second >> 32) as u32;
+    }
+
+    /// Submits a command to the admin queue or (if `io` is set) to the I/O queue and waits for its completion.
+    /// The commands of the I/O queue sleep until the interrupt of the controller when it's handled, the others are polled
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Timeout`] if the command never completes, or [`KernelError::Io`] if it fails
+    fn execute(&mut self, io: bool, mut command: [u32; 16]) -> Result<(), KernelError> {
+        let registers = self.registers;
+        let submission_doorbell = self.submission_doorbell(if io { IO_QUEUE_ID } else { 0 });
+        let completion_doorbell = self.completion_doorbell(if io { IO_QUEUE_ID } else { 0 });
+        let queue = if io { &mut self.io } else { &mut self.admin };
+
+        command[0] |= (queue.next_command as u32) << 16;
+        queue.next_command = queue.next_command.wrapping_add(1);
+
+        let entry = unsafe { queue.submissions.page(0).add(queue.tail as usize * SUBMISSION_ENTRY_SIZE) } as *mut u32;
+
+        for (index, &word) in command.iter().enumerate() {
+            unsafe { core::ptr::write_volatile(entry.add(index), word) };
+        }
+
+        queue.tail = (queue.tail + 1) % queue.entries;
+
+        let interrupts = io && INTERRUPTS.load(Ordering::Acquire);
+
+        if interrupts {
+            registers.write(REGISTER_INTERRUPT_MASK_CLEAR, 1);
+        }
+
+        registers.write(submission_doorbell, queue.tail as u32);
+
+        let queue = &*queue;
+
+        // The interrupt only wakes the thread up, the completion itself is found through its phase bit
+        let finished = if interrupts {
+            WAITERS.wait_until_timeout(COMMAND_TIMEOUT_MS, || queue.peek().is_some())
+        } else {
+            wait_for(COMMAND_TIMEOUT_MS, || queue.peek().is_some()).is_ok()
+        };
+
+        let status = match queue.peek() {
+            Some(status) if finished => status,
+            _ => return Err(KernelError::Timeout)
+        };
+
+        let queue = if io { &mut self.io } else { &mut self.admin };
+
+        queue.head = (queue.head + 1) % queue.entries;
+
+        if queue.head == 0 {
+            queue.phase = !queue.phase;
+        }
+
+        registers.write(completion_doorbell, queue.head as u32);
+
+        // Bits 17 to 31 have the status code, 0 means success
+        if status >> 17 != 0 {
+            return Err(KernelError::Io);
+        }
+
+        Ok(())
+    }
+
+    /// Reads the identify data structure selected by `kind` (of the namespace `namespace`, for the ones about
+    /// a namespace) into the first page of the buffer
+    fn identify(&mut self, kind: u32, namespace: u32) -> Result<(), KernelError> {
+        let mut command = new_command(ADMIN_IDENTIFY, namespace);
+        command[10] = kind;
+
+        self.set_data(&mut command, 4096);
+        self.execute(false, command)
+    }
+
+    /// Creates the I/O queue pair, its completions raise the interrupt of the controller
+    fn create_io_queues(&mut self) -> Result<(), KernelError> {
+        let size = (self.io.entries as u32 - 1) << 16 | IO_QUEUE_ID as u32;
+
+        let mut command = new_command(ADMIN_CREATE_IO_COMPLETION_QUEUE, 0);
+        let completions = self.io.completions.physical(0).as_u64();
+
+        command[6] = completions as u32;
+        command[7] = (completions >> 32) as u32;
+        command[10] = size;
+        command[11] = QUEUE_CONTIGUOUS | QUEUE_INTERRUPTS;
+
+        self.execute(false, command)?;
+
+        let mut command = new_command(ADMIN_CREATE_IO_SUBMISSION_QUEUE, 0);
+        let submissions = self.io.submissions.physical(0).as_u64();
+
+        command[6] = submissions as u32;
+        command[7] = (submissions >> 32) as u32;
+        command[10] = size;
+        command[11] = QUEUE_CONTIGUOUS | (IO_QUEUE_ID as u32) << 16;
+
+        self.execute(false, command)
+    }
+
+    /// Returns a pointer to the data the last identify command left in the buffer
+    fn identify_data(&self) -> *const u8 {
+        self.buffer.page(0)
+    }
+}
+
+/// Builds a command with the given opcode for the given namespace, with every other field set to 0
+fn new_command(opcode: u8, namespace: u32) -> [u32; 16] {
+    let mut command = [0u32; 16];
+
+    command[0] = opcode as u32;
+    command[1] = namespace;
+
+    return command;
+}
+
+/// A namespace of an NVMe controller, which is what the disk exposes as a block device.
+/// Only the namespaces formatted with sectors of [`SECTOR_SIZE`] bytes are used
+pub struct NvmeDrive {
+    name: String,
+    /// The model reported by the controller
+    model: String,
+    controller: Arc<SleepMutex<Controller>>,
+    namespace: u32,
+    sectors: u64,
+    /// How many sectors a single command transfers at most
+    max_sectors: usize,
+    /// Whatever the controller has a volatile write cache, which has to be flushed after the writes
+    write_cache: bool
+}
+
+impl NvmeDrive {
+    /// Returns the model reported by the controller
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Runs a read or a write (if `write` is set) of up to [`NvmeDrive::max_sectors`] sectors, the data is
+    /// already in the buffer of the controller for a write and is left there by a read
+    fn transfer(&self, controller: &mut Controller, sector: u64, count: usize, write: bool) -> Result<(), KernelError> {
+        let mut command = new_command(if write { IO_WRITE } else { IO_READ }, self.namespace);
+
+        controller.set_data(&mut command, count * SECTOR_SIZE);
+        command[10] = sector as u32;
+        command[11] = (sector >> 32) as u32;
+        command[12] = count as u32 - 1;
+
+        controller.execute(true, command)?;
+
+        if write && self.write_cache {
+            controller.execute(true, new_command(IO_FLUSH, self.namespace))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl BlockDevice for NvmeDrive {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read(&self, sector: u64, buffer: &mut [u8]) -> Result<(), KernelError> {
+        block::check_transfer(self, sector, buffer.len())?;
+
+        let mut controller = self.controller.lock();
+        let mut sector = sector;
+
+        for chunk in buffer.chunks_mut(self.max_sectors * SECTOR_SIZE) {
+            let count = chunk.len() / SECTOR_SIZE;
+
+            self.transfer(&mut controller, sector, count, false)?;
+            controller.buffer.copy_out(chunk);
+            sector += count as u64;
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, sector: u64, buffer: &[u8]) -> Result<(), KernelError> {
+        block::check_transfer(self, sector, buffer.len())?;
+
+        let mut controller = self.controller.lock();
+        let mut sector = sector;
+
+        for chunk in buffer.chunks(self.max_sectors * SECTOR_SIZE) {
+            let count = chunk.len() / SECTOR_SIZE;
+
+            controller.buffer.copy_in(chunk);
+            self.transfer(&mut controller, sector, count, true)?;
+            sector += count as u64;
+        }
+
+        Ok(())
+    }
+}
+
+/// Maps the registers of `controller`, after letting it answer to its memory, start transfers on its own and
+/// raise its interrupt. Returns the registers and the IRQ line of the controller, if it has one the PIC can deliver
+fn map_controller(controller: &PciDevice) -> Option<(Registers, Option<u8>)> {
+    // Bit 0 tells whatever the BAR has I/O ports, bits 1 and 2 whatever it's a 64 bits one
+    let bar = controller.read_config(PCI_BAR0);
+
+    if bar & 1 != 0 {
+        return None;
+    }
+
+    let high = if bar >> 1 & 0b11 == 0b10 { controller.read_config(PCI_BAR1) as u64 } else { 0 };
+    let address = high << 32 | (bar & !0xF) as u64;
+
+    if address == 0 {
+        return None;
+    }
+
+    let command = controller.read_config(PCI_COMMAND);
+    let command = command & !PCI_COMMAND_INTERRUPT_DISABLE | PCI_COMMAND_MEMORY | PCI_COMMAND_BUS_MASTER;
+    controller.write_config(PCI_COMMAND, command);
+
+    let base = crate::memory::mmio::map(PhysAddr::new(address), REGISTERS_SIZE).ok()?;
+
+    // 0xFF means the firmware didn't route the interrupt anywhere
+    let irq = controller.read_config(PCI_INTERRUPT_LINE) as u8;
+
+    return Some((Registers(base), (irq < 16).then_some(irq)));
+}
+
+/// Resets the controller and sets up its admin queues and its I/O queues
+fn enable(registers: Registers) -> Result<Controller, KernelError> {
+    let capabilities = registers.read_u64(REGISTER_CAPABILITIES);
+
+    // The maximum queue size (minus one) is in bits 0 to 15, the doorbell stride (as a power of 2 of
+    // 4 bytes) in bits 32 to 35 and the time the controller can take to get ready (in 500ms units) in bits 24 to 31
+    let entries = QUEUE_ENTRIES.min((capabilities & 0xFFFF) as u16 + 1);
+    let doorbell_stride = 4 << (capabilities >> 32 & 0xF);
+    let ready_timeout = (capabilities >> 24 & 0xFF).max(1) * 500;
+
+    let mut controller = Controller {
+        registers,
+        doorbell_stride,
+        admin: QueuePair::new(entries)?,
+        io: QueuePair::new(entries)?,
+        prp_list: DmaBuffer::new(1)?,
+        buffer: DmaBuffer::new(BUFFER_PAGES)?
+    };
+
+    if controller.completion_doorbell(IO_QUEUE_ID) + 4 > REGISTERS_SIZE {
+        return Err(KernelError::Unsupported);
+    }
+
+    // The queues can only be set up while the controller is disabled
+    registers.write(REGISTER_CONFIGURATION, registers.read(REGISTER_CONFIGURATION) & !CONFIGURATION_ENABLE);
+    wait_for(ready_timeout, || registers.read(REGISTER_STATUS) & STATUS_READY == 0)?;
+
+    let size = (entries as u32 - 1) << 16 | (entries as u32 - 1);
+
+    registers.write(REGISTER_ADMIN_QUEUE_ATTRIBUTES, size);
+    registers.write_u64(REGISTER_ADMIN_SUBMISSION_QUEUE, controller.admin.submissions.physical(0).as_u64());
+    registers.write_u64(REGISTER_ADMIN_COMPLETION_QUEUE, controller.admin.completions.physical(0).as_u64());
+
+    // The admin commands are polled, the interrupt stays masked until the I/O commands need it
+    registers.write(REGISTER_INTERRUPT_MASK_SET, 1);
+    registers.write(REGISTER_CONFIGURATION, CONFIGURATION_ENABLE | CONFIGURATION_ENTRY_SIZES);
+
+    wait_for(ready_timeout, || registers.read(REGISTER_STATUS) & (STATUS_READY | STATUS_FATAL) != 0)?;
+
+    if registers.read(REGISTER_STATUS) & STATUS_FATAL != 0 {
+        return Err(KernelError::Io);
+    }
+
+    controller.create_io_queues()?;
+    Ok(controller)
+}
+
+/// Reads the ASCII text in the given bytes of the identify data, padded with spaces
+fn identify_string(data: *const u8, start: usize, end: usize) -> String {
+    let bytes = unsafe { core::slice::from_raw_parts(data.add(start), end - start) };
+    let text: String = bytes.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '?' }).collect();
+
+    return String::from(text.trim());
+}
+
+/// The driver bound to the NVMe controller
+struct NvmeDriver;
+
+impl Driver for NvmeDriver {
+    fn name(&self) -> &'static str {
+        "nvme"
+    }
+}
+
+/// Binds to `device` if it's an NVMe controller, setting it up and registering each of its active namespaces as
+/// `nvme0nN`, where N is the ID of the namespace. Only the first controller is used
+pub fn probe(device: &PciDevice) -> Option<Box<dyn Driver>> {
+    if (device.class, device.subclass, device.prog_if) != NVME_CLASS || REGISTERS.load(Ordering::Acquire) != 0 {
+        return None;
+    }
+
+    let (registers, irq) = map_controller(device)?;
+
+    let mut controller = match enable(registers) {
+        Ok(controller) => controller,
+        Err(error) => {
+            println!("NVMe: failed to set up the controller: {:?}", error);
+            return None;
+        }
+    };
+
+    controller.identify(IDENTIFY_CONTROLLER, 0).ok()?;
+
+    // The model is in bytes 24 to 63, the maximum transfer size (as a power of 2 of pages, 0 for no limit)
+    // in byte 77, whatever there's a volatile write cache in bit 0 of byte 525 and the namespace count in bytes 516 to 519
+    let data = controller.identify_data();
+    let model = identify_string(data, 24, 64);
+
+    let (max_transfer, write_cache, namespaces) = unsafe {
+        (*data.add(77), *data.add(525) & 1 != 0, core::ptr::read_unaligned(data.add(516) as *const u32))
+    };
+
+    let mut max_sectors = BUFFER_PAGES * 4096 / SECTOR_SIZE;
+
+    if max_transfer != 0 && (max_transfer as u32) < 16 {
+        max_sectors = max_sectors.min((4096 << max_transfer) / SECTOR_SIZE);
+    }
+
+    let mut found = Vec::new();
+
+    for namespace in 1..=namespaces.min(MAX_NAMESPACES) {
+        if controller.identify(IDENTIFY_NAMESPACE, namespace).is_err() {
+            continue;
+        }
+
+        // The size in sectors is in bytes 0 to 7 (0 for an inactive namespace), the index of the format in use
+        // in the low bits of byte 26 and the formats themselves start at byte 128, with the sector size
+        // (as a power of 2) in their third byte
+        let data = controller.identify_data();
+
+        let (sectors, sector_shift) = unsafe {
+            let format = (*data.add(26) & 0xF) as usize;
+            (core::ptr::read_unaligned(data as *const u64), *data.add(128 + format * 4 + 2))
+        };
+
+        if sectors == 0 {
+            continue;
+        }
+
+        if 1usize << sector_shift != SECTOR_SIZE {
+            println!("NVMe: skipping namespace {}, its sectors have {} bytes", namespace, 1u64 << sector_shift);
+            continue;
+        }
+
+        found.push((namespace, sectors));
+    }
+
+    REGISTERS.store(registers.0.as_u64(), Ordering::Release);
+
+    // Without an interrupt the I/O commands are polled like the admin ones
+    if let Some(irq) = irq {
+        if crate::interrupts::interrupt_manager::register_irq(irq, handle_interrupt).is_ok() {
+            INTERRUPTS.store(true, Ordering::Release);
+        }
+    }
+
+    let controller = Arc::new(SleepMutex::new(controller));
+
+    for (namespace, sectors) in found {
+        let drive = NvmeDrive {
+            name: format!("nvme0n{}", namespace),
+            model: model.clone(),
+            controller: controller.clone(),
+            namespace,
+            sectors,
+            max_sectors,
+            write_cache
+        };
+
+        println!("{}: {} ({} MiB)", drive.name, drive.model(), sectors * SECTOR_SIZE as u64 / (1024 * 1024));
+        block::register(Arc::new(drive));
+    }
+
+    return Some(Box::new(NvmeDriver));
+}
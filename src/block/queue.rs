@@ -0,0 +1,317 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use x86_64::VirtAddr;
+use crate::block::{self, BlockDevice};
+use crate::sched::WaitQueue;
+use crate::utils::error::KernelError;
+use crate::utils::{IrqCell, Mutex};
+use crate::workqueue::WorkQueue;
+
+/// The work queue whose worker runs the requests of every device
+pub const WORK_QUEUE_NAME: &str = "block";
+
+/// The most bytes a transfer made of merged requests covers, the size of the buffer they're gathered in
+const MAX_MERGED_SIZE: usize = 64 * 1024;
+
+/// How many requests can wait in a queue, so the queue stays in a single small heap block
+const MAX_PENDING: usize = 32;
+
+/// What a request asks the device to do
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Operation {
+    Read,
+    Write,
+    Flush
+}
+
+struct Request {
+    operation: Operation,
+    sector: u64,
+    /// The buffer the sectors are read into or written from, handed back once the request is done
+    buffer: Vec<u8>,
+    completion: Arc<Completion>
+}
+
+/// Where a request leaves its result, shared between the queue and the [`RequestHandle`]
+struct Completion {
+    state: IrqCell<CompletionState>,
+    /// The threads blocked in [`RequestHandle::wait`]
+    waiters: WaitQueue
+}
+
+struct CompletionState {
+    result: Option<Result<Vec<u8>, KernelError>>,
+    /// The task polling the [`RequestHandle`], if it's awaited
+    waker: Option<Waker>
+}
+
+impl Completion {
+    fn finish(&self, result: Result<Vec<u8>, KernelError>) {
+        let waker = self.state.with(|state| {
+            state.result = Some(result);
+            state.waker.take()
+        });
+
+        self.waiters.wake_all();
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// The result of a request given to a [`RequestQueue`]: the buffer of the request once the device is done with it,
+/// holding the sectors read for a read. It can be waited for by blocking the thread or awaited by a task
+pub struct RequestHandle {
+    completion: Arc<Completion>
+}
+
+#[allow(dead_code)]
+impl RequestHandle {
+    /// Returns whatever the request is done, then [`RequestHandle::wait`] doesn't block
+    pub fn is_done(&self) -> bool {
+        self.completion.state.with(|state| state.result.is_some())
+    }
+
+    /// Blocks the current thread until the request is done, returning its buffer
+    ///
+    /// ## Errors
+    ///
+    /// Returns the error reported by the device
+    pub fn wait(self) -> Result<Vec<u8>, KernelError> {
+        let mut result = None;
+
+        self.completion.waiters.wait_until(|| {
+            result = self.completion.state.with(|state| state.result.take());
+            result.is_some()
+        });
+
+        return result.unwrap();
+    }
+}
+
+impl Future for RequestHandle {
+    type Output = Result<Vec<u8>, KernelError>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        self.completion.state.with(|state| {
+            match state.result.take() {
+                Some(result) => Poll::Ready(result),
+                None => {
+                    state.waker = Some(context.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+    }
+}
+
+/// The requests waiting for a block device, run in the order they were given by the worker of the
+/// [`WORK_QUEUE_NAME`] work queue. A run of requests of the same kind on consecutive sectors is merged into a
+/// single transfer, so the device sees fewer and larger commands.
+///
+/// This is what the filesystems use, they don't need to know which driver the device belongs to
+pub struct RequestQueue {
+    device: Arc<dyn BlockDevice>,
+    pending: Mutex<VecDeque<Request>>,
+    /// Whatever a job running the queue is already waiting in the work queue
+    scheduled: AtomicBool,
+    /// Where the merged requests are gathered, only touched by the worker. Without it nothing is merged
+    bounce: Option<VirtAddr>
+}
+
+// The bounce buffer is only used by the worker, one request run at a time
+unsafe impl Send for RequestQueue {}
+unsafe impl Sync for RequestQueue {}
+
+#[allow(dead_code)]
+impl RequestQueue {
+    /// Creates the queue of `device`, the buffer merged requests are gathered in is mapped by
+    /// [`crate::memory::mmio::allocate`], so the same rules apply
+    pub fn new(device: Arc<dyn BlockDevice>) -> Self {
+        RequestQueue {
+            device,
+            pending: Mutex::new(VecDeque::new()),
+            scheduled: AtomicBool::new(false),
+            bounce: crate::memory::mmio::allocate(MAX_MERGED_SIZE).ok()
+        }
+    }
+
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.device
+    }
+
+    /// Reads the sectors starting at `sector` into `buffer`, as many as fit in it
+    ///
+    /// ## Errors
+    ///
+    /// Returns the errors of [`RequestQueue::submit`]
+    pub fn read(self: &Arc<Self>, sector: u64, buffer: Vec<u8>) -> Result<RequestHandle, KernelError> {
+        self.submit(Operation::Read, sector, buffer)
+    }
+
+    /// Writes `buffer` to the sectors starting at `sector`
+    ///
+    /// ## Errors
+    ///
+    /// Returns the errors of [`RequestQueue::submit`]
+    pub fn write(self: &Arc<Self>, sector: u64, buffer: Vec<u8>) -> Result<RequestHandle, KernelError> {
+        self.submit(Operation::Write, sector, buffer)
+    }
+
+    /// Makes sure every write given before this one is on the device once this request is done
+    ///
+    /// ## Errors
+    ///
+    /// Returns the errors of [`RequestQueue::submit`]
+    pub fn flush(self: &Arc<Self>) -> Result<RequestHandle, KernelError> {
+        self.submit(Operation::Flush, 0, Vec::new())
+    }
+
+    /// Adds a request at the end of the queue, the device gets it once every request before it is done. Can't be
+    /// called from interrupt handlers, since the job running the queue is moved to the heap
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if the transfer doesn't fit in the device (see
+    /// [`block::check_transfer`]) and [`KernelError::Busy`] if there are already too many requests waiting
+    pub fn submit(
+        self: &Arc<Self>, operation: Operation, sector: u64, buffer: Vec<u8>
+    ) -> Result<RequestHandle, KernelError> {
+        if operation != Operation::Flush {
+            block::check_transfer(self.device.as_ref(), sector, buffer.len())?;
+        }
+
+        let completion = Arc::new(Completion {
+            state: IrqCell::new(CompletionState { result: None, waker: None }),
+            waiters: WaitQueue::new()
+        });
+
+        let mut pending = self.pending.lock();
+
+        if pending.len() >= MAX_PENDING {
+            return Err(KernelError::Busy);
+        }
+
+        pending.push_back(Request { operation, sector, buffer, completion: completion.clone() });
+        drop(pending);
+
+        if !self.scheduled.swap(true, Ordering::AcqRel) {
+            let queue = self.clone();
+            let work_queue = WorkQueue::find(WORK_QUEUE_NAME).expect("The block work queue wasn't created yet");
+
+            work_queue.enqueue_fn(move || queue.run());
+        }
+
+        return Ok(RequestHandle { completion });
+    }
+
+    /// Runs the requests until the queue is empty
+    fn run(&self) {
+        // Cleared first, so a request added while the last batch runs schedules the queue again
+        self.scheduled.store(false, Ordering::Release);
+
+        loop {
+            let batch = self.take_batch();
+
+            if batch.is_empty() {
+                return;
+            }
+
+            self.execute(batch);
+        }
+    }
+
+    /// Takes the first request with the ones right after it that continue it: same operation, on the sectors
+    /// that follow and small enough together to fit in the bounce buffer. The requests are never reordered
+    fn take_batch(&self) -> Vec<Request> {
+        let mut pending = self.pending.lock();
+        let mut batch = Vec::new();
+
+        let first = match pending.pop_front() {
+            Some(first) => first,
+            None => return batch
+        };
+
+        let sector_size = self.device.sector_size();
+        let mut end = first.sector + (first.buffer.len() / sector_size) as u64;
+        let mut size = first.buffer.len();
+        let mergeable = first.operation != Operation::Flush && self.bounce.is_some();
+        let operation = first.operation;
+
+        batch.push(first);
+
+        while let Some(next) = pending.front() {
+            if !mergeable || next.operation != operation || next.sector != end
+                || size + next.buffer.len() > MAX_MERGED_SIZE {
+                break;
+            }
+
+            end += (next.buffer.len() / sector_size) as u64;
+            size += next.buffer.len();
+            batch.push(pending.pop_front().unwrap());
+        }
+
+        return batch;
+    }
+
+    /// Runs the requests of a batch as a single transfer, completing each of them with the result. When the
+    /// transfer fails the requests are run again one by one, so only the ones that can't be done fail
+    fn execute(&self, mut batch: Vec<Request>) {
+        if batch.len() == 1 {
+            self.execute_one(batch.pop().unwrap());
+            return;
+        }
+
+        // Only batches of several reads or writes get here, which means there's a bounce buffer
+        let size: usize = batch.iter().map(|request| request.buffer.len()).sum();
+        let bounce = unsafe { core::slice::from_raw_parts_mut(self.bounce.unwrap().as_mut_ptr::<u8>(), size) };
+        let sector = batch[0].sector;
+
+        let result = if batch[0].operation == Operation::Read {
+            self.device.read(sector, bounce)
+        } else {
+            let mut offset = 0;
+
+            for request in &batch {
+                bounce[offset..offset + request.buffer.len()].copy_from_slice(&request.buffer);
+                offset += request.buffer.len();
+            }
+
+            self.device.write(sector, bounce)
+        };
+
+        let mut offset = 0;
+
+        for mut request in batch {
+            if result.is_err() {
+                self.execute_one(request);
+                continue;
+            }
+
+            let length = request.buffer.len();
+
+            if request.operation == Operation::Read {
+                request.buffer.copy_from_slice(&bounce[offset..offset + length]);
+            }
+
+            offset += length;
+            request.completion.finish(Ok(request.buffer));
+        }
+    }
+
+    fn execute_one(&self, mut request: Request) {
+        let result = match request.operation {
+            Operation::Read => self.device.read(request.sector, &mut request.buffer),
+            Operation::Write => self.device.write(request.sector, &request.buffer),
+            Operation::Flush => self.device.flush()
+        };
+
+        request.completion.finish(result.map(|_| request.buffer));
+    }
+}
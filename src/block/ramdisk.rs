@@ -0,0 +1,172 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::block::{self, BlockDevice, SECTOR_SIZE};
+use crate::drivers::registry::{self, Driver};
+use crate::println;
+use crate::sync::SleepMutex;
+use crate::utils::error::KernelError;
+
+/// The size of the RAM disk created at boot, larger when its image doesn't fit
+const DEFAULT_SIZE: usize = 4 * 1024 * 1024;
+
+/// The image the RAM disk created at boot starts with. The bootloader can't load one next to the kernel, so with
+/// the `ramdisk-image` feature it's built in the kernel from the file named by the `RAMDISK_IMAGE` environment variable
+#[cfg(feature = "ramdisk-image")]
+const IMAGE: Option<&[u8]> = Some(include_bytes!(env!("RAMDISK_IMAGE")));
+
+#[cfg(not(feature = "ramdisk-image"))]
+const IMAGE: Option<&[u8]> = None;
+
+/// The name of the read-only disk made of the package loaded by the bootloader
+pub const INITRD_NAME: &str = "initrd";
+
+/// A block device whose sectors are in RAM, everything written to it is lost once the machine stops
+pub struct RamDisk {
+    name: String,
+    /// Where the sectors are mapped, one after the other. The lock makes every transfer happen as a whole
+    memory: SleepMutex<VirtAddr>,
+    sectors: u64,
+    /// Whatever the memory is only mapped for reading, the writes are refused
+    read_only: bool
+}
+
+impl RamDisk {
+    /// Creates a RAM disk called `name` with enough sectors for `size` bytes, all of them zeroed. The memory comes
+    /// from [`crate::memory::mmio::allocate`], so the same rules apply and it's never freed
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::OutOfMemory`] if there isn't enough memory for it
+    pub fn new(name: &str, size: usize) -> Result<Self, KernelError> {
+        let sectors = (size.max(1) + SECTOR_SIZE - 1) / SECTOR_SIZE;
+        let memory = crate::memory::mmio::allocate(sectors * SECTOR_SIZE)?;
+
+        Ok(unsafe { RamDisk::from_memory(name, memory, sectors * SECTOR_SIZE, false) })
+    }
+
+    /// Creates a RAM disk called `name` whose sectors are the `size` bytes already mapped at `memory`, the last
+    /// partial sector is left out
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that `memory` is mapped for the `size` bytes (and
+    /// writable unless `read_only` is set) for as long as the kernel runs, and that nothing else uses it
+    pub unsafe fn from_memory(name: &str, memory: VirtAddr, size: usize, read_only: bool) -> Self {
+        let sectors = (size / SECTOR_SIZE) as u64;
+        RamDisk { name: String::from(name), memory: SleepMutex::new(memory), sectors, read_only }
+    }
+
+    /// Creates a RAM disk called `name` that starts with the content of `image`, with at least `size` bytes
+    ///
+    /// ## Errors
+    ///
+    /// Same as [`RamDisk::new`]
+    pub fn with_image(name: &str, image: &[u8], size: usize) -> Result<Self, KernelError> {
+        let disk = RamDisk::new(name, size.max(image.len()))?;
+        let memory = disk.memory.lock();
+
+        unsafe { core::ptr::copy_nonoverlapping(image.as_ptr(), memory.as_mut_ptr::<u8>(), image.len()) };
+
+        drop(memory);
+        Ok(disk)
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read(&self, sector: u64, buffer: &mut [u8]) -> Result<(), KernelError> {
+        block::check_transfer(self, sector, buffer.len())?;
+
+        let memory = self.memory.lock();
+        let source = (*memory + sector * SECTOR_SIZE as u64).as_ptr::<u8>();
+
+        // The transfer was checked to be inside the disk
+        unsafe { core::ptr::copy_nonoverlapping(source, buffer.as_mut_ptr(), buffer.len()) };
+        Ok(())
+    }
+
+    fn write(&self, sector: u64, buffer: &[u8]) -> Result<(), KernelError> {
+        if self.read_only {
+            return Err(KernelError::Unsupported);
+        }
+
+        block::check_transfer(self, sector, buffer.len())?;
+
+        let memory = self.memory.lock();
+        let destination = (*memory + sector * SECTOR_SIZE as u64).as_mut_ptr::<u8>();
+
+        unsafe { core::ptr::copy_nonoverlapping(buffer.as_ptr(), destination, buffer.len()) };
+        Ok(())
+    }
+}
+
+/// The driver bound to the RAM disk created at boot
+struct RamDiskDriver;
+
+impl Driver for RamDiskDriver {
+    fn name(&self) -> &'static str {
+        "ramdisk"
+    }
+}
+
+/// Creates the RAM disk `ram0`, from the image built in the kernel if there's one, and registers it
+pub fn probe() -> Option<Box<dyn Driver>> {
+    let disk = match IMAGE {
+        Some(image) => RamDisk::with_image("ram0", image, DEFAULT_SIZE),
+        None => RamDisk::new("ram0", DEFAULT_SIZE)
+    };
+
+    let disk = match disk {
+        Ok(disk) => disk,
+        Err(error) => {
+            println!("ram0: failed to allocate the RAM disk: {:?}", error);
+            return None;
+        }
+    };
+
+    println!("{}: RAM disk ({} KiB)", disk.name, disk.sectors * SECTOR_SIZE as u64 / 1024);
+    block::register(Arc::new(disk));
+
+    return Some(Box::new(RamDiskDriver));
+}
+
+/// Registers the package the bootloader loaded next to the kernel, if there's one, as the read-only disk `initrd`.
+/// Its memory is only mapped, never copied, so the programs and files in it are there without any disk driver.
+///
+/// The disk is handed over like the other block devices, [`crate::fs::init`] then mounts the ustar archive in it at
+/// `/`. Must be called once, after [`block::init`] and before any process is created
+pub fn register_initrd(memory_map: &MemoryMap) {
+    let package = match memory_map.iter().find(|region| region.region_type == MemoryRegionType::Package) {
+        Some(package) => package,
+        None => return
+    };
+
+    let start = package.range.start_addr();
+    let size = (package.range.end_addr() - start) as usize;
+
+    let memory = match crate::memory::mmio::map_read_only(PhysAddr::new(start), size) {
+        Ok(memory) => memory,
+        Err(error) => {
+            println!("{}: failed to map the package of the bootloader: {:?}", INITRD_NAME, error);
+            return;
+        }
+    };
+
+    // The frames of the package are never handed out by the frame allocator, and the mapping is never removed
+    let disk = unsafe { RamDisk::from_memory(INITRD_NAME, memory, size, true) };
+
+    println!("{}: read-only RAM disk at {:#x} ({} KiB)", INITRD_NAME, start, disk.sectors * SECTOR_SIZE as u64 / 1024);
+    block::register(Arc::new(disk));
+
+    registry::add_platform_device(INITRD_NAME, "ramdisk");
+}
@@ -0,0 +1,265 @@
+use x86_64::instructions::port::Port;
+use crate::println;
+use crate::time::calendar::DateTime;
+use crate::utils::error::KernelError;
+use crate::utils::IrqCell;
+
+/// The byte of the CMOS accessed through the data port is picked by writing its offset to the index port
+const INDEX_PORT: u16 = 0x70;
+const DATA_PORT: u16 = 0x71;
+
+/// The registers of the RTC that hold the date and the time
+const RTC_SECONDS: u8 = 0x00;
+const RTC_MINUTES: u8 = 0x02;
+const RTC_HOURS: u8 = 0x04;
+const RTC_DAY: u8 = 0x07;
+const RTC_MONTH: u8 = 0x08;
+const RTC_YEAR: u8 = 0x09;
+
+/// The status registers of the RTC: A tells when the date is being updated, B how it's written
+const RTC_STATUS_A: u8 = 0x0A;
+const RTC_STATUS_B: u8 = 0x0B;
+const RTC_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const RTC_24_HOURS: u8 = 1 << 1;
+const RTC_BINARY: u8 = 1 << 2;
+
+/// The bit of the hours set for the afternoon, when the RTC counts 12 hours
+const RTC_PM: u8 = 1 << 7;
+
+/// The bytes before this one are the registers of the RTC, everything after it up to [`CMOS_SIZE`] is NVRAM
+const NVRAM_START: u8 = 0x0E;
+const CMOS_SIZE: u8 = 0x80;
+
+/// The bytes of the BIOS covered by the standard checksum, which is stored big endian in the two bytes after them
+const CHECKSUM_START: u8 = 0x10;
+const CHECKSUM_END: u8 = 0x2D;
+const CHECKSUM_HIGH: u8 = 0x2E;
+const CHECKSUM_LOW: u8 = 0x2F;
+
+/// The bytes the kernel keeps its settings in, the last 16 of the NVRAM which the firmwares leave alone. The first
+/// byte is [`SETTINGS_MAGIC`] and the last one makes all of them add up to 0, so settings that were never written
+/// (or were overwritten by someone else) are noticed
+const SETTINGS_START: u8 = 0x70;
+const SETTINGS_SIZE: usize = 16;
+const SETTINGS_MAGIC: u8 = 0x6B;
+
+/// Where the settings are in the bytes of [`SETTINGS_START`], the boot counter is 4 bytes in little endian
+const SETTING_BOOT_COUNT: usize = 1;
+const SETTING_CONSOLE: usize = 5;
+
+static CMOS: IrqCell<Cmos> = IrqCell::new(Cmos::new());
+
+/// Where the console is shown by default
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Console {
+    /// Only on the screen
+    Screen,
+    /// On the screen and copied to the serial port, when there's one
+    Serial
+}
+
+struct Cmos {
+    index: Port<u8>,
+    data: Port<u8>
+}
+
+impl Cmos {
+    const fn new() -> Self {
+        Cmos {
+            index: Port::new(INDEX_PORT),
+            data: Port::new(DATA_PORT)
+        }
+    }
+
+    // Bit 7 of the index port masks the NMI, it's left clear so the NMI stays enabled
+    fn read(&mut self, offset: u8) -> u8 {
+        unsafe {
+            self.index.write(offset);
+            self.data.read()
+        }
+    }
+
+    fn write(&mut self, offset: u8, value: u8) {
+        unsafe {
+            self.index.write(offset);
+            self.data.write(value);
+        }
+    }
+
+    /// Writes the standard checksum again after one of the bytes it covers changed
+    fn update_checksum(&mut self) {
+        let sum = (CHECKSUM_START..=CHECKSUM_END).map(|offset| self.read(offset) as u16).fold(0u16, u16::wrapping_add);
+
+        self.write(CHECKSUM_HIGH, (sum >> 8) as u8);
+        self.write(CHECKSUM_LOW, sum as u8);
+    }
+
+    /// Returns the settings of the kernel, or `None` if they aren't valid
+    fn read_settings(&mut self) -> Option<[u8; SETTINGS_SIZE]> {
+        let mut settings = [0; SETTINGS_SIZE];
+
+        for (offset, byte) in (SETTINGS_START..).zip(settings.iter_mut()) {
+            *byte = self.read(offset);
+        }
+
+        let sum = settings.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        return (settings[0] == SETTINGS_MAGIC && sum == 0).then_some(settings);
+    }
+
+    fn write_settings(&mut self, settings: &mut [u8; SETTINGS_SIZE]) {
+        settings[0] = SETTINGS_MAGIC;
+        settings[SETTINGS_SIZE - 1] = 0;
+        settings[SETTINGS_SIZE - 1] = settings.iter().fold(0u8, |sum, &byte| sum.wrapping_sub(byte));
+
+        for (offset, &byte) in (SETTINGS_START..).zip(settings.iter()) {
+            self.write(offset, byte);
+        }
+    }
+
+    /// Returns the settings of the kernel, the defaults if they aren't valid
+    fn settings(&mut self) -> [u8; SETTINGS_SIZE] {
+        return self.read_settings().unwrap_or_else(default_settings);
+    }
+}
+
+/// The settings used until the first ones are written: no boots yet and the console copied to the serial port
+fn default_settings() -> [u8; SETTINGS_SIZE] {
+    let mut settings = [0; SETTINGS_SIZE];
+    settings[SETTING_CONSOLE] = Console::Serial as u8;
+
+    return settings;
+}
+
+/// Returns whatever `offset` is in the NVRAM, and so can be read and written
+fn is_nvram(offset: u8) -> bool {
+    (NVRAM_START..CMOS_SIZE).contains(&offset)
+}
+
+/// Reads the byte of the NVRAM at `offset`
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if `offset` is one of the registers of the RTC or past the NVRAM
+#[allow(dead_code)]
+pub fn read(offset: u8) -> Result<u8, KernelError> {
+    if !is_nvram(offset) {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    return Ok(CMOS.with(|cmos| cmos.read(offset)));
+}
+
+/// Writes the byte of the NVRAM at `offset`, updating the standard checksum if it covers it. The byte is kept
+/// across reboots (while the battery of the CMOS lasts)
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if `offset` is one of the registers of the RTC, past the NVRAM or one
+/// of the bytes of the checksum
+#[allow(dead_code)]
+pub fn write(offset: u8, value: u8) -> Result<(), KernelError> {
+    if !is_nvram(offset) || offset == CHECKSUM_HIGH || offset == CHECKSUM_LOW {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    CMOS.with(|cmos| {
+        cmos.write(offset, value);
+
+        if (CHECKSUM_START..=CHECKSUM_END).contains(&offset) {
+            cmos.update_checksum();
+        }
+    });
+
+    Ok(())
+}
+
+/// Reads the date and the time kept by the RTC, which is taken to be in UTC and in the 21st century (the century
+/// register isn't at the same place on every machine)
+pub fn read_clock() -> DateTime {
+    let (registers, status) = CMOS.with(|cmos| {
+        let mut last = None;
+
+        // The registers are read until two reads agree, so an update in the middle of a read isn't seen
+        loop {
+            while cmos.read(RTC_STATUS_A) & RTC_UPDATE_IN_PROGRESS != 0 {
+                core::hint::spin_loop();
+            }
+
+            let registers = [RTC_SECONDS, RTC_MINUTES, RTC_HOURS, RTC_DAY, RTC_MONTH, RTC_YEAR]
+                .map(|offset| cmos.read(offset));
+
+            if last == Some(registers) {
+                return (registers, cmos.read(RTC_STATUS_B));
+            }
+
+            last = Some(registers);
+        }
+    });
+
+    let decode = |value: u8| if status & RTC_BINARY != 0 { value } else { (value >> 4) * 10 + (value & 0x0F) };
+    let [second, minute, hours, day, month, year] = registers;
+
+    let mut hour = decode(hours & !RTC_PM);
+
+    if status & RTC_24_HOURS == 0 {
+        hour = hour % 12 + if hours & RTC_PM != 0 { 12 } else { 0 };
+    }
+
+    DateTime {
+        year: 2000 + decode(year) as u16,
+        month: decode(month),
+        day: decode(day),
+        hour,
+        minute: decode(minute),
+        second: decode(second)
+    }
+}
+
+/// Returns how many times the kernel booted, counting this one, since its settings were first written
+pub fn boot_count() -> u32 {
+    let settings = CMOS.with(|cmos| cmos.settings());
+    return u32::from_le_bytes(settings[SETTING_BOOT_COUNT..SETTING_BOOT_COUNT + 4].try_into().unwrap());
+}
+
+/// Returns where the console is shown by default
+pub fn console() -> Console {
+    match CMOS.with(|cmos| cmos.settings())[SETTING_CONSOLE] {
+        value if value == Console::Screen as u8 => Console::Screen,
+        _ => Console::Serial
+    }
+}
+
+/// Sets where the console is shown by default, starting with the next boot
+pub fn set_console(console: Console) {
+    CMOS.with(|cmos| {
+        let mut settings = cmos.settings();
+        settings[SETTING_CONSOLE] = console as u8;
+
+        cmos.write_settings(&mut settings);
+    });
+}
+
+/// Counts this boot in the settings of the kernel, writing the defaults first if there are none. Must be called
+/// once, early during boot
+pub fn init() {
+    let (count, valid) = CMOS.with(|cmos| {
+        let (mut settings, valid) = match cmos.read_settings() {
+            Some(settings) => (settings, true),
+            None => (default_settings(), false)
+        };
+
+        let count = u32::from_le_bytes(settings[SETTING_BOOT_COUNT..SETTING_BOOT_COUNT + 4].try_into().unwrap())
+            .wrapping_add(1);
+
+        settings[SETTING_BOOT_COUNT..SETTING_BOOT_COUNT + 4].copy_from_slice(&count.to_le_bytes());
+        cmos.write_settings(&mut settings);
+
+        (count, valid)
+    });
+
+    if valid {
+        println!("CMOS: boot #{}", count);
+    } else {
+        println!("CMOS: no valid settings, using the defaults");
+    }
+}
@@ -0,0 +1,67 @@
+use core::fmt;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::instructions::port::Port;
+
+#[macro_export]
+macro_rules! debugcon_print {
+    ($($arg:tt)*) => ($crate::debugcon::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! debugcon_println {
+    () => ($crate::debugcon_print!("\n"));
+    ($($arg:tt)*) => ($crate::debugcon_print!("{}\n", format_args!($($arg)*)));
+}
+
+/// The port of the debug console of QEMU and Bochs (`-debugcon`), every byte written to it is printed right away
+const PORT: u16 = 0xE9;
+
+/// Set once [`init`] found the debug console and the kernel console is copied to it
+static CONSOLE: AtomicBool = AtomicBool::new(false);
+
+/// Writes `bytes` to the debug console. The port takes every byte right away and nothing is locked, so this works
+/// from anywhere (before the other consoles are ready, in interrupt handlers or while panicking). The bytes are
+/// simply lost when there's no debug console
+pub fn write_bytes(bytes: &[u8]) {
+    let mut port = Port::<u8>::new(PORT);
+
+    for &byte in bytes {
+        unsafe { port.write(byte) };
+    }
+}
+
+/// Looks for the debug console, which reads as its own port number, and copies everything printed to the kernel
+/// console (see [`crate::vga`]) to it when it's there. Returns whatever it was found
+pub fn init() -> bool {
+    let present = unsafe { Port::<u8>::new(PORT).read() } == PORT as u8;
+
+    CONSOLE.store(present, Ordering::Release);
+    return present;
+}
+
+/// Sets whatever everything printed to the kernel console is also written to the debug console
+pub fn set_console(enabled: bool) {
+    CONSOLE.store(enabled, Ordering::Release);
+}
+
+struct DebugconWriter;
+
+impl Write for DebugconWriter {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        write_bytes(text.as_bytes());
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    DebugconWriter.write_fmt(args).unwrap();
+}
+
+/// Called by the kernel console with everything it prints, see [`init`]
+pub(crate) fn console_print(args: fmt::Arguments) {
+    if CONSOLE.load(Ordering::Acquire) {
+        _print(args);
+    }
+}
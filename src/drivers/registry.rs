@@ -0,0 +1,174 @@
+use core::fmt;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::pci::PciDevice;
+use crate::print;
+use crate::utils::Mutex;
+
+/// The class of the bridges to another PCI bus, their secondary bus number is byte 1 of the bus numbers register
+const PCI_BRIDGE_CLASS: (u8, u8) = (0x06, 0x04);
+const PCI_BUS_NUMBERS: u16 = 0x18;
+
+/// A driver bound to a device, it owns whatever the driver keeps for that device
+pub trait Driver: Send {
+    /// The short name of the driver, like `ahci`
+    fn name(&self) -> &'static str;
+}
+
+/// Tries to bind a driver to a function of the PCI bus, returning the bound driver if the function is one it handles
+pub type PciProbe = fn(&PciDevice) -> Option<Box<dyn Driver>>;
+
+/// Looks for a device that can't be found by walking a bus (like the legacy IDE channels), returning the bound driver
+/// if it's there
+pub type PlatformProbe = fn() -> Option<Box<dyn Driver>>;
+
+static PCI_PROBES: Mutex<Vec<PciProbe>> = Mutex::new(Vec::new());
+
+/// The platform probes with the name of the device they look for
+static PLATFORM_PROBES: Mutex<Vec<(&'static str, PlatformProbe)>> = Mutex::new(Vec::new());
+
+/// The devices found so far, the roots are the buses
+static TREE: Mutex<Vec<Node>> = Mutex::new(Vec::new());
+
+/// The name of the root the platform devices are under
+const PLATFORM_ROOT: &str = "platform";
+
+struct Node {
+    name: String,
+    driver: Option<Box<dyn Driver>>,
+    children: Vec<Node>
+}
+
+/// The driver of the devices set up before the registry walks the buses, it's only there for its name
+struct Builtin(&'static str);
+
+impl Driver for Builtin {
+    fn name(&self) -> &'static str {
+        self.0
+    }
+}
+
+/// Registers a probe that [`probe_all`] calls with every function of the PCI bus that has no driver yet, the probes
+/// are tried in the order they're registered
+pub fn register(probe: PciProbe) {
+    PCI_PROBES.lock().push(probe);
+}
+
+/// Registers a probe that [`probe_all`] calls once, after every PCI probe, the device it finds is called `name`
+pub fn register_platform(name: &'static str, probe: PlatformProbe) {
+    PLATFORM_PROBES.lock().push((name, probe));
+}
+
+/// Adds a platform device set up on its own before the drivers are bound (like the serial port), with the name of
+/// its driver
+pub fn add_platform_device(name: &str, driver: &'static str) {
+    add_platform_node(Node { name: String::from(name), driver: Some(Box::new(Builtin(driver))), children: Vec::new() });
+}
+
+fn add_platform_node(node: Node) {
+    let mut tree = TREE.lock();
+
+    match tree.iter_mut().find(|root| root.name == PLATFORM_ROOT) {
+        Some(root) => root.children.push(node),
+        None => tree.push(Node { name: String::from(PLATFORM_ROOT), driver: None, children: alloc::vec![node] })
+    }
+}
+
+/// Binds every function of the PCI bus to the first driver whose probe accepts it, then runs the platform probes.
+/// Must be called once, after the drivers registered their probes and before any process is created (the probes
+/// map the memory of their devices)
+pub fn probe_all() {
+    let devices = crate::pci::devices();
+
+    // The probes run without the lock held, they can take a while and some register more probes
+    let probes = PCI_PROBES.lock().clone();
+
+    let mut drivers: Vec<Option<Box<dyn Driver>>> = devices
+        .iter()
+        .map(|device| probes.iter().find_map(|probe| probe(device)))
+        .collect();
+
+    // The buses behind a bridge are under it, the ones no bridge leads to (like the ones of a second host
+    // bridge) are directly under the root
+    let mut visited = [false; 256];
+    let mut root = Node { name: String::from("pci"), driver: None, children: Vec::new() };
+
+    for device in &devices {
+        root.children.extend(pci_nodes(&devices, &mut drivers, device.bus, &mut visited));
+    }
+
+    TREE.lock().insert(0, root);
+
+    let platform_probes = PLATFORM_PROBES.lock().clone();
+
+    for (name, probe) in platform_probes {
+        if let Some(driver) = probe() {
+            add_platform_node(Node { name: String::from(name), driver: Some(driver), children: Vec::new() });
+        }
+    }
+}
+
+/// Builds the nodes of the functions on `bus`, a bridge has the functions of its secondary bus as its children.
+/// Returns nothing for a bus that was already visited
+fn pci_nodes(
+    devices: &[PciDevice], drivers: &mut [Option<Box<dyn Driver>>], bus: u8, visited: &mut [bool; 256]
+) -> Vec<Node> {
+    if visited[bus as usize] {
+        return Vec::new();
+    }
+
+    visited[bus as usize] = true;
+
+    let mut nodes = Vec::new();
+
+    for (index, device) in devices.iter().enumerate().filter(|(_, device)| device.bus == bus) {
+        let name = format!(
+            "{:02x}:{:02x}.{} {:04x}:{:04x} {}",
+            device.bus, device.device, device.function, device.vendor_id, device.device_id,
+            device.class_name().unwrap_or("Unknown device")
+        );
+
+        let children = if (device.class, device.subclass) == PCI_BRIDGE_CLASS {
+            let secondary = (device.read_config(PCI_BUS_NUMBERS) >> 8) as u8;
+            pci_nodes(devices, drivers, secondary, visited)
+        } else {
+            Vec::new()
+        };
+
+        nodes.push(Node { name, driver: drivers[index].take(), children });
+    }
+
+    return nodes;
+}
+
+/// Prints the device tree, every device under its parent with the driver bound to it
+pub fn print_tree() {
+    let mut tree = String::new();
+    let _ = write_tree(&mut tree);
+
+    print!("{}", tree);
+}
+
+/// Writes the device tree to `out` as [`print_tree`] shows it
+pub fn write_tree(out: &mut dyn fmt::Write) -> fmt::Result {
+    for root in TREE.lock().iter() {
+        write_node(out, root, 0)?;
+    }
+
+    Ok(())
+}
+
+fn write_node(out: &mut dyn fmt::Write, node: &Node, depth: usize) -> fmt::Result {
+    match &node.driver {
+        Some(driver) => writeln!(out, "{:indent$}{} [{}]", "", node.name, driver.name(), indent = depth * 2)?,
+        None => writeln!(out, "{:indent$}{}", "", node.name, indent = depth * 2)?
+    }
+
+    for child in &node.children {
+        write_node(out, child, depth + 1)?;
+    }
+
+    Ok(())
+}
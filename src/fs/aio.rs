@@ -0,0 +1,177 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use crate::block::queue::RequestQueue;
+use crate::fs::{vfs, NodeKind};
+use crate::utils::error::KernelError;
+use crate::utils::IrqCell;
+use crate::workqueue::WorkQueue;
+
+/// The work queue whose worker runs the operations of the async tasks, see [`submit`]
+pub const WORK_QUEUE_NAME: &str = "vfs-io";
+
+/// The most bytes read from the device with a single request by [`read`], so the buffer of the request stays small
+const MAX_REQUEST_SIZE: u64 = 16 * 1024;
+
+/// Where an operation leaves its result, shared between the worker and the [`IoFuture`]
+struct Pending<T> {
+    state: IrqCell<PendingState<T>>
+}
+
+struct PendingState<T> {
+    result: Option<T>,
+    /// The task polling the [`IoFuture`], woken up once the result is there
+    waker: Option<Waker>
+}
+
+/// The result of an operation given to [`submit`], the task awaiting it is woken up once it's done
+pub struct IoFuture<T> {
+    pending: Arc<Pending<T>>
+}
+
+impl<T> Future for IoFuture<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<T> {
+        self.pending.state.with(|state| {
+            match state.result.take() {
+                Some(result) => Poll::Ready(result),
+                None => {
+                    state.waker = Some(context.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+    }
+}
+
+/// Runs `operation` on the worker of the [`WORK_QUEUE_NAME`] work queue, returning a future that completes with its
+/// result. The operations of the filesystems block the thread that runs them until the device is done (the disk
+/// interrupt wakes it up), so an async task hands them to the worker and the executor goes on with the other tasks
+/// meanwhile. The operations run one after the other, in the order they were submitted. The content of the files
+/// that can be read from the device directly doesn't go through here, see [`read`]
+///
+/// ## Note
+///
+/// The operation runs even if the future is dropped before it's done, only its result is lost
+pub fn submit<T: Send + 'static>(operation: impl FnOnce() -> T + Send + 'static) -> IoFuture<T> {
+    let pending = Arc::new(Pending {
+        state: IrqCell::new(PendingState { result: None, waker: None })
+    });
+
+    let completion = pending.clone();
+    let work_queue = WorkQueue::find(WORK_QUEUE_NAME).expect("The VFS work queue wasn't created yet");
+
+    work_queue.enqueue_fn(move || {
+        let result = operation();
+
+        let waker = completion.state.with(|state| {
+            state.result = Some(result);
+            state.waker.take()
+        });
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    });
+
+    return IoFuture { pending };
+}
+
+/// How the bytes asked of [`read`] are read
+enum Plan {
+    /// The node isn't stored as it is on a block device, the worker read it already
+    Read(Vec<u8>),
+    /// Where the bytes are on the device of the queue, in order, see [`crate::fs::Node::extent`]
+    Extents(Arc<RequestQueue>, Vec<(Option<u64>, u64)>)
+}
+
+/// Reads `length` bytes (at most) of the file at the absolute `path` from `offset`. The worker only walks the path
+/// and finds where the bytes are on the device, they're read with the requests of the queue of the device (see
+/// [`RequestQueue`]) so the task is woken up by their completion. The nodes that can't tell where their content is
+/// (see [`crate::fs::Node::extent`]) are read by the worker
+///
+/// ## Errors
+///
+/// Returns [`KernelError::OutOfMemory`] if the heap can't hold the buffers, the errors of submitting the requests
+/// (see [`RequestQueue::submit`]) and the ones reported by the device, otherwise the errors of [`vfs::read`]
+pub async fn read(path: String, offset: u64, length: usize) -> Result<Vec<u8>, KernelError> {
+    let (queue, extents) = match submit(move || plan(&path, offset, length)).await? {
+        Plan::Read(data) => return Ok(data),
+        Plan::Extents(queue, extents) => (queue, extents)
+    };
+
+    let total = extents.iter().map(|&(_, length)| length as usize).sum();
+    let mut data = allocate(total)?;
+    let sector_size = queue.device().sector_size() as u64;
+    let mut position = 0;
+
+    for (address, length) in extents {
+        let end = position + length as usize;
+
+        // The holes are zeros already
+        if let Some(address) = address {
+            let first = address / sector_size;
+            let last = (address + length - 1) / sector_size;
+            let buffer = queue.read(first, allocate(((last - first + 1) * sector_size) as usize)?)?.await?;
+
+            let start = (address % sector_size) as usize;
+            data[position..end].copy_from_slice(&buffer[start..start + length as usize]);
+        }
+
+        position = end;
+    }
+
+    return Ok(data);
+}
+
+/// Finds where the bytes asked of [`read`] are, run by the worker
+fn plan(path: &str, offset: u64, length: usize) -> Result<Plan, KernelError> {
+    let (fs, node) = vfs::resolve_with_fs(path)?;
+    let queue = fs.device().and_then(crate::block::find_queue);
+
+    let end = offset.saturating_add(length as u64).min(node.size());
+    let mut extents = Vec::new();
+    let mut position = offset;
+
+    if let (Some(queue), NodeKind::File) = (queue, node.kind()) {
+        while position < end {
+            let (address, length) = match node.extent(position) {
+                Ok((_, 0)) => return Err(KernelError::Io),
+                Ok((address, length)) => (address, length.min(end - position).min(MAX_REQUEST_SIZE)),
+                Err(KernelError::Unsupported) => break,
+                Err(error) => return Err(error)
+            };
+
+            extents.push((address, length));
+            position += length;
+        }
+
+        if position >= end {
+            return Ok(Plan::Extents(queue, extents));
+        }
+    }
+
+    let mut buffer = allocate(length)?;
+    let count = node.read_at(offset, &mut buffer)?;
+    buffer.truncate(count);
+
+    return Ok(Plan::Read(buffer));
+}
+
+/// Returns a buffer of `size` zeros, without panicking if the heap can't hold it
+fn allocate(size: usize) -> Result<Vec<u8>, KernelError> {
+    let mut buffer = Vec::new();
+    buffer.try_reserve_exact(size).map_err(|_| KernelError::OutOfMemory)?;
+    buffer.resize(size, 0);
+
+    return Ok(buffer);
+}
+
+/// Creates the work queue the operations run on, must be called once after the scheduler is initialized
+pub fn init() {
+    WorkQueue::create(WORK_QUEUE_NAME).expect("Failed to create the VFS work queue");
+}
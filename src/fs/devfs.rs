@@ -0,0 +1,300 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::block::BlockDevice;
+use crate::sched::WaitQueue;
+use crate::sync::SleepMutex;
+use crate::utils::error::KernelError;
+use crate::utils::queue::SpscQueue;
+use crate::utils::Mutex;
+use super::{DirEntry, FileSystem, Node, NodeKind};
+
+/// The devices registered so far besides the block devices, which are all shown as they're found
+static DEVICES: Mutex<Vec<(String, Arc<dyn Node>)>> = Mutex::new(Vec::new());
+
+/// How many bytes typed on the keyboard can wait to be read from the console
+const INPUT_BUFFER_SIZE: usize = 256;
+
+/// The lines typed for the program in the foreground, see [`push_input`]
+static INPUT: SpscQueue<u8, INPUT_BUFFER_SIZE> = SpscQueue::new();
+
+/// The threads waiting for [`INPUT`] to get a line
+static INPUT_WAITERS: WaitQueue = WaitQueue::new();
+
+/// Only one thread takes bytes out of [`INPUT`] at a time, which is all the queue allows
+static INPUT_READER: SleepMutex<()> = SleepMutex::new(());
+
+/// Hands a line typed on the keyboard to the readers of the console, followed by a new line. The shell calls this
+/// instead of running the line while a program is in the foreground. What doesn't fit in the buffer is lost
+///
+/// ## Note
+///
+/// This function must only be called by the shell, the queue can only be filled by one writer
+pub fn push_input(line: &str) {
+    for &byte in line.as_bytes().iter().chain(b"\n") {
+        let _ = INPUT.push(byte);
+    }
+
+    INPUT_WAITERS.wake_all();
+}
+
+/// Returns the console as a node, for the standard files of a process
+pub fn console() -> Arc<dyn Node> {
+    Arc::new(Console)
+}
+
+/// Shows `node` in the device filesystem as `name`, for the drivers of devices that aren't block devices
+///
+/// ## Errors
+///
+/// Returns [`KernelError::AlreadyExists`] if there's a device with that name already
+pub fn register(name: &str, node: Arc<dyn Node>) -> Result<(), KernelError> {
+    let mut devices = DEVICES.lock();
+
+    if devices.iter().any(|(registered, _)| registered == name) || crate::block::find(name).is_some() {
+        return Err(KernelError::AlreadyExists);
+    }
+
+    devices.push((String::from(name), node));
+    Ok(())
+}
+
+/// A filesystem made of the devices, usually mounted at `/dev`. It has no directories and nothing can be created
+/// in it, the devices are added by their drivers
+pub struct DevFs {
+    root: Arc<DevRoot>
+}
+
+impl DevFs {
+    pub fn new() -> Self {
+        DevFs { root: Arc::new(DevRoot) }
+    }
+}
+
+struct DevRoot;
+
+impl Node for DevRoot {
+    fn kind(&self) -> NodeKind {
+        NodeKind::Directory
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Node>, KernelError> {
+        if let Some((_, node)) = DEVICES.lock().iter().find(|(registered, _)| registered == name) {
+            return Ok(node.clone());
+        }
+
+        match crate::block::find(name) {
+            Some(device) => Ok(Arc::new(BlockNode(device))),
+            None => Err(KernelError::NotFound)
+        }
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, KernelError> {
+        let mut entries: Vec<DirEntry> = DEVICES
+            .lock()
+            .iter()
+            .map(|(name, node)| DirEntry { name: name.clone(), kind: NodeKind::Device, size: node.size() })
+            .collect();
+
+        for device in crate::block::devices() {
+            let node = BlockNode(device);
+            entries.push(DirEntry { name: String::from(node.0.name()), kind: NodeKind::Device, size: node.size() });
+        }
+
+        return Ok(entries);
+    }
+}
+
+/// The console, what's written to it is printed like the messages of the kernel. Reading it blocks until a line
+/// is typed for the program in the foreground, see [`push_input`]
+struct Console;
+
+impl Node for Console {
+    fn kind(&self) -> NodeKind {
+        NodeKind::Device
+    }
+
+    /// Reads at most one line, the rest of a line that doesn't fit in `buffer` is left for the next read
+    fn read_at(&self, _offset: u64, buffer: &mut [u8]) -> Result<usize, KernelError> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        let _reader = INPUT_READER.lock();
+
+        INPUT_WAITERS.wait_until(|| !INPUT.is_empty());
+
+        let mut count = 0;
+
+        while count < buffer.len() {
+            match INPUT.pop() {
+                Some(byte) => buffer[count] = byte,
+                None => break
+            }
+
+            count += 1;
+
+            if buffer[count - 1] == b'\n' {
+                break;
+            }
+        }
+
+        return Ok(count);
+    }
+
+    fn write_at(&self, _offset: u64, buffer: &[u8]) -> Result<usize, KernelError> {
+        let text = core::str::from_utf8(buffer).map_err(|_| KernelError::InvalidArgument)?;
+        crate::print!("{}", text);
+
+        return Ok(buffer.len());
+    }
+}
+
+/// Reads nothing and throws away what's written to it
+struct Null;
+
+impl Node for Null {
+    fn kind(&self) -> NodeKind {
+        NodeKind::Device
+    }
+
+    fn read_at(&self, _offset: u64, _buffer: &mut [u8]) -> Result<usize, KernelError> {
+        Ok(0)
+    }
+
+    fn write_at(&self, _offset: u64, buffer: &[u8]) -> Result<usize, KernelError> {
+        Ok(buffer.len())
+    }
+}
+
+/// Reads as many zeros as asked for and throws away what's written to it
+struct Zero;
+
+impl Node for Zero {
+    fn kind(&self) -> NodeKind {
+        NodeKind::Device
+    }
+
+    fn read_at(&self, _offset: u64, buffer: &mut [u8]) -> Result<usize, KernelError> {
+        buffer.fill(0);
+        Ok(buffer.len())
+    }
+
+    fn write_at(&self, _offset: u64, buffer: &[u8]) -> Result<usize, KernelError> {
+        Ok(buffer.len())
+    }
+}
+
+/// Reads random bytes from [`crate::rand::fill`], what's written to it is thrown away
+struct Random;
+
+impl Node for Random {
+    fn kind(&self) -> NodeKind {
+        NodeKind::Device
+    }
+
+    fn read_at(&self, _offset: u64, buffer: &mut [u8]) -> Result<usize, KernelError> {
+        crate::rand::fill(buffer);
+        Ok(buffer.len())
+    }
+
+    fn write_at(&self, _offset: u64, buffer: &[u8]) -> Result<usize, KernelError> {
+        Ok(buffer.len())
+    }
+}
+
+/// A block device, read and written at any offset. The sectors only partly covered are read first, so the bytes
+/// around what's written are kept
+struct BlockNode(Arc<dyn BlockDevice>);
+
+impl BlockNode {
+    /// Reads or writes `length` bytes of the device starting at `offset`, `copy` gets every sector covered with the
+    /// part of it that's asked for and where that part is in the bytes transferred. The transfer stops at the end of
+    /// the device
+    fn transfer(
+        &self,
+        offset: u64,
+        length: usize,
+        writing: bool,
+        mut copy: impl FnMut(&mut [u8], core::ops::Range<usize>, usize)
+    ) -> Result<usize, KernelError> {
+        let sector_size = self.0.sector_size() as u64;
+        let end = (offset + length as u64).min(self.0.sector_count() * sector_size);
+
+        let mut sector = vec![0; sector_size as usize];
+        let mut position = offset;
+
+        while position < end {
+            let index = position / sector_size;
+            let in_sector = (position % sector_size) as usize;
+            let count = (sector_size as usize - in_sector).min((end - position) as usize);
+
+            // Only the sectors that aren't covered whole have to be read before they're written
+            if !writing || count < sector.len() {
+                self.0.read(index, &mut sector)?;
+            }
+
+            copy(&mut sector, in_sector..in_sector + count, (position - offset) as usize);
+
+            if writing {
+                self.0.write(index, &sector)?;
+            }
+
+            position += count as u64;
+        }
+
+        return Ok(end.saturating_sub(offset) as usize);
+    }
+}
+
+impl Node for BlockNode {
+    fn kind(&self) -> NodeKind {
+        NodeKind::Device
+    }
+
+    fn size(&self) -> u64 {
+        self.0.sector_count() * self.0.sector_size() as u64
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, KernelError> {
+        self.transfer(offset, buffer.len(), false, |sector, range, copied| {
+            buffer[copied..copied + range.len()].copy_from_slice(&sector[range]);
+        })
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize, KernelError> {
+        self.transfer(offset, buffer.len(), true, |sector, range, copied| {
+            sector[range.clone()].copy_from_slice(&buffer[copied..copied + range.len()]);
+        })
+    }
+
+    fn sync(&self) -> Result<(), KernelError> {
+        self.0.flush()
+    }
+}
+
+impl FileSystem for DevFs {
+    fn name(&self) -> &str {
+        "devfs"
+    }
+
+    fn root(&self) -> Arc<dyn Node> {
+        self.root.clone()
+    }
+}
+
+/// Registers the devices every machine has: `console`, `null`, `zero` and `random`
+pub fn init() {
+    let devices: [(&str, Arc<dyn Node>); 4] = [
+        ("console", Arc::new(Console)),
+        ("null", Arc::new(Null)),
+        ("zero", Arc::new(Zero)),
+        ("random", Arc::new(Random))
+    ];
+
+    for (name, node) in devices {
+        // Nothing else is registered before these
+        let _ = register(name, node);
+    }
+}
@@ -0,0 +1,496 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::block::{BlockDevice, SECTOR_SIZE};
+use crate::utils::error::KernelError;
+use super::{DirEntry, FileSystem, Metadata, Node, NodeKind};
+
+/// Where the superblock is on the device, whatever the size of the blocks, and the size of it
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+
+const MAGIC: u16 = 0xEF53;
+
+/// The incompatible features that don't change how the filesystem is read: the type of the entries stored in the
+/// directories, a journal that wasn't replayed (what's read is what was last written back) and the flexible block
+/// groups (the descriptors still tell where everything is)
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+const INCOMPAT_RECOVER: u32 = 0x0004;
+const INCOMPAT_FLEX_BG: u32 = 0x0200;
+const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE | INCOMPAT_RECOVER | INCOMPAT_FLEX_BG;
+
+/// The size of the inodes of revision 0, the later ones tell it in the superblock
+const REVISION_0_INODE_SIZE: u64 = 128;
+
+/// The size of a block group descriptor
+const GROUP_DESCRIPTOR_SIZE: u64 = 32;
+
+/// The inode of the root directory
+const ROOT_INODE: u32 = 2;
+
+/// The type of an inode, in the upper bits of its mode
+const MODE_TYPE_MASK: u16 = 0xF000;
+const MODE_DIRECTORY: u16 = 0x4000;
+const MODE_REGULAR: u16 = 0x8000;
+const MODE_CHARACTER_DEVICE: u16 = 0x2000;
+const MODE_BLOCK_DEVICE: u16 = 0x6000;
+const MODE_SYMLINK: u16 = 0xA000;
+
+/// The blocks of an inode: 12 direct ones, followed by a single, a double and a triple indirect one
+const DIRECT_BLOCKS: u64 = 12;
+const SINGLY_INDIRECT: usize = 12;
+const DOUBLY_INDIRECT: usize = 13;
+const TRIPLY_INDIRECT: usize = 14;
+
+/// The types of the entries of a directory, when the filesystem stores them
+const ENTRY_TYPE_DIRECTORY: u8 = 2;
+const ENTRY_TYPE_CHARACTER_DEVICE: u8 = 3;
+const ENTRY_TYPE_BLOCK_DEVICE: u8 = 4;
+const ENTRY_TYPE_SYMLINK: u8 = 7;
+
+/// The paths of the symbolic links shorter than the block pointers of the inode are stored in them
+const FAST_SYMLINK_SIZE: u64 = 60;
+
+/// The size of the fixed part of an entry of a directory, before its name
+const ENTRY_HEADER_SIZE: usize = 8;
+
+/// The longest path a symbolic link can point to, like `PATH_MAX` of Linux
+const MAX_LINK_SIZE: u64 = 4096;
+
+/// How many blocks of a file [`Ext2Node::extent`] gives at most
+const MAX_EXTENT_BLOCKS: u64 = 16;
+
+/// An ext2 filesystem on a block device, only read
+pub struct Ext2(Arc<Volume>);
+
+/// What the nodes of an [`Ext2`] need from it
+struct Volume {
+    device: Arc<dyn BlockDevice>,
+    block_size: u64,
+    inodes_per_group: u64,
+    inode_size: u64,
+    /// The block the table of block group descriptors starts at, the one after the superblock
+    descriptors_block: u64
+}
+
+/// The part of an inode the kernel uses
+#[derive(Debug, Copy, Clone)]
+struct Inode {
+    mode: u16,
+    size: u64,
+    /// How many sectors of 512 bytes the inode uses, including the block of its extended attributes
+    sectors: u32,
+    /// The block of the extended attributes, 0 if there's none
+    attributes_block: u32,
+    blocks: [u32; 15],
+    /// When the inode was last read and last written, in seconds since the Unix epoch. ext2 doesn't keep when it
+    /// was created
+    accessed: u32,
+    modified: u32,
+    uid: u32,
+    gid: u32
+}
+
+impl Inode {
+    fn kind(&self) -> NodeKind {
+        match self.mode & MODE_TYPE_MASK {
+            MODE_DIRECTORY => NodeKind::Directory,
+            MODE_CHARACTER_DEVICE | MODE_BLOCK_DEVICE => NodeKind::Device,
+            MODE_SYMLINK => NodeKind::Symlink,
+            _ => NodeKind::File
+        }
+    }
+}
+
+impl Ext2 {
+    /// Looks for an ext2 filesystem on the whole of `device`
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Unsupported`] if there's no ext2 filesystem on the device (or one using features that
+    /// aren't supported, like the extents of ext4), otherwise the error of reading the device
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Self, KernelError> {
+        if device.sector_size() != SECTOR_SIZE {
+            return Err(KernelError::Unsupported);
+        }
+
+        let mut superblock = [0; SUPERBLOCK_SIZE];
+        device.read(SUPERBLOCK_OFFSET / SECTOR_SIZE as u64, &mut superblock)?;
+
+        let word = |offset: usize| u16::from_le_bytes([superblock[offset], superblock[offset + 1]]);
+        let dword = |offset: usize| u32::from_le_bytes(superblock[offset..offset + 4].try_into().unwrap());
+
+        if word(56) != MAGIC || dword(96) & !INCOMPAT_SUPPORTED != 0 {
+            return Err(KernelError::Unsupported);
+        }
+
+        // The size of a block is 1024 shifted by the value in the superblock, it's at most 64 KiB
+        let block_size = 1024u64
+            .checked_shl(dword(24))
+            .filter(|&size| size <= 0x10000)
+            .ok_or(KernelError::Unsupported)?;
+        let inode_size = if dword(76) == 0 { REVISION_0_INODE_SIZE } else { word(88) as u64 };
+
+        // An inode is read from the sector it starts in, which only has all of its first 128 bytes if the size of the
+        // inodes is a power of two (like ext2 itself requires)
+        let sizes = REVISION_0_INODE_SIZE..=block_size;
+
+        if dword(40) == 0 || !inode_size.is_power_of_two() || !sizes.contains(&inode_size) {
+            return Err(KernelError::Unsupported);
+        }
+
+        Ok(Ext2(Arc::new(Volume {
+            device,
+            block_size,
+            inodes_per_group: dword(40) as u64,
+            inode_size,
+            descriptors_block: dword(20) as u64 + 1
+        })))
+    }
+}
+
+impl Volume {
+    /// Reads the sector of the device with the byte at `offset`, returning where that byte is in it
+    fn read_sector_at(&self, offset: u64, sector: &mut [u8; SECTOR_SIZE]) -> Result<usize, KernelError> {
+        self.device.read(offset / SECTOR_SIZE as u64, sector)?;
+        return Ok((offset % SECTOR_SIZE as u64) as usize);
+    }
+
+    fn read_u32(&self, offset: u64) -> Result<u32, KernelError> {
+        let mut sector = [0; SECTOR_SIZE];
+        let index = self.read_sector_at(offset, &mut sector)?;
+
+        return Ok(u32::from_le_bytes(sector[index..index + 4].try_into().unwrap()));
+    }
+
+    fn read_inode(&self, number: u32) -> Result<Inode, KernelError> {
+        let number = number.checked_sub(1).ok_or(KernelError::Io)? as u64;
+        let (group, index) = (number / self.inodes_per_group, number % self.inodes_per_group);
+
+        // The block of the inode table is at byte 8 of the descriptor of the group
+        let descriptor = self.descriptors_block * self.block_size + group * GROUP_DESCRIPTOR_SIZE;
+        let table = self.read_u32(descriptor + 8)? as u64;
+
+        // The first 128 bytes of an inode are the same whatever its size, so they're always in one sector
+        let mut sector = [0; SECTOR_SIZE];
+        let start = self.read_sector_at(table * self.block_size + index * self.inode_size, &mut sector)?;
+        let inode = &sector[start..start + REVISION_0_INODE_SIZE as usize];
+
+        let dword = |offset: usize| u32::from_le_bytes(inode[offset..offset + 4].try_into().unwrap());
+        let word = |offset: usize| u16::from_le_bytes([inode[offset], inode[offset + 1]]) as u32;
+        let mode = word(0) as u16;
+
+        // The upper half of the size of a regular file is where the ACL of a directory would be
+        let size_high = if mode & MODE_TYPE_MASK == MODE_REGULAR { dword(108) as u64 } else { 0 };
+
+        let mut blocks = [0; 15];
+
+        for (index, block) in blocks.iter_mut().enumerate() {
+            *block = dword(40 + index * 4);
+        }
+
+        Ok(Inode {
+            mode,
+            size: size_high << 32 | dword(4) as u64,
+            sectors: dword(28),
+            attributes_block: dword(104),
+            blocks,
+            accessed: dword(8),
+            modified: dword(16),
+            // The upper halves of the IDs are in the part of the inode that depends on the OS, where Linux puts them
+            uid: word(120) << 16 | word(2),
+            gid: word(122) << 16 | word(24)
+        })
+    }
+
+    /// Returns the block of the device with the block number `index` of the file, 0 for a hole that reads as zeros
+    fn block_of(&self, inode: &Inode, index: u64) -> Result<u32, KernelError> {
+        let per_block = self.block_size / 4;
+
+        if index < DIRECT_BLOCKS {
+            return Ok(inode.blocks[index as usize]);
+        }
+
+        // Finds which indirect block the index goes through, and the index in the blocks it points to
+        let mut index = index - DIRECT_BLOCKS;
+        let mut span = per_block;
+        let mut levels = 1;
+
+        for root in [SINGLY_INDIRECT, DOUBLY_INDIRECT, TRIPLY_INDIRECT] {
+            if index < span {
+                let mut block = inode.blocks[root];
+
+                // Every level of indirection divides the span by the number of blocks an indirect block has
+                for level in (0..levels).rev() {
+                    if block == 0 {
+                        return Ok(0);
+                    }
+
+                    let entry = index / per_block.pow(level) % per_block;
+                    block = self.read_u32(block as u64 * self.block_size + entry * 4)?;
+                }
+
+                return Ok(block);
+            }
+
+            index -= span;
+            span *= per_block;
+            levels += 1;
+        }
+
+        return Err(KernelError::InvalidArgument);
+    }
+
+    /// Reads the bytes of the file starting at `offset` into `buffer`, which must not go past its end
+    fn read(&self, inode: &Inode, offset: u64, buffer: &mut [u8]) -> Result<(), KernelError> {
+        let mut sector = [0; SECTOR_SIZE];
+        let mut position = offset;
+        let end = offset + buffer.len() as u64;
+
+        while position < end {
+            let in_sector = (position % SECTOR_SIZE as u64) as usize;
+            let count = (SECTOR_SIZE - in_sector).min((end - position) as usize);
+            let copied = (position - offset) as usize;
+
+            match self.block_of(inode, position / self.block_size)? {
+                0 => buffer[copied..copied + count].fill(0),
+                block => {
+                    let address = block as u64 * self.block_size + position % self.block_size;
+                    let start = self.read_sector_at(address, &mut sector)?;
+                    buffer[copied..copied + count].copy_from_slice(&sector[start..start + count]);
+                }
+            }
+
+            position += count as u64;
+        }
+
+        Ok(())
+    }
+
+    /// Calls `f` with the name, the inode and the type (0 if unknown) of every entry of the directory until it returns
+    /// something
+    fn find_entry<T>(
+        &self,
+        directory: &Inode,
+        mut f: impl FnMut(&str, u32, u8) -> Option<T>
+    ) -> Result<Option<T>, KernelError> {
+        let mut header = [0; ENTRY_HEADER_SIZE];
+        let mut name = [0; 255];
+        let mut offset = 0;
+
+        // An entry never crosses a block, so the block it starts in has the rest of it
+        while offset + ENTRY_HEADER_SIZE as u64 <= directory.size {
+            self.read(directory, offset, &mut header)?;
+
+            let inode = u32::from_le_bytes(header[0..4].try_into().unwrap());
+            let length = u16::from_le_bytes([header[4], header[5]]) as u64;
+            let name_length = header[6] as usize;
+
+            if length < ENTRY_HEADER_SIZE as u64 || offset + length > directory.size {
+                return Err(KernelError::Io);
+            }
+
+            // The deleted entries have inode 0
+            if inode != 0 {
+                self.read(directory, offset + ENTRY_HEADER_SIZE as u64, &mut name[..name_length])?;
+
+                let text = core::str::from_utf8(&name[..name_length]).map_err(|_| KernelError::Io)?;
+
+                if let Some(result) = f(text, inode, header[7]) {
+                    return Ok(Some(result));
+                }
+            }
+
+            offset += length;
+        }
+
+        return Ok(None);
+    }
+}
+
+/// A file, a directory, a device or a symbolic link of an [`Ext2`] filesystem. The other special files are shown as
+/// files that can't be read
+struct Ext2Node {
+    fs: Arc<Volume>,
+    inode: Inode
+}
+
+impl Node for Ext2Node {
+    fn kind(&self) -> NodeKind {
+        self.inode.kind()
+    }
+
+    fn size(&self) -> u64 {
+        match self.kind() {
+            NodeKind::File | NodeKind::Symlink => self.inode.size,
+            _ => 0
+        }
+    }
+
+    fn metadata(&self) -> Result<Metadata, KernelError> {
+        Ok(Metadata {
+            mode: self.inode.mode & 0o7777,
+            uid: self.inode.uid,
+            gid: self.inode.gid,
+            accessed: self.inode.accessed as u64,
+            modified: self.inode.modified as u64,
+            ..Metadata::new(self.kind(), self.size())
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Node>, KernelError> {
+        if self.kind() != NodeKind::Directory {
+            return Err(KernelError::NotDirectory);
+        }
+
+        let number = self.fs
+            .find_entry(&self.inode, |entry, inode, _| (entry == name).then_some(inode))?
+            .ok_or(KernelError::NotFound)?;
+
+        return Ok(Arc::new(Ext2Node { fs: self.fs.clone(), inode: self.fs.read_inode(number)? }));
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, KernelError> {
+        if self.inode.mode & MODE_TYPE_MASK != MODE_REGULAR {
+            return Err(self.refusal());
+        }
+
+        let end = (offset + buffer.len() as u64).min(self.inode.size);
+
+        if offset >= end {
+            return Ok(0);
+        }
+
+        let count = (end - offset) as usize;
+        self.fs.read(&self.inode, offset, &mut buffer[..count])?;
+
+        return Ok(count);
+    }
+
+    /// The blocks that follow the one at `offset` on the device are looked for until the next [`MAX_EXTENT_BLOCKS`]
+    /// blocks of the file, so a big file doesn't have all of its indirect blocks read at once
+    fn extent(&self, offset: u64) -> Result<(Option<u64>, u64), KernelError> {
+        if self.inode.mode & MODE_TYPE_MASK != MODE_REGULAR {
+            return Err(self.refusal());
+        }
+
+        let block_size = self.fs.block_size;
+        let first = offset / block_size;
+        let start = self.fs.block_of(&self.inode, first)?;
+        let last = (self.inode.size.saturating_sub(1) / block_size).min(first + MAX_EXTENT_BLOCKS - 1);
+        let mut blocks = 1;
+
+        // The blocks of a hole are 0, so a hole goes on as long as the next blocks are 0 too
+        while first + blocks <= last {
+            let expected = if start == 0 { 0 } else { start as u64 + blocks };
+
+            if self.fs.block_of(&self.inode, first + blocks)? as u64 != expected {
+                break;
+            }
+
+            blocks += 1;
+        }
+
+        let address = (start != 0).then(|| start as u64 * block_size + offset % block_size);
+        return Ok((address, blocks * block_size - offset % block_size));
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, KernelError> {
+        if self.kind() != NodeKind::Directory {
+            return Err(KernelError::NotDirectory);
+        }
+
+        let mut entries = Vec::new();
+
+        let failed = self.fs.find_entry(&self.inode, |name, inode, kind| {
+            if name == "." || name == ".." {
+                return None;
+            }
+
+            // The size is only in the inode, which also tells the type when the entries don't have it
+            let inode = match self.fs.read_inode(inode) {
+                Ok(inode) => inode,
+                Err(error) => return Some(error)
+            };
+
+            let kind = match kind {
+                0 => inode.kind(),
+                ENTRY_TYPE_DIRECTORY => NodeKind::Directory,
+                ENTRY_TYPE_CHARACTER_DEVICE | ENTRY_TYPE_BLOCK_DEVICE => NodeKind::Device,
+                ENTRY_TYPE_SYMLINK => NodeKind::Symlink,
+                _ => NodeKind::File
+            };
+
+            let size = match inode.kind() {
+                NodeKind::File | NodeKind::Symlink => inode.size,
+                _ => 0
+            };
+
+            entries.push(DirEntry { name: String::from(name), kind, size });
+            None
+        })?;
+
+        if let Some(error) = failed {
+            return Err(error);
+        }
+
+        return Ok(entries);
+    }
+
+    fn read_link(&self) -> Result<String, KernelError> {
+        if self.kind() != NodeKind::Symlink {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        // The path of a symbolic link is in a single block at most, and no longer than a path can be
+        if self.inode.size > self.fs.block_size.min(MAX_LINK_SIZE) {
+            return Err(KernelError::Io);
+        }
+
+        let mut target = alloc::vec![0; self.inode.size as usize];
+
+        // A short path is in the block pointers when the inode has no blocks besides the one of its attributes
+        let attributes_sectors = if self.inode.attributes_block != 0 { (self.fs.block_size / 512) as u32 } else { 0 };
+
+        if self.inode.size < FAST_SYMLINK_SIZE && self.inode.sectors == attributes_sectors {
+            let mut pointers = [0; FAST_SYMLINK_SIZE as usize];
+
+            for (bytes, block) in pointers.chunks_exact_mut(4).zip(self.inode.blocks) {
+                bytes.copy_from_slice(&block.to_le_bytes());
+            }
+
+            let length = target.len();
+            target.copy_from_slice(&pointers[..length]);
+        } else {
+            self.fs.read(&self.inode, 0, &mut target)?;
+        }
+
+        return String::from_utf8(target).map_err(|_| KernelError::Io);
+    }
+}
+
+impl FileSystem for Ext2 {
+    fn name(&self) -> &str {
+        "ext2"
+    }
+
+    fn root(&self) -> Arc<dyn Node> {
+        // The root is read again every time, a filesystem whose root can't be read is of no use anyway
+        let inode = self.0.read_inode(ROOT_INODE).unwrap_or(Inode {
+            mode: MODE_DIRECTORY,
+            size: 0,
+            sectors: 0,
+            attributes_block: 0,
+            blocks: [0; 15],
+            accessed: 0,
+            modified: 0,
+            uid: 0,
+            gid: 0
+        });
+        Arc::new(Ext2Node { fs: self.0.clone(), inode })
+    }
+
+    fn device(&self) -> Option<&str> {
+        Some(self.0.device.name())
+    }
+}
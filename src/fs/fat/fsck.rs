@@ -0,0 +1,354 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::block::{BlockDevice, SECTOR_SIZE};
+use crate::memory::address_space::UnmappedFrame;
+use crate::println;
+use crate::utils::error::KernelError;
+use super::{CLUSTER_BAD, CLUSTER_END, CLUSTER_FREE, CLUSTER_LAST, CLUSTER_MASK, ENTRY_DELETED, FIRST_CLUSTER};
+use super::{Entry, Fat32, Volume, set_entry_cluster};
+
+/// How many clusters a page of a [`ClusterMap`] has a bit for
+const CLUSTERS_PER_PAGE: usize = 4096 * 8;
+
+/// What [`fsck`] found on a volume
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Report {
+    /// How many files and directories were checked (with the root), and how many clusters their chains have
+    pub nodes: u64,
+    pub clusters: u64,
+    /// The chains going to a cluster that's free, bad or out of the volume
+    pub broken_chains: u64,
+    /// The chains going to a cluster another chain goes through (or to one of their own, when they loop)
+    pub cross_links: u64,
+    /// The clusters used in the FAT that no chain goes through, their content is lost
+    pub orphaned_clusters: u64,
+    /// The files whose size doesn't match the length of their chain
+    pub wrong_sizes: u64,
+    /// Whatever the problems found were repaired
+    pub repaired: bool
+}
+
+impl Report {
+    /// Returns whatever nothing is wrong with the volume
+    pub fn is_clean(&self) -> bool {
+        self.broken_chains == 0 && self.cross_links == 0 && self.orphaned_clusters == 0 && self.wrong_sizes == 0
+    }
+}
+
+/// A bit for every cluster of a volume, set once a chain goes through the cluster. It takes a page for 32768
+/// clusters, which is too much for the heap on most volumes, so its pages are frames of their own
+struct ClusterMap(Vec<UnmappedFrame>);
+
+impl ClusterMap {
+    fn new(clusters: u32) -> Result<Self, KernelError> {
+        let pages = (clusters as usize + CLUSTERS_PER_PAGE - 1) / CLUSTERS_PER_PAGE;
+        let mut frames = Vec::new();
+
+        frames.try_reserve_exact(pages).map_err(|_| KernelError::OutOfMemory)?;
+
+        for _ in 0..pages {
+            frames.push(UnmappedFrame::new()?);
+        }
+
+        Ok(ClusterMap(frames))
+    }
+
+    /// Returns the byte with the bit of `cluster`, and the mask of the bit
+    fn bit(&mut self, cluster: u32) -> (&mut u8, u8) {
+        let index = (cluster - FIRST_CLUSTER) as usize;
+        let page = self.0[index / CLUSTERS_PER_PAGE].bytes();
+
+        return (&mut page[index % CLUSTERS_PER_PAGE / 8], 1 << (index % 8));
+    }
+
+    fn is_set(&mut self, cluster: u32) -> bool {
+        let (byte, mask) = self.bit(cluster);
+        *byte & mask != 0
+    }
+
+    /// Sets the bit of `cluster`, returning whatever it was set already
+    fn set(&mut self, cluster: u32) -> bool {
+        let (byte, mask) = self.bit(cluster);
+        let set = *byte & mask != 0;
+
+        *byte |= mask;
+        return set;
+    }
+}
+
+/// Where a chain goes next, see [`Checker::follow`]
+enum Link {
+    End,
+    Next(u32),
+    /// To a number that's not a cluster of the volume, like a free or a bad entry
+    Broken(u32),
+    /// To a cluster a chain went through already
+    CrossLinked(u32)
+}
+
+/// A check of a volume going on
+struct Checker<'a> {
+    volume: &'a Volume,
+    repair: bool,
+    /// The clusters the chains checked so far go through
+    reached: ClusterMap,
+    report: Report
+}
+
+impl Checker<'_> {
+    /// Tells where a chain goes when `value` is the entry of its last cluster, or the first cluster of its node,
+    /// marking the cluster it goes to as reached
+    fn follow(&mut self, value: u32) -> Link {
+        let clusters = FIRST_CLUSTER..self.volume.cluster_count + FIRST_CLUSTER;
+
+        match value {
+            value if value >= CLUSTER_END => Link::End,
+            value if !clusters.contains(&value) => Link::Broken(value),
+            value if self.reached.set(value) => Link::CrossLinked(value),
+            value => Link::Next(value)
+        }
+    }
+
+    /// Follows the chain starting at `first` of the node at `path`, returning how many clusters it has up to the
+    /// first link that's wrong. A repair ends the chain before that link, when it's the first one the node is
+    /// left to the caller
+    fn check_chain(&mut self, path: &str, first: u32) -> Result<u64, KernelError> {
+        let mut length = 0;
+        let mut last = None;
+
+        let mut link = match self.follow(first) {
+            Link::End => Link::Broken(first),
+            link => link
+        };
+
+        loop {
+            let (value, problem) = match link {
+                Link::End => return Ok(length),
+                Link::Next(cluster) => {
+                    length += 1;
+                    last = Some(cluster);
+                    link = self.follow(self.volume.fat_value(cluster)?);
+
+                    continue;
+                },
+                Link::Broken(value) => {
+                    self.report.broken_chains += 1;
+                    (value, "isn't a cluster in use")
+                },
+                Link::CrossLinked(value) => {
+                    self.report.cross_links += 1;
+                    (value, "is in another chain already")
+                }
+            };
+
+            match last {
+                Some(cluster) => println!("fsck: {}: cluster {} goes to {}, which {}", path, cluster, value, problem),
+                None => println!("fsck: {}: the chain starts at {}, which {}", path, value, problem)
+            }
+
+            if let (true, Some(cluster)) = (self.repair, last) {
+                self.volume.set_next_cluster(cluster, CLUSTER_LAST)?;
+            }
+
+            return Ok(length);
+        }
+    }
+
+    /// Checks the chain of the file at `path` and that its size matches it. A repair makes the size what the chain
+    /// holds when the chain is too short, and cuts the chain to the size when it's too long
+    fn check_file(&mut self, path: &str, entry: &Entry) -> Result<(), KernelError> {
+        let length = if entry.cluster != 0 { self.check_chain(path, entry.cluster)? } else { 0 };
+        let cluster_size = self.volume.cluster_size();
+        let needed = (entry.size as u64 + cluster_size - 1) / cluster_size;
+
+        self.report.nodes += 1;
+        self.report.clusters += length;
+
+        if length < needed {
+            self.report.wrong_sizes += 1;
+            println!("fsck: {}: the size is {} bytes, the chain holds {}", path, entry.size, length * cluster_size);
+        } else if length > needed {
+            self.report.wrong_sizes += 1;
+            println!("fsck: {}: the chain has {} clusters, the size only needs {}", path, length, needed);
+        }
+
+        // A chain whose first link is wrong is already counted, the entry still has to let go of it
+        if !self.repair || (length == needed && (length != 0 || entry.cluster == 0)) {
+            return Ok(());
+        }
+
+        if length > needed && needed != 0 {
+            let last = self.volume.cluster_at(entry.cluster, needed - 1)?;
+
+            if let Some(rest) = self.volume.next_cluster(last)? {
+                self.volume.set_next_cluster(last, CLUSTER_LAST)?;
+                self.volume.free_chain(rest)?;
+            }
+        } else if length > needed {
+            self.volume.free_chain(entry.cluster)?;
+        }
+
+        let cluster = if length == 0 || needed == 0 { 0 } else { entry.cluster };
+        let size = (entry.size as u64).min(length * cluster_size) as u32;
+
+        self.volume.change_entry(entry.location, |raw| {
+            set_entry_cluster(raw, cluster);
+            raw[28..32].copy_from_slice(&size.to_le_bytes());
+        })
+    }
+
+    /// Checks the chain of the directory at `path`, returning how many of its clusters can be read. A directory
+    /// without a single one is removed by a repair
+    fn check_directory(&mut self, path: &str, entry: &Entry) -> Result<u64, KernelError> {
+        let length = match entry.cluster {
+            0 => {
+                self.report.broken_chains += 1;
+                println!("fsck: {}: the directory has no clusters", path);
+
+                0
+            },
+            cluster => self.check_chain(path, cluster)?
+        };
+
+        self.report.nodes += 1;
+        self.report.clusters += length;
+
+        if length == 0 && self.repair {
+            for &location in entry.long_entries.iter().chain([&entry.location]) {
+                self.volume.change_entry(location, |raw| raw[0] = ENTRY_DELETED)?;
+            }
+        }
+
+        return Ok(length);
+    }
+
+    /// Checks every file and directory from the root down
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Io`] if the first cluster of the root directory is wrong
+    fn check_tree(&mut self) -> Result<(), KernelError> {
+        let root = self.volume.root_cluster;
+        let length = self.check_chain("/", root)?;
+
+        if length == 0 {
+            return Err(KernelError::Io);
+        }
+
+        self.report.nodes += 1;
+        self.report.clusters += length;
+
+        // The directories left to go through: their path, their first cluster and how many clusters can be read
+        let mut directories = vec![(String::new(), root, length)];
+
+        while let Some((path, cluster, clusters)) = directories.pop() {
+            let volume = self.volume;
+
+            let failed = volume.find_entry_within(cluster, clusters, |entry| {
+                if entry.name == "." || entry.name == ".." {
+                    return None;
+                }
+
+                let path = format!("{}/{}", path, entry.name);
+
+                if !entry.directory {
+                    return self.check_file(&path, entry).err();
+                }
+
+                match self.check_directory(&path, entry) {
+                    Ok(0) => None,
+                    Ok(length) => {
+                        directories.push((path, entry.cluster, length));
+                        None
+                    },
+                    Err(error) => Some(error)
+                }
+            })?;
+
+            if let Some(error) = failed {
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Counts the clusters used in the FAT that no chain went through, a repair frees them
+    fn check_orphans(&mut self) -> Result<(), KernelError> {
+        let entries_per_sector = (SECTOR_SIZE / 4) as u32;
+        let mut sector = [0; SECTOR_SIZE];
+
+        for cluster in FIRST_CLUSTER..self.volume.cluster_count + FIRST_CLUSTER {
+            let (sector_number, index) = self.volume.fat_entry(cluster);
+
+            // A sector of the FAT is only read once, the entries freed meanwhile are never looked at again
+            if cluster == FIRST_CLUSTER || cluster % entries_per_sector == 0 {
+                self.volume.read_sector(sector_number, &mut sector)?;
+            }
+
+            let value = u32::from_le_bytes(sector[index..index + 4].try_into().unwrap()) & CLUSTER_MASK;
+
+            if value == CLUSTER_FREE || value == CLUSTER_BAD || self.reached.is_set(cluster) {
+                continue;
+            }
+
+            self.report.orphaned_clusters += 1;
+
+            if self.repair {
+                self.volume.set_next_cluster(cluster, CLUSTER_FREE)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks the FAT32 volume on `device` (see [`Fat32::new`]): the chains of the files and directories must only go
+/// through clusters in use that no other chain goes through, the size of every file must match the length of its
+/// chain, and every cluster used in the FAT must be in a chain. The problems are printed as they're found
+///
+/// With `repair` they're fixed losing as little as possible: a chain is ended before its first wrong link, a file
+/// takes the size its chain holds (or its chain is cut to its size), a directory without any cluster is removed
+/// and the clusters no chain goes through are freed
+///
+/// ## Note
+///
+/// The volume is read straight from the device, the filesystem mounted on it doesn't know about the check. It must
+/// be synced first, and nothing should write to it until the check is over
+///
+/// ## Errors
+///
+/// Returns [`KernelError::Io`] if the first cluster of the root directory is wrong, since nothing can be checked
+/// then, [`KernelError::OutOfMemory`] if there aren't enough free frames to keep track of the clusters, otherwise
+/// the errors of [`Fat32::new`] and of the device
+pub fn fsck(device: Arc<dyn BlockDevice>, repair: bool) -> Result<Report, KernelError> {
+    let fs = Fat32::new(device)?;
+    let volume = fs.0.as_ref();
+
+    let mut checker = Checker {
+        volume,
+        repair,
+        reached: ClusterMap::new(volume.cluster_count)?,
+        report: Report::default()
+    };
+
+    let change = volume.change();
+
+    checker.check_tree()?;
+    checker.check_orphans()?;
+
+    drop(change);
+
+    let mut report = checker.report;
+    report.repaired = repair && !report.is_clean();
+
+    // The free cluster count of the FSInfo sector is wrong after a repair, syncing marks it as unknown
+    if report.repaired {
+        volume.sync()?;
+    }
+
+    return Ok(report);
+}
@@ -0,0 +1,230 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use super::{ATTRIBUTE_LONG_NAME, EntryLocation, is_short_name_byte};
+
+/// Set in the sequence number of the entry with the end of a long name, which is the first one in the directory.
+/// The number itself is in the low bits, the entry with the start of the name has 1
+const LAST_ENTRY: u8 = 0x40;
+const SEQUENCE_MASK: u8 = 0x1F;
+
+/// How many UCS-2 characters an entry holds, and how many entries a name of [`MAX_LENGTH`] characters takes
+const CHARACTERS_PER_ENTRY: usize = 13;
+const MAX_ENTRIES: usize = 20;
+
+/// How many UCS-2 characters a long name has at most
+const MAX_LENGTH: usize = 255;
+
+/// Where the characters are in an entry, they're split in three parts around the attributes, the checksum and the
+/// cluster (always 0)
+const CHARACTER_OFFSETS: [usize; CHARACTERS_PER_ENTRY] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/// The characters no long name has, with the control ones
+const FORBIDDEN: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+/// The checksum of the 11 bytes of an 8.3 name, every entry of its long name has it so a long name left behind by
+/// a system that doesn't know about them isn't taken for the one of the entry after it
+pub(super) fn checksum(short: &[u8]) -> u8 {
+    short.iter().fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+/// Returns how many entries a long name of `length` UCS-2 characters takes
+pub(super) fn entry_count(length: usize) -> usize {
+    (length + CHARACTERS_PER_ENTRY - 1) / CHARACTERS_PER_ENTRY
+}
+
+/// Returns the UCS-2 characters of `name` (UTF-16, like Windows does), or `None` if it can't be a long name: it
+/// must have between 1 and 255 of them, none of the [`FORBIDDEN`] or control ones, and can't end with a dot or a
+/// space
+pub(super) fn encode(name: &str) -> Option<Vec<u16>> {
+    let valid = name.chars().all(|character| !character.is_control() && !FORBIDDEN.contains(&character));
+
+    if !valid || name.is_empty() || name.ends_with('.') || name.ends_with(' ') {
+        return None;
+    }
+
+    let characters: Vec<u16> = name.encode_utf16().collect();
+    return (characters.len() <= MAX_LENGTH).then_some(characters);
+}
+
+/// Fills `raw` with the entry number `sequence` (starting at 1) of the long name `characters`, whose 8.3 entry
+/// has `checksum`. The name ends with a NUL if it doesn't fill its last entry, the rest of which is padded with
+/// `0xFFFF`
+pub(super) fn fill_entry(raw: &mut [u8], characters: &[u16], sequence: usize, checksum: u8) {
+    let last = sequence == entry_count(characters.len());
+
+    raw.fill(0);
+    raw[0] = sequence as u8 | if last { LAST_ENTRY } else { 0 };
+    raw[11] = ATTRIBUTE_LONG_NAME;
+    raw[13] = checksum;
+
+    for (index, &offset) in CHARACTER_OFFSETS.iter().enumerate() {
+        let position = (sequence - 1) * CHARACTERS_PER_ENTRY + index;
+
+        let character = match position {
+            position if position < characters.len() => characters[position],
+            position if position == characters.len() => 0,
+            _ => 0xFFFF
+        };
+
+        raw[offset..offset + 2].copy_from_slice(&character.to_le_bytes());
+    }
+}
+
+/// Turns `name` into the 11 bytes of the 8.3 name Windows would start from for it: in uppercase, without the
+/// spaces and the dots but the one of the extension, with the characters 8.3 names can't have replaced by `_`, and
+/// cut to 8 and 3 characters. Also returns whatever something was lost on the way, apart from the case
+pub(super) fn basis(name: &str) -> ([u8; 11], bool) {
+    let trimmed = name.trim_start_matches('.');
+    let (base, extension) = trimmed.rsplit_once('.').unwrap_or((trimmed, ""));
+
+    let mut short = [b' '; 11];
+    let mut lossy = trimmed.len() != name.len();
+
+    for (part, start, length) in [(base, 0, 8), (extension, 8, 3)] {
+        let mut count = 0;
+
+        for character in part.chars() {
+            let byte = match character {
+                ' ' | '.' => {
+                    lossy = true;
+                    continue;
+                },
+                character if character.is_ascii() && is_short_name_byte(character as u8) => {
+                    character.to_ascii_uppercase() as u8
+                },
+                _ => {
+                    lossy = true;
+                    b'_'
+                }
+            };
+
+            if count == length {
+                lossy = true;
+                break;
+            }
+
+            short[start + count] = byte;
+            count += 1;
+        }
+    }
+
+    // A name made only of what's left out still needs a base
+    if short[0] == b' ' {
+        short[0] = b'_';
+        lossy = true;
+    }
+
+    return (short, lossy);
+}
+
+/// Returns `basis` (see [`basis`]) with the numeric tail `~number` at the end of its base, which is cut to make room
+/// for it
+pub(super) fn with_tail(basis: &[u8; 11], number: u32) -> [u8; 11] {
+    let mut tail = [0; 8];
+    let mut length = 0;
+    let mut rest = number;
+
+    // The digits come out backwards, from the last one
+    while rest != 0 || length == 0 {
+        tail[length] = b'0' + (rest % 10) as u8;
+        rest /= 10;
+        length += 1;
+    }
+
+    tail[length] = b'~';
+    length += 1;
+
+    let base_length = basis[..8].iter().position(|&byte| byte == b' ').unwrap_or(8).min(8 - length);
+    let mut short = *basis;
+
+    short[base_length..8].fill(b' ');
+
+    for (index, &byte) in tail[..length].iter().rev().enumerate() {
+        short[base_length + index] = byte;
+    }
+
+    return short;
+}
+
+/// The entries of a long name gathered while going through a directory, until the 8.3 entry they belong to comes.
+/// They're in the directory from the end of the name to its start
+pub(super) struct LongName {
+    characters: [u16; MAX_ENTRIES * CHARACTERS_PER_ENTRY],
+    /// How many entries the name has, 0 when none is being gathered
+    count: usize,
+    /// The sequence number of the entry that has to come next, 0 once they all came
+    next: usize,
+    checksum: u8,
+    locations: Vec<EntryLocation>
+}
+
+impl LongName {
+    pub(super) fn new() -> Self {
+        LongName {
+            characters: [0; MAX_ENTRIES * CHARACTERS_PER_ENTRY],
+            count: 0,
+            next: 0,
+            checksum: 0,
+            locations: Vec::new()
+        }
+    }
+
+    /// Forgets the entries gathered so far
+    pub(super) fn clear(&mut self) {
+        self.count = 0;
+        self.next = 0;
+        self.locations.clear();
+    }
+
+    /// Adds the long name entry `raw`, which is at `location`. An entry that doesn't follow the ones before it
+    /// drops them, the name has to start over from its last entry
+    pub(super) fn push(&mut self, raw: &[u8], location: EntryLocation) {
+        let sequence = (raw[0] & SEQUENCE_MASK) as usize;
+
+        if raw[0] & LAST_ENTRY != 0 {
+            self.clear();
+
+            if sequence == 0 || sequence > MAX_ENTRIES {
+                return;
+            }
+
+            self.count = sequence;
+            self.checksum = raw[13];
+        } else if self.next == 0 || sequence != self.next || raw[13] != self.checksum {
+            self.clear();
+            return;
+        }
+
+        let start = (sequence - 1) * CHARACTERS_PER_ENTRY;
+
+        for (index, &offset) in CHARACTER_OFFSETS.iter().enumerate() {
+            self.characters[start + index] = u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        }
+
+        self.locations.push(location);
+        self.next = sequence - 1;
+    }
+
+    /// Returns the name gathered and where its entries are if it belongs to the 8.3 entry whose name is `short`
+    /// (its 11 bytes), forgetting it. The characters that aren't valid UTF-16 are replaced
+    pub(super) fn take(&mut self, short: &[u8]) -> Option<(String, Vec<EntryLocation>)> {
+        if self.count == 0 || self.next != 0 || checksum(short) != self.checksum {
+            self.clear();
+            return None;
+        }
+
+        let name: String = char::decode_utf16(
+            self.characters[..self.count * CHARACTERS_PER_ENTRY]
+                .iter()
+                .copied()
+                .take_while(|&character| character != 0)
+        )
+        .map(|character| character.unwrap_or('?'))
+        .collect();
+
+        let locations = core::mem::take(&mut self.locations);
+        self.clear();
+
+        return (!name.is_empty()).then_some((name, locations));
+    }
+}
@@ -0,0 +1,1190 @@
+mod fsck;
+mod lfn;
+
+#[allow(unused_imports)]
+pub use fsck::{fsck, Report};
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::block::{BlockDevice, SECTOR_SIZE};
+use core::ops::{Deref, DerefMut};
+use crate::sync::{SleepMutex, SleepMutexGuard};
+use crate::time::calendar::DateTime;
+use crate::utils::error::KernelError;
+use lfn::LongName;
+use super::{DirEntry, FileSystem, Metadata, Node, NodeKind};
+
+/// The signature at the end of the boot sector (and of the MBR)
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+/// The partition types of the MBR that hold a FAT32 volume, addressed with CHS or with LBA
+const PARTITION_FAT32: u8 = 0x0B;
+const PARTITION_FAT32_LBA: u8 = 0x0C;
+
+/// Where the 4 entries of the partition table are in the MBR, and the size of each of them
+const PARTITION_TABLE: usize = 0x1BE;
+const PARTITION_ENTRY_SIZE: usize = 16;
+
+/// The signatures of the FSInfo sector, at its start and right before the free cluster count and the hint of the
+/// next free cluster
+const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FSINFO_FREE_COUNT: usize = 488;
+const FSINFO_NEXT_FREE: usize = 492;
+
+/// Written as the free cluster count of the FSInfo sector once the kernel changed the FAT, it doesn't keep count
+const FSINFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+/// The size of an entry of a directory
+const ENTRY_SIZE: usize = 32;
+
+/// The first byte of the name of an entry: the end of the directory, a deleted entry and a name that really starts
+/// with the byte of deleted entries
+const ENTRY_END: u8 = 0x00;
+const ENTRY_DELETED: u8 = 0xE5;
+const ENTRY_KANJI_E5: u8 = 0x05;
+
+/// The names of the first two entries of every directory but the root, padded like every 8.3 name
+const DOT_NAME: &[u8; 11] = b".          ";
+const DOT_DOT_NAME: &[u8; 11] = b"..         ";
+
+/// The attributes of an entry, the ones of a long file name entry are all of the first four together
+const ATTRIBUTE_READ_ONLY: u8 = 0x01;
+const ATTRIBUTE_VOLUME_ID: u8 = 0x08;
+const ATTRIBUTE_DIRECTORY: u8 = 0x10;
+const ATTRIBUTE_ARCHIVE: u8 = 0x20;
+const ATTRIBUTE_LONG_NAME: u8 = 0x0F;
+
+/// Set by Windows in the reserved byte of an entry when the base name or the extension are all in lowercase, the
+/// 8.3 name is always stored in uppercase
+const LOWERCASE_BASE: u8 = 1 << 3;
+const LOWERCASE_EXTENSION: u8 = 1 << 4;
+
+/// The characters other than letters and digits allowed in an 8.3 name
+const SHORT_NAME_SYMBOLS: &[u8] = b"!#$%&'()-@^_`{}~";
+
+/// Only the lower 28 bits of an entry of the FAT are the number of the next cluster
+const CLUSTER_MASK: u32 = 0x0FFF_FFFF;
+
+/// The entries of the FAT from this one up end the chain, and this one marks a bad cluster
+const CLUSTER_END: u32 = 0x0FFF_FFF8;
+const CLUSTER_BAD: u32 = 0x0FFF_FFF7;
+
+/// What's written in the FAT to end a chain, and the entry of a cluster that isn't used
+const CLUSTER_LAST: u32 = 0x0FFF_FFFF;
+const CLUSTER_FREE: u32 = 0;
+
+/// The first cluster of the data region, the first two entries of the FAT aren't clusters
+const FIRST_CLUSTER: u32 = 2;
+
+/// The year the dates of the entries count from, they can't be before it
+const FAT_EPOCH_YEAR: u16 = 1980;
+
+/// A FAT32 volume on a block device
+pub struct Fat32(Arc<Volume>);
+
+/// What the nodes of a [`Fat32`] need from it
+struct Volume {
+    device: Arc<dyn BlockDevice>,
+    /// The sector of the device the volume starts at, not 0 when it's in a partition
+    start: u64,
+    sectors_per_cluster: u64,
+    /// The sector (of the volume) of the first FAT, the other ones are copies of it right after
+    fat_start: u64,
+    fat_count: u64,
+    /// The size of a FAT in sectors
+    fat_size: u64,
+    /// The sector (of the volume) of the first cluster
+    data_start: u64,
+    root_cluster: u32,
+    /// How many clusters the data region has, the last one is `cluster_count + 1`
+    cluster_count: u32,
+    /// The sector (of the volume) of the FSInfo sector, if there's one
+    fsinfo: Option<u64>,
+    /// Where the search for a free cluster starts. The lock is held for the whole of every change to the volume,
+    /// so they never see each other half done
+    next_free: SleepMutex<u32>
+}
+
+/// Where an entry is in its directory: the sector (of the volume) and the offset in it
+#[derive(Debug, Copy, Clone)]
+struct EntryLocation {
+    sector: u64,
+    offset: usize
+}
+
+impl Fat32 {
+    /// Looks for a FAT32 volume on `device`, either on the whole of it or in the first FAT32 partition of its MBR
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Unsupported`] if there's no FAT32 volume on the device or if its sectors aren't of
+    /// [`SECTOR_SIZE`] bytes, otherwise the error of reading the device
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Self, KernelError> {
+        if device.sector_size() != SECTOR_SIZE {
+            return Err(KernelError::Unsupported);
+        }
+
+        let mut sector = [0; SECTOR_SIZE];
+        device.read(0, &mut sector)?;
+
+        if sector[510..] != BOOT_SIGNATURE {
+            return Err(KernelError::Unsupported);
+        }
+
+        if let Some(volume) = Volume::parse(device.clone(), 0, &sector) {
+            return Ok(Fat32(Arc::new(volume)));
+        }
+
+        // Not a volume, so the sector is an MBR. The start of the partition is at byte 8 of its entry
+        let partition = sector[PARTITION_TABLE..PARTITION_TABLE + 4 * PARTITION_ENTRY_SIZE]
+            .chunks_exact(PARTITION_ENTRY_SIZE)
+            .find(|entry| entry[4] == PARTITION_FAT32 || entry[4] == PARTITION_FAT32_LBA)
+            .map(|entry| u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64)
+            .ok_or(KernelError::Unsupported)?;
+
+        device.read(partition, &mut sector)?;
+
+        let volume = Volume::parse(device, partition, &sector).ok_or(KernelError::Unsupported)?;
+        return Ok(Fat32(Arc::new(volume)));
+    }
+}
+
+impl Volume {
+    /// Parses the BIOS parameter block of the boot sector of a volume starting at sector `start`, returning `None`
+    /// if it isn't a FAT32 one
+    fn parse(device: Arc<dyn BlockDevice>, start: u64, boot: &[u8; SECTOR_SIZE]) -> Option<Self> {
+        let word = |offset: usize| u16::from_le_bytes([boot[offset], boot[offset + 1]]) as u64;
+        let dword = |offset: usize| u32::from_le_bytes(boot[offset..offset + 4].try_into().unwrap()) as u64;
+
+        let bytes_per_sector = word(0x0B);
+        let sectors_per_cluster = boot[0x0D] as u64;
+        let reserved_sectors = word(0x0E);
+        let fat_count = boot[0x10] as u64;
+
+        // FAT32 has neither a fixed root directory nor a 16 bits FAT size, it's told apart from FAT12 and FAT16 by
+        // that instead of by the number of clusters, so the small volumes made with `mkfs.fat -F 32` work too
+        let fat32 = word(0x11) == 0 && word(0x16) == 0;
+
+        let valid = bytes_per_sector == SECTOR_SIZE as u64 && sectors_per_cluster.is_power_of_two() && fat_count > 0;
+
+        // The extended boot signature is what tells a boot sector from an MBR
+        if !fat32 || !valid || (boot[0x42] != 0x28 && boot[0x42] != 0x29) {
+            return None;
+        }
+
+        let total_sectors = if word(0x13) != 0 { word(0x13) } else { dword(0x20) };
+        let fat_size = dword(0x24);
+        let data_start = reserved_sectors + fat_count * fat_size;
+
+        let cluster_count = (total_sectors.checked_sub(data_start)? / sectors_per_cluster) as u32;
+
+        if cluster_count == 0 {
+            return None;
+        }
+
+        // The FSInfo sector is in the reserved sectors, 0 and 0xFFFF mean there's none
+        let fsinfo = Some(word(0x30)).filter(|&sector| sector != 0 && sector != 0xFFFF && sector < reserved_sectors);
+
+        Some(Volume {
+            device,
+            start,
+            sectors_per_cluster,
+            fat_start: reserved_sectors,
+            fat_count,
+            fat_size,
+            data_start,
+            root_cluster: dword(0x2C) as u32,
+            cluster_count,
+            fsinfo,
+            next_free: SleepMutex::new(FIRST_CLUSTER)
+        })
+    }
+
+    fn cluster_size(&self) -> u64 {
+        self.sectors_per_cluster * SECTOR_SIZE as u64
+    }
+
+    fn read_sector(&self, sector: u64, buffer: &mut [u8; SECTOR_SIZE]) -> Result<(), KernelError> {
+        self.device.read(self.start + sector, buffer)
+    }
+
+    fn write_sector(&self, sector: u64, buffer: &[u8; SECTOR_SIZE]) -> Result<(), KernelError> {
+        self.device.write(self.start + sector, buffer)
+    }
+
+    /// Returns the first sector (of the volume) of `cluster`
+    fn cluster_sector(&self, cluster: u32) -> Result<u64, KernelError> {
+        if !(FIRST_CLUSTER..self.cluster_count + FIRST_CLUSTER).contains(&cluster) {
+            return Err(KernelError::Io);
+        }
+
+        return Ok(self.data_start + (cluster - FIRST_CLUSTER) as u64 * self.sectors_per_cluster);
+    }
+
+    /// Returns the sector (of the volume) of the first FAT with the entry of `cluster`, and where the entry is in it
+    fn fat_entry(&self, cluster: u32) -> (u64, usize) {
+        let offset = cluster as u64 * 4;
+        return (self.fat_start + offset / SECTOR_SIZE as u64, (offset % SECTOR_SIZE as u64) as usize);
+    }
+
+    /// Returns the entry of `cluster` in the first FAT, without its reserved upper bits
+    fn fat_value(&self, cluster: u32) -> Result<u32, KernelError> {
+        let (sector_number, index) = self.fat_entry(cluster);
+        let mut sector = [0; SECTOR_SIZE];
+
+        self.read_sector(sector_number, &mut sector)?;
+
+        return Ok(u32::from_le_bytes(sector[index..index + 4].try_into().unwrap()) & CLUSTER_MASK);
+    }
+
+    /// Returns the cluster after `cluster` in its chain, or `None` if it's the last one
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Io`] if the chain goes to a bad or free cluster, otherwise the error of reading the
+    /// device
+    fn next_cluster(&self, cluster: u32) -> Result<Option<u32>, KernelError> {
+        match self.fat_value(cluster)? {
+            next if next >= CLUSTER_END => Ok(None),
+            CLUSTER_BAD => Err(KernelError::Io),
+            next if next < FIRST_CLUSTER => Err(KernelError::Io),
+            next => Ok(Some(next))
+        }
+    }
+
+    /// Returns the cluster number `index` (starting at 0) of the chain starting at `first`
+    fn cluster_at(&self, first: u32, index: u64) -> Result<u32, KernelError> {
+        let mut cluster = first;
+
+        for _ in 0..index {
+            cluster = self.next_cluster(cluster)?.ok_or(KernelError::Io)?;
+        }
+
+        return Ok(cluster);
+    }
+
+    /// Sets the entry of `cluster` to `next` in every copy of the FAT, keeping the reserved upper bits
+    fn set_next_cluster(&self, cluster: u32, next: u32) -> Result<(), KernelError> {
+        let (first_sector, index) = self.fat_entry(cluster);
+        let mut sector = [0; SECTOR_SIZE];
+
+        for fat in 0..self.fat_count {
+            let sector_number = first_sector + fat * self.fat_size;
+            self.read_sector(sector_number, &mut sector)?;
+
+            let old = u32::from_le_bytes(sector[index..index + 4].try_into().unwrap());
+            sector[index..index + 4].copy_from_slice(&(old & !CLUSTER_MASK | next).to_le_bytes());
+
+            self.write_sector(sector_number, &sector)?;
+        }
+
+        Ok(())
+    }
+
+    /// Finds a free cluster from `next_free` on, going around to the first one, makes it the end of a chain and
+    /// zeroes it. `next_free` is moved past it
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::NoSpace`] if every cluster is used, otherwise the error of the device
+    fn allocate_cluster(&self, next_free: &mut u32) -> Result<u32, KernelError> {
+        let entries_per_sector = (SECTOR_SIZE / 4) as u32;
+        let start = next_free.saturating_sub(FIRST_CLUSTER) % self.cluster_count;
+        let mut sector = [0; SECTOR_SIZE];
+
+        for step in 0..self.cluster_count {
+            let cluster = FIRST_CLUSTER + (start + step) % self.cluster_count;
+            let (sector_number, index) = self.fat_entry(cluster);
+
+            // A sector of the FAT is only read again once the search moves to the next one
+            if step == 0 || cluster % entries_per_sector == 0 || cluster == FIRST_CLUSTER {
+                self.read_sector(sector_number, &mut sector)?;
+            }
+
+            if u32::from_le_bytes(sector[index..index + 4].try_into().unwrap()) & CLUSTER_MASK == CLUSTER_FREE {
+                self.set_next_cluster(cluster, CLUSTER_LAST)?;
+                self.zero_cluster(cluster)?;
+
+                *next_free = cluster + 1;
+                return Ok(cluster);
+            }
+        }
+
+        return Err(KernelError::NoSpace);
+    }
+
+    fn zero_cluster(&self, cluster: u32) -> Result<(), KernelError> {
+        let first_sector = self.cluster_sector(cluster)?;
+
+        for sector_number in first_sector..first_sector + self.sectors_per_cluster {
+            self.write_sector(sector_number, &[0; SECTOR_SIZE])?;
+        }
+
+        Ok(())
+    }
+
+    /// Makes the chain starting at `first` long enough for at least `length` bytes, returning its first cluster
+    /// (a new one if `first` is 0, like for an empty file). The clusters added are zeroed
+    fn extend_chain(&self, next_free: &mut u32, first: u32, length: u64) -> Result<u32, KernelError> {
+        let needed = (length + self.cluster_size() - 1) / self.cluster_size();
+
+        if needed == 0 {
+            return Ok(first);
+        }
+
+        let (first, allocated) = match first {
+            0 => (self.allocate_cluster(next_free)?, true),
+            first => (first, false)
+        };
+
+        let mut last = first;
+        let mut count = 1;
+
+        while let Some(next) = self.next_cluster(last)? {
+            last = next;
+            count += 1;
+        }
+
+        while count < needed {
+            let cluster = match self.allocate_cluster(next_free) {
+                Ok(cluster) => cluster,
+                Err(error) => {
+                    // A new chain isn't in any entry yet, it would be lost. The clusters added to an existing one
+                    // are past the end of the file, the next truncate cuts them
+                    if allocated {
+                        self.free_chain(first)?;
+                    }
+
+                    return Err(error);
+                }
+            };
+
+            self.set_next_cluster(last, cluster)?;
+            last = cluster;
+            count += 1;
+        }
+
+        return Ok(first);
+    }
+
+    /// Frees every cluster of the chain starting at `first`
+    fn free_chain(&self, first: u32) -> Result<(), KernelError> {
+        let mut cluster = Some(first);
+
+        while let Some(current) = cluster {
+            cluster = self.next_cluster(current)?;
+            self.set_next_cluster(current, CLUSTER_FREE)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `length` bytes starting at `offset` of the chain starting at `first`, which must be long enough.
+    /// `fill` gives the bytes: it's called with the part of a sector to fill and how far from `offset` it starts
+    fn write_chain(
+        &self,
+        first: u32,
+        offset: u64,
+        length: u64,
+        mut fill: impl FnMut(&mut [u8], usize)
+    ) -> Result<(), KernelError> {
+        let cluster_size = self.cluster_size();
+        let end = offset + length;
+
+        let mut cluster = self.cluster_at(first, offset / cluster_size)?;
+        let mut sector = [0; SECTOR_SIZE];
+        let mut position = offset;
+
+        while position < end {
+            let sector_number = self.cluster_sector(cluster)? + position % cluster_size / SECTOR_SIZE as u64;
+            let in_sector = (position % SECTOR_SIZE as u64) as usize;
+            let count = (SECTOR_SIZE - in_sector).min((end - position) as usize);
+
+            // Only the sectors written in part have to be read first
+            if count < SECTOR_SIZE {
+                self.read_sector(sector_number, &mut sector)?;
+            }
+
+            fill(&mut sector[in_sector..in_sector + count], (position - offset) as usize);
+            self.write_sector(sector_number, &sector)?;
+
+            position += count as u64;
+
+            if position % cluster_size == 0 && position < end {
+                cluster = self.next_cluster(cluster)?.ok_or(KernelError::Io)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Calls `f` with every entry of the directory starting at `cluster` (except the entries of the long names, the
+    /// volume label and the deleted ones) until it returns something. The entries that have a long name are given
+    /// with it
+    fn find_entry<T>(&self, cluster: u32, f: impl FnMut(&Entry) -> Option<T>) -> Result<Option<T>, KernelError> {
+        self.find_entry_within(cluster, u64::MAX, f)
+    }
+
+    /// Like [`Volume::find_entry`], but only goes through the first `clusters` clusters (at least one) of the
+    /// directory, so a chain known to be broken (see [`fsck`]) is never followed past where it's still right
+    fn find_entry_within<T>(
+        &self,
+        cluster: u32,
+        clusters: u64,
+        mut f: impl FnMut(&Entry) -> Option<T>
+    ) -> Result<Option<T>, KernelError> {
+        let mut cluster = Some(cluster);
+        let mut sector = [0; SECTOR_SIZE];
+        let mut remaining = clusters;
+        let mut long_name = LongName::new();
+
+        while let Some(current) = cluster {
+            let first_sector = self.cluster_sector(current)?;
+
+            for sector_number in first_sector..first_sector + self.sectors_per_cluster {
+                self.read_sector(sector_number, &mut sector)?;
+
+                for (index, raw) in sector.chunks_exact(ENTRY_SIZE).enumerate() {
+                    let location = EntryLocation { sector: sector_number, offset: index * ENTRY_SIZE };
+
+                    // The entries of a long name come right before the 8.3 entry they belong to
+                    match raw[0] {
+                        ENTRY_END => return Ok(None),
+                        ENTRY_DELETED => {
+                            long_name.clear();
+                            continue;
+                        },
+                        _ if raw[11] & ATTRIBUTE_LONG_NAME == ATTRIBUTE_LONG_NAME => {
+                            long_name.push(raw, location);
+                            continue;
+                        },
+                        _ if raw[11] & ATTRIBUTE_VOLUME_ID != 0 => {
+                            long_name.clear();
+                            continue;
+                        },
+                        _ => {}
+                    }
+
+                    let mut entry = Entry::parse(raw, location);
+
+                    if let Some((name, locations)) = long_name.take(&raw[0..11]) {
+                        entry.alias = Some(core::mem::replace(&mut entry.name, name));
+                        entry.long_entries = locations;
+                    }
+
+                    if let Some(result) = f(&entry) {
+                        return Ok(Some(result));
+                    }
+                }
+            }
+
+            remaining -= 1;
+            cluster = if remaining > 0 { self.next_cluster(current)? } else { None };
+        }
+
+        return Ok(None);
+    }
+
+    /// Returns `count` free entries in a row of the directory starting at `cluster` (like for the entries of a long
+    /// name and their 8.3 entry), adding clusters to it if there isn't room enough
+    fn free_entries(&self, next_free: &mut u32, cluster: u32, count: usize) -> Result<Vec<EntryLocation>, KernelError> {
+        let mut free = Vec::new();
+        let mut current = cluster;
+        let mut sector = [0; SECTOR_SIZE];
+
+        // Every entry after the one ending the directory is free
+        let mut ended = false;
+
+        loop {
+            let first_sector = self.cluster_sector(current)?;
+
+            for sector_number in first_sector..first_sector + self.sectors_per_cluster {
+                self.read_sector(sector_number, &mut sector)?;
+
+                for (index, raw) in sector.chunks_exact(ENTRY_SIZE).enumerate() {
+                    ended |= raw[0] == ENTRY_END;
+
+                    if !ended && raw[0] != ENTRY_DELETED {
+                        free.clear();
+                        continue;
+                    }
+
+                    free.push(EntryLocation { sector: sector_number, offset: index * ENTRY_SIZE });
+
+                    if free.len() == count {
+                        return Ok(free);
+                    }
+                }
+            }
+
+            current = match self.next_cluster(current)? {
+                Some(next) => next,
+                None => {
+                    // The new cluster is zeroed, so its entries are all free
+                    let added = self.allocate_cluster(next_free)?;
+                    self.set_next_cluster(current, added)?;
+
+                    added
+                }
+            };
+        }
+    }
+
+    /// Returns the 8.3 name (its 11 bytes) of a new entry of the directory starting at `cluster` whose name doesn't
+    /// fit in one, like Windows makes it: the 8.3 name closest to the long one if nothing was lost on the way and no
+    /// other entry has it, otherwise that name with the first numeric tail (like `~1`) no other entry has
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::AlreadyExists`] if every numeric tail is taken
+    fn alias(&self, cluster: u32, name: &str) -> Result<[u8; 11], KernelError> {
+        let (basis, lossy) = lfn::basis(name);
+
+        let is_taken = |short: &[u8; 11]| {
+            let shown = short_name_text(short, 0);
+            self.find_entry(cluster, |entry| entry.short_name().eq_ignore_ascii_case(&shown).then_some(()))
+        };
+
+        if !lossy && is_taken(&basis)?.is_none() {
+            return Ok(basis);
+        }
+
+        for number in 1..1_000_000 {
+            let short = lfn::with_tail(&basis, number);
+
+            if is_taken(&short)?.is_none() {
+                return Ok(short);
+            }
+        }
+
+        return Err(KernelError::AlreadyExists);
+    }
+
+    fn read_entry(&self, location: EntryLocation) -> Result<Entry, KernelError> {
+        let mut sector = [0; SECTOR_SIZE];
+        self.read_sector(location.sector, &mut sector)?;
+
+        return Ok(Entry::parse(&sector[location.offset..location.offset + ENTRY_SIZE], location));
+    }
+
+    /// Changes the entry at `location` with `change`, which is given its 32 bytes
+    fn change_entry(&self, location: EntryLocation, change: impl FnOnce(&mut [u8])) -> Result<(), KernelError> {
+        let mut sector = [0; SECTOR_SIZE];
+        self.read_sector(location.sector, &mut sector)?;
+
+        change(&mut sector[location.offset..location.offset + ENTRY_SIZE]);
+        return self.write_sector(location.sector, &sector);
+    }
+
+    /// Sets the first cluster and the size of the file whose entry is at `location`, it's written now
+    fn set_file(&self, location: EntryLocation, cluster: u32, size: u32) -> Result<(), KernelError> {
+        self.change_entry(location, |raw| {
+            set_entry_cluster(raw, cluster);
+            raw[28..32].copy_from_slice(&size.to_le_bytes());
+
+            let (date, time) = fat_date_time(crate::time::unix_time());
+            raw[18..20].copy_from_slice(&date.to_le_bytes());
+            raw[22..24].copy_from_slice(&time.to_le_bytes());
+            raw[24..26].copy_from_slice(&date.to_le_bytes());
+        })
+    }
+
+    /// Writes `next_free` in the FSInfo sector, whose free cluster count is set to unknown since the kernel changes
+    /// the FAT without keeping count
+    fn update_fsinfo(&self, next_free: u32) -> Result<(), KernelError> {
+        let fsinfo = match self.fsinfo {
+            Some(fsinfo) => fsinfo,
+            None => return Ok(())
+        };
+
+        let mut sector = [0; SECTOR_SIZE];
+        self.read_sector(fsinfo, &mut sector)?;
+
+        let signature = |offset: usize| u32::from_le_bytes(sector[offset..offset + 4].try_into().unwrap());
+
+        if signature(0) != FSINFO_LEAD_SIGNATURE || signature(484) != FSINFO_STRUCT_SIGNATURE {
+            return Ok(());
+        }
+
+        sector[FSINFO_FREE_COUNT..FSINFO_FREE_COUNT + 4].copy_from_slice(&FSINFO_UNKNOWN.to_le_bytes());
+        sector[FSINFO_NEXT_FREE..FSINFO_NEXT_FREE + 4].copy_from_slice(&next_free.to_le_bytes());
+
+        return self.write_sector(fsinfo, &sector);
+    }
+
+    /// Updates the FSInfo sector and flushes the device, so everything written so far is on it
+    fn sync(&self) -> Result<(), KernelError> {
+        let next_free = self.next_free.lock();
+
+        self.update_fsinfo(*next_free)?;
+        return self.device.flush();
+    }
+
+    /// Locks the volume for a change, see [`Change`]
+    fn change(&self) -> Change<'_> {
+        Change { next_free: self.next_free.lock(), device: self.device.as_ref() }
+    }
+}
+
+/// The lock of a volume held for the whole of a change, it gives the hint of the next free cluster. Once it's
+/// dropped the device is told the change is over (see [`BlockDevice::end_change`]). A change the device failed to
+/// write is written with the next one, or by the next sync which tells the error
+struct Change<'a> {
+    next_free: SleepMutexGuard<'a, u32>,
+    device: &'a dyn BlockDevice
+}
+
+impl Deref for Change<'_> {
+    type Target = u32;
+
+    fn deref(&self) -> &u32 {
+        &self.next_free
+    }
+}
+
+impl DerefMut for Change<'_> {
+    fn deref_mut(&mut self) -> &mut u32 {
+        &mut self.next_free
+    }
+}
+
+impl Drop for Change<'_> {
+    fn drop(&mut self) {
+        let _ = self.device.end_change();
+    }
+}
+
+/// An entry of a directory, with its name as it's shown: its long name if it has one, otherwise its 8.3 name
+struct Entry {
+    name: String,
+    /// The 8.3 name of an entry that has a long name, and where the entries of its long name are
+    alias: Option<String>,
+    long_entries: Vec<EntryLocation>,
+    directory: bool,
+    read_only: bool,
+    cluster: u32,
+    size: u32,
+    /// When the entry was created, last read (only the day is kept) and last written, in seconds since the Unix
+    /// epoch, 0 if the entry doesn't have them
+    created: u64,
+    accessed: u64,
+    modified: u64,
+    location: EntryLocation
+}
+
+impl Entry {
+    /// Parses the 8.3 entry `raw`, the long name is added by [`Volume::find_entry`]
+    fn parse(raw: &[u8], location: EntryLocation) -> Self {
+        let name = short_name_text(&raw[0..11], raw[12]);
+
+        // The cluster is split in two halves, the high one at 20 and the low one at 26
+        let high = u16::from_le_bytes([raw[20], raw[21]]) as u32;
+        let cluster = high << 16 | u16::from_le_bytes([raw[26], raw[27]]) as u32;
+        let word = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+
+        Entry {
+            name,
+            alias: None,
+            long_entries: Vec::new(),
+            directory: raw[11] & ATTRIBUTE_DIRECTORY != 0,
+            read_only: raw[11] & ATTRIBUTE_READ_ONLY != 0,
+            cluster,
+            size: u32::from_le_bytes(raw[28..32].try_into().unwrap()),
+            created: entry_time(word(16), word(14)),
+            accessed: entry_time(word(18), 0),
+            modified: entry_time(word(24), word(22)),
+            location
+        }
+    }
+
+    /// Returns whatever the entry is called `name`, by its long name or its 8.3 one. The case doesn't matter
+    fn is_called(&self, name: &str) -> bool {
+        let alias = self.alias.as_deref();
+        self.name.eq_ignore_ascii_case(name) || alias.map_or(false, |alias| alias.eq_ignore_ascii_case(name))
+    }
+
+    /// Returns the 8.3 name of the entry as it's shown
+    fn short_name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+/// Turns a date and a time of an entry into seconds since the Unix epoch, 0 if there's no date. The date has the
+/// year (from 1980) in its bits 15-9, the month in 8-5 and the day in 4-0, the time has the hours in its bits 15-11,
+/// the minutes in 10-5 and the seconds divided by 2 in 4-0
+fn entry_time(date: u16, time: u16) -> u64 {
+    if date == 0 {
+        return 0;
+    }
+
+    let date_time = DateTime {
+        year: FAT_EPOCH_YEAR + (date >> 9),
+        month: (date >> 5 & 0xF) as u8,
+        day: (date & 0x1F) as u8,
+        hour: (time >> 11) as u8,
+        minute: (time >> 5 & 0x3F) as u8,
+        second: (time & 0x1F) as u8 * 2
+    };
+
+    return date_time.to_unix();
+}
+
+/// Turns seconds since the Unix epoch into the date and the time of an entry, see [`entry_time`]. The times before
+/// 1980 are the start of 1980
+fn fat_date_time(seconds: u64) -> (u16, u16) {
+    let date_time = DateTime::from_unix(seconds);
+
+    if date_time.year < FAT_EPOCH_YEAR {
+        return (1 << 5 | 1, 0);
+    }
+
+    let date = (date_time.year - FAT_EPOCH_YEAR).min(127) << 9 | (date_time.month as u16) << 5 | date_time.day as u16;
+    let time = (date_time.hour as u16) << 11 | (date_time.minute as u16) << 5 | (date_time.second / 2) as u16;
+
+    return (date, time);
+}
+
+fn set_entry_cluster(raw: &mut [u8], cluster: u32) {
+    raw[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    raw[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+}
+
+/// Fills `raw` with a new entry called `name` (already in the 11 bytes of an 8.3 name), with the lowercase flags
+/// of [`short_name`]
+fn new_entry(raw: &mut [u8], name: &[u8; 11], lowercase: u8, directory: bool, cluster: u32) {
+    raw.fill(0);
+    raw[0..11].copy_from_slice(name);
+    raw[11] = if directory { ATTRIBUTE_DIRECTORY } else { ATTRIBUTE_ARCHIVE };
+    raw[12] = lowercase;
+
+    // The entry is created, read and written now
+    let (date, time) = fat_date_time(crate::time::unix_time());
+
+    for (offset, value) in [(14, time), (16, date), (18, date), (22, time), (24, date)] {
+        raw[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    set_entry_cluster(raw, cluster);
+}
+
+/// Turns the 11 bytes of an 8.3 name into text, with the lowercase flags of [`short_name`]
+fn short_name_text(short: &[u8], lowercase: u8) -> String {
+    let mut base = short[0..8].to_vec();
+
+    if base[0] == ENTRY_KANJI_E5 {
+        base[0] = ENTRY_DELETED;
+    }
+
+    let mut name = short_name_part(&base, lowercase & LOWERCASE_BASE != 0);
+    let extension = short_name_part(&short[8..11], lowercase & LOWERCASE_EXTENSION != 0);
+
+    if !extension.is_empty() {
+        name.push('.');
+        name.push_str(&extension);
+    }
+
+    return name;
+}
+
+/// Turns the base name or the extension of an 8.3 name into text, without the spaces it's padded with. The bytes
+/// that aren't ASCII are in a code page that isn't known, they're replaced
+fn short_name_part(bytes: &[u8], lowercase: bool) -> String {
+    bytes
+        .iter()
+        .take_while(|&&byte| byte != b' ')
+        .map(|&byte| match byte {
+            byte if byte.is_ascii() && lowercase => byte.to_ascii_lowercase() as char,
+            byte if byte.is_ascii() => byte as char,
+            _ => '?'
+        })
+        .collect()
+}
+
+/// Returns whatever `byte` can be in an 8.3 name
+fn is_short_name_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || SHORT_NAME_SYMBOLS.contains(&byte)
+}
+
+/// Turns `name` into the 11 bytes of an 8.3 name and the flags telling which parts of it are in lowercase.
+/// Returns `None` if it doesn't fit in an 8.3 name, like when the base name or the extension mix the cases
+fn short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, extension) = name.rsplit_once('.').unwrap_or((name, ""));
+
+    let valid = |part: &str, length: usize| part.len() <= length && part.bytes().all(is_short_name_byte);
+
+    if base.is_empty() || !valid(base, 8) || !valid(extension, 3) {
+        return None;
+    }
+
+    let mut short = [b' '; 11];
+    let mut lowercase = 0;
+
+    for (part, start, flag) in [(base, 0, LOWERCASE_BASE), (extension, 8, LOWERCASE_EXTENSION)] {
+        let has_lowercase = part.bytes().any(|byte| byte.is_ascii_lowercase());
+
+        if has_lowercase && part.bytes().any(|byte| byte.is_ascii_uppercase()) {
+            return None;
+        }
+
+        if has_lowercase {
+            lowercase |= flag;
+        }
+
+        short[start..start + part.len()].copy_from_slice(part.to_ascii_uppercase().as_bytes());
+    }
+
+    return Some((short, lowercase));
+}
+
+/// A file or a directory of a [`Fat32`] volume
+struct FatNode {
+    fs: Arc<Volume>,
+    /// Where the entry of the node is in its directory, `None` for the root directory which has none
+    location: Option<EntryLocation>,
+    /// The first cluster of the node as it was looked up. The one of a directory never changes, but the one of a
+    /// file does when it's written, so it's read from the entry every time instead
+    cluster: u32,
+    directory: bool
+}
+
+impl FatNode {
+    /// Returns the first cluster (0 if there's none) and the size of the file as they are in its entry
+    fn file(&self) -> Result<(u32, u32), KernelError> {
+        match self.location {
+            Some(location) => self.fs.read_entry(location).map(|entry| (entry.cluster, entry.size)),
+            None => Ok((self.cluster, 0))
+        }
+    }
+
+    /// Returns the node of an entry of this directory
+    fn child(&self, entry: &Entry) -> FatNode {
+        // The `..` of a directory right under the root points at cluster 0 instead of at the root
+        let (cluster, location) = match entry.cluster {
+            0 if entry.directory => (self.fs.root_cluster, None),
+            cluster => (cluster, Some(entry.location))
+        };
+
+        FatNode { fs: self.fs.clone(), location, cluster, directory: entry.directory }
+    }
+
+    /// Makes the file `size` bytes long, the lock of the volume must be held
+    fn resize(&self, next_free: &mut u32, size: u64) -> Result<(), KernelError> {
+        let location = self.location.ok_or(KernelError::IsDirectory)?;
+        let (cluster, old_size) = self.file()?;
+        let cluster_size = self.fs.cluster_size();
+
+        if size > old_size as u64 {
+            // The clusters added are zeroed, but not the end of the last one the file already had
+            let cluster = self.fs.extend_chain(next_free, cluster, size)?;
+            self.fs.write_chain(cluster, old_size as u64, size - old_size as u64, |bytes, _| bytes.fill(0))?;
+
+            return self.fs.set_file(location, cluster, size as u32);
+        }
+
+        let kept = (size + cluster_size - 1) / cluster_size;
+
+        // The entry is changed first, so a failure leaves clusters nobody uses instead of a file going past its chain
+        if kept == 0 {
+            self.fs.set_file(location, 0, 0)?;
+            return if cluster != 0 { self.fs.free_chain(cluster) } else { Ok(()) };
+        }
+
+        self.fs.set_file(location, cluster, size as u32)?;
+
+        let last = self.fs.cluster_at(cluster, kept - 1)?;
+
+        if let Some(rest) = self.fs.next_cluster(last)? {
+            self.fs.set_next_cluster(last, CLUSTER_LAST)?;
+            self.fs.free_chain(rest)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Node for FatNode {
+    fn kind(&self) -> NodeKind {
+        if self.directory { NodeKind::Directory } else { NodeKind::File }
+    }
+
+    fn size(&self) -> u64 {
+        if self.directory {
+            return 0;
+        }
+
+        return self.file().map(|(_, size)| size as u64).unwrap_or(0);
+    }
+
+    /// The root directory has no entry, so it has no times
+    fn metadata(&self) -> Result<Metadata, KernelError> {
+        let location = match self.location {
+            Some(location) => location,
+            None => return Ok(Metadata::new(NodeKind::Directory, 0))
+        };
+
+        let entry = self.fs.read_entry(location)?;
+        let mut metadata = Metadata::new(self.kind(), if self.directory { 0 } else { entry.size as u64 });
+
+        // The entries only say whatever they can be written
+        if entry.read_only {
+            metadata.mode &= !0o222;
+        }
+
+        return Ok(Metadata { created: entry.created, accessed: entry.accessed, modified: entry.modified, ..metadata });
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Node>, KernelError> {
+        if !self.directory {
+            return Err(KernelError::NotDirectory);
+        }
+
+        // The names are compared without case, like FAT does, and a node with a long name is found by both names
+        let node = self.fs
+            .find_entry(self.cluster, |entry| entry.is_called(name).then(|| self.child(entry)))?
+            .ok_or(KernelError::NotFound)?;
+
+        return Ok(Arc::new(node));
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, KernelError> {
+        if self.directory {
+            return Err(KernelError::IsDirectory);
+        }
+
+        let (first, size) = self.file()?;
+        let end = (offset + buffer.len() as u64).min(size as u64);
+
+        if offset >= end {
+            return Ok(0);
+        }
+
+        let cluster_size = self.fs.cluster_size();
+        let mut cluster = self.fs.cluster_at(first, offset / cluster_size)?;
+
+        let mut sector = [0; SECTOR_SIZE];
+        let mut position = offset;
+
+        while position < end {
+            let sector_number = self.fs.cluster_sector(cluster)? + position % cluster_size / SECTOR_SIZE as u64;
+
+            self.fs.read_sector(sector_number, &mut sector)?;
+
+            let in_sector = (position % SECTOR_SIZE as u64) as usize;
+            let count = (SECTOR_SIZE - in_sector).min((end - position) as usize);
+            let copied = (position - offset) as usize;
+
+            buffer[copied..copied + count].copy_from_slice(&sector[in_sector..in_sector + count]);
+            position += count as u64;
+
+            if position % cluster_size == 0 && position < end {
+                cluster = self.fs.next_cluster(cluster)?.ok_or(KernelError::Io)?;
+            }
+        }
+
+        return Ok((end - offset) as usize);
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize, KernelError> {
+        if self.directory {
+            return Err(KernelError::IsDirectory);
+        }
+
+        let location = self.location.ok_or(KernelError::IsDirectory)?;
+
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        // The size of a file is 32 bits
+        let end = offset
+            .checked_add(buffer.len() as u64)
+            .filter(|&end| end <= u32::MAX as u64)
+            .ok_or(KernelError::InvalidArgument)?;
+
+        let mut next_free = self.fs.change();
+        let (cluster, size) = self.file()?;
+        let cluster = self.fs.extend_chain(&mut next_free, cluster, end)?;
+
+        // Writing past the end leaves zeros between the end and the offset, the clusters added are zeroed already
+        // but not the end of the last one the file had
+        if offset > size as u64 {
+            self.fs.write_chain(cluster, size as u64, offset - size as u64, |bytes, _| bytes.fill(0))?;
+        }
+
+        self.fs.write_chain(cluster, offset, buffer.len() as u64, |bytes, start| {
+            bytes.copy_from_slice(&buffer[start..start + bytes.len()]);
+        })?;
+
+        self.fs.set_file(location, cluster, end.max(size as u64) as u32)?;
+        return Ok(buffer.len());
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, KernelError> {
+        if !self.directory {
+            return Err(KernelError::NotDirectory);
+        }
+
+        let mut entries = Vec::new();
+
+        self.fs.find_entry::<()>(self.cluster, |entry| {
+            if entry.name != "." && entry.name != ".." {
+                let kind = if entry.directory { NodeKind::Directory } else { NodeKind::File };
+                entries.push(DirEntry { name: entry.name.clone(), kind, size: entry.size as u64 });
+            }
+
+            None
+        })?;
+
+        return Ok(entries);
+    }
+
+    fn create(&self, name: &str, kind: NodeKind) -> Result<Arc<dyn Node>, KernelError> {
+        if !self.directory {
+            return Err(KernelError::NotDirectory);
+        }
+
+        let directory = match kind {
+            NodeKind::File => false,
+            NodeKind::Directory => true,
+            NodeKind::Device | NodeKind::Symlink | NodeKind::Pipe => return Err(KernelError::Unsupported)
+        };
+
+        let mut next_free = self.fs.change();
+
+        if self.fs.find_entry(self.cluster, |entry| entry.is_called(name).then_some(()))?.is_some() {
+            return Err(KernelError::AlreadyExists);
+        }
+
+        // The names that don't fit in an 8.3 name get a long name, with an 8.3 alias for the systems that only know
+        // about those
+        let (short, lowercase, long_name) = match short_name(name) {
+            Some((short, lowercase)) => (short, lowercase, None),
+            None => {
+                let long_name = lfn::encode(name).ok_or(KernelError::InvalidArgument)?;
+                (self.fs.alias(self.cluster, name)?, 0, Some(long_name))
+            }
+        };
+
+        let long_entries = long_name.as_ref().map_or(0, |long_name| lfn::entry_count(long_name.len()));
+        let locations = self.fs.free_entries(&mut next_free, self.cluster, long_entries + 1)?;
+        let location = locations[long_entries];
+
+        // A directory starts with its `.` and `..` entries, the `..` of one in the root points at cluster 0
+        let cluster = if directory {
+            let cluster = self.fs.allocate_cluster(&mut next_free)?;
+            let parent = if self.location.is_none() { 0 } else { self.cluster };
+
+            let mut sector = [0; SECTOR_SIZE];
+            new_entry(&mut sector[..ENTRY_SIZE], DOT_NAME, 0, true, cluster);
+            new_entry(&mut sector[ENTRY_SIZE..2 * ENTRY_SIZE], DOT_DOT_NAME, 0, true, parent);
+
+            self.fs.write_sector(self.fs.cluster_sector(cluster)?, &sector)?;
+            cluster
+        } else {
+            0
+        };
+
+        // The entries of the long name go from its end to its start
+        if let Some(long_name) = &long_name {
+            let checksum = lfn::checksum(&short);
+
+            for (index, &long_location) in locations[..long_entries].iter().enumerate() {
+                let sequence = long_entries - index;
+                self.fs.change_entry(long_location, |raw| lfn::fill_entry(raw, long_name, sequence, checksum))?;
+            }
+        }
+
+        self.fs.change_entry(location, |raw| new_entry(raw, &short, lowercase, directory, cluster))?;
+
+        return Ok(Arc::new(FatNode { fs: self.fs.clone(), location: Some(location), cluster, directory }));
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), KernelError> {
+        if !self.directory {
+            return Err(KernelError::NotDirectory);
+        }
+
+        if name == "." || name == ".." {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let _change = self.fs.change();
+
+        let (location, long_entries, cluster, directory) = self.fs
+            .find_entry(self.cluster, |entry| {
+                let found = || (entry.location, entry.long_entries.clone(), entry.cluster, entry.directory);
+                entry.is_called(name).then(found)
+            })?
+            .ok_or(KernelError::NotFound)?;
+
+        if directory {
+            let other = self.fs.find_entry(cluster, |entry| (entry.name != "." && entry.name != "..").then_some(()))?;
+
+            if other.is_some() {
+                return Err(KernelError::NotEmpty);
+            }
+        }
+
+        // Like for a truncate, the entry goes first. The entries of its long name go with it
+        self.fs.change_entry(location, |raw| raw[0] = ENTRY_DELETED)?;
+
+        for long_location in long_entries {
+            self.fs.change_entry(long_location, |raw| raw[0] = ENTRY_DELETED)?;
+        }
+
+        if cluster != 0 {
+            self.fs.free_chain(cluster)?;
+        }
+
+        Ok(())
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), KernelError> {
+        if self.directory {
+            return Err(KernelError::IsDirectory);
+        }
+
+        if size > u32::MAX as u64 {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let mut next_free = self.fs.change();
+        return self.resize(&mut next_free, size);
+    }
+
+    /// The entries only keep whatever the node can be written, so the mode makes it read-only when none of its
+    /// classes can write it and the other permissions are left out
+    fn set_mode(&self, mode: u16) -> Result<(), KernelError> {
+        let location = self.location.ok_or(KernelError::Unsupported)?;
+        let _change = self.fs.change();
+
+        self.fs.change_entry(location, |raw| match mode & 0o222 {
+            0 => raw[11] |= ATTRIBUTE_READ_ONLY,
+            _ => raw[11] &= !ATTRIBUTE_READ_ONLY
+        })
+    }
+
+    fn sync(&self) -> Result<(), KernelError> {
+        self.fs.sync()
+    }
+}
+
+impl FileSystem for Fat32 {
+    fn name(&self) -> &str {
+        "fat32"
+    }
+
+    fn root(&self) -> Arc<dyn Node> {
+        Arc::new(FatNode { fs: self.0.clone(), location: None, cluster: self.0.root_cluster, directory: true })
+    }
+
+    fn device(&self) -> Option<&str> {
+        Some(self.0.device.name())
+    }
+
+    fn sync(&self) -> Result<(), KernelError> {
+        self.0.sync()
+    }
+}
@@ -0,0 +1,308 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::block::{BlockDevice, SECTOR_SIZE};
+use crate::utils::error::KernelError;
+use super::{DirEntry, FileSystem, Metadata, Node, NodeKind};
+
+/// Where the fields of a ustar header are, every entry of the archive starts with one in a sector of its own
+const NAME: core::ops::Range<usize> = 0..100;
+const MODE: core::ops::Range<usize> = 100..108;
+const UID: core::ops::Range<usize> = 108..116;
+const GID: core::ops::Range<usize> = 116..124;
+const SIZE: core::ops::Range<usize> = 124..136;
+const MODIFIED: core::ops::Range<usize> = 136..148;
+const CHECKSUM: core::ops::Range<usize> = 148..156;
+const TYPE: usize = 156;
+const LINK_NAME: core::ops::Range<usize> = 157..257;
+const MAGIC: core::ops::Range<usize> = 257..262;
+const PREFIX: core::ops::Range<usize> = 345..500;
+
+/// The types of the entries, the others (like the devices) are left out
+const TYPE_FILE: u8 = b'0';
+const TYPE_OLD_FILE: u8 = 0;
+const TYPE_HARD_LINK: u8 = b'1';
+const TYPE_SYMLINK: u8 = b'2';
+const TYPE_DIRECTORY: u8 = b'5';
+
+/// The archive loaded by the bootloader, as a filesystem that's only read. The tree of the archive is made once
+/// when it's mounted, the content of the files stays on the device and is read from it
+pub struct Initramfs {
+    root: Arc<TarNode>
+}
+
+struct TarNode {
+    device: Arc<dyn BlockDevice>,
+    content: Content,
+    /// The permissions and the time of the last write (in seconds since the Unix epoch) given by the header,
+    /// [`None`] for the directories the archive has no entry for
+    mode: Option<u16>,
+    modified: u64,
+    uid: u32,
+    gid: u32
+}
+
+enum Content {
+    /// The `size` bytes of a file, starting at `sector`
+    File { sector: u64, size: u64 },
+    Directory(BTreeMap<String, Arc<TarNode>>),
+    /// The path a symbolic link points to
+    Symlink(String)
+}
+
+/// Parses a number of a header, written in octal and ended by a space or a NUL
+fn parse_octal(field: &[u8]) -> Option<u64> {
+    let digits = field.split(|&byte| byte == 0 || byte == b' ').find(|digits| !digits.is_empty()).unwrap_or(&[]);
+
+    return digits.iter().try_fold(0u64, |value, &digit| match digit {
+        b'0'..=b'7' => value.checked_mul(8)?.checked_add((digit - b'0') as u64),
+        _ => None
+    });
+}
+
+/// Returns the text of a field of a header, which ends at the first NUL if it doesn't fill the field
+fn parse_text(field: &[u8]) -> Result<&str, KernelError> {
+    let end = field.iter().position(|&byte| byte == 0).unwrap_or(field.len());
+    return core::str::from_utf8(&field[..end]).map_err(|_| KernelError::Io);
+}
+
+/// Returns whatever the checksum of the header is right, it's the sum of its bytes with the ones of the checksum
+/// counted as spaces
+fn is_valid(header: &[u8; SECTOR_SIZE]) -> bool {
+    let sum = header
+        .iter()
+        .enumerate()
+        .map(|(index, &byte)| if CHECKSUM.contains(&index) { b' ' } else { byte } as u64)
+        .sum::<u64>();
+
+    return parse_octal(&header[CHECKSUM]) == Some(sum);
+}
+
+impl TarNode {
+    fn directory(device: &Arc<dyn BlockDevice>) -> Self {
+        TarNode {
+            device: device.clone(),
+            content: Content::Directory(BTreeMap::new()),
+            mode: None,
+            modified: 0,
+            uid: 0,
+            gid: 0
+        }
+    }
+
+    /// Returns the node at the components of `path` under this directory, if there's one
+    fn find<'a>(&self, mut path: impl Iterator<Item = &'a str>) -> Option<&TarNode> {
+        match path.next() {
+            None => Some(self),
+            Some(name) => match &self.content {
+                Content::Directory(entries) => entries.get(name)?.find(path),
+                _ => None
+            }
+        }
+    }
+
+    /// Adds `node` at the components of `path` under this directory, creating the directories on the way when the
+    /// archive has no entries for them. A directory that's already there is kept with its entries, only taking the
+    /// permissions, the owner and the time of `node`
+    fn insert(&mut self, path: &[&str], node: TarNode) -> Result<(), KernelError> {
+        let entries = match &mut self.content {
+            Content::Directory(entries) => entries,
+            _ => return Err(KernelError::Io)
+        };
+
+        // The tree is only shared once it's complete, so every node of it has a single owner while it's made
+        let next = match path {
+            [] => return Ok(()),
+            [name] => {
+                if let (Content::Directory(_), Some(existing)) = (&node.content, entries.get_mut(*name)) {
+                    let existing = Arc::get_mut(existing).ok_or(KernelError::Io)?;
+
+                    if let Content::Directory(_) = existing.content {
+                        existing.mode = node.mode;
+                        existing.modified = node.modified;
+                        existing.uid = node.uid;
+                        existing.gid = node.gid;
+                    }
+
+                    return Ok(());
+                }
+
+                entries.insert(String::from(*name), Arc::new(node));
+                return Ok(());
+            },
+            [name, ..] => entries
+                .entry(String::from(*name))
+                .or_insert_with(|| Arc::new(TarNode::directory(&node.device)))
+        };
+
+        return Arc::get_mut(next).ok_or(KernelError::Io)?.insert(&path[1..], node);
+    }
+}
+
+impl Initramfs {
+    /// Reads the tree of the ustar archive at the start of `device`
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Unsupported`] if the device doesn't start with a ustar archive, [`KernelError::Io`] if
+    /// the archive is damaged, otherwise the error of reading the device
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Self, KernelError> {
+        if device.sector_size() != SECTOR_SIZE {
+            return Err(KernelError::Unsupported);
+        }
+
+        let mut root = TarNode::directory(&device);
+        let mut header = [0; SECTOR_SIZE];
+        let mut sector = 0;
+
+        // The archive ends with a sector of zeros, or with the device when it was cut short
+        while sector < device.sector_count() {
+            device.read(sector, &mut header)?;
+
+            if header.iter().all(|&byte| byte == 0) {
+                break;
+            }
+
+            if !is_valid(&header) || &header[MAGIC] != b"ustar" {
+                return Err(if sector == 0 { KernelError::Unsupported } else { KernelError::Io });
+            }
+
+            let size = parse_octal(&header[SIZE]).ok_or(KernelError::Io)?;
+            let (prefix, name) = (parse_text(&header[PREFIX])?, parse_text(&header[NAME])?);
+
+            // The prefix holds the start of the names that don't fit in the field of the name
+            let path: Vec<&str> = prefix
+                .split('/')
+                .chain(name.split('/'))
+                .filter(|component| !component.is_empty() && *component != ".")
+                .collect();
+
+            let content = match header[TYPE] {
+                TYPE_FILE | TYPE_OLD_FILE => Some(Content::File { sector: sector + 1, size }),
+                TYPE_DIRECTORY => Some(Content::Directory(BTreeMap::new())),
+                TYPE_SYMLINK => Some(Content::Symlink(String::from(parse_text(&header[LINK_NAME])?))),
+                // A hard link shares the content of a file earlier in the archive
+                TYPE_HARD_LINK => {
+                    let target = parse_text(&header[LINK_NAME])?;
+                    let components = target.split('/').filter(|component| !component.is_empty() && *component != ".");
+
+                    match root.find(components).map(|node| &node.content) {
+                        Some(&Content::File { sector, size }) => Some(Content::File { sector, size }),
+                        _ => None
+                    }
+                },
+                _ => None
+            };
+
+            if let Some(content) = content {
+                let node = TarNode {
+                    device: device.clone(),
+                    content,
+                    mode: parse_octal(&header[MODE]).map(|mode| (mode & 0o7777) as u16),
+                    modified: parse_octal(&header[MODIFIED]).unwrap_or(0),
+                    uid: parse_octal(&header[UID]).unwrap_or(0) as u32,
+                    gid: parse_octal(&header[GID]).unwrap_or(0) as u32
+                };
+
+                root.insert(&path, node)?;
+            }
+
+            sector += 1 + (size + SECTOR_SIZE as u64 - 1) / SECTOR_SIZE as u64;
+        }
+
+        Ok(Initramfs { root: Arc::new(root) })
+    }
+}
+
+impl Node for TarNode {
+    fn kind(&self) -> NodeKind {
+        match self.content {
+            Content::File { .. } => NodeKind::File,
+            Content::Directory(_) => NodeKind::Directory,
+            Content::Symlink(_) => NodeKind::Symlink
+        }
+    }
+
+    fn size(&self) -> u64 {
+        match &self.content {
+            Content::File { size, .. } => *size,
+            Content::Directory(_) => 0,
+            Content::Symlink(target) => target.len() as u64
+        }
+    }
+
+    fn metadata(&self) -> Result<Metadata, KernelError> {
+        let metadata = Metadata::new(self.kind(), self.size());
+
+        Ok(Metadata {
+            mode: self.mode.unwrap_or(metadata.mode),
+            uid: self.uid,
+            gid: self.gid,
+            modified: self.modified,
+            ..metadata
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Node>, KernelError> {
+        match &self.content {
+            Content::Directory(entries) => Ok(entries.get(name).ok_or(KernelError::NotFound)?.clone()),
+            _ => Err(KernelError::NotDirectory)
+        }
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, KernelError> {
+        let (start, size) = match self.content {
+            Content::File { sector, size } => (sector, size),
+            _ => return Err(self.refusal())
+        };
+
+        let end = (offset + buffer.len() as u64).min(size);
+        let mut sector = [0; SECTOR_SIZE];
+        let mut position = offset;
+
+        while position < end {
+            let in_sector = (position % SECTOR_SIZE as u64) as usize;
+            let count = (SECTOR_SIZE - in_sector).min((end - position) as usize);
+            let copied = (position - offset) as usize;
+
+            self.device.read(start + position / SECTOR_SIZE as u64, &mut sector)?;
+            buffer[copied..copied + count].copy_from_slice(&sector[in_sector..in_sector + count]);
+
+            position += count as u64;
+        }
+
+        return Ok(end.saturating_sub(offset) as usize);
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, KernelError> {
+        match &self.content {
+            Content::Directory(entries) => Ok(entries
+                .iter()
+                .map(|(name, node)| DirEntry { name: name.clone(), kind: node.kind(), size: node.size() })
+                .collect()),
+            _ => Err(KernelError::NotDirectory)
+        }
+    }
+
+    fn read_link(&self) -> Result<String, KernelError> {
+        match &self.content {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(KernelError::InvalidArgument)
+        }
+    }
+}
+
+impl FileSystem for Initramfs {
+    fn name(&self) -> &str {
+        "initramfs"
+    }
+
+    fn root(&self) -> Arc<dyn Node> {
+        self.root.clone()
+    }
+
+    fn device(&self) -> Option<&str> {
+        Some(self.root.device.name())
+    }
+}
@@ -0,0 +1,616 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::block::BlockDevice;
+use crate::time::calendar::DateTime;
+use crate::utils::error::KernelError;
+use super::{DirEntry, FileSystem, Metadata, Node, NodeKind};
+
+/// The size of a descriptor of the volume, and where the first one is: the 16 blocks before are left to the system
+const DESCRIPTOR_SIZE: usize = 2048;
+const DESCRIPTORS_OFFSET: u64 = 16 * DESCRIPTOR_SIZE as u64;
+
+/// The types of the descriptors, their list ends with the terminator
+const DESCRIPTOR_PRIMARY: u8 = 1;
+const DESCRIPTOR_TERMINATOR: u8 = 255;
+
+/// What every descriptor has right after its type
+const STANDARD_IDENTIFIER: &[u8] = b"CD001";
+
+/// Where the record of the root directory is in the primary descriptor
+const ROOT_RECORD: core::ops::Range<usize> = 156..190;
+
+/// The size of a directory record without its name
+const RECORD_HEADER_SIZE: usize = 33;
+
+/// The flags of a directory record
+const FLAG_DIRECTORY: u8 = 1 << 1;
+
+/// The names of the first two records of every directory, the one of the directory itself and the one of its parent
+const NAME_SELF: &[u8] = &[0];
+const NAME_PARENT: &[u8] = &[1];
+
+/// The signature of the SUSP entry telling that the volume has Rock Ridge entries, at the start of the first record of
+/// the root directory
+const SP_CHECK: [u8; 2] = [0xBE, 0xEF];
+
+/// How many continuation areas of Rock Ridge entries are followed for a record at most, they could lead to each other
+const MAX_CONTINUATIONS: usize = 8;
+
+/// The flags of the Rock Ridge entries with a name (NM) and of the components of a symbolic link (SL)
+const RR_CONTINUE: u8 = 1 << 0;
+const RR_CURRENT: u8 = 1 << 1;
+const RR_PARENT: u8 = 1 << 2;
+const RR_ROOT: u8 = 1 << 3;
+
+/// The flags of the Rock Ridge entry with the times (TF), the times follow in this order. With the long form they're
+/// written as text instead of in 7 bytes
+const TIME_CREATION: u8 = 1 << 0;
+const TIME_MODIFY: u8 = 1 << 1;
+const TIME_ACCESS: u8 = 1 << 2;
+const TIME_LONG_FORM: u8 = 1 << 7;
+
+/// The type of the node in the mode of a Rock Ridge PX entry, for the symbolic links
+const MODE_TYPE_MASK: u32 = 0o170000;
+const MODE_SYMLINK: u32 = 0o120000;
+
+/// An ISO9660 filesystem, like the one of a CD, only read. The names and the attributes of the Rock Ridge extensions
+/// are used when the volume has them, otherwise the names are shown in lowercase without their version
+pub struct Iso9660 {
+    volume: Arc<Volume>,
+    root: Record
+}
+
+/// What the nodes of an [`Iso9660`] need from it
+struct Volume {
+    device: Arc<dyn BlockDevice>,
+    /// The size of a logical block, the extents of the records count them
+    block_size: u64,
+    /// How many bytes of the system use area of every record are skipped before the Rock Ridge entries, [`None`] if
+    /// the volume doesn't have them
+    rock_ridge: Option<usize>
+}
+
+/// A directory record, with what the Rock Ridge entries of it tell
+#[derive(Clone)]
+struct Record {
+    name: String,
+    /// The first logical block of the data and its size in bytes. Only the first extent of the files made of several
+    /// (the ones over 4 GiB) is read
+    extent: u32,
+    size: u32,
+    directory: bool,
+    /// The permissions, only known with Rock Ridge like the owner
+    mode: Option<u16>,
+    uid: u32,
+    gid: u32,
+    /// The path a symbolic link points to
+    link: Option<String>,
+    /// In seconds since the Unix epoch, 0 if the volume doesn't have them
+    created: u64,
+    accessed: u64,
+    modified: u64
+}
+
+/// What's gathered from the Rock Ridge entries of a record, which can be split between several of them
+#[derive(Default)]
+struct RockRidge {
+    name: Option<String>,
+    link: Option<String>,
+    /// Whatever the last component of the link goes on in the next SL entry
+    component_continues: bool,
+    /// The mode, the user and the group of the PX entry
+    owner: Option<(u32, u32, u32)>,
+    created: Option<u64>,
+    accessed: Option<u64>,
+    modified: Option<u64>,
+    /// The record of a directory moved away from where it belongs (because the tree was too deep), it's hidden
+    relocated: bool,
+    /// The block of the directory moved away that this record stands for
+    child_link: Option<u32>
+}
+
+/// Reads the bytes of `device` starting at `offset` into `buffer`, whatever the size of its sectors
+fn read_bytes(device: &dyn BlockDevice, offset: u64, buffer: &mut [u8]) -> Result<(), KernelError> {
+    let sector_size = device.sector_size() as u64;
+    let mut sector = vec![0; sector_size as usize];
+    let mut position = offset;
+    let end = offset + buffer.len() as u64;
+
+    while position < end {
+        let in_sector = (position % sector_size) as usize;
+        let count = (sector_size as usize - in_sector).min((end - position) as usize);
+        let copied = (position - offset) as usize;
+
+        device.read(position / sector_size, &mut sector)?;
+        buffer[copied..copied + count].copy_from_slice(&sector[in_sector..in_sector + count]);
+
+        position += count as u64;
+    }
+
+    Ok(())
+}
+
+/// Returns the system use area of a directory record, where the Rock Ridge entries are. It comes after the name,
+/// which is padded to an even length
+fn system_use(raw: &[u8]) -> &[u8] {
+    let name_length = raw[32] as usize;
+    let start = RECORD_HEADER_SIZE + name_length + (name_length % 2 == 0) as usize;
+
+    return &raw[start.min(raw.len())..];
+}
+
+/// Turns a date of 7 bytes into seconds since the Unix epoch: the years since 1900, the month, the day, the hours,
+/// the minutes, the seconds and the offset from UTC in quarters of hours. 0 if there's no date
+fn short_date(raw: &[u8]) -> u64 {
+    let date_time = DateTime {
+        year: 1900 + raw[0] as u16,
+        month: raw[1],
+        day: raw[2],
+        hour: raw[3],
+        minute: raw[4],
+        second: raw[5]
+    };
+
+    return to_utc(date_time, raw[6] as i8);
+}
+
+/// Turns a date of 17 bytes into seconds since the Unix epoch: the year, the month, the day, the hours, the minutes,
+/// the seconds and the hundredths of a second as text, then the offset from UTC like [`short_date`]
+fn long_date(raw: &[u8]) -> u64 {
+    let number = |start: usize, length: usize| {
+        raw[start..start + length]
+            .iter()
+            .fold(0u16, |value, &digit| value * 10 + digit.wrapping_sub(b'0').min(9) as u16)
+    };
+
+    let date_time = DateTime {
+        year: number(0, 4),
+        month: number(4, 2) as u8,
+        day: number(6, 2) as u8,
+        hour: number(8, 2) as u8,
+        minute: number(10, 2) as u8,
+        second: number(12, 2) as u8
+    };
+
+    return to_utc(date_time, raw[16] as i8);
+}
+
+/// Returns the seconds since the Unix epoch of a date `offset` quarters of hours from UTC, 0 if there's no month
+fn to_utc(date_time: DateTime, offset: i8) -> u64 {
+    if date_time.month == 0 {
+        return 0;
+    }
+
+    let offset = offset as i64 * 15 * 60;
+    return (date_time.to_unix() as i64 - offset).max(0) as u64;
+}
+
+impl RockRidge {
+    /// Reads the entries of a system use area (or of a continuation area), returning where the next continuation
+    /// area is and its size, if there's one
+    fn parse(&mut self, area: &[u8]) -> Option<(u64, u64, usize)> {
+        let mut continuation = None;
+        let mut offset = 0;
+
+        while offset + 4 <= area.len() {
+            let length = area[offset + 2] as usize;
+
+            if length < 4 || offset + length > area.len() {
+                break;
+            }
+
+            let entry = &area[offset..offset + length];
+            let dword = |at: usize| entry.get(at..at + 4).map_or(0, |bytes| {
+                u32::from_le_bytes(bytes.try_into().unwrap())
+            });
+
+            match &entry[0..2] {
+                b"NM" if length > 4 => self.add_name(entry[4], &entry[5..]),
+                b"SL" if length > 4 => self.add_link(&entry[5..]),
+                b"PX" => self.owner = Some((dword(4), dword(20), dword(28))),
+                b"TF" if length > 4 => self.add_times(entry[4], &entry[5..]),
+                b"CE" => continuation = Some((dword(4) as u64, dword(12) as u64, dword(20) as usize)),
+                b"RE" => self.relocated = true,
+                b"CL" => self.child_link = Some(dword(4)),
+                b"ST" => break,
+                _ => {}
+            }
+
+            offset += length;
+        }
+
+        return continuation;
+    }
+
+    fn add_name(&mut self, flags: u8, text: &[u8]) {
+        let name = self.name.get_or_insert_with(String::new);
+
+        match flags {
+            flags if flags & RR_CURRENT != 0 => name.push('.'),
+            flags if flags & RR_PARENT != 0 => name.push_str(".."),
+            _ => name.push_str(&String::from_utf8_lossy(text))
+        }
+    }
+
+    /// Adds the components of an SL entry to the path of the link, each of them has its flags, its length and its
+    /// text
+    fn add_link(&mut self, mut components: &[u8]) {
+        let link = self.link.get_or_insert_with(String::new);
+
+        while components.len() >= 2 && components.len() >= 2 + components[1] as usize {
+            let (flags, length) = (components[0], components[1] as usize);
+            let text = String::from_utf8_lossy(&components[2..2 + length]);
+
+            let part = match flags {
+                flags if flags & RR_ROOT != 0 => "/",
+                flags if flags & RR_CURRENT != 0 => ".",
+                flags if flags & RR_PARENT != 0 => "..",
+                _ => &text
+            };
+
+            if !self.component_continues && !link.is_empty() && !link.ends_with('/') {
+                link.push('/');
+            }
+
+            link.push_str(part);
+
+            self.component_continues = flags & RR_CONTINUE != 0;
+            components = &components[2 + length..];
+        }
+    }
+
+    /// Reads the times of a TF entry, the ones with their flag set are there one after the other
+    fn add_times(&mut self, flags: u8, mut times: &[u8]) {
+        let size = if flags & TIME_LONG_FORM != 0 { 17 } else { 7 };
+
+        for flag in [TIME_CREATION, TIME_MODIFY, TIME_ACCESS] {
+            if flags & flag == 0 {
+                continue;
+            }
+
+            if times.len() < size {
+                return;
+            }
+
+            let time = if size == 17 { long_date(&times[..size]) } else { short_date(&times[..size]) };
+
+            match flag {
+                TIME_CREATION => self.created = Some(time),
+                TIME_MODIFY => self.modified = Some(time),
+                _ => self.accessed = Some(time)
+            }
+
+            times = &times[size..];
+        }
+    }
+}
+
+impl Iso9660 {
+    /// Looks for an ISO9660 filesystem on the whole of `device`, whatever the size of its sectors
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Unsupported`] if there's no ISO9660 filesystem on the device, [`KernelError::Io`] if
+    /// it's damaged, otherwise the error of reading the device
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Self, KernelError> {
+        let device_size = device.sector_count() * device.sector_size() as u64;
+        let mut descriptor = vec![0; DESCRIPTOR_SIZE];
+        let mut offset = DESCRIPTORS_OFFSET;
+
+        loop {
+            if offset + DESCRIPTOR_SIZE as u64 > device_size {
+                return Err(KernelError::Unsupported);
+            }
+
+            read_bytes(device.as_ref(), offset, &mut descriptor)?;
+
+            if &descriptor[1..6] != STANDARD_IDENTIFIER {
+                return Err(KernelError::Unsupported);
+            }
+
+            match descriptor[0] {
+                DESCRIPTOR_PRIMARY => break,
+                DESCRIPTOR_TERMINATOR => return Err(KernelError::Unsupported),
+                _ => offset += DESCRIPTOR_SIZE as u64
+            }
+        }
+
+        // The size of a logical block is written in both byte orders, the little endian one first
+        let block_size = u16::from_le_bytes([descriptor[128], descriptor[129]]) as u64;
+
+        if !block_size.is_power_of_two() || !(512..=DESCRIPTOR_SIZE as u64).contains(&block_size) {
+            return Err(KernelError::Unsupported);
+        }
+
+        let root = &descriptor[ROOT_RECORD];
+        let extent = u32::from_le_bytes(root[2..6].try_into().unwrap());
+
+        // The first record of the root directory is the one of the root itself, with the SP entry if the volume has
+        // Rock Ridge entries
+        let mut first = [0; 255];
+        read_bytes(device.as_ref(), extent as u64 * block_size, &mut first)?;
+
+        let length = first[0] as usize;
+
+        if length < RECORD_HEADER_SIZE + 1 {
+            return Err(KernelError::Io);
+        }
+
+        let area = system_use(&first[..length]);
+        let rock_ridge = match area {
+            [b'S', b'P', 7, _, check_0, check_1, skip, ..] if [*check_0, *check_1] == SP_CHECK => Some(*skip as usize),
+            _ => None
+        };
+
+        let volume = Volume { device, block_size, rock_ridge };
+        let root = volume.parse_record(&first[..length])?.ok_or(KernelError::Io)?;
+
+        Ok(Iso9660 { volume: Arc::new(volume), root })
+    }
+}
+
+impl Volume {
+    fn read(&self, offset: u64, buffer: &mut [u8]) -> Result<(), KernelError> {
+        read_bytes(self.device.as_ref(), offset, buffer)
+    }
+
+    /// Parses the directory record `raw` with its Rock Ridge entries, [`None`] if it has to be hidden (the directories
+    /// moved away from where they belong are reached from the records standing for them)
+    fn parse_record(&self, raw: &[u8]) -> Result<Option<Record>, KernelError> {
+        let name_length = raw[32] as usize;
+
+        if RECORD_HEADER_SIZE + name_length > raw.len() {
+            return Err(KernelError::Io);
+        }
+
+        let identifier = &raw[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + name_length];
+
+        // The names are in uppercase with a version after a `;`, and a `.` when they have no extension
+        let name = match identifier {
+            NAME_SELF => String::from("."),
+            NAME_PARENT => String::from(".."),
+            _ => {
+                let name = String::from_utf8_lossy(identifier);
+                let name = name.split(';').next().unwrap_or("");
+
+                name.strip_suffix('.').unwrap_or(name).to_ascii_lowercase()
+            }
+        };
+
+        let mut record = Record {
+            name,
+            extent: u32::from_le_bytes(raw[2..6].try_into().unwrap()),
+            size: u32::from_le_bytes(raw[10..14].try_into().unwrap()),
+            directory: raw[25] & FLAG_DIRECTORY != 0,
+            mode: None,
+            uid: 0,
+            gid: 0,
+            link: None,
+            created: 0,
+            accessed: 0,
+            modified: short_date(&raw[18..25])
+        };
+
+        let skip = match self.rock_ridge {
+            Some(skip) => skip,
+            None => return Ok(Some(record))
+        };
+
+        let mut rock_ridge = RockRidge::default();
+        let mut continuation = rock_ridge.parse(system_use(raw).get(skip..).unwrap_or(&[]));
+
+        for _ in 0..MAX_CONTINUATIONS {
+            let (block, offset, length) = match continuation {
+                Some(continuation) => continuation,
+                None => break
+            };
+
+            let mut area = vec![0; length.min(self.block_size as usize)];
+            self.read(block * self.block_size + offset, &mut area)?;
+
+            continuation = rock_ridge.parse(&area);
+        }
+
+        if rock_ridge.relocated {
+            return Ok(None);
+        }
+
+        // The record stands for a directory moved away, whose size is in the record of the directory itself
+        if let Some(extent) = rock_ridge.child_link {
+            let mut header = [0; RECORD_HEADER_SIZE];
+            self.read(extent as u64 * self.block_size, &mut header)?;
+
+            record.extent = extent;
+            record.size = u32::from_le_bytes(header[10..14].try_into().unwrap());
+            record.directory = true;
+        }
+
+        if let Some(name) = rock_ridge.name {
+            record.name = name;
+        }
+
+        let mode = rock_ridge.owner.map(|(mode, _, _)| mode);
+
+        if mode.map_or(false, |mode| mode & MODE_TYPE_MASK == MODE_SYMLINK) || rock_ridge.link.is_some() {
+            record.link = Some(rock_ridge.link.unwrap_or_default());
+        }
+
+        if let Some((mode, uid, gid)) = rock_ridge.owner {
+            record.mode = Some((mode & 0o7777) as u16);
+            record.uid = uid;
+            record.gid = gid;
+        }
+
+        record.created = rock_ridge.created.unwrap_or(0);
+        record.accessed = rock_ridge.accessed.unwrap_or(0);
+        record.modified = rock_ridge.modified.unwrap_or(record.modified);
+
+        return Ok(Some(record));
+    }
+
+    /// Calls `f` with every record of `directory` but the ones of itself and of its parent, until it returns something
+    fn find_record<T>(
+        &self,
+        directory: &Record,
+        mut f: impl FnMut(&Record) -> Option<T>
+    ) -> Result<Option<T>, KernelError> {
+        let mut block = vec![0; self.block_size as usize];
+        let blocks = (directory.size as u64 + self.block_size - 1) / self.block_size;
+
+        for index in 0..blocks {
+            self.read((directory.extent as u64 + index) * self.block_size, &mut block)?;
+
+            // A record never crosses a block, the rest of a block after its last record is zeros
+            let mut offset = 0;
+
+            while offset < block.len() && block[offset] != 0 {
+                let length = block[offset] as usize;
+
+                if length <= RECORD_HEADER_SIZE || offset + length > block.len() {
+                    return Err(KernelError::Io);
+                }
+
+                if let Some(record) = self.parse_record(&block[offset..offset + length])? {
+                    if record.name != "." && record.name != ".." {
+                        if let Some(result) = f(&record) {
+                            return Ok(Some(result));
+                        }
+                    }
+                }
+
+                offset += length;
+            }
+        }
+
+        return Ok(None);
+    }
+}
+
+/// A file, a directory or a symbolic link of an [`Iso9660`] filesystem
+struct IsoNode {
+    fs: Arc<Volume>,
+    record: Record
+}
+
+impl Record {
+    fn kind(&self) -> NodeKind {
+        match (self.directory, &self.link) {
+            (true, _) => NodeKind::Directory,
+            (false, Some(_)) => NodeKind::Symlink,
+            (false, None) => NodeKind::File
+        }
+    }
+
+    fn size(&self) -> u64 {
+        match (self.kind(), &self.link) {
+            (NodeKind::File, _) => self.size as u64,
+            (NodeKind::Symlink, Some(link)) => link.len() as u64,
+            _ => 0
+        }
+    }
+}
+
+impl Node for IsoNode {
+    fn kind(&self) -> NodeKind {
+        self.record.kind()
+    }
+
+    fn size(&self) -> u64 {
+        self.record.size()
+    }
+
+    /// Without Rock Ridge everything can be read by anyone and written by nobody
+    fn metadata(&self) -> Result<Metadata, KernelError> {
+        let metadata = Metadata::new(self.kind(), self.size());
+
+        Ok(Metadata {
+            mode: self.record.mode.unwrap_or(metadata.mode & !0o222),
+            uid: self.record.uid,
+            gid: self.record.gid,
+            created: self.record.created,
+            accessed: self.record.accessed,
+            modified: self.record.modified,
+            ..metadata
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Node>, KernelError> {
+        if !self.record.directory {
+            return Err(KernelError::NotDirectory);
+        }
+
+        // The names of Rock Ridge are compared as they are, the others without case like ISO9660 does
+        let matches = |record: &Record| match self.fs.rock_ridge {
+            Some(_) => record.name == name,
+            None => record.name.eq_ignore_ascii_case(name)
+        };
+
+        let record = self.fs
+            .find_record(&self.record, |record| matches(record).then(|| record.clone()))?
+            .ok_or(KernelError::NotFound)?;
+
+        return Ok(Arc::new(IsoNode { fs: self.fs.clone(), record }));
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, KernelError> {
+        if self.kind() != NodeKind::File {
+            return Err(self.refusal());
+        }
+
+        let end = (offset + buffer.len() as u64).min(self.record.size as u64);
+
+        if offset >= end {
+            return Ok(0);
+        }
+
+        let count = (end - offset) as usize;
+        self.fs.read(self.record.extent as u64 * self.fs.block_size + offset, &mut buffer[..count])?;
+
+        return Ok(count);
+    }
+
+    /// The content of a file is a single extent of the device
+    fn extent(&self, offset: u64) -> Result<(Option<u64>, u64), KernelError> {
+        if self.kind() != NodeKind::File {
+            return Err(self.refusal());
+        }
+
+        let start = self.record.extent as u64 * self.fs.block_size;
+        return Ok((Some(start + offset), (self.record.size as u64).saturating_sub(offset)));
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, KernelError> {
+        if !self.record.directory {
+            return Err(KernelError::NotDirectory);
+        }
+
+        let mut entries = Vec::new();
+
+        self.fs.find_record::<()>(&self.record, |record| {
+            entries.push(DirEntry { name: record.name.clone(), kind: record.kind(), size: record.size() });
+            None
+        })?;
+
+        return Ok(entries);
+    }
+
+    fn read_link(&self) -> Result<String, KernelError> {
+        self.record.link.clone().ok_or(KernelError::InvalidArgument)
+    }
+}
+
+impl FileSystem for Iso9660 {
+    fn name(&self) -> &str {
+        "iso9660"
+    }
+
+    fn root(&self) -> Arc<dyn Node> {
+        Arc::new(IsoNode { fs: self.volume.clone(), record: self.root.clone() })
+    }
+
+    fn device(&self) -> Option<&str> {
+        Some(self.volume.device.name())
+    }
+}
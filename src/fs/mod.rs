@@ -0,0 +1,626 @@
+pub mod aio;
+pub mod devfs;
+pub mod ext2;
+pub mod fat;
+pub mod initramfs;
+pub mod iso9660;
+pub mod pipe;
+pub mod procfs;
+pub mod romfs;
+pub mod tmpfs;
+pub mod vfs;
+
+use core::any::Any;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::block::BlockDevice;
+use crate::println;
+use crate::process::credentials::Credentials;
+use crate::sync::SleepMutex;
+use crate::utils::error::KernelError;
+
+/// What a [`Node`] is
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NodeKind {
+    File,
+    Directory,
+    /// A node whose reads and writes go to a device instead of stored data, like the console
+    Device,
+    /// A symbolic link, a node that stands for the one at the path it holds
+    Symlink,
+    /// An end of a pipe, what's written to it is read from the other end (see [`pipe::pair`])
+    Pipe
+}
+
+/// An entry of a directory, as returned by [`Node::readdir`]
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub kind: NodeKind,
+    /// The size of the node, see [`Node::size`]
+    pub size: u64
+}
+
+/// What a process asks to do with a node, checked by [`Metadata::check_access`]. They're the bits of the permissions
+/// of a Unix mode, for one of its classes
+pub const ACCESS_READ: u16 = 0o4;
+pub const ACCESS_WRITE: u16 = 0o2;
+pub const ACCESS_EXECUTE: u16 = 0o1;
+
+/// What's known about a node besides its content, see [`Node::metadata`]
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone)]
+pub struct Metadata {
+    pub kind: NodeKind,
+    pub size: u64,
+    /// The permissions, the low 12 bits of a Unix mode (like `0o644`)
+    pub mode: u16,
+    /// The user and the group that own the node, root for a filesystem that doesn't keep them
+    pub uid: u32,
+    pub gid: u32,
+    /// When the node was last read, last written and created, in seconds since the Unix epoch. A filesystem that
+    /// doesn't keep one of them has 0
+    pub accessed: u64,
+    pub modified: u64,
+    pub created: u64
+}
+
+impl Metadata {
+    /// The metadata of a node that only has a kind and a size, with the usual permissions for its kind, owned by root
+    /// and without times
+    pub fn new(kind: NodeKind, size: u64) -> Self {
+        let mode = match kind {
+            NodeKind::File => 0o644,
+            NodeKind::Directory => 0o755,
+            NodeKind::Device => 0o666,
+            NodeKind::Symlink => 0o777,
+            NodeKind::Pipe => 0o600
+        };
+
+        Metadata { kind, size, mode, uid: 0, gid: 0, accessed: 0, modified: 0, created: 0 }
+    }
+
+    /// Checks that the permissions let `credentials` do `access` (made of [`ACCESS_READ`], [`ACCESS_WRITE`] and
+    /// [`ACCESS_EXECUTE`]): the permissions of the owner apply to the owner, the ones of the group to the group and
+    /// the last ones to everyone else. Root can read and write anything, and execute what anyone can execute
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::PermissionDenied`] if the access isn't allowed
+    pub fn check_access(&self, credentials: &Credentials, access: u16) -> Result<(), KernelError> {
+        let allowed = if credentials.is_root() {
+            let anyone = (self.mode >> 6 | self.mode >> 3 | self.mode) & ACCESS_EXECUTE;
+            ACCESS_READ | ACCESS_WRITE | if self.kind == NodeKind::Directory { ACCESS_EXECUTE } else { anyone }
+        } else if credentials.uid == self.uid {
+            self.mode >> 6 & 0o7
+        } else if credentials.gid == self.gid {
+            self.mode >> 3 & 0o7
+        } else {
+            self.mode & 0o7
+        };
+
+        if access & !allowed != 0 {
+            return Err(KernelError::PermissionDenied);
+        }
+
+        Ok(())
+    }
+
+    /// Checks that `credentials` can change the permissions of the node, only its owner and root can
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::NotPermitted`] if they can't
+    pub fn check_owner(&self, credentials: &Credentials) -> Result<(), KernelError> {
+        if !credentials.is_root() && credentials.uid != self.uid {
+            return Err(KernelError::NotPermitted);
+        }
+
+        Ok(())
+    }
+}
+
+/// A filesystem that can be mounted with [`vfs::mount`], every node of it is reached from its root
+#[allow(dead_code)]
+pub trait FileSystem: Send + Sync {
+    /// The name of the kind of filesystem, like `fat32`
+    fn name(&self) -> &str;
+
+    /// Returns the root directory of the filesystem
+    fn root(&self) -> Arc<dyn Node>;
+
+    /// The name of the block device the filesystem is on, [`None`] for the ones that aren't on any (like a tmpfs)
+    fn device(&self) -> Option<&str> {
+        None
+    }
+
+    /// Makes sure everything written to the filesystem so far is on the device
+    fn sync(&self) -> Result<(), KernelError> {
+        Ok(())
+    }
+}
+
+/// Gives the [`Any`] of a node, so a filesystem can find its own nodes behind a `dyn Node`
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A file, a directory or a device of a [`FileSystem`]. The operations that don't make sense for the kind of the
+/// node are refused unless the implementation says otherwise
+#[allow(dead_code)]
+pub trait Node: AsAny + Send + Sync {
+    fn kind(&self) -> NodeKind;
+
+    /// The size of the file (or of the storage of the device, or of the path of the symbolic link) in bytes, 0 for
+    /// the other nodes
+    fn size(&self) -> u64 {
+        0
+    }
+
+    /// Returns the kind, the size, the permissions and the times of the node. By default only the kind and the size
+    /// are known, see [`Metadata::new`]
+    fn metadata(&self) -> Result<Metadata, KernelError> {
+        Ok(Metadata::new(self.kind(), self.size()))
+    }
+
+    /// Returns the entry of this directory called `name`
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::NotFound`] if there's no such entry, or [`KernelError::NotDirectory`] if this node
+    /// isn't a directory
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Node>, KernelError> {
+        Err(KernelError::NotDirectory)
+    }
+
+    /// Reads the bytes starting at `offset` into `buffer`, returning how many were read. Fewer bytes than asked
+    /// for are read at the end of the file, and none past it
+    fn read_at(&self, _offset: u64, _buffer: &mut [u8]) -> Result<usize, KernelError> {
+        Err(self.refusal())
+    }
+
+    /// Writes `buffer` at `offset`, returning how many bytes were written
+    fn write_at(&self, _offset: u64, _buffer: &[u8]) -> Result<usize, KernelError> {
+        Err(self.refusal())
+    }
+
+    /// Returns the entries of this directory
+    fn readdir(&self) -> Result<Vec<DirEntry>, KernelError> {
+        Err(KernelError::NotDirectory)
+    }
+
+    /// Creates an empty file or directory called `name` in this directory, returning it
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::AlreadyExists`] if there's an entry with that name already, or
+    /// [`KernelError::NotDirectory`] if this node isn't a directory
+    fn create(&self, _name: &str, _kind: NodeKind) -> Result<Arc<dyn Node>, KernelError> {
+        Err(self.directory_refusal())
+    }
+
+    /// Removes the entry of this directory called `name`, a directory must be empty
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::NotFound`] if there's no such entry, [`KernelError::NotEmpty`] if it's a directory
+    /// with entries or [`KernelError::NotDirectory`] if this node isn't a directory
+    fn unlink(&self, _name: &str) -> Result<(), KernelError> {
+        Err(self.directory_refusal())
+    }
+
+    /// Creates a symbolic link called `name` in this directory, that points to `target`
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::AlreadyExists`] if there's an entry with that name already, or
+    /// [`KernelError::NotDirectory`] if this node isn't a directory
+    fn symlink(&self, _name: &str, _target: &str) -> Result<(), KernelError> {
+        Err(self.directory_refusal())
+    }
+
+    /// Returns the path this symbolic link points to, as it was given when it was created
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if this node isn't a symbolic link
+    fn read_link(&self) -> Result<String, KernelError> {
+        Err(KernelError::InvalidArgument)
+    }
+
+    /// Moves the entry of this directory called `name` to `directory`, of the same filesystem, as `new_name`. An
+    /// entry that's there already is replaced, if it's a file or an empty directory like the one moved
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::NotFound`] if there's no such entry, [`KernelError::NotEmpty`] if the entry replaced
+    /// is a directory with entries, [`KernelError::IsDirectory`] or [`KernelError::NotDirectory`] if it isn't of
+    /// the same kind as the one moved and [`KernelError::CrossDevice`] if `directory` is of another filesystem
+    fn rename(&self, _name: &str, _directory: &dyn Node, _new_name: &str) -> Result<(), KernelError> {
+        Err(self.directory_refusal())
+    }
+
+    /// Makes the file `size` bytes long, what's past the end is cut and what's added is zeroed
+    fn truncate(&self, _size: u64) -> Result<(), KernelError> {
+        Err(self.refusal())
+    }
+
+    /// Makes sure everything written to the node so far is on the device
+    fn sync(&self) -> Result<(), KernelError> {
+        Ok(())
+    }
+
+    /// Returns where the content of this file is on the device of its filesystem from `offset` (before the end of
+    /// the file): the byte of the device with the byte of the file at `offset` ([`None`] in a hole, which reads as
+    /// zeros) and how many bytes of the file follow it on the device. Lets [`aio::read`] read the file with the
+    /// requests of the queue of the device instead of on a worker
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Unsupported`] if the content of the node isn't stored as it is on a block device, or
+    /// the device may not have what was written to it yet (like the writes kept in a journal)
+    fn extent(&self, _offset: u64) -> Result<(Option<u64>, u64), KernelError> {
+        Err(KernelError::Unsupported)
+    }
+
+    /// Changes the permissions of the node to the low 12 bits of `mode`, see [`Metadata::mode`]
+    fn set_mode(&self, _mode: u16) -> Result<(), KernelError> {
+        Err(KernelError::Unsupported)
+    }
+
+    /// Gives the node to the user `uid` and the group `gid`
+    fn set_owner(&self, _uid: u32, _gid: u32) -> Result<(), KernelError> {
+        Err(KernelError::Unsupported)
+    }
+
+    /// The error of reading or writing the node when it doesn't support it
+    fn refusal(&self) -> KernelError {
+        match self.kind() {
+            NodeKind::Directory => KernelError::IsDirectory,
+            _ => KernelError::Unsupported
+        }
+    }
+
+    /// The error of changing the entries of the node when it doesn't support it
+    fn directory_refusal(&self) -> KernelError {
+        match self.kind() {
+            NodeKind::Directory => KernelError::Unsupported,
+            _ => KernelError::NotDirectory
+        }
+    }
+}
+
+/// Where [`FileHandle::seek`] moves the offset of the next read or write to
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SeekFrom {
+    /// That many bytes from the start of the node
+    Start(u64),
+    /// That many bytes from the offset the handle is at
+    Current(i64),
+    /// That many bytes from the end of the node
+    End(i64)
+}
+
+/// A node opened by [`vfs::open`], reads and writes go one after the other from the offset of the handle, which
+/// starts at the start of the node and can be moved with [`FileHandle::seek`]
+#[allow(dead_code)]
+pub trait FileHandle: Send + Sync {
+    /// Reads from the offset of the handle and moves it past what was read, returning how many bytes that is. It's
+    /// less than the buffer holds when the end of the node comes first, 0 once the offset is at the end or past it
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, KernelError>;
+
+    /// Writes at the offset of the handle (or at the end of the node when it was opened for appending) and moves
+    /// it past what was written, returning how many bytes that is. When the write fails after some bytes are
+    /// written, it returns how many there are and the error comes with the next write
+    fn write(&self, buffer: &[u8]) -> Result<usize, KernelError>;
+
+    /// Moves the offset of the next read or write, returning where it is from the start of the node. It can go
+    /// past the end, the bytes in between are zeros once something is written there
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if the offset would be before the start of the node
+    fn seek(&self, _position: SeekFrom) -> Result<u64, KernelError> {
+        Err(KernelError::Unsupported)
+    }
+
+    /// Returns the metadata of the node that was opened, see [`Node::metadata`]
+    fn metadata(&self) -> Result<Metadata, KernelError>;
+
+    /// Hands `fill` the entries of the directory that was opened one after the other, starting at the offset of the
+    /// handle (which counts the entries), until it doesn't take one or there are none left. The offset is moved past
+    /// the entries taken
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::NotDirectory`] if the node opened isn't a directory
+    fn read_entries(&self, _fill: &mut dyn FnMut(&DirEntry) -> bool) -> Result<(), KernelError> {
+        Err(KernelError::NotDirectory)
+    }
+
+    /// Makes sure everything written through the handle is on the device
+    fn sync(&self) -> Result<(), KernelError> {
+        Ok(())
+    }
+
+    /// Returns the node whose content the handle reads, for the handles that can be mapped in memory
+    fn node(&self) -> Option<Arc<dyn Node>> {
+        None
+    }
+
+    /// Returns the filesystem the node of the handle is in, so what uses the node can keep it mounted too
+    fn filesystem(&self) -> Option<Arc<dyn FileSystem>> {
+        None
+    }
+}
+
+/// Moves `offset` like [`FileHandle::seek`], `end` is where [`SeekFrom::End`] counts from
+fn seek_offset(offset: &mut u64, position: SeekFrom, end: u64) -> Result<u64, KernelError> {
+    let (base, distance) = match position {
+        SeekFrom::Start(position) => (position, 0),
+        SeekFrom::Current(distance) => (*offset, distance),
+        SeekFrom::End(distance) => (end, distance)
+    };
+
+    *offset = i64::try_from(base)
+        .ok()
+        .and_then(|base| base.checked_add(distance))
+        .and_then(|position| u64::try_from(position).ok())
+        .ok_or(KernelError::InvalidArgument)?;
+
+    Ok(*offset)
+}
+
+/// The handle of every node opened through the VFS, it keeps where the next read or write goes. The offset is
+/// held while the node is read or written, so the reads and writes through the same handle don't overlap
+pub struct OpenNode {
+    node: Arc<dyn Node>,
+    offset: SleepMutex<u64>,
+    /// Whatever every write goes to the end of the node, wherever the offset was
+    append: bool,
+    /// The filesystem the node is in, held so it can't be unmounted while the node is open (see [`vfs::umount`]).
+    /// [`None`] for the nodes that aren't reached through a mount point, like the console of the processes
+    fs: Option<Arc<dyn FileSystem>>
+}
+
+impl OpenNode {
+    pub fn new(node: Arc<dyn Node>) -> Self {
+        OpenNode {
+            node,
+            offset: SleepMutex::new(0),
+            append: false,
+            fs: None
+        }
+    }
+
+    /// Keeps `fs`, the filesystem the node is in, mounted for as long as the handle is open
+    pub fn in_filesystem(self, fs: Arc<dyn FileSystem>) -> Self {
+        OpenNode { fs: Some(fs), ..self }
+    }
+
+    /// Opens `node` for appending, the offset is moved to the end of the node before every write so nothing
+    /// written through the handle lands over what's there (reads still go from the offset)
+    pub fn appending(node: Arc<dyn Node>) -> Self {
+        OpenNode { append: true, ..OpenNode::new(node) }
+    }
+}
+
+impl FileHandle for OpenNode {
+    /// A file is read until the buffer is full or its end comes, the other nodes (like the console) only once since
+    /// they may block until there's more
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, KernelError> {
+        let mut offset = self.offset.lock();
+
+        if self.node.kind() != NodeKind::File {
+            let count = self.node.read_at(*offset, buffer)?;
+
+            *offset += count as u64;
+            return Ok(count);
+        }
+
+        let mut read = 0;
+
+        while read < buffer.len() {
+            match self.node.read_at(*offset + read as u64, &mut buffer[read..]) {
+                Ok(0) => break,
+                Ok(count) => read += count,
+                Err(error) if read == 0 => return Err(error),
+                Err(_) => break
+            }
+        }
+
+        *offset += read as u64;
+        Ok(read)
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize, KernelError> {
+        let mut offset = self.offset.lock();
+
+        if self.append {
+            *offset = self.node.size();
+        }
+
+        let mut written = 0;
+
+        while written < buffer.len() {
+            match self.node.write_at(*offset + written as u64, &buffer[written..]) {
+                Ok(0) => break,
+                Ok(count) => written += count,
+                Err(error) if written == 0 => return Err(error),
+                Err(_) => break
+            }
+        }
+
+        *offset += written as u64;
+        Ok(written)
+    }
+
+    fn seek(&self, position: SeekFrom) -> Result<u64, KernelError> {
+        seek_offset(&mut self.offset.lock(), position, self.node.size())
+    }
+
+    fn metadata(&self) -> Result<Metadata, KernelError> {
+        self.node.metadata()
+    }
+
+    fn sync(&self) -> Result<(), KernelError> {
+        self.node.sync()
+    }
+
+    fn node(&self) -> Option<Arc<dyn Node>> {
+        Some(self.node.clone())
+    }
+
+    fn filesystem(&self) -> Option<Arc<dyn FileSystem>> {
+        self.fs.clone()
+    }
+}
+
+/// A directory opened through the VFS, its entries are read once when it's opened (see [`vfs::open_dir`]). The
+/// offset of the handle counts the entries
+pub struct Dir {
+    /// The directory itself, [`None`] for the directories that only lead to mount points
+    node: Option<Arc<dyn Node>>,
+    entries: Vec<DirEntry>,
+    offset: SleepMutex<u64>,
+    /// The filesystem the directory is in, held like the one of an [`OpenNode`]
+    _fs: Option<Arc<dyn FileSystem>>
+}
+
+impl Dir {
+    /// Opens the directory `node` of the filesystem `fs` with its `entries`
+    pub fn new(directory: Option<(Arc<dyn FileSystem>, Arc<dyn Node>)>, entries: Vec<DirEntry>) -> Self {
+        let (fs, node) = match directory {
+            Some((fs, node)) => (Some(fs), Some(node)),
+            None => (None, None)
+        };
+
+        Dir {
+            node,
+            entries,
+            offset: SleepMutex::new(0),
+            _fs: fs
+        }
+    }
+
+    /// Returns the entries of the directory, whatever the offset of the handle
+    pub fn iter(&self) -> core::slice::Iter<'_, DirEntry> {
+        self.entries.iter()
+    }
+}
+
+impl FileHandle for Dir {
+    fn read(&self, _buffer: &mut [u8]) -> Result<usize, KernelError> {
+        Err(KernelError::IsDirectory)
+    }
+
+    fn write(&self, _buffer: &[u8]) -> Result<usize, KernelError> {
+        Err(KernelError::IsDirectory)
+    }
+
+    fn seek(&self, position: SeekFrom) -> Result<u64, KernelError> {
+        seek_offset(&mut self.offset.lock(), position, self.entries.len() as u64)
+    }
+
+    fn metadata(&self) -> Result<Metadata, KernelError> {
+        match &self.node {
+            Some(node) => node.metadata(),
+            None => Ok(Metadata::new(NodeKind::Directory, 0))
+        }
+    }
+
+    fn read_entries(&self, fill: &mut dyn FnMut(&DirEntry) -> bool) -> Result<(), KernelError> {
+        let mut offset = self.offset.lock();
+
+        while let Some(entry) = self.entries.get(*offset as usize) {
+            if !fill(entry) {
+                break;
+            }
+
+            *offset += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// Opens the filesystem on `device` so it can be mounted, trying FAT32, ext2 then ISO9660. A device with a journal
+/// is used through it (see [`crate::block::journal::open`]), the filesystem ends before the journal
+///
+/// ## Errors
+///
+/// Returns [`KernelError::Unsupported`] if the device has none of them, or the error of opening the journal
+pub fn detect(device: Arc<dyn BlockDevice>) -> Result<Arc<dyn FileSystem>, KernelError> {
+    let device = crate::block::journal::open(device)?;
+
+    if let Ok(fs) = fat::Fat32::new(device.clone()) {
+        return Ok(Arc::new(fs));
+    }
+
+    if let Ok(fs) = ext2::Ext2::new(device.clone()) {
+        return Ok(Arc::new(fs));
+    }
+
+    match iso9660::Iso9660::new(device) {
+        Ok(fs) => Ok(Arc::new(fs)),
+        Err(_) => Err(KernelError::Unsupported)
+    }
+}
+
+/// Mounts `fs` at `path`, telling how it went
+fn mount(path: &str, fs: Arc<dyn FileSystem>) {
+    let name = fs.name().to_uppercase();
+
+    match vfs::mount(path, fs) {
+        Ok(()) => println!("{}: mounted at {}", name, path),
+        Err(error) => println!("{}: failed to mount at {} ({:?})", name, path, error)
+    }
+}
+
+/// Mounts the archive of the initrd at `/`, the files built in the kernel at `/rom`, the filesystem of every block
+/// device that has one at `/mnt/` followed by the name of the device (see [`detect`]), the devices
+/// at `/dev`, the state of the kernel at `/proc` and a tmpfs at `/tmp`, after creating the work queue of [`aio`].
+/// Must be called once, after [`crate::drivers::registry::probe_all`] found the devices
+pub fn init() {
+    aio::init();
+
+    if let Some(device) = crate::block::find(crate::block::ramdisk::INITRD_NAME) {
+        match initramfs::Initramfs::new(device) {
+            Ok(fs) => mount("/", Arc::new(fs)),
+            Err(error) => println!("INITRAMFS: no archive in the initrd ({:?})", error)
+        }
+    }
+
+    match romfs::RomFs::new() {
+        Ok(fs) => mount("/rom", Arc::new(fs)),
+        Err(error) => println!("ROMFS: the table of the files is damaged ({:?})", error)
+    }
+
+    for device in crate::block::devices() {
+        let path = format!("/mnt/{}", device.name());
+
+        // The devices without a filesystem the kernel knows are left alone
+        match detect(device.clone()) {
+            Ok(fs) => mount(&path, fs),
+            Err(KernelError::Unsupported) => continue,
+            Err(error) => println!("Journal: {} has a damaged journal ({:?})", device.name(), error)
+        }
+    }
+
+    devfs::init();
+
+    mount("/dev", Arc::new(devfs::DevFs::new()));
+    mount("/proc", Arc::new(procfs::ProcFs::new()));
+    mount("/tmp", Arc::new(tmpfs::TmpFs::new()));
+}
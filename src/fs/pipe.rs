@@ -0,0 +1,190 @@
+use alloc::sync::Arc;
+use crate::memory::address_space::UnmappedFrame;
+use crate::sched::WaitQueue;
+use crate::utils::error::KernelError;
+use crate::utils::IrqCell;
+use super::{FileHandle, Metadata, NodeKind};
+
+/// How many bytes written to a pipe can wait to be read, a writer blocks once the buffer is full. The buffer is a
+/// frame of its own, see [`UnmappedFrame`]
+const PIPE_CAPACITY: usize = 4096;
+
+/// The buffer shared by the two ends of a pipe, together with the threads waiting for data (or for room)
+struct Pipe {
+    state: IrqCell<PipeState>,
+    /// The threads waiting for something to read
+    readers: WaitQueue,
+    /// The threads waiting for room in the buffer
+    writers: WaitQueue,
+    /// The owner of the pipe, the one of the process that created it
+    uid: u32,
+    gid: u32
+}
+
+/// A ring buffer of [`PIPE_CAPACITY`] bytes, the ones waiting to be read start at `start` and go around its end
+struct PipeState {
+    buffer: UnmappedFrame,
+    start: usize,
+    length: usize,
+    /// Cleared once the end is closed, that is once every file descriptor of it is
+    reader_open: bool,
+    writer_open: bool
+}
+
+impl PipeState {
+    /// Copies the bytes waiting to `buffer`, as many as fit, returning how many that is
+    fn pop(&mut self, buffer: &mut [u8]) -> usize {
+        let count = buffer.len().min(self.length);
+        let first = count.min(PIPE_CAPACITY - self.start);
+        let data = self.buffer.bytes();
+
+        buffer[..first].copy_from_slice(&data[self.start..self.start + first]);
+        buffer[first..count].copy_from_slice(&data[..count - first]);
+
+        self.start = (self.start + count) % PIPE_CAPACITY;
+        self.length -= count;
+
+        return count;
+    }
+
+    /// Copies as much of `buffer` as there's room for after the bytes waiting, returning how many bytes that is
+    fn push(&mut self, buffer: &[u8]) -> usize {
+        let count = buffer.len().min(PIPE_CAPACITY - self.length);
+        let end = (self.start + self.length) % PIPE_CAPACITY;
+        let first = count.min(PIPE_CAPACITY - end);
+        let data = self.buffer.bytes();
+
+        data[end..end + first].copy_from_slice(&buffer[..first]);
+        data[..count - first].copy_from_slice(&buffer[first..count]);
+
+        self.length += count;
+
+        return count;
+    }
+}
+
+impl Pipe {
+    fn metadata(&self) -> Metadata {
+        let length = self.state.with(|state| state.length);
+
+        Metadata { uid: self.uid, gid: self.gid, ..Metadata::new(NodeKind::Pipe, length as u64) }
+    }
+}
+
+/// The end of a pipe that's read, see [`pair`]
+pub struct PipeReader(Arc<Pipe>);
+
+/// The end of a pipe that's written, see [`pair`]
+pub struct PipeWriter(Arc<Pipe>);
+
+/// Creates an anonymous pipe: what's written to the [`PipeWriter`] is read from the [`PipeReader`] in the same order.
+/// The pipe isn't in any directory, it goes away once both ends are closed
+///
+/// ## Errors
+///
+/// Returns [`KernelError::OutOfMemory`] if there's no free frame for the buffer of the pipe
+pub fn pair() -> Result<(PipeReader, PipeWriter), KernelError> {
+    let buffer = UnmappedFrame::new()?;
+
+    let credentials = crate::process::credentials::current();
+
+    let pipe = Arc::new(Pipe {
+        state: IrqCell::new(PipeState { buffer, start: 0, length: 0, reader_open: true, writer_open: true }),
+        readers: WaitQueue::new(),
+        writers: WaitQueue::new(),
+        uid: credentials.uid,
+        gid: credentials.gid
+    });
+
+    return Ok((PipeReader(pipe.clone()), PipeWriter(pipe)));
+}
+
+impl FileHandle for PipeReader {
+    /// Reads the bytes waiting in the pipe, as many as fit in `buffer`, blocking until there's at least one.
+    /// Returns 0 once the buffer is empty and the writing end is closed
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, KernelError> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        let mut count = 0;
+
+        self.0.readers.wait_until(|| {
+            self.0.state.with(|state| {
+                count = state.pop(buffer);
+                count != 0 || !state.writer_open
+            })
+        });
+
+        self.0.writers.wake_one();
+        return Ok(count);
+    }
+
+    fn write(&self, _buffer: &[u8]) -> Result<usize, KernelError> {
+        Err(KernelError::PermissionDenied)
+    }
+
+    fn metadata(&self) -> Result<Metadata, KernelError> {
+        Ok(self.0.metadata())
+    }
+}
+
+impl FileHandle for PipeWriter {
+    fn read(&self, _buffer: &mut [u8]) -> Result<usize, KernelError> {
+        Err(KernelError::PermissionDenied)
+    }
+
+    /// Writes the whole `buffer` to the pipe, blocking whenever it's full until the reader makes room
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Closed`] if the reading end is closed, nothing written after that would ever be read.
+    /// When it's closed after some bytes are written, their count is returned instead
+    fn write(&self, buffer: &[u8]) -> Result<usize, KernelError> {
+        let mut written = 0;
+
+        while written < buffer.len() {
+            let mut closed = false;
+
+            self.0.writers.wait_until(|| {
+                self.0.state.with(|state| {
+                    closed = !state.reader_open;
+                    closed || state.length < PIPE_CAPACITY
+                })
+            });
+
+            if closed {
+                return if written != 0 { Ok(written) } else { Err(KernelError::Closed) };
+            }
+
+            written += self.0.state.with(|state| state.push(&buffer[written..]));
+            self.0.readers.wake_one();
+        }
+
+        return Ok(written);
+    }
+
+    fn metadata(&self) -> Result<Metadata, KernelError> {
+        Ok(self.0.metadata())
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        // The bytes left are never going to be read, the writers waiting for room find out the pipe is closed
+        self.0.state.with(|state| {
+            state.reader_open = false;
+            state.length = 0;
+        });
+
+        self.0.writers.wake_all();
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        // The readers waiting for data read what's left, then the end of the pipe
+        self.0.state.with(|state| state.writer_open = false);
+        self.0.readers.wake_all();
+    }
+}
@@ -0,0 +1,161 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use crate::utils::error::KernelError;
+use super::{DirEntry, FileSystem, Node, NodeKind};
+
+/// Writes the text of a file of the filesystem
+type Generator = fn(&mut String) -> fmt::Result;
+
+/// The files of the filesystem with the function that writes their text
+const FILES: [(&str, Generator); 5] = [
+    ("meminfo", meminfo),
+    ("interrupts", interrupts),
+    ("uptime", uptime),
+    ("tasks", tasks),
+    ("devices", devices)
+];
+
+/// A filesystem of files telling the state of the kernel, usually mounted at `/proc`. The text of a file is made
+/// again on every read, from the same statistics the shell commands show
+pub struct ProcFs {
+    root: Arc<ProcRoot>
+}
+
+impl ProcFs {
+    pub fn new() -> Self {
+        ProcFs { root: Arc::new(ProcRoot) }
+    }
+}
+
+struct ProcRoot;
+
+impl Node for ProcRoot {
+    fn kind(&self) -> NodeKind {
+        NodeKind::Directory
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Node>, KernelError> {
+        match FILES.iter().find(|(file, _)| *file == name) {
+            Some(&(_, generate)) => Ok(Arc::new(ProcFile { generate })),
+            None => Err(KernelError::NotFound)
+        }
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, KernelError> {
+        Ok(FILES
+            .iter()
+            .map(|(name, _)| DirEntry { name: String::from(*name), kind: NodeKind::File, size: 0 })
+            .collect())
+    }
+}
+
+/// A file whose text is written by `generate`. Its size is unknown until it's read, so it's always 0
+struct ProcFile {
+    generate: Generator
+}
+
+impl Node for ProcFile {
+    fn kind(&self) -> NodeKind {
+        NodeKind::File
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, KernelError> {
+        let mut text = String::new();
+        (self.generate)(&mut text).map_err(|_| KernelError::OutOfMemory)?;
+
+        let start = offset.min(text.len() as u64) as usize;
+        let count = buffer.len().min(text.len() - start);
+
+        buffer[..count].copy_from_slice(&text.as_bytes()[start..start + count]);
+        return Ok(count);
+    }
+}
+
+fn meminfo(out: &mut String) -> fmt::Result {
+    let free_frames = crate::memory::free_frames();
+
+    writeln!(out, "HeapTotal: {:>8} KiB", crate::memory::HEAP_SIZE / 1024)?;
+    writeln!(out, "HeapFree:  {:>8} KiB", crate::memory::heap_free() / 1024)?;
+    writeln!(out, "FramesFree:{:>8}", free_frames)?;
+    writeln!(out, "MemFree:   {:>8} KiB", free_frames * 4)?;
+
+    if let Some(cache) = crate::block::cache::stats() {
+        writeln!(out, "Cached:    {:>8} KiB", cache.cached * crate::block::SECTOR_SIZE / 1024)?;
+        writeln!(out, "Dirty:     {:>8} KiB", cache.dirty * crate::block::SECTOR_SIZE / 1024)?;
+    }
+
+    Ok(())
+}
+
+fn interrupts(out: &mut String) -> fmt::Result {
+    use crate::interrupts::interrupt_manager;
+
+    for (irq, count) in interrupt_manager::irq_counts().into_iter().enumerate() {
+        // The lines the kernel handles itself have their name, the others tell how many drivers share them
+        let name = match irq {
+            0 => String::from("timer"),
+            1 => String::from("keyboard"),
+            2 => String::from("cascade"),
+            14 => String::from("ata0"),
+            15 => String::from("ata1"),
+            irq => match interrupt_manager::shared_handler_count(irq as u8) {
+                0 => continue,
+                handlers => alloc::format!("shared ({} handlers)", handlers)
+            }
+        };
+
+        writeln!(out, "{:>3}: {:>10} {}", irq, count, name)?;
+    }
+
+    writeln!(out, "LOC: {:>10} local APIC timer", interrupt_manager::local_timer_count())
+}
+
+fn uptime(out: &mut String) -> fmt::Result {
+    let uptime_ms = crate::time::uptime_ms();
+    let idle_ms = crate::sched::idle_ticks() * 1000 / crate::time::TICKS_PER_SECOND;
+
+    writeln!(out, "{}.{:03} {}.{:03}", uptime_ms / 1000, uptime_ms % 1000, idle_ms / 1000, idle_ms % 1000)
+}
+
+fn tasks(out: &mut String) -> fmt::Result {
+    writeln!(out, "{:>4} {:>4} {:<16} {:>8} {:>8} {:>8}", "TID", "PID", "NAME", "RUN", "WAIT", "SWITCHES")?;
+
+    for report in crate::sched::stats() {
+        let process = match report.process {
+            Some(process) => alloc::format!("{}", process.as_u64()),
+            None => String::from("-")
+        };
+
+        writeln!(
+            out, "{:>4} {:>4} {:<16} {:>8} {:>8} {:>8}",
+            report.id.as_u64(), process, report.name, report.run_ticks, report.wait_ticks, report.switches
+        )?;
+    }
+
+    Ok(())
+}
+
+fn devices(out: &mut String) -> fmt::Result {
+    crate::drivers::registry::write_tree(out)?;
+
+    writeln!(out)?;
+
+    for device in crate::block::devices() {
+        let size = device.sector_count() * device.sector_size() as u64;
+        writeln!(out, "{:<8} {:>10} KiB", device.name(), size / 1024)?;
+    }
+
+    Ok(())
+}
+
+impl FileSystem for ProcFs {
+    fn name(&self) -> &str {
+        "procfs"
+    }
+
+    fn root(&self) -> Arc<dyn Node> {
+        self.root.clone()
+    }
+}
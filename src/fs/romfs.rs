@@ -0,0 +1,141 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::utils::error::KernelError;
+use super::{DirEntry, FileSystem, Metadata, Node, NodeKind};
+
+/// A file or a directory of the `romfs` directory of the source tree, the table of all of them ([`ENTRIES`]) is
+/// generated by `build.rs`. The parents of an entry always come before it
+struct RomEntry {
+    /// The path of the entry in the romfs, starting with `/`
+    path: &'static str,
+    mode: u16,
+    /// The content of a file, [`None`] for a directory
+    content: Option<&'static [u8]>
+}
+
+include!(concat!(env!("OUT_DIR"), "/romfs.rs"));
+
+/// The files built in the kernel, as a filesystem that's only read. It's there even without an initrd or a disk,
+/// so it holds what the kernel can't do without (like the first program to run)
+pub struct RomFs {
+    root: Arc<RomNode>
+}
+
+struct RomNode {
+    content: Content,
+    mode: u16
+}
+
+enum Content {
+    File(&'static [u8]),
+    Directory(BTreeMap<&'static str, Arc<RomNode>>)
+}
+
+impl RomNode {
+    /// Adds `node` at the components of `path` under this directory, the directories on the way are there already
+    fn insert(&mut self, path: &[&'static str], node: RomNode) -> Result<(), KernelError> {
+        let entries = match &mut self.content {
+            Content::Directory(entries) => entries,
+            Content::File(_) => return Err(KernelError::NotDirectory)
+        };
+
+        // The tree is only shared once it's complete, so every node of it has a single owner while it's made
+        match path {
+            [] => Ok(()),
+            [name] => {
+                entries.insert(name, Arc::new(node));
+                Ok(())
+            },
+            [name, ..] => {
+                let next = entries.get_mut(name).ok_or(KernelError::NotFound)?;
+                Arc::get_mut(next).ok_or(KernelError::Busy)?.insert(&path[1..], node)
+            }
+        }
+    }
+}
+
+impl RomFs {
+    /// Makes the tree of the files built in the kernel
+    pub fn new() -> Result<Self, KernelError> {
+        let mut root = RomNode {
+            content: Content::Directory(BTreeMap::new()),
+            mode: 0o555
+        };
+
+        for entry in ENTRIES {
+            let path: Vec<&'static str> = entry.path.split('/').filter(|component| !component.is_empty()).collect();
+
+            let content = match entry.content {
+                Some(data) => Content::File(data),
+                None => Content::Directory(BTreeMap::new())
+            };
+
+            root.insert(&path, RomNode { content, mode: entry.mode })?;
+        }
+
+        Ok(RomFs { root: Arc::new(root) })
+    }
+}
+
+impl Node for RomNode {
+    fn kind(&self) -> NodeKind {
+        match self.content {
+            Content::File(_) => NodeKind::File,
+            Content::Directory(_) => NodeKind::Directory
+        }
+    }
+
+    fn size(&self) -> u64 {
+        match self.content {
+            Content::File(data) => data.len() as u64,
+            Content::Directory(_) => 0
+        }
+    }
+
+    fn metadata(&self) -> Result<Metadata, KernelError> {
+        Ok(Metadata { mode: self.mode, ..Metadata::new(self.kind(), self.size()) })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Node>, KernelError> {
+        match &self.content {
+            Content::Directory(entries) => Ok(entries.get(name).ok_or(KernelError::NotFound)?.clone()),
+            Content::File(_) => Err(KernelError::NotDirectory)
+        }
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, KernelError> {
+        let data = match self.content {
+            Content::File(data) => data,
+            Content::Directory(_) => return Err(self.refusal())
+        };
+
+        let start = offset.min(data.len() as u64) as usize;
+        let count = buffer.len().min(data.len() - start);
+
+        buffer[..count].copy_from_slice(&data[start..start + count]);
+
+        return Ok(count);
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, KernelError> {
+        match &self.content {
+            Content::Directory(entries) => Ok(entries
+                .iter()
+                .map(|(name, node)| DirEntry { name: String::from(*name), kind: node.kind(), size: node.size() })
+                .collect()),
+            Content::File(_) => Err(KernelError::NotDirectory)
+        }
+    }
+}
+
+impl FileSystem for RomFs {
+    fn name(&self) -> &str {
+        "romfs"
+    }
+
+    fn root(&self) -> Arc<dyn Node> {
+        self.root.clone()
+    }
+}
@@ -0,0 +1,337 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU16, AtomicU32, AtomicU64, Ordering};
+use crate::utils::error::KernelError;
+use crate::utils::Mutex;
+use super::{DirEntry, FileSystem, Metadata, Node, NodeKind};
+
+/// A filesystem whose files and directories are only on the heap, so everything in it is lost once the machine
+/// stops
+pub struct TmpFs {
+    root: Arc<TmpNode>
+}
+
+impl TmpFs {
+    /// The root can be written by everyone, like the `/tmp` of Unix
+    pub fn new() -> Self {
+        let root = TmpNode::new(NodeKind::Directory);
+        root.mode.store(0o777, Ordering::Relaxed);
+
+        TmpFs { root: Arc::new(root) }
+    }
+}
+
+struct TmpNode {
+    content: Content,
+    /// When the node was created, last read and last written, in seconds since the Unix epoch
+    created: u64,
+    accessed: AtomicU64,
+    modified: AtomicU64,
+    /// The permissions and the owner, the ones of the process that created the node at first
+    mode: AtomicU16,
+    uid: AtomicU32,
+    gid: AtomicU32
+}
+
+enum Content {
+    File(Mutex<Vec<u8>>),
+    Directory(Mutex<BTreeMap<String, Arc<TmpNode>>>),
+    /// The path a symbolic link points to, it never changes
+    Symlink(String)
+}
+
+impl TmpNode {
+    fn new(kind: NodeKind) -> Self {
+        match kind {
+            NodeKind::Directory => TmpNode::with_content(Content::Directory(Mutex::new(BTreeMap::new()))),
+            _ => TmpNode::with_content(Content::File(Mutex::new(Vec::new())))
+        }
+    }
+
+    fn with_content(content: Content) -> Self {
+        let now = crate::time::unix_time();
+        let credentials = crate::process::credentials::current();
+
+        let node = TmpNode {
+            content,
+            created: now,
+            accessed: AtomicU64::new(now),
+            modified: AtomicU64::new(now),
+            mode: AtomicU16::new(0),
+            uid: AtomicU32::new(credentials.uid),
+            gid: AtomicU32::new(credentials.gid)
+        };
+
+        // The permissions start as the usual ones of the kind of the node
+        node.mode.store(Metadata::new(node.kind(), 0).mode, Ordering::Relaxed);
+        return node;
+    }
+
+    fn entries(&self) -> Result<&Mutex<BTreeMap<String, Arc<TmpNode>>>, KernelError> {
+        match &self.content {
+            Content::Directory(entries) => Ok(entries),
+            _ => Err(KernelError::NotDirectory)
+        }
+    }
+
+    fn data(&self) -> Result<&Mutex<Vec<u8>>, KernelError> {
+        match &self.content {
+            Content::File(data) => Ok(data),
+            _ => Err(self.refusal())
+        }
+    }
+
+    /// Records that the content of the node was read now
+    fn touch_accessed(&self) {
+        self.accessed.store(crate::time::unix_time(), Ordering::Relaxed);
+    }
+
+    /// Records that the content of the node (the entries of a directory) was written now
+    fn touch_modified(&self) {
+        self.modified.store(crate::time::unix_time(), Ordering::Relaxed);
+    }
+
+    /// Adds `node` to this directory as `name`
+    fn add(&self, name: &str, node: Arc<TmpNode>) -> Result<(), KernelError> {
+        if name.is_empty() || name.contains('/') {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let mut entries = self.entries()?.lock();
+
+        if entries.contains_key(name) {
+            return Err(KernelError::AlreadyExists);
+        }
+
+        entries.insert(String::from(name), node);
+        self.touch_modified();
+
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        match &self.content {
+            Content::Directory(entries) => entries.lock().is_empty(),
+            _ => true
+        }
+    }
+}
+
+/// Makes `data` `size` bytes long, zeroing what's added
+///
+/// ## Errors
+///
+/// Returns [`KernelError::OutOfMemory`] if the heap can't hold the bytes added, the file is left as it was
+fn resize(data: &mut Vec<u8>, size: usize) -> Result<(), KernelError> {
+    data.try_reserve(size.saturating_sub(data.len())).map_err(|_| KernelError::OutOfMemory)?;
+    data.resize(size, 0);
+
+    Ok(())
+}
+
+/// Checks that `node` can replace `replaced` when it's renamed over it, a directory only replaces an empty one and
+/// the other nodes anything but a directory
+fn check_replace(node: &TmpNode, replaced: &TmpNode) -> Result<(), KernelError> {
+    match (&node.content, &replaced.content) {
+        (Content::Directory(_), Content::Directory(_)) if replaced.is_empty() => Ok(()),
+        (Content::Directory(_), Content::Directory(_)) => Err(KernelError::NotEmpty),
+        (Content::Directory(_), _) => Err(KernelError::NotDirectory),
+        (_, Content::Directory(_)) => Err(KernelError::IsDirectory),
+        _ => Ok(())
+    }
+}
+
+impl Node for TmpNode {
+    fn kind(&self) -> NodeKind {
+        match self.content {
+            Content::File(_) => NodeKind::File,
+            Content::Directory(_) => NodeKind::Directory,
+            Content::Symlink(_) => NodeKind::Symlink
+        }
+    }
+
+    fn size(&self) -> u64 {
+        match &self.content {
+            Content::File(data) => data.lock().len() as u64,
+            Content::Directory(_) => 0,
+            Content::Symlink(target) => target.len() as u64
+        }
+    }
+
+    fn metadata(&self) -> Result<Metadata, KernelError> {
+        Ok(Metadata {
+            created: self.created,
+            accessed: self.accessed.load(Ordering::Relaxed),
+            modified: self.modified.load(Ordering::Relaxed),
+            mode: self.mode.load(Ordering::Relaxed),
+            uid: self.uid.load(Ordering::Relaxed),
+            gid: self.gid.load(Ordering::Relaxed),
+            ..Metadata::new(self.kind(), self.size())
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Node>, KernelError> {
+        Ok(self.entries()?.lock().get(name).ok_or(KernelError::NotFound)?.clone())
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, KernelError> {
+        let data = self.data()?.lock();
+
+        let start = (offset.min(data.len() as u64)) as usize;
+        let count = buffer.len().min(data.len() - start);
+
+        buffer[..count].copy_from_slice(&data[start..start + count]);
+        self.touch_accessed();
+
+        return Ok(count);
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize, KernelError> {
+        let mut data = self.data()?.lock();
+
+        let start = usize::try_from(offset).map_err(|_| KernelError::InvalidArgument)?;
+        let end = start.checked_add(buffer.len()).ok_or(KernelError::InvalidArgument)?;
+
+        // Writing past the end grows the file, with zeros between its old end and the start of the write
+        if end > data.len() {
+            resize(&mut data, end)?;
+        }
+
+        data[start..end].copy_from_slice(buffer);
+        self.touch_modified();
+
+        return Ok(buffer.len());
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, KernelError> {
+        let entries = self.entries()?.lock();
+        return Ok(entries
+            .iter()
+            .map(|(name, node)| DirEntry { name: name.clone(), kind: node.kind(), size: node.size() })
+            .collect());
+    }
+
+    fn create(&self, name: &str, kind: NodeKind) -> Result<Arc<dyn Node>, KernelError> {
+        if kind != NodeKind::File && kind != NodeKind::Directory {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let node = Arc::new(TmpNode::new(kind));
+        self.add(name, node.clone())?;
+
+        return Ok(node);
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<(), KernelError> {
+        self.add(name, Arc::new(TmpNode::with_content(Content::Symlink(String::from(target)))))
+    }
+
+    fn read_link(&self) -> Result<String, KernelError> {
+        match &self.content {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(KernelError::InvalidArgument)
+        }
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), KernelError> {
+        let mut entries = self.entries()?.lock();
+
+        if !entries.get(name).ok_or(KernelError::NotFound)?.is_empty() {
+            return Err(KernelError::NotEmpty);
+        }
+
+        // The node stays around while it's still open, its content is freed with the last reference
+        entries.remove(name);
+        self.touch_modified();
+
+        Ok(())
+    }
+
+    fn rename(&self, name: &str, directory: &dyn Node, new_name: &str) -> Result<(), KernelError> {
+        let destination = directory.as_any().downcast_ref::<TmpNode>().ok_or(KernelError::CrossDevice)?;
+
+        if new_name.is_empty() || new_name.contains('/') {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        // Both directories are locked for the whole move, always in the same order so two moves between them the
+        // other way around don't lock each other out
+        let (source_entries, target_entries) = (self.entries()?, destination.entries()?);
+
+        if core::ptr::eq(source_entries, target_entries) {
+            let mut entries = source_entries.lock();
+            let node = entries.get(name).ok_or(KernelError::NotFound)?.clone();
+
+            if let Some(replaced) = entries.get(new_name) {
+                check_replace(&node, replaced)?;
+            }
+
+            entries.remove(name);
+            entries.insert(String::from(new_name), node);
+            self.touch_modified();
+
+            return Ok(());
+        }
+
+        let (mut source, mut target) = if (source_entries as *const _) < (target_entries as *const _) {
+            let source = source_entries.lock();
+            (source, target_entries.lock())
+        } else {
+            let target = target_entries.lock();
+            (source_entries.lock(), target)
+        };
+
+        let node = source.get(name).ok_or(KernelError::NotFound)?.clone();
+
+        if let Some(replaced) = target.get(new_name) {
+            // The directory the node is moved from is replaced by it, it's locked already and can't be empty anyway
+            if core::ptr::eq(replaced.as_ref(), self) {
+                return Err(KernelError::NotEmpty);
+            }
+
+            check_replace(&node, replaced)?;
+        }
+
+        source.remove(name);
+        target.insert(String::from(new_name), node);
+
+        self.touch_modified();
+        destination.touch_modified();
+
+        Ok(())
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), KernelError> {
+        let mut data = self.data()?.lock();
+
+        resize(&mut data, usize::try_from(size).map_err(|_| KernelError::InvalidArgument)?)?;
+        data.shrink_to_fit();
+        self.touch_modified();
+
+        Ok(())
+    }
+
+    fn set_mode(&self, mode: u16) -> Result<(), KernelError> {
+        self.mode.store(mode & 0o7777, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn set_owner(&self, uid: u32, gid: u32) -> Result<(), KernelError> {
+        self.uid.store(uid, Ordering::Relaxed);
+        self.gid.store(gid, Ordering::Relaxed);
+
+        Ok(())
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn name(&self) -> &str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Node> {
+        self.root.clone()
+    }
+}
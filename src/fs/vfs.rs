@@ -0,0 +1,594 @@
+use core::future::Future;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::process::credentials::{self, Credentials};
+use crate::utils::error::KernelError;
+use crate::utils::Mutex;
+use super::aio;
+use super::{ACCESS_EXECUTE, ACCESS_WRITE, Dir, DirEntry, FileHandle, FileSystem, Metadata, Node, NodeKind, OpenNode};
+
+/// The filesystems mounted so far, a path goes to the one mounted at the longest prefix of it
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+
+struct Mount {
+    /// The components of the path the filesystem is mounted at, none for `/`
+    path: Vec<String>,
+    fs: Arc<dyn FileSystem>
+}
+
+/// Splits an absolute path into its components, the empty ones (of `//` or of a `/` at the end) are left out
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if the path doesn't start with `/`
+fn components(path: &str) -> Result<impl Iterator<Item = &str>, KernelError> {
+    let path = path.strip_prefix('/').ok_or(KernelError::InvalidArgument)?;
+    return Ok(path.split('/').filter(|component| !component.is_empty()));
+}
+
+/// Mounts `fs` at `path`, everything under it then goes to the filesystem. The path doesn't have to exist in the
+/// filesystem it's in, and what was under it there is hidden while `fs` is mounted. Filesystems can be mounted
+/// under other ones, down to any depth
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if the path isn't absolute, or [`KernelError::Busy`] if there's a
+/// filesystem mounted at it already or if the device of `fs` has one mounted (two of them would overwrite what
+/// the other one writes)
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> Result<(), KernelError> {
+    let path: Vec<String> = components(path)?.map(String::from).collect();
+    let mut mounts = MOUNTS.lock();
+
+    let device_taken = |mount: &Mount| fs.device().is_some() && mount.fs.device() == fs.device();
+
+    if mounts.iter().any(|mount| mount.path == path || device_taken(mount)) {
+        return Err(KernelError::Busy);
+    }
+
+    mounts.push(Mount { path, fs });
+    Ok(())
+}
+
+/// Unmounts the filesystem mounted at `path` and syncs it, what was under the path in the filesystem it's in shows
+/// up again
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if the path isn't absolute or there's no filesystem mounted at it,
+/// [`KernelError::Busy`] if the filesystem is still used: another one is mounted under it, a node of it is open
+/// (see [`OpenNode::in_filesystem`]) or mapped in memory (see [`crate::process::mmap`]) or it has the working
+/// directory of a process. If it can't be synced it stays mounted, and the error of syncing it is returned
+pub fn umount(path: &str) -> Result<(), KernelError> {
+    let path: Vec<String> = components(path)?.map(String::from).collect();
+
+    let in_use = crate::process::all().iter().any(|process| {
+        let directory = process.working_directory();
+        components(&directory).map_or(false, |components| components.take(path.len()).eq(path.iter()))
+    });
+
+    let mut mounts = MOUNTS.lock();
+    let index = mounts.iter().position(|mount| mount.path == path).ok_or(KernelError::InvalidArgument)?;
+
+    // The table holds the only reference to a filesystem nobody uses, a walk going on holds one too
+    let nested = mounts.iter().any(|mount| mount.path.len() > path.len() && mount.path.starts_with(&path));
+
+    if in_use || nested || Arc::strong_count(&mounts[index].fs) > 1 {
+        return Err(KernelError::Busy);
+    }
+
+    let mount = mounts.remove(index);
+    drop(mounts);
+
+    // Syncing can block, so it's done once the filesystem is out of the table. If it fails the filesystem goes back
+    // in, nothing written to it is lost
+    if let Err(error) = mount.fs.sync() {
+        MOUNTS.lock().push(mount);
+        return Err(error);
+    }
+
+    Ok(())
+}
+
+/// A filesystem in the mount table, see [`mounts`]
+pub struct MountInfo {
+    /// The absolute path it's mounted at
+    pub path: String,
+    /// The kind of filesystem and the device it's on, see [`FileSystem::name`] and [`FileSystem::device`]
+    pub kind: String,
+    pub device: Option<String>
+}
+
+/// Returns the filesystems mounted, sorted by their path so a filesystem comes before the ones mounted under it
+pub fn mounts() -> Vec<MountInfo> {
+    let mut mounts: Vec<MountInfo> = MOUNTS
+        .lock()
+        .iter()
+        .map(|mount| MountInfo {
+            path: format!("/{}", mount.path.join("/")),
+            kind: String::from(mount.fs.name()),
+            device: mount.fs.device().map(String::from)
+        })
+        .collect();
+
+    mounts.sort_by(|a, b| a.path.cmp(&b.path));
+    return mounts;
+}
+
+/// The most symbolic links followed while resolving a single path, past that they're taken as a loop
+const MAX_SYMLINKS: usize = 40;
+
+/// A node with the filesystem it's in, see [`resolve_with_fs`]
+pub type FsNode = (Arc<dyn FileSystem>, Arc<dyn Node>);
+
+/// A node reached by [`walk`] with the filesystem it's in, [`None`] for the directories that aren't in any
+/// filesystem and only lead to mount points (like `/mnt` when nothing is mounted at `/`)
+type Step = Option<FsNode>;
+
+/// Returns the root of the filesystem mounted at exactly the components of `path`, if there's one
+fn mounted_at(path: &[String]) -> Step {
+    let fs = MOUNTS.lock().iter().find(|mount| mount.path == path).map(|mount| mount.fs.clone())?;
+    let root = fs.root();
+
+    return Some((fs, root));
+}
+
+/// Returns whatever there's a filesystem mounted under the components of `path`
+fn leads_to_mount(path: &[String]) -> bool {
+    MOUNTS.lock().iter().any(|mount| mount.path.len() > path.len() && mount.path.starts_with(path))
+}
+
+/// Returns `path` as an absolute path, a relative one is taken from the working directory of the current process
+/// (or from `/` outside of a process)
+fn absolute(path: &str) -> Result<String, KernelError> {
+    if path.is_empty() {
+        return Err(KernelError::NotFound);
+    }
+
+    if path.starts_with('/') {
+        return Ok(String::from(path));
+    }
+
+    let mut absolute = crate::process::current()
+        .map(|process| process.working_directory())
+        .unwrap_or_else(|| String::from("/"));
+
+    absolute.push('/');
+    absolute.push_str(path);
+
+    return Ok(absolute);
+}
+
+/// Goes through every component of `path` from the root, or from the working directory for a relative path.
+/// `.` stays in the same directory and `..` goes to the one above it (the root is above itself), after a symbolic
+/// link that's the one above its target. The symbolic links on the way are followed, the last component too if
+/// `follow` is set or the path ends with `/`, which also requires a directory.
+///
+/// Returns the components of the path the node was reached at, without any `.`, `..` or symbolic link, together
+/// with the node and the filesystem it's in
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NotFound`] if there's no such node, [`KernelError::NotDirectory`] if one of the
+/// components before the last isn't a directory and [`KernelError::SymlinkLoop`] after following
+/// [`MAX_SYMLINKS`] symbolic links
+fn walk(path: &str, follow: bool) -> Result<(Vec<String>, FsNode), KernelError> {
+    let (names, step) = walk_to(path, follow)?;
+    return Ok((names, step.ok_or(KernelError::NotFound)?));
+}
+
+/// Same as [`walk`], the node can also be one of the directories that only lead to mount points
+fn walk_to(path: &str, follow: bool) -> Result<(Vec<String>, Step), KernelError> {
+    let path = absolute(path)?;
+    let directory_only = path.ends_with('/');
+
+    // The components left to go through, the next one is at the end
+    let mut pending: Vec<String> = components(&path)?.map(String::from).collect();
+    pending.reverse();
+    let mut names: Vec<String> = Vec::new();
+    let mut steps: Vec<Step> = alloc::vec![mounted_at(&names)];
+    let mut links = 0;
+    let credentials = credentials::current();
+
+    while let Some(component) = pending.pop() {
+        if let Some(Some((_, node))) = steps.last() {
+            if node.kind() != NodeKind::Directory {
+                return Err(KernelError::NotDirectory);
+            }
+        }
+
+        match component.as_str() {
+            "." => continue,
+            ".." => {
+                // The root has no entry in the steps to take out
+                if names.pop().is_some() {
+                    steps.pop();
+                }
+
+                continue;
+            },
+            _ => names.push(component)
+        }
+
+        // A mount point hides whatever is at its path in the filesystem it's in, and the directories on the way to
+        // one don't have to be there
+        let step = match (mounted_at(&names), steps.last()) {
+            (Some(step), _) => Some(step),
+            (None, Some(Some((fs, directory)))) => match search(directory, &credentials, names.last().unwrap()) {
+                Ok(node) => Some((fs.clone(), node)),
+                Err(KernelError::NotFound) if leads_to_mount(&names) => None,
+                Err(error) => return Err(error)
+            },
+            _ if leads_to_mount(&names) => None,
+            _ => return Err(KernelError::NotFound)
+        };
+
+        let link = match &step {
+            Some((_, node)) if node.kind() == NodeKind::Symlink => Some(node.clone()),
+            _ => None
+        };
+
+        match link {
+            Some(link) if follow || directory_only || !pending.is_empty() => {
+                links += 1;
+
+                if links > MAX_SYMLINKS {
+                    return Err(KernelError::SymlinkLoop);
+                }
+
+                let target = link.read_link()?;
+
+                if target.is_empty() {
+                    return Err(KernelError::NotFound);
+                }
+
+                // The target replaces the link, from the root if it's absolute or from the directory of the link
+                names.pop();
+
+                if target.starts_with('/') {
+                    names.clear();
+                    steps.truncate(1);
+                }
+
+                pending.extend(target.split('/').rev().filter(|component| !component.is_empty()).map(String::from));
+            },
+            _ => steps.push(step)
+        }
+    }
+
+    let step = steps.pop().flatten();
+
+    match &step {
+        Some((_, node)) if directory_only && node.kind() != NodeKind::Directory => Err(KernelError::NotDirectory),
+        _ => Ok((names, step))
+    }
+}
+
+/// Looks for `name` in `directory` for `credentials`, who must be able to execute the directory. Root can execute
+/// any directory, its metadata isn't even read
+fn search(directory: &Arc<dyn Node>, credentials: &Credentials, name: &str) -> Result<Arc<dyn Node>, KernelError> {
+    if !credentials.is_root() {
+        directory.metadata()?.check_access(credentials, ACCESS_EXECUTE)?;
+    }
+
+    return directory.lookup(name);
+}
+
+/// Checks that the current process can do `access` (see [`Metadata::check_access`]) with `node`
+///
+/// ## Errors
+///
+/// Returns [`KernelError::PermissionDenied`] if it can't, or the error of reading the metadata of the node
+pub fn check_access(node: &dyn Node, access: u16) -> Result<(), KernelError> {
+    node.metadata()?.check_access(&credentials::current(), access)
+}
+
+/// Returns the node at `path`, following the symbolic links. A relative path starts from the working directory of
+/// the current process, see [`walk`] for the details
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NotFound`] if there's no such node (or no filesystem to look for it in),
+/// [`KernelError::NotDirectory`] if one of the components before the last isn't a directory,
+/// [`KernelError::PermissionDenied`] if the process can't execute one of the directories on the way and
+/// [`KernelError::SymlinkLoop`] if the symbolic links point to each other
+pub fn resolve(path: &str) -> Result<Arc<dyn Node>, KernelError> {
+    walk(path, true).map(|(_, (_, node))| node)
+}
+
+/// Same as [`resolve`], also returns the filesystem the node is in. A handle of the node holds it so it isn't
+/// unmounted while the node is open, see [`OpenNode::in_filesystem`]
+pub fn resolve_with_fs(path: &str) -> Result<FsNode, KernelError> {
+    walk(path, true).map(|(_, fs_node)| fs_node)
+}
+
+/// Returns the absolute path of the directory at `path` without any `.`, `..` or symbolic link, like the working
+/// directory of a process is kept
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NotDirectory`] if the node isn't a directory, or the errors of [`resolve`]
+pub fn canonical_directory(path: &str) -> Result<String, KernelError> {
+    let (names, (_, node)) = walk(path, true)?;
+
+    if node.kind() != NodeKind::Directory {
+        return Err(KernelError::NotDirectory);
+    }
+
+    let mut canonical = String::new();
+
+    for name in &names {
+        canonical.push('/');
+        canonical.push_str(name);
+    }
+
+    if canonical.is_empty() {
+        canonical.push('/');
+    }
+
+    return Ok(canonical);
+}
+
+/// Opens the node at `path` for reading and writing, or for reading its entries if it's a directory (see
+/// [`open_dir`]). See [`resolve`] for the errors
+pub fn open(path: &str) -> Result<Arc<dyn FileHandle>, KernelError> {
+    match walk_to(path, true)? {
+        (_, Some((fs, node))) if node.kind() != NodeKind::Directory => {
+            Ok(Arc::new(OpenNode::new(node).in_filesystem(fs)))
+        },
+        (names, step) => Ok(Arc::new(list(&names, step)?))
+    }
+}
+
+/// Returns the metadata of the node at `path`, following the symbolic links (see [`Node::metadata`]). See
+/// [`resolve`] for the errors
+pub fn metadata(path: &str) -> Result<Metadata, KernelError> {
+    match walk_to(path, true)? {
+        (_, Some((_, node))) => node.metadata(),
+        (_, None) => Ok(Metadata::new(NodeKind::Directory, 0))
+    }
+}
+
+/// Opens the directory at `path` to go through its entries, with the filesystems mounted in it
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NotDirectory`] if the node isn't a directory, or the errors of [`resolve`]
+pub fn open_dir(path: &str) -> Result<Dir, KernelError> {
+    let (names, step) = walk_to(path, true)?;
+    return list(&names, step);
+}
+
+/// Reads the entries of the directory reached by [`walk_to`] at `path`. The mount points in it are directories,
+/// whatever the filesystem of the directory has with the same name
+fn list(path: &[String], step: Step) -> Result<Dir, KernelError> {
+    let mut entries = match &step {
+        Some((_, node)) => node.readdir()?,
+        None => Vec::new()
+    };
+
+    for mount in MOUNTS.lock().iter().filter(|mount| mount.path.len() > path.len() && mount.path.starts_with(path)) {
+        let name = &mount.path[path.len()];
+
+        // The deeper mount points only need the directory on the way to them
+        if mount.path.len() > path.len() + 1 && entries.iter().any(|entry| &entry.name == name) {
+            continue;
+        }
+
+        entries.retain(|entry| &entry.name != name);
+        entries.push(DirEntry { name: name.clone(), kind: NodeKind::Directory, size: 0 });
+    }
+
+    return Ok(Dir::new(step, entries));
+}
+
+/// Reads the file at `path` starting at `offset` into `buffer`, returning how many bytes were read
+#[allow(dead_code)]
+pub fn read(path: &str, offset: u64, buffer: &mut [u8]) -> Result<usize, KernelError> {
+    resolve(path)?.read_at(offset, buffer)
+}
+
+/// Writes `buffer` to the file at `path` starting at `offset`, returning how many bytes were written
+#[allow(dead_code)]
+pub fn write(path: &str, offset: u64, buffer: &[u8]) -> Result<usize, KernelError> {
+    resolve(path)?.write_at(offset, buffer)
+}
+
+/// Same as [`read`] for the async tasks, the file is read from the request queue of its device (see [`aio::read`])
+/// so the executor isn't held up until the device is done. Completes with the `length` bytes (at most) read from
+/// `offset`. A relative path is made absolute right away, the worker isn't in the process
+///
+/// ## Errors
+///
+/// Returns the errors of [`aio::read`]
+#[allow(dead_code)]
+pub fn read_async(path: &str, offset: u64, length: usize) -> impl Future<Output = Result<Vec<u8>, KernelError>> {
+    let path = absolute(path);
+
+    async move { aio::read(path?, offset, length).await }
+}
+
+/// Same as [`write`] for the async tasks, the file is written by the worker of [`aio`] (the filesystems decide where
+/// the bytes go, and may keep them in a journal first). Completes with how many bytes of `data` were written
+#[allow(dead_code)]
+pub fn write_async(path: &str, offset: u64, data: Vec<u8>) -> aio::IoFuture<Result<usize, KernelError>> {
+    let path = absolute(path);
+    aio::submit(move || write(&path?, offset, &data))
+}
+
+/// The directory a path is in, with the last component of the path
+struct Parent {
+    /// The components of the path of the directory, see [`walk`]
+    path: Vec<String>,
+    fs: Arc<dyn FileSystem>,
+    directory: Arc<dyn Node>,
+    name: String
+}
+
+impl Parent {
+    /// Checks that the current process can add and remove the entries of the directory, it has to write and execute it
+    fn check_writable(&self) -> Result<(), KernelError> {
+        check_access(self.directory.as_ref(), ACCESS_WRITE | ACCESS_EXECUTE)
+    }
+}
+
+/// Resolves the directory `path` is in, following the symbolic links on the way but not the last component. The
+/// ones that add or remove an entry check the directory with [`Parent::check_writable`]
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if the last component is `.` or `..` (or there's none, for `/`), or the
+/// errors of [`resolve`] for the directory
+fn resolve_parent(path: &str) -> Result<Parent, KernelError> {
+    let path = absolute(path)?;
+    let (parent, name) = path.trim_end_matches('/').rsplit_once('/').ok_or(KernelError::InvalidArgument)?;
+
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let (names, (fs, directory)) = walk(if parent.is_empty() { "/" } else { parent }, true)?;
+
+    if directory.kind() != NodeKind::Directory {
+        return Err(KernelError::NotDirectory);
+    }
+
+    return Ok(Parent { path: names, fs, directory, name: String::from(name) });
+}
+
+/// Creates an empty file or directory at `path`, the directory it's in must exist already
+///
+/// ## Errors
+///
+/// Returns [`KernelError::AlreadyExists`] if there's a node at `path` already, [`KernelError::PermissionDenied`] if
+/// the process can't write the directory it's in, or the errors of [`resolve`] for that directory
+#[allow(dead_code)]
+pub fn create(path: &str, kind: NodeKind) -> Result<Arc<dyn Node>, KernelError> {
+    create_with_fs(path, kind).map(|(_, node)| node)
+}
+
+/// Same as [`create`], also returns the filesystem the node is in like [`resolve_with_fs`]
+pub fn create_with_fs(path: &str, kind: NodeKind) -> Result<FsNode, KernelError> {
+    let parent = resolve_parent(path)?;
+    parent.check_writable()?;
+
+    let node = parent.directory.create(&parent.name, kind)?;
+    return Ok((parent.fs, node));
+}
+
+/// Creates a symbolic link at `path` that points to `target`, which doesn't have to exist
+///
+/// ## Errors
+///
+/// Returns [`KernelError::AlreadyExists`] if there's a node at `path` already, [`KernelError::PermissionDenied`] if
+/// the process can't write the directory it's in, or the errors of [`resolve`] for that directory
+#[allow(dead_code)]
+pub fn symlink(target: &str, path: &str) -> Result<(), KernelError> {
+    let parent = resolve_parent(path)?;
+    parent.check_writable()?;
+
+    return parent.directory.symlink(&parent.name, target);
+}
+
+/// Returns the path the symbolic link at `path` points to, the link itself isn't followed
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if the node at `path` isn't a symbolic link
+#[allow(dead_code)]
+pub fn read_link(path: &str) -> Result<String, KernelError> {
+    let parent = resolve_parent(path)?;
+    return parent.directory.lookup(&parent.name)?.read_link();
+}
+
+/// Removes the file, the symbolic link or the empty directory at `path`, the process must be able to write the
+/// directory it's in
+#[allow(dead_code)]
+pub fn remove(path: &str) -> Result<(), KernelError> {
+    let parent = resolve_parent(path)?;
+    parent.check_writable()?;
+
+    return parent.directory.unlink(&parent.name);
+}
+
+/// Moves the file or the directory at `from` to `to`, replacing what's there already, see [`Node::rename`]
+///
+/// ## Errors
+///
+/// Returns [`KernelError::CrossDevice`] if the paths are in different filesystems, [`KernelError::InvalidArgument`]
+/// if a directory would be moved under itself, [`KernelError::PermissionDenied`] if the process can't write both
+/// directories they're in, or the errors of [`resolve`] for those directories
+#[allow(dead_code)]
+pub fn rename(from: &str, to: &str) -> Result<(), KernelError> {
+    let (source, target) = (resolve_parent(from)?, resolve_parent(to)?);
+    source.check_writable()?;
+    target.check_writable()?;
+
+    let mut source_path = source.path.clone();
+    source_path.push(source.name.clone());
+
+    let mut target_path = target.path.clone();
+    target_path.push(target.name.clone());
+
+    if source_path == target_path {
+        return Ok(());
+    }
+
+    if target_path.starts_with(&source_path) {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    // Only the data of the filesystems is compared, the same one can be seen through different vtables
+    if Arc::as_ptr(&source.fs) as *const u8 != Arc::as_ptr(&target.fs) as *const u8 {
+        return Err(KernelError::CrossDevice);
+    }
+
+    return source.directory.rename(&source.name, target.directory.as_ref(), &target.name);
+}
+
+/// Makes the file at `path` `size` bytes long if the process can write it, see [`Node::truncate`]
+#[allow(dead_code)]
+pub fn truncate(path: &str, size: u64) -> Result<(), KernelError> {
+    let node = resolve(path)?;
+    check_access(node.as_ref(), ACCESS_WRITE)?;
+
+    return node.truncate(size);
+}
+
+/// Changes the permissions of the node at `path`, following the symbolic links (see [`Node::set_mode`])
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NotPermitted`] if the process isn't root or the owner of the node, or the errors of
+/// [`resolve`]
+pub fn chmod(path: &str, mode: u16) -> Result<(), KernelError> {
+    let node = resolve(path)?;
+    node.metadata()?.check_owner(&credentials::current())?;
+
+    return node.set_mode(mode);
+}
+
+/// Gives the node at `path` to the user `uid` and the group `gid`, following the symbolic links (see
+/// [`Node::set_owner`])
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NotPermitted`] if the process isn't root, or the errors of [`resolve`]
+pub fn chown(path: &str, uid: u32, gid: u32) -> Result<(), KernelError> {
+    let node = resolve(path)?;
+
+    if !credentials::current().is_root() {
+        return Err(KernelError::NotPermitted);
+    }
+
+    return node.set_owner(uid, gid);
+}
+
+/// Makes sure everything written to every filesystem mounted is on the devices, going on after a failure and
+/// returning the first error
+pub fn sync() -> Result<(), KernelError> {
+    let filesystems: Vec<Arc<dyn FileSystem>> = MOUNTS.lock().iter().map(|mount| mount.fs.clone()).collect();
+
+    return filesystems.iter().map(|fs| fs.sync()).fold(Ok(()), |result, synced| result.and(synced));
+}
@@ -0,0 +1,233 @@
+use x86_64::instructions::port::Port;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::graphics::Framebuffer;
+use crate::pci::PciDevice;
+use crate::utils::error::KernelError;
+use crate::utils::Mutex;
+
+/// The ports of the Bochs Graphics Adapter (called DISPI by Bochs): the index of the register is written to
+/// the first one and the register is then read or written through the second one
+const INDEX_PORT: u16 = 0x1CE;
+const DATA_PORT: u16 = 0x1CF;
+
+/// The registers of the adapter
+const INDEX_ID: u16 = 0x0;
+const INDEX_X_RESOLUTION: u16 = 0x1;
+const INDEX_Y_RESOLUTION: u16 = 0x2;
+const INDEX_BPP: u16 = 0x3;
+const INDEX_ENABLE: u16 = 0x4;
+const INDEX_VIRTUAL_WIDTH: u16 = 0x6;
+const INDEX_X_OFFSET: u16 = 0x8;
+const INDEX_Y_OFFSET: u16 = 0x9;
+/// The size of the video memory in 64 KiB blocks, only QEMU has it
+const INDEX_VIDEO_MEMORY_64K: u16 = 0xA;
+
+/// The versions of the adapter that have a linear framebuffer with 32 bits per pixel and report their limits,
+/// QEMU has the last one
+const ID_MIN: u16 = 0xB0C2;
+const ID_MAX: u16 = 0xB0C5;
+
+/// The bits of the enable register: the graphics mode is on, the framebuffer is linear (instead of banked)
+/// and the resolution registers read as the largest mode
+const ENABLE_ENABLED: u16 = 1 << 0;
+const ENABLE_GET_CAPABILITIES: u16 = 1 << 1;
+const ENABLE_LINEAR_FRAMEBUFFER: u16 = 1 << 6;
+
+/// The only depth the kernel uses, so a pixel is a `u32`
+const BPP: u16 = 32;
+
+/// The PCI IDs of the adapter: QEMU (the standard VGA and `bochs-display`) and VirtualBox
+const PCI_IDS: [(u16, u16); 2] = [(0x1234, 0x1111), (0x80EE, 0xBEEF)];
+
+const PCI_COMMAND: u16 = 0x04;
+const PCI_COMMAND_MEMORY: u32 = 1 << 1;
+
+/// The BAR with the framebuffer, and the one with the registers on QEMU (which `bochs-display` needs since it
+/// has no ports)
+const PCI_BAR0: u16 = 0x10;
+const PCI_BAR2: u16 = 0x18;
+
+/// Where the registers are in the BAR of QEMU, 16 bits each in the order of their indexes
+const MMIO_REGISTERS_OFFSET: u64 = 0x500;
+const MMIO_REGISTERS_SIZE: usize = 0x20;
+
+/// Where the framebuffer is when the adapter isn't on the PCI bus, the address Bochs always used
+const ISA_FRAMEBUFFER: u64 = 0xE000_0000;
+
+/// The video memory assumed when the adapter doesn't tell its size, the least any version has
+const FALLBACK_MEMORY_SIZE: usize = 4 * 1024 * 1024;
+
+/// The adapter found by [`init`]
+static ADAPTER: Mutex<Option<Adapter>> = Mutex::new(None);
+
+/// How the registers of the adapter are reached
+#[derive(Debug, Copy, Clone)]
+enum Registers {
+    Ports,
+    Memory(VirtAddr)
+}
+
+impl Registers {
+    fn read(&self, index: u16) -> u16 {
+        match self {
+            Registers::Ports => unsafe {
+                Port::<u16>::new(INDEX_PORT).write(index);
+                Port::<u16>::new(DATA_PORT).read()
+            },
+            Registers::Memory(base) => unsafe {
+                core::ptr::read_volatile((*base + index as u64 * 2).as_ptr::<u16>())
+            }
+        }
+    }
+
+    fn write(&self, index: u16, value: u16) {
+        match self {
+            Registers::Ports => unsafe {
+                Port::<u16>::new(INDEX_PORT).write(index);
+                Port::<u16>::new(DATA_PORT).write(value);
+            },
+            Registers::Memory(base) => unsafe {
+                core::ptr::write_volatile((*base + index as u64 * 2).as_mut_ptr::<u16>(), value);
+            }
+        }
+    }
+}
+
+struct Adapter {
+    registers: Registers,
+    /// Where the whole video memory is mapped, the framebuffer of every mode starts there
+    memory: VirtAddr,
+    memory_size: usize,
+    max_width: usize,
+    max_height: usize,
+    /// The size of the mode set by [`set_mode`], `None` while the adapter is still in text mode
+    mode: Option<(usize, usize)>
+}
+
+/// Returns whatever `device` is one of the adapters on the PCI bus
+pub fn is_adapter(device: &PciDevice) -> bool {
+    PCI_IDS.contains(&(device.vendor_id, device.device_id))
+}
+
+/// Finds the registers of the adapter and the physical address of its framebuffer, in the BARs of `pci` after
+/// letting it answer to its memory, or where Bochs always has them when the adapter isn't on the PCI bus
+fn find_adapter(pci: Option<&PciDevice>) -> Result<(Registers, PhysAddr), KernelError> {
+    let adapter = match pci {
+        Some(adapter) => adapter,
+        None => return Ok((Registers::Ports, PhysAddr::new(ISA_FRAMEBUFFER)))
+    };
+
+    adapter.write_config(PCI_COMMAND, adapter.read_config(PCI_COMMAND) | PCI_COMMAND_MEMORY);
+
+    // Bit 0 tells whatever the BAR has I/O ports, the memory of the adapter is always below 4 GiB
+    let framebuffer = adapter.read_config(PCI_BAR0);
+
+    if framebuffer & 1 != 0 || framebuffer & !0xF == 0 {
+        return Err(KernelError::NoDevice);
+    }
+
+    let bar = adapter.read_config(PCI_BAR2);
+
+    let registers = if bar & 1 == 0 && bar & !0xF != 0 {
+        let address = PhysAddr::new((bar & !0xF) as u64 + MMIO_REGISTERS_OFFSET);
+        Registers::Memory(crate::memory::mmio::map(address, MMIO_REGISTERS_SIZE)?)
+    } else {
+        Registers::Ports
+    };
+
+    return Ok((registers, PhysAddr::new((framebuffer & !0xF) as u64)));
+}
+
+/// Sets up the Bochs Graphics Adapter (the one of Bochs, QEMU and VirtualBox) found at `pci`, or on its ISA ports
+/// without one, and maps its whole video memory so the modes can be changed at any time later. Must be called once,
+/// before any process is created
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NoDevice`] if there's no adapter, or one too old to have a linear framebuffer
+pub fn init(pci: Option<&PciDevice>) -> Result<(), KernelError> {
+    let (registers, framebuffer) = find_adapter(pci)?;
+
+    // Without an adapter the ports read as all ones
+    if !(ID_MIN..=ID_MAX).contains(&registers.read(INDEX_ID)) {
+        return Err(KernelError::NoDevice);
+    }
+
+    let enable = registers.read(INDEX_ENABLE);
+
+    registers.write(INDEX_ENABLE, enable | ENABLE_GET_CAPABILITIES);
+    let max_width = registers.read(INDEX_X_RESOLUTION) as usize;
+    let max_height = registers.read(INDEX_Y_RESOLUTION) as usize;
+    registers.write(INDEX_ENABLE, enable);
+
+    let memory_size = match registers.read(INDEX_VIDEO_MEMORY_64K) as usize {
+        0 => FALLBACK_MEMORY_SIZE,
+        blocks => blocks * 64 * 1024
+    };
+
+    let memory = crate::memory::mmio::map(framebuffer, memory_size)?;
+
+    *ADAPTER.lock() = Some(Adapter { registers, memory, memory_size, max_width, max_height, mode: None });
+    Ok(())
+}
+
+/// Disables the adapter, sets the mode and enables it again, returning whatever the adapter accepted the mode
+fn program(registers: Registers, width: usize, height: usize) -> bool {
+    // The mode can only be changed while the adapter is disabled
+    registers.write(INDEX_ENABLE, 0);
+    registers.write(INDEX_X_RESOLUTION, width as u16);
+    registers.write(INDEX_Y_RESOLUTION, height as u16);
+    registers.write(INDEX_BPP, BPP);
+    registers.write(INDEX_X_OFFSET, 0);
+    registers.write(INDEX_Y_OFFSET, 0);
+    registers.write(INDEX_ENABLE, ENABLE_ENABLED | ENABLE_LINEAR_FRAMEBUFFER);
+
+    // The adapter ignores the values it doesn't like
+    registers.read(INDEX_X_RESOLUTION) as usize == width
+        && registers.read(INDEX_Y_RESOLUTION) as usize == height
+        && registers.read(INDEX_BPP) == BPP
+}
+
+/// Switches the adapter to a mode of `width` by `height` pixels with 32 bits per pixel, returning its framebuffer
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NoDevice`] if [`init`] found no adapter and [`KernelError::Unsupported`] if the mode
+/// is larger than the adapter allows, doesn't fit in its memory or `width` isn't a multiple of 8
+pub fn set_mode(width: usize, height: usize) -> Result<Framebuffer, KernelError> {
+    let mut adapter = ADAPTER.lock();
+    let adapter = adapter.as_mut().ok_or(KernelError::NoDevice)?;
+
+    let fits = width <= adapter.max_width && height <= adapter.max_height && width * height * 4 <= adapter.memory_size;
+
+    if width == 0 || height == 0 || width % 8 != 0 || !fits {
+        return Err(KernelError::Unsupported);
+    }
+
+    let registers = adapter.registers;
+
+    if !program(registers, width, height) {
+        // The mode that was there before worked, otherwise the adapter goes back to text mode
+        match adapter.mode {
+            Some((width, height)) => program(registers, width, height),
+            None => {
+                registers.write(INDEX_ENABLE, 0);
+                false
+            }
+        };
+
+        return Err(KernelError::Unsupported);
+    }
+
+    // The rows may be wider than the mode
+    let stride = (registers.read(INDEX_VIRTUAL_WIDTH) as usize).max(width);
+    adapter.mode = Some((width, height));
+
+    // The whole video memory is mapped and only the console draws on it
+    return Ok(unsafe { Framebuffer::new(adapter.memory, width, height, stride, None) });
+}
+
+/// Returns the size of the mode set by [`set_mode`], or `None` if the adapter is still in text mode
+pub fn mode() -> Option<(usize, usize)> {
+    ADAPTER.lock().as_ref().and_then(|adapter| adapter.mode)
+}
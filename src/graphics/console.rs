@@ -0,0 +1,85 @@
+use crate::graphics::font::{GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::graphics::Framebuffer;
+
+/// A text console drawn on a framebuffer, it behaves like the text mode one: the text is always written on
+/// the last row and every new line moves the rows above up.
+///
+/// The characters aren't kept anywhere else, scrolling moves the pixels of the framebuffer (the heap only has
+/// small blocks, the cells of a large mode wouldn't fit in them)
+pub struct FramebufferConsole {
+    framebuffer: Framebuffer,
+    columns: usize,
+    rows: usize,
+    cursor_x: usize,
+    foreground: u32,
+    background: u32
+}
+
+impl FramebufferConsole {
+    /// Creates a console filling `framebuffer`, which is cleared to `background`
+    pub fn new(mut framebuffer: Framebuffer, foreground: u32, background: u32) -> Self {
+        let columns = framebuffer.width() / GLYPH_WIDTH;
+        let rows = framebuffer.height() / GLYPH_HEIGHT;
+
+        framebuffer.fill_rect(0, 0, framebuffer.width(), framebuffer.height(), background);
+
+        FramebufferConsole {
+            framebuffer,
+            columns,
+            rows,
+            cursor_x: 0,
+            foreground,
+            background
+        }
+    }
+
+    /// Writes a byte, the new line and backspace characters are handled like the text mode does
+    pub fn write_byte(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(),
+            0x08 => self.backspace(),
+            byte => {
+                if self.cursor_x >= self.columns {
+                    self.new_line();
+                }
+
+                self.draw(self.cursor_x, byte);
+                self.cursor_x += 1;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cursor_x = 0;
+
+        let (width, height) = (self.framebuffer.width(), self.framebuffer.height());
+        self.framebuffer.fill_rect(0, 0, width, height, self.background);
+    }
+
+    /// Shows what was written since the last flush, see [`Framebuffer::flush`]
+    pub fn flush(&mut self) {
+        self.framebuffer.flush();
+    }
+
+    /// Draws a character in the given column of the last row
+    fn draw(&mut self, column: usize, character: u8) {
+        let (x, y) = (column * GLYPH_WIDTH, (self.rows - 1) * GLYPH_HEIGHT);
+        self.framebuffer.draw_glyph(x, y, character, self.foreground, self.background);
+    }
+
+    fn new_line(&mut self) {
+        // The pixels below the last full row (when the height isn't a multiple of the glyphs) stay blank
+        self.framebuffer.scroll_up(GLYPH_HEIGHT, self.rows * GLYPH_HEIGHT, self.background);
+        self.cursor_x = 0;
+    }
+
+    /// Erases the character before the cursor, like the text mode the cursor never goes back to the previous row
+    fn backspace(&mut self) {
+        if self.cursor_x == 0 {
+            return;
+        }
+
+        self.cursor_x -= 1;
+        self.draw(self.cursor_x, b' ');
+    }
+}
@@ -0,0 +1,228 @@
+pub mod bga;
+pub mod console;
+mod font;
+pub mod virtio_gpu;
+
+use alloc::boxed::Box;
+use x86_64::VirtAddr;
+use crate::drivers::registry::{self, Driver};
+use crate::pci::PciDevice;
+use crate::println;
+use crate::utils::error::KernelError;
+use crate::utils::Mutex;
+
+/// The adapter bound by the probes registered in [`init`]
+static ADAPTER: Mutex<Option<Adapter>> = Mutex::new(None);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Adapter {
+    Bochs,
+    VirtioGpu
+}
+
+/// The driver bound to the adapter
+struct GraphicsDriver(Adapter);
+
+impl Driver for GraphicsDriver {
+    fn name(&self) -> &'static str {
+        match self.0 {
+            Adapter::Bochs => "bga",
+            Adapter::VirtioGpu => "virtio-gpu"
+        }
+    }
+}
+
+/// A linear framebuffer with 32 bits per pixel, each pixel is `0x00RRGGBB`
+pub struct Framebuffer {
+    address: VirtAddr,
+    width: usize,
+    height: usize,
+    /// How many pixels there are from the start of a row to the start of the next one, at least `width`
+    stride: usize,
+    /// Called by [`Framebuffer::flush`] with the rectangle that changed, for the adapters that only show what's
+    /// in the framebuffer once they're told to
+    flush: Option<fn(usize, usize, usize, usize)>,
+    /// The rectangle changed since the last flush, as its left, top, right and bottom edges
+    dirty: Option<(usize, usize, usize, usize)>
+}
+
+// The framebuffer is only reached through `&mut self`, so it's safe to move it to another thread
+unsafe impl Send for Framebuffer {}
+
+impl Framebuffer {
+    /// Creates a framebuffer of `width` by `height` pixels whose memory starts at `address`, with the function
+    /// that shows a rectangle of it on the screen if the adapter needs one
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that `address` is mapped for the `stride * height`
+    /// pixels and that nothing else uses that memory while the framebuffer exists
+    pub unsafe fn new(
+        address: VirtAddr, width: usize, height: usize, stride: usize, flush: Option<fn(usize, usize, usize, usize)>
+    ) -> Self {
+        Framebuffer { address, width, height, stride, flush, dirty: None }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Fills the rectangle with its top left corner at `x` and `y` with `color`, the part outside of the
+    /// framebuffer is ignored
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        let right = (x + width).min(self.width);
+        let bottom = (y + height).min(self.height);
+
+        self.mark_dirty(x, y, right, bottom);
+
+        for row in y..bottom {
+            for column in x..right {
+                self.write_pixel(column, row, color);
+            }
+        }
+    }
+
+    /// Draws a glyph of [`font`] with its top left corner at `x` and `y`, the pixels outside of the framebuffer
+    /// are ignored
+    pub fn draw_glyph(&mut self, x: usize, y: usize, byte: u8, foreground: u32, background: u32) {
+        self.mark_dirty(x, y, (x + font::GLYPH_WIDTH).min(self.width), (y + font::GLYPH_HEIGHT).min(self.height));
+
+        for (row, bits) in font::glyph(byte).iter().enumerate() {
+            for column in 0..font::GLYPH_WIDTH {
+                let color = if bits >> column & 1 != 0 { foreground } else { background };
+
+                if x + column < self.width && y + row < self.height {
+                    self.write_pixel(x + column, y + row, color);
+                }
+            }
+        }
+    }
+
+    /// Moves the first `height` rows of pixels up by `lines` rows, the rows left at the bottom are filled with `color`
+    pub fn scroll_up(&mut self, lines: usize, height: usize, color: u32) {
+        let height = height.min(self.height);
+        let lines = lines.min(height);
+        let base = self.address.as_mut_ptr::<u32>();
+
+        self.mark_dirty(0, 0, self.width, height);
+
+        for row in 0..height - lines {
+            // Different rows never overlap, the stride is at least the width
+            unsafe {
+                let source = base.add((row + lines) * self.stride);
+                core::ptr::copy_nonoverlapping(source, base.add(row * self.stride), self.width);
+            }
+        }
+
+        self.fill_rect(0, height - lines, self.width, lines, color);
+    }
+
+    /// Shows what changed since the last flush on the screen, for the adapters that need it
+    pub fn flush(&mut self) {
+        if let (Some(flush), Some((left, top, right, bottom))) = (self.flush, self.dirty.take()) {
+            flush(left, top, right - left, bottom - top);
+        }
+    }
+
+    /// Adds the rectangle between the given edges to the one that changed
+    fn mark_dirty(&mut self, left: usize, top: usize, right: usize, bottom: usize) {
+        if left >= right || top >= bottom {
+            return;
+        }
+
+        self.dirty = Some(match self.dirty {
+            Some((l, t, r, b)) => (l.min(left), t.min(top), r.max(right), b.max(bottom)),
+            None => (left, top, right, bottom)
+        });
+    }
+
+    fn write_pixel(&mut self, x: usize, y: usize, color: u32) {
+        let pixel = self.address.as_mut_ptr::<u32>().wrapping_add(y * self.stride + x);
+
+        // The memory belongs to the device, the writes must happen even if nothing reads them back
+        unsafe { core::ptr::write_volatile(pixel, color) };
+    }
+}
+
+/// Registers the probes of the graphics adapters, the first one found is the one [`set_mode`] uses. The Bochs
+/// adapter is also looked for on its ISA ports when there's none on the PCI bus. Must be called once, before
+/// [`registry::probe_all`]
+pub fn init() {
+    registry::register(probe);
+    registry::register_platform("dispi", probe_isa);
+}
+
+/// Binds to `device` if it's an adapter and none was found before it, mapping its memory so the mode can be
+/// changed later
+fn probe(device: &PciDevice) -> Option<Box<dyn Driver>> {
+    if ADAPTER.lock().is_some() {
+        return None;
+    }
+
+    let (adapter, result) = if virtio_gpu::is_gpu(device) {
+        (Adapter::VirtioGpu, virtio_gpu::init(*device))
+    } else if bga::is_adapter(device) {
+        (Adapter::Bochs, bga::init(Some(device)))
+    } else {
+        return None;
+    };
+
+    if let Err(error) = result {
+        println!("Graphics: failed to set up the {:?} adapter ({:?})", adapter, error);
+        return None;
+    }
+
+    *ADAPTER.lock() = Some(adapter);
+    return Some(Box::new(GraphicsDriver(adapter)));
+}
+
+/// Looks for the Bochs adapter on its ISA ports, for the machines where it isn't on the PCI bus
+fn probe_isa() -> Option<Box<dyn Driver>> {
+    if ADAPTER.lock().is_some() {
+        return None;
+    }
+
+    bga::init(None).ok()?;
+
+    *ADAPTER.lock() = Some(Adapter::Bochs);
+    return Some(Box::new(GraphicsDriver(Adapter::Bochs)));
+}
+
+/// Switches the adapter found by the probes to a graphics mode of `width` by `height` pixels and moves
+/// the kernel console to it, keeping what was on the screen
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NoDevice`] if there's no adapter, [`KernelError::InvalidArgument`] if the mode
+/// can't hold a single character and [`KernelError::Unsupported`] if the adapter can't show that mode
+pub fn set_mode(width: usize, height: usize) -> Result<(), KernelError> {
+    if width < font::GLYPH_WIDTH || height < font::GLYPH_HEIGHT {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let adapter = *ADAPTER.lock();
+
+    let framebuffer = match adapter {
+        Some(Adapter::Bochs) => bga::set_mode(width, height)?,
+        Some(Adapter::VirtioGpu) => virtio_gpu::set_mode(width, height)?,
+        None => return Err(KernelError::NoDevice)
+    };
+
+    crate::vga::use_framebuffer(framebuffer);
+    Ok(())
+}
+
+/// Returns the size of the current graphics mode, or `None` when the screen is still in text mode
+pub fn mode() -> Option<(usize, usize)> {
+    let adapter = *ADAPTER.lock();
+
+    match adapter {
+        Some(Adapter::Bochs) => bga::mode(),
+        Some(Adapter::VirtioGpu) => virtio_gpu::mode(),
+        None => None
+    }
+}
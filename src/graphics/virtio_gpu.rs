@@ -0,0 +1,330 @@
+use x86_64::VirtAddr;
+use crate::graphics::Framebuffer;
+use crate::memory::dma::DmaBuffer;
+use crate::pci::PciDevice;
+use crate::utils::error::KernelError;
+use crate::utils::IrqCell;
+use crate::virtio::queue::{Buffer, Virtqueue, MAX_QUEUE_SIZE};
+use crate::virtio::VirtioDevice;
+
+/// The virtio device type of a GPU
+const DEVICE_TYPE: u16 = 16;
+
+/// The queue the commands are sent through, the other one is only for the mouse cursor
+const CONTROL_QUEUE: u16 = 0;
+
+/// The commands of the 2D mode used here
+const COMMAND_GET_DISPLAY_INFO: u32 = 0x0100;
+const COMMAND_RESOURCE_CREATE_2D: u32 = 0x0101;
+const COMMAND_RESOURCE_UNREF: u32 = 0x0102;
+const COMMAND_SET_SCANOUT: u32 = 0x0103;
+const COMMAND_RESOURCE_FLUSH: u32 = 0x0104;
+const COMMAND_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+const COMMAND_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+
+/// The answers to the commands that worked, the errors start at 0x1200
+const RESPONSE_OK_NODATA: u32 = 0x1100;
+const RESPONSE_OK_DISPLAY_INFO: u32 = 0x1101;
+
+/// The format of the resources, its bytes are blue, green, red and unused so a pixel is `0x00RRGGBB` like
+/// [`Framebuffer`] wants
+const FORMAT_B8G8R8X8: u32 = 2;
+
+/// The size of the header every command and response starts with, in 32 bits words
+const HEADER_WORDS: usize = 6;
+
+/// How many displays the answer to [`COMMAND_GET_DISPLAY_INFO`] has, each one is 6 words (its rectangle,
+/// whatever it's enabled and its flags)
+const MAX_SCANOUTS: usize = 16;
+
+/// Where the command and its response are in the page they are built in
+const RESPONSE_OFFSET: usize = 2048;
+const RESPONSE_SIZE: u32 = 2048;
+
+/// The size of an entry of the backing of a resource: its address, its length and padding
+const BACKING_ENTRY_SIZE: usize = 16;
+
+/// The mode used to size the memory of the screen when the device doesn't tell the size of its display
+const DEFAULT_MODE: (usize, usize) = (1024, 768);
+
+/// How many times the used ring is checked before a command is considered lost. The commands are polled instead of
+/// waiting for the interrupt, since the console flushes with the interrupts disabled
+const POLL_ATTEMPTS: usize = 10_000_000;
+
+/// The GPU found by [`init`]
+static GPU: IrqCell<Option<Gpu>> = IrqCell::new(None);
+
+/// The queue of the commands, with the page they're built in
+struct Control {
+    queue: Virtqueue,
+    page: DmaBuffer
+}
+
+impl Control {
+    /// Sends the command made of the header for `command` and `body`, followed by the buffers in `extra`, and waits
+    /// for the device to answer. Returns the type of the response
+    fn send(&mut self, command: u32, body: &[u32], extra: &[Buffer]) -> Result<u32, KernelError> {
+        let words = self.page.page(0) as *mut u32;
+        let length = HEADER_WORDS + body.len();
+
+        // The flags, the fence and the context of the header stay zero
+        unsafe {
+            core::ptr::write_bytes(words, 0, HEADER_WORDS);
+            words.write_volatile(command);
+            core::ptr::copy_nonoverlapping(body.as_ptr(), words.add(HEADER_WORDS), body.len());
+        }
+
+        let request = Buffer { address: self.page.physical(0), length: (length * 4) as u32, writable: false };
+        let response_address = self.page.physical(0) + RESPONSE_OFFSET;
+        let response = Buffer { address: response_address, length: RESPONSE_SIZE, writable: true };
+
+        let mut buffers = [request; MAX_QUEUE_SIZE as usize];
+        buffers[1..extra.len() + 1].copy_from_slice(extra);
+        buffers[extra.len() + 1] = response;
+
+        let head = self.queue.push(&buffers[..extra.len() + 2])?;
+        self.queue.notify();
+
+        for _ in 0..POLL_ATTEMPTS {
+            // Nothing else is sent while this waits, so the chain that comes back is this one
+            if let Some((used, _)) = self.queue.pop_used() {
+                debug_assert_eq!(used, head);
+                return Ok(self.response(0));
+            }
+
+            core::hint::spin_loop();
+        }
+
+        return Err(KernelError::Timeout);
+    }
+
+    /// Sends a command that doesn't answer with data
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Io`] if the device refuses the command, or [`KernelError::Timeout`] if it doesn't answer
+    fn command(&mut self, command: u32, body: &[u32]) -> Result<(), KernelError> {
+        match self.send(command, body, &[])? {
+            RESPONSE_OK_NODATA => Ok(()),
+            _ => Err(KernelError::Io)
+        }
+    }
+
+    /// Reads a word of the last response
+    fn response(&self, word: usize) -> u32 {
+        unsafe { (self.page.page(0).add(RESPONSE_OFFSET) as *const u32).add(word).read_volatile() }
+    }
+
+    /// Returns the size of the first enabled display, if the device tells it
+    fn display_size(&mut self) -> Option<(usize, usize)> {
+        if self.send(COMMAND_GET_DISPLAY_INFO, &[], &[]).ok()? != RESPONSE_OK_DISPLAY_INFO {
+            return None;
+        }
+
+        (0..MAX_SCANOUTS)
+            .map(|scanout| HEADER_WORDS + scanout * 6)
+            .find(|&display| self.response(display + 4) != 0)
+            .map(|display| (self.response(display + 2) as usize, self.response(display + 3) as usize))
+            .filter(|&(width, height)| width > 0 && height > 0)
+    }
+}
+
+/// The resource shown on the screen
+#[derive(Debug, Copy, Clone)]
+struct Resource {
+    id: u32,
+    width: usize,
+    height: usize
+}
+
+struct Gpu {
+    control: Control,
+    /// The memory every resource is backed by, mapped as a single block
+    memory: VirtAddr,
+    memory_size: usize,
+    /// The list of the pages of `memory` given to the device when a resource is created, merged when they are
+    /// contiguous, and how many entries it has
+    backing: DmaBuffer,
+    backing_entries: usize,
+    resource: Option<Resource>,
+    next_resource: u32
+}
+
+impl Gpu {
+    /// Gives `memory` to the resource as its backing, the entries are sent after the command one page at a time
+    fn attach_backing(&mut self, resource: u32) -> Result<(), KernelError> {
+        let size = self.backing_entries * BACKING_ENTRY_SIZE;
+        let pages = (size + 4095) / 4096;
+
+        let first = Buffer { address: self.backing.physical(0), length: 0, writable: false };
+        let mut extra = [first; MAX_QUEUE_SIZE as usize];
+
+        for (page, buffer) in extra[..pages].iter_mut().enumerate() {
+            buffer.address = self.backing.physical(page);
+            buffer.length = (size - page * 4096).min(4096) as u32;
+        }
+
+        let body = [resource, self.backing_entries as u32];
+
+        match self.control.send(COMMAND_RESOURCE_ATTACH_BACKING, &body, &extra[..pages])? {
+            RESPONSE_OK_NODATA => Ok(()),
+            _ => Err(KernelError::Io)
+        }
+    }
+}
+
+/// Builds the list of the pages of the `size` bytes at `memory` for [`COMMAND_RESOURCE_ATTACH_BACKING`], the
+/// contiguous ones are merged into a single entry. Returns the list and how many entries it has
+fn build_backing(memory: VirtAddr, size: usize) -> Result<(DmaBuffer, usize), KernelError> {
+    let pages = size / 4096;
+
+    // At worst every page is an entry, and the entries of the command can't take more descriptors than the queue has
+    let list_pages = (pages * BACKING_ENTRY_SIZE + 4095) / 4096;
+
+    if list_pages + 2 > MAX_QUEUE_SIZE as usize {
+        return Err(KernelError::Unsupported);
+    }
+
+    let list = DmaBuffer::new(list_pages)?;
+    let mut entries = 0;
+    let mut run: Option<(u64, u64)> = None;
+
+    let mut push = |start: u64, length: u64| {
+        let entry = unsafe { list.page(entries * BACKING_ENTRY_SIZE / 4096).add(entries * BACKING_ENTRY_SIZE % 4096) };
+
+        unsafe {
+            (entry as *mut u64).write_volatile(start);
+            (entry.add(8) as *mut u32).write_volatile(length as u32);
+        }
+
+        entries += 1;
+    };
+
+    for page in 0..pages {
+        let physical = crate::memory::mmio::translate(memory + page * 4096).ok_or(KernelError::Unsupported)?.as_u64();
+
+        run = match run {
+            Some((start, length)) if start + length == physical => Some((start, length + 4096)),
+            Some((start, length)) => {
+                push(start, length);
+                Some((physical, 4096))
+            },
+            None => Some((physical, 4096))
+        };
+    }
+
+    if let Some((start, length)) = run {
+        push(start, length);
+    }
+
+    return Ok((list, entries));
+}
+
+/// Returns whatever `device` is a virtio-gpu
+pub fn is_gpu(device: &PciDevice) -> bool {
+    crate::virtio::is_device(device, DEVICE_TYPE)
+}
+
+/// Sets up the virtio-gpu at `pci` and allocates the memory of the screen, as large as its display (or
+/// [`DEFAULT_MODE`]). Must be called once, before any process is created
+///
+/// ## Errors
+///
+/// Returns the error that made the initialization of the device fail
+pub fn init(pci: PciDevice) -> Result<(), KernelError> {
+    let device = VirtioDevice::new(pci)?;
+
+    device.negotiate(0)?;
+
+    let mut control = Control {
+        queue: device.setup_queue(CONTROL_QUEUE)?,
+        page: DmaBuffer::new(1)?
+    };
+
+    device.start();
+
+    let (width, height) = control.display_size().unwrap_or(DEFAULT_MODE);
+    let memory_size = (width * height * 4 + 4095) / 4096 * 4096;
+
+    let memory = crate::memory::mmio::allocate(memory_size)?;
+    let (backing, backing_entries) = build_backing(memory, memory_size)?;
+
+    let gpu = Gpu { control, memory, memory_size, backing, backing_entries, resource: None, next_resource: 1 };
+
+    GPU.with(|slot| *slot = Some(gpu));
+    Ok(())
+}
+
+/// Creates a resource of `width` by `height` pixels backed by the memory of the screen and shows it on the
+/// first display, returning its framebuffer. The resource shown before is destroyed
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NoDevice`] if [`init`] found no GPU, [`KernelError::Unsupported`] if the mode doesn't fit
+/// in the memory of the screen and [`KernelError::Io`] if the device refuses it
+pub fn set_mode(width: usize, height: usize) -> Result<Framebuffer, KernelError> {
+    GPU.with(|gpu| {
+        let gpu = gpu.as_mut().ok_or(KernelError::NoDevice)?;
+
+        if width * height * 4 > gpu.memory_size {
+            return Err(KernelError::Unsupported);
+        }
+
+        let resource = Resource { id: gpu.next_resource, width, height };
+        gpu.next_resource += 1;
+
+        gpu.control.command(COMMAND_RESOURCE_CREATE_2D, &[resource.id, FORMAT_B8G8R8X8, width as u32, height as u32])?;
+
+        // The rectangle of the resource shown, the display (the first one) and the resource
+        let scanout = [0, 0, width as u32, height as u32, 0, resource.id];
+        let shown = gpu.attach_backing(resource.id).and_then(|_| gpu.control.command(COMMAND_SET_SCANOUT, &scanout));
+
+        // Whatever resource isn't shown anymore is destroyed, which also takes its backing away
+        let unused = match shown {
+            Ok(()) => gpu.resource.replace(resource).map(|previous| previous.id),
+            Err(_) => Some(resource.id)
+        };
+
+        if let Some(id) = unused {
+            let _ = gpu.control.command(COMMAND_RESOURCE_UNREF, &[id, 0]);
+        }
+
+        shown?;
+
+        // The memory is mapped for as long as the kernel runs and only the console draws on it
+        Ok(unsafe { Framebuffer::new(gpu.memory, width, height, width, Some(flush)) })
+    })
+}
+
+/// Copies a rectangle of the memory of the screen to the resource shown and shows it, the flush function
+/// of the framebuffers returned by [`set_mode`]
+fn flush(x: usize, y: usize, width: usize, height: usize) {
+    GPU.with(|gpu| {
+        let gpu = match gpu {
+            Some(gpu) => gpu,
+            None => return
+        };
+
+        let resource = match gpu.resource {
+            Some(resource) => resource,
+            None => return
+        };
+
+        let (x, y, width, height) = (x as u32, y as u32, width as u32, height as u32);
+
+        // The offset is where the rectangle starts in the backing, the rows of the resource are as wide as it is
+        let offset = (y as u64 * resource.width as u64 + x as u64) * 4;
+
+        let transfer = [x, y, width, height, offset as u32, (offset >> 32) as u32, resource.id, 0];
+        let shown = [x, y, width, height, resource.id, 0];
+
+        // There's nothing the console could do about a failure
+        let _ = gpu.control.command(COMMAND_TRANSFER_TO_HOST_2D, &transfer)
+            .and_then(|_| gpu.control.command(COMMAND_RESOURCE_FLUSH, &shown));
+    });
+}
+
+/// Returns the size of the resource shown, or `None` before the first [`set_mode`]
+pub fn mode() -> Option<(usize, usize)> {
+    GPU.with(|gpu| gpu.as_ref()?.resource.map(|resource| (resource.width, resource.height)))
+}
@@ -1,21 +1,62 @@
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU64, Ordering};
 use lazy_static::lazy_static;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::VirtAddr;
-use crate::{print, println};
+use crate::println;
 use crate::interrupts::pic::PICPair;
+use crate::process::signal::{Signal, SIGFPE, SIGILL, SIGSEGV, SIGTRAP};
+use crate::utils::error::KernelError;
+use crate::utils::IrqCell;
 
 const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
+/// How many pages the stack used to handle double faults has
+const DOUBLE_FAULT_STACK_PAGES: usize = 5;
+
+/// How many pages the stack used when an interrupt arrives in user mode has, while the running thread
+/// doesn't have a kernel stack of its own (see [`set_kernel_stack`])
+const PRIVILEGE_STACK_PAGES: usize = 4;
+
 const PIC_1_OFFSET: u8 = 32;
 const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
+/// The IRQ lines whose handlers are registered at runtime with [`register_irq`], the others are either
+/// handled by the kernel itself (timer, keyboard and ATA) or connect the PICs (IRQ 2)
+const FIRST_SHARED_IRQ: u8 = 3;
+const LAST_SHARED_IRQ: u8 = 13;
+
+/// How many handlers a single IRQ line can have, PCI devices often have to share the few lines of the PIC
+const HANDLERS_PER_IRQ: usize = 4;
+
+type SharedHandlers = [[Option<fn()>; HANDLERS_PER_IRQ]; (LAST_SHARED_IRQ - FIRST_SHARED_IRQ + 1) as usize];
+
+/// The handlers registered for each of the shared IRQ lines
+static SHARED_HANDLERS: IrqCell<SharedHandlers> = IrqCell::new([[None; HANDLERS_PER_IRQ]; (LAST_SHARED_IRQ - FIRST_SHARED_IRQ + 1) as usize]);
+
+/// How many interrupts each line of the PICs delivered since boot, without the spurious ones
+static IRQ_COUNTS: [AtomicU64; 16] = [const { AtomicU64::new(0) }; 16];
+
+/// How many interrupts the timer of the local APIC delivered since boot
+static LOCAL_TIMER_COUNT: AtomicU64 = AtomicU64::new(0);
+
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
 
+        idt.divide_error.set_handler_fn(divide_error_handler);
+        idt.debug.set_handler_fn(debug_handler);
         idt.breakpoint.set_handler_fn(breakpoint_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        idt.segment_not_present.set_handler_fn(segment_not_present_handler);
+        idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
+        idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+        idt.page_fault.set_handler_fn(page_fault_handler);
+        idt.x87_floating_point.set_handler_fn(x87_floating_point_handler);
+        idt.alignment_check.set_handler_fn(alignment_check_handler);
+        idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
 
         unsafe {
             idt.double_fault.set_handler_fn(double_fault_handler).set_stack_index(DOUBLE_FAULT_IST_INDEX);
@@ -23,41 +64,82 @@ lazy_static! {
 
         idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_handler);
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_handler);
+        idt[InterruptIndex::PrimaryAta.as_usize()].set_handler_fn(primary_ata_handler);
+        idt[InterruptIndex::SecondaryAta.as_usize()].set_handler_fn(secondary_ata_handler);
+        idt[crate::time::lapic::TIMER_VECTOR as usize].set_handler_fn(apic_timer_handler);
+        idt[crate::time::lapic::SPURIOUS_VECTOR as usize].set_handler_fn(apic_spurious_handler);
+
+        let shared_handlers: [extern "x86-interrupt" fn(InterruptStackFrame); (LAST_SHARED_IRQ - FIRST_SHARED_IRQ + 1) as usize] = [
+            shared_irq_handler::<3>, shared_irq_handler::<4>, shared_irq_handler::<5>, shared_irq_handler::<6>,
+            shared_irq_handler::<7>, shared_irq_handler::<8>, shared_irq_handler::<9>, shared_irq_handler::<10>,
+            shared_irq_handler::<11>, shared_irq_handler::<12>, shared_irq_handler::<13>
+        ];
+
+        for (index, handler) in shared_handlers.into_iter().enumerate() {
+            idt[(PIC_1_OFFSET + FIRST_SHARED_IRQ) as usize + index].set_handler_fn(handler);
+        }
 
         idt
     };
 }
 
+/// The TSS has to be updated on every context switch (see [`set_kernel_stack`]) while the CPU keeps a pointer to it
+struct TaskState {
+    tss: UnsafeCell<TaskStateSegment>,
+    /// The kernel stack used by the threads that don't have one of their own
+    shared_stack_top: VirtAddr
+}
+
+// The TSS is only written with interrupts disabled, by the scheduler
+unsafe impl Sync for TaskState {}
+
 lazy_static! {
-    static ref TSS: TaskStateSegment = {
+    static ref TSS: TaskState = {
         let mut tss = TaskStateSegment::new();
 
         tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+            // The stack is never freed, the double fault handler may be needed until the very end
+            let stack = crate::memory::stack::allocate_stack(DOUBLE_FAULT_STACK_PAGES)
+                .expect("Failed to allocate the double fault stack");
+
+            stack.top()
+        };
 
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            let stack_end = stack_start + STACK_SIZE;
+        // The stack the CPU switches to when an interrupt (or exception) arrives while running in ring 3
+        let shared_stack_top = {
+            let stack = crate::memory::stack::allocate_stack(PRIVILEGE_STACK_PAGES)
+                .expect("Failed to allocate the privilege stack");
 
-            stack_end
+            stack.top()
         };
 
-        tss
+        tss.privilege_stack_table[0] = shared_stack_top;
+
+        TaskState {
+            tss: UnsafeCell::new(tss),
+            shared_stack_top
+        }
     };
 }
 
 lazy_static! {
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
+
+        // The order of the segments matters to `syscall`/`sysret`, which compute the selectors from the first
+        // kernel one: kernel code, kernel data, then user data and user code
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
+        let user_data_selector = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe { &*TSS.tss.get() }));
 
-        (gdt, Selectors { code_selector, tss_selector })
+        (gdt, Selectors { code_selector, data_selector, user_code_selector, user_data_selector, tss_selector })
     };
 }
 
 lazy_static! {
-    static ref PICS: spin::Mutex<PICPair> = spin::Mutex::new(PICPair::new());
+    static ref PICS: IrqCell<PICPair> = IrqCell::new(PICPair::new());
 }
 
 /// Loads a backup stack used in case of a stackoverflow exception is raised,
@@ -65,21 +147,130 @@ lazy_static! {
 /// and configures the CPU to call the correct handles in case of an interrupt of exception
 pub fn init() {
     use x86_64::instructions::tables::load_tss;
-    use x86_64::instructions::segmentation::{CS, Segment};
+    use x86_64::instructions::segmentation::{CS, DS, ES, SS, Segment};
 
     GDT.0.load();
 
     unsafe {
         CS::set_reg(GDT.1.code_selector);
+        DS::set_reg(GDT.1.data_selector);
+        ES::set_reg(GDT.1.data_selector);
+        SS::set_reg(GDT.1.data_selector);
         load_tss(GDT.1.tss_selector);
+        set_kernel_stack(None);
     }
 
     IDT.load();
 
-    PICS.lock().initialize(PIC_1_OFFSET, PIC_2_OFFSET);
+    PICS.with(|pics| pics.initialize(PIC_1_OFFSET, PIC_2_OFFSET));
     x86_64::instructions::interrupts::enable()
 }
 
+/// Lets the PIC deliver the interrupts of the given IRQ line, for the drivers of the devices that use one.
+/// The lines of the slave PIC also need the line the slave is connected to on the master (IRQ 2)
+pub fn enable_irq(irq: u8) {
+    PICS.with(|pics| {
+        if irq >= 8 {
+            pics.set_mask(2, false);
+        }
+
+        pics.set_mask(irq, false);
+    });
+}
+
+/// Stops the PIC from delivering the interrupts of the given IRQ line, like the one of the PIT once the tick comes
+/// from the local APIC
+pub fn disable_irq(irq: u8) {
+    PICS.with(|pics| pics.set_mask(irq, true));
+}
+
+/// Registers a handler for the given IRQ line and lets the PIC deliver its interrupts, for the drivers of the devices
+/// whose line is only known at runtime (like the ones of PCI devices, see the interrupt line of their configuration).
+/// A line can be shared by a few devices, every handler of the line is called on each of its interrupts and
+/// must check whatever its device is the one that raised it
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if the line doesn't exist or is handled by the kernel itself (0, 1, 2,
+/// 14 and 15), or [`KernelError::Busy`] if the line already has as many handlers as it can take
+///
+/// ## Note
+///
+/// The handler runs inside an interrupt handler, so it must not allocate or block
+pub fn register_irq(irq: u8, handler: fn()) -> Result<(), KernelError> {
+    if !(FIRST_SHARED_IRQ..=LAST_SHARED_IRQ).contains(&irq) {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let registered: Result<(), KernelError> = SHARED_HANDLERS.with(|handlers| {
+        let slot = handlers[(irq - FIRST_SHARED_IRQ) as usize]
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(KernelError::Busy)?;
+
+        *slot = Some(handler);
+        Ok(())
+    });
+
+    registered?;
+    enable_irq(irq);
+    Ok(())
+}
+
+/// Returns how many interrupts each of the 16 IRQ lines delivered since boot, the spurious ones left out
+pub fn irq_counts() -> [u64; 16] {
+    let mut counts = [0; 16];
+
+    for (count, counter) in counts.iter_mut().zip(&IRQ_COUNTS) {
+        *count = counter.load(Ordering::Relaxed);
+    }
+
+    return counts;
+}
+
+/// Returns how many interrupts the timer of the local APIC delivered since boot
+pub fn local_timer_count() -> u64 {
+    LOCAL_TIMER_COUNT.load(Ordering::Relaxed)
+}
+
+/// Returns how many handlers were registered for the given IRQ line with [`register_irq`]
+pub fn shared_handler_count(irq: u8) -> usize {
+    if !(FIRST_SHARED_IRQ..=LAST_SHARED_IRQ).contains(&irq) {
+        return 0;
+    }
+
+    return SHARED_HANDLERS.with(|handlers| handlers[(irq - FIRST_SHARED_IRQ) as usize].iter().flatten().count());
+}
+
+/// Counts an interrupt of the given IRQ line that wasn't spurious
+fn count_irq(irq: u8) {
+    IRQ_COUNTS[irq as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the code and data selectors of ring 0
+pub fn kernel_selectors() -> (SegmentSelector, SegmentSelector) {
+    return (GDT.1.code_selector, GDT.1.data_selector);
+}
+
+/// Returns the code and data selectors of ring 3, already with the requested privilege level set to 3
+pub fn user_selectors() -> (SegmentSelector, SegmentSelector) {
+    return (GDT.1.user_code_selector, GDT.1.user_data_selector);
+}
+
+/// Sets the stack the CPU switches to when an interrupt or a system call arrives while running in user mode,
+/// the scheduler calls this on every context switch so each thread traps into its own kernel stack.
+/// [`None`] selects the stack shared by the threads that don't have a stack of their own
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that interrupts are disabled and
+/// that `stack_top` is the top of a mapped stack that isn't used by anything else while in user mode
+pub unsafe fn set_kernel_stack(stack_top: Option<VirtAddr>) {
+    let stack_top = stack_top.unwrap_or(TSS.shared_stack_top);
+
+    (*TSS.tss.get()).privilege_stack_table[0] = stack_top;
+    crate::syscall::set_kernel_stack(stack_top);
+}
 
 /// Contains the IRQ indexes for the PIC8259, these IRQs are used to sent interrupts
 /// to the CPU than can be sent from external hardware such as the keyboard
@@ -87,7 +278,9 @@ pub fn init() {
 #[repr(u8)]
 enum InterruptIndex {
     Timer = PIC_1_OFFSET,
-    Keyboard = PIC_1_OFFSET + 1
+    Keyboard = PIC_1_OFFSET + 1,
+    PrimaryAta = PIC_2_OFFSET + 6,
+    SecondaryAta = PIC_2_OFFSET + 7
 }
 
 impl InterruptIndex {
@@ -106,6 +299,9 @@ impl InterruptIndex {
 
 struct Selectors {
     code_selector: SegmentSelector,
+    data_selector: SegmentSelector,
+    user_code_selector: SegmentSelector,
+    user_data_selector: SegmentSelector,
     tss_selector: SegmentSelector
 }
 
@@ -113,6 +309,120 @@ struct Selectors {
 ////////////////////////////// CPU EXCEPTIONS //////////////////////////////
 ////////////////////////////////////////////////////////////////////////////
 
+/// Terminates the current thread if it raised the exception called `exception` in user mode, with the exit code of
+/// the signal matching the exception (like the page fault handler does). Returns if the exception comes from the
+/// kernel, which is fatal
+fn terminate_user_thread(interrupt_stack_frame: &InterruptStackFrame, exception: &str, signal: Signal) {
+    if interrupt_stack_frame.code_segment & 3 != 3 {
+        return;
+    }
+
+    let address = interrupt_stack_frame.instruction_pointer.as_u64();
+
+    println!("{} at {:#x}, killing the thread", exception, address);
+    crate::task::exit(-(signal as i32));
+}
+
+/// Handler for the divide error exception
+///
+/// ## Cause
+///
+/// This handler is called by the CPU when a division by zero is made, or when the result of a division doesn't fit.
+/// A program doing it is terminated as if by [`SIGFPE`], it's fatal in the kernel
+extern "x86-interrupt" fn divide_error_handler(interrupt_stack_frame: InterruptStackFrame) {
+    terminate_user_thread(&interrupt_stack_frame, "Division error", SIGFPE);
+    panic!("\n\nEXCEPTION: [DIVIDE_ERROR] \n{:#?}\n\n", interrupt_stack_frame);
+}
+
+/// Handler for the debug exception
+///
+/// ## Cause
+///
+/// This handler is called by the CPU after every instruction while the trap flag is set, which a program can do
+/// with `popf`. There's no debugger, so the program is terminated as if by [`SIGTRAP`]
+extern "x86-interrupt" fn debug_handler(interrupt_stack_frame: InterruptStackFrame) {
+    terminate_user_thread(&interrupt_stack_frame, "Debug trap", SIGTRAP);
+    panic!("\n\nEXCEPTION: [DEBUG] \n{:#?}\n\n", interrupt_stack_frame);
+}
+
+/// Handler for the invalid opcode exception
+///
+/// ## Cause
+///
+/// This handler is called by the CPU when it runs an instruction that doesn't exist (like `ud2`). A program doing it
+/// is terminated as if by [`SIGILL`], it's fatal in the kernel
+extern "x86-interrupt" fn invalid_opcode_handler(interrupt_stack_frame: InterruptStackFrame) {
+    terminate_user_thread(&interrupt_stack_frame, "Invalid opcode", SIGILL);
+    panic!("\n\nEXCEPTION: [INVALID_OPCODE] \n{:#?}\n\n", interrupt_stack_frame);
+}
+
+/// Handler for the segment not present exception
+///
+/// ## Cause
+///
+/// This handler is called by the CPU when a segment register is loaded with a descriptor that isn't present. A
+/// program doing it is terminated as if by [`SIGSEGV`], it's fatal in the kernel
+extern "x86-interrupt" fn segment_not_present_handler(interrupt_stack_frame: InterruptStackFrame, error_code: u64) {
+    terminate_user_thread(&interrupt_stack_frame, "Segment not present", SIGSEGV);
+    panic!("\n\nEXCEPTION: [SEGMENT_NOT_PRESENT] ({:#x}) \n{:#?}\n\n", error_code, interrupt_stack_frame);
+}
+
+/// Handler for the stack segment fault exception
+///
+/// ## Cause
+///
+/// This handler is called by the CPU when the stack is used at an address that isn't canonical. A program doing it
+/// is terminated as if by [`SIGSEGV`], it's fatal in the kernel
+extern "x86-interrupt" fn stack_segment_fault_handler(interrupt_stack_frame: InterruptStackFrame, error_code: u64) {
+    terminate_user_thread(&interrupt_stack_frame, "Stack segment fault", SIGSEGV);
+    panic!("\n\nEXCEPTION: [STACK_SEGMENT_FAULT] ({:#x}) \n{:#?}\n\n", error_code, interrupt_stack_frame);
+}
+
+/// Handler for the general protection fault exception
+///
+/// ## Cause
+///
+/// This handler is called by the CPU when an instruction breaks a rule of protection that isn't about pages, like
+/// running a privileged instruction (`cli`, `hlt`) in user mode or using an address that isn't canonical. A program
+/// doing it is terminated as if by [`SIGSEGV`], it's fatal in the kernel
+extern "x86-interrupt" fn general_protection_fault_handler(interrupt_stack_frame: InterruptStackFrame, error_code: u64) {
+    terminate_user_thread(&interrupt_stack_frame, "General protection fault", SIGSEGV);
+    panic!("\n\nEXCEPTION: [GENERAL_PROTECTION_FAULT] ({:#x}) \n{:#?}\n\n", error_code, interrupt_stack_frame);
+}
+
+/// Handler for the x87 floating point exception
+///
+/// ## Cause
+///
+/// This handler is called by the CPU when an x87 instruction fails and its exception isn't masked, which a program
+/// can do by changing its control word. It's terminated as if by [`SIGFPE`], the kernel doesn't use the FPU
+extern "x86-interrupt" fn x87_floating_point_handler(interrupt_stack_frame: InterruptStackFrame) {
+    terminate_user_thread(&interrupt_stack_frame, "Floating point error", SIGFPE);
+    panic!("\n\nEXCEPTION: [X87_FLOATING_POINT] \n{:#?}\n\n", interrupt_stack_frame);
+}
+
+/// Handler for the alignment check exception
+///
+/// ## Cause
+///
+/// This handler is called by the CPU when user code accesses memory that isn't aligned while the alignment check
+/// flag is set. The program is terminated as if by [`SIGSEGV`]
+extern "x86-interrupt" fn alignment_check_handler(interrupt_stack_frame: InterruptStackFrame, error_code: u64) {
+    terminate_user_thread(&interrupt_stack_frame, "Alignment check", SIGSEGV);
+    panic!("\n\nEXCEPTION: [ALIGNMENT_CHECK] ({:#x}) \n{:#?}\n\n", error_code, interrupt_stack_frame);
+}
+
+/// Handler for the SIMD floating point exception
+///
+/// ## Cause
+///
+/// This handler is called by the CPU when an SSE instruction fails and its exception isn't masked, like the x87
+/// floating point exception. The program is terminated as if by [`SIGFPE`]
+extern "x86-interrupt" fn simd_floating_point_handler(interrupt_stack_frame: InterruptStackFrame) {
+    terminate_user_thread(&interrupt_stack_frame, "SIMD floating point error", SIGFPE);
+    panic!("\n\nEXCEPTION: [SIMD_FLOATING_POINT] \n{:#?}\n\n", interrupt_stack_frame);
+}
+
 /// Handler for the breakpoint exception
 ///
 /// ## Cause
@@ -123,6 +433,49 @@ extern "x86-interrupt" fn breakpoint_handler(interrupt_stack_frame: InterruptSta
     println!("\n\nEXCEPTION: [BREAKPOINT] \n{:#?}\n\n", interrupt_stack_frame);
 }
 
+/// Handler for the page fault exception
+///
+/// ## Cause
+///
+/// This handler is called by the CPU when an address that isn't mapped is accessed, or when the access isn't allowed
+/// by the page (like writing to a read-only page). Writes to the copy-on-write pages of a process and the first
+/// accesses to the pages of its file mappings are resolved here, a process accessing anything else it shouldn't is
+/// terminated and a fault in the kernel is fatal
+extern "x86-interrupt" fn page_fault_handler(interrupt_stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode) {
+    use x86_64::registers::control::Cr2;
+
+    let address = Cr2::read();
+    let copy_on_write = PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+
+    if error_code.contains(copy_on_write) && crate::process::handle_write_fault(address) {
+        return;
+    }
+
+    // A page of a file mapping that wasn't touched yet is read from its file, which may block. The kernel touches
+    // them through the system calls, which map them first (see `crate::process::mmap::populate`)
+    let missing = !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION);
+
+    if missing && error_code.contains(PageFaultErrorCode::USER_MODE) {
+        x86_64::instructions::interrupts::enable();
+
+        let write = error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE);
+        let resolved = crate::process::handle_missing_page(address, write);
+
+        x86_64::instructions::interrupts::disable();
+
+        if resolved {
+            return;
+        }
+    }
+
+    if error_code.contains(PageFaultErrorCode::USER_MODE) {
+        println!("Segmentation fault at {:?} ({:?}), killing the thread", address, error_code);
+        crate::task::exit(-11);
+    }
+
+    panic!("\n\nEXCEPTION: [PAGE_FAULT] at {:?} ({:?}) \n{:#?}\n\n", address, error_code, interrupt_stack_frame);
+}
+
 /// Handler for the double fault exception
 ///
 /// ## Cause
@@ -141,35 +494,140 @@ extern "x86-interrupt" fn double_fault_handler(interrupt_stack_frame: InterruptS
 ///
 /// ## Cause
 ///
-/// This handler is called ten times a second (every 100ms)
-extern "x86-interrupt" fn timer_handler(_interrupt_stack_frame: InterruptStackFrame) {
-    let mut pics = PICS.lock();
-    if pics.check_for_spurious(InterruptIndex::Timer.get_irq_line()) {
+/// This handler is called [`crate::time::TICKS_PER_SECOND`] times a second (every 10ms)
+///
+/// ## Note
+///
+/// The interrupt is acknowledged before the tick is handled, since the scheduler may switch to another thread
+/// and this handler only finishes once the interrupted thread runs again
+extern "x86-interrupt" fn timer_handler(interrupt_stack_frame: InterruptStackFrame) {
+    let spurious = PICS.with(|pics| {
+        if pics.check_for_spurious(InterruptIndex::Timer.get_irq_line()) {
+            return true;
+        }
+
+        pics.end_of_interrupt(InterruptIndex::Timer.as_u8());
+        count_irq(InterruptIndex::Timer.get_irq_line());
+        return false;
+    });
+
+    if spurious {
         return;
     }
 
-    pics.end_of_interrupt(InterruptIndex::Timer.as_u8());
+    crate::time::tick();
+    crate::watchdog::check(&interrupt_stack_frame);
+    crate::sched::on_tick();
+
+    // A program stuck in a loop never makes a system call, so this is where it finds out it was killed
+    if interrupt_stack_frame.code_segment & 3 == 3 {
+        crate::process::signal::check_terminated();
+    }
+}
+
+/// Handler for the interrupt of the timer of the local APIC, which replaces the PIT when the CPU has the TSC-deadline
+/// mode (see [`crate::time::lapic`])
+///
+/// ## Cause
+///
+/// This handler is called on every tick, or once the CPU was idle for a few of them
+extern "x86-interrupt" fn apic_timer_handler(interrupt_stack_frame: InterruptStackFrame) {
+    // Same as the timer of the PIT, the interrupt is acknowledged (by the local APIC) before switching threads
+    crate::time::lapic::handle_interrupt();
+    LOCAL_TIMER_COUNT.fetch_add(1, Ordering::Relaxed);
+    crate::watchdog::check(&interrupt_stack_frame);
+    crate::sched::on_tick();
+
+    if interrupt_stack_frame.code_segment & 3 == 3 {
+        crate::process::signal::check_terminated();
+    }
 }
 
+/// Handler for the spurious interrupts of the local APIC
+///
+/// ## Cause
+///
+/// This handler is called when the local APIC drops an interrupt it was about to deliver, which isn't acknowledged
+extern "x86-interrupt" fn apic_spurious_handler(_interrupt_stack_frame: InterruptStackFrame) {}
+
 /// Handler for the keyboard interrupt
 ///
 /// ## Cause
 ///
 /// This handler is called every time a key on the user keyboard is pressed or released
 extern "x86-interrupt" fn keyboard_handler(_interrupt_stack_frame: InterruptStackFrame) {
-    let mut pics = PICS.lock();
+    PICS.with(|pics| {
+        // If the interrupt is proven to be a spurious IRQ then we just ignore it and don't send an EOI signal
+        if pics.check_for_spurious(InterruptIndex::Keyboard.get_irq_line()) {
+            return;
+        }
 
-    // If the interrupt is proven to be a spurious IRQ then we just ignore it and don't send an EOI signal
-    if pics.check_for_spurious(InterruptIndex::Keyboard.get_irq_line()) {
-        return;
-    }
+        let scancode = crate::ps2::read_data();
+        count_irq(InterruptIndex::Keyboard.get_irq_line());
+
+        crate::task::keyboard::add_scancode(scancode);
+
+        pics.end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+    });
+}
 
-    use x86_64::instructions::port::Port;
+/// Handler for the interrupt of the primary ATA channel (IRQ 14)
+///
+/// ## Cause
+///
+/// This handler is called when a drive of the primary channel finishes a DMA transfer
+extern "x86-interrupt" fn primary_ata_handler(_interrupt_stack_frame: InterruptStackFrame) {
+    ata_interrupt(InterruptIndex::PrimaryAta, 0);
+}
 
-    let mut port = Port::new(0x60);
-    let scancode: u8 = unsafe { port.read() };
+/// Handler for the interrupt of the secondary ATA channel (IRQ 15)
+///
+/// ## Cause
+///
+/// This handler is called when a drive of the secondary channel finishes a DMA transfer
+extern "x86-interrupt" fn secondary_ata_handler(_interrupt_stack_frame: InterruptStackFrame) {
+    ata_interrupt(InterruptIndex::SecondaryAta, 1);
+}
 
-    print!("{}", scancode);
+/// Hands the interrupt of an ATA channel over to the driver, both channels work the same way
+fn ata_interrupt(index: InterruptIndex, channel: usize) {
+    PICS.with(|pics| {
+        // A spurious IRQ of the slave PIC still has to be acknowledged to the master, which doesn't know it's spurious
+        if pics.check_for_spurious(index.get_irq_line()) {
+            pics.end_of_interrupt_master_only();
+            return;
+        }
 
-    pics.end_of_interrupt(InterruptIndex::Keyboard.as_u8());
-}
\ No newline at end of file
+        crate::block::ata::handle_interrupt(channel);
+        count_irq(index.get_irq_line());
+
+        pics.end_of_interrupt(index.get_irq_line());
+    });
+}
+
+/// Handler for the interrupts of the IRQ lines shared by the devices found at runtime, see [`register_irq`]
+///
+/// ## Cause
+///
+/// This handler is called when any of the devices using the line `IRQ` wants the attention of its driver
+extern "x86-interrupt" fn shared_irq_handler<const IRQ: u8>(_interrupt_stack_frame: InterruptStackFrame) {
+    PICS.with(|pics| {
+        if pics.check_for_spurious(IRQ) {
+            if IRQ >= 8 {
+                pics.end_of_interrupt_master_only();
+            }
+
+            return;
+        }
+
+        // The handlers are copied out so they don't run with the table held
+        let handlers = SHARED_HANDLERS.with(|handlers| handlers[(IRQ - FIRST_SHARED_IRQ) as usize]);
+
+        for handler in handlers.into_iter().flatten() {
+            handler();
+        }
+
+        count_irq(IRQ);
+        pics.end_of_interrupt(IRQ);
+    });
+}
@@ -0,0 +1,206 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::process::fd::File;
+use crate::sched::WaitQueue;
+use crate::utils::error::KernelError;
+use crate::utils::IrqCell;
+
+/// A message sent through a [`Channel`]: some bytes and, optionally, handles to files (which may be other channels)
+/// that the receiver gets access to
+#[allow(dead_code)]
+pub struct Message {
+    pub data: Vec<u8>,
+    pub handles: Vec<Arc<dyn File>>
+}
+
+#[allow(dead_code)]
+impl Message {
+    /// Creates a message with just data
+    pub fn new(data: Vec<u8>) -> Self {
+        Message {
+            data,
+            handles: Vec::new()
+        }
+    }
+
+    /// Creates a message that carries handles along with the data
+    pub fn with_handles(data: Vec<u8>, handles: Vec<Arc<dyn File>>) -> Self {
+        Message { data, handles }
+    }
+}
+
+/// One end of a bidirectional message channel, created in pairs by [`Channel::pair`].
+///
+/// Each direction has a bounded queue: sending to a full queue blocks the sender and receiving from an empty one
+/// blocks the receiver, until the other end makes room or sends something. Once one end is dropped the other one
+/// can still receive what's left in its queue, after that every operation fails with [`KernelError::Closed`].
+///
+/// A channel is a [`File`], so it can be put in the file table of a process or sent through another channel
+pub struct Channel {
+    /// The messages sent to this end
+    inbox: Arc<Queue>,
+    /// The messages sent to the other end
+    outbox: Arc<Queue>
+}
+
+#[allow(dead_code)]
+impl Channel {
+    /// Creates both ends of a channel, each end can have up to `capacity` messages waiting to be received
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if `capacity` is 0
+    pub fn pair(capacity: usize) -> Result<(Channel, Channel), KernelError> {
+        if capacity == 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let first = Arc::new(Queue::new(capacity));
+        let second = Arc::new(Queue::new(capacity));
+
+        return Ok((
+            Channel { inbox: first.clone(), outbox: second.clone() },
+            Channel { inbox: second, outbox: first }
+        ));
+    }
+
+    /// Sends `message` to the other end, blocking while its queue is full
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Closed`] if the other end was dropped, the message is dropped with it
+    pub fn send(&self, message: Message) -> Result<(), KernelError> {
+        let mut message = Some(message);
+        let mut result = None;
+
+        self.outbox.senders.wait_until(|| {
+            result = self.outbox.state.with(|state| {
+                if state.closed {
+                    return Some(Err(KernelError::Closed));
+                }
+
+                if state.messages.len() >= state.capacity {
+                    return None;
+                }
+
+                // The queue was allocated with room for `capacity` messages, so this never allocates
+                state.messages.push_back(message.take().unwrap());
+                return Some(Ok(()));
+            });
+
+            result.is_some()
+        });
+
+        self.outbox.receivers.wake_one();
+        return result.unwrap();
+    }
+
+    /// Receives the next message sent to this end, blocking until there's one
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Closed`] if the queue is empty and the other end was dropped
+    pub fn receive(&self) -> Result<Message, KernelError> {
+        let mut result = None;
+
+        self.inbox.receivers.wait_until(|| {
+            result = self.inbox.state.with(|state| {
+                match state.messages.pop_front() {
+                    Some(message) => Some(Ok(message)),
+                    None if state.closed => Some(Err(KernelError::Closed)),
+                    None => None
+                }
+            });
+
+            result.is_some()
+        });
+
+        self.inbox.senders.wake_one();
+        return result.unwrap();
+    }
+
+    /// Receives the next message sent to this end without blocking, returning [`None`] if there's none yet
+    pub fn try_receive(&self) -> Result<Option<Message>, KernelError> {
+        let result = self.inbox.state.with(|state| {
+            match state.messages.pop_front() {
+                Some(message) => Ok(Some(message)),
+                None if state.closed => Err(KernelError::Closed),
+                None => Ok(None)
+            }
+        });
+
+        if let Ok(Some(_)) = result {
+            self.inbox.senders.wake_one();
+        }
+
+        return result;
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        // The messages nobody is going to receive are freed outside the queue, since that deallocates.
+        // The ones sent to the other end stay there, it can still receive them
+        let _unreceived = self.inbox.state.with(|state| core::mem::take(&mut state.messages));
+
+        self.inbox.close();
+        self.outbox.close();
+    }
+}
+
+impl File for Channel {
+    /// Receives a message, copying as much of its data as fits in `buffer`. The handles of the message are dropped
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, KernelError> {
+        let message = self.receive()?;
+        let length = message.data.len().min(buffer.len());
+
+        buffer[..length].copy_from_slice(&message.data[..length]);
+
+        return Ok(length);
+    }
+
+    /// Sends the whole `buffer` as a single message
+    fn write(&self, buffer: &[u8]) -> Result<usize, KernelError> {
+        self.send(Message::new(buffer.to_vec()))?;
+        return Ok(buffer.len());
+    }
+}
+
+/// The messages going in one direction of a channel, together with the threads waiting for them (or for room)
+struct Queue {
+    state: IrqCell<QueueState>,
+    /// The threads waiting for room in the queue
+    senders: WaitQueue,
+    /// The threads waiting for a message
+    receivers: WaitQueue
+}
+
+struct QueueState {
+    messages: VecDeque<Message>,
+    capacity: usize,
+    /// Set once either end is dropped
+    closed: bool
+}
+
+impl Queue {
+    fn new(capacity: usize) -> Self {
+        Queue {
+            state: IrqCell::new(QueueState {
+                messages: VecDeque::with_capacity(capacity),
+                capacity,
+                closed: false
+            }),
+            senders: WaitQueue::new(),
+            receivers: WaitQueue::new()
+        }
+    }
+
+    /// Marks the queue as closed and wakes up every waiter, so they see it
+    fn close(&self) {
+        self.state.with(|state| state.closed = true);
+
+        self.senders.wake_all();
+        self.receivers.wake_all();
+    }
+}
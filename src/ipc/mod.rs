@@ -0,0 +1,4 @@
+mod channel;
+
+#[allow(unused_imports)]
+pub use channel::{Channel, Message};
@@ -1,5 +1,6 @@
 #![feature(abi_x86_interrupt)]
 #![feature(const_mut_refs)]
+#![feature(inline_const)]
 
 #![no_std]
 #![no_main]
@@ -10,39 +11,121 @@ mod vga;
 mod interrupts;
 mod memory;
 mod utils;
+mod task;
+mod time;
+mod sched;
+mod usermode;
+mod syscall;
+mod process;
+mod ipc;
+mod sync;
+mod workqueue;
+mod acpi;
+mod pci;
+mod block;
+mod shell;
+mod serial;
+mod ps2;
+mod rand;
+mod power;
+mod graphics;
+mod virtio;
+mod drivers;
+mod watchdog;
+mod debugcon;
+mod sound;
+mod usb;
+mod smbios;
+mod cmos;
+mod fs;
+mod net;
 
 use core::panic::PanicInfo;
 use bootloader::{BootInfo, entry_point};
 use x86_64::VirtAddr;
-use crate::memory::{create_memory_mapper, InternalFrameAllocator};
+use crate::task::executor::Executor;
+use crate::task::Task;
 
 entry_point!(kernel_main);
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    // The debug console can't be stuck behind a lock, so the message gets out even if the screen can't show it.
+    // It stops being a copy of the console first, so the message isn't there twice
+    debugcon::set_console(false);
+    debugcon_println!("{}", info);
     println!("{}", info);
+    println!("Press R to reboot");
 
-    hlt_loop();
+    x86_64::instructions::interrupts::disable();
+
+    // The interrupts stay off, so the keyboard is polled, 0x13 is the scancode of R being pressed
+    loop {
+        if ps2::poll_data() == Some(0x13) {
+            power::reboot();
+        }
+
+        core::hint::spin_loop();
+    }
 }
 
 fn kernel_main(info: &'static BootInfo) -> ! {
-    unsafe {
-        let mut memory_mapper = create_memory_mapper(VirtAddr::new(info.physical_memory_offset));
-        let mut frame_allocator = InternalFrameAllocator::new(&info.memory_map);
+    debugcon::init();
 
-        memory::init_heap(&mut memory_mapper, &mut frame_allocator).expect("Failed to initialize the heap");
+    unsafe {
+        memory::init(VirtAddr::new(info.physical_memory_offset), &info.memory_map).expect("Failed to initialize the memory");
     }
 
+    sched::init();
+    workqueue::init();
+    time::init();
     interrupts::interrupt_manager::init();
+    cmos::init();
+
+    // The kernel works the same without a serial port, it's only one more place to see its output
+    if serial::init(115200).is_ok() {
+        serial::set_interrupt_driven_tx(true);
+        serial::set_console(cmos::console() == cmos::Console::Serial);
+        drivers::registry::add_platform_device("COM1", "serial");
+    }
+
+    rand::init();
+
+    match ps2::init() {
+        Ok(()) => {
+            println!("PS/2: {:?}", ps2::devices());
+            drivers::registry::add_platform_device("i8042", "ps2");
+        },
+        Err(error) => println!("PS/2: no controller ({:?})", error)
+    }
+
+    syscall::init();
+    acpi::init();
+    smbios::init();
+    time::init_deadline_timer();
+    time::clocksource::init();
+    pci::init();
+    block::init();
+    net::init();
+    block::ramdisk::register_initrd(&info.memory_map);
+    graphics::init();
+    sound::init();
+    usb::init();
+    drivers::registry::probe_all();
+    fs::init();
 
     println!("Hello, World!");
     println!("Approximation of PI: {}", 62832.0 / 20000.0);
 
-    hlt_loop();
-}
+    watchdog::init(watchdog::DEFAULT_TIMEOUT_MS);
+    usermode::demo::run();
+    shell::init();
 
-fn hlt_loop() -> ! {
-    loop {
-        x86_64::instructions::hlt();
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(shell::run()));
+
+    if serial::is_present() {
+        executor.spawn(Task::new(shell::run_serial()));
     }
-}
\ No newline at end of file
+    executor.run();
+}
@@ -0,0 +1,728 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::structures::paging::mapper::TranslateResult;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::memory::{physical_to_virtual, with_paging, kernel_cr3, InternalFrameAllocator};
+use crate::utils::error::KernelError;
+use crate::utils::Mutex;
+
+/// The flags given to the page tables created for user mappings, the final permissions are decided by the last level
+const USER_TABLE_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::USER_ACCESSIBLE);
+
+/// Marks a page that is shared after a fork and must be copied before it's written, it was writable before the fork
+const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+
+/// Marks a page of a [`SharedRegion`], it stays shared after a fork instead of becoming copy-on-write
+const SHARED: PageTableFlags = PageTableFlags::BIT_10;
+
+/// The end of the lower half (exclusive), user mappings are always under it
+pub const USER_SPACE_END: u64 = 0x_8000_0000_0000;
+
+/// The most pages a single [`SharedRegion`] can have, the list of its frames has to fit in a heap block
+const MAX_SHARED_PAGES: usize = 256;
+
+/// How many extra address spaces map each shared user frame, a frame that isn't here is only mapped once.
+/// Always locked after the paging lock
+static SHARED_FRAMES: Mutex<BTreeMap<PhysFrame, usize>> = Mutex::new(BTreeMap::new());
+
+/// A set of page tables of its own, used by a process so it can't see the memory of the others.
+///
+/// The kernel isn't in the higher half (the bootloader puts it, the heap and the physical memory mapping in the
+/// lower half), so every level 4 entry used by the kernel when the address space is created is shared with it and
+/// the user mappings go to the entries the kernel doesn't use. Since the lower level tables are shared, whatever
+/// the kernel maps later inside its entries (like new thread stacks) is seen by every address space
+pub struct AddressSpace {
+    level_4_frame: PhysFrame
+}
+
+impl AddressSpace {
+    /// Creates an address space that only has the mappings of the kernel
+    pub fn new() -> Result<Self, KernelError> {
+        let level_4_frame = with_paging(|_, frame_allocator| frame_allocator.allocate_frame())
+            .ok_or(KernelError::OutOfMemory)?;
+
+        // Both tables are accessed through the physical memory mapping, the new one isn't used by anyone yet
+        unsafe {
+            let table = &mut *table_at(level_4_frame.start_address());
+            let kernel_table = &*table_at(PhysAddr::new(kernel_cr3()));
+
+            table.zero();
+
+            for (entry, kernel_entry) in table.iter_mut().zip(kernel_table.iter()) {
+                if !kernel_entry.is_unused() {
+                    entry.set_addr(kernel_entry.addr(), kernel_entry.flags());
+                }
+            }
+        }
+
+        Ok(AddressSpace { level_4_frame })
+    }
+
+    /// The value CR3 must have for this address space to be active
+    pub fn cr3(&self) -> u64 {
+        self.level_4_frame.start_address().as_u64()
+    }
+
+    /// Maps `pages` new (zeroed) pages starting at `start` so they can be accessed from user mode, the pages are
+    /// writable if `writable` is set and executable if `executable` is set
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if `start` isn't page aligned or if any page would land in a part of the
+    /// address space used by the kernel, or the error of the mapping if it fails
+    #[allow(dead_code)]
+    pub fn map_user_pages(&mut self, start: VirtAddr, pages: usize, writable: bool, executable: bool) -> Result<(), KernelError> {
+        if !start.is_aligned(4096u64) || pages == 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let first_page: Page<Size4KiB> = Page::containing_address(start);
+        let last_page = first_page + (pages as u64 - 1);
+
+        if !is_user_range(first_page, last_page) {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let flags = user_page_flags(writable, executable);
+
+        self.with_mapper(|mapper, frame_allocator| {
+            for page in Page::range_inclusive(first_page, last_page) {
+                let frame = frame_allocator.allocate_frame().ok_or(KernelError::OutOfMemory)?;
+
+                unsafe {
+                    (*table_at(frame.start_address())).zero();
+
+                    // The address space may not be the active one, so there's nothing to flush
+                    mapper.map_to_with_table_flags(page, frame, flags, USER_TABLE_FLAGS, frame_allocator)?.ignore();
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Maps `frame` at `address` so it can be accessed from user mode, like [`AddressSpace::map_user_pages`] does with
+    /// a new page. Returns `false` without mapping anything if the page is already mapped, the frame is freed then
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if `address` isn't page aligned or is in a part of the address space
+    /// used by the kernel, or the error of the mapping if it fails
+    pub fn map_frame(&mut self, address: VirtAddr, frame: UnmappedFrame, writable: bool, executable: bool) -> Result<bool, KernelError> {
+        use x86_64::structures::paging::Translate;
+
+        let page: Page<Size4KiB> = Page::from_start_address(address).map_err(|_| KernelError::InvalidArgument)?;
+
+        if !is_user_range(page, page) {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let flags = user_page_flags(writable, executable);
+
+        self.with_mapper(|mapper, frame_allocator| {
+            if !matches!(mapper.translate(address), TranslateResult::NotMapped) {
+                return Ok(false);
+            }
+
+            // The frame belongs to the mapping from now on
+            let physical = frame.0;
+            core::mem::forget(frame);
+
+            // The page isn't mapped, so it can't be in the TLB
+            if let Err(error) = unsafe { mapper.map_to_with_table_flags(page, physical, flags, USER_TABLE_FLAGS, frame_allocator) } {
+                unsafe { frame_allocator.deallocate_frame(physical) };
+                return Err(KernelError::from(error));
+            }
+
+            Ok(true)
+        })
+    }
+
+    /// Unmaps whatever is mapped in the `pages` pages starting at `start`, the pages that aren't mapped are skipped.
+    /// The frames are freed once nothing maps them anymore
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if the range isn't valid for user mappings (see
+    /// [`AddressSpace::map_user_pages`])
+    pub fn unmap_user_pages(&mut self, start: VirtAddr, pages: usize) -> Result<(), KernelError> {
+        if !start.is_aligned(4096u64) || pages == 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let first_page: Page<Size4KiB> = Page::containing_address(start);
+        let last_page = first_page + (pages as u64 - 1);
+
+        if !is_user_range(first_page, last_page) {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        self.with_mapper(|mapper, frame_allocator| {
+            for page in Page::range_inclusive(first_page, last_page) {
+                // The tables on the way may not even be there
+                if let Ok((frame, flush)) = mapper.unmap(page) {
+                    flush.flush();
+                    unsafe { release_frame(frame, frame_allocator) };
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Creates a copy of this address space for a forked process. Nothing is copied right away, the user pages are
+    /// shared by both address spaces and the writable ones are copied by whoever writes them first (copy-on-write)
+    pub fn fork(&mut self) -> Result<AddressSpace, KernelError> {
+        use x86_64::registers::control::Cr3;
+
+        let child = AddressSpace::new()?;
+
+        let copied = with_paging(|_, frame_allocator| unsafe {
+            let table = &mut *table_at(self.level_4_frame.start_address());
+            let child_table = &mut *table_at(child.level_4_frame.start_address());
+            let kernel_table = &*table_at(PhysAddr::new(kernel_cr3()));
+
+            for index in 0..512 {
+                if table[index].is_unused() || !kernel_table[index].is_unused() {
+                    continue;
+                }
+
+                let copy = copy_table(table[index].frame().unwrap(), 3, frame_allocator)?;
+                child_table[index].set_frame(copy, table[index].flags());
+            }
+
+            Ok(())
+        });
+
+        // The pages that became read-only may still be writable in the TLB
+        if Cr3::read().0 == self.level_4_frame {
+            x86_64::instructions::tlb::flush_all();
+        }
+
+        // If the copy failed the child is dropped, which gives back whatever was already shared with it
+        copied.map(|_| child)
+    }
+
+    /// Maps every page of `region` starting at `start`, so they can be accessed from user mode. The pages are writable
+    /// if `writable` is set, which is only allowed if the region itself is writable
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if the range isn't valid for user mappings (see
+    /// [`AddressSpace::map_user_pages`]) or the region isn't writable, or the error of the mapping if any page
+    /// is already mapped, in which case nothing is mapped
+    pub fn map_shared(&mut self, start: VirtAddr, region: &SharedRegion, writable: bool) -> Result<(), KernelError> {
+        if !start.is_aligned(4096u64) || (writable && !region.writable) {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let first_page: Page<Size4KiB> = Page::containing_address(start);
+        let last_page = first_page + (region.frames.len() as u64 - 1);
+
+        if !is_user_range(first_page, last_page) {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE | SHARED;
+
+        if writable {
+            flags |= PageTableFlags::WRITABLE;
+        }
+
+        let mapped = self.with_mapper(|mapper, frame_allocator| {
+            for (index, &frame) in region.frames.iter().enumerate() {
+                let page = first_page + index as u64;
+
+                // The address space may not be the active one, so there's nothing to flush
+                if let Err(error) = unsafe { mapper.map_to_with_table_flags(page, frame, flags, USER_TABLE_FLAGS, frame_allocator) } {
+                    return Err((index, KernelError::from(error)));
+                }
+
+                *SHARED_FRAMES.lock().entry(frame).or_insert(0) += 1;
+            }
+
+            Ok(())
+        });
+
+        if let Err((mapped_pages, error)) = mapped {
+            if mapped_pages > 0 {
+                self.unmap_shared(start, mapped_pages)?;
+            }
+
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// Unmaps `pages` pages of a [`SharedRegion`] starting at `start`, the frames are freed once
+    /// nothing maps them anymore
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if any of the pages isn't a mapped page of a shared region,
+    /// in which case nothing is unmapped
+    pub fn unmap_shared(&mut self, start: VirtAddr, pages: usize) -> Result<(), KernelError> {
+        use x86_64::structures::paging::Translate;
+
+        if !start.is_aligned(4096u64) || pages == 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let first_page: Page<Size4KiB> = Page::containing_address(start);
+        let last_page = first_page + (pages as u64 - 1);
+
+        if !is_user_range(first_page, last_page) {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        self.with_mapper(|mapper, frame_allocator| {
+            let all_shared = Page::range_inclusive(first_page, last_page).all(|page| {
+                matches!(mapper.translate(page.start_address()), TranslateResult::Mapped { flags, .. } if flags.contains(SHARED))
+            });
+
+            if !all_shared {
+                return Err(KernelError::InvalidArgument);
+            }
+
+            for page in Page::range_inclusive(first_page, last_page) {
+                let (frame, flush) = mapper.unmap(page).unwrap();
+
+                // Flushing the page of an address space that isn't active is harmless
+                flush.flush();
+                unsafe { release_frame(frame, frame_allocator) };
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Resolves a write to a copy-on-write page at `address`, giving this address space a copy of its own (or just
+    /// making the page writable if nobody else maps it anymore). Returns `false` if the page isn't copy-on-write,
+    /// which means the write is really a protection violation
+    pub fn handle_cow_fault(&mut self, address: VirtAddr) -> bool {
+        let page: Page<Size4KiB> = Page::containing_address(address);
+
+        self.with_mapper(|mapper, frame_allocator| {
+            use x86_64::structures::paging::Translate;
+
+            let (frame, flags) = match mapper.translate(page.start_address()) {
+                TranslateResult::Mapped { frame, flags, .. } if flags.contains(COPY_ON_WRITE) => (frame, flags),
+                _ => return false
+            };
+
+            let frame = PhysFrame::<Size4KiB>::containing_address(frame.start_address());
+            let flags = (flags - COPY_ON_WRITE) | PageTableFlags::WRITABLE;
+
+            let shared = SHARED_FRAMES.lock().contains_key(&frame);
+
+            if !shared {
+                unsafe { mapper.update_flags(page, flags).unwrap().flush() };
+                return true;
+            }
+
+            let copy = match frame_allocator.allocate_frame() {
+                Some(copy) => copy,
+                None => return false
+            };
+
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    physical_to_virtual(frame.start_address()).as_ptr::<u8>(),
+                    physical_to_virtual(copy.start_address()).as_mut_ptr::<u8>(),
+                    4096
+                );
+
+                mapper.unmap(page).unwrap().1.flush();
+                release_frame(frame, frame_allocator);
+                mapper.map_to_with_table_flags(page, copy, flags, USER_TABLE_FLAGS, frame_allocator).unwrap().flush();
+            }
+
+            return true;
+        })
+    }
+
+    /// Copies `data` to `address` in this address space, which must already be mapped (see [`AddressSpace::map_user_pages`])
+    #[allow(dead_code)]
+    pub fn write(&mut self, address: VirtAddr, data: &[u8]) -> Result<(), KernelError> {
+        let mut written = 0;
+
+        while written < data.len() {
+            let current = address + written;
+            let frame_address = self.translate(current).ok_or(KernelError::InvalidArgument)?;
+            let chunk = (4096 - (current.as_u64() % 4096) as usize).min(data.len() - written);
+
+            unsafe {
+                let destination = physical_to_virtual(frame_address).as_mut_ptr::<u8>();
+                core::ptr::copy_nonoverlapping(data[written..].as_ptr(), destination, chunk);
+            }
+
+            written += chunk;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the physical address `address` is mapped to in this address space
+    pub fn translate(&mut self, address: VirtAddr) -> Option<PhysAddr> {
+        use x86_64::structures::paging::Translate;
+
+        return self.with_mapper(|mapper, _| mapper.translate_addr(address));
+    }
+
+    /// Copies the bytes at `address` in this address space to `buffer`. The copy goes through the physical memory
+    /// mapping, so it works whatever address space is active and never faults
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if a byte isn't in a page mapped for user mode, see
+    /// [`is_user_memory`]
+    pub fn copy_from_user(&mut self, address: VirtAddr, buffer: &mut [u8]) -> Result<(), KernelError> {
+        if !is_user_memory(address.as_u64(), buffer.len() as u64) {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let mut copied = 0;
+
+        while copied < buffer.len() {
+            let current = address + copied;
+            let physical = self.user_address(current, false).ok_or(KernelError::InvalidArgument)?;
+            let chunk = (4096 - (current.as_u64() % 4096) as usize).min(buffer.len() - copied);
+
+            unsafe {
+                let source = physical_to_virtual(physical).as_ptr::<u8>();
+                core::ptr::copy_nonoverlapping(source, buffer[copied..].as_mut_ptr(), chunk);
+            }
+
+            copied += chunk;
+        }
+
+        Ok(())
+    }
+
+    /// Copies `data` to `address` in this address space, like [`AddressSpace::copy_from_user`]. The pages must be
+    /// writable from user mode, the copy-on-write ones get a copy of their own first
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if a byte isn't in a page user mode can write to
+    pub fn copy_to_user(&mut self, address: VirtAddr, data: &[u8]) -> Result<(), KernelError> {
+        if !is_user_memory(address.as_u64(), data.len() as u64) {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let mut copied = 0;
+
+        while copied < data.len() {
+            let current = address + copied;
+            let physical = self.user_address(current, true).ok_or(KernelError::InvalidArgument)?;
+            let chunk = (4096 - (current.as_u64() % 4096) as usize).min(data.len() - copied);
+
+            unsafe {
+                let destination = physical_to_virtual(physical).as_mut_ptr::<u8>();
+                core::ptr::copy_nonoverlapping(data[copied..].as_ptr(), destination, chunk);
+            }
+
+            copied += chunk;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that user mode can access every page of the `length` bytes at `address` (and write to them if `write`
+    /// is set), the copy-on-write pages written get a copy of their own
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if it can't
+    pub fn check_user_range(&mut self, address: VirtAddr, length: u64, write: bool) -> Result<(), KernelError> {
+        if !is_user_memory(address.as_u64(), length) {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let end = address + length;
+        let mut page = address.align_down(4096u64);
+
+        while page < end {
+            self.user_address(page, write).ok_or(KernelError::InvalidArgument)?;
+            page += 4096u64;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the physical address `address` is mapped to if user mode can access it (and write to it if `write` is
+    /// set). A copy-on-write page that's written is copied first, like on a page fault
+    fn user_address(&mut self, address: VirtAddr, write: bool) -> Option<PhysAddr> {
+        use x86_64::structures::paging::Translate;
+
+        let (physical, flags) = self.with_mapper(|mapper, _| match mapper.translate(address) {
+            TranslateResult::Mapped { frame, offset, flags } => Some((frame.start_address() + offset, flags)),
+            _ => None
+        })?;
+
+        if !flags.contains(PageTableFlags::USER_ACCESSIBLE) {
+            return None;
+        }
+
+        if !write || flags.contains(PageTableFlags::WRITABLE) {
+            return Some(physical);
+        }
+
+        if !self.handle_cow_fault(address) {
+            return None;
+        }
+
+        return self.translate(address);
+    }
+
+    /// Runs `f` with a mapper for this address space and the frame allocator of the kernel
+    fn with_mapper<R>(&mut self, f: impl FnOnce(&mut OffsetPageTable, &mut InternalFrameAllocator) -> R) -> R {
+        with_paging(|kernel_mapper, frame_allocator| {
+            let physical_memory_offset = kernel_mapper.phys_offset();
+
+            // The table belongs to this address space, which is borrowed mutably
+            let mut mapper = unsafe { OffsetPageTable::new(&mut *table_at(self.level_4_frame.start_address()), physical_memory_offset) };
+
+            return f(&mut mapper, frame_allocator);
+        })
+    }
+}
+
+/// A zeroed frame for a user page that isn't mapped yet, so it can be filled (like by reading a file, which can
+/// block) without holding the lock of the address space it goes to, see [`AddressSpace::map_frame`]. The frame is
+/// freed if it's dropped before being mapped. It's also how the kernel gets a page that's too big for the heap and
+/// never mapped anywhere, like the map of the clusters of [`crate::fs::fat::fsck`]
+pub struct UnmappedFrame(PhysFrame);
+
+impl UnmappedFrame {
+    /// Allocates the frame
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::OutOfMemory`] if there are no free frames
+    pub fn new() -> Result<Self, KernelError> {
+        let frame = with_paging(|_, frame_allocator| frame_allocator.allocate_frame()).ok_or(KernelError::OutOfMemory)?;
+        unsafe { (*table_at(frame.start_address())).zero() };
+
+        Ok(UnmappedFrame(frame))
+    }
+
+    /// The content of the frame, through the physical memory mapping
+    pub fn bytes(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(physical_to_virtual(self.0.start_address()).as_mut_ptr(), 4096) }
+    }
+}
+
+impl Drop for UnmappedFrame {
+    fn drop(&mut self) {
+        with_paging(|_, frame_allocator| unsafe { frame_allocator.deallocate_frame(self.0) });
+    }
+}
+
+/// Physical memory that can be mapped by more than one address space at the same time, so processes can share data
+/// without copying it through the kernel. The region holds a reference to its frames, and so does every mapping
+/// of it, so the memory is freed once the region is dropped and nothing maps it anymore
+pub struct SharedRegion {
+    frames: Vec<PhysFrame>,
+    /// Whatever the region may be mapped as writable
+    writable: bool
+}
+
+#[allow(dead_code)]
+impl SharedRegion {
+    /// Allocates a zeroed region of `pages` pages
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if `pages` is 0 or more than [`MAX_SHARED_PAGES`],
+    /// or [`KernelError::OutOfMemory`] if there aren't enough free frames
+    pub fn new(pages: usize, writable: bool) -> Result<Self, KernelError> {
+        if pages == 0 || pages > MAX_SHARED_PAGES {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let mut region = SharedRegion {
+            frames: Vec::with_capacity(pages),
+            writable
+        };
+
+        let allocated: Result<(), KernelError> = with_paging(|_, frame_allocator| {
+            for _ in 0..pages {
+                let frame = frame_allocator.allocate_frame().ok_or(KernelError::OutOfMemory)?;
+
+                unsafe { (*table_at(frame.start_address())).zero() };
+                region.frames.push(frame);
+            }
+
+            Ok(())
+        });
+
+        // On failure the region is dropped, which frees the frames that were already allocated
+        allocated?;
+
+        return Ok(region);
+    }
+
+    pub fn pages(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+}
+
+impl Drop for SharedRegion {
+    fn drop(&mut self) {
+        with_paging(|_, frame_allocator| {
+            for &frame in &self.frames {
+                unsafe { release_frame(frame, frame_allocator) };
+            }
+        });
+    }
+}
+
+impl Drop for AddressSpace {
+    /// Frees every user page and page table, the ones shared with the kernel are left alone
+    fn drop(&mut self) {
+        let level_4_frame = self.level_4_frame;
+
+        with_paging(|_, frame_allocator| unsafe {
+            let table = &*table_at(level_4_frame.start_address());
+            let kernel_table = &*table_at(PhysAddr::new(kernel_cr3()));
+
+            for (entry, kernel_entry) in table.iter().zip(kernel_table.iter()) {
+                if !entry.is_unused() && kernel_entry.is_unused() {
+                    free_table(entry.frame().unwrap(), 3, frame_allocator);
+                }
+            }
+
+            frame_allocator.deallocate_frame(level_4_frame);
+        });
+    }
+}
+
+/// The flags of a user page, see [`AddressSpace::map_user_pages`]
+fn user_page_flags(writable: bool, executable: bool) -> PageTableFlags {
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+
+    if writable {
+        flags |= PageTableFlags::WRITABLE;
+    }
+
+    if !executable {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    return flags;
+}
+
+/// Returns whatever every page between `first` and `last` can be used for user mappings,
+/// which means they are all in level 4 entries that the kernel doesn't use
+pub fn is_user_range(first: Page<Size4KiB>, last: Page<Size4KiB>) -> bool {
+    if last.start_address().as_u64() >= USER_SPACE_END || last < first {
+        return false;
+    }
+
+    let kernel_table = unsafe { &*table_at(PhysAddr::new(kernel_cr3())) };
+
+    let first_index = usize::from(first.p4_index());
+    let last_index = usize::from(last.p4_index());
+
+    return (first_index..=last_index).all(|index| kernel_table[index].is_unused());
+}
+
+/// Returns whatever the `length` bytes at `address` are all in the part of the address space left to user mappings
+/// (see [`is_user_range`]). The level 4 entries of the kernel are shared by every address space, so a pointer given
+/// by a program must be checked with this before the kernel touches it
+pub fn is_user_memory(address: u64, length: u64) -> bool {
+    let end = match address.checked_add(length) {
+        Some(end) if address != 0 && end <= USER_SPACE_END => end,
+        _ => return false
+    };
+
+    if length == 0 {
+        return true;
+    }
+
+    let first = Page::containing_address(VirtAddr::new(address));
+    return is_user_range(first, Page::containing_address(VirtAddr::new(end - 1)));
+}
+
+/// Frees the table at `frame` of the given level (3 for a level 3 table) together with every
+/// table and page under it
+unsafe fn free_table(frame: PhysFrame, level: u8, frame_allocator: &mut InternalFrameAllocator) {
+    let table = &*table_at(frame.start_address());
+
+    for entry in table.iter().filter(|entry| !entry.is_unused()) {
+        // User mappings are always made of 4 KiB pages, so every entry above level 1 is a table
+        let entry_frame = PhysFrame::containing_address(entry.addr());
+
+        if level > 1 {
+            free_table(entry_frame, level - 1, frame_allocator);
+        } else {
+            release_frame(entry_frame, frame_allocator);
+        }
+    }
+
+    frame_allocator.deallocate_frame(frame);
+}
+
+/// Copies the table at `frame` of the given level (and every table under it) for a forked address space, the pages
+/// themselves are shared and the writable ones become copy-on-write in both address spaces, except for the pages
+/// of a [`SharedRegion`], which are meant to be written by both
+unsafe fn copy_table(frame: PhysFrame, level: u8, frame_allocator: &mut InternalFrameAllocator) -> Result<PhysFrame, KernelError> {
+    let copy = frame_allocator.allocate_frame().ok_or(KernelError::OutOfMemory)?;
+
+    let table = &mut *table_at(frame.start_address());
+    let copy_table_ref = &mut *table_at(copy.start_address());
+    copy_table_ref.zero();
+
+    for (entry, copy_entry) in table.iter_mut().zip(copy_table_ref.iter_mut()) {
+        if entry.is_unused() {
+            continue;
+        }
+
+        let entry_frame = PhysFrame::containing_address(entry.addr());
+
+        if level > 1 {
+            let child = copy_table(entry_frame, level - 1, frame_allocator)?;
+            copy_entry.set_frame(child, entry.flags());
+            continue;
+        }
+
+        let mut flags = entry.flags();
+
+        if flags.contains(PageTableFlags::WRITABLE) && !flags.contains(SHARED) {
+            flags = (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE;
+            entry.set_flags(flags);
+        }
+
+        *SHARED_FRAMES.lock().entry(entry_frame).or_insert(0) += 1;
+        copy_entry.set_frame(entry_frame, flags);
+    }
+
+    Ok(copy)
+}
+
+/// Drops one mapping of a user frame, the frame is only freed once no address space maps it anymore
+unsafe fn release_frame(frame: PhysFrame, frame_allocator: &mut InternalFrameAllocator) {
+    let mut shared_frames = SHARED_FRAMES.lock();
+
+    match shared_frames.get_mut(&frame) {
+        Some(1) => {
+            shared_frames.remove(&frame);
+        },
+        Some(count) => *count -= 1,
+        None => frame_allocator.deallocate_frame(frame)
+    }
+}
+
+/// Returns a pointer to the page table stored in the frame at `address`
+fn table_at(address: PhysAddr) -> *mut PageTable {
+    physical_to_virtual(address).as_mut_ptr()
+}
@@ -0,0 +1,144 @@
+use alloc::vec::Vec;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame};
+use x86_64::PhysAddr;
+use crate::memory::{physical_to_virtual, with_paging};
+use crate::utils::error::KernelError;
+
+/// Devices doing DMA with 32 bits addresses (like the IDE bus masters) only reach the first 4 GiB of
+/// physical memory, so every DMA frame is taken from there
+const DMA_LIMIT: u64 = 1 << 32;
+
+/// Memory a device reads or writes on its own (DMA), made of whole frames that are known to be below 4 GiB.
+///
+/// The frames aren't contiguous, so the devices get a list of pages (like a PRD table) to go through,
+/// and the kernel accesses them through the physical memory mapping. The frames are freed when this is dropped
+pub struct DmaBuffer {
+    frames: Vec<PhysFrame>
+}
+
+#[allow(dead_code)]
+impl DmaBuffer {
+    /// Allocates a buffer of `pages` zeroed pages
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::OutOfMemory`] if there aren't enough free frames, or [`KernelError::Unsupported`]
+    /// if the frames handed out aren't below 4 GiB
+    pub fn new(pages: usize) -> Result<Self, KernelError> {
+        let mut buffer = DmaBuffer {
+            frames: Vec::with_capacity(pages)
+        };
+
+        let allocated: Result<(), KernelError> = with_paging(|_, frame_allocator| {
+            for _ in 0..pages {
+                let frame = frame_allocator.allocate_frame().ok_or(KernelError::OutOfMemory)?;
+
+                if frame.start_address().as_u64() + 4096 > DMA_LIMIT {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                    return Err(KernelError::Unsupported);
+                }
+
+                buffer.frames.push(frame);
+            }
+
+            Ok(())
+        });
+
+        // The buffer frees whatever frames it got if the allocation failed halfway
+        allocated?;
+        buffer.zero();
+
+        Ok(buffer)
+    }
+
+    /// Allocates a buffer of `pages` zeroed pages that follow each other in physical memory, for the devices that
+    /// are given a single address for the whole buffer (like the receive ring of the RTL8139). The kernel can then
+    /// access the whole buffer from [`DmaBuffer::page`]`(0)`
+    ///
+    /// ## Errors
+    ///
+    /// Same as [`DmaBuffer::new`], the free frames may also be too scattered for the buffer
+    pub fn new_contiguous(pages: usize) -> Result<Self, KernelError> {
+        let first = with_paging(|_, frame_allocator| {
+            let first = frame_allocator.allocate_contiguous(pages).ok_or(KernelError::OutOfMemory)?;
+
+            if first.start_address().as_u64() + pages as u64 * 4096 > DMA_LIMIT {
+                for frame in PhysFrame::range(first, first + pages as u64) {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                }
+
+                return Err(KernelError::Unsupported);
+            }
+
+            Ok(first)
+        })?;
+
+        let buffer = DmaBuffer {
+            frames: PhysFrame::range(first, first + pages as u64).collect()
+        };
+
+        buffer.zero();
+        Ok(buffer)
+    }
+
+    pub fn pages(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// The size of the buffer in bytes
+    pub fn size(&self) -> usize {
+        self.frames.len() * 4096
+    }
+
+    /// Returns the physical address of the page with the given index, which is what the device is told
+    pub fn physical(&self, page: usize) -> PhysAddr {
+        self.frames[page].start_address()
+    }
+
+    /// Returns a pointer to the page with the given index, for the kernel to access
+    pub fn page(&self, page: usize) -> *mut u8 {
+        physical_to_virtual(self.physical(page)).as_mut_ptr()
+    }
+
+    /// Copies `data` to the start of the buffer
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `data` doesn't fit in the buffer
+    pub fn copy_in(&self, data: &[u8]) {
+        assert!(data.len() <= self.size(), "The data doesn't fit in the DMA buffer");
+
+        for (index, chunk) in data.chunks(4096).enumerate() {
+            unsafe { core::ptr::copy_nonoverlapping(chunk.as_ptr(), self.page(index), chunk.len()) };
+        }
+    }
+
+    /// Copies the start of the buffer into `data`
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `data` is bigger than the buffer
+    pub fn copy_out(&self, data: &mut [u8]) {
+        assert!(data.len() <= self.size(), "The data doesn't fit in the DMA buffer");
+
+        for (index, chunk) in data.chunks_mut(4096).enumerate() {
+            unsafe { core::ptr::copy_nonoverlapping(self.page(index), chunk.as_mut_ptr(), chunk.len()) };
+        }
+    }
+
+    fn zero(&self) {
+        for index in 0..self.pages() {
+            unsafe { core::ptr::write_bytes(self.page(index), 0, 4096) };
+        }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        with_paging(|_, frame_allocator| {
+            for &frame in &self.frames {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+            }
+        });
+    }
+}
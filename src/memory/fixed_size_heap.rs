@@ -1,7 +1,8 @@
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr;
-use crate::println;
+use crate::utils::error::KernelError;
 use crate::utils::Mutex;
+use crate::utils::mutex::RawLock;
 
 /// These are all the different block sizes this allocator will create when initialized.
 /// The distribution of said blocks is based on [`BLOCK_DISTRIBUTIONS`]
@@ -15,8 +16,8 @@ const BLOCK_SIZES: &[usize] = &[ 8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096
 ///
 /// ## Note
 ///
-/// This distribution is not checked so make sure it always sums up to 100% or else memory can be
-/// waste or in the worst case collisions can happen
+/// [`FixedSizeAllocator::init`] refuses distributions that sum up to more than 100%, but a sum lower
+/// than 100% is accepted, so make sure it always sums up to 100% or else memory will be wasted
 const BLOCK_DISTRIBUTIONS: &[f32] = &[10.0 / 100.0; 10]; // Each block size has 10% of the total memory
 
 /// The fixed size allocator rely on a linked list to know the addresses of all the free (unused)
@@ -58,7 +59,14 @@ impl FixedSizeAllocator {
     /// This method is unsafe because the caller must guarantee that `heap_address` and `heap_size`
     /// point to a mapped region in memory and that `memory_size` perfectly fits the distributions
     /// of the blocks
-    pub unsafe fn init(&mut self, heap_address: usize, heap_size: usize) {
+    pub unsafe fn init(&mut self, heap_address: usize, heap_size: usize) -> Result<(), KernelError> {
+        let total_distribution: f32 = BLOCK_DISTRIBUTIONS.iter().sum();
+
+        // Give some room for the rounding errors of the floats
+        if heap_size == 0 || total_distribution > 1.0001 || BLOCK_DISTRIBUTIONS.len() != BLOCK_SIZES.len() {
+            return Err(KernelError::InvalidArgument);
+        }
+
         let mut current_memory_offset = heap_address;
 
         for (index, &block_size) in BLOCK_SIZES.iter().enumerate() {
@@ -71,6 +79,8 @@ impl FixedSizeAllocator {
 
             current_memory_offset += memory_share;
         }
+
+        Ok(())
     }
 
     /// Creates `count` blocks os `block_size` bytes and builds a linked list between then, where the
@@ -110,60 +120,71 @@ impl FixedSizeAllocator {
         }
     }
 
+    /// Returns how many bytes are in the free blocks, counting every block size
+    pub fn free_bytes(&self) -> usize {
+        let mut free = 0;
+
+        for (index, head) in self.heads.iter().enumerate() {
+            let mut node = head.as_deref();
+
+            while let Some(current) = node {
+                free += BLOCK_SIZES[index];
+                node = current.next.as_deref();
+            }
+        }
+
+        return free;
+    }
+
     /// Finds out which block size is better for an allocation that follow the given `layout`.
     /// This method returns [`None`] if no existing block size satisfies the given `layout`
     pub fn block_size_for(layout: &Layout) -> Option<usize> {
         let required_block_size = layout.size().max(layout.align());
         return BLOCK_SIZES.iter().position(|&s| s >= required_block_size);
     }
-}
 
-unsafe impl GlobalAlloc for Mutex<FixedSizeAllocator> {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut allocator = self.lock();
-
-        match FixedSizeAllocator::block_size_for(&layout) {
-            Some(index) => {
-                let head = &mut allocator.heads[index];
-                let head = head.take();
-
-                if let Some(node) = head {
-                    let next_node = node.next.take();
-                    allocator.heads[index] = next_node;
-
-                    return node as *mut MemoryNode as *mut u8;
-                } else {
-                    println!("No block available for size {}", BLOCK_SIZES[index]);
-                }
-            },
-            None => {
-                println!("Failed to allocate heap for layout {:?}: No memory block available", layout);
-            }
-        }
+    /// Takes a free block that fits `layout` out of its list, failing with [`KernelError::OutOfMemory`]
+    /// if there's no block big enough or if all the blocks of the right size are in use
+    fn allocate(&mut self, layout: &Layout) -> Result<*mut u8, KernelError> {
+        let index = FixedSizeAllocator::block_size_for(layout).ok_or(KernelError::OutOfMemory)?;
+        let node = self.heads[index].take().ok_or(KernelError::OutOfMemory)?;
 
-        return ptr::null_mut();
+        self.heads[index] = node.next.take();
+
+        return Ok(node as *mut MemoryNode as *mut u8);
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let mut allocator = self.lock();
+    /// Gives the block at `ptr` back to the list of free blocks of its size
+    ///
+    /// ## Safety
+    ///
+    /// This method is unsafe because the caller must guarantee that `ptr` was returned by [`FixedSizeAllocator::allocate`]
+    /// with the same `layout` and that the block is no longer used
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: &Layout) -> Result<(), KernelError> {
+        let index = FixedSizeAllocator::block_size_for(layout).ok_or(KernelError::InvalidArgument)?;
 
-        match FixedSizeAllocator::block_size_for(&layout) {
-            Some(index) => {
-                let head = &mut allocator.heads[index];
-                let head = head.take();
+        let new_node = MemoryNode {
+            next: self.heads[index].take()
+        };
 
-                let new_node = MemoryNode {
-                    next: head
-                };
+        let new_node_ptr = ptr as *mut MemoryNode;
+        new_node_ptr.write(new_node);
 
-                let new_node_ptr = ptr as *mut MemoryNode;
-                new_node_ptr.write(new_node);
+        self.heads[index] = Some(&mut *new_node_ptr);
 
-                allocator.heads[index] = Some(&mut *new_node_ptr);
-            },
-            None => {
-                println!("Attempt to deallocate a block that doesn't exist, this shouldn't be possible")
-            }
-        }
+        Ok(())
     }
-}
\ No newline at end of file
+}
+
+unsafe impl<L: RawLock> GlobalAlloc for Mutex<FixedSizeAllocator, L> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // The allocator can't print its errors since printing may need to allocate, so a
+        // failed allocation is reported to the caller only through the null pointer
+        return self.lock().allocate(&layout).unwrap_or(ptr::null_mut());
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // A layout without a block size can't come from `alloc`, so there's nothing to give back
+        let _ = self.lock().deallocate(ptr, &layout);
+    }
+}
@@ -0,0 +1,113 @@
+use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB, Translate};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::memory::with_paging;
+use crate::utils::error::KernelError;
+use crate::utils::Mutex;
+
+/// Address where the region reserved for the registers of the devices starts
+pub const MMIO_START: u64 = 0x_7777_0000_0000;
+
+/// The size of the region reserved for the registers of the devices
+const MMIO_SIZE: u64 = 1 << 39;
+
+/// Where the next mapping goes, the mappings are never removed since the drivers live as long as the kernel
+static NEXT: Mutex<u64> = Mutex::new(MMIO_START);
+
+/// Maps the `size` bytes of device registers at `address` as uncached memory, returning where they can be accessed.
+///
+/// Must be called before the first process is created, unless the region already has a mapping, since the address
+/// spaces of the processes only share the level 4 entries the kernel had when they were created
+///
+/// ## Errors
+///
+/// Returns [`KernelError::OutOfMemory`] if the region is full, or the error of the mapping if it fails
+pub fn map(address: PhysAddr, size: usize) -> Result<VirtAddr, KernelError> {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE | PageTableFlags::NO_EXECUTE;
+    return map_with_flags(address, size, flags);
+}
+
+/// Maps the `size` bytes of RAM at `address` as read-only (and cached) memory, for the memory the kernel is handed
+/// and only reads, like the package loaded by the bootloader. The same rules as [`map`] apply
+///
+/// ## Errors
+///
+/// Same as [`map`]
+pub fn map_read_only(address: PhysAddr, size: usize) -> Result<VirtAddr, KernelError> {
+    return map_with_flags(address, size, PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE);
+}
+
+fn map_with_flags(address: PhysAddr, size: usize, flags: PageTableFlags) -> Result<VirtAddr, KernelError> {
+    let first_frame: PhysFrame<Size4KiB> = PhysFrame::containing_address(address);
+    let last_frame: PhysFrame<Size4KiB> = PhysFrame::containing_address(address + (size.max(1) - 1));
+    let frames = PhysFrame::range_inclusive(first_frame, last_frame);
+
+    let start = reserve(frames.count())?;
+    let first_page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(start));
+
+    // The tables created on the way are shared with the later mappings, which may be writable
+    let table_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    let mapped: Result<(), KernelError> = with_paging(|mapper, frame_allocator| {
+        for (index, frame) in PhysFrame::range_inclusive(first_frame, last_frame).enumerate() {
+            let page = first_page + index as u64;
+
+            // These frames aren't usable RAM, so the frame allocator never hands them out
+            unsafe { mapper.map_to_with_table_flags(page, frame, flags, table_flags, frame_allocator)?.flush() };
+        }
+
+        Ok(())
+    });
+
+    mapped?;
+
+    return Ok(VirtAddr::new(start) + (address.as_u64() - first_frame.start_address().as_u64()));
+}
+
+/// Reserves `pages` pages of the region, returning where they start
+fn reserve(pages: usize) -> Result<u64, KernelError> {
+    let mut next = NEXT.lock();
+    let start = *next;
+    let end = start + pages as u64 * 4096;
+
+    if end > MMIO_START + MMIO_SIZE {
+        return Err(KernelError::OutOfMemory);
+    }
+
+    *next = end;
+    return Ok(start);
+}
+
+/// Allocates `size` bytes of zeroed RAM mapped as a single block in the region, for the memory a device reads
+/// through a list of its pages (like the framebuffer of virtio-gpu) that the kernel wants to see as contiguous.
+/// The frames are never freed, use [`translate`] to find them. The same rules as [`map`] apply
+///
+/// ## Errors
+///
+/// Returns [`KernelError::OutOfMemory`] if the region is full or there aren't enough free frames
+pub fn allocate(size: usize) -> Result<VirtAddr, KernelError> {
+    let pages = (size.max(1) + 4095) / 4096;
+    let start = reserve(pages)?;
+
+    // Unlike the registers this is RAM, so it can be cached
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let first_page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(start));
+
+    let mapped: Result<(), KernelError> = with_paging(|mapper, frame_allocator| {
+        for index in 0..pages as u64 {
+            let frame = frame_allocator.allocate_frame().ok_or(KernelError::OutOfMemory)?;
+            unsafe { mapper.map_to(first_page + index, frame, flags, frame_allocator)?.flush() };
+        }
+
+        Ok(())
+    });
+
+    mapped?;
+
+    unsafe { core::ptr::write_bytes(VirtAddr::new(start).as_mut_ptr::<u8>(), 0, pages * 4096) };
+    return Ok(VirtAddr::new(start));
+}
+
+/// Returns the physical address `address` is mapped to, or `None` if it isn't mapped
+pub fn translate(address: VirtAddr) -> Option<PhysAddr> {
+    with_paging(|mapper, _| mapper.translate_addr(address))
+}
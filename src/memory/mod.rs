@@ -1,11 +1,16 @@
 mod fixed_size_heap;
+pub mod stack;
+pub mod address_space;
+pub mod dma;
+pub mod mmio;
 
+use core::sync::atomic::{AtomicU64, Ordering};
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
-use x86_64::structures::paging::{FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB};
-use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB};
 use x86_64::{PhysAddr, VirtAddr};
 use crate::memory::fixed_size_heap::FixedSizeAllocator;
-use crate::utils::Mutex;
+use crate::utils::error::KernelError;
+use crate::utils::{Bitmap, Mutex, TicketMutex};
 
 /// Address where the mapped heap memory starts
 pub const HEAP_START: usize = 0x_4444_4444_0000;
@@ -14,11 +19,81 @@ pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 120 * 1024; // 120 KiB
 
 #[global_allocator]
-pub static ALLOCATOR: Mutex<FixedSizeAllocator> = Mutex::new(FixedSizeAllocator::new());
+pub static ALLOCATOR: TicketMutex<FixedSizeAllocator> = TicketMutex::new(FixedSizeAllocator::new());
+
+/// The page table mapper and the frame allocator used by the kernel, available after [`init`] is called
+static PAGING: Mutex<Option<Paging>> = Mutex::new(None);
+
+/// Where the whole physical memory is mapped, set by [`init`]
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// The physical address of the level 4 table used by the kernel (and the kernel threads), set by [`init`]
+static KERNEL_CR3: AtomicU64 = AtomicU64::new(0);
+
+struct Paging {
+    mapper: OffsetPageTable<'static>,
+    frame_allocator: InternalFrameAllocator
+}
+
+/// Creates the memory mapper and the frame allocator, maps the heap and keeps both of them around
+/// so the rest of the kernel can map memory through [`with_paging`]
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee the entire physical memory is mapped at the given
+/// `physical_memory_offset` and that all the regions of `memory_map` marked as usable are really not being used.
+/// This function should be called only once
+pub unsafe fn init(physical_memory_offset: VirtAddr, memory_map: &'static MemoryMap) -> Result<(), KernelError> {
+    use x86_64::registers::control::Cr3;
+
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
+    KERNEL_CR3.store(Cr3::read().0.start_address().as_u64(), Ordering::Relaxed);
+
+    let mut mapper = create_memory_mapper(physical_memory_offset);
+    let mut frame_allocator = InternalFrameAllocator::new(memory_map, physical_memory_offset)?;
+
+    init_heap(&mut mapper, &mut frame_allocator)?;
+
+    *PAGING.lock() = Some(Paging { mapper, frame_allocator });
+
+    Ok(())
+}
+
+/// Runs `f` with the page table mapper and the frame allocator of the kernel
+///
+/// ## Panics
+///
+/// Panics if called before [`init`]
+pub fn with_paging<R>(f: impl FnOnce(&mut OffsetPageTable<'static>, &mut InternalFrameAllocator) -> R) -> R {
+    let mut paging = PAGING.lock();
+    let paging = paging.as_mut().expect("Memory wasn't initialized yet");
+
+    return f(&mut paging.mapper, &mut paging.frame_allocator);
+}
+
+/// Returns the virtual address where the given physical address can be accessed
+pub fn physical_to_virtual(address: PhysAddr) -> VirtAddr {
+    return VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed) + address.as_u64());
+}
+
+/// Returns the value of CR3 for the address space of the kernel
+pub fn kernel_cr3() -> u64 {
+    KERNEL_CR3.load(Ordering::Relaxed)
+}
+
+/// Returns how many bytes of the heap aren't being used, some of them may be in blocks too small for an allocation
+pub fn heap_free() -> usize {
+    ALLOCATOR.lock().free_bytes()
+}
+
+/// Returns how many physical frames are still free
+pub fn free_frames() -> usize {
+    with_paging(|_, frame_allocator| frame_allocator.free_frames())
+}
 
 /// Maps the heap to [`HEAP_START`] address with the [`HEAP_SIZE`]. This function will also allocate any necessary frames
 /// in order for the heap to be valid
-pub fn init_heap(mapper: &mut impl Mapper<Size4KiB>, frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> Result<(), MapToError<Size4KiB>> {
+pub fn init_heap(mapper: &mut impl Mapper<Size4KiB>, frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> Result<(), KernelError> {
     let page_range = {
         let heap_start = VirtAddr::new(HEAP_START as u64);
         let heap_end = heap_start + HEAP_SIZE - 1u64;
@@ -32,7 +107,7 @@ pub fn init_heap(mapper: &mut impl Mapper<Size4KiB>, frame_allocator: &mut impl
     for page in page_range {
         let frame = frame_allocator
             .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
+            .ok_or(KernelError::OutOfMemory)?;
 
         let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
 
@@ -42,10 +117,8 @@ pub fn init_heap(mapper: &mut impl Mapper<Size4KiB>, frame_allocator: &mut impl
     }
 
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE)
     }
-
-    Ok(())
 }
 
 /// Returns an [`OffsetPageTable`] object used to create mappings in memory
@@ -78,40 +151,87 @@ unsafe fn get_level_4_active_table(physical_memory_offset: VirtAddr) -> &'static
 }
 
 /// General purpose frame allocator used by the kernel to allocate new physical frames when needed
+///
+/// Every physical frame is represented by a bit in a [`Bitmap`], which makes it possible to give frames
+/// back to the allocator when they are no longer needed
 pub struct InternalFrameAllocator {
-    memory_map: &'static MemoryMap,
+    frames: Bitmap<'static>,
+    /// Where the search for the next free frame starts, every frame before this one is known to be used
     next: usize
 }
 
 impl InternalFrameAllocator {
 
-    /// Creates a frame allocators that uses the given memory map for allocations
+    /// Creates a frame allocators that uses the given memory map for allocations. The bitmap used to track
+    /// the frames is stored in the first usable region big enough to hold it
     ///
     /// ## Safety
     ///
     /// This method is unsafe because the caller must guarantee that all frames marked as [`MemoryRegionType::Usable`]
-    /// are really not being used
-    pub unsafe  fn new(memory_map: &'static MemoryMap) -> Self {
-        InternalFrameAllocator {
-            memory_map,
-            next: 0
+    /// are really not being used and that the entire physical memory is mapped at the given `physical_memory_offset`
+    pub unsafe fn new(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Result<Self, KernelError> {
+        let usable_regions = || memory_map.iter().filter(|r| r.region_type == MemoryRegionType::Usable);
+
+        let frame_count = usable_regions().map(|r| r.range.end_frame_number).max().unwrap_or(0) as usize;
+        let bitmap_words = Bitmap::words_for(frame_count);
+        let bitmap_frames = (bitmap_words * 8 + 4095) / 4096;
+
+        let bitmap_region = usable_regions()
+            .find(|r| r.range.end_frame_number - r.range.start_frame_number >= bitmap_frames as u64)
+            .ok_or(KernelError::OutOfMemory)?;
+
+        let bitmap_start_frame = bitmap_region.range.start_frame_number as usize;
+        let bitmap_ptr: *mut u64 = (physical_memory_offset + bitmap_region.range.start_addr()).as_mut_ptr();
+        let mut frames = Bitmap::new(core::slice::from_raw_parts_mut(bitmap_ptr, bitmap_words));
+
+        // Every frame starts as used and only the usable regions are freed, this way the holes
+        // in the memory map (and the padding at the end of the bitmap) are never handed out
+        frames.set_range(0..frames.len());
+
+        for region in usable_regions() {
+            frames.clear_range(region.range.start_frame_number as usize..region.range.end_frame_number as usize);
         }
+
+        frames.set_range(bitmap_start_frame..bitmap_start_frame + bitmap_frames);
+
+        Ok(InternalFrameAllocator {
+            frames,
+            next: 0
+        })
     }
 
-    /// Returns an [`Iterator`] that only returns physical frames marked as [`MemoryRegionType::Usable`]
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        return  self.memory_map.iter()
-            .filter(|r| r.region_type == MemoryRegionType::Usable)
-            .map(|r| r.range.start_addr()..r.range.end_addr())
-            .flat_map(|r| r.step_by(4096))
-            .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)));
+    /// Returns how many physical frames are still free
+    pub fn free_frames(&self) -> usize {
+        return self.frames.len() - self.frames.count_ones();
+    }
+
+    /// Allocates `count` frames that follow each other in physical memory, returning the first one. It's for the
+    /// devices that need a single physically contiguous region, the others take one frame at a time
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrame<Size4KiB>> {
+        let index = self.frames.find_zero_range(count)?;
+
+        self.frames.set_range(index..index + count);
+
+        return Some(PhysFrame::containing_address(PhysAddr::new(index as u64 * 4096)));
     }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for InternalFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        return frame;
+        let index = self.frames.find_first_zero_from(self.next)?;
+
+        self.frames.set(index);
+        self.next = index + 1;
+
+        return Some(PhysFrame::containing_address(PhysAddr::new(index as u64 * 4096)));
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for InternalFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        let index = (frame.start_address().as_u64() / 4096) as usize;
+
+        self.frames.clear(index);
+        self.next = self.next.min(index);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,145 @@
+use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB};
+use x86_64::structures::paging::mapper::UnmapError;
+use x86_64::structures::paging::FrameDeallocator;
+use x86_64::VirtAddr;
+use crate::memory::with_paging;
+use crate::utils::error::KernelError;
+use crate::utils::{Bitmap, Mutex};
+
+/// Address where the region reserved for the kernel stacks starts
+pub const KERNEL_STACKS_START: u64 = 0x_5555_0000_0000;
+
+/// The maximum amount of pages a single kernel stack can have, not counting the guard page
+pub const MAX_STACK_PAGES: usize = 15;
+
+/// Every stack lives in a slot of this size inside the stacks region, the first page of each slot is never
+/// mapped (the guard page), so a stack overflow causes a page fault instead of silently corrupting whatever
+/// is below the stack
+const STACK_SLOT_SIZE: u64 = (MAX_STACK_PAGES as u64 + 1) * 4096;
+
+/// The maximum amount of kernel stacks that can exist at the same time
+const MAX_STACKS: usize = 1024;
+
+/// The value written at the bottom of every stack, if it ever changes the stack grew past its end
+const STACK_CANARY: u64 = 0xC0FF_EE57_ACC0_FFEE;
+
+/// How many words at the bottom of every stack hold the [`STACK_CANARY`]
+const STACK_CANARY_WORDS: usize = 4;
+
+/// Which stack slots are in use
+static SLOTS: Mutex<[u64; Bitmap::words_for(MAX_STACKS)]> = Mutex::new([0; Bitmap::words_for(MAX_STACKS)]);
+
+/// A stack allocated for a kernel thread, with an unmapped guard page right below it.
+///
+/// The stack isn't freed when this is dropped, since the stack may still be in use by the thread
+/// that owns it, use [`free_stack`] once the thread is done with it
+#[derive(Debug)]
+pub struct KernelStack {
+    slot: usize,
+    pages: usize
+}
+
+impl KernelStack {
+    /// The lowest usable address of the stack, the guard page is right below it
+    pub fn bottom(&self) -> VirtAddr {
+        let slot_start = KERNEL_STACKS_START + self.slot as u64 * STACK_SLOT_SIZE;
+        return VirtAddr::new(slot_start + STACK_SLOT_SIZE - self.pages as u64 * 4096);
+    }
+
+    /// The address right after the end of the stack, which is where the stack pointer starts since the stack grows down
+    pub fn top(&self) -> VirtAddr {
+        let slot_start = KERNEL_STACKS_START + self.slot as u64 * STACK_SLOT_SIZE;
+        return VirtAddr::new(slot_start + STACK_SLOT_SIZE);
+    }
+
+    /// The usable size of the stack in bytes
+    #[allow(dead_code)]
+    pub fn size(&self) -> usize {
+        self.pages * 4096
+    }
+
+    /// Returns whatever the canary words at the bottom of the stack are still intact, if they aren't then the stack
+    /// overflowed (or something wrote past the end of a buffer in it) and whatever is running on it can't be trusted
+    pub fn canary_intact(&self) -> bool {
+        let canary = self.bottom().as_ptr::<u64>();
+
+        // The stack stays mapped for as long as this exists
+        return (0..STACK_CANARY_WORDS).all(|i| unsafe { canary.add(i).read_volatile() } == STACK_CANARY);
+    }
+
+    fn page_range(&self) -> impl Iterator<Item = Page<Size4KiB>> {
+        let first_page = Page::containing_address(self.bottom());
+        let last_page = Page::containing_address(self.top() - 1u64);
+
+        return Page::range_inclusive(first_page, last_page);
+    }
+}
+
+/// Allocates and maps a new kernel stack with `pages` pages, plus an unmapped guard page below it.
+/// The lowest words of the stack are filled with a canary, see [`KernelStack::canary_intact`]
+pub fn allocate_stack(pages: usize) -> Result<KernelStack, KernelError> {
+    if pages == 0 || pages > MAX_STACK_PAGES {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let slot = {
+        let mut slots = SLOTS.lock();
+        let mut bitmap = Bitmap::new(&mut *slots);
+
+        let slot = bitmap.find_first_zero().filter(|&slot| slot < MAX_STACKS).ok_or(KernelError::OutOfMemory)?;
+        bitmap.set(slot);
+
+        slot
+    };
+
+    let stack = KernelStack { slot, pages };
+
+    let mapped = with_paging(|mapper, frame_allocator| {
+        for page in stack.page_range() {
+            let frame = frame_allocator.allocate_frame().ok_or(KernelError::OutOfMemory)?;
+            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+            unsafe {
+                mapper.map_to(page, frame, flags, frame_allocator)?.flush();
+            }
+        }
+
+        Ok(())
+    });
+
+    // Give back whatever was mapped before the failure
+    if let Err(error) = mapped {
+        unsafe { free_stack(stack) };
+        return Err(error);
+    }
+
+    let canary = stack.bottom().as_mut_ptr::<u64>();
+
+    for i in 0..STACK_CANARY_WORDS {
+        unsafe { canary.add(i).write_volatile(STACK_CANARY) };
+    }
+
+    return Ok(stack);
+}
+
+/// Unmaps the given stack, giving its frames back to the frame allocator, and frees its slot
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that nothing is using the stack anymore
+pub unsafe fn free_stack(stack: KernelStack) {
+    with_paging(|mapper, frame_allocator| {
+        for page in stack.page_range() {
+            match mapper.unmap(page) {
+                Ok((frame, flush)) => {
+                    flush.flush();
+                    frame_allocator.deallocate_frame(frame);
+                },
+                Err(UnmapError::PageNotMapped) => {},
+                Err(error) => panic!("Failed to unmap kernel stack page {:?}: {:?}", page, error)
+            }
+        }
+    });
+
+    Bitmap::new(&mut *SLOTS.lock()).clear(stack.slot);
+}
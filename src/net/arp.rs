@@ -0,0 +1,363 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::time;
+use crate::time::wheel::{self, TimerEntry, TimerTarget};
+use crate::utils::error::KernelError;
+use crate::utils::{IrqCell, Mutex};
+use crate::workqueue::WorkItem;
+use super::ethernet::{self, EthernetHeader, ETHER_TYPE_ARP, ETHER_TYPE_IPV4};
+use super::{Ipv4Address, MacAddress, NetDevice, PacketBuffer};
+
+/// The size of an ARP packet for IPv4 over Ethernet, the only kind that's handled
+const PACKET_SIZE: usize = 28;
+
+const HARDWARE_ETHERNET: u16 = 1;
+const OPERATION_REQUEST: u16 = 1;
+const OPERATION_REPLY: u16 = 2;
+
+/// How long a learned address is used before it has to be asked for again
+const REACHABLE_TIME_MS: u64 = 60_000;
+
+/// How long to wait for the reply to a request before sending another one, and how many are sent before giving up
+const REQUEST_INTERVAL_MS: u64 = 1000;
+const MAX_REQUESTS: u8 = 3;
+
+/// How many packets can wait for an address to be resolved, the oldest ones are dropped to make room
+const MAX_QUEUED_PACKETS: usize = 4;
+
+/// The neighbors of every device, by the name of the device and their address
+static CACHE: Mutex<BTreeMap<(String, Ipv4Address), Neighbor>> = Mutex::new(BTreeMap::new());
+
+/// Goes through the cache once the earliest deadline of its entries is there, see [`arm_timer`]
+static EXPIRY_WORK: WorkItem = WorkItem::new(expire);
+static EXPIRY_TIMER: IrqCell<Option<&'static TimerEntry>> = IrqCell::new(None);
+
+struct Neighbor {
+    state: State,
+    /// The tick a resolved entry expires at, or the next request of an incomplete one is sent at
+    deadline: u64
+}
+
+enum State {
+    /// The address was learned
+    Resolved(MacAddress),
+    /// The address is being asked for, the packets to send to it are kept until the reply comes
+    Incomplete {
+        device: Arc<dyn NetDevice>,
+        requests: u8,
+        queued: Vec<PacketBuffer>
+    }
+}
+
+/// An entry of the neighbor cache, see [`neighbors`]
+pub struct NeighborInfo {
+    pub device: String,
+    pub address: Ipv4Address,
+    /// The hardware address, [`None`] while it's being asked for
+    pub mac_address: Option<MacAddress>,
+    /// How long until the entry expires, or until the next request is sent
+    pub remaining_ms: u64
+}
+
+/// An ARP packet for IPv4 over Ethernet
+struct ArpPacket {
+    operation: u16,
+    sender_mac: MacAddress,
+    sender_address: Ipv4Address,
+    target_mac: MacAddress,
+    target_address: Ipv4Address
+}
+
+impl ArpPacket {
+    /// Reads the packet at the start of `data`, returning [`None`] if it isn't about IPv4 over Ethernet
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < PACKET_SIZE {
+            return None;
+        }
+
+        let hardware = u16::from_be_bytes([data[0], data[1]]);
+        let protocol = u16::from_be_bytes([data[2], data[3]]);
+
+        if hardware != HARDWARE_ETHERNET || protocol != ETHER_TYPE_IPV4 || data[4] != 6 || data[5] != 4 {
+            return None;
+        }
+
+        Some(ArpPacket {
+            operation: u16::from_be_bytes([data[6], data[7]]),
+            sender_mac: MacAddress(data[8..14].try_into().unwrap()),
+            sender_address: Ipv4Address(data[14..18].try_into().unwrap()),
+            target_mac: MacAddress(data[18..24].try_into().unwrap()),
+            target_address: Ipv4Address(data[24..28].try_into().unwrap())
+        })
+    }
+
+    fn write(&self, buffer: &mut [u8]) {
+        buffer[0..2].copy_from_slice(&HARDWARE_ETHERNET.to_be_bytes());
+        buffer[2..4].copy_from_slice(&ETHER_TYPE_IPV4.to_be_bytes());
+        buffer[4] = 6;
+        buffer[5] = 4;
+        buffer[6..8].copy_from_slice(&self.operation.to_be_bytes());
+        buffer[8..14].copy_from_slice(&self.sender_mac.0);
+        buffer[14..18].copy_from_slice(&self.sender_address.0);
+        buffer[18..24].copy_from_slice(&self.target_mac.0);
+        buffer[24..28].copy_from_slice(&self.target_address.0);
+    }
+
+    /// Sends the packet from `device` to `destination`
+    fn send(&self, device: &Arc<dyn NetDevice>, destination: MacAddress) -> Result<(), KernelError> {
+        let mut packet = PacketBuffer::new(PACKET_SIZE)?;
+        self.write(packet.data_mut()?);
+
+        return ethernet::send(device, destination, ETHER_TYPE_ARP, packet);
+    }
+}
+
+/// Asks every host on the link of `device` which one has `address`
+fn send_request(device: &Arc<dyn NetDevice>, address: Ipv4Address) -> Result<(), KernelError> {
+    let sender_address = super::ipv4_address(device.name()).map_or(Ipv4Address::UNSPECIFIED, |own| own.address);
+
+    let request = ArpPacket {
+        operation: OPERATION_REQUEST,
+        sender_mac: device.mac_address(),
+        sender_address,
+        target_mac: MacAddress::ZERO,
+        target_address: address
+    };
+
+    return request.send(device, MacAddress::BROADCAST);
+}
+
+/// The hardware address an IPv4 multicast address is sent to, the low 23 bits of the group under 01:00:5e
+fn multicast_mac(address: Ipv4Address) -> MacAddress {
+    let [_, b, c, d] = address.0;
+    MacAddress([0x01, 0x00, 0x5E, b & 0x7F, c, d])
+}
+
+/// Returns the hardware address of the neighbor of `device` with the given address, if it's known
+#[allow(dead_code)]
+pub fn lookup(device: &str, address: Ipv4Address) -> Option<MacAddress> {
+    match CACHE.lock().get(&(String::from(device), address)) {
+        Some(Neighbor { state: State::Resolved(mac_address), .. }) => Some(*mac_address),
+        _ => None
+    }
+}
+
+/// Sends the IPv4 packet `packet` from `device` to the neighbor with the address `next_hop`. If its hardware
+/// address isn't known yet the packet waits for it, and it's asked for unless that's being done already. The
+/// packet is dropped if no neighbor answers
+///
+/// ## Errors
+///
+/// Returns the error of sending the packet or the request, a packet that waits isn't sent yet so it has none
+#[allow(dead_code)]
+pub fn send(device: &Arc<dyn NetDevice>, next_hop: Ipv4Address, packet: PacketBuffer) -> Result<(), KernelError> {
+    let own = super::ipv4_address(device.name());
+
+    if next_hop.is_broadcast() || own.map_or(false, |own| own.prefix_length < 31 && own.broadcast() == next_hop) {
+        return ethernet::send(device, MacAddress::BROADCAST, ETHER_TYPE_IPV4, packet);
+    }
+
+    if next_hop.is_multicast() {
+        return ethernet::send(device, multicast_mac(next_hop), ETHER_TYPE_IPV4, packet);
+    }
+
+    let key = (String::from(device.name()), next_hop);
+    let mut cache = CACHE.lock();
+
+    match cache.get_mut(&key) {
+        Some(Neighbor { state: State::Resolved(mac_address), .. }) => {
+            let mac_address = *mac_address;
+            drop(cache);
+
+            ethernet::send(device, mac_address, ETHER_TYPE_IPV4, packet)
+        },
+        Some(Neighbor { state: State::Incomplete { queued, .. }, .. }) => {
+            if queued.len() == MAX_QUEUED_PACKETS {
+                queued.remove(0);
+            }
+
+            queued.push(packet);
+            Ok(())
+        },
+        None => {
+            let queued = vec![packet];
+
+            let deadline = time::ticks() + time::ms_to_ticks(REQUEST_INTERVAL_MS);
+            let state = State::Incomplete { device: device.clone(), requests: 1, queued };
+
+            cache.insert(key, Neighbor { state, deadline });
+            drop(cache);
+
+            arm_timer(deadline);
+            send_request(device, next_hop)
+        }
+    }
+}
+
+/// Returns every entry of the neighbor cache
+#[allow(dead_code)]
+pub fn neighbors() -> Vec<NeighborInfo> {
+    let now = time::ticks();
+
+    CACHE
+        .lock()
+        .iter()
+        .map(|((device, address), neighbor)| NeighborInfo {
+            device: device.clone(),
+            address: *address,
+            mac_address: match neighbor.state {
+                State::Resolved(mac_address) => Some(mac_address),
+                State::Incomplete { .. } => None
+            },
+            remaining_ms: neighbor.deadline.saturating_sub(now) * 1000 / time::ms_to_ticks(1000)
+        })
+        .collect()
+}
+
+/// Forgets every neighbor of the device with the given name, like after its address changed. The packets waiting
+/// for them are dropped
+#[allow(dead_code)]
+pub fn flush(device: &str) {
+    let removed: Vec<Neighbor> = {
+        let mut cache = CACHE.lock();
+        let keys: Vec<_> = cache.keys().filter(|(name, _)| name == device).cloned().collect();
+
+        keys.iter().filter_map(|key| cache.remove(key)).collect()
+    };
+
+    // The packets are freed without the cache held
+    drop(removed);
+}
+
+/// Records that `address` is at `mac_address` on `device`, sending the packets that were waiting for it. Only
+/// updates an entry that's there already unless `create` is set
+fn learn(device: &Arc<dyn NetDevice>, address: Ipv4Address, mac_address: MacAddress, create: bool) {
+    let key = (String::from(device.name()), address);
+    let deadline = time::ticks() + time::ms_to_ticks(REACHABLE_TIME_MS);
+
+    let previous = {
+        let mut cache = CACHE.lock();
+
+        if !create && !cache.contains_key(&key) {
+            return;
+        }
+
+        cache.insert(key, Neighbor { state: State::Resolved(mac_address), deadline })
+    };
+
+    arm_timer(deadline);
+
+    if let Some(Neighbor { state: State::Incomplete { queued, .. }, .. }) = previous {
+        for packet in queued {
+            // The packets that can't be sent are dropped, like they would be by a busy link
+            let _ = ethernet::send(device, mac_address, ETHER_TYPE_IPV4, packet);
+        }
+    }
+}
+
+/// Handles an ARP packet received by `device`: learns the address of the sender and answers if the packet asks for
+/// the address of the device
+fn receive(device: &Arc<dyn NetDevice>, _header: &EthernetHeader, packet: PacketBuffer) {
+    let arp = match ArpPacket::parse(packet.data()) {
+        Some(arp) => arp,
+        None => return
+    };
+
+    // A packet from a host that's still probing whatever its address is free has no sender address
+    if arp.sender_address.is_unspecified() || arp.sender_mac.is_multicast() {
+        return;
+    }
+
+    let own = super::ipv4_address(device.name()).map(|own| own.address);
+    let for_us = own == Some(arp.target_address);
+
+    // The sender is only added to the cache if it's talking to us, it would likely talk to us next (RFC 826)
+    learn(device, arp.sender_address, arp.sender_mac, for_us);
+
+    if for_us && arp.operation == OPERATION_REQUEST {
+        let reply = ArpPacket {
+            operation: OPERATION_REPLY,
+            sender_mac: device.mac_address(),
+            sender_address: arp.target_address,
+            target_mac: arp.sender_mac,
+            target_address: arp.sender_address
+        };
+
+        let _ = reply.send(device, arp.sender_mac);
+    }
+}
+
+/// Makes sure the expiry runs at `deadline` or earlier
+fn arm_timer(deadline: u64) {
+    // The timer is held while it's moved, so two threads don't both hang it on the wheel
+    EXPIRY_TIMER.with(|timer| {
+        let timer = timer.expect("ARP wasn't initialized yet");
+
+        // The timer is on the wheel until it expires, only an earlier deadline moves it
+        if timer.has_expired() || timer.deadline() > deadline {
+            unsafe {
+                wheel::set_deadline(timer, deadline);
+                wheel::insert(timer);
+            }
+        }
+    });
+}
+
+/// Removes the resolved entries that expired and sends the requests that are due, dropping the incomplete entries
+/// that were asked for [`MAX_REQUESTS`] times already. Run on the network work queue once the timer expires
+fn expire() {
+    let now = time::ticks();
+    let mut requests = Vec::new();
+    let mut removed = Vec::new();
+
+    let next_deadline = {
+        let mut cache = CACHE.lock();
+        let due: Vec<_> = cache
+            .iter()
+            .filter(|(_, neighbor)| neighbor.deadline <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in due {
+            let neighbor = cache.get_mut(&key).unwrap();
+
+            match &mut neighbor.state {
+                State::Incomplete { device, requests: sent, .. } if *sent < MAX_REQUESTS => {
+                    *sent += 1;
+                    neighbor.deadline = now + time::ms_to_ticks(REQUEST_INTERVAL_MS);
+                    requests.push((device.clone(), key.1));
+                },
+                _ => removed.extend(cache.remove(&key))
+            }
+        }
+
+        cache.values().map(|neighbor| neighbor.deadline).min()
+    };
+
+    // The packets of the entries that were given up on are freed without the cache held
+    drop(removed);
+
+    if let Some(deadline) = next_deadline {
+        arm_timer(deadline);
+    }
+
+    for (device, address) in requests {
+        let _ = send_request(&device, address);
+    }
+}
+
+/// Registers the handler of the ARP packets and makes the timer of the cache, called by [`super::init`] once the
+/// network work queue is there
+pub fn init() {
+    let target = TimerTarget::Work(super::work_queue(), &EXPIRY_WORK);
+
+    // The timer never moves since it's never freed
+    let timer: &'static TimerEntry = Box::leak(Box::new(TimerEntry::new(0, target)));
+    EXPIRY_TIMER.with(|slot| *slot = Some(timer));
+
+    ethernet::register_handler(ETHER_TYPE_ARP, receive);
+}
@@ -0,0 +1,182 @@
+use alloc::sync::Arc;
+use x86_64::PhysAddr;
+use crate::memory::dma::DmaBuffer;
+use crate::utils::error::KernelError;
+
+/// The size of the memory of a packet, a page is enough for the biggest Ethernet frame with its headers
+pub const CAPACITY: usize = 4096;
+
+/// The space kept free in front of the data of a new packet, so the protocols can add their headers on the way down
+/// without moving it (Ethernet, IPv6 with a few options and TCP with every option fit in it)
+pub const HEADROOM: usize = 256;
+
+/// The biggest data a new packet can hold, what's left of the page after the headroom
+pub const MAX_DATA: usize = CAPACITY - HEADROOM;
+
+/// The content of a packet going through the network stack, from the driver of the card to the protocols or the
+/// other way around. The memory is a page that devices can reach with DMA (see [`DmaBuffer`]), so the drivers can
+/// give it to the card as it is.
+///
+/// The memory is shared by the clones of the buffer (like a packet that's queued while it's also sent), and each
+/// clone has its own view of it: the data starts after the headroom and the protocols add or remove their headers
+/// at the front. A clone that changes the data while the memory is shared gets a copy of its own first
+pub struct PacketBuffer {
+    memory: Arc<DmaBuffer>,
+    /// Where the data starts and ends in the page
+    start: usize,
+    end: usize
+}
+
+#[allow(dead_code)]
+impl PacketBuffer {
+    /// Makes a packet with `length` zeroed bytes of data and [`HEADROOM`] bytes free in front of it
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if `length` is more than [`MAX_DATA`], or
+    /// [`KernelError::OutOfMemory`] if there's no free frame for it
+    pub fn new(length: usize) -> Result<Self, KernelError> {
+        if length > MAX_DATA {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        Ok(PacketBuffer {
+            memory: Arc::new(DmaBuffer::new(1)?),
+            start: HEADROOM,
+            end: HEADROOM + length
+        })
+    }
+
+    /// Makes a packet with a copy of `data`, see [`PacketBuffer::new`]
+    ///
+    /// ## Errors
+    ///
+    /// Same as [`PacketBuffer::new`]
+    pub fn from_bytes(data: &[u8]) -> Result<Self, KernelError> {
+        let mut packet = Self::new(data.len())?;
+        packet.data_mut()?.copy_from_slice(data);
+
+        return Ok(packet);
+    }
+
+    /// Makes a packet from a frame a card wrote to the start of `memory`, the driver gives away the page it got it
+    /// in instead of copying it. There's no headroom, the headers are all there already
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `memory` isn't a single page or `length` doesn't fit in it
+    pub fn from_dma(memory: DmaBuffer, length: usize) -> Self {
+        assert!(memory.pages() == 1 && length <= CAPACITY, "The packet doesn't fit in the DMA buffer");
+
+        PacketBuffer {
+            memory: Arc::new(memory),
+            start: 0,
+            end: length
+        }
+    }
+
+    /// The size of the data in bytes
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// How many bytes of headers can still be added in front of the data
+    pub fn headroom(&self) -> usize {
+        self.start
+    }
+
+    /// The data, starting with the outermost header that's still there
+    pub fn data(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.memory.page(0).add(self.start), self.len()) }
+    }
+
+    /// The data to change
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::OutOfMemory`] if the memory is shared and there's no free frame for the copy
+    pub fn data_mut(&mut self) -> Result<&mut [u8], KernelError> {
+        self.make_unique()?;
+
+        let (start, end) = (self.start, self.end);
+        Ok(&mut self.bytes_mut()[start..end])
+    }
+
+    /// Adds `length` bytes in front of the data for a header, returning them to be filled
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::NoSpace`] if there isn't enough headroom left, otherwise same as
+    /// [`PacketBuffer::data_mut`]
+    pub fn push_header(&mut self, length: usize) -> Result<&mut [u8], KernelError> {
+        if length > self.start {
+            return Err(KernelError::NoSpace);
+        }
+
+        self.make_unique()?;
+        self.start -= length;
+
+        let start = self.start;
+        Ok(&mut self.bytes_mut()[start..start + length])
+    }
+
+    /// Removes the first `length` bytes of the data (the header of the protocol that's done with the packet),
+    /// returning them
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if the data is shorter than `length`, the packet is left as it was
+    pub fn pull_header(&mut self, length: usize) -> Result<&[u8], KernelError> {
+        if length > self.len() {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        self.start += length;
+        Ok(unsafe { core::slice::from_raw_parts(self.memory.page(0).add(self.start - length), length) })
+    }
+
+    /// Cuts the data at `length` bytes, like the padding a frame got to reach the minimum size of Ethernet. Nothing
+    /// changes if the data is already shorter
+    pub fn truncate(&mut self, length: usize) {
+        self.end = self.end.min(self.start + length);
+    }
+
+    /// The physical address of the data, which is what the card is told to send. The data is all in one page
+    pub fn physical(&self) -> PhysAddr {
+        self.memory.physical(0) + self.start as u64
+    }
+
+    /// The whole page, only called once the memory isn't shared anymore
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        debug_assert!(Arc::strong_count(&self.memory) == 1);
+        unsafe { core::slice::from_raw_parts_mut(self.memory.page(0), CAPACITY) }
+    }
+
+    /// Copies the memory to a page of its own if a clone shares it, so the data can be changed
+    fn make_unique(&mut self) -> Result<(), KernelError> {
+        if Arc::strong_count(&self.memory) == 1 {
+            return Ok(());
+        }
+
+        let memory = DmaBuffer::new(1)?;
+        unsafe { core::ptr::copy_nonoverlapping(self.memory.page(0), memory.page(0), CAPACITY) };
+
+        self.memory = Arc::new(memory);
+        return Ok(());
+    }
+}
+
+impl Clone for PacketBuffer {
+    /// Another view of the same memory, nothing is copied until one of them changes the data
+    fn clone(&self) -> Self {
+        PacketBuffer {
+            memory: self.memory.clone(),
+            start: self.start,
+            end: self.end
+        }
+    }
+}
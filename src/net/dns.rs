@@ -0,0 +1,193 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::utils::error::KernelError;
+use crate::utils::Mutex;
+use super::udp::UdpSocket;
+use super::Ipv4Address;
+
+/// The port the DNS servers listen on
+const SERVER_PORT: u16 = 53;
+
+/// How long to wait for the answer to a query before sending it again, and how many times it's sent
+const TIMEOUT_MS: u64 = 2000;
+const ATTEMPTS: usize = 3;
+
+/// The size of the header of a message, and the biggest message sent over UDP without EDNS
+const HEADER_SIZE: usize = 12;
+const MAX_MESSAGE_SIZE: usize = 512;
+
+/// The longest label of a name and the longest name, in bytes
+const MAX_LABEL_LENGTH: usize = 63;
+const MAX_NAME_LENGTH: usize = 253;
+
+/// Recursion Desired in the flags of a query, and the Response bit and the response code in the flags of an answer
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const FLAG_RESPONSE: u16 = 0x8000;
+const RCODE_MASK: u16 = 0x000F;
+const RCODE_NAME_ERROR: u16 = 3;
+
+/// The type of the records with an IPv4 address, and the class of the internet
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+
+/// The server the queries are sent to, see [`set_server`]
+static SERVER: Mutex<Option<Ipv4Address>> = Mutex::new(None);
+
+/// Sets the server the names are resolved by, or leaves the resolver without one with [`None`]
+pub fn set_server(server: Option<Ipv4Address>) {
+    *SERVER.lock() = server;
+}
+
+/// Returns the server the names are resolved by, if there's one
+#[allow(dead_code)]
+pub fn server() -> Option<Ipv4Address> {
+    *SERVER.lock()
+}
+
+/// Returns the IPv4 addresses of the host called `name` (like `example.com`), asking the server set with
+/// [`set_server`]. The query is sent again if no answer comes in time. A name that's already an address is given
+/// back as it is
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if `name` isn't a valid host name, [`KernelError::NoDevice`] if there's
+/// no server, [`KernelError::NotFound`] if the name doesn't exist or has no IPv4 address,
+/// [`KernelError::Timeout`] if the server never answered, [`KernelError::Io`] if it failed to answer, otherwise the
+/// error of sending the query
+pub fn resolve(name: &str) -> Result<Vec<Ipv4Address>, KernelError> {
+    if let Some(address) = Ipv4Address::parse(name) {
+        return Ok(vec![address]);
+    }
+
+    let server = server().ok_or(KernelError::NoDevice)?;
+    let socket = UdpSocket::bind(0)?;
+
+    // The identifier tells the answer apart from the late answers to the queries that came before
+    let identifier = crate::rand::next_u64() as u16;
+    let query = build_query(identifier, name)?;
+
+    let mut answer = Vec::new();
+    answer.try_reserve_exact(MAX_MESSAGE_SIZE).map_err(|_| KernelError::OutOfMemory)?;
+    answer.resize(MAX_MESSAGE_SIZE, 0);
+
+    for _ in 0..ATTEMPTS {
+        socket.send_to(server, SERVER_PORT, &query)?;
+
+        loop {
+            let (size, source, port) = match socket.receive_from(&mut answer, Some(TIMEOUT_MS)) {
+                Ok(received) => received,
+                Err(KernelError::Timeout) => break,
+                Err(error) => return Err(error)
+            };
+
+            // Whatever doesn't come from the server or doesn't answer this query is ignored
+            if source != server || port != SERVER_PORT {
+                continue;
+            }
+
+            if let Some(result) = parse_answer(identifier, &answer[..size]) {
+                return result;
+            }
+        }
+    }
+
+    return Err(KernelError::Timeout);
+}
+
+/// Makes a query for the A records of `name`
+fn build_query(identifier: u16, name: &str) -> Result<Vec<u8>, KernelError> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let mut query = Vec::with_capacity(HEADER_SIZE + name.len() + 6);
+
+    query.extend_from_slice(&identifier.to_be_bytes());
+    query.extend_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    // One question, no answer, no authority and no additional record
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > MAX_LABEL_LENGTH {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+
+    query.push(0);
+    query.extend_from_slice(&TYPE_A.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    return Ok(query);
+}
+
+/// Returns the offset right after the name starting at `offset` in `message`, [`None`] if it goes past the end. A
+/// name that ends with a pointer (the compression of RFC 1035) ends with the pointer
+fn skip_name(message: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let length = *message.get(offset)? as usize;
+
+        match length {
+            0 => return Some(offset + 1),
+            length if length & 0xC0 == 0xC0 => return Some(offset + 2).filter(|&end| end <= message.len()),
+            length => offset += 1 + length
+        }
+    }
+}
+
+/// Reads the answer to the query with the given identifier, returning [`None`] if `message` isn't that answer
+fn parse_answer(identifier: u16, message: &[u8]) -> Option<Result<Vec<Ipv4Address>, KernelError>> {
+    if message.len() < HEADER_SIZE || u16::from_be_bytes([message[0], message[1]]) != identifier {
+        return None;
+    }
+
+    let flags = u16::from_be_bytes([message[2], message[3]]);
+
+    if flags & FLAG_RESPONSE == 0 {
+        return None;
+    }
+
+    match flags & RCODE_MASK {
+        0 => {},
+        RCODE_NAME_ERROR => return Some(Err(KernelError::NotFound)),
+        _ => return Some(Err(KernelError::Io))
+    }
+
+    let questions = u16::from_be_bytes([message[4], message[5]]);
+    let answers = u16::from_be_bytes([message[6], message[7]]);
+    let mut offset = HEADER_SIZE;
+
+    for _ in 0..questions {
+        // The name, the type and the class
+        offset = skip_name(message, offset)? + 4;
+    }
+
+    let mut addresses = Vec::new();
+
+    // The aliases (CNAME) come with the addresses of the name they stand for, so only the addresses are looked at
+    for _ in 0..answers {
+        offset = skip_name(message, offset)?;
+        let record = message.get(offset..offset + 10)?;
+
+        let kind = u16::from_be_bytes([record[0], record[1]]);
+        let class = u16::from_be_bytes([record[2], record[3]]);
+        let length = u16::from_be_bytes([record[8], record[9]]) as usize;
+        let data = message.get(offset + 10..offset + 10 + length)?;
+
+        if kind == TYPE_A && class == CLASS_IN && length == 4 {
+            addresses.push(Ipv4Address(data.try_into().unwrap()));
+        }
+
+        offset += 10 + length;
+    }
+
+    if addresses.is_empty() {
+        return Some(Err(KernelError::NotFound));
+    }
+
+    return Some(Ok(addresses));
+}
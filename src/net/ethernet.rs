@@ -0,0 +1,197 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::utils::error::KernelError;
+use crate::utils::Mutex;
+use super::{MacAddress, NetDevice, PacketBuffer};
+
+/// The size of the header of an Ethernet II frame, without the VLAN tag
+pub const HEADER_SIZE: usize = 14;
+
+/// The EtherTypes of the protocols the stack knows about, the type of what the frame carries
+#[allow(dead_code)]
+pub const ETHER_TYPE_IPV4: u16 = 0x0800;
+#[allow(dead_code)]
+pub const ETHER_TYPE_ARP: u16 = 0x0806;
+#[allow(dead_code)]
+pub const ETHER_TYPE_IPV6: u16 = 0x86DD;
+
+/// The smallest EtherType, the field is the size of the payload (IEEE 802.3) below it, which isn't handled
+const MIN_ETHER_TYPE: u16 = 0x0600;
+
+/// Called with the payload of every frame of its EtherType received by a device, the header is already pulled from
+/// the packet
+pub type Handler = fn(&Arc<dyn NetDevice>, &EthernetHeader, PacketBuffer);
+
+/// The handlers of the protocols with their EtherType, see [`register_handler`]
+static HANDLERS: Mutex<Vec<(u16, Handler)>> = Mutex::new(Vec::new());
+
+/// The header of an Ethernet II frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EthernetHeader {
+    pub destination: MacAddress,
+    pub source: MacAddress,
+    pub ether_type: u16
+}
+
+#[allow(dead_code)]
+impl EthernetHeader {
+    /// Reads the header at the start of `frame`, returning [`None`] if the frame is too short or isn't an Ethernet II
+    /// frame
+    pub fn parse(frame: &[u8]) -> Option<Self> {
+        if frame.len() < HEADER_SIZE {
+            return None;
+        }
+
+        let ether_type = u16::from_be_bytes([frame[12], frame[13]]);
+
+        if ether_type < MIN_ETHER_TYPE {
+            return None;
+        }
+
+        Some(EthernetHeader {
+            destination: MacAddress(frame[0..6].try_into().unwrap()),
+            source: MacAddress(frame[6..12].try_into().unwrap()),
+            ether_type
+        })
+    }
+
+    /// Writes the header to the first [`HEADER_SIZE`] bytes of `buffer`
+    pub fn write(&self, buffer: &mut [u8]) {
+        buffer[0..6].copy_from_slice(&self.destination.0);
+        buffer[6..12].copy_from_slice(&self.source.0);
+        buffer[12..14].copy_from_slice(&self.ether_type.to_be_bytes());
+    }
+}
+
+/// Registers the handler of the frames with the given EtherType, replacing the one that was there. The frames with
+/// an EtherType that has no handler (like IPv6 for now) are dropped
+#[allow(dead_code)]
+pub fn register_handler(ether_type: u16, handler: Handler) {
+    let mut handlers = HANDLERS.lock();
+
+    match handlers.iter_mut().find(|(registered, _)| *registered == ether_type) {
+        Some(entry) => entry.1 = handler,
+        None => handlers.push((ether_type, handler))
+    }
+}
+
+/// Sends `packet` to `destination` with the given EtherType, adding the header in front of it. The source is the
+/// address the device has now, see [`set_mac_address`]
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NetworkDown`] if the interface of the device is down, [`KernelError::InvalidArgument`] if
+/// the packet is bigger than the MTU of the device, [`KernelError::NoSpace`] if it has no room left for the header,
+/// otherwise the error of sending it
+#[allow(dead_code)]
+pub fn send(
+    device: &Arc<dyn NetDevice>,
+    destination: MacAddress,
+    ether_type: u16,
+    mut packet: PacketBuffer
+) -> Result<(), KernelError> {
+    if packet.len() > device.mtu() {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let header = EthernetHeader {
+        destination,
+        source: device.mac_address(),
+        ether_type
+    };
+
+    header.write(packet.push_header(HEADER_SIZE)?);
+
+    return transmit(device, packet);
+}
+
+/// Gives `device` the whole frame `packet`, counting it in the statistics of the interface. What sends frames
+/// without [`send`] (like smoltcp) goes through here, so bringing the interface down stops it too
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NetworkDown`] if the interface of the device is down, otherwise the error of sending the
+/// frame (see [`NetDevice::transmit`])
+pub fn transmit(device: &Arc<dyn NetDevice>, packet: PacketBuffer) -> Result<(), KernelError> {
+    let state = device.interface();
+
+    if !state.is_up() {
+        return Err(KernelError::NetworkDown);
+    }
+
+    let size = packet.len();
+    let result = device.transmit(packet);
+
+    match result {
+        Ok(()) => state.count_sent(size),
+        Err(KernelError::Busy) => state.count_transmit_drop(),
+        Err(_) => state.count_transmit_error()
+    }
+
+    return result;
+}
+
+/// Gives `device` another hardware address, the frames are sent from it and only the ones sent to it (or to a group)
+/// are received from now on
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if the address is a group address or is all zeroes, which a device
+/// can't have, or the error of the device (like [`KernelError::Unsupported`] if it can't change its address)
+#[allow(dead_code)]
+pub fn set_mac_address(device: &Arc<dyn NetDevice>, address: MacAddress) -> Result<(), KernelError> {
+    if address.is_multicast() || address == MacAddress::ZERO {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    return device.set_mac_address(address);
+}
+
+/// Sets the receive callback of `device`, so the frames it receives go to the handlers of their EtherType
+pub fn attach(device: &Arc<dyn NetDevice>) {
+    // The device has the callback, which would keep it alive forever if it had the device too
+    let weak = Arc::downgrade(device);
+
+    device.set_receive_callback(Box::new(move |packet| {
+        if let Some(device) = weak.upgrade() {
+            receive(&device, packet);
+        }
+    }));
+}
+
+/// Checks that a frame received by `device` was meant for it and gives its payload to the handler of its EtherType,
+/// counting it in the statistics of the interface
+fn receive(device: &Arc<dyn NetDevice>, mut packet: PacketBuffer) {
+    let state = device.interface();
+
+    if !state.is_up() {
+        state.count_receive_drop();
+        return;
+    }
+
+    state.count_received(packet.len());
+
+    let header = match EthernetHeader::parse(packet.data()) {
+        Some(header) => header,
+        None => {
+            state.count_receive_error();
+            return;
+        }
+    };
+
+    // The cards usually filter the frames already, unless they're promiscuous. The broadcast is a group address
+    if header.destination != device.mac_address() && !header.destination.is_multicast() {
+        return;
+    }
+
+    let handler = HANDLERS.lock().iter().find(|(ether_type, _)| *ether_type == header.ether_type).map(|entry| entry.1);
+
+    match handler {
+        Some(handler) => {
+            packet.pull_header(HEADER_SIZE).unwrap();
+            handler(device, &header, packet);
+        },
+        None => state.count_receive_drop()
+    }
+}
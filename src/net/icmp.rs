@@ -0,0 +1,162 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU16, Ordering};
+use crate::sched::WaitQueue;
+use crate::time::clocksource;
+use crate::utils::error::KernelError;
+use crate::utils::{IrqCell, Mutex};
+use super::ipv4::{self, Ipv4Header, PROTOCOL_ICMP};
+use super::{Ipv4Address, PacketBuffer};
+
+/// The size of the header of an echo message, the type, the code, the checksum, the identifier and the sequence
+/// number
+const HEADER_SIZE: usize = 8;
+
+const TYPE_ECHO_REPLY: u8 = 0;
+const TYPE_ECHO_REQUEST: u8 = 8;
+
+/// The size of the data of the echo requests sent, like the one of most ping programs
+pub const ECHO_DATA_SIZE: usize = 56;
+
+/// The identifier of the next call to [`ping`], which tells its replies apart from the ones of the other calls
+static NEXT_IDENTIFIER: AtomicU16 = AtomicU16::new(1);
+
+/// The calls to [`ping`] waiting for a reply
+static WAITERS: Mutex<Vec<Arc<Waiter>>> = Mutex::new(Vec::new());
+
+/// An echo reply received for an echo request sent by [`ping`]
+#[derive(Clone, Copy, Debug)]
+pub struct EchoReply {
+    pub source: Ipv4Address,
+    pub sequence: u16,
+    pub ttl: u8,
+    /// The size of the data of the reply, without the headers
+    pub size: usize,
+    /// How long the reply took to come back, in nanoseconds
+    pub rtt_ns: u64
+}
+
+struct Waiter {
+    identifier: u16,
+    sequence: u16,
+    /// When the request was sent, in nanoseconds of the clock source
+    sent_at: u64,
+    reply: IrqCell<Option<EchoReply>>,
+    queue: WaitQueue
+}
+
+/// Writes the header of an echo message with its checksum in front of the data of `packet`
+fn push_echo_header(packet: &mut PacketBuffer, kind: u8, identifier: u16, sequence: u16) -> Result<(), KernelError> {
+    let header = packet.push_header(HEADER_SIZE)?;
+
+    header[0] = kind;
+    header[1] = 0;
+    header[2..4].copy_from_slice(&[0, 0]);
+    header[4..6].copy_from_slice(&identifier.to_be_bytes());
+    header[6..8].copy_from_slice(&sequence.to_be_bytes());
+
+    let data = packet.data_mut()?;
+    let sum = ipv4::checksum(&[data]);
+    data[2..4].copy_from_slice(&sum.to_be_bytes());
+
+    return Ok(());
+}
+
+/// Sends an echo request to `destination` and waits up to `timeout_ms` milliseconds for its reply. Each call uses
+/// an identifier of its own, `sequence` is for the caller to number the requests
+///
+/// ## Errors
+///
+/// Returns [`KernelError::Timeout`] if no reply came in time, otherwise the error of sending the request (like
+/// [`KernelError::NotFound`] if there's no route to the destination)
+pub fn ping(destination: Ipv4Address, sequence: u16, timeout_ms: u64) -> Result<EchoReply, KernelError> {
+    let identifier = NEXT_IDENTIFIER.fetch_add(1, Ordering::Relaxed);
+
+    // The data is the usual pattern of bytes counting up, only its size matters
+    let mut packet = PacketBuffer::new(ECHO_DATA_SIZE)?;
+
+    for (index, byte) in packet.data_mut()?.iter_mut().enumerate() {
+        *byte = index as u8;
+    }
+
+    push_echo_header(&mut packet, TYPE_ECHO_REQUEST, identifier, sequence)?;
+
+    let waiter = Arc::new(Waiter {
+        identifier,
+        sequence,
+        sent_at: clocksource::now_ns(),
+        reply: IrqCell::new(None),
+        queue: WaitQueue::new()
+    });
+
+    // The waiter is there before the request goes out, the reply may be faster than this thread
+    WAITERS.lock().push(waiter.clone());
+
+    let result = ipv4::send(destination, PROTOCOL_ICMP, packet).and_then(|_| {
+        waiter.queue.wait_until_timeout(timeout_ms, || waiter.reply.with(|reply| reply.is_some()));
+        waiter.reply.with(|reply| reply.take()).ok_or(KernelError::Timeout)
+    });
+
+    WAITERS.lock().retain(|other| !Arc::ptr_eq(other, &waiter));
+    return result;
+}
+
+/// Handles an ICMP message received for this host: answers the echo requests and hands the echo replies to the
+/// call of [`ping`] waiting for them. The other messages are dropped
+fn receive(header: &Ipv4Header, mut packet: PacketBuffer) {
+    let data = packet.data();
+
+    if data.len() < HEADER_SIZE || ipv4::checksum(&[data]) != 0 {
+        return;
+    }
+
+    let kind = data[0];
+    let identifier = u16::from_be_bytes([data[4], data[5]]);
+    let sequence = u16::from_be_bytes([data[6], data[7]]);
+
+    match kind {
+        TYPE_ECHO_REQUEST => {
+            // The reply is the request with another type, so the data is sent back in the same packet. The requests
+            // sent to a broadcast address aren't answered, so a host can't make the whole network answer it
+            if header.destination.is_broadcast() || header.destination.is_multicast() {
+                return;
+            }
+
+            if packet.pull_header(HEADER_SIZE).is_err() {
+                return;
+            }
+
+            if push_echo_header(&mut packet, TYPE_ECHO_REPLY, identifier, sequence).is_ok() {
+                let _ = ipv4::send(header.source, PROTOCOL_ICMP, packet);
+            }
+        },
+        TYPE_ECHO_REPLY => {
+            let arrived_at = clocksource::now_ns();
+
+            let waiter = WAITERS
+                .lock()
+                .iter()
+                .find(|waiter| waiter.identifier == identifier && waiter.sequence == sequence)
+                .cloned();
+
+            if let Some(waiter) = waiter {
+                let reply = EchoReply {
+                    source: header.source,
+                    sequence,
+                    ttl: header.ttl,
+                    size: data.len() - HEADER_SIZE,
+                    rtt_ns: arrived_at.saturating_sub(waiter.sent_at)
+                };
+
+                waiter.reply.with(|slot| *slot = Some(reply));
+                waiter.queue.wake_all();
+            }
+        },
+        _ => {}
+    }
+}
+
+/// Registers the handler of the ICMP messages, called by [`super::init`]
+pub fn init() {
+    ipv4::register_handler(PROTOCOL_ICMP, receive);
+}
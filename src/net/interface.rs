@@ -0,0 +1,157 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use crate::utils::error::KernelError;
+use super::{InterfaceAddress, MacAddress};
+
+/// The traffic of an interface since it was found, see [`statistics`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Statistics {
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    /// The frames that were broken, like the ones too short for their header
+    pub rx_errors: u64,
+    /// The frames nothing wanted, like the ones of an unknown protocol or the ones received while the interface is
+    /// down
+    pub rx_dropped: u64,
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    /// The frames the device failed to send
+    pub tx_errors: u64,
+    /// The frames the device had no room for
+    pub tx_dropped: u64
+}
+
+/// What the stack keeps about a device besides the device itself: whatever it's up and the counters of its traffic.
+/// The Ethernet layer keeps it up to date, the device holds it (see [`super::NetDevice::interface`])
+pub struct InterfaceState {
+    up: AtomicBool,
+    rx_packets: AtomicU64,
+    rx_bytes: AtomicU64,
+    rx_errors: AtomicU64,
+    rx_dropped: AtomicU64,
+    tx_packets: AtomicU64,
+    tx_bytes: AtomicU64,
+    tx_errors: AtomicU64,
+    tx_dropped: AtomicU64
+}
+
+impl InterfaceState {
+    /// The state of a device that was just found, an interface starts up
+    pub const fn new() -> Self {
+        InterfaceState {
+            up: AtomicBool::new(true),
+            rx_packets: AtomicU64::new(0),
+            rx_bytes: AtomicU64::new(0),
+            rx_errors: AtomicU64::new(0),
+            rx_dropped: AtomicU64::new(0),
+            tx_packets: AtomicU64::new(0),
+            tx_bytes: AtomicU64::new(0),
+            tx_errors: AtomicU64::new(0),
+            tx_dropped: AtomicU64::new(0)
+        }
+    }
+
+    /// Whatever the interface sends and receives, an interface that's down drops every frame
+    pub fn is_up(&self) -> bool {
+        self.up.load(Ordering::Relaxed)
+    }
+
+    pub fn count_received(&self, bytes: usize) {
+        self.rx_packets.fetch_add(1, Ordering::Relaxed);
+        self.rx_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn count_receive_error(&self) {
+        self.rx_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_receive_drop(&self) {
+        self.rx_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_sent(&self, bytes: usize) {
+        self.tx_packets.fetch_add(1, Ordering::Relaxed);
+        self.tx_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn count_transmit_error(&self) {
+        self.tx_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_transmit_drop(&self) {
+        self.tx_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn statistics(&self) -> Statistics {
+        Statistics {
+            rx_packets: self.rx_packets.load(Ordering::Relaxed),
+            rx_bytes: self.rx_bytes.load(Ordering::Relaxed),
+            rx_errors: self.rx_errors.load(Ordering::Relaxed),
+            rx_dropped: self.rx_dropped.load(Ordering::Relaxed),
+            tx_packets: self.tx_packets.load(Ordering::Relaxed),
+            tx_bytes: self.tx_bytes.load(Ordering::Relaxed),
+            tx_errors: self.tx_errors.load(Ordering::Relaxed),
+            tx_dropped: self.tx_dropped.load(Ordering::Relaxed)
+        }
+    }
+}
+
+/// Everything about an interface at once, see [`ifconfig`]
+#[derive(Clone, Debug)]
+pub struct InterfaceInfo {
+    pub name: String,
+    pub mac_address: MacAddress,
+    pub mtu: usize,
+    /// Whatever the device is connected to the network
+    pub link_up: bool,
+    /// Whatever the interface was brought up, see [`set_up`]
+    pub up: bool,
+    pub address: Option<InterfaceAddress>,
+    pub statistics: Statistics
+}
+
+/// Brings the interface of the device with the given name up or down. The neighbors it learned are forgotten when
+/// it goes down, it keeps its address
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NoDevice`] if there's no such device
+pub fn set_up(device: &str, up: bool) -> Result<(), KernelError> {
+    let device = super::find(device).ok_or(KernelError::NoDevice)?;
+
+    device.interface().up.store(up, Ordering::Relaxed);
+
+    if !up {
+        super::arp::flush(device.name());
+    }
+
+    return Ok(());
+}
+
+/// Returns the counters of the traffic of the device with the given name, [`None`] if there's no such device
+#[allow(dead_code)]
+pub fn statistics(device: &str) -> Option<Statistics> {
+    let device = super::find(device)?;
+    return Some(device.interface().statistics());
+}
+
+/// Returns everything about each device, in the order they were found
+pub fn ifconfig() -> Vec<InterfaceInfo> {
+    super::devices()
+        .into_iter()
+        .map(|device| {
+            let state = device.interface();
+
+            InterfaceInfo {
+                name: String::from(device.name()),
+                mac_address: device.mac_address(),
+                mtu: device.mtu(),
+                link_up: device.link_up(),
+                up: state.is_up(),
+                address: super::ipv4_address(device.name()),
+                statistics: state.statistics()
+            }
+        })
+        .collect()
+}
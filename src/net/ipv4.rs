@@ -0,0 +1,344 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU16, Ordering};
+use crate::utils::error::KernelError;
+use crate::utils::Mutex;
+use super::ethernet::{self, EthernetHeader, ETHER_TYPE_IPV4};
+use super::{arp, InterfaceAddress, Ipv4Address, NetDevice, PacketBuffer};
+
+/// The size of a header without options, which is what the packets sent have
+pub const HEADER_SIZE: usize = 20;
+
+/// The protocols carried by IPv4 that the stack knows about
+#[allow(dead_code)]
+pub const PROTOCOL_ICMP: u8 = 1;
+#[allow(dead_code)]
+pub const PROTOCOL_TCP: u8 = 6;
+#[allow(dead_code)]
+pub const PROTOCOL_UDP: u8 = 17;
+
+/// The TTL of the packets sent, how many routers they can go through
+pub const DEFAULT_TTL: u8 = 64;
+
+/// The Don't Fragment flag and the More Fragments flag in the flags and fragment offset field, the offset is the low
+/// 13 bits
+const FLAG_DONT_FRAGMENT: u16 = 0x4000;
+const FLAG_MORE_FRAGMENTS: u16 = 0x2000;
+const FRAGMENT_OFFSET_MASK: u16 = 0x1FFF;
+
+/// Called with the payload of every packet of its protocol received for this host, see [`register_handler`]
+pub type Handler = fn(&Ipv4Header, PacketBuffer);
+
+/// The handlers of the protocols with their number, see [`register_handler`]
+static HANDLERS: Mutex<Vec<(u8, Handler)>> = Mutex::new(Vec::new());
+
+/// The routes added with [`add_route`], the networks of the devices are routes too without being here
+static ROUTES: Mutex<Vec<Route>> = Mutex::new(Vec::new());
+
+/// The identification of the next packet sent, only used to put fragments back together, which never happens to
+/// the packets sent since they aren't fragmented
+static NEXT_IDENTIFICATION: AtomicU16 = AtomicU16::new(1);
+
+/// The header of a received packet
+#[derive(Clone, Copy, Debug)]
+#[allow(dead_code)]
+pub struct Ipv4Header {
+    pub source: Ipv4Address,
+    pub destination: Ipv4Address,
+    pub protocol: u8,
+    pub ttl: u8,
+    /// The size of the header with its options, in bytes
+    pub header_length: usize,
+    /// The size of the packet with the header, in bytes
+    pub total_length: usize
+}
+
+impl Ipv4Header {
+    /// Reads the header at the start of `data`, returning [`None`] if it isn't a valid IPv4 header (the checksum
+    /// included) or the packet is longer than `data`
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < HEADER_SIZE || data[0] >> 4 != 4 {
+            return None;
+        }
+
+        let header_length = (data[0] & 0xF) as usize * 4;
+        let total_length = u16::from_be_bytes([data[2], data[3]]) as usize;
+
+        if header_length < HEADER_SIZE || total_length < header_length || total_length > data.len() {
+            return None;
+        }
+
+        // The sum of a header with its checksum is zero
+        if checksum(&[&data[..header_length]]) != 0 {
+            return None;
+        }
+
+        Some(Ipv4Header {
+            source: Ipv4Address(data[12..16].try_into().unwrap()),
+            destination: Ipv4Address(data[16..20].try_into().unwrap()),
+            protocol: data[9],
+            ttl: data[8],
+            header_length,
+            total_length
+        })
+    }
+}
+
+/// A route to a network, taken by the packets to the addresses in it that aren't in a smaller network with a
+/// route of its own
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Route {
+    /// The network, the default route is `0.0.0.0/0`
+    pub network: InterfaceAddress,
+    /// The router the packets go through, [`None`] if the network is on the link of the device
+    pub gateway: Option<Ipv4Address>,
+    /// The name of the device the packets are sent from
+    pub device: String
+}
+
+/// Computes the internet checksum (RFC 1071) of the concatenation of `parts`, like a pseudo-header followed by a
+/// segment. The result is ready to be written in big-endian, and the sum of data with a valid checksum is zero
+pub fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    let mut odd = false;
+
+    for part in parts {
+        for &byte in part.iter() {
+            // The bytes at an even offset of the whole data are the high halves of the words
+            sum += if odd { byte as u32 } else { (byte as u32) << 8 };
+            odd = !odd;
+
+            if sum > 0xFFFF_0000 {
+                sum = (sum & 0xFFFF) + (sum >> 16);
+            }
+        }
+    }
+
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+
+    return !(sum as u16);
+}
+
+/// Registers the handler of the packets with the given protocol number, replacing the one that was there. The
+/// packets of a protocol that has no handler are dropped
+#[allow(dead_code)]
+pub fn register_handler(protocol: u8, handler: Handler) {
+    let mut handlers = HANDLERS.lock();
+
+    match handlers.iter_mut().find(|(registered, _)| *registered == protocol) {
+        Some(entry) => entry.1 = handler,
+        None => handlers.push((protocol, handler))
+    }
+}
+
+/// Adds a route, replacing the one to the same network if there's one already. The default gateway is the route to
+/// `0.0.0.0/0`, see [`set_default_gateway`]
+#[allow(dead_code)]
+pub fn add_route(route: Route) {
+    let mut routes = ROUTES.lock();
+
+    routes.retain(|other| other.network != route.network);
+    routes.push(route);
+}
+
+/// Removes the route to the given network, returning whatever there was one
+#[allow(dead_code)]
+pub fn remove_route(network: InterfaceAddress) -> bool {
+    let mut routes = ROUTES.lock();
+    let count = routes.len();
+
+    routes.retain(|route| route.network != network);
+    return routes.len() != count;
+}
+
+/// Sends the packets to the addresses that have no other route to `gateway`, or takes that route away with [`None`].
+/// The device is the one whose network has the gateway
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if no device is on the network of the gateway
+#[allow(dead_code)]
+pub fn set_default_gateway(gateway: Option<Ipv4Address>) -> Result<(), KernelError> {
+    let network = InterfaceAddress { address: Ipv4Address::UNSPECIFIED, prefix_length: 0 };
+
+    let gateway = match gateway {
+        Some(gateway) => gateway,
+        None => {
+            remove_route(network);
+            return Ok(());
+        }
+    };
+
+    let device = super::ipv4_addresses()
+        .into_iter()
+        .find(|(_, address)| address.contains(gateway))
+        .map(|(device, _)| device)
+        .ok_or(KernelError::InvalidArgument)?;
+
+    add_route(Route { network, gateway: Some(gateway), device });
+    return Ok(());
+}
+
+/// Returns every route, the networks of the devices first
+#[allow(dead_code)]
+pub fn routes() -> Vec<Route> {
+    let mut routes: Vec<Route> = super::ipv4_addresses()
+        .into_iter()
+        .map(|(device, address)| Route {
+            network: address.network(),
+            gateway: None,
+            device
+        })
+        .collect();
+
+    routes.extend(ROUTES.lock().iter().cloned());
+    return routes;
+}
+
+/// Finds the route to `destination`, the one to the smallest network that has it. Returns the device to send from
+/// with its address and the address of the next hop, which is the destination itself or a gateway
+fn route(destination: Ipv4Address) -> Option<(Arc<dyn NetDevice>, Ipv4Address, Ipv4Address)> {
+    let route = routes()
+        .into_iter()
+        .filter(|route| route.network.contains(destination))
+        .max_by_key(|route| route.network.prefix_length)?;
+
+    let device = super::find(&route.device)?;
+    let source = super::ipv4_address(&route.device)?.address;
+
+    // The broadcasts and the groups are sent to the link as they are, whatever route they take
+    let next_hop = match route.gateway {
+        Some(gateway) if !destination.is_broadcast() && !destination.is_multicast() => gateway,
+        _ => destination
+    };
+
+    Some((device, source, next_hop))
+}
+
+/// Returns the address the packets sent to `destination` come from, the one of the device the route goes through.
+/// The protocols that sum the addresses in their checksum need it before the packet is sent
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NotFound`] if there's no route to the destination
+pub fn source_address(destination: Ipv4Address) -> Result<Ipv4Address, KernelError> {
+    if is_local(destination) {
+        return Ok(destination);
+    }
+
+    route(destination).map(|(_, source, _)| source).ok_or(KernelError::NotFound)
+}
+
+/// Returns whatever `address` is one of the addresses of this host
+fn is_local(address: Ipv4Address) -> bool {
+    super::ipv4_addresses().iter().any(|(_, own)| own.address == address)
+}
+
+/// Sends `packet` to `destination` with the given protocol, adding the header in front of it. The source is the
+/// address of the device the route goes through. A packet to this host is received without going out
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NotFound`] if there's no route to the destination, [`KernelError::InvalidArgument`] if
+/// the packet is bigger than the MTU of the device (it isn't fragmented), [`KernelError::NoSpace`] if it has no room
+/// left for the header, otherwise the error of sending it
+#[allow(dead_code)]
+pub fn send(destination: Ipv4Address, protocol: u8, mut packet: PacketBuffer) -> Result<(), KernelError> {
+    let (device, source, next_hop) = if is_local(destination) {
+        (None, destination, destination)
+    } else {
+        let (device, source, next_hop) = route(destination).ok_or(KernelError::NotFound)?;
+        (Some(device), source, next_hop)
+    };
+
+    let total_length = HEADER_SIZE + packet.len();
+
+    if total_length > u16::MAX as usize || device.as_ref().map_or(false, |device| total_length > device.mtu()) {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let identification = NEXT_IDENTIFICATION.fetch_add(1, Ordering::Relaxed);
+    let header = packet.push_header(HEADER_SIZE)?;
+
+    header[0] = 0x45;
+    header[1] = 0;
+    header[2..4].copy_from_slice(&(total_length as u16).to_be_bytes());
+    header[4..6].copy_from_slice(&identification.to_be_bytes());
+    header[6..8].copy_from_slice(&FLAG_DONT_FRAGMENT.to_be_bytes());
+    header[8] = DEFAULT_TTL;
+    header[9] = protocol;
+    header[10..12].copy_from_slice(&[0, 0]);
+    header[12..16].copy_from_slice(&source.0);
+    header[16..20].copy_from_slice(&destination.0);
+
+    let sum = checksum(&[&*header]);
+    header[10..12].copy_from_slice(&sum.to_be_bytes());
+
+    match device {
+        Some(device) => arp::send(&device, next_hop, packet),
+        None => {
+            // The handlers may send an answer right away, so the packet is received later on the network work queue
+            super::work_queue().enqueue_fn(move || receive_local(packet));
+            Ok(())
+        }
+    }
+}
+
+/// Checks a packet received by `device` and gives its payload to the handler of its protocol if it's for this host.
+/// The packets that are for other hosts are dropped, nothing is forwarded
+fn receive(device: &Arc<dyn NetDevice>, _header: &EthernetHeader, packet: PacketBuffer) {
+    let header = match Ipv4Header::parse(packet.data()) {
+        Some(header) => header,
+        None => return
+    };
+
+    let own = super::ipv4_address(device.name());
+
+    let for_us = match own {
+        Some(own) => {
+            header.destination == own.address || (own.prefix_length < 31 && header.destination == own.broadcast())
+        },
+        None => false
+    };
+
+    if !for_us && !header.destination.is_broadcast() && !header.destination.is_multicast() {
+        return;
+    }
+
+    deliver(header, packet);
+}
+
+/// Receives a packet sent by this host to itself
+fn receive_local(packet: PacketBuffer) {
+    if let Some(header) = Ipv4Header::parse(packet.data()) {
+        deliver(header, packet);
+    }
+}
+
+/// Gives the payload of a packet for this host to the handler of its protocol
+fn deliver(header: Ipv4Header, mut packet: PacketBuffer) {
+    // The fragments aren't put back together, which leaves the packets that were too big for a link on the way
+    let fragment = u16::from_be_bytes([packet.data()[6], packet.data()[7]]);
+
+    if fragment & FLAG_MORE_FRAGMENTS != 0 || fragment & FRAGMENT_OFFSET_MASK != 0 || header.ttl == 0 {
+        return;
+    }
+
+    let handler = HANDLERS.lock().iter().find(|(protocol, _)| *protocol == header.protocol).map(|entry| entry.1);
+
+    if let Some(handler) = handler {
+        // The frame may be longer than the packet, the minimum size of Ethernet is reached with padding
+        packet.truncate(header.total_length);
+        packet.pull_header(header.header_length).unwrap();
+
+        handler(&header, packet);
+    }
+}
+
+/// Registers the handler of the IPv4 packets, called by [`super::init`]
+pub fn init() {
+    ethernet::register_handler(ETHER_TYPE_IPV4, receive);
+}
@@ -0,0 +1,367 @@
+pub mod arp;
+pub mod buffer;
+pub mod dns;
+pub mod ethernet;
+pub mod icmp;
+pub mod interface;
+pub mod ipv4;
+pub mod rtl8139;
+#[cfg(feature = "smoltcp")]
+pub mod smoltcp;
+pub mod socket;
+pub mod tftp;
+pub mod udp;
+pub mod virtio_net;
+
+pub use self::buffer::PacketBuffer;
+pub use self::interface::{ifconfig, set_up, InterfaceState};
+
+use core::fmt;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::drivers::registry;
+use crate::utils::error::KernelError;
+use crate::utils::{IrqCell, Mutex};
+use crate::workqueue::{WorkItem, WorkQueue};
+
+/// The MTU of Ethernet, the biggest packet a device sends without the header of its link
+pub const DEFAULT_MTU: usize = 1500;
+
+/// The address the first device gets, the router it goes through and the DNS server, the ones QEMU gives to the
+/// guest of its user network. There's no other way to get them from the network (like DHCP) yet
+const DEFAULT_ADDRESS: InterfaceAddress = InterfaceAddress { address: Ipv4Address([10, 0, 2, 15]), prefix_length: 24 };
+const DEFAULT_GATEWAY: Ipv4Address = Ipv4Address([10, 0, 2, 2]);
+const DEFAULT_DNS_SERVER: Ipv4Address = Ipv4Address([10, 0, 2, 3]);
+
+/// The work queue the drivers hand the received packets over on, see [`schedule`]
+pub const WORK_QUEUE_NAME: &str = "net";
+
+/// The queue of [`WORK_QUEUE_NAME`], kept here since looking it up takes a lock the interrupt handlers can't take
+static QUEUE: IrqCell<Option<&'static WorkQueue>> = IrqCell::new(None);
+
+/// The devices found by the drivers, in the order they were found
+static DEVICES: Mutex<Vec<Arc<dyn NetDevice>>> = Mutex::new(Vec::new());
+
+/// The IPv4 address of each device that has one, by the name of the device
+static ADDRESSES: Mutex<BTreeMap<String, InterfaceAddress>> = Mutex::new(BTreeMap::new());
+
+/// The hardware address of a network device
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct MacAddress(pub [u8; 6]);
+
+#[allow(dead_code)]
+impl MacAddress {
+    pub const BROADCAST: MacAddress = MacAddress([0xFF; 6]);
+    pub const ZERO: MacAddress = MacAddress([0; 6]);
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    /// Whatever the address is a group of devices (which includes the broadcast), bit 0 of the first byte is set
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 1 != 0
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", a, b, c, d, e, g)
+    }
+}
+
+impl fmt::Debug for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// An IPv4 address
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Ipv4Address(pub [u8; 4]);
+
+#[allow(dead_code)]
+impl Ipv4Address {
+    pub const UNSPECIFIED: Ipv4Address = Ipv4Address([0; 4]);
+    pub const BROADCAST: Ipv4Address = Ipv4Address([0xFF; 4]);
+
+    /// Reads an address in the dotted notation, like `10.0.2.15`
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut bytes = [0; 4];
+        let mut parts = text.split('.');
+
+        for byte in &mut bytes {
+            *byte = parts.next()?.parse().ok()?;
+        }
+
+        if parts.next().is_some() {
+            return None;
+        }
+
+        Some(Ipv4Address(bytes))
+    }
+
+    pub fn to_u32(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    pub fn from_u32(value: u32) -> Self {
+        Ipv4Address(value.to_be_bytes())
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        *self == Self::UNSPECIFIED
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    /// Whatever the address is a group of hosts, the addresses of 224.0.0.0/4
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0xF0 == 0xE0
+    }
+}
+
+impl fmt::Display for Ipv4Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d] = self.0;
+        write!(f, "{}.{}.{}.{}", a, b, c, d)
+    }
+}
+
+impl fmt::Debug for Ipv4Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// The IPv4 address of a device with the length of the prefix of its network, like `10.0.2.15/24`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct InterfaceAddress {
+    pub address: Ipv4Address,
+    pub prefix_length: u8
+}
+
+#[allow(dead_code)]
+impl InterfaceAddress {
+    /// Reads an address with the length of its prefix, like `10.0.2.15/24`
+    pub fn parse(text: &str) -> Option<Self> {
+        let (address, prefix_length) = text.split_once('/')?;
+        let prefix_length = prefix_length.parse().ok().filter(|&length| length <= 32)?;
+
+        Some(InterfaceAddress { address: Ipv4Address::parse(address)?, prefix_length })
+    }
+
+    /// The mask of the network, the first [`InterfaceAddress::prefix_length`] bits are set
+    pub fn netmask(&self) -> Ipv4Address {
+        match self.prefix_length {
+            0 => Ipv4Address::UNSPECIFIED,
+            length => Ipv4Address::from_u32(u32::MAX << (32 - length.min(32) as u32))
+        }
+    }
+
+    /// Whatever `address` is on the network of the device, so it's reached without a gateway
+    pub fn contains(&self, address: Ipv4Address) -> bool {
+        let mask = self.netmask().to_u32();
+        address.to_u32() & mask == self.address.to_u32() & mask
+    }
+
+    /// The network itself, the address with the bits after the prefix cleared
+    pub fn network(&self) -> InterfaceAddress {
+        InterfaceAddress {
+            address: Ipv4Address::from_u32(self.address.to_u32() & self.netmask().to_u32()),
+            prefix_length: self.prefix_length
+        }
+    }
+
+    /// The address every host of the network receives
+    pub fn broadcast(&self) -> Ipv4Address {
+        Ipv4Address::from_u32(self.address.to_u32() | !self.netmask().to_u32())
+    }
+}
+
+impl fmt::Display for InterfaceAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_length)
+    }
+}
+
+/// Called with every packet a device receives, the whole frame with the header of its link
+pub type ReceiveCallback = Box<dyn Fn(PacketBuffer) + Send + Sync>;
+
+/// A card that sends and receives the frames of a link (like Ethernet), the protocols only see it through this
+#[allow(dead_code)]
+pub trait NetDevice: Send + Sync {
+    /// A short name that identifies the device, like `eth0`
+    fn name(&self) -> &str;
+
+    /// The hardware address of the device, the frames sent to it are the ones it receives
+    fn mac_address(&self) -> MacAddress;
+
+    /// The biggest packet the device sends or receives, not counting the header of its link
+    fn mtu(&self) -> usize {
+        DEFAULT_MTU
+    }
+
+    /// Whatever the device is connected to the network, the packets sent while it isn't are lost
+    fn link_up(&self) -> bool {
+        true
+    }
+
+    /// Sends `packet`, a whole frame with the header of the link. The packet may still be in the queue of the device
+    /// once this returns, it's kept alive until it's sent
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if the packet is bigger than the device can send,
+    /// [`KernelError::Busy`] if the queue of the device is full, otherwise the error reported by the device
+    fn transmit(&self, packet: PacketBuffer) -> Result<(), KernelError>;
+
+    /// Makes the device use `address` as its hardware address, see [`ethernet::set_mac_address`]
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Unsupported`] if the device can't change its address, which is the default
+    fn set_mac_address(&self, _address: MacAddress) -> Result<(), KernelError> {
+        Err(KernelError::Unsupported)
+    }
+
+    /// Sets what's called with the packets the device receives from now on, replacing the callback that was there.
+    /// The drivers keep it in a [`ReceiveSlot`]
+    fn set_receive_callback(&self, callback: ReceiveCallback);
+
+    /// What the stack keeps about the interface of the device, whatever it's up and the counters of its traffic. The
+    /// drivers keep it next to their [`ReceiveSlot`]
+    fn interface(&self) -> &InterfaceState;
+}
+
+/// Where a driver keeps the callback of [`NetDevice::set_receive_callback`]. The callback runs the protocols, which
+/// allocate and take locks, so the packets are delivered by a thread: the interrupt handler of the device only queues
+/// the work of the driver that takes them from the card, see [`schedule`]
+pub struct ReceiveSlot {
+    callback: IrqCell<Option<SharedCallback>>
+}
+
+/// A [`ReceiveCallback`] that can be called without holding the slot, see [`ReceiveSlot::deliver`]
+type SharedCallback = Arc<dyn Fn(PacketBuffer) + Send + Sync>;
+
+#[allow(dead_code)]
+impl ReceiveSlot {
+    pub const fn new() -> Self {
+        ReceiveSlot {
+            callback: IrqCell::new(None)
+        }
+    }
+
+    pub fn set(&self, callback: ReceiveCallback) {
+        let callback: SharedCallback = Arc::from(callback);
+        self.callback.with(|slot| *slot = Some(callback));
+    }
+
+    /// Gives `packet` to the callback, it's dropped if there's none yet
+    pub fn deliver(&self, packet: PacketBuffer) {
+        // The callback is called without the interrupts being held off, it can be replaced meanwhile
+        let callback = self.callback.with(|slot| slot.clone());
+
+        if let Some(callback) = callback {
+            callback(packet);
+        }
+    }
+}
+
+/// Queues `item` on the worker of [`WORK_QUEUE_NAME`], which is where the drivers take the packets from the cards
+/// and deliver them. Never blocks nor allocates, so it can be called from interrupt handlers
+#[allow(dead_code)]
+pub fn schedule(item: &'static WorkItem) -> bool {
+    return work_queue().enqueue(item);
+}
+
+fn work_queue() -> &'static WorkQueue {
+    QUEUE.with(|queue| *queue).expect("The network work queue wasn't created yet")
+}
+
+/// Gives the device with the given name an IPv4 address, or takes it away with [`None`]. The neighbors learned
+/// by the device are forgotten, they may not be on its network anymore
+pub fn set_ipv4_address(device: &str, address: Option<InterfaceAddress>) {
+    {
+        let mut addresses = ADDRESSES.lock();
+
+        match address {
+            Some(address) => addresses.insert(String::from(device), address),
+            None => addresses.remove(device)
+        };
+    }
+
+    arp::flush(device);
+}
+
+/// Returns the IPv4 address of the device with the given name, if it has one
+pub fn ipv4_address(device: &str) -> Option<InterfaceAddress> {
+    ADDRESSES.lock().get(device).copied()
+}
+
+/// Returns the IPv4 address of every device that has one, with the name of the device
+pub fn ipv4_addresses() -> Vec<(String, InterfaceAddress)> {
+    ADDRESSES.lock().iter().map(|(device, address)| (device.clone(), *address)).collect()
+}
+
+/// Returns the name for the next Ethernet device a driver finds, `eth0` then `eth1` and so on
+#[allow(dead_code)]
+pub fn next_name() -> String {
+    format!("eth{}", DEVICES.lock().len())
+}
+
+/// Makes a device available to the protocols, called by the drivers when they find one. The frames it receives go
+/// through the Ethernet layer from now on. The first device gets [`DEFAULT_ADDRESS`] if it has no address yet
+#[allow(dead_code)]
+pub fn register(device: Arc<dyn NetDevice>) {
+    ethernet::attach(&device);
+
+    let first = {
+        let mut devices = DEVICES.lock();
+        devices.push(device.clone());
+        devices.len() == 1
+    };
+
+    if first && ipv4_address(device.name()).is_none() {
+        set_ipv4_address(device.name(), Some(DEFAULT_ADDRESS));
+        ipv4::set_default_gateway(Some(DEFAULT_GATEWAY)).expect("The default gateway isn't on the default network");
+        dns::set_server(Some(DEFAULT_DNS_SERVER));
+    }
+}
+
+/// Returns every network device found so far
+#[allow(dead_code)]
+pub fn devices() -> Vec<Arc<dyn NetDevice>> {
+    DEVICES.lock().clone()
+}
+
+/// Returns the network device with the given name
+#[allow(dead_code)]
+pub fn find(name: &str) -> Option<Arc<dyn NetDevice>> {
+    DEVICES.lock().iter().find(|device| device.name() == name).cloned()
+}
+
+/// Creates the work queue the protocols run on and registers the probes of the network cards, must be called once
+/// after the scheduler is initialized and before [`registry::probe_all`]
+pub fn init() {
+    let queue = WorkQueue::create(WORK_QUEUE_NAME).expect("Failed to create the network work queue");
+    QUEUE.with(|slot| *slot = Some(queue));
+
+    arp::init();
+    ipv4::init();
+    icmp::init();
+    udp::init();
+
+    #[cfg(feature = "smoltcp")]
+    smoltcp::init();
+
+    registry::register(virtio_net::probe);
+    registry::register(rtl8139::probe);
+}
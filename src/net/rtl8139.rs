@@ -0,0 +1,469 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU16, Ordering};
+use x86_64::instructions::port::Port;
+use crate::drivers::registry::Driver;
+use crate::memory::dma::DmaBuffer;
+use crate::pci::PciDevice;
+use crate::println;
+use crate::utils::error::KernelError;
+use crate::utils::Mutex;
+use crate::workqueue::WorkItem;
+use super::{InterfaceState, MacAddress, NetDevice, PacketBuffer, ReceiveCallback, ReceiveSlot};
+
+/// The RTL8139 of Realtek, which is also what QEMU emulates with `-device rtl8139`
+const VENDOR_ID: u16 = 0x10EC;
+const DEVICE_ID: u16 = 0x8139;
+
+const PCI_COMMAND: u16 = 0x04;
+const PCI_COMMAND_IO: u32 = 1 << 0;
+const PCI_COMMAND_BUS_MASTER: u32 = 1 << 2;
+
+/// The BAR with the ports of the registers
+const PCI_IO_BAR: u16 = 0x10;
+
+/// The registers, as offsets from the first port. The status and the address of the transmit slots are 4 registers
+/// of 4 bytes each
+const REGISTER_ID: u16 = 0x00;
+const REGISTER_TRANSMIT_STATUS: u16 = 0x10;
+const REGISTER_TRANSMIT_ADDRESS: u16 = 0x20;
+const REGISTER_RECEIVE_START: u16 = 0x30;
+const REGISTER_COMMAND: u16 = 0x37;
+const REGISTER_RECEIVE_READ: u16 = 0x38;
+const REGISTER_INTERRUPT_MASK: u16 = 0x3C;
+const REGISTER_INTERRUPT_STATUS: u16 = 0x3E;
+const REGISTER_TRANSMIT_CONFIG: u16 = 0x40;
+const REGISTER_RECEIVE_CONFIG: u16 = 0x44;
+const REGISTER_EEPROM_COMMAND: u16 = 0x50;
+const REGISTER_CONFIG_1: u16 = 0x52;
+const REGISTER_MEDIA_STATUS: u16 = 0x58;
+
+/// The bits of the command: the receive buffer is empty, the transmitter and the receiver are on, and the reset
+const COMMAND_BUFFER_EMPTY: u8 = 1 << 0;
+const COMMAND_TRANSMIT_ENABLE: u8 = 1 << 2;
+const COMMAND_RECEIVE_ENABLE: u8 = 1 << 3;
+const COMMAND_RESET: u8 = 1 << 4;
+
+/// The interrupts: a packet was received or failed to be, a packet was sent or failed to be, and the receive
+/// buffer (or its FIFO) overflowed. The status bits are cleared by writing 1 to them
+const INTERRUPT_RECEIVE_OK: u16 = 1 << 0;
+const INTERRUPT_RECEIVE_ERROR: u16 = 1 << 1;
+const INTERRUPT_TRANSMIT_OK: u16 = 1 << 2;
+const INTERRUPT_TRANSMIT_ERROR: u16 = 1 << 3;
+const INTERRUPT_RECEIVE_OVERFLOW: u16 = 1 << 4;
+const INTERRUPT_FIFO_OVERFLOW: u16 = 1 << 6;
+const INTERRUPTS: u16 = INTERRUPT_RECEIVE_OK
+    | INTERRUPT_RECEIVE_ERROR
+    | INTERRUPT_TRANSMIT_OK
+    | INTERRUPT_TRANSMIT_ERROR
+    | INTERRUPT_RECEIVE_OVERFLOW
+    | INTERRUPT_FIFO_OVERFLOW;
+
+/// The interrupts that mean there's something to take from the receive buffer
+const RECEIVE_INTERRUPTS: u16 = INTERRUPT_RECEIVE_OK
+    | INTERRUPT_RECEIVE_ERROR
+    | INTERRUPT_RECEIVE_OVERFLOW
+    | INTERRUPT_FIFO_OVERFLOW;
+
+/// The bits of the receive configuration: the frames to this card, to a group and broadcast ones are accepted, a
+/// frame that goes past the end of the ring is written after it instead of wrapping around (so a frame is always in
+/// one piece) and the DMA has no burst limit. The size bits are left at 0, which is a ring of [`RECEIVE_RING_SIZE`]
+/// bytes
+const RECEIVE_ACCEPT_PHYSICAL: u32 = 1 << 1;
+const RECEIVE_ACCEPT_MULTICAST: u32 = 1 << 2;
+const RECEIVE_ACCEPT_BROADCAST: u32 = 1 << 3;
+const RECEIVE_WRAP: u32 = 1 << 7;
+const RECEIVE_DMA_UNLIMITED: u32 = 0b111 << 8;
+const RECEIVE_CONFIG: u32 = RECEIVE_ACCEPT_PHYSICAL
+    | RECEIVE_ACCEPT_MULTICAST
+    | RECEIVE_ACCEPT_BROADCAST
+    | RECEIVE_WRAP
+    | RECEIVE_DMA_UNLIMITED;
+
+/// The transmit configuration, the DMA bursts are as big as they can be
+const TRANSMIT_CONFIG: u32 = 0b111 << 8;
+
+/// The bits of the status of a transmit slot: the card is done with the slot (it's set after a reset too), and the
+/// size of the frame
+const TRANSMIT_OWN: u32 = 1 << 13;
+const TRANSMIT_SIZE_MASK: u32 = 0x1FFF;
+
+/// Written to the EEPROM command to unlock the configuration registers (like the address of the card), and to
+/// lock them again
+const EEPROM_UNLOCK: u8 = 0xC0;
+const EEPROM_LOCK: u8 = 0x00;
+
+/// Cleared in the media status while the link is up
+const MEDIA_LINK_DOWN: u8 = 1 << 2;
+
+/// The bits of the status the card writes in front of each received frame: it was received fine
+const FRAME_RECEIVE_OK: u16 = 1 << 0;
+
+/// The size of the receive ring, the card writes the frames one after the other and starts over at the beginning
+const RECEIVE_RING_SIZE: usize = 8192;
+
+/// The receive buffer is the ring, the 16 bytes the card may write past it and room for a whole frame written past
+/// its end (see [`RECEIVE_WRAP`]), in pages
+const RECEIVE_BUFFER_PAGES: usize = (RECEIVE_RING_SIZE + 16 + 2048 + 4095) / 4096;
+
+/// The size of the status and the length the card writes in front of each frame, and of the CRC at its end
+const FRAME_HEADER_SIZE: usize = 4;
+const CRC_SIZE: usize = 4;
+
+/// How many frames can be on their way out at once, each slot has a buffer of [`TRANSMIT_SLOT_SIZE`] bytes
+const TRANSMIT_SLOTS: usize = 4;
+const TRANSMIT_SLOT_SIZE: usize = 2048;
+
+/// The biggest frame the card sends, and the smallest one Ethernet allows (without the CRC the card adds)
+const MAX_FRAME_SIZE: usize = 1792;
+const MIN_FRAME_SIZE: usize = 60;
+
+/// How many times the command is read while waiting for a reset to finish
+const RESET_ATTEMPTS: usize = 1_000_000;
+
+/// The first port of the card, 0 until one is found. It's what the interrupt handler needs
+static IO_BASE: AtomicU16 = AtomicU16::new(0);
+
+/// The card found by [`probe`], for the work that takes the received frames from it
+static CARD: Mutex<Option<Arc<Rtl8139>>> = Mutex::new(None);
+
+/// Queued by the interrupt handler when there are frames in the receive ring
+static RECEIVE_WORK: WorkItem = WorkItem::new(receive_frames);
+
+struct Rtl8139 {
+    name: String,
+    io: u16,
+    mac_address: Mutex<MacAddress>,
+    receive: ReceiveSlot,
+    interface: InterfaceState,
+    ring: Mutex<ReceiveRing>,
+    transmit: Mutex<TransmitSlots>
+}
+
+struct ReceiveRing {
+    /// The ring the card writes the frames to, physically contiguous since the card is only given its start
+    buffer: DmaBuffer,
+    /// Where the next frame starts in the ring
+    offset: usize
+}
+
+struct TransmitSlots {
+    /// The buffers of the slots, two in each page. The frames are copied there, so they're padded and aligned the
+    /// way the card wants them
+    buffers: DmaBuffer,
+    /// The slot the next frame goes to, the card sends from the slots in turn
+    next: usize
+}
+
+impl Rtl8139 {
+    fn read8(&self, register: u16) -> u8 {
+        unsafe { Port::<u8>::new(self.io + register).read() }
+    }
+
+    fn write8(&self, register: u16, value: u8) {
+        unsafe { Port::<u8>::new(self.io + register).write(value) };
+    }
+
+    fn write16(&self, register: u16, value: u16) {
+        unsafe { Port::<u16>::new(self.io + register).write(value) };
+    }
+
+    fn read32(&self, register: u16) -> u32 {
+        unsafe { Port::<u32>::new(self.io + register).read() }
+    }
+
+    fn write32(&self, register: u16, value: u32) {
+        unsafe { Port::<u32>::new(self.io + register).write(value) };
+    }
+
+    /// Resets the card and sets it up to receive into the ring and to send from the slots, with every interrupt
+    /// of [`INTERRUPTS`] enabled
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Timeout`] if the reset never finishes
+    fn reset(&self, ring: &mut ReceiveRing, transmit: &mut TransmitSlots) -> Result<(), KernelError> {
+        // Wakes the card up (LWAKE and LWPTN low) before resetting it
+        self.write8(REGISTER_CONFIG_1, 0);
+        self.write8(REGISTER_COMMAND, COMMAND_RESET);
+
+        if !(0..RESET_ATTEMPTS).any(|_| self.read8(REGISTER_COMMAND) & COMMAND_RESET == 0) {
+            return Err(KernelError::Timeout);
+        }
+
+        self.write32(REGISTER_RECEIVE_START, ring.buffer.physical(0).as_u64() as u32);
+        ring.offset = 0;
+
+        for slot in 0..TRANSMIT_SLOTS {
+            self.write32(REGISTER_TRANSMIT_ADDRESS + 4 * slot as u16, transmit.physical(slot));
+        }
+
+        transmit.next = 0;
+
+        self.write16(REGISTER_INTERRUPT_MASK, INTERRUPTS);
+        self.write8(REGISTER_COMMAND, COMMAND_RECEIVE_ENABLE | COMMAND_TRANSMIT_ENABLE);
+
+        // The configurations only stick once the receiver and the transmitter are on
+        self.write32(REGISTER_RECEIVE_CONFIG, RECEIVE_CONFIG);
+        self.write32(REGISTER_TRANSMIT_CONFIG, TRANSMIT_CONFIG);
+
+        return Ok(());
+    }
+
+    /// Takes the next frame out of the ring, returning [`None`] once it's empty. A frame received with an error is
+    /// skipped, a frame that can't be read means the ring is lost and the card is reset
+    fn next_frame(&self) -> Option<PacketBuffer> {
+        let mut ring = self.ring.lock();
+
+        while self.read8(REGISTER_COMMAND) & COMMAND_BUFFER_EMPTY == 0 {
+            let start = unsafe { ring.buffer.page(0).add(ring.offset) };
+            let header = unsafe { core::slice::from_raw_parts(start, FRAME_HEADER_SIZE) };
+
+            let status = u16::from_le_bytes([header[0], header[1]]);
+            let length = u16::from_le_bytes([header[2], header[3]]) as usize;
+
+            // The card only writes the frames received fine, anything else means the driver lost track of where
+            // they are
+            if status & FRAME_RECEIVE_OK == 0 || !(CRC_SIZE..=MAX_FRAME_SIZE + CRC_SIZE).contains(&length) {
+                println!("{}: the receive ring is corrupted, resetting the card", self.name);
+
+                let mut transmit = self.transmit.lock();
+
+                if let Err(error) = self.reset(&mut ring, &mut transmit) {
+                    println!("{}: failed to reset the card: {:?}", self.name, error);
+                }
+
+                return None;
+            }
+
+            // The frame is in one piece thanks to RECEIVE_WRAP, even if it goes past the end of the ring
+            let data = unsafe { core::slice::from_raw_parts(start.add(FRAME_HEADER_SIZE), length - CRC_SIZE) };
+            let packet = PacketBuffer::from_bytes(data);
+
+            // The next frame starts on a multiple of 4 bytes, the card is told what's read with 16 bytes less
+            ring.offset = (ring.offset + FRAME_HEADER_SIZE + length + 3) & !3;
+            ring.offset %= RECEIVE_RING_SIZE;
+            self.write16(REGISTER_RECEIVE_READ, ring.offset.wrapping_sub(16) as u16);
+
+            match packet {
+                Ok(packet) => return Some(packet),
+                // The frame is dropped, the next ones may fit once memory is freed
+                Err(_) => continue
+            }
+        }
+
+        return None;
+    }
+}
+
+impl TransmitSlots {
+    fn buffer(&self, slot: usize) -> *mut u8 {
+        let per_page = 4096 / TRANSMIT_SLOT_SIZE;
+        unsafe { self.buffers.page(slot / per_page).add(slot % per_page * TRANSMIT_SLOT_SIZE) }
+    }
+
+    fn physical(&self, slot: usize) -> u32 {
+        let per_page = 4096 / TRANSMIT_SLOT_SIZE;
+        (self.buffers.physical(slot / per_page).as_u64() + (slot % per_page * TRANSMIT_SLOT_SIZE) as u64) as u32
+    }
+}
+
+impl NetDevice for Rtl8139 {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn mac_address(&self) -> MacAddress {
+        *self.mac_address.lock()
+    }
+
+    fn link_up(&self) -> bool {
+        self.read8(REGISTER_MEDIA_STATUS) & MEDIA_LINK_DOWN == 0
+    }
+
+    fn transmit(&self, packet: PacketBuffer) -> Result<(), KernelError> {
+        let data = packet.data();
+
+        if data.len() > MAX_FRAME_SIZE {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let mut transmit = self.transmit.lock();
+        let slot = transmit.next;
+        let status_register = REGISTER_TRANSMIT_STATUS + 4 * slot as u16;
+
+        // The slots are used in turn, if the next one is still being sent every one of them is
+        if self.read32(status_register) & TRANSMIT_OWN == 0 {
+            return Err(KernelError::Busy);
+        }
+
+        // The frames shorter than the minimum of Ethernet are padded with zeroes, the card doesn't do it
+        let size = data.len().max(MIN_FRAME_SIZE);
+
+        unsafe {
+            let buffer = transmit.buffer(slot);
+
+            core::ptr::copy_nonoverlapping(data.as_ptr(), buffer, data.len());
+            core::ptr::write_bytes(buffer.add(data.len()), 0, size - data.len());
+        }
+
+        // Writing the size clears the OWN bit, which hands the slot over to the card
+        self.write32(status_register, size as u32 & TRANSMIT_SIZE_MASK);
+        transmit.next = (slot + 1) % TRANSMIT_SLOTS;
+
+        return Ok(());
+    }
+
+    fn set_mac_address(&self, address: MacAddress) -> Result<(), KernelError> {
+        let mut mac_address = self.mac_address.lock();
+        let [a, b, c, d, e, f] = address.0;
+
+        // The address registers can only be written 4 bytes at a time, and only while the configuration is unlocked
+        self.write8(REGISTER_EEPROM_COMMAND, EEPROM_UNLOCK);
+        self.write32(REGISTER_ID, u32::from_le_bytes([a, b, c, d]));
+        self.write32(REGISTER_ID + 4, u32::from_le_bytes([e, f, 0, 0]));
+        self.write8(REGISTER_EEPROM_COMMAND, EEPROM_LOCK);
+
+        *mac_address = address;
+        return Ok(());
+    }
+
+    fn set_receive_callback(&self, callback: ReceiveCallback) {
+        self.receive.set(callback);
+    }
+
+    fn interface(&self) -> &InterfaceState {
+        &self.interface
+    }
+}
+
+/// Called by the interrupt handler of the IRQ line of the card, it acknowledges the interrupt and queues the work
+/// that takes the received frames out of the ring
+///
+/// ## Note
+///
+/// This function must not allocate or block since it runs inside an interrupt handler
+fn handle_interrupt() {
+    let io = IO_BASE.load(Ordering::Acquire);
+
+    if io == 0 {
+        return;
+    }
+
+    let mut status: Port<u16> = Port::new(io + REGISTER_INTERRUPT_STATUS);
+    let pending = unsafe { status.read() } & INTERRUPTS;
+
+    // The line may be shared with another device
+    if pending == 0 {
+        return;
+    }
+
+    unsafe { status.write(pending) };
+
+    // The transmit slots are reclaimed by looking at their status, so only the receive needs some work
+    if pending & RECEIVE_INTERRUPTS != 0 {
+        super::schedule(&RECEIVE_WORK);
+    }
+}
+
+/// Runs on the network work queue, delivers every frame in the receive ring
+fn receive_frames() {
+    let card = match CARD.lock().clone() {
+        Some(card) => card,
+        None => return
+    };
+
+    while let Some(packet) = card.next_frame() {
+        card.receive.deliver(packet);
+    }
+}
+
+/// Returns the base of the I/O ports of the BAR at `register`, or `None` if it doesn't have ports
+fn io_bar(pci: &PciDevice, register: u16) -> Option<u16> {
+    let bar = pci.read_config(register);
+
+    // Bit 0 tells whatever the BAR has I/O ports (instead of memory)
+    if bar & 1 == 0 || bar & 0xFFFC == 0 {
+        return None;
+    }
+
+    return Some((bar & 0xFFFC) as u16);
+}
+
+/// The driver bound to the RTL8139
+struct Rtl8139Driver;
+
+impl Driver for Rtl8139Driver {
+    fn name(&self) -> &'static str {
+        "rtl8139"
+    }
+}
+
+/// Binds to `pci` if it's an RTL8139 and none was found before it: resets the card, allocates the receive ring and
+/// the transmit slots and makes it available to the network stack
+pub fn probe(pci: &PciDevice) -> Option<Box<dyn Driver>> {
+    if (pci.vendor_id, pci.device_id) != (VENDOR_ID, DEVICE_ID) || IO_BASE.load(Ordering::Acquire) != 0 {
+        return None;
+    }
+
+    let io = io_bar(pci, PCI_IO_BAR)?;
+
+    // 0xFF means the firmware didn't route the interrupt anywhere, nothing would tell that frames were received
+    let irq = pci.read_config(0x3C) as u8;
+
+    if irq >= 16 {
+        println!("RTL8139: the card has no interrupt line");
+        return None;
+    }
+
+    pci.write_config(PCI_COMMAND, pci.read_config(PCI_COMMAND) | PCI_COMMAND_IO | PCI_COMMAND_BUS_MASTER);
+
+    let (ring, slots) = match (DmaBuffer::new_contiguous(RECEIVE_BUFFER_PAGES), DmaBuffer::new(2)) {
+        (Ok(ring), Ok(slots)) => (ring, slots),
+        (Err(error), _) | (_, Err(error)) => {
+            println!("RTL8139: failed to allocate the buffers: {:?}", error);
+            return None;
+        }
+    };
+
+    let mut mac_address = MacAddress::ZERO;
+
+    for (index, byte) in mac_address.0.iter_mut().enumerate() {
+        *byte = unsafe { Port::<u8>::new(io + REGISTER_ID + index as u16).read() };
+    }
+
+    if let Err(error) = crate::interrupts::interrupt_manager::register_irq(irq, handle_interrupt) {
+        println!("RTL8139: failed to register IRQ {}: {:?}", irq, error);
+        return None;
+    }
+
+    let card = Arc::new(Rtl8139 {
+        name: super::next_name(),
+        io,
+        mac_address: Mutex::new(mac_address),
+        receive: ReceiveSlot::new(),
+        interface: InterfaceState::new(),
+        ring: Mutex::new(ReceiveRing { buffer: ring, offset: 0 }),
+        transmit: Mutex::new(TransmitSlots { buffers: slots, next: 0 })
+    });
+
+    // The handler is there before the reset enables the interrupts of the card
+    *CARD.lock() = Some(card.clone());
+    IO_BASE.store(io, Ordering::Release);
+
+    let reset = card.reset(&mut card.ring.lock(), &mut card.transmit.lock());
+
+    if let Err(error) = reset {
+        println!("RTL8139: failed to reset the card: {:?}", error);
+
+        IO_BASE.store(0, Ordering::Release);
+        *CARD.lock() = None;
+        return None;
+    }
+
+    println!("RTL8139: {} at port {:#x}, IRQ {}, address {}", card.name, io, irq, mac_address);
+
+    super::register(card);
+    return Some(Box::new(Rtl8139Driver));
+}
@@ -0,0 +1,330 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use smoltcp::iface::{Config, Interface, SocketSet};
+use smoltcp::phy::{self, DeviceCapabilities, Medium};
+use smoltcp::time::Instant;
+use smoltcp::wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr};
+use crate::sched::WaitQueue;
+use crate::sync::SleepMutex;
+use crate::time;
+use crate::utils::error::KernelError;
+use crate::utils::Mutex;
+use super::buffer::MAX_DATA;
+use super::{ethernet, InterfaceAddress, Ipv4Address, NetDevice, PacketBuffer};
+
+/// How many frames an interface keeps until it's polled, the next ones are dropped
+const RECEIVE_QUEUE_SIZE: usize = 32;
+
+/// The longest the polling task sleeps while there are interfaces, the timers of the sockets may wake it earlier
+const MAX_POLL_DELAY_MS: u64 = 100;
+
+/// The interfaces driven by smoltcp, see [`add_interface`]
+static INTERFACES: SleepMutex<Vec<SmoltcpInterface>> = SleepMutex::new(Vec::new());
+
+/// Where the polling task sleeps, and whatever something happened that it should look at before its delay is over
+static POLLER: WaitQueue = WaitQueue::new();
+static POLL_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Counts the polls that changed the state of a socket, see [`wait_for_activity`]
+static ACTIVITY: AtomicU64 = AtomicU64::new(0);
+static ACTIVITY_WAITERS: WaitQueue = WaitQueue::new();
+
+/// A [`NetDevice`] seen as a device of smoltcp. It takes the frames the device receives from the Ethernet layer,
+/// which gets them back once this is dropped
+pub struct SmoltcpDevice {
+    device: Arc<dyn NetDevice>,
+    received: Arc<Mutex<VecDeque<PacketBuffer>>>
+}
+
+#[allow(dead_code)]
+impl SmoltcpDevice {
+    pub fn new(device: Arc<dyn NetDevice>) -> Self {
+        let received = Arc::new(Mutex::new(VecDeque::new()));
+        let queue = received.clone();
+        let weak = Arc::downgrade(&device);
+
+        // The callback runs on the network work queue, the frames are only queued there and the polling task
+        // hands them to smoltcp. They're counted like the ones of the Ethernet layer, see [`ethernet::attach`]
+        device.set_receive_callback(Box::new(move |packet| {
+            let device = match weak.upgrade() {
+                Some(device) => device,
+                None => return
+            };
+
+            let state = device.interface();
+            let mut queue = queue.lock();
+
+            if !state.is_up() || queue.len() >= RECEIVE_QUEUE_SIZE {
+                state.count_receive_drop();
+                return;
+            }
+
+            state.count_received(packet.len());
+            queue.push_back(packet);
+            drop(queue);
+
+            wake_poller();
+        }));
+
+        SmoltcpDevice { device, received }
+    }
+
+    pub fn device(&self) -> &Arc<dyn NetDevice> {
+        &self.device
+    }
+}
+
+impl Drop for SmoltcpDevice {
+    fn drop(&mut self) {
+        ethernet::attach(&self.device);
+    }
+}
+
+/// A frame received by a [`SmoltcpDevice`]
+pub struct RxToken(PacketBuffer);
+
+/// A frame being made by smoltcp, it's allocated up front so running out of memory only means there's no token
+pub struct TxToken<'a> {
+    device: &'a Arc<dyn NetDevice>,
+    packet: PacketBuffer
+}
+
+impl<'a> TxToken<'a> {
+    fn new(device: &'a Arc<dyn NetDevice>) -> Option<Self> {
+        let packet = PacketBuffer::new(MAX_DATA).ok()?;
+        return Some(TxToken { device, packet });
+    }
+}
+
+impl phy::Device for SmoltcpDevice {
+    type RxToken<'a> = RxToken where Self: 'a;
+    type TxToken<'a> = TxToken<'a> where Self: 'a;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let mut received = self.received.lock();
+
+        if received.is_empty() {
+            return None;
+        }
+
+        // The frame stays in the queue if there's no memory for the answer, it's taken once there is
+        let transmit = TxToken::new(&self.device)?;
+        let packet = received.pop_front()?;
+
+        return Some((RxToken(packet), transmit));
+    }
+
+    fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        TxToken::new(&self.device)
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut capabilities = DeviceCapabilities::default();
+
+        capabilities.medium = Medium::Ethernet;
+        capabilities.max_transmission_unit = ethernet::HEADER_SIZE + self.device.mtu();
+
+        return capabilities;
+    }
+}
+
+impl phy::RxToken for RxToken {
+    fn consume<R, F>(mut self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R
+    {
+        // The frames of the drivers aren't shared, so they're never copied here
+        match self.0.data_mut() {
+            Ok(data) => f(data),
+            Err(_) => f(&mut [])
+        }
+    }
+}
+
+impl<'a> phy::TxToken for TxToken<'a> {
+    fn consume<R, F>(mut self, length: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R
+    {
+        self.packet.truncate(length);
+
+        let result = f(self.packet.data_mut().expect("A new packet is shared"));
+
+        // A frame the device can't take (or that's sent while the interface is down) is lost, the protocols of
+        // smoltcp send it again if they need to
+        let _ = ethernet::transmit(self.device, self.packet);
+        return result;
+    }
+}
+
+/// A network device driven by smoltcp, with its sockets
+struct SmoltcpInterface {
+    name: String,
+    device: SmoltcpDevice,
+    interface: Interface,
+    sockets: SocketSet<'static>
+}
+
+fn now() -> Instant {
+    Instant::from_millis(time::uptime_ms() as i64)
+}
+
+fn wake_poller() {
+    POLL_PENDING.store(true, Ordering::Release);
+    POLLER.wake_all();
+}
+
+/// Makes smoltcp drive the device with the given name, with `address` and a default route through `gateway`. The
+/// stack of the kernel doesn't see the device anymore (it loses its IPv4 address) until [`remove_interface`]
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NoDevice`] if there's no such device, [`KernelError::Busy`] if smoltcp already drives it
+/// and [`KernelError::InvalidArgument`] if the address can't be used by smoltcp
+#[allow(dead_code)]
+pub fn add_interface(
+    device: &str,
+    address: InterfaceAddress,
+    gateway: Option<Ipv4Address>
+) -> Result<(), KernelError> {
+    let device = super::find(device).ok_or(KernelError::NoDevice)?;
+    let mut interfaces = INTERFACES.lock();
+
+    if interfaces.iter().any(|interface| interface.name == device.name()) {
+        return Err(KernelError::Busy);
+    }
+
+    let mac_address = device.mac_address();
+
+    // smoltcp panics with an address that isn't a single device
+    if mac_address.is_multicast() || mac_address == super::MacAddress::ZERO {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let mut config = Config::new(HardwareAddress::Ethernet(EthernetAddress(mac_address.0)));
+    config.random_seed = crate::rand::next_u64();
+
+    let name = String::from(device.name());
+    let mut device = SmoltcpDevice::new(device);
+    let mut interface = Interface::new(config, &mut device, now());
+
+    let cidr = IpCidr::new(IpAddress::Ipv4(smoltcp::wire::Ipv4Address(address.address.0)), address.prefix_length);
+    let mut added = Ok(());
+
+    interface.update_ip_addrs(|addresses| added = addresses.push(cidr).map_err(|_| KernelError::InvalidArgument));
+    added?;
+
+    if let Some(gateway) = gateway {
+        interface
+            .routes_mut()
+            .add_default_ipv4_route(smoltcp::wire::Ipv4Address(gateway.0))
+            .map_err(|_| KernelError::InvalidArgument)?;
+    }
+
+    super::set_ipv4_address(&name, None);
+    interfaces.push(SmoltcpInterface { name, device, interface, sockets: SocketSet::new(Vec::new()) });
+    drop(interfaces);
+
+    wake_poller();
+    return Ok(());
+}
+
+/// Stops smoltcp from driving the device with the given name, its sockets are dropped and the device goes back to
+/// the stack of the kernel (without an address, see [`super::set_ipv4_address`])
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NotFound`] if smoltcp doesn't drive that device
+#[allow(dead_code)]
+pub fn remove_interface(device: &str) -> Result<(), KernelError> {
+    let mut interfaces = INTERFACES.lock();
+    let position = interfaces.iter().position(|interface| interface.name == device).ok_or(KernelError::NotFound)?;
+
+    interfaces.remove(position);
+    return Ok(());
+}
+
+/// Calls `f` with the interface of smoltcp driving the device with the given name and its sockets, to add sockets
+/// or use them. The polling task looks at the sockets right after
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NotFound`] if smoltcp doesn't drive that device
+#[allow(dead_code)]
+pub fn with_interface<R>(
+    device: &str,
+    f: impl FnOnce(&mut Interface, &mut SocketSet<'static>) -> R
+) -> Result<R, KernelError> {
+    let result = {
+        let mut interfaces = INTERFACES.lock();
+        let interface = interfaces.iter_mut().find(|interface| interface.name == device).ok_or(KernelError::NotFound)?;
+
+        f(&mut interface.interface, &mut interface.sockets)
+    };
+
+    wake_poller();
+    return Ok(result);
+}
+
+/// Blocks the current thread until a poll changes the state of a socket (like data that was received) or
+/// `timeout_ms` milliseconds passed, returning whatever a poll did. It's how the users of the sockets wait for them
+#[allow(dead_code)]
+pub fn wait_for_activity(timeout_ms: u64) -> bool {
+    let activity = ACTIVITY.load(Ordering::Acquire);
+    ACTIVITY_WAITERS.wait_until_timeout(timeout_ms, || ACTIVITY.load(Ordering::Acquire) != activity)
+}
+
+/// Polls every interface, returning how long until one of them must be polled again
+fn poll_all() -> Option<u64> {
+    let mut interfaces = INTERFACES.lock();
+
+    if interfaces.is_empty() {
+        return None;
+    }
+
+    let now = now();
+    let mut delay = MAX_POLL_DELAY_MS;
+    let mut changed = false;
+
+    for SmoltcpInterface { device, interface, sockets, .. } in interfaces.iter_mut() {
+        changed |= interface.poll(now, device, sockets);
+
+        if let Some(next) = interface.poll_delay(now, sockets) {
+            delay = delay.min(next.total_millis());
+        }
+    }
+
+    drop(interfaces);
+
+    if changed {
+        ACTIVITY.fetch_add(1, Ordering::AcqRel);
+        ACTIVITY_WAITERS.wake_all();
+    }
+
+    return Some(delay);
+}
+
+/// The polling task: polls the interfaces when frames come in, when the sockets were used and when their timers
+/// (like the retransmissions of TCP) expire
+fn poll_loop() {
+    loop {
+        POLL_PENDING.store(false, Ordering::Release);
+        let pending = || POLL_PENDING.load(Ordering::Acquire);
+
+        match poll_all() {
+            Some(delay) => {
+                POLLER.wait_until_timeout(delay, pending);
+            },
+            None => POLLER.wait_until(pending)
+        }
+    }
+}
+
+/// Starts the polling task, called by [`super::init`]
+pub fn init() {
+    crate::task::spawn_kthread(poll_loop, "smoltcp").expect("Failed to spawn the smoltcp polling task");
+}
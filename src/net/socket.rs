@@ -0,0 +1,172 @@
+use alloc::sync::Arc;
+use crate::process::fd::File;
+use crate::utils::error::KernelError;
+use crate::utils::Mutex;
+use super::udp::UdpSocket;
+use super::Ipv4Address;
+
+/// The family of the IPv4 addresses, the only one the sockets have. It has the value of Linux
+pub const AF_INET: u16 = 2;
+
+/// The types of sockets, they have the values of Linux
+pub const SOCK_STREAM: u64 = 1;
+pub const SOCK_DGRAM: u64 = 2;
+
+/// The protocols of the IPv4 sockets, 0 is the usual one of the type
+pub const IPPROTO_UDP: u64 = 17;
+
+/// An IPv4 address with a port
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SocketAddress {
+    pub address: Ipv4Address,
+    pub port: u16
+}
+
+/// The end of a connection a process (or the kernel) uses to talk to another host, it's a [`File`] so it goes in
+/// the file table like any file. Only the datagrams of UDP are there for now.
+///
+/// A socket gets a port when it's bound or when it first sends something. Once it's connected to a peer, what's
+/// sent without an address goes to the peer and only what comes from the peer is received
+pub struct Socket {
+    state: Mutex<State>
+}
+
+struct State {
+    udp: Option<Arc<UdpSocket>>,
+    /// The address the socket was bound to, `0.0.0.0` stands for every address of this host
+    address: Ipv4Address,
+    peer: Option<SocketAddress>
+}
+
+#[allow(dead_code)]
+impl Socket {
+    /// Creates a socket of the given type, `protocol` can be 0 for the usual protocol of the type
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if the type or the protocol isn't known, [`KernelError::Unsupported`]
+    /// for the stream sockets since there's no TCP yet
+    pub fn new(kind: u64, protocol: u64) -> Result<Self, KernelError> {
+        match (kind, protocol) {
+            (SOCK_DGRAM, 0 | IPPROTO_UDP) => {},
+            (SOCK_STREAM, _) => return Err(KernelError::Unsupported),
+            _ => return Err(KernelError::InvalidArgument)
+        }
+
+        Ok(Socket {
+            state: Mutex::new(State { udp: None, address: Ipv4Address::UNSPECIFIED, peer: None })
+        })
+    }
+
+    /// Binds the socket to `local`, a port of 0 is a free ephemeral port. The address must be one of this host or
+    /// `0.0.0.0`, the socket receives from every device either way
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if the socket is bound already or the address isn't one of this
+    /// host, [`KernelError::Busy`] if another socket is bound to the port
+    pub fn bind(&self, local: SocketAddress) -> Result<(), KernelError> {
+        let mut state = self.state.lock();
+
+        if state.udp.is_some() {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let is_own = super::ipv4_addresses().iter().any(|(_, own)| own.address == local.address);
+
+        if !local.address.is_unspecified() && !is_own {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        state.udp = Some(Arc::new(UdpSocket::bind(local.port)?));
+        state.address = local.address;
+
+        return Ok(());
+    }
+
+    /// Makes `peer` the address what's sent goes to by default and the only one what's received comes from,
+    /// binding the socket to an ephemeral port if it isn't bound yet
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if the port is 0, otherwise the error of binding the socket
+    pub fn connect(&self, peer: SocketAddress) -> Result<(), KernelError> {
+        if peer.port == 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let mut state = self.state.lock();
+
+        if state.udp.is_none() {
+            state.udp = Some(Arc::new(UdpSocket::bind(0)?));
+        }
+
+        state.peer = Some(peer);
+        return Ok(());
+    }
+
+    /// The address and the port the socket is bound to, `0.0.0.0:0` if it isn't bound yet
+    pub fn local_address(&self) -> SocketAddress {
+        let state = self.state.lock();
+
+        SocketAddress {
+            address: state.address,
+            port: state.udp.as_ref().map_or(0, |udp| udp.port())
+        }
+    }
+
+    /// Sends `data` to `destination`, or to the peer if it's [`None`]. The socket is bound to an ephemeral port if
+    /// it isn't bound yet
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::NotConnected`] if there's no destination and no peer, otherwise the error of binding
+    /// the socket or of sending (see [`UdpSocket::send_to`])
+    pub fn send_to(&self, data: &[u8], destination: Option<SocketAddress>) -> Result<usize, KernelError> {
+        let (udp, destination) = {
+            let mut state = self.state.lock();
+            let destination = destination.or(state.peer).ok_or(KernelError::NotConnected)?;
+
+            if state.udp.is_none() {
+                state.udp = Some(Arc::new(UdpSocket::bind(0)?));
+            }
+
+            (state.udp.clone().unwrap(), destination)
+        };
+
+        return udp.send_to(destination.address, destination.port, data);
+    }
+
+    /// Receives the next datagram into `buffer`, blocking until there's one. Returns the size of the data (what
+    /// doesn't fit in `buffer` is lost) with the address it was sent from. A connected socket drops what doesn't
+    /// come from its peer
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::NotConnected`] if the socket isn't bound, it would never receive anything
+    pub fn receive_from(&self, buffer: &mut [u8]) -> Result<(usize, SocketAddress), KernelError> {
+        let (udp, peer) = {
+            let state = self.state.lock();
+            (state.udp.clone().ok_or(KernelError::NotConnected)?, state.peer)
+        };
+
+        loop {
+            let (size, address, port) = udp.receive_from(buffer, None)?;
+            let source = SocketAddress { address, port };
+
+            if peer.map_or(true, |peer| peer == source) {
+                return Ok((size, source));
+            }
+        }
+    }
+}
+
+impl File for Socket {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, KernelError> {
+        self.receive_from(buffer).map(|(size, _)| size)
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize, KernelError> {
+        self.send_to(buffer, None)
+    }
+}
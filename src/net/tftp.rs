@@ -0,0 +1,226 @@
+use alloc::vec::Vec;
+use crate::fs::{vfs, NodeKind};
+use crate::utils::error::KernelError;
+use super::udp::UdpSocket;
+use super::Ipv4Address;
+
+/// The port the TFTP servers listen on for the requests, the transfer goes on from another port of theirs
+const SERVER_PORT: u16 = 69;
+
+/// The opcodes of the packets
+const OPCODE_READ_REQUEST: u16 = 1;
+const OPCODE_DATA: u16 = 3;
+const OPCODE_ACK: u16 = 4;
+const OPCODE_ERROR: u16 = 5;
+
+/// The error codes of the error packets that have a matching [`KernelError`], and the one sent to a port that isn't
+/// the one of the transfer
+const ERROR_NOT_DEFINED: u16 = 0;
+const ERROR_FILE_NOT_FOUND: u16 = 1;
+const ERROR_ACCESS_VIOLATION: u16 = 2;
+const ERROR_DISK_FULL: u16 = 3;
+const ERROR_UNKNOWN_TRANSFER: u16 = 5;
+
+/// The size of the data of every block but the last one, which is shorter (maybe empty) and ends the transfer
+const BLOCK_SIZE: usize = 512;
+
+/// The size of the opcode and the block number in front of the data
+const HEADER_SIZE: usize = 4;
+
+/// How long to wait for the next block before sending the last packet again, and how many times it's sent
+const TIMEOUT_MS: u64 = 1000;
+const ATTEMPTS: usize = 5;
+
+/// Downloads the file called `file` from the TFTP server at `server`, giving its content to `sink` a block at a
+/// time in order. Returns the size of the file
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if the name of the file is empty or too long, [`KernelError::NotFound`]
+/// if the server doesn't have it, [`KernelError::PermissionDenied`] if the server doesn't let it be read,
+/// [`KernelError::Io`] for the other errors of the server, [`KernelError::Timeout`] if the server stopped answering,
+/// the error of `sink` (which ends the transfer), otherwise the error of sending the packets
+pub fn download(
+    server: Ipv4Address,
+    file: &str,
+    mut sink: impl FnMut(&[u8]) -> Result<(), KernelError>
+) -> Result<u64, KernelError> {
+    // The request with the opcode and the mode has to fit in a block too
+    if file.is_empty() || file.len() > BLOCK_SIZE - 10 || file.contains('\0') {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let socket = UdpSocket::bind(0)?;
+
+    // Only the octet mode is asked for, the content is given as it is
+    let mut request = Vec::with_capacity(file.len() + 10);
+    request.extend_from_slice(&OPCODE_READ_REQUEST.to_be_bytes());
+    request.extend_from_slice(file.as_bytes());
+    request.push(0);
+    request.extend_from_slice(b"octet\0");
+
+    let mut packet = [0; HEADER_SIZE + BLOCK_SIZE];
+
+    // The port of the server the transfer goes on from, it's known once the first block came. Until then the
+    // request is what's sent again, the ack of the last block afterwards
+    let mut transfer_port = None;
+    let mut block: u16 = 1;
+    let mut size = 0;
+
+    socket.send_to(server, SERVER_PORT, &request)?;
+
+    loop {
+        let mut attempts = 1;
+
+        let (length, port) = loop {
+            let (length, source, port) = match socket.receive_from(&mut packet, Some(TIMEOUT_MS)) {
+                Ok(received) => received,
+                Err(KernelError::Timeout) if attempts < ATTEMPTS => {
+                    attempts += 1;
+
+                    match transfer_port {
+                        Some(port) => send_ack(&socket, server, port, block.wrapping_sub(1))?,
+                        None => {
+                            socket.send_to(server, SERVER_PORT, &request)?;
+                        }
+                    }
+
+                    continue;
+                },
+                Err(error) => return Err(error)
+            };
+
+            if source != server || length < HEADER_SIZE {
+                continue;
+            }
+
+            // Another transfer of the server that got lost, it's told so without ending this one
+            if transfer_port.map_or(false, |transfer_port| transfer_port != port) {
+                send_error(&socket, server, port, ERROR_UNKNOWN_TRANSFER)?;
+                continue;
+            }
+
+            break (length, port);
+        };
+
+        let opcode = u16::from_be_bytes([packet[0], packet[1]]);
+        let number = u16::from_be_bytes([packet[2], packet[3]]);
+
+        match opcode {
+            OPCODE_DATA => {},
+            OPCODE_ERROR => {
+                return Err(match number {
+                    ERROR_FILE_NOT_FOUND => KernelError::NotFound,
+                    ERROR_ACCESS_VIOLATION => KernelError::PermissionDenied,
+                    _ => KernelError::Io
+                });
+            },
+            _ => continue
+        }
+
+        transfer_port = Some(port);
+
+        // The ack of the block before was lost, so the server sent it again
+        if number == block.wrapping_sub(1) && block != 1 {
+            send_ack(&socket, server, port, number)?;
+            continue;
+        }
+
+        if number != block {
+            continue;
+        }
+
+        let data = &packet[HEADER_SIZE..length];
+
+        if let Err(error) = sink(data) {
+            let code = if let KernelError::NoSpace = error { ERROR_DISK_FULL } else { ERROR_NOT_DEFINED };
+            send_error(&socket, server, port, code)?;
+
+            return Err(error);
+        }
+
+        size += data.len() as u64;
+
+        send_ack(&socket, server, port, block)?;
+
+        // The block numbers start over after 65535 for the files bigger than 32 MiB
+        block = block.wrapping_add(1);
+
+        if data.len() < BLOCK_SIZE {
+            return Ok(size);
+        }
+    }
+}
+
+/// Downloads `file` from `server` (see [`download`]) into `buffer`, returning the size of the file
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NoSpace`] if the file doesn't fit in `buffer`, otherwise the errors of [`download`]
+#[allow(dead_code)]
+pub fn download_to_buffer(server: Ipv4Address, file: &str, buffer: &mut [u8]) -> Result<usize, KernelError> {
+    let mut offset = 0;
+
+    download(server, file, |data| {
+        let destination = buffer.get_mut(offset..offset + data.len()).ok_or(KernelError::NoSpace)?;
+        destination.copy_from_slice(data);
+        offset += data.len();
+
+        Ok(())
+    })?;
+
+    return Ok(offset);
+}
+
+/// Downloads `file` from `server` (see [`download`]) into the file at `path`, which is created if it doesn't exist
+/// and replaced otherwise. Returns the size of the file
+///
+/// ## Errors
+///
+/// Returns the errors of creating or writing the file at `path` (see [`vfs::create`]), otherwise the errors of
+/// [`download`]. What was written before an error stays in the file
+pub fn download_to_file(server: Ipv4Address, file: &str, path: &str) -> Result<u64, KernelError> {
+    let node = match vfs::create(path, NodeKind::File) {
+        Ok(node) => node,
+        Err(KernelError::AlreadyExists) => {
+            vfs::truncate(path, 0)?;
+            vfs::resolve(path)?
+        },
+        Err(error) => return Err(error)
+    };
+
+    let mut offset = 0;
+
+    return download(server, file, |data| {
+        let written = node.write_at(offset, data)?;
+
+        if written < data.len() {
+            return Err(KernelError::NoSpace);
+        }
+
+        offset += written as u64;
+        Ok(())
+    });
+}
+
+/// Tells the server the block with the given number was received, which makes it send the next one
+fn send_ack(socket: &UdpSocket, server: Ipv4Address, port: u16, block: u16) -> Result<(), KernelError> {
+    let mut packet = [0; HEADER_SIZE];
+
+    packet[0..2].copy_from_slice(&OPCODE_ACK.to_be_bytes());
+    packet[2..4].copy_from_slice(&block.to_be_bytes());
+
+    socket.send_to(server, port, &packet)?;
+    return Ok(());
+}
+
+/// Tells the other end of a transfer that it failed, the message is left empty
+fn send_error(socket: &UdpSocket, server: Ipv4Address, port: u16, code: u16) -> Result<(), KernelError> {
+    let mut packet = [0; HEADER_SIZE + 1];
+
+    packet[0..2].copy_from_slice(&OPCODE_ERROR.to_be_bytes());
+    packet[2..4].copy_from_slice(&code.to_be_bytes());
+
+    socket.send_to(server, port, &packet)?;
+    return Ok(());
+}
@@ -0,0 +1,236 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use crate::sched::WaitQueue;
+use crate::utils::error::KernelError;
+use crate::utils::{IrqCell, Mutex};
+use super::ipv4::{self, Ipv4Header, PROTOCOL_UDP};
+use super::{Ipv4Address, PacketBuffer};
+
+/// The size of the header of a datagram, the ports, the length and the checksum
+pub const HEADER_SIZE: usize = 8;
+
+/// The ports given to the sockets bound to port 0, the dynamic ports of the IANA
+const EPHEMERAL_FIRST: u16 = 49152;
+const EPHEMERAL_LAST: u16 = 65535;
+
+/// How many datagrams a socket keeps until they're received, the next ones are dropped
+const RECEIVE_QUEUE_SIZE: usize = 16;
+
+/// The sockets by the port they're bound to
+static PORTS: Mutex<BTreeMap<u16, Weak<Shared>>> = Mutex::new(BTreeMap::new());
+
+/// The port the search for a free ephemeral port starts at, the one after the last one given
+static NEXT_EPHEMERAL: Mutex<u16> = Mutex::new(EPHEMERAL_FIRST);
+
+/// A datagram received by a socket
+struct Datagram {
+    source: Ipv4Address,
+    source_port: u16,
+    /// The data, without the headers
+    packet: PacketBuffer
+}
+
+/// The part of a socket the receive path sees
+struct Shared {
+    port: u16,
+    received: IrqCell<VecDeque<Datagram>>,
+    readers: WaitQueue
+}
+
+/// A socket bound to a UDP port of this host, it sends datagrams from it and receives the ones sent to it. The port
+/// is free again once the socket is dropped
+pub struct UdpSocket {
+    shared: Arc<Shared>
+}
+
+#[allow(dead_code)]
+impl UdpSocket {
+    /// Binds a socket to `port`, or to a free ephemeral port if it's 0
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Busy`] if another socket is bound to the port, or if every ephemeral port is taken
+    pub fn bind(port: u16) -> Result<Self, KernelError> {
+        let mut ports = PORTS.lock();
+
+        let is_free = |ports: &BTreeMap<u16, Weak<Shared>>, port: u16| {
+            ports.get(&port).map_or(true, |socket| socket.strong_count() == 0)
+        };
+
+        let port = if port != 0 {
+            if !is_free(&ports, port) {
+                return Err(KernelError::Busy);
+            }
+
+            port
+        } else {
+            let mut next = NEXT_EPHEMERAL.lock();
+            let count = (EPHEMERAL_LAST - EPHEMERAL_FIRST) as u32 + 1;
+            let start = (*next - EPHEMERAL_FIRST) as u32;
+
+            // The ports are tried in turn from the one after the last one given, so a port isn't reused right away
+            let port = (0..count)
+                .map(|offset| EPHEMERAL_FIRST + ((start + offset) % count) as u16)
+                .find(|&port| is_free(&ports, port))
+                .ok_or(KernelError::Busy)?;
+
+            *next = if port == EPHEMERAL_LAST { EPHEMERAL_FIRST } else { port + 1 };
+            port
+        };
+
+        let shared = Arc::new(Shared {
+            port,
+            received: IrqCell::new(VecDeque::new()),
+            readers: WaitQueue::new()
+        });
+
+        ports.insert(port, Arc::downgrade(&shared));
+        return Ok(UdpSocket { shared });
+    }
+
+    /// The port the socket is bound to
+    pub fn port(&self) -> u16 {
+        self.shared.port
+    }
+
+    /// Sends `data` in a datagram to the given port of `destination`
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if the datagram doesn't fit in a packet or the port is 0, otherwise
+    /// the error of sending it (like [`KernelError::NotFound`] if there's no route to the destination)
+    pub fn send_to(&self, destination: Ipv4Address, port: u16, data: &[u8]) -> Result<usize, KernelError> {
+        if port == 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let source = ipv4::source_address(destination)?;
+        let mut packet = PacketBuffer::from_bytes(data)?;
+        let length = (HEADER_SIZE + data.len()) as u16;
+
+        let header = packet.push_header(HEADER_SIZE)?;
+        header[0..2].copy_from_slice(&self.shared.port.to_be_bytes());
+        header[2..4].copy_from_slice(&port.to_be_bytes());
+        header[4..6].copy_from_slice(&length.to_be_bytes());
+        header[6..8].copy_from_slice(&[0, 0]);
+
+        let datagram = packet.data_mut()?;
+        let sum = match checksum(source, destination, datagram) {
+            // A checksum of 0 means there's none, the same sum is written with all the bits set
+            0 => 0xFFFF,
+            sum => sum
+        };
+
+        datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+
+        ipv4::send(destination, PROTOCOL_UDP, packet)?;
+        return Ok(data.len());
+    }
+
+    /// Receives the next datagram into `buffer`, blocking until there's one or `timeout_ms` milliseconds passed if
+    /// it's set. Returns the size of the data (what doesn't fit in `buffer` is lost) with the address and the port it
+    /// was sent from
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Timeout`] if no datagram came in time
+    pub fn receive_from(
+        &self,
+        buffer: &mut [u8],
+        timeout_ms: Option<u64>
+    ) -> Result<(usize, Ipv4Address, u16), KernelError> {
+        let shared = &self.shared;
+        let has_datagram = || shared.received.with(|received| !received.is_empty());
+
+        match timeout_ms {
+            Some(ms) => {
+                shared.readers.wait_until_timeout(ms, has_datagram);
+            },
+            None => shared.readers.wait_until(has_datagram)
+        }
+
+        let datagram = shared.received.with(|received| received.pop_front()).ok_or(KernelError::Timeout)?;
+
+        let data = datagram.packet.data();
+        let count = data.len().min(buffer.len());
+        buffer[..count].copy_from_slice(&data[..count]);
+
+        return Ok((count, datagram.source, datagram.source_port));
+    }
+
+    /// Whatever a datagram is there to be received without blocking
+    pub fn has_data(&self) -> bool {
+        self.shared.received.with(|received| !received.is_empty())
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        let mut ports = PORTS.lock();
+
+        // The port may have been taken again already if the socket was dropped before
+        if ports.get(&self.shared.port).map_or(false, |socket| socket.as_ptr() == Arc::as_ptr(&self.shared)) {
+            ports.remove(&self.shared.port);
+        }
+    }
+}
+
+/// The checksum of `datagram` (the header and the data) with the pseudo-header of IPv4 in front of it
+fn checksum(source: Ipv4Address, destination: Ipv4Address, datagram: &[u8]) -> u16 {
+    let length = (datagram.len() as u16).to_be_bytes();
+    let pseudo_header = [0, PROTOCOL_UDP, length[0], length[1]];
+
+    ipv4::checksum(&[&source.0, &destination.0, &pseudo_header, datagram])
+}
+
+/// Checks a datagram received for this host and queues it on the socket bound to its destination port. The
+/// datagrams to a port without a socket are dropped
+fn receive(header: &Ipv4Header, mut packet: PacketBuffer) {
+    let data = packet.data();
+
+    if data.len() < HEADER_SIZE {
+        return;
+    }
+
+    let source_port = u16::from_be_bytes([data[0], data[1]]);
+    let port = u16::from_be_bytes([data[2], data[3]]);
+    let length = u16::from_be_bytes([data[4], data[5]]) as usize;
+    let sum = u16::from_be_bytes([data[6], data[7]]);
+
+    if length < HEADER_SIZE || length > data.len() {
+        return;
+    }
+
+    if sum != 0 && checksum(header.source, header.destination, &data[..length]) != 0 {
+        return;
+    }
+
+    let socket = match PORTS.lock().get(&port).and_then(|socket| socket.upgrade()) {
+        Some(socket) => socket,
+        None => return
+    };
+
+    packet.truncate(length);
+    packet.pull_header(HEADER_SIZE).unwrap();
+
+    let datagram = Datagram { source: header.source, source_port, packet };
+
+    let dropped = socket.received.with(|received| {
+        if received.len() == RECEIVE_QUEUE_SIZE {
+            return Some(datagram);
+        }
+
+        received.push_back(datagram);
+        None
+    });
+
+    // A datagram that doesn't fit is freed with the interrupts enabled
+    if dropped.is_none() {
+        socket.readers.wake_all();
+    }
+}
+
+/// Registers the handler of the UDP datagrams, called by [`super::init`]
+pub fn init() {
+    ipv4::register_handler(PROTOCOL_UDP, receive);
+}
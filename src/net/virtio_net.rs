@@ -0,0 +1,312 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::drivers::registry::Driver;
+use crate::memory::dma::DmaBuffer;
+use crate::pci::PciDevice;
+use crate::println;
+use crate::utils::error::KernelError;
+use crate::utils::{IrqCell, Mutex};
+use crate::virtio::queue::{Buffer, Virtqueue};
+use crate::virtio::VirtioDevice;
+use crate::workqueue::WorkItem;
+use super::{ipv4, InterfaceState, MacAddress, NetDevice, PacketBuffer, ReceiveCallback, ReceiveSlot};
+
+/// The type of the network devices
+const DEVICE_TYPE: u16 = 1;
+
+/// The queues: the frames received come back through the first one, the frames to send go in the second one
+const RECEIVE_QUEUE: u16 = 0;
+const TRANSMIT_QUEUE: u16 = 1;
+
+/// The features used: the device may give frames whose checksum is left to the driver (see
+/// [`HEADER_NEEDS_CHECKSUM`]), has an address of its own and tells whatever the link is up
+const FEATURE_GUEST_CHECKSUM: u64 = 1 << 1;
+const FEATURE_MAC: u64 = 1 << 5;
+const FEATURE_STATUS: u64 = 1 << 16;
+
+/// The registers of the configuration of the device: the address and the status of the link
+const CONFIG_MAC: usize = 0x00;
+const CONFIG_STATUS: usize = 0x06;
+
+/// Set in the status of the link while it's up
+const STATUS_LINK_UP: u16 = 1 << 0;
+
+/// The size of the header in front of each frame, the same both ways. Its fields are the flags, the type of
+/// segmentation offload, the size of the headers, the size of the segments, where the checksum starts, where it's
+/// written and how many buffers the frame takes
+const HEADER_SIZE: usize = 12;
+
+/// Set in the flags of the header of a received frame whose checksum only has the sum of the pseudo-header, the rest
+/// of the sum is left to the driver
+const HEADER_NEEDS_CHECKSUM: u8 = 1 << 0;
+
+/// The biggest frame sent or received, the header of Ethernet and the MTU
+const MAX_FRAME_SIZE: usize = super::ethernet::HEADER_SIZE + super::DEFAULT_MTU;
+
+/// How many buffers the device has to receive frames into, a page each
+const RECEIVE_BUFFERS: usize = 32;
+
+/// The card found by [`probe`], for the interrupt handler and for the work that takes the received frames
+static CARD: IrqCell<Option<Arc<VirtioNet>>> = IrqCell::new(None);
+
+/// Queued by the interrupt handler when the device is done with some buffers
+static QUEUE_WORK: WorkItem = WorkItem::new(process_used);
+
+struct VirtioNet {
+    name: String,
+    device: VirtioDevice,
+    mac_address: MacAddress,
+    /// Whatever the device tells the status of the link, it's always up otherwise
+    has_status: bool,
+    receive: ReceiveSlot,
+    interface: InterfaceState,
+    receive_queue: Mutex<Queue<DmaBuffer>>,
+    transmit_queue: Mutex<Queue<PacketBuffer>>
+}
+
+/// A virtqueue with what each chain the device owns was made from, by the ID of the chain. It's kept alive until
+/// the device gives the chain back
+struct Queue<T> {
+    queue: Virtqueue,
+    pending: Vec<(u16, T)>
+}
+
+impl<T> Queue<T> {
+    /// Takes the next chain the device is done with, with what it was made from and how many bytes the device wrote
+    fn pop_used(&mut self) -> Option<(T, u32)> {
+        let (head, written) = self.queue.pop_used()?;
+        let position = self.pending.iter().position(|(id, _)| *id == head)?;
+
+        return Some((self.pending.swap_remove(position).1, written));
+    }
+}
+
+impl VirtioNet {
+    /// Gives the device a page to receive a frame into
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::OutOfMemory`] if there's no free frame, [`KernelError::Busy`] if the queue is full
+    fn add_receive_buffer(&self, queue: &mut Queue<DmaBuffer>) -> Result<(), KernelError> {
+        let page = DmaBuffer::new(1)?;
+        let buffer = Buffer { address: page.physical(0), length: 4096, writable: true };
+
+        let head = queue.queue.push(&[buffer])?;
+        queue.pending.push((head, page));
+
+        return Ok(());
+    }
+
+    /// Takes the frames the device received and gives it new pages in place of theirs, returning the frames
+    fn take_received(&self) -> Vec<PacketBuffer> {
+        let mut queue = self.receive_queue.lock();
+        let mut frames = Vec::new();
+
+        while let Some((page, written)) = queue.pop_used() {
+            if let Some(frame) = read_frame(page, written as usize) {
+                frames.push(frame);
+            }
+        }
+
+        // The pages that can't be replaced now are next time, the device drops the frames meanwhile
+        while queue.pending.len() < RECEIVE_BUFFERS && self.add_receive_buffer(&mut queue).is_ok() {}
+
+        queue.queue.notify();
+        return frames;
+    }
+
+    /// Frees the frames the device is done sending
+    fn reclaim_transmitted(&self, queue: &mut Queue<PacketBuffer>) {
+        while queue.pop_used().is_some() {}
+    }
+}
+
+/// Makes a packet of the frame the device wrote to `page` with its header in front of it, finishing its checksum
+/// if the device left it to the driver. Returns [`None`] if the frame is broken
+fn read_frame(page: DmaBuffer, written: usize) -> Option<PacketBuffer> {
+    if !(HEADER_SIZE..=4096).contains(&written) {
+        return None;
+    }
+
+    let mut packet = PacketBuffer::from_dma(page, written);
+    let header = packet.pull_header(HEADER_SIZE).ok()?;
+
+    let flags = header[0];
+    let checksum_start = u16::from_le_bytes([header[6], header[7]]) as usize;
+    let checksum_offset = u16::from_le_bytes([header[8], header[9]]) as usize;
+
+    if flags & HEADER_NEEDS_CHECKSUM != 0 {
+        let data = packet.data_mut().ok()?;
+        let field = checksum_start + checksum_offset;
+
+        if checksum_start > data.len() || field + 2 > data.len() {
+            return None;
+        }
+
+        // The field already has the sum of the pseudo-header, so the sum of the rest with it gives the checksum
+        let sum = ipv4::checksum(&[&data[checksum_start..]]);
+        data[field..field + 2].copy_from_slice(&sum.to_be_bytes());
+    }
+
+    return Some(packet);
+}
+
+impl NetDevice for VirtioNet {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn mac_address(&self) -> MacAddress {
+        self.mac_address
+    }
+
+    fn link_up(&self) -> bool {
+        !self.has_status || self.device.read_config::<u16>(CONFIG_STATUS) & STATUS_LINK_UP != 0
+    }
+
+    fn transmit(&self, mut packet: PacketBuffer) -> Result<(), KernelError> {
+        if packet.len() > MAX_FRAME_SIZE {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        // The header goes in the headroom of the packet, so the frame is sent from where it is. No offload is
+        // asked for, the protocols already did all the work
+        packet.push_header(HEADER_SIZE)?.fill(0);
+
+        let mut queue = self.transmit_queue.lock();
+        self.reclaim_transmitted(&mut queue);
+
+        let buffer = Buffer { address: packet.physical(), length: packet.len() as u32, writable: false };
+        let head = queue.queue.push(&[buffer])?;
+
+        queue.pending.push((head, packet));
+        queue.queue.notify();
+
+        return Ok(());
+    }
+
+    fn set_receive_callback(&self, callback: ReceiveCallback) {
+        self.receive.set(callback);
+    }
+
+    fn interface(&self) -> &InterfaceState {
+        &self.interface
+    }
+}
+
+/// Called by the interrupt handler of the IRQ line of the device, it acknowledges the interrupt and queues the work
+/// that takes the buffers the device is done with
+///
+/// ## Note
+///
+/// This function must not allocate or block since it runs inside an interrupt handler
+fn handle_interrupt() {
+    // Reading the status acknowledges the interrupt, bit 0 is set when a queue has used buffers
+    let status = CARD.with(|card| card.as_ref().map_or(0, |card| card.device.interrupt_status()));
+
+    if status & 1 != 0 {
+        super::schedule(&QUEUE_WORK);
+    }
+}
+
+/// Runs on the network work queue, delivers the frames received and frees the ones sent
+fn process_used() {
+    let card = match CARD.with(|card| card.clone()) {
+        Some(card) => card,
+        None => return
+    };
+
+    card.reclaim_transmitted(&mut card.transmit_queue.lock());
+
+    for frame in card.take_received() {
+        card.receive.deliver(frame);
+    }
+}
+
+/// The driver bound to the virtio network device
+struct VirtioNetDriver;
+
+impl Driver for VirtioNetDriver {
+    fn name(&self) -> &'static str {
+        "virtio-net"
+    }
+}
+
+/// Binds to `pci` if it's a virtio network device and none was found before it: agrees on the features, sets up
+/// both queues, fills the receive one and makes the device available to the network stack
+pub fn probe(pci: &PciDevice) -> Option<Box<dyn Driver>> {
+    if !crate::virtio::is_device(pci, DEVICE_TYPE) || CARD.with(|card| card.is_some()) {
+        return None;
+    }
+
+    return match setup(*pci) {
+        Ok(()) => Some(Box::new(VirtioNetDriver)),
+        Err(error) => {
+            println!("virtio-net: failed to set up the device: {:?}", error);
+            None
+        }
+    };
+}
+
+fn setup(pci: PciDevice) -> Result<(), KernelError> {
+    let device = VirtioDevice::new(pci)?;
+    let irq = device.irq().ok_or(KernelError::NoDevice)?;
+    let features = device.negotiate(FEATURE_GUEST_CHECKSUM | FEATURE_MAC | FEATURE_STATUS)?;
+
+    let mac_address = if features & FEATURE_MAC != 0 {
+        let mut address = MacAddress::ZERO;
+
+        for (index, byte) in address.0.iter_mut().enumerate() {
+            *byte = device.read_config(CONFIG_MAC + index);
+        }
+
+        address
+    } else {
+        // A random address administered locally (bit 1) for a single device (bit 0 clear)
+        let mut address = MacAddress::ZERO;
+        address.0.copy_from_slice(&crate::rand::next_u64().to_le_bytes()[..6]);
+        address.0[0] = (address.0[0] | 0x02) & !0x01;
+
+        address
+    };
+
+    let receive_queue = Queue { queue: device.setup_queue(RECEIVE_QUEUE)?, pending: Vec::new() };
+    let transmit_queue = Queue { queue: device.setup_queue(TRANSMIT_QUEUE)?, pending: Vec::new() };
+
+    let card = Arc::new(VirtioNet {
+        name: super::next_name(),
+        device,
+        mac_address,
+        has_status: features & FEATURE_STATUS != 0,
+        receive: ReceiveSlot::new(),
+        interface: InterfaceState::new(),
+        receive_queue: Mutex::new(receive_queue),
+        transmit_queue: Mutex::new(transmit_queue)
+    });
+
+    {
+        let mut queue = card.receive_queue.lock();
+
+        for _ in 0..RECEIVE_BUFFERS {
+            card.add_receive_buffer(&mut queue)?;
+        }
+    }
+
+    // The handler is there before the device starts raising interrupts
+    CARD.with(|slot| *slot = Some(card.clone()));
+
+    if let Err(error) = crate::interrupts::interrupt_manager::register_irq(irq, handle_interrupt) {
+        CARD.with(|slot| *slot = None);
+        return Err(error);
+    }
+
+    card.device.start();
+    card.receive_queue.lock().queue.notify();
+
+    println!("virtio-net: {} with IRQ {}, address {}", card.name, irq, mac_address);
+
+    super::register(card);
+    return Ok(());
+}
@@ -0,0 +1,90 @@
+use x86_64::structures::paging::{Mapper, Page, PageSize, PageTableFlags, PhysFrame, Size2MiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Where the configuration space of the PCI segment is mapped, in chunks of 2 MiB as they're used
+const ECAM_START: u64 = 0x_6666_0000_0000;
+
+/// How much configuration space every bus has, 4 KiB for each of its 256 functions
+const BUS_SIZE: u64 = 1 << 20;
+
+/// How many 2 MiB chunks the configuration space of 256 buses takes
+const CHUNK_COUNT: usize = 128;
+
+/// The memory-mapped configuration space (ECAM) of the buses of PCI segment 0, described by the MCFG table.
+/// It has 4 KiB per function instead of the 256 bytes reachable through the I/O ports
+pub(super) struct Ecam {
+    /// The physical address of the configuration space of bus 0, even if the range doesn't start at bus 0
+    base: PhysAddr,
+    start_bus: u8,
+    end_bus: u8,
+    /// Which 2 MiB chunks are already mapped, one bit per chunk
+    mapped: [u64; CHUNK_COUNT / 64]
+}
+
+impl Ecam {
+    /// Finds the configuration space of segment 0 in the MCFG table, returning `None` if there's no such table
+    /// (like on machines without PCI Express). The table must already be parsed, see [`crate::acpi::init`]
+    pub(super) fn find() -> Option<Self> {
+        for entry in crate::acpi::mcfg() {
+            // The chunks are mapped with 2 MiB pages, so the configuration space must be aligned to them
+            if entry.segment != 0 || entry.base.as_u64() % Size2MiB::SIZE != 0 {
+                continue;
+            }
+
+            let mut ecam = Ecam {
+                base: entry.base,
+                start_bus: entry.start_bus,
+                end_bus: entry.end_bus,
+                mapped: [0; CHUNK_COUNT / 64]
+            };
+
+            // Mapping the first chunk right away creates the upper page tables of the whole window, before
+            // any process copies the tables of the kernel into its own address space
+            ecam.address(ecam.start_bus, 0, 0, 0)?;
+
+            return Some(ecam);
+        }
+
+        return None;
+    }
+
+    /// Returns where the given register of a function can be accessed, mapping its configuration space if needed.
+    /// Returns `None` if the bus isn't covered by the MCFG table or the mapping failed
+    pub(super) fn address(&mut self, bus: u8, device: u8, function: u8, offset: u16) -> Option<VirtAddr> {
+        if bus < self.start_bus || bus > self.end_bus {
+            return None;
+        }
+
+        let offset = ((bus as u64) * BUS_SIZE)
+            | (((device as u64) & 0x1F) << 15)
+            | (((function as u64) & 0x07) << 12)
+            | ((offset as u64) & 0xFFC);
+
+        self.map_chunk((offset / Size2MiB::SIZE) as usize)?;
+
+        return Some(VirtAddr::new(ECAM_START + offset));
+    }
+
+    /// Maps the 2 MiB chunk of the configuration space with the given index, as uncached memory since
+    /// those are device registers
+    fn map_chunk(&mut self, chunk: usize) -> Option<()> {
+        if self.mapped[chunk / 64] & (1 << (chunk % 64)) != 0 {
+            return Some(());
+        }
+
+        let offset = chunk as u64 * Size2MiB::SIZE;
+        let page: Page<Size2MiB> = Page::containing_address(VirtAddr::new(ECAM_START + offset));
+        let frame: PhysFrame<Size2MiB> = PhysFrame::containing_address(self.base + offset);
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+
+        // The configuration space isn't RAM, so the frame allocator never hands these frames out
+        let mapped = crate::memory::with_paging(|mapper, frame_allocator| unsafe {
+            mapper.map_to(page, frame, flags, frame_allocator).map(|flush| flush.flush())
+        });
+
+        mapped.ok()?;
+
+        self.mapped[chunk / 64] |= 1 << (chunk % 64);
+        Some(())
+    }
+}
@@ -0,0 +1,264 @@
+mod ecam;
+
+use alloc::vec::Vec;
+use x86_64::instructions::port::Port;
+use crate::utils::Mutex;
+use self::ecam::Ecam;
+
+/// Where the address of the configuration register to access is written
+const CONFIG_ADDRESS: u16 = 0xCF8;
+
+/// Where the configuration register selected through [`CONFIG_ADDRESS`] is read from
+const CONFIG_DATA: u16 = 0xCFC;
+
+/// The vendor ID read from a slot without any device
+const NO_VENDOR: u16 = 0xFFFF;
+
+/// The size of the configuration space of a function reachable through the I/O ports
+const LEGACY_CONFIG_SIZE: u16 = 256;
+
+/// The size of the configuration space of a PCI Express function, only reachable through ECAM
+const EXTENDED_CONFIG_SIZE: u16 = 4096;
+
+/// The register of the status of a function, bit 4 tells whatever it has a list of capabilities
+const STATUS_REGISTER: u16 = 0x06;
+const STATUS_CAPABILITIES: u32 = 1 << 4;
+
+/// The register with the offset of the first capability
+const CAPABILITIES_POINTER: u16 = 0x34;
+
+/// The memory-mapped configuration space found by [`init`], the I/O ports are used if there isn't one
+static ECAM: Mutex<Option<Ecam>> = Mutex::new(None);
+
+/// A function of a device found on the PCI bus
+#[derive(Debug, Copy, Clone)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8
+}
+
+impl PciDevice {
+    /// Returns a short description of the class of the device, or `None` for the classes without one
+    pub fn class_name(&self) -> Option<&'static str> {
+        let name = match (self.class, self.subclass) {
+            (0x01, 0x01) => "IDE controller",
+            (0x01, 0x06) => "SATA controller",
+            (0x01, 0x08) => "NVMe controller",
+            (0x01, _) => "Mass storage controller",
+            (0x02, 0x00) => "Ethernet controller",
+            (0x02, _) => "Network controller",
+            (0x03, _) => "Display controller",
+            (0x04, _) => "Multimedia controller",
+            (0x05, _) => "Memory controller",
+            (0x06, 0x00) => "Host bridge",
+            (0x06, 0x01) => "ISA bridge",
+            (0x06, 0x04) => "PCI bridge",
+            (0x06, _) => "Bridge",
+            (0x0C, 0x03) => "USB controller",
+            (0x0C, 0x05) => "SMBus controller",
+            (0x0C, _) => "Serial bus controller",
+            _ => return None
+        };
+
+        Some(name)
+    }
+
+    pub fn read_config(&self, offset: u16) -> u32 {
+        read_config(self.bus, self.device, self.function, offset)
+    }
+
+    pub fn write_config(&self, offset: u16, value: u32) {
+        write_config(self.bus, self.device, self.function, offset, value)
+    }
+
+    /// Returns the ID and the offset of every capability in the list that starts at [`CAPABILITIES_POINTER`],
+    /// like the ones of MSI (0x05) and MSI-X (0x11)
+    #[allow(dead_code)]
+    pub fn capabilities(&self) -> Vec<(u8, u16)> {
+        let mut capabilities = Vec::new();
+
+        if self.read_config(STATUS_REGISTER) >> 16 & STATUS_CAPABILITIES == 0 {
+            return capabilities;
+        }
+
+        let mut offset = (self.read_config(CAPABILITIES_POINTER) & 0xFC) as u16;
+
+        // A broken list could loop forever, but there's only room for 48 capabilities in the 256 bytes
+        while offset != 0 && capabilities.len() < 48 {
+            let header = self.read_config(offset);
+
+            capabilities.push((header as u8, offset));
+            offset = (header >> 8 & 0xFC) as u16;
+        }
+
+        return capabilities;
+    }
+
+    /// Returns the ID and the offset of every extended capability of a PCI Express function, which start right
+    /// after the first 256 bytes, like the ones of Advanced Error Reporting (0x01). Always empty without ECAM
+    #[allow(dead_code)]
+    pub fn extended_capabilities(&self) -> Vec<(u16, u16)> {
+        let mut capabilities = Vec::new();
+
+        if !has_extended_config() {
+            return capabilities;
+        }
+
+        let mut offset = LEGACY_CONFIG_SIZE;
+
+        // Same as in `capabilities`, 4 bytes is the smallest an extended capability can be
+        while offset >= LEGACY_CONFIG_SIZE && capabilities.len() < 960 {
+            let header = self.read_config(offset);
+
+            // The list is empty when the first header is all zeros (or missing, all ones)
+            if header == 0 || header == u32::MAX {
+                break;
+            }
+
+            capabilities.push((header as u16, offset));
+            offset = (header >> 20) as u16 & 0xFFC;
+        }
+
+        return capabilities;
+    }
+}
+
+/// Looks for the memory-mapped configuration space (ECAM) in the MCFG ACPI table, which gives access to
+/// the extended configuration space of PCI Express functions. Must be called once, after the memory is
+/// initialized and before any process is created
+pub fn init() {
+    *ECAM.lock() = Ecam::find();
+}
+
+/// Returns whatever the 4 KiB extended configuration space is reachable, see [`init`]
+pub fn has_extended_config() -> bool {
+    ECAM.lock().is_some()
+}
+
+/// Returns the address of `offset` through the I/O ports (configuration mechanism #1)
+fn legacy_address(bus: u8, device: u8, function: u8, offset: u16) -> u32 {
+    0x8000_0000
+        | (bus as u32) << 16
+        | (device as u32 & 0x1F) << 11
+        | (function as u32 & 0x07) << 8
+        | (offset as u32 & 0xFC)
+}
+
+/// Reads a 32 bits register from the configuration space of a function, through ECAM when available or
+/// through the I/O ports otherwise. `offset` is rounded down to a multiple of 4.
+///
+/// The registers past the first 256 bytes read as all ones when there's no ECAM, just like
+/// the registers of a function that doesn't exist
+pub fn read_config(bus: u8, device: u8, function: u8, offset: u16) -> u32 {
+    if offset >= EXTENDED_CONFIG_SIZE {
+        return u32::MAX;
+    }
+
+    if let Some(ecam) = ECAM.lock().as_mut() {
+        if let Some(address) = ecam.address(bus, device, function, offset) {
+            // The address is mapped and aligned to 4 bytes
+            return unsafe { core::ptr::read_volatile(address.as_ptr::<u32>()) };
+        }
+    }
+
+    if offset >= LEGACY_CONFIG_SIZE {
+        return u32::MAX;
+    }
+
+    let mut address_port: Port<u32> = Port::new(CONFIG_ADDRESS);
+    let mut data_port: Port<u32> = Port::new(CONFIG_DATA);
+
+    // Both ports have to be accessed together, an interrupt handler reading another register in between would
+    // change the selected one
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        address_port.write(legacy_address(bus, device, function, offset));
+        data_port.read()
+    })
+}
+
+/// Writes a 32 bits register of the configuration space of a function, the same way as [`read_config`].
+/// Writes past the first 256 bytes are ignored when there's no ECAM
+pub fn write_config(bus: u8, device: u8, function: u8, offset: u16, value: u32) {
+    if offset >= EXTENDED_CONFIG_SIZE {
+        return;
+    }
+
+    if let Some(ecam) = ECAM.lock().as_mut() {
+        if let Some(address) = ecam.address(bus, device, function, offset) {
+            unsafe { core::ptr::write_volatile(address.as_mut_ptr::<u32>(), value) };
+            return;
+        }
+    }
+
+    if offset >= LEGACY_CONFIG_SIZE {
+        return;
+    }
+
+    let mut address_port: Port<u32> = Port::new(CONFIG_ADDRESS);
+    let mut data_port: Port<u32> = Port::new(CONFIG_DATA);
+
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        address_port.write(legacy_address(bus, device, function, offset));
+        data_port.write(value);
+    });
+}
+
+/// Reads the identification of a function, or returns `None` if there's nothing there
+fn probe(bus: u8, device: u8, function: u8) -> Option<PciDevice> {
+    let id = read_config(bus, device, function, 0x00);
+
+    if id as u16 == NO_VENDOR {
+        return None;
+    }
+
+    let class = read_config(bus, device, function, 0x08);
+
+    Some(PciDevice {
+        bus,
+        device,
+        function,
+        vendor_id: id as u16,
+        device_id: (id >> 16) as u16,
+        class: (class >> 24) as u8,
+        subclass: (class >> 16) as u8,
+        prog_if: (class >> 8) as u8
+    })
+}
+
+/// Finds every function of every device on every bus by trying all of them, which takes a while
+/// (about 8000 reads) but doesn't depend on the bridges being set up in any particular way
+pub fn devices() -> Vec<PciDevice> {
+    let mut devices = Vec::new();
+
+    for bus in 0..=255 {
+        for device in 0..32 {
+            let first = match probe(bus, device, 0) {
+                Some(first) => first,
+                None => continue
+            };
+
+            devices.push(first);
+
+            // Bit 7 of the header type tells whatever the device has more than one function
+            let header_type = (read_config(bus, device, 0, 0x0C) >> 16) as u8;
+
+            if header_type & 0x80 == 0 {
+                continue;
+            }
+
+            for function in 1..8 {
+                if let Some(found) = probe(bus, device, function) {
+                    devices.push(found);
+                }
+            }
+        }
+    }
+
+    return devices;
+}
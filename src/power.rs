@@ -0,0 +1,240 @@
+use x86_64::instructions::port::Port;
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::acpi::{self, Fadt, GenericAddress, SdtHeader};
+use crate::memory::physical_to_virtual;
+use crate::println;
+
+/// The bits of the PM1 control registers: the SCI is on (the machine is in ACPI mode), the sleep type
+/// (bits 10 to 12) and the bit that enters the sleep state
+const PM1_SCI_ENABLE: u16 = 1 << 0;
+const PM1_SLEEP_TYPE_SHIFT: u16 = 10;
+const PM1_SLEEP_TYPE_MASK: u16 = 0b111 << PM1_SLEEP_TYPE_SHIFT;
+const PM1_SLEEP_ENABLE: u16 = 1 << 13;
+
+/// How many times the SCI is checked after asking the firmware to switch to ACPI mode, about 3 seconds
+/// counting the delay between them
+const ACPI_ENABLE_ATTEMPTS: usize = 300;
+
+/// The AML opcodes needed to find the `\_S5` package in the DSDT
+const AML_NAME: u8 = 0x08;
+const AML_PACKAGE: u8 = 0x12;
+const AML_BYTE_PREFIX: u8 = 0x0A;
+const AML_ZERO: u8 = 0x00;
+const AML_ONE: u8 = 0x01;
+
+/// The PM1a control registers of the machines QEMU emulates (q35 and the older i440fx), writing
+/// [`PM1_SLEEP_ENABLE`] with a sleep type of 0 powers them off
+const QEMU_PM1A_CONTROL_PORTS: [u16; 2] = [0x604, 0xB004];
+
+/// The port of the `isa-debug-exit` device of QEMU, when it's added with `-device isa-debug-exit,iobase=0xf4,iosize=0x04`
+const QEMU_DEBUG_EXIT_PORT: u16 = 0xF4;
+
+/// The address spaces of a [`GenericAddress`] the reset register can be in
+const ADDRESS_SPACE_MEMORY: u8 = 0;
+const ADDRESS_SPACE_IO: u8 = 1;
+const ADDRESS_SPACE_PCI: u8 = 2;
+
+/// The command of the 8042 PS/2 controller that pulses its output lines, the first of them is wired to the reset of the CPU
+const PS2_COMMAND_PORT: u16 = 0x64;
+const PS2_STATUS_INPUT_FULL: u8 = 1 << 1;
+const PS2_PULSE_RESET: u8 = 0xFE;
+
+/// How many times each way to reset is checked for having worked (by still running) before the next one is tried
+const RESET_ATTEMPTS: usize = 100_000;
+
+/// The sleep types to write to the PM1 control registers of both blocks to enter a sleep state
+#[derive(Debug, Copy, Clone)]
+struct SleepTypes {
+    a: u8,
+    b: u8
+}
+
+/// Reads the value of an integer element of an AML package, returning it together with the size of its encoding
+fn read_aml_integer(bytes: &[u8]) -> Option<(u8, usize)> {
+    match *bytes.first()? {
+        AML_ZERO => Some((0, 1)),
+        AML_ONE => Some((1, 1)),
+        AML_BYTE_PREFIX => Some((*bytes.get(1)?, 2)),
+        _ => None
+    }
+}
+
+/// Finds the sleep types of the S5 state (soft off) in the `\_S5` package of the DSDT. The AML isn't interpreted,
+/// the object is found by its name like every hobby kernel does, which works on all the firmware that matters
+fn find_s5(fadt: &Fadt) -> Option<SleepTypes> {
+    let header: SdtHeader = unsafe { acpi::read_table(fadt.dsdt, 0) };
+
+    // The DSDT isn't in the RSDT, so nothing checked it yet, a wrong signature means the address is wrong
+    if &header.signature != b"DSDT" {
+        return None;
+    }
+
+    let dsdt = unsafe {
+        core::slice::from_raw_parts(physical_to_virtual(fadt.dsdt).as_ptr::<u8>(), header.length as usize)
+    };
+
+    let start = dsdt.windows(4).position(|window| window == b"_S5_")?;
+
+    // The name must be defined right there (`Name (_S5, Package ...)`), optionally from the root (`\_S5`)
+    let defined = start >= 1 && dsdt[start - 1] == AML_NAME
+        || start >= 2 && dsdt[start - 1] == b'\\' && dsdt[start - 2] == AML_NAME;
+
+    if !defined || *dsdt.get(start + 4)? != AML_PACKAGE {
+        return None;
+    }
+
+    // Bits 6 and 7 of the first byte of the length of the package tell how many bytes follow it,
+    // then comes the element count
+    let length_bytes = (*dsdt.get(start + 5)? >> 6) as usize + 1;
+    let elements = dsdt.get(start + 5 + length_bytes + 1..)?;
+
+    let (a, size) = read_aml_integer(elements)?;
+    let (b, _) = read_aml_integer(&elements[size..])?;
+
+    Some(SleepTypes { a, b })
+}
+
+/// Switches the machine to ACPI mode if the firmware still handles the power management, returning whatever it worked
+fn enable_acpi(fadt: &Fadt) -> bool {
+    let mut control: Port<u16> = Port::new(fadt.pm1a_control_block as u16);
+
+    if unsafe { control.read() } & PM1_SCI_ENABLE != 0 {
+        return true;
+    }
+
+    if fadt.smi_command_port == 0 || fadt.acpi_enable == 0 {
+        return false;
+    }
+
+    unsafe { Port::<u8>::new(fadt.smi_command_port as u16).write(fadt.acpi_enable) };
+
+    for _ in 0..ACPI_ENABLE_ATTEMPTS {
+        if unsafe { control.read() } & PM1_SCI_ENABLE != 0 {
+            return true;
+        }
+
+        // The interrupts are disabled while shutting down, reading an unused port takes about 1µs
+        for _ in 0..10_000 {
+            unsafe { Port::<u8>::new(0x80).read() };
+        }
+    }
+
+    return false;
+}
+
+/// Enters the sleep state with the given types by writing them to the PM1 control registers
+fn enter_sleep_state(fadt: &Fadt, types: SleepTypes) {
+    for (block, sleep_type) in [(fadt.pm1a_control_block, types.a), (fadt.pm1b_control_block, types.b)] {
+        if block == 0 {
+            continue;
+        }
+
+        let mut control: Port<u16> = Port::new(block as u16);
+
+        unsafe {
+            let value = control.read() & !PM1_SLEEP_TYPE_MASK;
+            control.write(value | (sleep_type as u16 & 0b111) << PM1_SLEEP_TYPE_SHIFT | PM1_SLEEP_ENABLE);
+        }
+    }
+}
+
+/// Powers the machine off through ACPI (the S5 state), falling back to the ports that power off QEMU
+/// and halting forever if nothing worked
+#[allow(dead_code)]
+pub fn shutdown() -> ! {
+    x86_64::instructions::interrupts::disable();
+
+    if let Some(fadt) = acpi::fadt() {
+        match find_s5(&fadt) {
+            Some(types) if fadt.pm1a_control_block != 0 && enable_acpi(&fadt) => enter_sleep_state(&fadt, types),
+            _ => println!("Failed to power off through ACPI")
+        }
+    }
+
+    for port in QEMU_PM1A_CONTROL_PORTS {
+        unsafe { Port::<u16>::new(port).write(PM1_SLEEP_ENABLE) };
+    }
+
+    exit_qemu(0);
+}
+
+/// Makes QEMU exit with the status `(code << 1) | 1` through its `isa-debug-exit` device, meant for the tests.
+/// Halts forever when the device isn't there
+pub fn exit_qemu(code: u32) -> ! {
+    x86_64::instructions::interrupts::disable();
+
+    unsafe { Port::<u32>::new(QEMU_DEBUG_EXIT_PORT).write(code) };
+
+    println!("It is now safe to turn off your computer");
+
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Writes the reset value to the ACPI reset register, only the address spaces ACPI allows for it are handled
+fn reset_through_acpi(register: GenericAddress, value: u8) {
+    let address = register.address;
+
+    match register.address_space {
+        ADDRESS_SPACE_IO => unsafe { Port::<u8>::new(address as u16).write(value) },
+        ADDRESS_SPACE_MEMORY => {
+            if let Ok(mapped) = crate::memory::mmio::map(PhysAddr::new(address), 1) {
+                unsafe { core::ptr::write_volatile(mapped.as_mut_ptr::<u8>(), value) };
+            }
+        }
+        ADDRESS_SPACE_PCI => {
+            // The address has the device in bits 32 to 47, the function in bits 16 to 31 and the offset in bits 0 to 15,
+            // always on bus 0
+            let (device, function, offset) = ((address >> 32) as u8, (address >> 16) as u8, address as u16);
+            let shift = (offset & 3) * 8;
+
+            let old = crate::pci::read_config(0, device, function, offset);
+            crate::pci::write_config(0, device, function, offset, old & !(0xFF << shift) | (value as u32) << shift);
+        }
+        _ => {}
+    }
+}
+
+/// Asks the 8042 PS/2 controller to pulse the reset line of the CPU
+fn reset_through_8042() {
+    let mut status: Port<u8> = Port::new(PS2_COMMAND_PORT);
+
+    for _ in 0..RESET_ATTEMPTS {
+        if unsafe { status.read() } & PS2_STATUS_INPUT_FULL == 0 {
+            break;
+        }
+    }
+
+    unsafe { Port::<u8>::new(PS2_COMMAND_PORT).write(PS2_PULSE_RESET) };
+}
+
+/// Gives a reset some time to happen before trying the next way
+fn wait_for_reset() {
+    for _ in 0..RESET_ATTEMPTS {
+        core::hint::spin_loop();
+    }
+}
+
+/// Restarts the machine through the ACPI reset register, then through the 8042 PS/2 controller and finally
+/// by causing a triple fault, which always resets the CPU
+pub fn reboot() -> ! {
+    x86_64::instructions::interrupts::disable();
+
+    if let Some((register, value)) = acpi::fadt().and_then(|fadt| Some((fadt.reset_register?, fadt.reset_value))) {
+        reset_through_acpi(register, value);
+        wait_for_reset();
+    }
+
+    reset_through_8042();
+    wait_for_reset();
+
+    // With an empty IDT the breakpoint can't be handled, neither can the double fault that follows
+    unsafe {
+        let idt = DescriptorTablePointer { limit: 0, base: VirtAddr::new(0) };
+
+        x86_64::instructions::tables::lidt(&idt);
+        core::arch::asm!("int3", options(noreturn));
+    }
+}
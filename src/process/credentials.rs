@@ -0,0 +1,22 @@
+/// Who a process acts as, what it can do with the nodes of the VFS depends on it (see
+/// [`crate::fs::Metadata::check_access`])
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32
+}
+
+impl Credentials {
+    /// The credentials of the kernel and of the processes it starts, they can do everything
+    pub const ROOT: Credentials = Credentials { uid: 0, gid: 0 };
+
+    pub fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+}
+
+/// Returns the credentials of the running thread: the ones of its process, or [`Credentials::ROOT`] for the kernel
+/// threads
+pub fn current() -> Credentials {
+    crate::process::current().map_or(Credentials::ROOT, |process| process.credentials())
+}
@@ -0,0 +1,209 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use x86_64::structures::paging::{Page, Size4KiB};
+use x86_64::VirtAddr;
+use crate::fs::{FileSystem, Node};
+use crate::memory::address_space::AddressSpace;
+use crate::process::mmap::{FileMapping, Mappings};
+use crate::utils::error::KernelError;
+
+/// `\x7FELF`
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELF_CLASS_64: u8 = 2;
+const ELF_DATA_LITTLE_ENDIAN: u8 = 1;
+const ELF_TYPE_EXECUTABLE: u16 = 2;
+const ELF_MACHINE_X86_64: u16 = 0x3E;
+
+const PROGRAM_TYPE_LOAD: u32 = 1;
+const PROGRAM_FLAG_EXECUTE: u32 = 1;
+const PROGRAM_FLAG_WRITE: u32 = 2;
+
+/// The size of the ELF header and of every program header of a 64 bits ELF
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// A loadable segment described by a program header
+struct Segment {
+    offset: u64,
+    address: u64,
+    file_size: u64,
+    memory_size: u64,
+    flags: u32
+}
+
+impl Segment {
+    /// The first and the last address of the segment in memory
+    fn range(&self) -> Result<(VirtAddr, VirtAddr), KernelError> {
+        let end = self.address.checked_add(self.memory_size).ok_or(KernelError::InvalidArgument)?;
+
+        if self.file_size > self.memory_size {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let start = VirtAddr::try_new(self.address).map_err(|_| KernelError::InvalidArgument)?;
+        let last = VirtAddr::try_new(end - 1).map_err(|_| KernelError::InvalidArgument)?;
+
+        return Ok((start, last));
+    }
+
+    fn writable(&self) -> bool {
+        self.flags & PROGRAM_FLAG_WRITE != 0
+    }
+
+    fn executable(&self) -> bool {
+        self.flags & PROGRAM_FLAG_EXECUTE != 0
+    }
+}
+
+/// Reads the headers of an ELF executable, returning its entry point and its `PT_LOAD` segments that aren't empty.
+/// `read` fills a buffer with the bytes of the executable at an offset, failing if they aren't all there
+fn parse(read: impl Fn(u64, &mut [u8]) -> Result<(), KernelError>) -> Result<(VirtAddr, Vec<Segment>), KernelError> {
+    let mut header = [0; ELF_HEADER_SIZE];
+    read(0, &mut header)?;
+
+    if header[0..4] != ELF_MAGIC
+        || header[4] != ELF_CLASS_64
+        || header[5] != ELF_DATA_LITTLE_ENDIAN
+        || read_u16(&header, 16)? != ELF_TYPE_EXECUTABLE
+        || read_u16(&header, 18)? != ELF_MACHINE_X86_64 {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let entry = read_u64(&header, 24)?;
+    let program_headers_offset = read_u64(&header, 32)?;
+    let program_header_size = read_u16(&header, 54)? as u64;
+    let program_header_count = read_u16(&header, 56)? as u64;
+
+    if program_header_size < PROGRAM_HEADER_SIZE as u64 {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let mut segments = Vec::new();
+    let mut program_header = [0; PROGRAM_HEADER_SIZE];
+
+    for index in 0..program_header_count {
+        let header_offset = index.checked_mul(program_header_size)
+            .and_then(|offset| offset.checked_add(program_headers_offset))
+            .ok_or(KernelError::InvalidArgument)?;
+
+        read(header_offset, &mut program_header)?;
+
+        if read_u32(&program_header, 0)? != PROGRAM_TYPE_LOAD || read_u64(&program_header, 40)? == 0 {
+            continue;
+        }
+
+        segments.push(Segment {
+            flags: read_u32(&program_header, 4)?,
+            offset: read_u64(&program_header, 8)?,
+            address: read_u64(&program_header, 16)?,
+            file_size: read_u64(&program_header, 32)?,
+            memory_size: read_u64(&program_header, 40)?
+        });
+    }
+
+    let entry = VirtAddr::try_new(entry).map_err(|_| KernelError::InvalidArgument)?;
+    return Ok((entry, segments));
+}
+
+/// Loads the statically linked ELF executable in `image` into `address_space`, returning its entry point.
+///
+/// Every `PT_LOAD` segment is mapped with the permissions it asks for and the part that isn't in the file (like `.bss`)
+/// is left zeroed. The program must be linked at an address the kernel doesn't use, see [`AddressSpace::map_user_pages`]
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if the image isn't a valid x86_64 executable, or the error of the mapping
+pub fn load(image: &[u8], address_space: &mut AddressSpace) -> Result<VirtAddr, KernelError> {
+    let (entry, segments) = parse(|offset, buffer| {
+        let bytes = usize::try_from(offset).ok()
+            .and_then(|offset| image.get(offset..offset.checked_add(buffer.len())?))
+            .ok_or(KernelError::InvalidArgument)?;
+
+        buffer.copy_from_slice(bytes);
+        Ok(())
+    })?;
+
+    for segment in &segments {
+        load_segment(image, segment, address_space)?;
+    }
+
+    return Ok(entry);
+}
+
+/// Maps the statically linked ELF executable in the file `node` without reading its segments, they're read from the
+/// file when they're first touched (see [`crate::process::mmap`]). Returns the entry point and the mappings of the
+/// segments, the part of a segment that isn't in the file (like `.bss`) is zeros. The mappings keep `fs`, the
+/// filesystem the file is in, mounted
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if the file isn't a valid x86_64 executable or one of its segments isn't
+/// at the same offset in its page as in the file, [`KernelError::Unsupported`] if two segments share a page (the
+/// programs are linked with their sections page aligned for this, see `user/link.ld`), or the error of reading the file
+pub fn map(fs: &Arc<dyn FileSystem>, node: &Arc<dyn Node>) -> Result<(VirtAddr, Mappings), KernelError> {
+    let (entry, segments) = parse(|offset, buffer| match node.read_at(offset, buffer)? {
+        count if count == buffer.len() => Ok(()),
+        _ => Err(KernelError::InvalidArgument)
+    })?;
+
+    let mut mappings = Mappings::new();
+
+    for segment in &segments {
+        let (start, last) = segment.range()?;
+
+        if segment.offset % 4096 != start.as_u64() % 4096 {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        // The first page of the mapping also has the bytes of the file before the segment, like the ELF header
+        let first_page = start.align_down(4096u64);
+        let pages = (last - first_page) / 4096 + 1;
+        let in_file = (start - first_page) + segment.file_size;
+
+        let mapping = FileMapping::new(
+            first_page, pages, node.clone(), segment.offset - (start - first_page), segment.writable(),
+            segment.executable()
+        )?.in_filesystem(Some(fs.clone()));
+
+        mappings.insert(mapping.with_file_size(in_file)).map_err(|_| KernelError::Unsupported)?;
+    }
+
+    return Ok((entry, mappings));
+}
+
+fn load_segment(image: &[u8], segment: &Segment, address_space: &mut AddressSpace) -> Result<(), KernelError> {
+    let (start, last) = segment.range()?;
+
+    let data = usize::try_from(segment.offset).ok()
+        .zip(usize::try_from(segment.file_size).ok())
+        .and_then(|(offset, size)| image.get(offset..offset.checked_add(size)?))
+        .ok_or(KernelError::InvalidArgument)?;
+
+    let writable = segment.writable();
+    let executable = segment.executable();
+
+    // Two segments may share a page (like the end of `.text` and the start of `.data`),
+    // the page keeps the permissions of the first one that mapped it
+    for page in Page::<Size4KiB>::range_inclusive(Page::containing_address(start), Page::containing_address(last)) {
+        if address_space.translate(page.start_address()).is_none() {
+            address_space.map_user_pages(page.start_address(), 1, writable, executable)?;
+        }
+    }
+
+    return address_space.write(start, data);
+}
+
+fn read_u16(image: &[u8], offset: usize) -> Result<u16, KernelError> {
+    let bytes = image.get(offset..offset + 2).ok_or(KernelError::InvalidArgument)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(image: &[u8], offset: usize) -> Result<u32, KernelError> {
+    let bytes = image.get(offset..offset + 4).ok_or(KernelError::InvalidArgument)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(image: &[u8], offset: usize) -> Result<u64, KernelError> {
+    let bytes = image.get(offset..offset + 8).ok_or(KernelError::InvalidArgument)?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
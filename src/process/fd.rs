@@ -0,0 +1,167 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::fs::{ACCESS_READ, ACCESS_WRITE, AsAny, DirEntry, FileHandle, FileSystem, Metadata, Node, OpenNode, SeekFrom};
+use crate::utils::error::KernelError;
+
+/// The most files a single process can have open at the same time
+const MAX_OPEN_FILES: usize = 64;
+
+/// A file descriptor, an index into the [`FileTable`] of a process
+pub type Fd = usize;
+
+/// The file descriptors of the standard files every program starts with, all of them the console
+pub const STDIN: Fd = 0;
+pub const STDOUT: Fd = 1;
+pub const STDERR: Fd = 2;
+
+/// Something a process can read from or write to through a file descriptor,
+/// both operations are unsupported unless the implementation says otherwise. The system calls that only work on
+/// one kind of file (like the sockets) find it behind the `dyn File` with [`AsAny`]
+#[allow(dead_code)]
+pub trait File: Send + Sync + AsAny {
+    fn read(&self, _buffer: &mut [u8]) -> Result<usize, KernelError> {
+        Err(KernelError::Unsupported)
+    }
+
+    fn write(&self, _buffer: &[u8]) -> Result<usize, KernelError> {
+        Err(KernelError::Unsupported)
+    }
+
+    /// Moves the offset of the next read or write, returning where it is from the start of the file
+    fn seek(&self, _position: SeekFrom) -> Result<u64, KernelError> {
+        Err(KernelError::Unsupported)
+    }
+
+    /// Hands `fill` the next entries of the directory, see [`FileHandle::read_entries`]
+    fn read_entries(&self, _fill: &mut dyn FnMut(&DirEntry) -> bool) -> Result<(), KernelError> {
+        Err(KernelError::NotDirectory)
+    }
+
+    /// Returns the metadata of the node behind the file, see [`FileHandle::metadata`]
+    fn metadata(&self) -> Result<Metadata, KernelError> {
+        Err(KernelError::Unsupported)
+    }
+
+    /// Returns the node behind the file so its content can be mapped in memory, see [`crate::process::mmap`]. The
+    /// file must be open for reading
+    fn node(&self) -> Result<Arc<dyn Node>, KernelError> {
+        Err(KernelError::Unsupported)
+    }
+
+    /// Returns the filesystem the node behind the file is in, see [`FileHandle::filesystem`]
+    fn filesystem(&self) -> Option<Arc<dyn FileSystem>> {
+        None
+    }
+}
+
+/// A node of the VFS opened by a process, see [`crate::fs::vfs::open`]
+pub struct VfsFile {
+    handle: Arc<dyn FileHandle>,
+    /// What the node was opened for, [`ACCESS_READ`] and [`ACCESS_WRITE`]. The permissions are checked when it's
+    /// opened, changing them later doesn't change what the file can do
+    access: u16
+}
+
+impl VfsFile {
+    pub fn new(handle: Arc<dyn FileHandle>, access: u16) -> Self {
+        VfsFile { handle, access }
+    }
+
+    fn check(&self, access: u16) -> Result<(), KernelError> {
+        if self.access & access == 0 {
+            return Err(KernelError::PermissionDenied);
+        }
+
+        Ok(())
+    }
+}
+
+impl File for VfsFile {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, KernelError> {
+        self.check(ACCESS_READ)?;
+        self.handle.read(buffer)
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize, KernelError> {
+        self.check(ACCESS_WRITE)?;
+        self.handle.write(buffer)
+    }
+
+    fn seek(&self, position: SeekFrom) -> Result<u64, KernelError> {
+        self.handle.seek(position)
+    }
+
+    fn read_entries(&self, fill: &mut dyn FnMut(&DirEntry) -> bool) -> Result<(), KernelError> {
+        self.handle.read_entries(fill)
+    }
+
+    fn metadata(&self) -> Result<Metadata, KernelError> {
+        self.handle.metadata()
+    }
+
+    fn node(&self) -> Result<Arc<dyn Node>, KernelError> {
+        self.check(ACCESS_READ)?;
+        self.handle.node().ok_or(KernelError::Unsupported)
+    }
+
+    fn filesystem(&self) -> Option<Arc<dyn FileSystem>> {
+        self.handle.filesystem()
+    }
+}
+
+/// The files opened by a process, indexed by their file descriptor
+#[derive(Clone)]
+pub struct FileTable {
+    files: Vec<Option<Arc<dyn File>>>
+}
+
+#[allow(dead_code)]
+impl FileTable {
+    pub const fn new() -> Self {
+        FileTable {
+            files: Vec::new()
+        }
+    }
+
+    /// Creates a table with the standard files, [`STDIN`], [`STDOUT`] and [`STDERR`], all of them sharing the same
+    /// handle of the console
+    pub fn with_console() -> Self {
+        let handle = Arc::new(OpenNode::new(crate::fs::devfs::console()));
+        let console: Arc<dyn File> = Arc::new(VfsFile::new(handle, ACCESS_READ | ACCESS_WRITE));
+        let mut files = alloc::vec![None; 3];
+
+        for fd in [STDIN, STDOUT, STDERR] {
+            files[fd] = Some(console.clone());
+        }
+
+        FileTable { files }
+    }
+
+    /// Adds `file` to the table, returning the lowest file descriptor that was free
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Busy`] if the process already has [`MAX_OPEN_FILES`] files open
+    pub fn insert(&mut self, file: Arc<dyn File>) -> Result<Fd, KernelError> {
+        if let Some(fd) = self.files.iter().position(|slot| slot.is_none()) {
+            self.files[fd] = Some(file);
+            return Ok(fd);
+        }
+
+        if self.files.len() >= MAX_OPEN_FILES {
+            return Err(KernelError::Busy);
+        }
+
+        self.files.push(Some(file));
+        return Ok(self.files.len() - 1);
+    }
+
+    pub fn get(&self, fd: Fd) -> Option<Arc<dyn File>> {
+        self.files.get(fd)?.clone()
+    }
+
+    /// Removes the file from the table, it's closed once nobody else holds it
+    pub fn remove(&mut self, fd: Fd) -> Option<Arc<dyn File>> {
+        self.files.get_mut(fd)?.take()
+    }
+}
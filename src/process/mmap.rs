@@ -0,0 +1,346 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use x86_64::structures::paging::{Page, Size4KiB};
+use x86_64::VirtAddr;
+use crate::fs::{FileSystem, Node, NodeKind};
+use crate::process::fd::File;
+use crate::memory::address_space::{self, AddressSpace, UnmappedFrame};
+use crate::process::Process;
+use crate::utils::error::KernelError;
+
+/// Where the mappings whose address is chosen by the kernel go, between the programs (see `user/link.ld`) and the
+/// stack of the main thread
+const MMAP_START: u64 = 0x_7000_0000_0000;
+const MMAP_END: u64 = 0x_7F00_0000_0000;
+
+/// A part of the address space of a process whose content comes from a file. Nothing is mapped when it's created,
+/// every page is read from the file the first time it's touched (see [`handle_fault`]) into a frame of its own, so
+/// the writes to the pages of a writable mapping are private to the process and never reach the file (after a fork
+/// the pages are copy-on-write like the others). The pages read before the file changes keep the old content
+#[derive(Clone)]
+pub struct FileMapping {
+    /// The first page of the mapping
+    start: VirtAddr,
+    pages: u64,
+    node: Arc<dyn Node>,
+    /// Where the first page is in the file
+    offset: u64,
+    /// How many bytes from the start of the mapping come from the file, the rest of the mapping is zeros (like the
+    /// `.bss` of a program)
+    file_size: u64,
+    writable: bool,
+    executable: bool,
+    /// The filesystem the node is in, held so it can't be unmounted while the node is mapped (see
+    /// [`crate::fs::vfs::umount`]), like the one of an [`crate::fs::OpenNode`]
+    fs: Option<Arc<dyn FileSystem>>
+}
+
+impl FileMapping {
+    /// Maps `pages` pages starting at `start` (page aligned) to the content of `node` from `offset` (page aligned)
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if the address or the offset isn't page aligned, if there are no
+    /// pages or if they aren't valid for user mappings (see [`AddressSpace::map_user_pages`]), and
+    /// [`KernelError::Unsupported`] if the node isn't a file
+    pub(super) fn new(
+        start: VirtAddr, pages: u64, node: Arc<dyn Node>, offset: u64, writable: bool, executable: bool
+    ) -> Result<Self, KernelError> {
+        if !start.is_aligned(4096u64) || offset % 4096 != 0 || !is_user_range(start, pages) {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        if node.kind() != NodeKind::File {
+            return Err(KernelError::Unsupported);
+        }
+
+        Ok(FileMapping { start, pages, node, offset, file_size: pages * 4096, writable, executable, fs: None })
+    }
+
+    /// Keeps `fs`, the filesystem the node is in, mounted for as long as a page of the mapping is left
+    pub(super) fn in_filesystem(self, fs: Option<Arc<dyn FileSystem>>) -> Self {
+        FileMapping { fs, ..self }
+    }
+
+    /// Only takes the first `size` bytes of the mapping from the file, the others are zeros
+    pub(super) fn with_file_size(self, size: u64) -> Self {
+        FileMapping { file_size: size.min(self.file_size), ..self }
+    }
+
+    fn end(&self) -> VirtAddr {
+        self.start + self.pages * 4096
+    }
+
+    fn contains(&self, address: VirtAddr) -> bool {
+        (self.start..self.end()).contains(&address)
+    }
+
+    /// Returns the part of the mapping that's `skipped` pages after its start, with `pages` pages
+    fn slice(&self, skipped: u64, pages: u64) -> Self {
+        FileMapping {
+            start: self.start + skipped * 4096,
+            pages,
+            offset: self.offset + skipped * 4096,
+            file_size: self.file_size.saturating_sub(skipped * 4096),
+            node: self.node.clone(),
+            fs: self.fs.clone(),
+            ..*self
+        }
+    }
+
+    /// Fills `buffer` (zeroed) with the content of the page at `page`, the part past the end of the file stays zeros
+    fn read_page(&self, page: VirtAddr, buffer: &mut [u8]) -> Result<(), KernelError> {
+        let distance = page - self.start;
+        let in_file = self.file_size.saturating_sub(distance).min(4096) as usize;
+        let mut read = 0;
+
+        while read < in_file {
+            let count = self.node.read_at(self.offset + distance + read as u64, &mut buffer[read..in_file])?;
+
+            if count == 0 {
+                break;
+            }
+
+            read += count;
+        }
+
+        Ok(())
+    }
+}
+
+/// The file mappings of a process, by the address they start at. They never overlap
+#[derive(Clone)]
+pub struct Mappings(BTreeMap<u64, FileMapping>);
+
+impl Mappings {
+    pub const fn new() -> Self {
+        Mappings(BTreeMap::new())
+    }
+
+    /// Returns the mapping `address` is in
+    fn find(&self, address: VirtAddr) -> Option<&FileMapping> {
+        let (_, mapping) = self.0.range(..=address.as_u64()).next_back()?;
+        mapping.contains(address).then_some(mapping)
+    }
+
+    /// Returns whatever none of the mappings has a page between `start` and `end` (exclusive)
+    fn is_free(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        match self.0.range(..end.as_u64()).next_back() {
+            Some((_, mapping)) => mapping.end() <= start,
+            None => true
+        }
+    }
+
+    /// Adds `mapping`
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::AlreadyExists`] if it overlaps another mapping
+    pub(super) fn insert(&mut self, mapping: FileMapping) -> Result<(), KernelError> {
+        if !self.is_free(mapping.start, mapping.end()) {
+            return Err(KernelError::AlreadyExists);
+        }
+
+        self.0.insert(mapping.start.as_u64(), mapping);
+        Ok(())
+    }
+
+    /// Takes the pages between `start` and `end` (exclusive) out of the mappings, the mappings cut in the middle are
+    /// split in two
+    fn remove(&mut self, start: VirtAddr, end: VirtAddr) {
+        let cut: alloc::vec::Vec<FileMapping> = self.0
+            .range(..end.as_u64())
+            .rev()
+            .map(|(_, mapping)| mapping)
+            .take_while(|mapping| mapping.end() > start)
+            .cloned()
+            .collect();
+
+        for mapping in cut {
+            self.0.remove(&mapping.start.as_u64());
+
+            if mapping.start < start {
+                self.0.insert(mapping.start.as_u64(), mapping.slice(0, (start - mapping.start) / 4096));
+            }
+
+            if mapping.end() > end {
+                let skipped = (end - mapping.start) / 4096;
+                self.0.insert(end.as_u64(), mapping.slice(skipped, mapping.pages - skipped));
+            }
+        }
+    }
+
+    /// Returns the lowest address from [`MMAP_START`] where `pages` pages are free, in the mappings and in
+    /// `address_space`
+    fn find_free(&self, pages: u64, address_space: &mut AddressSpace) -> Option<VirtAddr> {
+        let size = pages.checked_mul(4096)?;
+        let mut start = VirtAddr::new(MMAP_START);
+
+        while start.as_u64().checked_add(size)? <= MMAP_END {
+            let end = start + size;
+
+            // The level 4 entries used by the kernel can't be used at all, the search goes on after them
+            if !is_user_range(start, pages) {
+                start = (start + 1u64).align_up(1u64 << 39);
+                continue;
+            }
+
+            if let Some((_, mapping)) = self.0.range(..end.as_u64()).next_back() {
+                if mapping.end() > start {
+                    start = mapping.end();
+                    continue;
+                }
+            }
+
+            // The pages mapped by something else, like a shared memory region, are skipped one by one
+            match (0..pages).map(|page| start + page * 4096).find(|&page| address_space.translate(page).is_some()) {
+                Some(taken) => start = taken + 4096u64,
+                None => return Some(start)
+            }
+        }
+
+        return None;
+    }
+}
+
+/// Returns whatever the `pages` pages from `start` can be used for user mappings, see
+/// [`address_space::is_user_range`]
+fn is_user_range(start: VirtAddr, pages: u64) -> bool {
+    let end = match pages.checked_mul(4096).and_then(|size| start.as_u64().checked_add(size)) {
+        Some(end) if pages != 0 && end <= 0x_8000_0000_0000 => end,
+        _ => return false
+    };
+
+    let first: Page<Size4KiB> = Page::containing_address(start);
+    return address_space::is_user_range(first, Page::containing_address(VirtAddr::new(end - 1)));
+}
+
+/// Maps `pages` pages of `process` to the content of the node behind `file` from `offset` (page aligned), see
+/// [`FileMapping`]. The filesystem of the node stays mounted while it's mapped. The pages start at `address` if
+/// there's one, otherwise at an address chosen by the kernel (see [`MMAP_START`]), which is returned
+///
+/// ## Errors
+///
+/// Returns [`KernelError::AlreadyExists`] if the pages at `address` overlap another mapping or a page that's mapped
+/// already, [`KernelError::OutOfMemory`] if there's no room left for the mapping when its address is chosen, or the
+/// errors of [`File::node`] and [`FileMapping::new`]
+pub fn map(
+    process: &Process, address: Option<VirtAddr>, pages: u64, file: &dyn File, offset: u64, writable: bool,
+    executable: bool
+) -> Result<VirtAddr, KernelError> {
+    let node = file.node()?;
+
+    let mut mappings = process.mappings();
+    let mut address_space = process.address_space();
+
+    let start = match address {
+        Some(address) => address,
+        None => mappings.find_free(pages, &mut address_space).ok_or(KernelError::OutOfMemory)?
+    };
+
+    let mapping = FileMapping::new(start, pages, node, offset, writable, executable)?
+        .in_filesystem(file.filesystem());
+
+    if (0..pages).any(|page| address_space.translate(start + page * 4096).is_some()) {
+        return Err(KernelError::AlreadyExists);
+    }
+
+    mappings.insert(mapping)?;
+    return Ok(start);
+}
+
+/// Unmaps the `pages` pages from `start` (page aligned) of the file mappings of `process`, the pages of the range
+/// that aren't in a file mapping are left alone
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if the range isn't valid for user mappings
+pub fn unmap(process: &Process, start: VirtAddr, pages: u64) -> Result<(), KernelError> {
+    if !start.is_aligned(4096u64) || !is_user_range(start, pages) {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let end = start + pages * 4096;
+    let mut mappings = process.mappings();
+    let mut address_space = process.address_space();
+
+    // Only the pages of file mappings go, the others (like a shared memory region) have their own way out
+    let mut page = start;
+
+    while page < end {
+        if mappings.find(page).is_some() {
+            address_space.unmap_user_pages(page, 1)?;
+        }
+
+        page += 4096u64;
+    }
+
+    mappings.remove(start, end);
+    Ok(())
+}
+
+/// Maps the page of `process` at `address` from the file mapping it's in, returning `false` if it's in none (or if
+/// it's written and the mapping isn't writable). The page is read without holding the locks of the process, so
+/// the threads that touch other pages meanwhile aren't held up
+pub fn handle_fault(process: &Process, address: VirtAddr, write: bool) -> bool {
+    let page = address.align_down(4096u64);
+
+    let mapping = match process.mappings().find(page) {
+        Some(mapping) if mapping.writable || !write => mapping.clone(),
+        _ => return false
+    };
+
+    let mut frame = match UnmappedFrame::new() {
+        Ok(frame) => frame,
+        Err(_) => return false
+    };
+
+    if mapping.read_page(page, frame.bytes()).is_err() {
+        return false;
+    }
+
+    let mappings = process.mappings();
+
+    // The mapping may have been unmapped (or replaced) while the page was read, the access faults again then and
+    // finds out what's there now
+    if mappings.find(page).map_or(true, |current| current.start != mapping.start || current.offset != mapping.offset) {
+        return true;
+    }
+
+    return process.address_space().map_frame(page, frame, mapping.writable, mapping.executable).is_ok();
+}
+
+/// Maps the pages of the file mappings of `process` between `address` and `address + length` that aren't mapped
+/// yet, so the kernel can touch them without faulting (see [`crate::syscall`]). The range doesn't have to be in a
+/// file mapping, the pages that are in none are left alone
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if a page is written and its mapping isn't writable (the kernel would
+/// fault on it), or if it couldn't be read from its file
+pub fn populate(process: &Process, address: VirtAddr, length: u64, write: bool) -> Result<(), KernelError> {
+    if length == 0 || process.mappings().0.is_empty() {
+        return Ok(());
+    }
+
+    let mut page = address.align_down(4096u64);
+    let end = address + length;
+
+    while page < end {
+        let writable = process.mappings().find(page).map(|mapping| mapping.writable);
+
+        if let Some(writable) = writable {
+            if write && !writable {
+                return Err(KernelError::InvalidArgument);
+            }
+
+            if process.address_space().translate(page).is_none() && !handle_fault(process, page, write) {
+                return Err(KernelError::InvalidArgument);
+            }
+        }
+
+        page += 4096u64;
+    }
+
+    Ok(())
+}
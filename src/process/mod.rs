@@ -0,0 +1,456 @@
+pub mod credentials;
+pub mod fd;
+pub mod mmap;
+pub mod shm;
+pub mod signal;
+mod elf;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::VirtAddr;
+use crate::memory::address_space::{self, AddressSpace, SharedRegion};
+use crate::memory::stack;
+use crate::process::credentials::Credentials;
+use crate::process::fd::FileTable;
+use crate::process::mmap::Mappings;
+use crate::process::signal::SignalState;
+use crate::usermode::UserRegisters;
+use crate::task::JoinHandle;
+use crate::task::thread::{Thread, ThreadId};
+use crate::utils::error::KernelError;
+use crate::utils::Mutex;
+use crate::utils::mutex::MutexGuard;
+
+/// How many pages the kernel stack of every thread of a process has
+const KERNEL_STACK_PAGES: usize = 4;
+
+/// Where the stack of the main thread of a program ends, the last level 4 entry of the lower half
+/// is never used by the kernel (see [`AddressSpace`])
+const USER_STACK_TOP: u64 = 0x_7FFF_FFFF_F000;
+
+/// How many pages the stack of the main thread of a program has
+const USER_STACK_PAGES: usize = 16;
+
+/// Every process that still has threads, indexed by their ID
+static PROCESSES: Mutex<BTreeMap<ProcessId, Arc<Process>>> = Mutex::new(BTreeMap::new());
+
+/// The ID of the process the keyboard sends its signals to (like Ctrl+C), 0 when there's none
+static FOREGROUND: AtomicU64 = AtomicU64::new(0);
+
+/// A unique identifier for a [`Process`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ProcessId(u64);
+
+impl ProcessId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        ProcessId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for ProcessId {
+    fn from(id: u64) -> Self {
+        ProcessId(id)
+    }
+}
+
+/// A user program: an isolated address space, the files it opened and the threads running its code.
+///
+/// The process stays around while any of its threads is alive, once the last one is reaped the process
+/// is removed from the process table and its address space is freed
+pub struct Process {
+    id: ProcessId,
+    name: &'static str,
+    address_space: Mutex<AddressSpace>,
+    /// The parts of the address space whose pages come from files, see [`mmap`]. Always locked before the address
+    /// space
+    mappings: Mutex<Mappings>,
+    /// Cached so the scheduler doesn't have to lock the address space, it only changes on [`Process::exec`]
+    cr3: AtomicU64,
+    files: Mutex<FileTable>,
+    /// The absolute path the relative paths given by the process start from, without `.`, `..` or symbolic links
+    working_directory: Mutex<String>,
+    /// Who the process acts as, see [`Credentials`]
+    credentials: Mutex<Credentials>,
+    threads: Mutex<Vec<ThreadId>>,
+    /// The shared memory regions created by this process, see [`shm`]
+    shared_regions: Mutex<Vec<Arc<SharedRegion>>>,
+    signals: SignalState
+}
+
+#[allow(dead_code)]
+impl Process {
+    /// Creates a process with an empty address space (only the kernel is mapped) and no threads
+    pub fn new(name: &'static str) -> Result<Arc<Self>, KernelError> {
+        return Ok(Process::from_parts(name, AddressSpace::new()?, Mappings::new(), FileTable::new()));
+    }
+
+    /// Creates a process running the statically linked ELF executable in `image`, its main thread starts right away.
+    /// The new process becomes the foreground one (see [`set_foreground`]) and starts with the standard files
+    pub fn from_elf(name: &'static str, image: &[u8]) -> Result<Arc<Self>, KernelError> {
+        let mut address_space = AddressSpace::new()?;
+        let (entry, stack) = load_program(image, &mut address_space)?;
+
+        return Process::start(name, address_space, Mappings::new(), entry, stack);
+    }
+
+    /// Creates a process running the statically linked ELF executable in the file at `path`, like
+    /// [`Process::from_elf`]. The segments of the program are mapped from the file (see [`mmap`]), they're only read
+    /// once they're touched
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::PermissionDenied`] if the current process (if any) can't execute the file, or the
+    /// error of the loader
+    pub fn from_file(name: &'static str, path: &str) -> Result<Arc<Self>, KernelError> {
+        let mut address_space = AddressSpace::new()?;
+        let (entry, stack, mappings) = map_program(path, &mut address_space)?;
+
+        return Process::start(name, address_space, mappings, entry, stack);
+    }
+
+    /// Creates a process with the standard files whose main thread starts at `entry`, it becomes the foreground one
+    fn start(
+        name: &'static str, address_space: AddressSpace, mappings: Mappings, entry: VirtAddr, stack: VirtAddr
+    ) -> Result<Arc<Self>, KernelError> {
+        let process = Process::from_parts(name, address_space, mappings, FileTable::with_console());
+
+        if let Err(error) = process.spawn_thread(entry, stack) {
+            PROCESSES.lock().remove(&process.id);
+            return Err(error);
+        }
+
+        set_foreground(Some(process.id));
+
+        return Ok(process);
+    }
+
+    fn from_parts(name: &'static str, address_space: AddressSpace, mappings: Mappings, files: FileTable) -> Arc<Self> {
+        let process = Arc::new(Process {
+            id: ProcessId::new(),
+            name,
+            cr3: AtomicU64::new(address_space.cr3()),
+            address_space: Mutex::new(address_space),
+            mappings: Mutex::new(mappings),
+            files: Mutex::new(files),
+            working_directory: Mutex::new(String::from("/")),
+            credentials: Mutex::new(Credentials::ROOT),
+            threads: Mutex::new(Vec::new()),
+            shared_regions: Mutex::new(Vec::new()),
+            signals: SignalState::new()
+        });
+
+        PROCESSES.lock().insert(process.id, process.clone());
+
+        return process;
+    }
+
+    pub fn id(&self) -> ProcessId {
+        self.id
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The value of CR3 that activates the address space of this process
+    pub fn address_space_cr3(&self) -> u64 {
+        self.cr3.load(Ordering::Relaxed)
+    }
+
+    pub fn address_space(&self) -> MutexGuard<'_, AddressSpace> {
+        self.address_space.lock()
+    }
+
+    pub fn mappings(&self) -> MutexGuard<'_, Mappings> {
+        self.mappings.lock()
+    }
+
+    pub fn files(&self) -> MutexGuard<'_, FileTable> {
+        self.files.lock()
+    }
+
+    pub fn working_directory(&self) -> String {
+        self.working_directory.lock().clone()
+    }
+
+    /// Makes the directory at `path` the working directory of this process, a relative path starts from the
+    /// current one
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::NotDirectory`] if the node at `path` isn't a directory, or the error of resolving it
+    pub fn set_working_directory(&self, path: &str) -> Result<(), KernelError> {
+        let directory = crate::fs::vfs::canonical_directory(path)?;
+        *self.working_directory.lock() = directory;
+
+        Ok(())
+    }
+
+    pub fn credentials(&self) -> Credentials {
+        *self.credentials.lock()
+    }
+
+    pub fn set_credentials(&self, credentials: Credentials) {
+        *self.credentials.lock() = credentials;
+    }
+
+    pub fn signals(&self) -> &SignalState {
+        &self.signals
+    }
+
+    /// Copies the bytes at `address` in the memory of this process to `buffer`, the way the kernel reads what a
+    /// program gives it. The pages of the file mappings that weren't touched yet are read first (see
+    /// [`mmap::populate`]), so this may block
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if a byte isn't in a page the process can read, see
+    /// [`AddressSpace::copy_from_user`]
+    pub fn copy_from_user(&self, address: u64, buffer: &mut [u8]) -> Result<(), KernelError> {
+        let address = self.populate(address, buffer.len() as u64, false)?;
+        return self.address_space().copy_from_user(address, buffer);
+    }
+
+    /// Copies `data` to `address` in the memory of this process, like [`Process::copy_from_user`]
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if a byte isn't in a page the process can write to
+    pub fn copy_to_user(&self, address: u64, data: &[u8]) -> Result<(), KernelError> {
+        let address = self.populate(address, data.len() as u64, true)?;
+        return self.address_space().copy_to_user(address, data);
+    }
+
+    /// Checks that the process can access the `length` bytes at `address` (and write to them if `write` is set),
+    /// like [`Process::copy_from_user`] without copying anything. Nothing keeps the pages there afterwards
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if it can't
+    pub fn check_user_range(&self, address: u64, length: u64, write: bool) -> Result<(), KernelError> {
+        let address = self.populate(address, length, write)?;
+        return self.address_space().check_user_range(address, length, write);
+    }
+
+    /// Maps the pages of the file mappings in the `length` bytes at `address`, which must be in the part of the
+    /// address space left to user mappings (see [`address_space::is_user_memory`])
+    fn populate(&self, address: u64, length: u64, write: bool) -> Result<VirtAddr, KernelError> {
+        if !address_space::is_user_memory(address, length) {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let address = VirtAddr::new(address);
+        mmap::populate(self, address, length, write)?;
+
+        return Ok(address);
+    }
+
+    /// Returns the IDs of the threads of this process that didn't exit yet
+    pub fn threads(&self) -> Vec<ThreadId> {
+        self.threads.lock().clone()
+    }
+
+    /// Starts a new thread in this process that runs the user code at `entry` with the stack pointer at `user_stack`,
+    /// both must already be mapped in the address space of the process
+    pub fn spawn_thread(self: &Arc<Self>, entry: VirtAddr, user_stack: VirtAddr) -> Result<JoinHandle, KernelError> {
+        return self.spawn_thread_with(UserRegisters::new(entry, user_stack));
+    }
+
+    /// Starts a new thread in this process that enters user mode with the given registers
+    fn spawn_thread_with(self: &Arc<Self>, registers: UserRegisters) -> Result<JoinHandle, KernelError> {
+        let kernel_stack = stack::allocate_stack(KERNEL_STACK_PAGES)?;
+        let thread = Thread::new_user(self.name, self.clone(), registers, kernel_stack);
+        let handle = JoinHandle::new(thread.id(), thread.exit_status().clone());
+
+        self.threads.lock().push(thread.id());
+        crate::sched::add_thread(thread);
+
+        return Ok(handle);
+    }
+
+    /// Creates a copy of this process whose only thread resumes user mode with the given registers (the ones
+    /// of the thread that called fork), except for `rax` which is 0 so the child knows it's the child.
+    /// The memory is shared copy-on-write (the file mappings too), the open files are shared and the working directory
+    /// and the credentials are the same
+    pub fn fork(self: &Arc<Self>, registers: &UserRegisters) -> Result<Arc<Process>, KernelError> {
+        let mappings = self.mappings().clone();
+        let address_space = self.address_space().fork()?;
+        let files = self.files().clone();
+
+        let child = Process::from_parts(self.name, address_space, mappings, files);
+        *child.working_directory.lock() = self.working_directory();
+        *child.credentials.lock() = self.credentials();
+
+        let registers = UserRegisters {
+            rax: 0,
+            ..registers.clone()
+        };
+
+        if let Err(error) = child.spawn_thread_with(registers) {
+            PROCESSES.lock().remove(&child.id);
+            return Err(error);
+        }
+
+        return Ok(child);
+    }
+
+    /// Replaces the program of this process with the statically linked ELF executable in `image`, which may be in the
+    /// memory of the process itself. Must be called by the only thread of the process, which is the one that runs the
+    /// new program, so the entry point and the stack it has to enter user mode with are returned
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Busy`] if the process has more than one thread, or the error of the loader,
+    /// in which case the old program is left untouched
+    pub fn exec(&self, image: &[u8]) -> Result<(VirtAddr, VirtAddr), KernelError> {
+        if self.threads.lock().len() != 1 {
+            return Err(KernelError::Busy);
+        }
+
+        // The image is read from the old address space while the new one is built, so it has to stay active until then
+        let mut address_space = AddressSpace::new()?;
+        let program = load_program(image, &mut address_space)?;
+
+        return Ok(self.replace_program(address_space, Mappings::new(), program));
+    }
+
+    /// Replaces the program of this process with the statically linked ELF executable in the file at `path`, like
+    /// [`Process::exec`]. The segments of the program are mapped from the file (see [`mmap`])
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Busy`] if the process has more than one thread, [`KernelError::PermissionDenied`] if
+    /// it can't execute the file, or the error of the loader, in which case the old program is left untouched
+    pub fn exec_file(&self, path: &str) -> Result<(VirtAddr, VirtAddr), KernelError> {
+        if self.threads.lock().len() != 1 {
+            return Err(KernelError::Busy);
+        }
+
+        let mut address_space = AddressSpace::new()?;
+        let (entry, stack, mappings) = map_program(path, &mut address_space)?;
+
+        return Ok(self.replace_program(address_space, mappings, (entry, stack)));
+    }
+
+    /// Switches the only thread of this process to `address_space` and frees the old one, returning `program`
+    fn replace_program(
+        &self, address_space: AddressSpace, mappings: Mappings, program: (VirtAddr, VirtAddr)
+    ) -> (VirtAddr, VirtAddr) {
+        use x86_64::registers::control::{Cr3, Cr3Flags};
+        use x86_64::structures::paging::PhysFrame;
+
+        let cr3 = address_space.cr3();
+
+        // The old address space can only be freed once the thread doesn't run on it anymore
+        x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+            crate::sched::current_thread().as_mut().set_cr3(cr3);
+            self.cr3.store(cr3, Ordering::Relaxed);
+
+            let frame = PhysFrame::containing_address(x86_64::PhysAddr::new(cr3));
+            Cr3::write(frame, Cr3Flags::empty());
+        });
+
+        // The mappings are locked first, like everywhere else
+        let mut current_mappings = self.mappings();
+        *current_mappings = mappings;
+        *self.address_space() = address_space;
+
+        return program;
+    }
+
+    /// Called by the reaper once a thread of this process is gone, the last thread takes the process with it
+    pub(crate) fn remove_thread(&self, id: ThreadId) {
+        let mut threads = self.threads.lock();
+        threads.retain(|&thread| thread != id);
+
+        if threads.is_empty() {
+            PROCESSES.lock().remove(&self.id);
+        }
+    }
+}
+
+/// Resolves a page fault caused by writing to `address`, returning `false` if the running thread has no business
+/// writing there. Only copy-on-write pages of the current process can be resolved
+pub fn handle_write_fault(address: VirtAddr) -> bool {
+    match current() {
+        Some(process) => process.address_space().handle_cow_fault(address),
+        None => false
+    }
+}
+
+/// Resolves a page fault caused by touching `address` while it isn't mapped, returning `false` if the running thread
+/// has no business touching it. Only the pages of the file mappings of the current process can be resolved, they're
+/// read from their file (see [`mmap::handle_fault`]), so this may block
+pub fn handle_missing_page(address: VirtAddr, write: bool) -> bool {
+    match current() {
+        Some(process) => mmap::handle_fault(&process, address, write),
+        None => false
+    }
+}
+
+/// Loads the program in `image` into `address_space` and maps the stack of its main thread,
+/// returning the entry point and the top of the stack
+fn load_program(image: &[u8], address_space: &mut AddressSpace) -> Result<(VirtAddr, VirtAddr), KernelError> {
+    let entry = elf::load(image, address_space)?;
+
+    let stack_top = VirtAddr::new(USER_STACK_TOP);
+    address_space.map_user_pages(stack_top - USER_STACK_PAGES as u64 * 4096, USER_STACK_PAGES, true, false)?;
+
+    // The System V ABI expects the stack to be 16 bytes aligned at the entry of a program
+    return Ok((entry, stack_top));
+}
+
+/// Maps the program in the file at `path` into `address_space` (see [`elf::map`]) and maps the stack of its main
+/// thread, returning the entry point, the top of the stack and the mappings of the program. The current process (if
+/// any) must be able to execute the file
+fn map_program(path: &str, address_space: &mut AddressSpace) -> Result<(VirtAddr, VirtAddr, Mappings), KernelError> {
+    let (fs, node) = crate::fs::vfs::resolve_with_fs(path)?;
+    crate::fs::vfs::check_access(node.as_ref(), crate::fs::ACCESS_EXECUTE)?;
+
+    let (entry, mappings) = elf::map(&fs, &node)?;
+
+    let stack_top = VirtAddr::new(USER_STACK_TOP);
+    address_space.map_user_pages(stack_top - USER_STACK_PAGES as u64 * 4096, USER_STACK_PAGES, true, false)?;
+
+    return Ok((entry, stack_top, mappings));
+}
+
+/// Returns the process of the running thread, [`None`] for kernel threads
+#[allow(dead_code)]
+pub fn current() -> Option<Arc<Process>> {
+    // The current thread is alive while it's running and its process never changes
+    let thread = unsafe { crate::sched::current_thread().as_ref() };
+    return thread.process().cloned();
+}
+
+/// Makes the process with the given ID the one that gets the signals sent from the keyboard
+pub fn set_foreground(id: Option<ProcessId>) {
+    FOREGROUND.store(id.map_or(0, |id| id.0), Ordering::Relaxed);
+}
+
+/// Returns the process that gets the signals sent from the keyboard, if it's still alive
+pub fn foreground() -> Option<Arc<Process>> {
+    match FOREGROUND.load(Ordering::Relaxed) {
+        0 => None,
+        id => find(ProcessId(id))
+    }
+}
+
+/// Returns every process that still has threads
+pub fn all() -> Vec<Arc<Process>> {
+    PROCESSES.lock().values().cloned().collect()
+}
+
+/// Returns the process with the given ID if it still has threads
+#[allow(dead_code)]
+pub fn find(id: ProcessId) -> Option<Arc<Process>> {
+    PROCESSES.lock().get(&id).cloned()
+}
@@ -0,0 +1,57 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::VirtAddr;
+use crate::memory::address_space::SharedRegion;
+use crate::process::Process;
+use crate::utils::error::KernelError;
+use crate::utils::Mutex;
+
+/// Every shared memory region that can still be mapped, indexed by the ID given to the process that created it.
+/// The regions are owned by the processes that created them, so they can't be mapped anymore once their creator is gone
+static REGIONS: Mutex<BTreeMap<u64, Weak<SharedRegion>>> = Mutex::new(BTreeMap::new());
+
+/// Creates a shared memory region of at least `size` bytes owned by `process`, returning the ID other processes
+/// use to map it. The region can only be mapped as writable if `writable` is set
+pub fn create(process: &Process, size: u64, writable: bool) -> Result<u64, KernelError> {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+    let pages = size.checked_add(4095).ok_or(KernelError::InvalidArgument)? / 4096;
+    let region = Arc::new(SharedRegion::new(pages as usize, writable)?);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+    {
+        let mut regions = REGIONS.lock();
+
+        // Forget the regions whose creators are gone
+        regions.retain(|_, region| region.strong_count() > 0);
+        regions.insert(id, Arc::downgrade(&region));
+    }
+
+    process.shared_regions.lock().push(region);
+
+    return Ok(id);
+}
+
+/// Maps the whole region with the given ID into the address space of `process`, starting at `address`.
+/// The mapping keeps the memory alive even after the creator of the region is gone
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if there's no region with that ID, if it's mapped as writable without
+/// being writable, or if it doesn't fit at `address`
+pub fn map(process: &Process, id: u64, address: VirtAddr, writable: bool) -> Result<(), KernelError> {
+    let region = REGIONS.lock()
+        .get(&id)
+        .and_then(Weak::upgrade)
+        .ok_or(KernelError::InvalidArgument)?;
+
+    return process.address_space().map_shared(address, &region, writable);
+}
+
+/// Unmaps the pages of shared regions covering `size` bytes from `address` in the address space of `process`
+pub fn unmap(process: &Process, address: VirtAddr, size: u64) -> Result<(), KernelError> {
+    let pages = size.checked_add(4095).ok_or(KernelError::InvalidArgument)? / 4096;
+
+    return process.address_space().unmap_shared(address, pages as usize);
+}
@@ -0,0 +1,256 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::memory::address_space;
+use crate::process::Process;
+use crate::usermode::UserRegisters;
+use crate::utils::error::KernelError;
+use crate::utils::Mutex;
+
+/// A signal number, the numbers follow the ones used by Linux
+pub type Signal = u32;
+
+/// Sent by the keyboard when Ctrl+C is pressed
+pub const SIGINT: Signal = 2;
+/// Sent when the process runs an instruction that doesn't exist or that it isn't allowed to run
+pub const SIGILL: Signal = 4;
+/// Sent when the process is stepped one instruction at a time (the trap flag) without a debugger
+pub const SIGTRAP: Signal = 5;
+/// Sent when an arithmetic operation of the process fails, like a division by zero
+pub const SIGFPE: Signal = 8;
+/// Terminates the process, can't be handled
+pub const SIGKILL: Signal = 9;
+/// Sent when the process accesses memory it shouldn't
+pub const SIGSEGV: Signal = 11;
+/// Asks the process to terminate
+#[allow(dead_code)]
+pub const SIGTERM: Signal = 15;
+
+/// How many signals exist, every signal is a bit of [`SignalState::pending`]
+const SIGNAL_COUNT: usize = 32;
+
+/// How many bytes under the stack pointer of the user are left alone when a handler is invoked,
+/// the System V ABI lets leaf functions use them without moving the stack pointer
+const RED_ZONE_SIZE: u64 = 128;
+
+/// A handler registered by the process for a signal
+#[derive(Copy, Clone)]
+struct Handler {
+    /// Called with the signal as its only argument
+    entry: u64,
+    /// Where the handler returns to, it must make the `sigreturn` system call
+    trampoline: u64
+}
+
+/// The signals of a process: the ones waiting to be delivered and what to do with each of them
+pub struct SignalState {
+    /// A bit for each signal sent to the process and not delivered yet
+    pending: AtomicU32,
+    /// A bit for each signal with a handler, the others terminate the process (the default for all of them).
+    /// Kept apart from the handlers so it can be checked in interrupt handlers without taking a lock
+    handled: AtomicU32,
+    handlers: Mutex<[Option<Handler>; SIGNAL_COUNT]>
+}
+
+/// What the thread returning to user mode must do about the pending signals
+pub enum Action {
+    /// Exit, since the process was terminated by the given signal
+    Terminate(Signal),
+    /// Run the handler of the given signal
+    Handle(Signal)
+}
+
+impl SignalState {
+    pub const fn new() -> Self {
+        SignalState {
+            pending: AtomicU32::new(0),
+            handled: AtomicU32::new(0),
+            handlers: Mutex::new([None; SIGNAL_COUNT])
+        }
+    }
+
+    /// Marks `signal` as pending, it's delivered the next time a thread of the process returns to user mode
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if `signal` isn't a valid signal number
+    pub fn send(&self, signal: Signal) -> Result<(), KernelError> {
+        if signal == 0 || signal as usize >= SIGNAL_COUNT {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        self.pending.fetch_or(1 << signal, Ordering::AcqRel);
+
+        return Ok(());
+    }
+
+    /// Registers the handler that runs when `signal` is delivered, the handler returns to `trampoline`, which must make
+    /// the `sigreturn` system call. A `handler` of 0 restores the default action (terminating the process)
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if `signal` isn't a valid signal number or it's [`SIGKILL`], or if
+    /// `handler` or `trampoline` isn't a user address (see [`address_space::is_user_memory`])
+    pub fn set_handler(&self, signal: Signal, handler: u64, trampoline: u64) -> Result<(), KernelError> {
+        if signal == 0 || signal as usize >= SIGNAL_COUNT || signal == SIGKILL {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let is_user_address = |address| address_space::is_user_memory(address, 1);
+
+        if handler != 0 && !(is_user_address(handler) && is_user_address(trampoline)) {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let mut handlers = self.handlers.lock();
+
+        handlers[signal as usize] = match handler {
+            0 => None,
+            entry => Some(Handler { entry, trampoline })
+        };
+
+        match handler {
+            0 => self.handled.fetch_and(!(1 << signal), Ordering::AcqRel),
+            _ => self.handled.fetch_or(1 << signal, Ordering::AcqRel)
+        };
+
+        return Ok(());
+    }
+
+    /// Decides what to do with the pending signals, the lowest one goes first.
+    /// A signal that terminates the process stays pending, so every thread of the process sees it
+    pub fn next_action(&self) -> Option<Action> {
+        let pending = self.pending.load(Ordering::Acquire);
+
+        if pending == 0 {
+            return None;
+        }
+
+        if let Some(signal) = self.terminated_by() {
+            return Some(Action::Terminate(signal));
+        }
+
+        let signal = pending.trailing_zeros();
+
+        // Another thread may have taken the signal in the meantime, then there's nothing left to do for this one
+        let previous = self.pending.fetch_and(!(1 << signal), Ordering::AcqRel);
+
+        if previous & (1 << signal) == 0 {
+            return None;
+        }
+
+        return Some(Action::Handle(signal));
+    }
+
+    /// Returns the signal that terminated the process, if any. Never blocks, so it can be used by interrupt handlers
+    pub fn terminated_by(&self) -> Option<Signal> {
+        let terminating = self.pending.load(Ordering::Acquire) & !self.handled.load(Ordering::Acquire);
+
+        if terminating == 0 {
+            return None;
+        }
+
+        return Some(terminating.trailing_zeros());
+    }
+}
+
+/// Acts on the pending signals of the current process right before the current thread returns to user mode
+/// with `registers`: either the thread exits or `registers` are changed so it runs the handler of the signal,
+/// in which case `true` is returned. Called on the way out of every system call
+pub fn deliver(registers: &mut UserRegisters) -> bool {
+    let process = match crate::process::current() {
+        Some(process) => process,
+        None => return false
+    };
+
+    let signal = match process.signals.next_action() {
+        None => return false,
+        Some(Action::Terminate(signal)) => exit(process, signal),
+        Some(Action::Handle(signal)) => signal
+    };
+
+    let handler = process.signals.handlers.lock()[signal as usize];
+
+    // The handler may have been removed right after the signal was taken
+    let handler = match handler {
+        Some(handler) => handler,
+        None => exit(process, signal)
+    };
+
+    if push_signal_frame(&process, registers, signal, handler).is_err() {
+        // There's no way to run the handler, the stack of the thread is broken
+        exit(process, SIGSEGV);
+    }
+
+    return true;
+}
+
+/// Terminates the current thread if its process was terminated by a signal, used when interrupting user mode
+/// (like on a timer tick) so a program stuck in a loop without system calls can still be stopped.
+///
+/// ## Note
+///
+/// Handlers only run when returning from a system call, since an interrupt handler doesn't have the
+/// registers of the user at hand to save them
+pub fn check_terminated() {
+    if let Some(process) = crate::process::current() {
+        if let Some(signal) = process.signals.terminated_by() {
+            exit(process, signal);
+        }
+    }
+}
+
+/// Restores the registers saved when the handler of a signal was invoked, `stack` is the stack pointer of the user
+/// right after the handler returned to the trampoline, which points to the saved registers
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if the saved registers aren't in the memory of the process or the
+/// handler changed them so the thread can't resume them (see [`UserRegisters::is_resumable`])
+pub fn restore(stack: u64) -> Result<UserRegisters, KernelError> {
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+    let mut registers = UserRegisters::default();
+
+    process.copy_from_user(stack, as_bytes_mut(&mut registers))?;
+
+    if !registers.is_resumable() {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    return Ok(registers);
+}
+
+/// Saves `registers` on the user stack and changes them so the thread calls the handler,
+/// which returns to the trampoline with the stack pointer pointing to the saved registers
+fn push_signal_frame(process: &Process, registers: &mut UserRegisters, signal: Signal, handler: Handler) -> Result<(), KernelError> {
+    let size = core::mem::size_of::<UserRegisters>() as u64;
+
+    let saved = registers.rsp.checked_sub(RED_ZONE_SIZE + size).ok_or(KernelError::InvalidArgument)? & !0xF;
+    let return_address = saved.checked_sub(8).ok_or(KernelError::InvalidArgument)?;
+
+    // The stack is written through the page tables of the process, like any buffer given by a program
+    let mut saved_registers = registers.clone();
+    process.copy_to_user(saved, as_bytes_mut(&mut saved_registers))?;
+    process.copy_to_user(return_address, &handler.trampoline.to_le_bytes())?;
+
+    // Like right after a call: the stack is 16 bytes aligned plus the return address
+    registers.rip = handler.entry;
+    registers.rsp = return_address;
+    registers.rdi = signal as u64;
+
+    return Ok(());
+}
+
+/// The bytes of `registers`, as they're saved on the user stack
+fn as_bytes_mut(registers: &mut UserRegisters) -> &mut [u8] {
+    let size = core::mem::size_of::<UserRegisters>();
+
+    // The registers are plain 64-bit numbers, any bytes are a valid value
+    return unsafe { core::slice::from_raw_parts_mut(registers as *mut UserRegisters as *mut u8, size) };
+}
+
+/// Exits the current thread as terminated by `signal`, with the exit code used by shells for it
+fn exit(process: alloc::sync::Arc<Process>, signal: Signal) -> ! {
+    // The process is kept alive by its threads, the reference can't stay on a stack that's abandoned
+    drop(process);
+
+    crate::task::exit(128 + signal as i32);
+}
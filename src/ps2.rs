@@ -0,0 +1,278 @@
+use x86_64::instructions::port::Port;
+use crate::time;
+use crate::utils::error::KernelError;
+use crate::utils::Mutex;
+
+/// The port the bytes of the devices (and the answers of the controller) are read from, and the bytes for them written to
+const DATA_PORT: u16 = 0x60;
+
+/// Reads as the status of the controller and writes as a command for it
+const STATUS_PORT: u16 = 0x64;
+const COMMAND_PORT: u16 = 0x64;
+
+/// The bits of the status: there's a byte to read, the controller is still busy with the last byte written
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+const COMMAND_READ_CONFIG: u8 = 0x20;
+const COMMAND_WRITE_CONFIG: u8 = 0x60;
+const COMMAND_DISABLE_SECOND: u8 = 0xA7;
+const COMMAND_ENABLE_SECOND: u8 = 0xA8;
+const COMMAND_TEST_SECOND: u8 = 0xA9;
+const COMMAND_SELF_TEST: u8 = 0xAA;
+const COMMAND_TEST_FIRST: u8 = 0xAB;
+const COMMAND_DISABLE_FIRST: u8 = 0xAD;
+const COMMAND_ENABLE_FIRST: u8 = 0xAE;
+/// Sends the next byte written to the data port to the device of the second port
+const COMMAND_WRITE_SECOND: u8 = 0xD4;
+
+/// The bits of the configuration byte: the interrupts of both ports, the clock of the second port (set to
+/// disable it) and the translation of the scancodes of the first port to set 1
+const CONFIG_FIRST_INTERRUPT: u8 = 1 << 0;
+const CONFIG_SECOND_INTERRUPT: u8 = 1 << 1;
+const CONFIG_SECOND_CLOCK_DISABLED: u8 = 1 << 5;
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+/// What the controller answers to its self-test and to the tests of its ports when everything works
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+const DEVICE_RESET: u8 = 0xFF;
+const DEVICE_IDENTIFY: u8 = 0xF2;
+const DEVICE_ENABLE_SCANNING: u8 = 0xF4;
+const DEVICE_DISABLE_SCANNING: u8 = 0xF5;
+
+/// What the devices answer to a command they accepted, and to a reset that worked
+const DEVICE_ACK: u8 = 0xFA;
+const DEVICE_RESET_PASSED: u8 = 0xAA;
+
+/// How long the controller or a device can take to answer, the reset of a device is the slowest
+const TIMEOUT_MS: u64 = 100;
+const RESET_TIMEOUT_MS: u64 = 1000;
+
+/// The devices found on the two ports by [`init`]
+static DEVICES: Mutex<[Option<DeviceKind>; 2]> = Mutex::new([None, None]);
+
+/// The kind of device connected to a port, as told by its answer to the identify command
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeviceKind {
+    /// An old AT keyboard, which doesn't answer to identify
+    AtKeyboard,
+    Mf2Keyboard,
+    Mouse,
+    ScrollMouse,
+    FiveButtonMouse,
+    /// A device that answered with bytes the kernel doesn't know
+    Unknown
+}
+
+impl DeviceKind {
+    fn from_identity(identity: &[u8]) -> Self {
+        match identity {
+            [] => DeviceKind::AtKeyboard,
+            [0x00] => DeviceKind::Mouse,
+            [0x03] => DeviceKind::ScrollMouse,
+            [0x04] => DeviceKind::FiveButtonMouse,
+            [0xAB, 0x41 | 0xC1 | 0x83] => DeviceKind::Mf2Keyboard,
+            _ => DeviceKind::Unknown
+        }
+    }
+
+    pub fn is_keyboard(&self) -> bool {
+        matches!(self, DeviceKind::AtKeyboard | DeviceKind::Mf2Keyboard)
+    }
+}
+
+fn status() -> u8 {
+    unsafe { Port::<u8>::new(STATUS_PORT).read() }
+}
+
+/// Reads the byte waiting in the output buffer of the controller, without checking there's one. This is what the
+/// interrupt handler of the keyboard uses, the interrupt itself tells there's a byte
+pub fn read_data() -> u8 {
+    unsafe { Port::<u8>::new(DATA_PORT).read() }
+}
+
+/// Returns the byte waiting in the output buffer, if there's one. Meant for the code running with interrupts
+/// disabled (like the panic handler), where the interrupt handler of the keyboard can't take it
+pub fn poll_data() -> Option<u8> {
+    (status() & STATUS_OUTPUT_FULL != 0).then(read_data)
+}
+
+/// Waits for a byte from the controller or a device
+fn read(timeout_ms: u64) -> Result<u8, KernelError> {
+    let deadline = time::ticks() + time::ms_to_ticks(timeout_ms).max(1);
+
+    while status() & STATUS_OUTPUT_FULL == 0 {
+        if time::ticks() > deadline {
+            return Err(KernelError::Timeout);
+        }
+
+        core::hint::spin_loop();
+    }
+
+    Ok(read_data())
+}
+
+/// Waits until the controller takes the last byte written, then writes `value` to `port`
+fn write(port: u16, value: u8) -> Result<(), KernelError> {
+    let deadline = time::ticks() + time::ms_to_ticks(TIMEOUT_MS).max(1);
+
+    while status() & STATUS_INPUT_FULL != 0 {
+        if time::ticks() > deadline {
+            return Err(KernelError::Timeout);
+        }
+
+        core::hint::spin_loop();
+    }
+
+    unsafe { Port::<u8>::new(port).write(value) };
+    Ok(())
+}
+
+fn command(command: u8) -> Result<(), KernelError> {
+    write(COMMAND_PORT, command)
+}
+
+/// Sends a command to the controller and returns its answer
+fn query(command: u8) -> Result<u8, KernelError> {
+    self::command(command)?;
+    read(TIMEOUT_MS)
+}
+
+fn write_config(config: u8) -> Result<(), KernelError> {
+    command(COMMAND_WRITE_CONFIG)?;
+    write(DATA_PORT, config)
+}
+
+/// Throws away whatever bytes are waiting in the output buffer
+fn flush_output() {
+    // The buffer is a single byte, the limit only protects against a controller that always reports data
+    for _ in 0..16 {
+        if status() & STATUS_OUTPUT_FULL == 0 {
+            break;
+        }
+
+        read_data();
+    }
+}
+
+/// Sends a byte to the device of the given port (0 or 1) and waits for its acknowledgement
+fn send_to_device(port: usize, byte: u8) -> Result<(), KernelError> {
+    if port == 1 {
+        command(COMMAND_WRITE_SECOND)?;
+    }
+
+    write(DATA_PORT, byte)?;
+
+    match read(TIMEOUT_MS)? {
+        DEVICE_ACK => Ok(()),
+        _ => Err(KernelError::Io)
+    }
+}
+
+/// Resets the device of the given port and finds out what it is, leaving it with its scanning disabled
+fn detect_device(port: usize) -> Result<DeviceKind, KernelError> {
+    send_to_device(port, DEVICE_RESET)?;
+
+    if read(RESET_TIMEOUT_MS)? != DEVICE_RESET_PASSED {
+        return Err(KernelError::Io);
+    }
+
+    // A mouse also sends its ID after the reset
+    let _ = read(TIMEOUT_MS);
+
+    send_to_device(port, DEVICE_DISABLE_SCANNING)?;
+    send_to_device(port, DEVICE_IDENTIFY)?;
+
+    // The identity has up to two bytes, the timeout is how the end of a shorter one is found
+    let mut identity = [0u8; 2];
+    let mut length = 0;
+
+    while length < identity.len() {
+        match read(TIMEOUT_MS) {
+            Ok(byte) => identity[length] = byte,
+            Err(_) => break
+        }
+
+        length += 1;
+    }
+
+    Ok(DeviceKind::from_identity(&identity[..length]))
+}
+
+/// Initializes the 8042 PS/2 controller and finds the devices on its ports: both ports are disabled, the controller
+/// tests itself and its ports and then the working ports are enabled again, with every device reset. The scancodes
+/// of the first port are translated to set 1 (which is what [`crate::task::keyboard`] decodes) and only its interrupt
+/// is enabled. Must be called once, after the timer is running and the interrupts are initialized
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NoDevice`] if the controller fails its self-test or doesn't answer (many recent
+/// machines have none), the ports and their devices failing don't make this fail
+pub fn init() -> Result<(), KernelError> {
+    // The devices mustn't send anything while the controller is set up
+    command(COMMAND_DISABLE_FIRST).map_err(|_| KernelError::NoDevice)?;
+    command(COMMAND_DISABLE_SECOND)?;
+    flush_output();
+
+    let mut config = query(COMMAND_READ_CONFIG)?;
+    config &= !(CONFIG_FIRST_INTERRUPT | CONFIG_SECOND_INTERRUPT | CONFIG_TRANSLATION);
+    write_config(config)?;
+
+    if query(COMMAND_SELF_TEST)? != SELF_TEST_PASSED {
+        return Err(KernelError::NoDevice);
+    }
+
+    // The self-test may reset the controller, configuration included
+    write_config(config)?;
+
+    // A controller with a single port ignores the command that enables the second one, leaving its clock disabled
+    let mut dual_port = false;
+
+    if config & CONFIG_SECOND_CLOCK_DISABLED != 0 {
+        command(COMMAND_ENABLE_SECOND)?;
+        dual_port = query(COMMAND_READ_CONFIG)? & CONFIG_SECOND_CLOCK_DISABLED == 0;
+        command(COMMAND_DISABLE_SECOND)?;
+    }
+
+    let first_works = query(COMMAND_TEST_FIRST)? == PORT_TEST_PASSED;
+    let second_works = dual_port && query(COMMAND_TEST_SECOND)? == PORT_TEST_PASSED;
+
+    if first_works {
+        command(COMMAND_ENABLE_FIRST)?;
+    }
+
+    if second_works {
+        command(COMMAND_ENABLE_SECOND)?;
+    }
+
+    let mut devices = [None, None];
+
+    for (port, works) in [first_works, second_works].into_iter().enumerate() {
+        if works {
+            devices[port] = detect_device(port).ok();
+        }
+    }
+
+    // The keyboard starts sending scancodes again, a mouse stays quiet since nothing handles its packets yet
+    if devices[0].map_or(false, |device| device.is_keyboard()) {
+        send_to_device(0, DEVICE_ENABLE_SCANNING)?;
+    }
+
+    flush_output();
+
+    if first_works {
+        config |= CONFIG_FIRST_INTERRUPT | CONFIG_TRANSLATION;
+    }
+
+    write_config(config)?;
+
+    *DEVICES.lock() = devices;
+    Ok(())
+}
+
+/// Returns the devices found on the first and the second port by [`init`]
+pub fn devices() -> [Option<DeviceKind>; 2] {
+    *DEVICES.lock()
+}
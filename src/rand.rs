@@ -0,0 +1,254 @@
+use core::arch::asm;
+use core::arch::x86_64::{__cpuid, __cpuid_count, _rdtsc};
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::time;
+use crate::utils::IrqCell;
+
+/// How many times RDRAND and RDSEED are retried when they run out of entropy, the number Intel suggests
+const RETRIES: usize = 10;
+
+/// The constant words of a ChaCha block, "expand 32-byte k"
+const CHACHA_CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646E, 0x7962_2D32, 0x6B20_6574];
+
+/// How many rounds the ChaCha used here has, it's ChaCha20
+const CHACHA_ROUNDS: usize = 20;
+
+/// How many times the TSC is sampled around the timer to seed the generator when there's no RDSEED
+const JITTER_SAMPLES: usize = 256;
+
+/// Set by [`init`] when the CPU has the RDRAND instruction
+static HAS_RDRAND: AtomicBool = AtomicBool::new(false);
+
+/// The generator used when there's no RDRAND, or when it keeps failing
+static GENERATOR: IrqCell<ChaCha> = IrqCell::new(ChaCha::new());
+
+/// A ChaCha20 generator that replaces its key with the start of every block it produces, so the earlier
+/// output can't be recovered from its state ("fast key erasure")
+struct ChaCha {
+    key: [u32; 8],
+    counter: u64,
+    /// What's left of the last block, only `available` bytes from the end are unused
+    block: [u8; 32],
+    available: usize
+}
+
+impl ChaCha {
+    const fn new() -> Self {
+        ChaCha {
+            key: [0; 8],
+            counter: 0,
+            block: [0; 32],
+            available: 0
+        }
+    }
+
+    /// Mixes `seed` into the key, the earlier key isn't lost so a weak seed never makes things worse
+    fn reseed(&mut self, seed: &[u32; 8]) {
+        for (word, seed) in self.key.iter_mut().zip(seed) {
+            *word ^= seed;
+        }
+
+        self.refill();
+    }
+
+    fn refill(&mut self) {
+        let block = chacha_block(&self.key, self.counter);
+        self.counter = self.counter.wrapping_add(1);
+
+        self.key.copy_from_slice(&block[..8]);
+
+        for (bytes, word) in self.block.chunks_exact_mut(4).zip(&block[8..]) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+
+        self.available = self.block.len();
+    }
+
+    fn fill(&mut self, buffer: &mut [u8]) {
+        for byte in buffer {
+            if self.available == 0 {
+                self.refill();
+            }
+
+            *byte = self.block[self.block.len() - self.available];
+
+            // The bytes handed out are wiped so the state never holds them
+            self.block[self.block.len() - self.available] = 0;
+            self.available -= 1;
+        }
+    }
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// Computes a ChaCha20 block for the given key and counter, with a nonce of 0
+fn chacha_block(key: &[u32; 8], counter: u64) -> [u32; 16] {
+    let mut initial = [0u32; 16];
+
+    initial[..4].copy_from_slice(&CHACHA_CONSTANTS);
+    initial[4..12].copy_from_slice(key);
+    initial[12] = counter as u32;
+    initial[13] = (counter >> 32) as u32;
+
+    let mut state = initial;
+
+    for _ in 0..CHACHA_ROUNDS / 2 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    for (word, initial) in state.iter_mut().zip(initial) {
+        *word = word.wrapping_add(initial);
+    }
+
+    return state;
+}
+
+/// Runs RDRAND, returning `None` if the CPU ran out of random numbers every time
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that the CPU has RDRAND
+unsafe fn rdrand() -> Option<u64> {
+    for _ in 0..RETRIES {
+        let value: u64;
+        let success: u8;
+
+        asm!("rdrand {}", "setc {}", out(reg) value, out(reg_byte) success, options(nomem, nostack));
+
+        if success != 0 {
+            return Some(value);
+        }
+    }
+
+    return None;
+}
+
+/// Runs RDSEED, which gives entropy straight from the hardware source (RDRAND goes through a generator of its own)
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that the CPU has RDSEED
+unsafe fn rdseed() -> Option<u64> {
+    for _ in 0..RETRIES {
+        let value: u64;
+        let success: u8;
+
+        asm!("rdseed {}", "setc {}", out(reg) value, out(reg_byte) success, options(nomem, nostack));
+
+        if success != 0 {
+            return Some(value);
+        }
+
+        core::hint::spin_loop();
+    }
+
+    return None;
+}
+
+/// Gathers a seed from the jitter between the TSC and the timer: how many cycles each stretch of work takes
+/// changes with caches, interrupts and the two clocks drifting apart. Weak on its own, but it's all there is
+/// on the CPUs without RDSEED and RDRAND
+fn jitter_seed() -> [u32; 8] {
+    let mut seed = [0u32; 8];
+
+    for sample in 0..JITTER_SAMPLES {
+        let start = unsafe { _rdtsc() };
+        let ticks = time::ticks();
+
+        // Some work whose length varies, so consecutive samples don't just differ by a constant
+        for _ in 0..(start & 0xFF) {
+            core::hint::spin_loop();
+        }
+
+        let elapsed = unsafe { _rdtsc() }.wrapping_sub(start);
+        let value = elapsed ^ ticks.rotate_left(32) ^ start;
+
+        seed[sample % 8] = seed[sample % 8].rotate_left(5) ^ value as u32 ^ (value >> 32) as u32;
+    }
+
+    return seed;
+}
+
+/// Finds out whatever the CPU has RDRAND and RDSEED and seeds the fallback generator, from RDSEED
+/// (or RDRAND) when there's one and from the jitter of the TSC otherwise. Must be called once, after the timer is running
+pub fn init() {
+    // RDRAND is bit 30 of ECX of leaf 1 and RDSEED bit 18 of EBX of leaf 7, CPUID is only unsafe on older compilers
+    #[allow(unused_unsafe)]
+    let has_rdrand = unsafe { __cpuid(1).ecx } & (1 << 30) != 0;
+    #[allow(unused_unsafe)]
+    let has_rdseed = unsafe { __cpuid(0).eax >= 7 && __cpuid_count(7, 0).ebx & (1 << 18) != 0 };
+
+    let mut seed = jitter_seed();
+
+    for pair in seed.chunks_exact_mut(2) {
+        let value = unsafe {
+            match (has_rdseed, has_rdrand) {
+                (true, _) => rdseed(),
+                (false, true) => rdrand(),
+                _ => None
+            }
+        };
+
+        if let Some(value) = value {
+            pair[0] ^= value as u32;
+            pair[1] ^= (value >> 32) as u32;
+        }
+    }
+
+    GENERATOR.with(|generator| generator.reseed(&seed));
+    HAS_RDRAND.store(has_rdrand, Ordering::Release);
+}
+
+/// Fills `buffer` with random bytes, good enough for ASLR, network sequence numbers and the like.
+/// They come from RDRAND when the CPU has it and from a ChaCha20 generator seeded by [`init`] otherwise
+///
+/// ## Note
+///
+/// This function doesn't allocate or block, so it can be used inside an interrupt handler
+pub fn fill(buffer: &mut [u8]) {
+    let mut chunks = buffer.chunks_mut(8);
+
+    if HAS_RDRAND.load(Ordering::Acquire) {
+        for chunk in chunks.by_ref() {
+            match unsafe { rdrand() } {
+                Some(value) => chunk.copy_from_slice(&value.to_le_bytes()[..chunk.len()]),
+                None => {
+                    // The hardware is out of random numbers for now, the generator takes over for the rest
+                    GENERATOR.with(|generator| generator.fill(chunk));
+                    break;
+                }
+            }
+        }
+    }
+
+    GENERATOR.with(|generator| {
+        for chunk in chunks {
+            generator.fill(chunk);
+        }
+    });
+}
+
+/// Returns a random `u64`, see [`fill`]
+#[allow(dead_code)]
+pub fn next_u64() -> u64 {
+    let mut bytes = [0u8; 8];
+    fill(&mut bytes);
+
+    return u64::from_le_bytes(bytes);
+}
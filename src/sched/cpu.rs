@@ -0,0 +1,95 @@
+use core::fmt;
+
+/// How many CPUs the scheduler can handle, every CPU has a bit in a [`CpuSet`]
+pub const MAX_CPUS: usize = 64;
+
+/// Identifies a CPU, which is also the index of its run queue in the scheduler
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CpuId(u8);
+
+impl CpuId {
+    /// The CPU the bootloader hands the control to, the only one running until the application processors are started
+    pub const BOOT: CpuId = CpuId(0);
+
+    /// Returns the CPU with the given index, or `None` if it's not below [`MAX_CPUS`]
+    #[allow(dead_code)]
+    pub fn new(index: usize) -> Option<Self> {
+        if index >= MAX_CPUS {
+            return None;
+        }
+
+        Some(CpuId(index as u8))
+    }
+
+    pub fn as_usize(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A set of CPUs, used as the affinity mask of a thread (the CPUs it's allowed to run on)
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct CpuSet(u64);
+
+#[allow(dead_code)]
+impl CpuSet {
+    pub const fn empty() -> Self {
+        CpuSet(0)
+    }
+
+    /// Returns the set with every CPU, including the ones that aren't online (yet)
+    pub const fn all() -> Self {
+        CpuSet(u64::MAX)
+    }
+
+    /// Returns the set with only `cpu`, used to pin a thread to a CPU
+    pub const fn single(cpu: CpuId) -> Self {
+        CpuSet(1 << cpu.0)
+    }
+
+    pub fn insert(&mut self, cpu: CpuId) {
+        self.0 |= 1 << cpu.0;
+    }
+
+    pub fn remove(&mut self, cpu: CpuId) {
+        self.0 &= !(1 << cpu.0);
+    }
+
+    pub fn contains(&self, cpu: CpuId) -> bool {
+        self.0 & (1 << cpu.0) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    /// Returns the CPUs that are in both sets
+    pub fn intersection(&self, other: CpuSet) -> CpuSet {
+        CpuSet(self.0 & other.0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = CpuId> {
+        let bits = self.0;
+
+        (0..MAX_CPUS as u8)
+            .filter(move |cpu| bits & (1 << cpu) != 0)
+            .map(CpuId)
+    }
+}
+
+impl fmt::Debug for CpuSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter().map(|cpu| cpu.0)).finish()
+    }
+}
+
+/// Returns the CPU running the caller.
+///
+/// There's no per-CPU data yet, so this is always the boot processor. Once the application processors are started
+/// this has to read the index of the CPU from its per-CPU data instead (through the GS base)
+pub fn current_cpu() -> CpuId {
+    CpuId::BOOT
+}
@@ -0,0 +1,65 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+use crate::utils::error::KernelError;
+use crate::utils::queue::MpscQueue;
+
+/// How many pieces of deferred work can be waiting at the same time
+const DEFERRED_QUEUE_SIZE: usize = 64;
+
+/// Work deferred by interrupt handlers, run by the idle thread
+static DEFERRED: MpscQueue<fn(), DEFERRED_QUEUE_SIZE> = MpscQueue::new();
+
+/// How many timer ticks were spent in the idle thread since boot
+static IDLE_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Queues `work` to be run by the idle thread, this never blocks nor allocates so it can be called from
+/// interrupt handlers to push work that doesn't have to happen right away out of the interrupt.
+///
+/// Since the idle thread has the lowest priority the work only runs when the CPU has nothing better to do
+///
+/// ## Errors
+///
+/// Returns [`KernelError::Busy`] if too much work is already waiting
+#[allow(dead_code)]
+pub fn defer(work: fn()) -> Result<(), KernelError> {
+    DEFERRED.push(work).map_err(|_| KernelError::Busy)
+}
+
+/// Returns how many timer ticks the CPU spent idle since boot
+#[allow(dead_code)]
+pub fn idle_ticks() -> u64 {
+    IDLE_TICKS.load(Ordering::Relaxed)
+}
+
+/// Called on every timer tick that interrupted the idle thread
+pub(super) fn account_tick() {
+    IDLE_TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The entry of the idle thread, it runs whenever no other thread is ready
+pub(super) fn idle_loop() {
+    loop {
+        while let Some(work) = DEFERRED.pop() {
+            work();
+        }
+
+        // Interrupts are disabled while checking the queues so work deferred (or a thread woken) right after the check
+        // isn't left waiting until the next interrupt, the `hlt` wakes up as soon as any interrupt arrives anyway
+        interrupts::disable();
+
+        if DEFERRED.is_empty() && !super::has_ready() {
+            // The ticks slept through are counted by the clock once the CPU wakes up, but not as idle ticks
+            crate::time::lapic::stop_tick();
+            interrupts::enable_and_hlt();
+            interrupts::without_interrupts(crate::time::lapic::restart_tick);
+        } else {
+            interrupts::enable();
+        }
+
+        // The threads woken by the interrupt (like the executor by the keyboard) are only queued, and the tick that
+        // would preempt the idle thread may be far away once it was stopped
+        if super::has_ready() {
+            super::yield_now();
+        }
+    }
+}
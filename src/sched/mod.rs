@@ -0,0 +1,526 @@
+mod idle;
+mod reaper;
+mod wait_queue;
+mod stats;
+mod preempt;
+mod cpu;
+mod run_queue;
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ptr::NonNull;
+use lazy_static::lazy_static;
+use crate::task::context::switch_to;
+use crate::task::thread::{Thread, ThreadState};
+use crate::utils::error::KernelError;
+use crate::utils::IrqCell;
+use crate::utils::list::List;
+use self::run_queue::RunQueue;
+
+/// For how many timer ticks a thread runs before being preempted
+const TIME_SLICE_TICKS: u32 = 5;
+
+/// How often (in timer ticks) every CPU checks whatever another CPU has too much work, see [`Scheduler::balance`]
+const BALANCE_INTERVAL_TICKS: u32 = 20;
+
+/// How many priority classes exist, see [`Priority`]
+const PRIORITY_COUNT: usize = 3;
+
+lazy_static! {
+    static ref SCHEDULER: IrqCell<Scheduler> = IrqCell::new(Scheduler::new());
+}
+
+/// The scheduling class of a thread, a thread only runs when there's no ready thread in a higher class
+/// and becoming ready in a higher class preempts the running thread on the next timer tick
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Priority {
+    /// Only runs when there's nothing else to do
+    Idle = 0,
+    /// Regular kernel work
+    Normal = 1,
+    /// Latency-sensitive work, like echoing the input of the user
+    Realtime = 2
+}
+
+/// A round-robin scheduler with priority classes, every thread runs for [`TIME_SLICE_TICKS`] ticks (or until it yields)
+/// and then goes to the end of the ready queue of its class, giving its place to the first thread of the highest class
+/// that has a ready thread.
+///
+/// Every CPU has its own [`RunQueue`] and a thread stays on the same CPU while it can. A CPU that runs out of work
+/// steals a thread from another one, and every [`BALANCE_INTERVAL_TICKS`] the CPUs with less work pull threads from
+/// the busiest one. A thread only ever goes to the CPUs in its affinity mask (see [`set_current_affinity`]).
+///
+/// The threads are kept in intrusive lists so the scheduler never allocates, which matters since it
+/// runs with interrupts disabled and a preempted thread may be holding the allocator lock
+pub struct Scheduler {
+    /// The run queue of every online CPU, indexed by [`CpuId`]
+    cpus: Vec<RunQueue>,
+    /// The threads that exited and still have to be freed by the reaper
+    zombies: List<Thread>,
+    /// The thread that frees the exited threads, set once it starts running
+    reaper: Option<NonNull<Thread>>
+}
+
+// The threads are only ever touched with the scheduler held
+unsafe impl Send for Scheduler {}
+
+/// What [`Scheduler::schedule`] decided the CPU should do next
+enum Decision {
+    /// The current thread keeps running
+    Keep,
+    /// Switch from the first thread to the second one, which is already the current thread of the CPU
+    Switch(NonNull<Thread>, NonNull<Thread>)
+}
+
+impl Scheduler {
+    fn new() -> Self {
+        let bootstrap = NonNull::from(Box::leak(Box::new(Thread::bootstrap())));
+
+        Scheduler {
+            cpus: vec![RunQueue::new(CpuId::BOOT, bootstrap)],
+            zombies: List::new(),
+            reaper: None
+        }
+    }
+
+    fn queue(&mut self, cpu: CpuId) -> &mut RunQueue {
+        &mut self.cpus[cpu.as_usize()]
+    }
+
+    fn current(&self, cpu: CpuId) -> NonNull<Thread> {
+        self.cpus[cpu.as_usize()].current
+    }
+
+    fn online(&self) -> CpuSet {
+        let mut online = CpuSet::empty();
+
+        for index in 0..self.cpus.len() {
+            online.insert(CpuId::new(index).unwrap());
+        }
+
+        return online;
+    }
+
+    /// Accounts a timer tick to the current thread of `cpu`, returning whatever it should be preempted, either because
+    /// its time slice expired or because a thread with a higher priority became ready
+    fn tick(&mut self, cpu: CpuId) -> bool {
+        let queue = self.queue(cpu);
+
+        unsafe { queue.current.as_ref().stats().account_tick() };
+
+        queue.balance_remaining = queue.balance_remaining.saturating_sub(1);
+
+        if queue.balance_remaining == 0 {
+            queue.balance_remaining = BALANCE_INTERVAL_TICKS;
+            self.balance(cpu);
+        }
+
+        let queue = self.queue(cpu);
+
+        // A blocked (or exited) thread is already on its way out through `schedule`
+        if queue.current_state() != ThreadState::Ready {
+            return false;
+        }
+
+        if queue.is_idle() {
+            idle::account_tick();
+        }
+
+        queue.slice_remaining = queue.slice_remaining.saturating_sub(1);
+
+        let preempted = queue.highest_ready().map_or(false, |priority| priority > queue.current_priority());
+        return queue.slice_remaining == 0 || preempted;
+    }
+
+    /// Picks the next thread to run on `cpu`, putting the current one at the end of its ready queue.
+    ///
+    /// The current thread keeps running if all the ready threads have a lower priority, unless it's blocked
+    /// or exited. When the CPU would go idle it first tries to steal a thread from another CPU. There's always
+    /// a thread to switch to, since the idle thread never blocks
+    fn schedule(&mut self, cpu: CpuId) -> Decision {
+        let queue = self.queue(cpu);
+        queue.slice_remaining = TIME_SLICE_TICKS;
+
+        let state = queue.current_state();
+        let current_priority = queue.current_priority();
+        let previous = queue.current;
+
+        let running = state == ThreadState::Ready;
+        let nothing_to_do = queue.highest_ready().map_or(true, |priority| priority == Priority::Idle);
+
+        if nothing_to_do && (!running || current_priority == Priority::Idle) {
+            if let Some(thread) = self.steal(cpu) {
+                unsafe { self.queue(cpu).push(thread) };
+            }
+        }
+
+        if state == ThreadState::Exited {
+            unsafe { self.zombies.push_back(previous) };
+
+            if let Some(reaper) = self.reaper {
+                self.wake(reaper);
+            }
+        }
+
+        let queue = self.queue(cpu);
+
+        let next_priority = match queue.highest_ready() {
+            Some(priority) if !running || priority >= current_priority => priority,
+            None if !running => panic!("The current thread stopped but there's no thread to run, not even the idle one"),
+            _ => return Decision::Keep
+        };
+
+        let next = queue.pop(next_priority).unwrap();
+        queue.current = next;
+
+        // A blocked thread goes back to a ready queue only when someone wakes it up
+        if running {
+            unsafe {
+                previous.as_ref().stats().account_ready();
+
+                // The thread may not be allowed on this CPU anymore, see `set_current_affinity`
+                let target = self.place(previous);
+                self.queue(target).push(previous);
+            }
+        }
+
+        unsafe { next.as_ref().stats().account_switch() };
+
+        return Decision::Switch(previous, next);
+    }
+
+    /// Picks the CPU a thread that becomes ready should wait on: the one it last ran on if it's still allowed
+    /// there (its data may still be in the cache), otherwise the allowed CPU with the least work
+    fn place(&self, thread: NonNull<Thread>) -> CpuId {
+        let thread = unsafe { thread.as_ref() };
+
+        if thread.affinity.contains(thread.cpu) && self.online().contains(thread.cpu) {
+            return thread.cpu;
+        }
+
+        return self.least_loaded(thread.affinity);
+    }
+
+    /// Returns the online CPU in `affinity` with the least work
+    ///
+    /// ## Panics
+    ///
+    /// Panics if none of the CPUs in `affinity` is online, which [`set_current_affinity`] never allows
+    fn least_loaded(&self, affinity: CpuSet) -> CpuId {
+        return affinity
+            .intersection(self.online())
+            .iter()
+            .min_by_key(|cpu| self.cpus[cpu.as_usize()].load())
+            .expect("A thread isn't allowed on any online CPU");
+    }
+
+    /// Takes a ready thread allowed on `cpu` from the CPU with the most work, if any
+    fn steal(&mut self, cpu: CpuId) -> Option<NonNull<Thread>> {
+        let busiest = self.online()
+            .iter()
+            .filter(|&other| other != cpu)
+            .max_by_key(|other| self.cpus[other.as_usize()].load())?;
+
+        return self.queue(busiest).take_for(cpu);
+    }
+
+    /// Evens out the work between `cpu` and the busiest CPU, moving a thread from the busiest one when it has
+    /// at least two more threads to run than `cpu`. Every CPU does this from its own timer, so the work
+    /// spreads out over a few passes
+    fn balance(&mut self, cpu: CpuId) {
+        let load = self.cpus[cpu.as_usize()].load();
+
+        let busiest = self.online()
+            .iter()
+            .filter(|&other| other != cpu)
+            .max_by_key(|other| self.cpus[other.as_usize()].load());
+
+        let busiest = match busiest {
+            Some(busiest) if self.cpus[busiest.as_usize()].load() > load + 1 => busiest,
+            _ => return
+        };
+
+        if let Some(thread) = self.queue(busiest).take_for(cpu) {
+            unsafe { self.queue(cpu).push(thread) };
+        }
+    }
+
+    fn boost(&mut self, thread: NonNull<Thread>, priority: Priority) {
+        unsafe {
+            let thread_ref = &mut *thread.as_ptr();
+
+            if thread_ref.priority >= priority {
+                return;
+            }
+
+            // Only a ready thread that isn't running is in a ready queue, and it has to move to the queue of its new class
+            let queued = thread_ref.state == ThreadState::Ready && thread != self.current(thread_ref.cpu);
+            let cpu = thread_ref.cpu;
+
+            if queued {
+                self.queue(cpu).remove(thread, thread_ref.priority);
+            }
+
+            thread_ref.priority = priority;
+
+            if queued {
+                self.queue(cpu).push(thread);
+            }
+        }
+    }
+
+    fn wake(&mut self, thread: NonNull<Thread>) {
+        unsafe {
+            let thread_ref = &mut *thread.as_ptr();
+
+            if thread_ref.state != ThreadState::Blocked {
+                return;
+            }
+
+            thread_ref.state = ThreadState::Ready;
+
+            // The thread may be woken up before it even left the CPU, then it just keeps running
+            if thread != self.current(thread_ref.cpu) {
+                thread_ref.stats().account_ready();
+
+                let cpu = self.place(thread);
+                self.queue(cpu).push(thread);
+            }
+        }
+    }
+}
+
+#[allow(unused_imports)]
+pub use idle::{defer, idle_ticks};
+pub use wait_queue::WaitQueue;
+#[allow(unused_imports)]
+pub use stats::{context_switches, ps, stats, ThreadReport};
+pub(crate) use stats::ThreadStats;
+#[allow(unused_imports)]
+pub use preempt::{is_preempt_disabled, preempt_disable, PreemptGuard};
+#[allow(unused_imports)]
+pub use cpu::{current_cpu, CpuId, CpuSet, MAX_CPUS};
+
+/// Must be called once, after the memory is initialized and before interrupts are enabled, so the scheduler
+/// doesn't have to be allocated for the first time inside the timer interrupt. This also spawns the idle thread
+/// of the boot processor
+pub fn init() {
+    lazy_static::initialize(&SCHEDULER);
+
+    // The thread that runs `kernel_main` never goes through `add_thread`
+    unsafe { stats::register(current_thread().as_ref()) };
+
+    spawn_idle(CpuId::BOOT);
+
+    crate::task::spawn_kthread(reaper::reaper_loop, "reaper").expect("Failed to spawn the reaper thread");
+}
+
+/// Gives a run queue to an application processor that was just started, `bootstrap` is the thread already running
+/// on it (like [`Thread::bootstrap`] for the boot processor). CPUs must come online in order, starting from 1
+///
+/// ## Panics
+///
+/// Panics if `cpu` isn't the next CPU to come online
+#[allow(dead_code)]
+pub(crate) fn add_cpu(cpu: CpuId, bootstrap: Thread) {
+    stats::register(&bootstrap);
+
+    let bootstrap = NonNull::from(Box::leak(Box::new(bootstrap)));
+
+    // The new list of run queues is allocated before interrupts are disabled and the old one is freed after
+    // they're enabled again, the scheduler itself never allocates
+    let mut cpus = Vec::with_capacity(cpu.as_usize() + 1);
+
+    let old = SCHEDULER.with(|scheduler| {
+        assert_eq!(scheduler.cpus.len(), cpu.as_usize(), "The CPUs must come online in order");
+
+        cpus.append(&mut scheduler.cpus);
+        cpus.push(RunQueue::new(cpu, bootstrap));
+
+        core::mem::replace(&mut scheduler.cpus, cpus)
+    });
+
+    drop(old);
+    spawn_idle(cpu);
+}
+
+/// Spawns the idle thread of `cpu`, which never leaves it
+fn spawn_idle(cpu: CpuId) {
+    let idle = crate::task::spawn_kthread_with(idle::idle_loop, "idle", Priority::Idle, CpuSet::single(cpu))
+        .expect("Failed to spawn the idle thread");
+
+    SCHEDULER.with(|scheduler| scheduler.queue(cpu).idle = Some(idle.id()));
+}
+
+/// Adds a thread to the end of the ready queue of its priority, on the allowed CPU with the least work
+pub fn add_thread(thread: Thread) {
+    stats::register(&thread);
+
+    // The thread is moved to the heap before interrupts are disabled, the scheduler itself never allocates
+    let thread = NonNull::from(Box::leak(Box::new(thread)));
+
+    SCHEDULER.with(|scheduler| unsafe {
+        let cpu = scheduler.least_loaded(thread.as_ref().affinity);
+        scheduler.queue(cpu).push(thread);
+    });
+}
+
+/// Returns the CPUs that are running and have a run queue
+#[allow(dead_code)]
+pub fn online_cpus() -> CpuSet {
+    SCHEDULER.with(|scheduler| scheduler.online())
+}
+
+/// Returns the CPUs the current thread is allowed to run on
+#[allow(dead_code)]
+pub fn current_affinity() -> CpuSet {
+    SCHEDULER.with(|scheduler| unsafe { scheduler.current(current_cpu()).as_ref().affinity })
+}
+
+/// Restricts the current thread to the CPUs in `affinity`, moving it to one of them right away if it's
+/// running on a CPU it's no longer allowed on. The CPUs that aren't online yet can be in the mask, the thread
+/// may go to them once they come online
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if none of the CPUs in `affinity` is online
+#[allow(dead_code)]
+pub fn set_current_affinity(affinity: CpuSet) -> Result<(), KernelError> {
+    let migrate = SCHEDULER.with(|scheduler| {
+        if affinity.intersection(scheduler.online()).is_empty() {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let cpu = current_cpu();
+        unsafe { scheduler.current(cpu).as_mut().affinity = affinity };
+
+        Ok(!affinity.contains(cpu))
+    })?;
+
+    // The thread goes to an allowed CPU when it leaves this one, see `Scheduler::schedule`
+    if migrate {
+        yield_now();
+    }
+
+    Ok(())
+}
+
+/// Changes the priority of the current thread, the change takes effect on the next scheduling decision.
+/// A boosted thread keeps its boost until [`restore_current_priority`]
+#[allow(dead_code)]
+pub fn set_current_priority(priority: Priority) {
+    SCHEDULER.with(|scheduler| unsafe {
+        let current = scheduler.current(current_cpu()).as_mut();
+
+        // A boost given by a lock is kept until it's dropped, the thread doesn't go below it meanwhile
+        if current.priority == current.base_priority {
+            current.priority = priority;
+        } else {
+            current.priority = current.priority.max(priority);
+        }
+
+        current.base_priority = priority;
+    });
+}
+
+/// Raises the priority of `thread` to `priority` (if it's lower) until it calls [`restore_current_priority`],
+/// used by the locks a thread with a higher priority is waiting for, so the holder isn't kept off the CPU by
+/// threads with a priority between both (priority inversion)
+pub(crate) fn boost(thread: NonNull<Thread>, priority: Priority) {
+    SCHEDULER.with(|scheduler| scheduler.boost(thread, priority));
+}
+
+/// Drops the boost given to the current thread by [`boost`], if any
+pub(crate) fn restore_current_priority() {
+    SCHEDULER.with(|scheduler| unsafe {
+        let current = scheduler.current(current_cpu()).as_mut();
+        current.priority = current.base_priority;
+    });
+}
+
+/// Gives up the rest of the time slice of the current thread, letting the next ready thread of the same
+/// or a higher priority run. If there's no such thread the current thread just keeps running
+#[allow(dead_code)]
+pub fn yield_now() {
+    x86_64::instructions::interrupts::without_interrupts(schedule);
+}
+
+/// Returns whatever a thread is waiting in the ready queue of this CPU
+fn has_ready() -> bool {
+    SCHEDULER.with(|scheduler| scheduler.queue(current_cpu()).highest_ready().is_some())
+}
+
+/// Returns the thread currently running on this CPU
+pub(crate) fn current_thread() -> NonNull<Thread> {
+    SCHEDULER.with(|scheduler| scheduler.current(current_cpu()))
+}
+
+/// Marks the current thread as blocked, it won't be scheduled again until [`wake`] is called with it.
+/// The thread keeps running until it calls [`yield_now`], so interrupts should be kept disabled between both
+/// calls, otherwise the thread may be woken up before it even starts waiting
+///
+/// ## Panics
+///
+/// Panics if preemption is disabled, see [`PreemptGuard`]
+pub(crate) fn block_current() {
+    assert!(!preempt::is_preempt_disabled(), "A thread can't block with preemption disabled");
+
+    SCHEDULER.with(|scheduler| unsafe {
+        scheduler.current(current_cpu()).as_mut().state = ThreadState::Blocked;
+    });
+}
+
+/// Makes a blocked thread ready to run again
+pub(crate) fn wake(thread: NonNull<Thread>) {
+    SCHEDULER.with(|scheduler| scheduler.wake(thread));
+}
+
+/// Marks the current thread as exited and leaves the CPU for good, the reaper frees the thread afterwards
+pub(crate) fn exit_current() -> ! {
+    x86_64::instructions::interrupts::disable();
+
+    SCHEDULER.with(|scheduler| unsafe {
+        scheduler.current(current_cpu()).as_mut().state = ThreadState::Exited;
+    });
+
+    schedule();
+    unreachable!("An exited thread was scheduled again");
+}
+
+/// Called by the timer interrupt on every tick, switches to another thread if the current one was preempted
+pub(crate) fn on_tick() {
+    if SCHEDULER.with(|scheduler| scheduler.tick(current_cpu())) && preempt::preemptible() {
+        schedule();
+    }
+}
+
+/// Switches to the next ready thread of this CPU right away, returning once the current thread is scheduled again.
+/// Must be called with interrupts disabled
+fn schedule() {
+    let (previous, next) = match SCHEDULER.with(|scheduler| scheduler.schedule(current_cpu())) {
+        Decision::Keep => return,
+        Decision::Switch(previous, next) => (previous, next)
+    };
+
+    // Catch a stack overflow while it's still fresh, before the damage spreads to other threads
+    let previous_ref = unsafe { previous.as_ref() };
+
+    if previous_ref.stack_overflowed() {
+        panic!("Thread {:?} ({}) overflowed its stack", previous_ref.id(), previous_ref.name());
+    }
+
+    // Interrupts arriving in user mode must land on the kernel stack of the thread that is about to run
+    unsafe {
+        let stack_top = next.as_ref().stack().map(|stack| stack.top());
+        crate::interrupts::interrupt_manager::set_kernel_stack(stack_top);
+    }
+
+    // The scheduler can't stay borrowed while switching, since the next thread resumes
+    // from its own call to this function (or starts from scratch) and borrows it again
+    unsafe {
+        switch_to(&mut (*previous.as_ptr()).context, &next.as_ref().context);
+    }
+}
@@ -0,0 +1,53 @@
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// How many [`PreemptGuard`]s are alive, the current thread isn't preempted while this isn't 0.
+/// There's a single CPU and the thread can't be switched out while it holds a guard, so one counter is enough
+static PREEMPT_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Set when the timer wanted to preempt the current thread while preemption was disabled,
+/// the thread yields as soon as the last guard is dropped
+static PREEMPT_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Keeps the timer from switching the current thread to another one until it's dropped, without disabling
+/// interrupts, so the interrupt handlers (and the timers) keep running meanwhile. Guards can be nested.
+///
+/// The thread must not block while holding a guard, since nothing could run in its place
+pub struct PreemptGuard {
+    /// The guard belongs to the thread that created it
+    _not_send: PhantomData<*const ()>
+}
+
+/// Disables preemption until the returned guard is dropped
+#[allow(dead_code)]
+pub fn preempt_disable() -> PreemptGuard {
+    PREEMPT_COUNT.fetch_add(1, Ordering::Acquire);
+
+    PreemptGuard {
+        _not_send: PhantomData
+    }
+}
+
+impl Drop for PreemptGuard {
+    /// Enables preemption again, yielding right away if the time slice of the thread ran out meanwhile
+    fn drop(&mut self) {
+        if PREEMPT_COUNT.fetch_sub(1, Ordering::Release) == 1 && PREEMPT_PENDING.swap(false, Ordering::AcqRel) {
+            super::yield_now();
+        }
+    }
+}
+
+/// Returns whatever the current thread can be switched out, called by the timer before preempting it
+pub(super) fn preemptible() -> bool {
+    if PREEMPT_COUNT.load(Ordering::Acquire) == 0 {
+        return true;
+    }
+
+    PREEMPT_PENDING.store(true, Ordering::Release);
+    return false;
+}
+
+/// Returns whatever preemption is disabled by a [`PreemptGuard`]
+pub fn is_preempt_disabled() -> bool {
+    PREEMPT_COUNT.load(Ordering::Acquire) != 0
+}
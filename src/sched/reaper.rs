@@ -0,0 +1,42 @@
+use alloc::boxed::Box;
+use x86_64::instructions::interrupts;
+use crate::memory::stack;
+use super::SCHEDULER;
+
+/// The entry of the reaper thread, which frees the stacks and the control blocks of the threads that exited.
+///
+/// An exited thread can't free its own stack since it's still running on it until the very last
+/// context switch, so this work is always done by another thread
+pub(super) fn reaper_loop() {
+    SCHEDULER.with(|scheduler| scheduler.reaper = Some(scheduler.current(super::current_cpu())));
+
+    loop {
+        // Interrupts stay disabled until the reaper blocks, so a thread can't exit between the check and blocking
+        let zombie = interrupts::without_interrupts(|| {
+            let zombie = SCHEDULER.with(|scheduler| scheduler.zombies.pop_front());
+
+            if zombie.is_none() {
+                super::block_current();
+                super::schedule();
+            }
+
+            zombie
+        });
+
+        if let Some(zombie) = zombie {
+            // The thread isn't in any queue anymore and the scheduler already switched away from it
+            let mut thread = unsafe { Box::from_raw(zombie.as_ptr()) };
+
+            if let Some(stack) = thread.take_stack() {
+                unsafe { stack::free_stack(stack) };
+            }
+
+            super::stats::unregister(thread.id());
+
+            // Dropping the thread may drop the last reference to its process, freeing the address space
+            if let Some(process) = thread.process() {
+                process.remove_thread(thread.id());
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use core::ptr::NonNull;
+use crate::task::thread::{Thread, ThreadId, ThreadState};
+use crate::utils::list::List;
+use super::cpu::CpuId;
+use super::{Priority, PRIORITY_COUNT, TIME_SLICE_TICKS};
+
+/// The threads of a single CPU: the one it's running and the ones waiting for their turn on it
+pub(super) struct RunQueue {
+    cpu: CpuId,
+    /// The thread currently running on the CPU
+    pub(super) current: NonNull<Thread>,
+    /// The threads waiting for their turn to run, one queue per [`Priority`]
+    ready: [List<Thread>; PRIORITY_COUNT],
+    /// How many ticks are left until the current thread is preempted
+    pub(super) slice_remaining: u32,
+    /// How many ticks are left until the next balancing pass, see [`super::Scheduler::balance`]
+    pub(super) balance_remaining: u32,
+    /// The thread that runs when nothing else is ready on this CPU, set once it's spawned
+    pub(super) idle: Option<ThreadId>
+}
+
+impl RunQueue {
+    /// Creates the run queue of `cpu`, which is already running `current`
+    pub(super) fn new(cpu: CpuId, current: NonNull<Thread>) -> Self {
+        RunQueue {
+            cpu,
+            current,
+            ready: [List::new(), List::new(), List::new()],
+            slice_remaining: TIME_SLICE_TICKS,
+            balance_remaining: super::BALANCE_INTERVAL_TICKS,
+            idle: None
+        }
+    }
+
+    pub(super) fn current_priority(&self) -> Priority {
+        unsafe { self.current.as_ref().priority }
+    }
+
+    pub(super) fn current_state(&self) -> ThreadState {
+        unsafe { self.current.as_ref().state }
+    }
+
+    pub(super) fn is_idle(&self) -> bool {
+        Some(unsafe { self.current.as_ref().id() }) == self.idle
+    }
+
+    /// Returns the highest priority class with a ready thread
+    pub(super) fn highest_ready(&self) -> Option<Priority> {
+        return [Priority::Realtime, Priority::Normal, Priority::Idle]
+            .into_iter()
+            .find(|&priority| !self.ready[priority as usize].is_empty());
+    }
+
+    /// Returns how much work this CPU has, the ready threads plus the running one. The threads with
+    /// [`Priority::Idle`] don't count, they only soak up the time nobody else wants
+    pub(super) fn load(&self) -> usize {
+        let ready = self.ready[Priority::Normal as usize].len() + self.ready[Priority::Realtime as usize].len();
+        let running = self.current_state() == ThreadState::Ready && self.current_priority() != Priority::Idle;
+
+        return ready + running as usize;
+    }
+
+    /// Adds a ready thread to the end of the queue of its priority, the thread runs on this CPU from now on
+    ///
+    /// ## Safety
+    ///
+    /// This method is unsafe because the caller must guarantee that `thread` is alive and not in any other queue
+    pub(super) unsafe fn push(&mut self, thread: NonNull<Thread>) {
+        (*thread.as_ptr()).cpu = self.cpu;
+        self.ready[thread.as_ref().priority as usize].push_back(thread);
+    }
+
+    pub(super) fn pop(&mut self, priority: Priority) -> Option<NonNull<Thread>> {
+        self.ready[priority as usize].pop_front()
+    }
+
+    /// Removes a thread from the queue of the given priority
+    ///
+    /// ## Safety
+    ///
+    /// This method is unsafe because the caller must guarantee that `thread` is in the queue of `priority`
+    pub(super) unsafe fn remove(&mut self, thread: NonNull<Thread>, priority: Priority) {
+        self.ready[priority as usize].remove(thread);
+    }
+
+    /// Takes the ready thread that can move to `cpu` with the highest priority, starting from the back of the queues
+    /// since those threads waited the least here. The threads with [`Priority::Idle`] never move
+    pub(super) fn take_for(&mut self, cpu: CpuId) -> Option<NonNull<Thread>> {
+        for priority in [Priority::Realtime, Priority::Normal] {
+            let mut cursor = self.ready[priority as usize].cursor_back_mut();
+
+            while let Some(thread) = cursor.current() {
+                if unsafe { thread.as_ref().affinity.contains(cpu) } {
+                    return cursor.remove_current();
+                }
+
+                cursor.move_prev();
+            }
+        }
+
+        return None;
+    }
+}
@@ -0,0 +1,136 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::println;
+use crate::process::ProcessId;
+use crate::task::thread::{Thread, ThreadId};
+use crate::utils::Mutex;
+
+/// The statistics of every thread that wasn't reaped yet, so they can be reported without going through the
+/// scheduler (the blocked threads aren't in any of its queues)
+static THREADS: Mutex<BTreeMap<ThreadId, Entry>> = Mutex::new(BTreeMap::new());
+
+/// How many times the CPU switched from one thread to another since boot
+static CONTEXT_SWITCHES: AtomicU64 = AtomicU64::new(0);
+
+struct Entry {
+    name: &'static str,
+    process: Option<ProcessId>,
+    stats: Arc<ThreadStats>
+}
+
+/// The counters kept by the scheduler for each thread, every time is measured in timer ticks
+pub struct ThreadStats {
+    /// The ticks that arrived while the thread was running
+    run_ticks: AtomicU64,
+    /// How many times the thread was switched to
+    switches: AtomicU64,
+    /// The ticks the thread spent in a ready queue, waiting for its turn
+    wait_ticks: AtomicU64,
+    /// The tick at which the thread last entered a ready queue
+    ready_since: AtomicU64
+}
+
+impl ThreadStats {
+    pub fn new() -> Self {
+        ThreadStats {
+            run_ticks: AtomicU64::new(0),
+            switches: AtomicU64::new(0),
+            wait_ticks: AtomicU64::new(0),
+            ready_since: AtomicU64::new(crate::time::ticks())
+        }
+    }
+
+    /// Called on every tick that interrupts the thread
+    pub(super) fn account_tick(&self) {
+        self.run_ticks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Called when the thread goes into a ready queue
+    pub(super) fn account_ready(&self) {
+        self.ready_since.store(crate::time::ticks(), Ordering::Relaxed);
+    }
+
+    /// Called when the CPU switches to the thread, which just left its ready queue
+    pub(super) fn account_switch(&self) {
+        let waited = crate::time::ticks().saturating_sub(self.ready_since.load(Ordering::Relaxed));
+
+        self.switches.fetch_add(1, Ordering::Relaxed);
+        self.wait_ticks.fetch_add(waited, Ordering::Relaxed);
+        CONTEXT_SWITCHES.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A snapshot of the statistics of a thread, see [`stats`]
+#[derive(Debug, Clone)]
+pub struct ThreadReport {
+    pub id: ThreadId,
+    pub name: &'static str,
+    /// The process the thread belongs to, [`None`] for kernel threads
+    pub process: Option<ProcessId>,
+    pub run_ticks: u64,
+    pub switches: u64,
+    pub wait_ticks: u64
+}
+
+/// Starts keeping the statistics of `thread` around until it's reaped
+pub(super) fn register(thread: &Thread) {
+    let entry = Entry {
+        name: thread.name(),
+        process: thread.process().map(|process| process.id()),
+        stats: thread.stats().clone()
+    };
+
+    THREADS.lock().insert(thread.id(), entry);
+}
+
+/// Forgets the statistics of a thread that was reaped
+pub(super) fn unregister(id: ThreadId) {
+    // The entry is taken out first so it isn't freed with the lock held
+    let entry = THREADS.lock().remove(&id);
+    drop(entry);
+}
+
+/// Returns the statistics of every thread that wasn't reaped yet, ordered by their ID
+#[allow(dead_code)]
+pub fn stats() -> Vec<ThreadReport> {
+    return THREADS.lock()
+        .iter()
+        .map(|(&id, entry)| ThreadReport {
+            id,
+            name: entry.name,
+            process: entry.process,
+            run_ticks: entry.stats.run_ticks.load(Ordering::Relaxed),
+            switches: entry.stats.switches.load(Ordering::Relaxed),
+            wait_ticks: entry.stats.wait_ticks.load(Ordering::Relaxed)
+        })
+        .collect();
+}
+
+/// Returns how many times the CPU switched from one thread to another since boot
+#[allow(dead_code)]
+pub fn context_switches() -> u64 {
+    CONTEXT_SWITCHES.load(Ordering::Relaxed)
+}
+
+/// Prints a `ps`-like table with the statistics of every thread to the screen
+#[allow(dead_code)]
+pub fn ps() {
+    let uptime = crate::time::ticks().max(1);
+
+    println!("{:>4} {:>4} {:<16} {:>8} {:>5} {:>8} {:>8}", "TID", "PID", "NAME", "RUN", "CPU%", "WAIT", "SWITCHES");
+
+    for report in stats() {
+        let process = match report.process {
+            Some(process) => alloc::format!("{}", process.as_u64()),
+            None => alloc::string::String::from("-")
+        };
+
+        println!("{:>4} {:>4} {:<16} {:>8} {:>5} {:>8} {:>8}",
+                 report.id.as_u64(), process, report.name, report.run_ticks,
+                 report.run_ticks * 100 / uptime, report.wait_ticks, report.switches);
+    }
+
+    println!("{} context switches, {} of {} ticks idle", context_switches(), super::idle_ticks(), uptime);
+}
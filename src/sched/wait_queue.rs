@@ -0,0 +1,127 @@
+use core::marker::PhantomPinned;
+use core::ptr::NonNull;
+use crate::task::thread::Thread;
+use crate::time;
+use crate::time::wheel::{TimerEntry, TimerTarget};
+use crate::utils::IrqCell;
+use crate::utils::list::{Link, Linked, List};
+
+/// A queue of threads blocked until some condition becomes true, like a buffer getting data or a device finishing
+/// a request. Whoever makes the condition true (another thread or an interrupt handler) wakes them up.
+///
+/// The waiters live on the stacks of the blocked threads and are linked intrusively, so neither waiting nor
+/// waking up allocates, which makes [`WaitQueue::wake_one`] and [`WaitQueue::wake_all`] safe to call from an ISR
+pub struct WaitQueue {
+    waiters: IrqCell<List<Waiter>>
+}
+
+#[allow(dead_code)]
+impl WaitQueue {
+    pub const fn new() -> Self {
+        WaitQueue {
+            waiters: IrqCell::new(List::new())
+        }
+    }
+
+    /// Blocks the current thread until `condition` returns `true`, it's checked right away and every time the thread
+    /// is woken up. The condition runs with interrupts disabled, so it must be quick and must not block
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        loop {
+            let waiter = Waiter::new();
+
+            // Interrupts stay disabled from the check until the thread is out of the CPU,
+            // so the condition can't become true (and the wake up get lost) in between
+            let done = x86_64::instructions::interrupts::without_interrupts(|| {
+                if condition() {
+                    return true;
+                }
+
+                self.waiters.with(|waiters| unsafe { waiters.push_back(NonNull::from(&waiter)) });
+
+                super::block_current();
+                super::yield_now();
+
+                // Someone else may have woken the thread up, then it's still in the queue
+                self.waiters.with(|waiters| {
+                    if waiter.link.is_linked() {
+                        unsafe { waiters.remove(NonNull::from(&waiter)) };
+                    }
+                });
+
+                return false;
+            });
+
+            if done {
+                return;
+            }
+        }
+    }
+
+    /// Same as [`WaitQueue::wait_until`] but gives up once `ms` milliseconds passed, returning whatever
+    /// the condition became true
+    pub fn wait_until_timeout(&self, ms: u64, mut condition: impl FnMut() -> bool) -> bool {
+        let deadline = time::ticks() + time::ms_to_ticks(ms).max(1);
+
+        // The timer only wakes the thread up, it checks the condition (and the deadline) again like after any wake up
+        let timer = TimerEntry::new(deadline, TimerTarget::Thread(super::current_thread()));
+        unsafe { time::wheel::insert(&timer) };
+
+        let mut satisfied = false;
+
+        self.wait_until(|| {
+            satisfied = condition();
+            satisfied || time::ticks() >= deadline
+        });
+
+        return satisfied;
+    }
+
+    /// Wakes up the thread that has been waiting the longest, if there's any
+    pub fn wake_one(&self) {
+        self.waiters.with(|waiters| {
+            if let Some(waiter) = waiters.pop_front() {
+                super::wake(unsafe { waiter.as_ref().thread });
+            }
+        });
+    }
+
+    /// Wakes up every waiting thread, each of them checks its condition again
+    pub fn wake_all(&self) {
+        self.waiters.with(|waiters| {
+            while let Some(waiter) = waiters.pop_front() {
+                super::wake(unsafe { waiter.as_ref().thread });
+            }
+        });
+    }
+
+    /// Returns whatever any thread is waiting in this queue
+    pub fn has_waiters(&self) -> bool {
+        self.waiters.with(|waiters| !waiters.is_empty())
+    }
+}
+
+/// A thread waiting on a [`WaitQueue`], it lives on the stack of the thread for as long as it waits
+struct Waiter {
+    link: Link<Waiter>,
+    thread: NonNull<Thread>,
+    _pinned: PhantomPinned
+}
+
+// The waiter is only touched with the queue held, and it stays alive while its thread is blocked
+unsafe impl Send for Waiter {}
+
+unsafe impl Linked for Waiter {
+    fn link(&self) -> &Link<Self> {
+        &self.link
+    }
+}
+
+impl Waiter {
+    fn new() -> Self {
+        Waiter {
+            link: Link::new(),
+            thread: super::current_thread(),
+            _pinned: PhantomPinned
+        }
+    }
+}
@@ -0,0 +1,496 @@
+use alloc::string::String;
+use core::fmt;
+use core::fmt::Write;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use x86_64::instructions::port::Port;
+use crate::sched::WaitQueue;
+use crate::sync::SleepMutex;
+use crate::utils::error::KernelError;
+use crate::utils::IrqCell;
+use crate::utils::queue::SpscQueue;
+
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => ($crate::serial::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($($arg:tt)*) => ($crate::serial_print!("{}\n", format_args!($($arg)*)));
+}
+
+/// The first port of COM1 and its IRQ line
+const COM1: u16 = 0x3F8;
+const COM1_IRQ: u8 = 4;
+
+/// The registers of the UART, as offsets from its first port. The first two are the divisor of the baud rate
+/// while the divisor latch is enabled in the line control register
+const REGISTER_DATA: u16 = 0;
+const REGISTER_INTERRUPT_ENABLE: u16 = 1;
+const REGISTER_DIVISOR_LOW: u16 = 0;
+const REGISTER_DIVISOR_HIGH: u16 = 1;
+/// Reads as the interrupt identification and writes as the FIFO control
+const REGISTER_INTERRUPT_ID: u16 = 2;
+const REGISTER_FIFO_CONTROL: u16 = 2;
+const REGISTER_LINE_CONTROL: u16 = 3;
+const REGISTER_MODEM_CONTROL: u16 = 4;
+const REGISTER_LINE_STATUS: u16 = 5;
+const REGISTER_MODEM_STATUS: u16 = 6;
+
+/// The interrupts of the UART: data received and transmitter holding register empty
+const INTERRUPT_RECEIVED: u8 = 1 << 0;
+const INTERRUPT_TRANSMITTER_EMPTY: u8 = 1 << 1;
+
+/// The interrupt identification, bit 0 is clear while an interrupt is pending and bits 1 to 3 tell which one
+const ID_NONE_PENDING: u8 = 1 << 0;
+const ID_MODEM_STATUS: u8 = 0b000;
+const ID_TRANSMITTER_EMPTY: u8 = 0b001;
+const ID_RECEIVED: u8 = 0b010;
+const ID_LINE_STATUS: u8 = 0b011;
+const ID_TIMEOUT: u8 = 0b110;
+
+/// Enables and clears both FIFOs, raising the received data interrupt once 14 bytes are waiting
+const FIFO_ENABLE_AND_CLEAR: u8 = 0xC7;
+
+/// Selects 8 data bits, no parity and one stop bit, the divisor latch bit gives access to the divisor
+const LINE_8N1: u8 = 0x03;
+const LINE_DIVISOR_LATCH: u8 = 1 << 7;
+
+/// DTR, RTS and OUT2, the last one connects the interrupt of the UART to the PIC
+const MODEM_READY: u8 = 0x0B;
+/// Loops the output back to the input, used to check the UART works
+const MODEM_LOOPBACK: u8 = 0x1E;
+
+/// The bits of the line status
+const STATUS_DATA_READY: u8 = 1 << 0;
+const STATUS_TRANSMITTER_EMPTY: u8 = 1 << 5;
+
+/// The clock of the UART divided by 16, the baud rate is this divided by the divisor
+const MAX_BAUD_RATE: u32 = 115200;
+
+/// How many bytes the FIFO of the transmitter holds
+const FIFO_SIZE: usize = 16;
+
+const RX_BUFFER_SIZE: usize = 1024;
+const TX_BUFFER_SIZE: usize = 1024;
+
+/// The longest line [`read_line`] returns, the bytes typed past it are ignored
+const MAX_LINE_LENGTH: usize = 256;
+
+/// What terminals send for the delete and backspace keys
+const DELETE: u8 = 0x7F;
+const BACKSPACE: u8 = 0x08;
+
+/// Set once [`init`] found a working UART
+static PRESENT: AtomicBool = AtomicBool::new(false);
+
+/// Whatever the kernel console (see [`crate::vga`]) is copied to the serial port
+static CONSOLE: AtomicBool = AtomicBool::new(false);
+
+/// The bytes received by the interrupt handler that weren't read yet, dropped once it's full
+static RX_QUEUE: SpscQueue<u8, RX_BUFFER_SIZE> = SpscQueue::new();
+
+/// The threads waiting for [`RX_QUEUE`] to get data
+static RX_WAITERS: WaitQueue = WaitQueue::new();
+
+/// Only one thread takes bytes out of [`RX_QUEUE`] at a time, which is all the queue allows
+static READER: SleepMutex<()> = SleepMutex::new(());
+
+/// The waker of the task waiting for [`RX_QUEUE`] to get data, if there's any
+static RX_WAKER: IrqCell<Option<Waker>> = IrqCell::new(None);
+
+/// Whatever the last byte [`read_line`] got ended a line with a carriage return, so the line feed that may follow it
+/// isn't taken as an empty line
+static LAST_WAS_CR: AtomicBool = AtomicBool::new(false);
+
+static TRANSMITTER: IrqCell<Transmitter> = IrqCell::new(Transmitter::new());
+
+/// The registers of a 16550 UART
+#[derive(Copy, Clone)]
+struct Uart {
+    base: u16
+}
+
+impl Uart {
+    fn read(&self, register: u16) -> u8 {
+        unsafe { Port::<u8>::new(self.base + register).read() }
+    }
+
+    fn write(&self, register: u16, value: u8) {
+        unsafe { Port::<u8>::new(self.base + register).write(value) };
+    }
+
+    fn line_status(&self) -> u8 {
+        self.read(REGISTER_LINE_STATUS)
+    }
+
+    /// Writes a byte once the transmitter has room for it
+    fn send_polled(&self, byte: u8) {
+        while self.line_status() & STATUS_TRANSMITTER_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+
+        self.write(REGISTER_DATA, byte);
+    }
+
+    /// Returns the next received byte, if there's any
+    fn receive(&self) -> Option<u8> {
+        (self.line_status() & STATUS_DATA_READY != 0).then(|| self.read(REGISTER_DATA))
+    }
+
+    fn set_interrupts(&self, interrupts: u8) {
+        self.write(REGISTER_INTERRUPT_ENABLE, interrupts);
+    }
+}
+
+const UART: Uart = Uart { base: COM1 };
+
+/// The bytes waiting to be sent, the interrupt handler moves them to the UART while the interrupt driven
+/// mode is on, otherwise every byte is sent right away by polling the UART
+struct Transmitter {
+    buffer: [u8; TX_BUFFER_SIZE],
+    head: usize,
+    len: usize,
+    interrupt_driven: bool
+}
+
+impl Transmitter {
+    const fn new() -> Self {
+        Transmitter {
+            buffer: [0; TX_BUFFER_SIZE],
+            head: 0,
+            len: 0,
+            interrupt_driven: false
+        }
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let byte = self.buffer[self.head];
+
+        self.head = (self.head + 1) % TX_BUFFER_SIZE;
+        self.len -= 1;
+
+        return Some(byte);
+    }
+
+    /// Fills the FIFO of the UART with the buffered bytes, the UART must be ready for them (after the
+    /// transmitter empty interrupt, or when its line status says so)
+    fn fill_fifo(&mut self) {
+        for _ in 0..FIFO_SIZE {
+            match self.pop() {
+                Some(byte) => UART.write(REGISTER_DATA, byte),
+                None => break
+            }
+        }
+
+        // The interrupt only makes sense while there's something left to send
+        let interrupts = if self.len > 0 { INTERRUPT_RECEIVED | INTERRUPT_TRANSMITTER_EMPTY } else { INTERRUPT_RECEIVED };
+        UART.set_interrupts(interrupts);
+    }
+
+    fn send(&mut self, byte: u8) {
+        if !self.interrupt_driven {
+            UART.send_polled(byte);
+            return;
+        }
+
+        // A full buffer is drained right here, the interrupt handler can't run while the transmitter is held
+        if self.len == TX_BUFFER_SIZE {
+            if let Some(oldest) = self.pop() {
+                UART.send_polled(oldest);
+            }
+        }
+
+        self.buffer[(self.head + self.len) % TX_BUFFER_SIZE] = byte;
+        self.len += 1;
+
+        // An idle transmitter won't raise its interrupt by itself, so it's started here
+        if UART.line_status() & STATUS_TRANSMITTER_EMPTY != 0 {
+            self.fill_fifo();
+        }
+    }
+
+    /// Sends every buffered byte by polling the UART
+    fn flush(&mut self) {
+        while let Some(byte) = self.pop() {
+            UART.send_polled(byte);
+        }
+    }
+}
+
+/// Looks for a 16550 UART on COM1 and sets it up with the given baud rate, 8 data bits, no parity and one stop bit,
+/// with its FIFOs on and the received bytes buffered by its interrupt. Must be called once, after the interrupts
+/// are initialized
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if the baud rate can't be reached (it must divide 115200),
+/// [`KernelError::NoDevice`] if there's no working UART and the error of [`crate::interrupts::interrupt_manager::register_irq`]
+/// if its interrupt can't be handled
+pub fn init(baud_rate: u32) -> Result<(), KernelError> {
+    if baud_rate == 0 || MAX_BAUD_RATE % baud_rate != 0 {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let divisor = (MAX_BAUD_RATE / baud_rate) as u16;
+
+    UART.set_interrupts(0);
+    UART.write(REGISTER_LINE_CONTROL, LINE_DIVISOR_LATCH);
+    UART.write(REGISTER_DIVISOR_LOW, divisor as u8);
+    UART.write(REGISTER_DIVISOR_HIGH, (divisor >> 8) as u8);
+    UART.write(REGISTER_LINE_CONTROL, LINE_8N1);
+    UART.write(REGISTER_FIFO_CONTROL, FIFO_ENABLE_AND_CLEAR);
+
+    // A byte sent in loopback mode comes right back if the UART is there and works
+    UART.write(REGISTER_MODEM_CONTROL, MODEM_LOOPBACK);
+    UART.write(REGISTER_DATA, 0xAE);
+
+    if UART.read(REGISTER_DATA) != 0xAE {
+        return Err(KernelError::NoDevice);
+    }
+
+    UART.write(REGISTER_MODEM_CONTROL, MODEM_READY);
+
+    crate::interrupts::interrupt_manager::register_irq(COM1_IRQ, handle_interrupt)?;
+    UART.set_interrupts(INTERRUPT_RECEIVED);
+
+    PRESENT.store(true, Ordering::Release);
+    Ok(())
+}
+
+/// Returns whatever [`init`] found a working UART
+pub fn is_present() -> bool {
+    PRESENT.load(Ordering::Acquire)
+}
+
+/// Sets whatever everything printed to the kernel console is also sent through the serial port
+pub fn set_console(enabled: bool) {
+    CONSOLE.store(enabled, Ordering::Release);
+}
+
+/// Sets whatever the bytes to send are buffered and moved to the UART by its interrupt, instead of waiting for
+/// the UART on every byte. Turning it off sends whatever is still buffered first
+pub fn set_interrupt_driven_tx(enabled: bool) {
+    TRANSMITTER.with(|transmitter| {
+        if !enabled {
+            transmitter.flush();
+            UART.set_interrupts(INTERRUPT_RECEIVED);
+        }
+
+        transmitter.interrupt_driven = enabled;
+    });
+}
+
+/// Called by the interrupt handler of the IRQ line of the UART, it moves the received bytes into the buffer and
+/// the buffered bytes to the UART
+///
+/// ## Note
+///
+/// This function must not allocate or block since it runs inside an interrupt handler
+pub fn handle_interrupt() {
+    if !is_present() {
+        return;
+    }
+
+    loop {
+        let id = UART.read(REGISTER_INTERRUPT_ID);
+
+        if id & ID_NONE_PENDING != 0 {
+            break;
+        }
+
+        match id >> 1 & 0b111 {
+            ID_RECEIVED | ID_TIMEOUT => {
+                while let Some(byte) = UART.receive() {
+                    // The bytes that don't fit are dropped, there's nothing better to do inside an interrupt
+                    let _ = RX_QUEUE.push(byte);
+                }
+
+                RX_WAITERS.wake_all();
+
+                // `wake_by_ref` is used so the waker is never dropped (and deallocated) inside the interrupt
+                RX_WAKER.with(|waker| {
+                    if let Some(waker) = waker.as_ref() {
+                        waker.wake_by_ref();
+                    }
+                });
+            }
+            ID_TRANSMITTER_EMPTY => TRANSMITTER.with(|transmitter| transmitter.fill_fifo()),
+            // Reading the status registers is what acknowledges these interrupts
+            ID_LINE_STATUS => { UART.line_status(); }
+            ID_MODEM_STATUS => { UART.read(REGISTER_MODEM_STATUS); }
+            _ => break
+        }
+    }
+}
+
+/// Sends `bytes` through the serial port, does nothing if there's no UART
+pub fn write_bytes(bytes: &[u8]) {
+    if !is_present() {
+        return;
+    }
+
+    TRANSMITTER.with(|transmitter| {
+        for &byte in bytes {
+            transmitter.send(byte);
+        }
+    });
+}
+
+/// Waits until every byte written so far is sent to the UART
+#[allow(dead_code)]
+pub fn flush() {
+    if is_present() {
+        TRANSMITTER.with(|transmitter| transmitter.flush());
+    }
+}
+
+/// Blocks the current thread until there's received data, then moves as much of it as fits into `buffer`,
+/// returning how many bytes were read. Returns 0 right away if there's no UART or `buffer` is empty
+#[allow(dead_code)]
+pub fn read(buffer: &mut [u8]) -> usize {
+    if !is_present() || buffer.is_empty() {
+        return 0;
+    }
+
+    let _reader = READER.lock();
+
+    RX_WAITERS.wait_until(|| !RX_QUEUE.is_empty());
+
+    let mut count = 0;
+
+    while count < buffer.len() {
+        match RX_QUEUE.pop() {
+            Some(byte) => buffer[count] = byte,
+            None => break
+        }
+
+        count += 1;
+    }
+
+    return count;
+}
+
+/// Returns the next received byte without blocking, looking at the UART itself once the buffer is empty.
+/// Meant for the code running with interrupts disabled (like a debugger stub stopped in an exception),
+/// where the interrupt handler can't fill the buffer
+///
+/// ## Note
+///
+/// This function must not be called while another thread may be in [`read`]
+#[allow(dead_code)]
+pub fn poll_byte() -> Option<u8> {
+    if !is_present() {
+        return None;
+    }
+
+    RX_QUEUE.pop().or_else(|| UART.receive())
+}
+
+/// Returns a future that completes with the next received byte, it never completes if there's no UART
+///
+/// ## Note
+///
+/// The future must not be polled while a thread may be in [`read`], the bytes can only be taken by one reader
+pub fn next_byte() -> NextByte {
+    NextByte { _private: () }
+}
+
+pub struct NextByte {
+    _private: ()
+}
+
+impl Future for NextByte {
+    type Output = u8;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<u8> {
+        if let Some(byte) = RX_QUEUE.pop() {
+            return Poll::Ready(byte);
+        }
+
+        // The old waker is dropped only after interrupts are enabled again, since dropping it may deallocate
+        let old_waker = RX_WAKER.with(|waker| waker.replace(context.waker().clone()));
+        drop(old_waker);
+
+        // A byte may have arrived after the first check but before the waker was registered
+        match RX_QUEUE.pop() {
+            Some(byte) => Poll::Ready(byte),
+            None => Poll::Pending
+        }
+    }
+}
+
+/// Reads a line typed on the other end of the serial port, echoing it back as it's typed, without the carriage
+/// return or line feed that ends it. Backspace and delete erase the last character, the other control characters
+/// and the characters past [`MAX_LINE_LENGTH`] are ignored
+///
+/// ## Note
+///
+/// Same as [`next_byte`]
+pub async fn read_line() -> String {
+    let mut line = String::new();
+
+    loop {
+        let byte = next_byte().await;
+        let last_was_cr = LAST_WAS_CR.swap(byte == b'\r', Ordering::AcqRel);
+
+        match byte {
+            b'\n' if last_was_cr => {},
+            b'\r' | b'\n' => {
+                write_bytes(b"\r\n");
+                return line;
+            },
+            BACKSPACE | DELETE => {
+                if line.pop().is_some() {
+                    write_bytes(b"\x08 \x08");
+                }
+            },
+            byte if byte.is_ascii_graphic() || byte == b' ' => {
+                if line.len() < MAX_LINE_LENGTH {
+                    line.push(byte as char);
+                    write_bytes(&[byte]);
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+/// Sends the terminal newlines (`\r\n`) instead of the bare `\n` of the kernel
+struct SerialWriter;
+
+impl Write for SerialWriter {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        for line in text.split_inclusive('\n') {
+            match line.strip_suffix('\n') {
+                Some(line) => {
+                    write_bytes(line.as_bytes());
+                    write_bytes(b"\r\n");
+                }
+                None => write_bytes(line.as_bytes())
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    SerialWriter.write_fmt(args).unwrap();
+}
+
+/// Called by the kernel console with everything it prints, see [`set_console`]
+pub(crate) fn console_print(args: fmt::Arguments) {
+    if CONSOLE.load(Ordering::Acquire) {
+        _print(args);
+    }
+}
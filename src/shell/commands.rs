@@ -0,0 +1,622 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::fs::{DirEntry, NodeKind};
+use crate::time::calendar::DateTime;
+use crate::utils::error::KernelError;
+use crate::{print, println};
+use super::CommandHandler;
+
+/// Registers the commands every kernel has
+pub(super) fn register_builtins() {
+    let builtins: [(&'static str, &'static str, CommandHandler); 25] = [
+        ("help", "Lists the available commands", help),
+        ("mem", "Shows how much memory is free", mem),
+        ("ps", "Lists the threads and their statistics", ps),
+        ("lspci", "Lists the devices found on the PCI bus", lspci),
+        ("lsdev", "Shows the device tree and the driver bound to each device", lsdev),
+        ("ticks", "Shows the timer ticks, the time since boot and the clock source", ticks),
+        ("clear", "Clears the screen", clear),
+        ("echo", "Prints its arguments", echo),
+        ("reboot", "Restarts the machine", reboot),
+        ("mode", "Shows or sets the graphics mode, like `mode 1024 768`", mode),
+        ("sysinfo", "Shows the firmware, the machine and its memory modules", sysinfo),
+        ("nvram", "Shows the settings kept in the CMOS or sets the console, like `nvram console screen`", nvram),
+        ("cat", "Prints the content of a file, like `cat /mnt/ram0/readme.txt`", cat),
+        ("ls", "Lists the entries of a directory with their type and size, like `ls /mnt/ram0`", ls),
+        ("stat", "Shows the type, the size, the permissions, the owner and the times of a file", stat),
+        ("sync", "Writes everything the filesystems and the block cache hold to the devices", sync),
+        ("journal", "Makes the end of a device a journal for its filesystem, like `journal ata0 256`", journal),
+        ("fsck", "Checks the FAT32 volume of a device, `fsck ata0 repair` also repairs it", fsck),
+        ("mount", "Lists the filesystems mounted, `mount ata0 /mnt/disk` mounts the one of a device", mount),
+        ("umount", "Unmounts the filesystem mounted at a path, like `umount /mnt/ata0`", umount),
+        ("arp", "Lists the neighbors whose hardware address was learned or is being asked for", arp),
+        ("ping", "Sends echo requests to a host and shows the time of the replies, like `ping 10.0.2.2 4`", ping),
+        ("host", "Looks up the IPv4 addresses of a host name with DNS, like `host example.com`", host),
+        ("ifconfig", "Shows the network interfaces, `ifconfig eth0 down` brings one down", ifconfig),
+        ("tftp", "Downloads a file from a TFTP server to a path, like `tftp 10.0.2.2 init.elf /tmp/init`", tftp)
+    ];
+
+    for (name, help, run) in builtins {
+        super::register(name, help, run).expect("Failed to register a built-in command");
+    }
+}
+
+fn help(_: &[&str]) {
+    for command in super::commands() {
+        println!("{:<8} {}", command.name, command.help);
+    }
+}
+
+fn mem(_: &[&str]) {
+    let heap_free = crate::memory::heap_free();
+    let heap_size = crate::memory::HEAP_SIZE;
+    let free_frames = crate::memory::free_frames();
+
+    println!("Heap: {} KiB free of {} KiB", heap_free / 1024, heap_size / 1024);
+    println!("Physical memory: {} frames free ({} KiB)", free_frames, free_frames * 4);
+}
+
+fn ps(_: &[&str]) {
+    crate::sched::ps();
+}
+
+fn lspci(_: &[&str]) {
+    for device in crate::pci::devices() {
+        println!(
+            "{:02x}:{:02x}.{} {:04x}:{:04x} {}",
+            device.bus, device.device, device.function, device.vendor_id, device.device_id,
+            device.class_name().unwrap_or("Unknown device")
+        );
+    }
+}
+
+fn lsdev(_: &[&str]) {
+    crate::drivers::registry::print_tree();
+}
+
+fn ticks(_: &[&str]) {
+    let uptime_ms = crate::time::uptime_ms();
+
+    println!("{} ticks, up for {}.{:03} seconds", crate::time::ticks(), uptime_ms / 1000, uptime_ms % 1000);
+
+    match crate::time::clocksource::current() {
+        Some(source) => println!("Clock source: {} at {} kHz", source.name, source.frequency / 1000),
+        None => println!("Clock source: none")
+    }
+
+    println!("Date: {} UTC", DateTime::from_unix(crate::time::unix_time()));
+}
+
+fn clear(_: &[&str]) {
+    crate::vga::clear();
+}
+
+fn echo(arguments: &[&str]) {
+    println!("{}", arguments.join(" "));
+}
+
+fn reboot(_: &[&str]) {
+    // What's only in the caches would be lost otherwise
+    sync(&[]);
+    crate::power::reboot();
+}
+
+fn mode(arguments: &[&str]) {
+    let size = match arguments {
+        [] => {
+            match crate::graphics::mode() {
+                Some((width, height)) => println!("{}x{}", width, height),
+                None => println!("Text mode")
+            }
+
+            return;
+        },
+        [width, height] => width.parse::<usize>().ok().zip(height.parse::<usize>().ok()),
+        _ => None
+    };
+
+    match size {
+        Some((width, height)) => {
+            if let Err(error) = crate::graphics::set_mode(width, height) {
+                println!("mode: can't set {}x{} ({:?})", width, height, error);
+            }
+        },
+        None => println!("Usage: mode [width height]")
+    }
+}
+
+fn sysinfo(_: &[&str]) {
+    let (major, minor) = match crate::smbios::version() {
+        Some(version) => version,
+        None => {
+            println!("sysinfo: no SMBIOS table");
+            return;
+        }
+    };
+
+    println!("SMBIOS {}.{}", major, minor);
+
+    if let Some(bios) = crate::smbios::bios() {
+        println!("BIOS:    {} {} ({})", bios.vendor, bios.version, bios.release_date);
+    }
+
+    if let Some(system) = crate::smbios::system() {
+        println!("System:  {} {} {}", system.manufacturer, system.product, system.version);
+        println!("Serial:  {}", system.serial_number);
+    }
+
+    for device in crate::smbios::memory_devices() {
+        println!(
+            "{:<8} {} MiB {} {} MT/s {} {}",
+            device.locator, device.size_kib / 1024, device.type_name(), device.speed, device.manufacturer,
+            device.part_number
+        );
+    }
+}
+
+fn nvram(arguments: &[&str]) {
+    use crate::cmos::Console;
+
+    let console = match arguments {
+        [] => {
+            println!("Boots:   {}", crate::cmos::boot_count());
+            println!("Console: {:?}", crate::cmos::console());
+            return;
+        },
+        ["console", "screen"] => Console::Screen,
+        ["console", "serial"] => Console::Serial,
+        _ => {
+            println!("Usage: nvram [console screen|serial]");
+            return;
+        }
+    };
+
+    crate::cmos::set_console(console);
+    println!("The console is {:?} from the next boot", console);
+}
+
+fn cat(arguments: &[&str]) {
+    let path = match arguments {
+        [path] => path,
+        _ => {
+            println!("Usage: cat <path>");
+            return;
+        }
+    };
+
+    let file = match crate::fs::vfs::open(path) {
+        Ok(file) => file,
+        Err(error) => {
+            println!("cat: {}: {}", path, error);
+            return;
+        }
+    };
+
+    // The file is printed a piece at a time, it can be much larger than the heap
+    let mut buffer = [0; 512];
+
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(count) => print!("{}", String::from_utf8_lossy(&buffer[..count])),
+            Err(error) => {
+                println!("cat: {}: {}", path, error);
+                return;
+            }
+        }
+    }
+}
+
+fn ls(arguments: &[&str]) {
+    let path = match arguments {
+        [] => "/",
+        [path] => path,
+        _ => {
+            println!("Usage: ls [path]");
+            return;
+        }
+    };
+
+    let directory = match crate::fs::vfs::open_dir(path) {
+        Ok(directory) => directory,
+        Err(error) => {
+            println!("ls: {}: {}", path, error);
+            return;
+        }
+    };
+
+    let mut entries: Vec<&DirEntry> = directory.iter().collect();
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for entry in entries {
+        let (kind, suffix) = match entry.kind {
+            NodeKind::File => ('-', ""),
+            NodeKind::Directory => ('d', "/"),
+            NodeKind::Device => ('c', ""),
+            NodeKind::Symlink => ('l', "@"),
+            NodeKind::Pipe => ('p', "|")
+        };
+
+        println!("{} {:>10} {}{}", kind, entry.size, entry.name, suffix);
+    }
+}
+
+fn stat(arguments: &[&str]) {
+    let path = match arguments {
+        [path] => path,
+        _ => {
+            println!("Usage: stat <path>");
+            return;
+        }
+    };
+
+    let metadata = match crate::fs::vfs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(error) => {
+            println!("stat: {}: {}", path, error);
+            return;
+        }
+    };
+
+    let kind = match metadata.kind {
+        NodeKind::File => "file",
+        NodeKind::Directory => "directory",
+        NodeKind::Device => "device",
+        NodeKind::Symlink => "symbolic link",
+        NodeKind::Pipe => "pipe"
+    };
+
+    println!("Type: {}", kind);
+    println!("Size: {} bytes", metadata.size);
+    println!("Mode: {:04o}", metadata.mode);
+    println!("Owner: {} (group {})", metadata.uid, metadata.gid);
+
+    // The filesystems that don't keep a time have 0 for it
+    let times = [("Accessed", metadata.accessed), ("Modified", metadata.modified), ("Created", metadata.created)];
+
+    for (name, time) in times {
+        match time {
+            0 => println!("{}: -", name),
+            time => println!("{}: {}", name, DateTime::from_unix(time))
+        }
+    }
+}
+
+fn sync(_: &[&str]) {
+    // The filesystems write what they hold to the cache first, then the cache goes to the devices
+    if let Err(error) = crate::fs::vfs::sync().and(crate::block::cache::sync()) {
+        println!("sync: {}", error);
+    }
+}
+
+fn journal(arguments: &[&str]) {
+    let (name, sectors) = match arguments {
+        [name, sectors] => match sectors.parse::<u64>() {
+            Ok(sectors) => (name, sectors),
+            Err(_) => {
+                println!("journal: {} isn't a number of sectors", sectors);
+                return;
+            }
+        },
+        _ => {
+            println!("Usage: journal <device> <sectors>");
+            return;
+        }
+    };
+
+    let device = match crate::block::find(name) {
+        Some(device) => device,
+        None => {
+            println!("journal: no device called {}", name);
+            return;
+        }
+    };
+
+    // The filesystem has to end before the journal, what's in the last sectors is lost
+    match crate::block::journal::format(device, sectors) {
+        Ok(()) => println!("The last {} sectors of {} are a journal from the next boot", sectors, name),
+        Err(error) => println!("journal: {}: {}", name, error)
+    }
+}
+
+fn fsck(arguments: &[&str]) {
+    let (name, repair) = match arguments {
+        [name] => (name, false),
+        [name, "repair"] => (name, true),
+        _ => {
+            println!("Usage: fsck <device> [repair]");
+            return;
+        }
+    };
+
+    let device = match crate::block::find(name) {
+        Some(device) => device,
+        None => {
+            println!("fsck: no device called {}", name);
+            return;
+        }
+    };
+
+    // The volume is read from the device, so what the filesystem mounted on it holds has to be there first
+    if let Err(error) = crate::fs::vfs::sync() {
+        println!("fsck: {}", error);
+        return;
+    }
+
+    let report = match crate::fs::fat::fsck(device, repair) {
+        Ok(report) => report,
+        Err(error) => {
+            println!("fsck: {}: {}", name, error);
+            return;
+        }
+    };
+
+    println!("{}: {} files and directories, {} clusters", name, report.nodes, report.clusters);
+
+    if report.is_clean() {
+        println!("No problems found");
+        return;
+    }
+
+    println!(
+        "{} broken chains, {} cross-links, {} orphaned clusters, {} wrong sizes",
+        report.broken_chains, report.cross_links, report.orphaned_clusters, report.wrong_sizes
+    );
+
+    if report.repaired {
+        println!("Every problem was repaired");
+    } else {
+        println!("Run `fsck {} repair` to repair them", name);
+    }
+}
+
+fn mount(arguments: &[&str]) {
+    let (name, path) = match arguments {
+        [] => {
+            for mount in crate::fs::vfs::mounts() {
+                println!("{:<10} {:<10} {}", mount.device.as_deref().unwrap_or("-"), mount.kind, mount.path);
+            }
+
+            return;
+        },
+        [name, path] => (name, path),
+        _ => {
+            println!("Usage: mount [<device> <path>]");
+            return;
+        }
+    };
+
+    let device = match crate::block::find(name) {
+        Some(device) => device,
+        None => {
+            println!("mount: no device called {}", name);
+            return;
+        }
+    };
+
+    let result = crate::fs::detect(device).and_then(|fs| crate::fs::vfs::mount(path, fs));
+
+    match result {
+        Ok(()) => println!("{} is mounted at {}", name, path),
+        Err(KernelError::Unsupported) => println!("mount: {} has no filesystem the kernel knows", name),
+        Err(error) => println!("mount: {}: {}", path, error)
+    }
+}
+
+fn umount(arguments: &[&str]) {
+    let path = match arguments {
+        [path] => path,
+        _ => {
+            println!("Usage: umount <path>");
+            return;
+        }
+    };
+
+    if let Err(error) = crate::fs::vfs::umount(path) {
+        println!("umount: {}: {}", path, error);
+    }
+}
+
+fn arp(_: &[&str]) {
+    for neighbor in crate::net::arp::neighbors() {
+        let mac_address = match neighbor.mac_address {
+            Some(mac_address) => format!("{}", mac_address),
+            None => String::from("(incomplete)")
+        };
+
+        println!("{:<15} {:<17} {:<6} {} ms", neighbor.address, mac_address, neighbor.device, neighbor.remaining_ms);
+    }
+}
+
+fn ping(arguments: &[&str]) {
+    const TIMEOUT_MS: u64 = 1000;
+
+    let (host, count) = match arguments {
+        [host] => (host, &"4"),
+        [host, count] => (host, count),
+        _ => {
+            println!("Usage: ping <host> [count]");
+            return;
+        }
+    };
+
+    let count = match count.parse::<u16>() {
+        Ok(count) => count,
+        Err(_) => {
+            println!("ping: {} isn't a count", count);
+            return;
+        }
+    };
+
+    let destination = match crate::net::dns::resolve(host) {
+        Ok(addresses) => addresses[0],
+        Err(error) => {
+            println!("ping: {}: {}", host, error);
+            return;
+        }
+    };
+
+    println!("PING {}: {} data bytes", destination, crate::net::icmp::ECHO_DATA_SIZE);
+
+    let mut times = Vec::new();
+
+    for sequence in 1..=count {
+        let started = crate::time::uptime_ms();
+
+        match crate::net::icmp::ping(destination, sequence, TIMEOUT_MS) {
+            Ok(reply) => {
+                println!(
+                    "{} bytes from {}: seq={} ttl={} time={}.{:03} ms",
+                    reply.size,
+                    reply.source,
+                    reply.sequence,
+                    reply.ttl,
+                    reply.rtt_ns / 1_000_000,
+                    reply.rtt_ns / 1000 % 1000
+                );
+
+                times.push(reply.rtt_ns);
+            },
+            Err(KernelError::Timeout) => println!("No reply for seq={}", sequence),
+            Err(error) => {
+                println!("ping: {}", error);
+                return;
+            }
+        }
+
+        // The requests are a second apart, whatever the time the reply took
+        let elapsed = crate::time::uptime_ms() - started;
+
+        if sequence != count && elapsed < 1000 {
+            crate::task::sleep_ms(1000 - elapsed);
+        }
+    }
+
+    let lost = (count as usize - times.len()) * 100 / count.max(1) as usize;
+    println!("{} sent, {} received, {}% lost", count, times.len(), lost);
+
+    if let (Some(minimum), Some(maximum)) = (times.iter().min(), times.iter().max()) {
+        let average = times.iter().sum::<u64>() / times.len() as u64;
+        println!("rtt min/avg/max = {} / {} / {} us", minimum / 1000, average / 1000, maximum / 1000);
+    }
+}
+
+fn host(arguments: &[&str]) {
+    let name = match arguments {
+        [name] => name,
+        _ => {
+            println!("Usage: host <name>");
+            return;
+        }
+    };
+
+    match crate::net::dns::resolve(name) {
+        Ok(addresses) => {
+            for address in addresses {
+                println!("{} has address {}", name, address);
+            }
+        },
+        Err(KernelError::NotFound) => println!("host: {} not found", name),
+        Err(error) => println!("host: {}: {}", name, error)
+    }
+}
+
+fn ifconfig(arguments: &[&str]) {
+    let result = match arguments {
+        [] => Ok(()),
+        [device] => crate::net::find(device).map(|_| ()).ok_or(KernelError::NoDevice),
+        [device, "up"] => crate::net::set_up(device, true),
+        [device, "down"] => crate::net::set_up(device, false),
+        [device, address] => set_interface_address(device, address, None),
+        [device, address, gateway] => set_interface_address(device, address, Some(gateway)),
+        _ => {
+            println!("Usage: ifconfig [device [up | down | none | address/prefix [gateway]]]");
+            return;
+        }
+    };
+
+    if let Err(error) = result {
+        println!("ifconfig: {}: {}", arguments[0], error);
+        return;
+    }
+
+    let device = arguments.first();
+
+    for interface in crate::net::ifconfig() {
+        if device.map_or(false, |device| *device != interface.name) {
+            continue;
+        }
+
+        let state = if interface.up { "up" } else { "down" };
+        let link = if interface.link_up { "link up" } else { "no link" };
+        let address = interface.address.map_or(String::from("no address"), |address| format!("inet {}", address));
+        let statistics = interface.statistics;
+
+        println!("{}: {}, {}, mtu {}", interface.name, state, link, interface.mtu);
+        println!("    ether {}  {}", interface.mac_address, address);
+        println!(
+            "    RX packets {} bytes {} errors {} dropped {}",
+            statistics.rx_packets,
+            statistics.rx_bytes,
+            statistics.rx_errors,
+            statistics.rx_dropped
+        );
+        println!(
+            "    TX packets {} bytes {} errors {} dropped {}",
+            statistics.tx_packets,
+            statistics.tx_bytes,
+            statistics.tx_errors,
+            statistics.tx_dropped
+        );
+    }
+}
+
+/// Gives a device the address of `ifconfig` (or takes it away with `none`), and makes `gateway` the default one
+fn set_interface_address(device: &str, address: &str, gateway: Option<&str>) -> Result<(), KernelError> {
+    let address = match address {
+        "none" => None,
+        address => Some(crate::net::InterfaceAddress::parse(address).ok_or(KernelError::InvalidArgument)?)
+    };
+
+    let gateway = match gateway {
+        Some(gateway) => Some(crate::net::Ipv4Address::parse(gateway).ok_or(KernelError::InvalidArgument)?),
+        None => None
+    };
+
+    crate::net::find(device).ok_or(KernelError::NoDevice)?;
+    crate::net::set_ipv4_address(device, address);
+
+    return match gateway {
+        Some(gateway) => crate::net::ipv4::set_default_gateway(Some(gateway)),
+        None => Ok(())
+    };
+}
+
+fn tftp(arguments: &[&str]) {
+    let (host, file, path) = match arguments {
+        [host, file, path] => (host, file, path),
+        _ => {
+            println!("Usage: tftp <host> <file> <path>");
+            return;
+        }
+    };
+
+    let server = match crate::net::dns::resolve(host) {
+        Ok(addresses) => addresses[0],
+        Err(error) => {
+            println!("tftp: {}: {}", host, error);
+            return;
+        }
+    };
+
+    let started = crate::time::uptime_ms();
+
+    match crate::net::tftp::download_to_file(server, file, path) {
+        Ok(size) => {
+            let elapsed = crate::time::uptime_ms() - started;
+            println!("Received {} bytes in {}.{:03} s", size, elapsed / 1000, elapsed % 1000);
+        },
+        Err(error) => println!("tftp: {}: {}", file, error)
+    }
+}
@@ -0,0 +1,75 @@
+use alloc::collections::VecDeque;
+use alloc::string::String;
+
+/// How many lines the history keeps, the oldest ones are forgotten first
+const MAX_HISTORY: usize = 32;
+
+/// The lines run in the shell, browsed with the up and down arrows
+pub(super) struct History {
+    lines: VecDeque<String>,
+    /// The line being shown while browsing, `None` while editing a new line
+    position: Option<usize>,
+    /// The line that was being typed when the browsing started, given back when going past the newest line
+    draft: String
+}
+
+impl History {
+    pub(super) fn new() -> Self {
+        History {
+            lines: VecDeque::new(),
+            position: None,
+            draft: String::new()
+        }
+    }
+
+    /// Remembers a line that was run and stops browsing. Empty lines and a line equal to the newest one are skipped
+    pub(super) fn push(&mut self, line: &str) {
+        self.stop_browsing();
+
+        if line.trim().is_empty() || self.lines.back().map_or(false, |last| last == line) {
+            return;
+        }
+
+        if self.lines.len() == MAX_HISTORY {
+            self.lines.pop_front();
+        }
+
+        self.lines.push_back(String::from(line));
+    }
+
+    /// Goes back to editing a new line, like after the typed line is thrown away
+    pub(super) fn stop_browsing(&mut self) {
+        self.position = None;
+    }
+
+    /// Returns the line older than the one being shown, `current` is what's typed right now.
+    /// Returns `None` when there's nothing older
+    pub(super) fn previous(&mut self, current: &str) -> Option<&str> {
+        let position = match self.position {
+            Some(0) => return None,
+            Some(position) => position - 1,
+            None if self.lines.is_empty() => return None,
+            None => {
+                self.draft = String::from(current);
+                self.lines.len() - 1
+            }
+        };
+
+        self.position = Some(position);
+        Some(&self.lines[position])
+    }
+
+    /// Returns the line newer than the one being shown, going past the newest line gives back the line
+    /// that was being typed. Returns `None` when not browsing
+    pub(super) fn next(&mut self) -> Option<&str> {
+        let position = self.position?;
+
+        if position + 1 == self.lines.len() {
+            self.position = None;
+            return Some(&self.draft);
+        }
+
+        self.position = Some(position + 1);
+        Some(&self.lines[position + 1])
+    }
+}
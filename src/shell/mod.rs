@@ -0,0 +1,214 @@
+mod commands;
+mod history;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::process::signal::SIGINT;
+use crate::task::keyboard::{Key, Keyboard};
+use crate::utils::error::KernelError;
+use crate::utils::Mutex;
+use self::history::History;
+use crate::{print, println};
+
+/// What the shell prints before every line it reads
+const PROMPT: &str = "> ";
+
+/// The longest line the shell reads, so a line always fits in a single row of the screen
+const MAX_LINE_LENGTH: usize = 80 - PROMPT.len() - 1;
+
+/// Every command the shell knows, by name
+static COMMANDS: Mutex<BTreeMap<&'static str, Command>> = Mutex::new(BTreeMap::new());
+
+/// What runs a command, with the words typed after its name
+pub type CommandHandler = fn(&[&str]);
+
+/// A command that can be run from the shell, it gets the words typed after its name
+#[derive(Copy, Clone)]
+pub struct Command {
+    pub name: &'static str,
+    /// A single line describing what the command does, shown by `help`
+    pub help: &'static str,
+    pub run: CommandHandler
+}
+
+/// Makes a command available in the shell, any module can register its own commands
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if `name` is empty or has whitespace in it and [`KernelError::Busy`]
+/// if there's already a command with the same name
+pub fn register(name: &'static str, help: &'static str, run: CommandHandler) -> Result<(), KernelError> {
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let mut commands = COMMANDS.lock();
+
+    if commands.contains_key(name) {
+        return Err(KernelError::Busy);
+    }
+
+    commands.insert(name, Command { name, help, run });
+    Ok(())
+}
+
+/// Returns every registered command, sorted by name
+pub fn commands() -> Vec<Command> {
+    COMMANDS.lock().values().copied().collect()
+}
+
+/// Runs the command typed in `line`, the first word is the name of the command and the rest are its arguments
+pub fn execute(line: &str) {
+    let words: Vec<&str> = line.split_whitespace().collect();
+
+    let (name, arguments) = match words.split_first() {
+        Some((name, arguments)) => (*name, arguments),
+        None => return
+    };
+
+    // The lock isn't held while the command runs, so a command can register other commands
+    let command = COMMANDS.lock().get(name).copied();
+
+    match command {
+        Some(command) => (command.run)(arguments),
+        None => println!("{}: command not found, try `help`", name)
+    }
+}
+
+/// Returns the names of the commands that start with `prefix`, sorted
+pub fn complete(prefix: &str) -> Vec<&'static str> {
+    COMMANDS.lock()
+        .keys()
+        .copied()
+        .filter(|name| name.starts_with(prefix))
+        .collect()
+}
+
+/// Registers the built-in commands, must be called before [`run`]
+pub fn init() {
+    commands::register_builtins();
+}
+
+/// The shell task, it reads lines typed on the keyboard and runs them as commands forever.
+/// Ctrl+C throws away the line being typed and sends [`SIGINT`] to the foreground process, which gets the lines
+/// typed while it runs through the console instead.
+///
+/// The up and down arrows browse the lines run before and Tab completes the name of the command
+pub async fn run() {
+    let mut keyboard = Keyboard::new();
+    let mut history = History::new();
+    let mut line = String::new();
+
+    print!("{}", PROMPT);
+
+    loop {
+        match keyboard.next_key().await {
+            Key::Char(character) if line.len() < MAX_LINE_LENGTH => {
+                line.push(character);
+                print!("{}", character);
+            },
+            Key::Backspace => {
+                if line.pop().is_some() {
+                    print!("\x08");
+                }
+            },
+            Key::Up => {
+                if let Some(previous) = history.previous(&line) {
+                    replace_line(&mut line, previous);
+                }
+            },
+            Key::Down => {
+                if let Some(next) = history.next() {
+                    replace_line(&mut line, next);
+                }
+            },
+            Key::Tab => complete_line(&mut line),
+            // While a program is in the foreground the lines typed are its input, read from the console
+            Key::Enter if crate::process::foreground().is_some() => {
+                println!();
+                crate::fs::devfs::push_input(&line);
+
+                line.clear();
+            },
+            Key::Enter => {
+                println!();
+                history.push(&line);
+                execute(&line);
+
+                line.clear();
+                print!("{}", PROMPT);
+            },
+            Key::Ctrl('c') => {
+                if let Some(process) = crate::process::foreground() {
+                    let _ = process.signals().send(SIGINT);
+                }
+
+                println!("^C");
+
+                history.stop_browsing();
+                line.clear();
+                print!("{}", PROMPT);
+            },
+            _ => {}
+        }
+    }
+}
+
+/// The shell task of the serial port, it reads the lines typed on the other end and runs them as commands forever.
+/// The output of the commands reaches the serial port as long as it's a copy of the kernel console
+pub async fn run_serial() {
+    loop {
+        crate::serial::write_bytes(PROMPT.as_bytes());
+
+        let line = crate::serial::read_line().await;
+        execute(&line);
+    }
+}
+
+/// Erases the line shown on the screen and shows `new` in its place
+fn replace_line(line: &mut String, new: &str) {
+    for _ in 0..line.len() {
+        print!("\x08");
+    }
+
+    line.clear();
+    line.extend(new.chars().take(MAX_LINE_LENGTH));
+
+    print!("{}", line);
+}
+
+/// Completes the name of the command being typed. A single match is completed entirely, otherwise the name is
+/// completed as far as all the matches agree and, if that adds nothing, the matches are listed
+fn complete_line(line: &mut String) {
+    // Only the name of the command is completed, the arguments can be anything
+    if line.contains(' ') {
+        return;
+    }
+
+    let matches = complete(line);
+
+    let (first, rest) = match matches.split_first() {
+        Some((first, rest)) => (*first, rest),
+        None => return
+    };
+
+    if rest.is_empty() {
+        let completed = alloc::format!("{} ", first);
+        replace_line(line, &completed);
+        return;
+    }
+
+    let common = rest.iter().fold(first.len(), |common, name| {
+        first.bytes().zip(name.bytes()).take(common).take_while(|(a, b)| a == b).count()
+    });
+
+    if common > line.len() {
+        replace_line(line, &first[..common]);
+        return;
+    }
+
+    println!();
+    println!("{}", matches.join("  "));
+    print!("{}{}", PROMPT, line);
+}
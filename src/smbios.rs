@@ -0,0 +1,267 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use x86_64::PhysAddr;
+use crate::memory::physical_to_virtual;
+use crate::utils::Mutex;
+
+/// The anchors the entry points start with, the one of SMBIOS 3.0 has 64 bits addresses
+const ENTRY_POINT_ANCHOR: &[u8; 4] = b"_SM_";
+const ENTRY_POINT_3_ANCHOR: &[u8; 5] = b"_SM3_";
+
+/// Where the BIOS leaves the entry point, on a 16 bytes boundary
+const SEARCH_START: u64 = 0xF0000;
+const SEARCH_END: u64 = 0x100000;
+
+/// The types of the structures parsed
+const TYPE_BIOS: u8 = 0;
+const TYPE_SYSTEM: u8 = 1;
+const TYPE_MEMORY_DEVICE: u8 = 17;
+const TYPE_END: u8 = 127;
+
+/// The size of a memory device that tells its size in the extended size field instead
+const MEMORY_SIZE_EXTENDED: u16 = 0x7FFF;
+const MEMORY_SIZE_UNKNOWN: u16 = 0xFFFF;
+
+/// Set in the size of a memory device when it's in KiB instead of MiB
+const MEMORY_SIZE_IN_KIB: u16 = 1 << 15;
+
+/// The information found by [`init`]
+static INFO: Mutex<Option<Smbios>> = Mutex::new(None);
+
+/// The firmware of the machine, from the structure of type 0
+#[derive(Debug, Clone)]
+pub struct BiosInfo {
+    pub vendor: String,
+    pub version: String,
+    pub release_date: String
+}
+
+/// The machine itself, from the structure of type 1
+#[derive(Debug, Clone)]
+pub struct SystemInfo {
+    pub manufacturer: String,
+    pub product: String,
+    pub version: String,
+    pub serial_number: String
+}
+
+/// A memory module (or a soldered bank), from a structure of type 17. The empty slots are left out
+#[derive(Debug, Clone)]
+pub struct MemoryDevice {
+    /// Where the module is, like `DIMM 0`
+    pub locator: String,
+    /// The size in KiB
+    pub size_kib: u64,
+    /// The type of the memory, see [`MemoryDevice::type_name`]
+    pub memory_type: u8,
+    /// The most MT/s the module can do, 0 if unknown
+    pub speed: u16,
+    pub manufacturer: String,
+    pub part_number: String
+}
+
+impl MemoryDevice {
+    /// Returns the name of the type of the memory, like `DDR4`
+    pub fn type_name(&self) -> &'static str {
+        match self.memory_type {
+            0x03 => "DRAM",
+            0x07 => "RAM",
+            0x0F => "SDRAM",
+            0x12 => "DDR",
+            0x13 => "DDR2",
+            0x18 => "DDR3",
+            0x1A => "DDR4",
+            0x1B => "LPDDR",
+            0x1C => "LPDDR2",
+            0x1D => "LPDDR3",
+            0x1E => "LPDDR4",
+            0x22 => "DDR5",
+            0x23 => "LPDDR5",
+            _ => "Other"
+        }
+    }
+}
+
+struct Smbios {
+    version: (u8, u8),
+    bios: Option<BiosInfo>,
+    system: Option<SystemInfo>,
+    memory_devices: Vec<MemoryDevice>
+}
+
+/// A structure of the table: its formatted area (starting with the header) and the strings that follow it
+struct Structure<'a> {
+    formatted: &'a [u8],
+    strings: &'a [u8]
+}
+
+impl Structure<'_> {
+    fn byte(&self, offset: usize) -> Option<u8> {
+        self.formatted.get(offset).copied()
+    }
+
+    fn word(&self, offset: usize) -> Option<u16> {
+        Some(u16::from_le_bytes([self.byte(offset)?, self.byte(offset + 1)?]))
+    }
+
+    fn dword(&self, offset: usize) -> Option<u32> {
+        Some((self.word(offset + 2)? as u32) << 16 | self.word(offset)? as u32)
+    }
+
+    /// Returns the string whose number (starting at 1) is the byte at `offset`, empty if there's none
+    fn string(&self, offset: usize) -> String {
+        let number = match self.byte(offset) {
+            Some(number) if number > 0 => number as usize,
+            _ => return String::new()
+        };
+
+        let string = self.strings.split(|&byte| byte == 0).nth(number - 1).unwrap_or(&[]);
+
+        // The strings are meant to be ASCII, whatever else is there is replaced
+        return string
+            .iter()
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '?' })
+            .collect();
+    }
+}
+
+/// Returns whatever the `length` bytes at `address` add up to 0, like the checksum of the entry points wants
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that the bytes are mapped
+unsafe fn checksum_valid(address: PhysAddr, length: usize) -> bool {
+    let bytes = core::slice::from_raw_parts(physical_to_virtual(address).as_ptr::<u8>(), length);
+    return bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0;
+}
+
+/// Finds the entry point where the BIOS left it, returning the version and where the table is and how long it is.
+/// The one of SMBIOS 3.0 is preferred, it can point above 4 GiB
+fn find_table() -> Option<((u8, u8), PhysAddr, usize)> {
+    let start = physical_to_virtual(PhysAddr::new(SEARCH_START)).as_ptr::<u8>();
+    let area = unsafe { core::slice::from_raw_parts(start, (SEARCH_END - SEARCH_START) as usize) };
+
+    let mut legacy = None;
+
+    for offset in (0..area.len() - 32).step_by(16) {
+        let entry = &area[offset..offset + 32];
+        let address = PhysAddr::new(SEARCH_START + offset as u64);
+
+        // The length of the entry point, which the checksum covers, is byte 6 for SMBIOS 3.0 and byte 5 before
+        if entry.starts_with(ENTRY_POINT_3_ANCHOR) && unsafe { checksum_valid(address, entry[6] as usize) } {
+            let table = u64::from_le_bytes(entry[0x10..0x18].try_into().unwrap());
+            let length = u32::from_le_bytes(entry[0x0C..0x10].try_into().unwrap());
+
+            return Some(((entry[7], entry[8]), PhysAddr::new(table), length as usize));
+        }
+
+        let legacy_entry = entry.starts_with(ENTRY_POINT_ANCHOR) && legacy.is_none();
+
+        if legacy_entry && unsafe { checksum_valid(address, entry[5] as usize) } {
+            let table = u32::from_le_bytes(entry[0x18..0x1C].try_into().unwrap());
+            let length = u16::from_le_bytes([entry[0x16], entry[0x17]]);
+
+            legacy = Some(((entry[6], entry[7]), PhysAddr::new(table as u64), length as usize));
+        }
+    }
+
+    return legacy;
+}
+
+/// Splits the table into its structures, stopping at the end structure
+fn structures(table: &[u8]) -> impl Iterator<Item = (u8, Structure<'_>)> {
+    let mut offset = 0;
+
+    core::iter::from_fn(move || {
+        let (kind, length) = (*table.get(offset)?, *table.get(offset + 1)? as usize);
+
+        if kind == TYPE_END || length < 4 || offset + length > table.len() {
+            return None;
+        }
+
+        // The strings go up to two zeros in a row, a structure without strings has just the two zeros
+        let strings_start = offset + length;
+        let strings_length = table[strings_start..].windows(2).position(|pair| pair == [0, 0])?;
+
+        let structure = Structure {
+            formatted: &table[offset..strings_start],
+            strings: &table[strings_start..strings_start + strings_length]
+        };
+
+        offset = strings_start + strings_length + 2;
+        Some((kind, structure))
+    })
+}
+
+fn parse_memory_device(structure: &Structure) -> Option<MemoryDevice> {
+    let size = structure.word(0x0C)?;
+
+    let size_kib = match size {
+        0 | MEMORY_SIZE_UNKNOWN => return None,
+        MEMORY_SIZE_EXTENDED => structure.dword(0x1C)? as u64 * 1024,
+        size if size & MEMORY_SIZE_IN_KIB != 0 => (size & !MEMORY_SIZE_IN_KIB) as u64,
+        size => size as u64 * 1024
+    };
+
+    Some(MemoryDevice {
+        locator: structure.string(0x10),
+        size_kib,
+        memory_type: structure.byte(0x12).unwrap_or(0),
+        speed: structure.word(0x15).unwrap_or(0),
+        manufacturer: structure.string(0x17),
+        part_number: structure.string(0x1A)
+    })
+}
+
+/// Finds the SMBIOS table and parses the structures about the firmware, the machine and its memory. Like the RSDP,
+/// the entry point is searched for in the memory of the BIOS, so it's only found on BIOS machines. Must be called
+/// once, after the memory is initialized
+pub fn init() {
+    let (version, address, length) = match find_table() {
+        Some(table) => table,
+        None => return
+    };
+
+    let table = unsafe { core::slice::from_raw_parts(physical_to_virtual(address).as_ptr::<u8>(), length) };
+    let mut info = Smbios { version, bios: None, system: None, memory_devices: Vec::new() };
+
+    for (kind, structure) in structures(table) {
+        match kind {
+            TYPE_BIOS => info.bios = Some(BiosInfo {
+                vendor: structure.string(0x04),
+                version: structure.string(0x05),
+                release_date: structure.string(0x08)
+            }),
+            TYPE_SYSTEM => info.system = Some(SystemInfo {
+                manufacturer: structure.string(0x04),
+                product: structure.string(0x05),
+                version: structure.string(0x06),
+                serial_number: structure.string(0x07)
+            }),
+            TYPE_MEMORY_DEVICE => info.memory_devices.extend(parse_memory_device(&structure)),
+            _ => {}
+        }
+    }
+
+    *INFO.lock() = Some(info);
+}
+
+/// Returns the version of the SMBIOS table found by [`init`]
+pub fn version() -> Option<(u8, u8)> {
+    INFO.lock().as_ref().map(|info| info.version)
+}
+
+/// Returns the information about the firmware found by [`init`]
+pub fn bios() -> Option<BiosInfo> {
+    INFO.lock().as_ref()?.bios.clone()
+}
+
+/// Returns the information about the machine found by [`init`]
+pub fn system() -> Option<SystemInfo> {
+    INFO.lock().as_ref()?.system.clone()
+}
+
+/// Returns the memory devices found by [`init`], empty if there's no table
+pub fn memory_devices() -> Vec<MemoryDevice> {
+    INFO.lock().as_ref().map(|info| info.memory_devices.clone()).unwrap_or_default()
+}
@@ -0,0 +1,382 @@
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use x86_64::instructions::port::Port;
+use crate::drivers::registry::Driver;
+use crate::memory::dma::DmaBuffer;
+use crate::pci::PciDevice;
+use crate::println;
+use crate::sched::WaitQueue;
+use crate::sync::SleepMutex;
+use crate::time;
+use crate::utils::error::KernelError;
+
+/// The Intel controllers with an AC'97 link, the first one is the one QEMU emulates
+const DEVICE_IDS: [(u16, u16); 7] = [
+    (0x8086, 0x2415),
+    (0x8086, 0x2425),
+    (0x8086, 0x2445),
+    (0x8086, 0x24C5),
+    (0x8086, 0x24D5),
+    (0x8086, 0x266E),
+    (0x8086, 0x27DE)
+];
+
+const PCI_COMMAND: u16 = 0x04;
+const PCI_COMMAND_IO: u32 = 1 << 0;
+const PCI_COMMAND_BUS_MASTER: u32 = 1 << 2;
+
+/// The BARs with the ports of the mixer of the codec (NAM) and of the bus masters (NABM)
+const PCI_MIXER_BAR: u16 = 0x10;
+const PCI_BUS_MASTER_BAR: u16 = 0x14;
+
+/// The registers of the mixer, as offsets from its first port
+const MIXER_RESET: u16 = 0x00;
+const MIXER_MASTER_VOLUME: u16 = 0x02;
+const MIXER_PCM_OUT_VOLUME: u16 = 0x18;
+const MIXER_EXTENDED_ID: u16 = 0x28;
+const MIXER_EXTENDED_CONTROL: u16 = 0x2A;
+const MIXER_FRONT_DAC_RATE: u16 = 0x2C;
+
+/// Set in the extended ID and control registers when the codec can play at another rate than [`FIXED_RATE`]
+const EXTENDED_VARIABLE_RATE: u16 = 1 << 0;
+
+/// The volumes written to the mixer: the loudest master volume and no gain on the PCM output
+const MASTER_VOLUME: u16 = 0x0000;
+const PCM_OUT_VOLUME: u16 = 0x0808;
+
+/// The registers of the PCM output bus master and the global ones, as offsets from the first port of the bus masters
+const PCM_OUT_LIST: u16 = 0x10;
+const PCM_OUT_CURRENT: u16 = 0x14;
+const PCM_OUT_LAST_VALID: u16 = 0x15;
+const PCM_OUT_STATUS: u16 = 0x16;
+const PCM_OUT_CONTROL: u16 = 0x1B;
+const GLOBAL_CONTROL: u16 = 0x2C;
+const GLOBAL_STATUS: u16 = 0x30;
+
+/// The bits of the control of a bus master: running, resetting its registers and the interrupts on the last valid
+/// buffer and on the completion of a buffer
+const CONTROL_RUN: u8 = 1 << 0;
+const CONTROL_RESET: u8 = 1 << 1;
+const CONTROL_LAST_VALID_INTERRUPT: u8 = 1 << 2;
+const CONTROL_COMPLETION_INTERRUPT: u8 = 1 << 4;
+
+/// The bits of the status of a bus master, the last three are cleared by writing 1 to them
+const STATUS_HALTED: u16 = 1 << 0;
+const STATUS_LAST_VALID: u16 = 1 << 2;
+const STATUS_COMPLETION: u16 = 1 << 3;
+const STATUS_FIFO_ERROR: u16 = 1 << 4;
+
+/// Takes the link out of the cold reset
+const GLOBAL_CONTROL_COLD_RESET: u32 = 1 << 1;
+
+/// Set in the global status once the primary codec is ready
+const GLOBAL_STATUS_CODEC_READY: u32 = 1 << 8;
+
+/// Makes the bus master raise its interrupt once it's done with the buffer of an entry
+const ENTRY_INTERRUPT: u16 = 1 << 15;
+
+/// How many entries the buffer descriptor list has, the bus master always goes through all of them in a ring
+const ENTRIES: usize = 32;
+
+/// How many samples the buffer of an entry has, each entry is a page
+const SAMPLES_PER_ENTRY: usize = 4096 / 2;
+
+/// The rate every codec can play at, the others need the variable rate
+const FIXED_RATE: u32 = 48000;
+
+/// How many times the global status is read while waiting for the codec to be ready, or the control while waiting
+/// for a reset to finish
+const RESET_ATTEMPTS: usize = 1_000_000;
+
+/// How long the bus master can take to be done with a buffer before the playback is considered stuck, an entry
+/// lasts 128 ms at 8000 Hz
+const BUFFER_TIMEOUT_MS: u64 = 1000;
+
+/// How often the status is checked when the controller has no interrupt
+const POLL_MS: u64 = 10;
+
+/// The first port of the bus masters of the controller, 0 until one is found. It's what the interrupt handler needs
+static BUS_MASTER: AtomicU16 = AtomicU16::new(0);
+
+/// Whatever the interrupt of the controller is registered, the playback is polled otherwise
+static INTERRUPTS: AtomicBool = AtomicBool::new(false);
+
+/// The thread playing samples, woken up every time the bus master is done with a buffer
+static WAITERS: WaitQueue = WaitQueue::new();
+
+/// The controller found by [`probe`], the lock makes the playbacks happen one after the other
+static CONTROLLER: SleepMutex<Option<Ac97>> = SleepMutex::new(None);
+
+/// An entry of the buffer descriptor list
+#[repr(C)]
+struct Entry {
+    address: u32,
+    /// How many samples the buffer has
+    samples: u16,
+    flags: u16
+}
+
+struct Ac97 {
+    mixer: u16,
+    bus_master: u16,
+    /// Whatever the codec can play at another rate than [`FIXED_RATE`]
+    variable_rate: bool,
+    /// The page of the buffer descriptor list
+    list: DmaBuffer,
+    /// The buffers of the entries, a page each
+    buffers: DmaBuffer
+}
+
+impl Ac97 {
+    fn read_mixer(&self, register: u16) -> u16 {
+        unsafe { Port::<u16>::new(self.mixer + register).read() }
+    }
+
+    fn write_mixer(&self, register: u16, value: u16) {
+        unsafe { Port::<u16>::new(self.mixer + register).write(value) };
+    }
+
+    fn status(&self) -> u16 {
+        unsafe { Port::<u16>::new(self.bus_master + PCM_OUT_STATUS).read() }
+    }
+
+    fn current_entry(&self) -> usize {
+        unsafe { Port::<u8>::new(self.bus_master + PCM_OUT_CURRENT).read() as usize }
+    }
+
+    fn set_control(&self, control: u8) {
+        unsafe { Port::<u8>::new(self.bus_master + PCM_OUT_CONTROL).write(control) };
+    }
+
+    /// Stops the PCM output and resets its registers
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Timeout`] if the reset never finishes
+    fn reset_output(&self) -> Result<(), KernelError> {
+        self.set_control(0);
+        self.set_control(CONTROL_RESET);
+
+        let mut control: Port<u8> = Port::new(self.bus_master + PCM_OUT_CONTROL);
+
+        if !(0..RESET_ATTEMPTS).any(|_| unsafe { control.read() } & CONTROL_RESET == 0) {
+            return Err(KernelError::Timeout);
+        }
+
+        unsafe { Port::<u32>::new(self.bus_master + PCM_OUT_LIST).write(self.list.physical(0).as_u64() as u32) };
+        Ok(())
+    }
+
+    /// Sets the rate the codec plays at
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Unsupported`] if the codec can't play at that rate
+    fn set_rate(&self, rate: u32) -> Result<(), KernelError> {
+        if !self.variable_rate {
+            return if rate == FIXED_RATE { Ok(()) } else { Err(KernelError::Unsupported) };
+        }
+
+        if rate > u16::MAX as u32 {
+            return Err(KernelError::Unsupported);
+        }
+
+        self.write_mixer(MIXER_FRONT_DAC_RATE, rate as u16);
+
+        // The codec rounds the rates it can't play to the closest one it can, which isn't good enough
+        if self.read_mixer(MIXER_FRONT_DAC_RATE) as u32 != rate {
+            return Err(KernelError::Unsupported);
+        }
+
+        Ok(())
+    }
+
+    /// Blocks the current thread until `condition` is true, the bus master is expected to change something
+    /// within [`BUFFER_TIMEOUT_MS`]
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Timeout`] if the condition is still false after that
+    fn wait(&self, mut condition: impl FnMut() -> bool) -> Result<(), KernelError> {
+        // Without the interrupt nothing wakes the thread up, the timeout is what makes it check again
+        let slice = if INTERRUPTS.load(Ordering::Acquire) { BUFFER_TIMEOUT_MS } else { POLL_MS };
+        let deadline = time::uptime_ms() + BUFFER_TIMEOUT_MS;
+
+        while time::uptime_ms() < deadline {
+            if WAITERS.wait_until_timeout(slice, &mut condition) {
+                return Ok(());
+            }
+        }
+
+        return Err(KernelError::Timeout);
+    }
+
+    /// Plays the interleaved stereo `samples` at `rate` frames per second, returning once all of them were played.
+    /// The entries are refilled while the bus master goes through the ring, so there's no gap between them
+    fn play(&self, samples: &[i16], rate: u32) -> Result<(), KernelError> {
+        self.set_rate(rate)?;
+        self.reset_output()?;
+
+        let list = self.list.page(0) as *mut Entry;
+        let mut running = false;
+
+        for (index, chunk) in samples.chunks(SAMPLES_PER_ENTRY).enumerate() {
+            let entry = index % ENTRIES;
+
+            // The entry after the last valid one is free unless it's the one being played, which means the ring is full
+            if running {
+                self.wait(|| self.status() & STATUS_HALTED != 0 || self.current_entry() != entry)?;
+            }
+
+            let buffer = self.buffers.page(entry) as *mut i16;
+
+            unsafe {
+                core::ptr::copy_nonoverlapping(chunk.as_ptr(), buffer, chunk.len());
+
+                let descriptor = Entry {
+                    address: self.buffers.physical(entry).as_u64() as u32,
+                    samples: chunk.len() as u16,
+                    flags: ENTRY_INTERRUPT
+                };
+
+                core::ptr::write_volatile(list.add(entry), descriptor);
+
+                // Moving the last valid entry also restarts a bus master that ran out of entries
+                Port::<u8>::new(self.bus_master + PCM_OUT_LAST_VALID).write(entry as u8);
+            }
+
+            if !running {
+                self.set_control(CONTROL_RUN | CONTROL_LAST_VALID_INTERRUPT | CONTROL_COMPLETION_INTERRUPT);
+                running = true;
+            }
+        }
+
+        // The bus master halts once it's done with the last valid entry
+        let played = self.wait(|| self.status() & STATUS_HALTED != 0);
+
+        self.set_control(0);
+        return played;
+    }
+}
+
+/// Called by the interrupt handler of the IRQ line of the controller, it acknowledges the interrupt and wakes up
+/// the thread playing samples
+///
+/// ## Note
+///
+/// This function must not allocate or block since it runs inside an interrupt handler
+fn handle_interrupt() {
+    let bus_master = BUS_MASTER.load(Ordering::Acquire);
+
+    if bus_master == 0 {
+        return;
+    }
+
+    let mut status: Port<u16> = Port::new(bus_master + PCM_OUT_STATUS);
+    let pending = unsafe { status.read() } & (STATUS_LAST_VALID | STATUS_COMPLETION | STATUS_FIFO_ERROR);
+
+    // The line may be shared with another device
+    if pending == 0 {
+        return;
+    }
+
+    unsafe { status.write(pending) };
+    WAITERS.wake_all();
+}
+
+/// Returns the base of the I/O ports of the BAR at `register`, or `None` if it doesn't have ports
+fn io_bar(pci: &PciDevice, register: u16) -> Option<u16> {
+    let bar = pci.read_config(register);
+
+    // Bit 0 tells whatever the BAR has I/O ports (instead of memory)
+    if bar & 1 == 0 || bar & 0xFFFC == 0 {
+        return None;
+    }
+
+    return Some((bar & 0xFFFC) as u16);
+}
+
+/// The driver bound to the AC'97 controller
+struct Ac97Driver;
+
+impl Driver for Ac97Driver {
+    fn name(&self) -> &'static str {
+        "ac97"
+    }
+}
+
+/// Binds to `pci` if it's an AC'97 controller and none was found before it: resets the codec, turns the volume
+/// up and allocates the ring of buffers the samples are played from
+pub fn probe(pci: &PciDevice) -> Option<Box<dyn Driver>> {
+    if !DEVICE_IDS.contains(&(pci.vendor_id, pci.device_id)) || BUS_MASTER.load(Ordering::Acquire) != 0 {
+        return None;
+    }
+
+    let (mixer, bus_master) = (io_bar(pci, PCI_MIXER_BAR)?, io_bar(pci, PCI_BUS_MASTER_BAR)?);
+
+    pci.write_config(PCI_COMMAND, pci.read_config(PCI_COMMAND) | PCI_COMMAND_IO | PCI_COMMAND_BUS_MASTER);
+
+    unsafe {
+        let mut control: Port<u32> = Port::new(bus_master + GLOBAL_CONTROL);
+        let value = control.read();
+
+        control.write(value | GLOBAL_CONTROL_COLD_RESET);
+    }
+
+    let mut global_status: Port<u32> = Port::new(bus_master + GLOBAL_STATUS);
+
+    if !(0..RESET_ATTEMPTS).any(|_| unsafe { global_status.read() } & GLOBAL_STATUS_CODEC_READY != 0) {
+        println!("AC'97: the codec never became ready");
+        return None;
+    }
+
+    let (list, buffers) = match (DmaBuffer::new(1), DmaBuffer::new(ENTRIES)) {
+        (Ok(list), Ok(buffers)) => (list, buffers),
+        (Err(error), _) | (_, Err(error)) => {
+            println!("AC'97: failed to allocate the buffers: {:?}", error);
+            return None;
+        }
+    };
+
+    let mut controller = Ac97 { mixer, bus_master, variable_rate: false, list, buffers };
+
+    // Any value written to the reset register resets the mixer to its defaults, which mute everything
+    controller.write_mixer(MIXER_RESET, 0);
+    controller.write_mixer(MIXER_MASTER_VOLUME, MASTER_VOLUME);
+    controller.write_mixer(MIXER_PCM_OUT_VOLUME, PCM_OUT_VOLUME);
+
+    if controller.read_mixer(MIXER_EXTENDED_ID) & EXTENDED_VARIABLE_RATE != 0 {
+        let control = controller.read_mixer(MIXER_EXTENDED_CONTROL);
+
+        controller.write_mixer(MIXER_EXTENDED_CONTROL, control | EXTENDED_VARIABLE_RATE);
+        controller.variable_rate = true;
+    }
+
+    if let Err(error) = controller.reset_output() {
+        println!("AC'97: failed to reset the PCM output: {:?}", error);
+        return None;
+    }
+
+    BUS_MASTER.store(bus_master, Ordering::Release);
+
+    // 0xFF means the firmware didn't route the interrupt anywhere, then the playback is polled
+    let irq = pci.read_config(0x3C) as u8;
+
+    if irq < 16 && crate::interrupts::interrupt_manager::register_irq(irq, handle_interrupt).is_ok() {
+        INTERRUPTS.store(true, Ordering::Release);
+    }
+
+    let rates = if controller.variable_rate { "variable rate" } else { "48000 Hz only" };
+    println!("AC'97: controller at ports {:#x}/{:#x} ({})", mixer, bus_master, rates);
+
+    *CONTROLLER.lock() = Some(controller);
+    return Some(Box::new(Ac97Driver));
+}
+
+/// Plays the interleaved stereo `samples` at `rate` frames per second, see [`crate::sound::play_pcm`]
+pub fn play_pcm(samples: &[i16], rate: u32) -> Result<(), KernelError> {
+    let controller = CONTROLLER.lock();
+    let controller = controller.as_ref().ok_or(KernelError::NoDevice)?;
+
+    return controller.play(samples, rate);
+}
@@ -0,0 +1,26 @@
+pub mod ac97;
+
+use crate::drivers::registry;
+use crate::utils::error::KernelError;
+
+/// Registers the probes of the audio controllers. Must be called once, before [`registry::probe_all`]
+pub fn init() {
+    registry::register(ac97::probe);
+}
+
+/// Plays `samples` at `rate` frames per second on the audio controller, blocking the current thread until they
+/// were all played. The samples are signed 16 bits ones for the left and the right channel one after the other
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if a sample of the last frame is missing, [`KernelError::NoDevice`] if
+/// there's no audio controller, [`KernelError::Unsupported`] if it can't play at that rate and
+/// [`KernelError::Timeout`] if the playback gets stuck
+#[allow(dead_code)]
+pub fn play_pcm(samples: &[i16], rate: u32) -> Result<(), KernelError> {
+    if samples.len() % 2 != 0 {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    return ac97::play_pcm(samples, rate);
+}
@@ -0,0 +1,4 @@
+mod sleep_mutex;
+
+#[allow(unused_imports)]
+pub use sleep_mutex::{SleepMutex, SleepMutexGuard};
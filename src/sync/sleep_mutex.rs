@@ -0,0 +1,123 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use crate::sched::WaitQueue;
+use crate::task::thread::Thread;
+
+/// A mutual exclusion lock that blocks the threads waiting for it instead of spinning.
+///
+/// Meant for long critical sections (like updating the metadata of a filesystem), where spinning would waste the
+/// time slices of the waiters, or keep the CPU with interrupts disabled for too long. Since waiting blocks the thread,
+/// this lock must never be taken by an interrupt handler, use [`crate::utils::Mutex`] or
+/// [`crate::utils::IrqCell`] for the state shared with them.
+///
+/// A thread waiting for the lock lends its priority to the holder until the lock is released (priority inheritance),
+/// so a holder with a low priority can't keep a waiter with a high one blocked for long. The holder must not exit
+/// while holding the lock
+pub struct SleepMutex<T> {
+    locked: AtomicBool,
+    /// The thread holding the lock, null while it's free
+    owner: AtomicPtr<Thread>,
+    /// The threads waiting for the lock to be released
+    waiters: WaitQueue,
+    data: UnsafeCell<T>
+}
+
+unsafe impl<T: Send> Sync for SleepMutex<T> {}
+unsafe impl<T: Send> Send for SleepMutex<T> {}
+
+#[allow(dead_code)]
+impl<T> SleepMutex<T> {
+    pub const fn new(data: T) -> Self {
+        SleepMutex {
+            locked: AtomicBool::new(false),
+            owner: AtomicPtr::new(ptr::null_mut()),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data)
+        }
+    }
+
+    /// Acquires the lock, blocking the current thread until it becomes available
+    pub fn lock(&self) -> SleepMutexGuard<'_, T> {
+        // The lock is usually free, so don't bother with the wait queue unless it isn't
+        if !self.try_acquire() {
+            self.waiters.wait_until(|| self.try_acquire() || self.boost_owner());
+        }
+
+        return SleepMutexGuard { mutex: self };
+    }
+
+    /// Tries to acquire the lock a single time, returning [`None`] if it's already held
+    pub fn try_lock(&self) -> Option<SleepMutexGuard<'_, T>> {
+        if !self.try_acquire() {
+            return None;
+        }
+
+        return Some(SleepMutexGuard { mutex: self });
+    }
+
+    /// Returns whatever the lock is currently held by someone
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    /// Returns a mutable reference to the data, this is safe since the `&mut self` guarantees no one else can access it
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    fn try_acquire(&self) -> bool {
+        if self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            return false;
+        }
+
+        self.owner.store(crate::sched::current_thread().as_ptr(), Ordering::Relaxed);
+        return true;
+    }
+
+    /// Lends the priority of the current thread to the holder of the lock, always returns `false`
+    /// so it can be chained to a failed attempt at taking the lock
+    fn boost_owner(&self) -> bool {
+        // The owner can't exit while holding the lock, and it's set before anyone can see the lock taken
+        // except for the moment between both stores, when there's no one to boost yet
+        if let Some(owner) = ptr::NonNull::new(self.owner.load(Ordering::Relaxed)) {
+            let priority = unsafe { crate::sched::current_thread().as_ref().priority() };
+            crate::sched::boost(owner, priority);
+        }
+
+        return false;
+    }
+}
+
+/// The guard returned by [`SleepMutex::lock`], the lock is released (and the next waiter woken up) once it's dropped
+pub struct SleepMutexGuard<'a, T> {
+    mutex: &'a SleepMutex<T>
+}
+
+impl<T> Deref for SleepMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for SleepMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for SleepMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.owner.store(ptr::null_mut(), Ordering::Relaxed);
+        self.mutex.locked.store(false, Ordering::Release);
+
+        // Any boost was lent to get this lock released, a thread holding more than one lock loses the boosts of
+        // the others too, which at worst brings the inversion back until it releases them
+        crate::sched::restore_current_priority();
+
+        self.mutex.waiters.wake_one();
+    }
+}
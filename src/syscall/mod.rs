@@ -0,0 +1,1069 @@
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::VirtAddr;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use crate::fs::{ACCESS_EXECUTE, ACCESS_READ, ACCESS_WRITE};
+use crate::fs::{DirEntry, FileHandle, Metadata, NodeKind, OpenNode, SeekFrom};
+use crate::interrupts::interrupt_manager;
+use crate::memory::address_space::{self, UnmappedFrame};
+use crate::net::Ipv4Address;
+use crate::net::socket::{Socket, SocketAddress, AF_INET};
+use crate::process::ProcessId;
+use crate::process::credentials::Credentials;
+use crate::process::fd::{File, VfsFile};
+use crate::process::signal::Signal;
+use crate::usermode::UserRegisters;
+use crate::utils::error::KernelError;
+
+/// The number of a system call, given in `rax`
+pub const SYS_EXIT: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_YIELD: u64 = 2;
+pub const SYS_SLEEP_MS: u64 = 3;
+pub const SYS_FORK: u64 = 4;
+pub const SYS_EXEC: u64 = 5;
+pub const SYS_SHM_CREATE: u64 = 6;
+pub const SYS_SHM_MAP: u64 = 7;
+pub const SYS_SHM_UNMAP: u64 = 8;
+pub const SYS_KILL: u64 = 9;
+pub const SYS_SIGACTION: u64 = 10;
+pub const SYS_SIGRETURN: u64 = 11;
+pub const SYS_OPEN: u64 = 12;
+pub const SYS_CLOSE: u64 = 13;
+pub const SYS_READ: u64 = 14;
+pub const SYS_LSEEK: u64 = 15;
+pub const SYS_DUP: u64 = 16;
+pub const SYS_CHDIR: u64 = 17;
+pub const SYS_READDIR: u64 = 18;
+pub const SYS_STAT: u64 = 19;
+pub const SYS_FSTAT: u64 = 20;
+pub const SYS_GETUID: u64 = 21;
+pub const SYS_GETGID: u64 = 22;
+pub const SYS_SETUID: u64 = 23;
+pub const SYS_SETGID: u64 = 24;
+pub const SYS_CHMOD: u64 = 25;
+pub const SYS_CHOWN: u64 = 26;
+pub const SYS_MMAP: u64 = 27;
+pub const SYS_MUNMAP: u64 = 28;
+pub const SYS_EXEC_FILE: u64 = 29;
+pub const SYS_PIPE: u64 = 30;
+pub const SYS_SOCKET: u64 = 31;
+pub const SYS_BIND: u64 = 32;
+pub const SYS_CONNECT: u64 = 33;
+pub const SYS_SEND: u64 = 34;
+pub const SYS_RECV: u64 = 35;
+
+/// The flags of `open`, they have the values of Linux. The low two bits are what the file is opened for
+pub const O_RDONLY: u64 = 0;
+pub const O_WRONLY: u64 = 1;
+pub const O_RDWR: u64 = 2;
+pub const O_ACCMODE: u64 = 3;
+pub const O_CREAT: u64 = 0x40;
+pub const O_TRUNC: u64 = 0x200;
+pub const O_APPEND: u64 = 0x400;
+
+/// What the pages mapped by `mmap` can be used for and how they're shared, they have the values of Linux
+pub const PROT_READ: u64 = 1;
+pub const PROT_WRITE: u64 = 2;
+pub const PROT_EXEC: u64 = 4;
+pub const MAP_SHARED: u64 = 0x01;
+pub const MAP_PRIVATE: u64 = 0x02;
+pub const MAP_FIXED: u64 = 0x10;
+
+/// Where `lseek` counts the offset from
+pub const SEEK_SET: u64 = 0;
+pub const SEEK_CUR: u64 = 1;
+pub const SEEK_END: u64 = 2;
+
+/// The types of the entries given by `readdir`, they have the values of Linux
+pub const DT_FIFO: u8 = 1;
+pub const DT_CHR: u8 = 2;
+pub const DT_DIR: u8 = 4;
+pub const DT_REG: u8 = 8;
+pub const DT_LNK: u8 = 10;
+
+/// The bytes before the name in an entry given by `readdir`: its size, its length and its type
+const DIRENT_HEADER_SIZE: usize = 11;
+
+/// The types of the nodes in the mode given by `stat`, in its bits 15-12. They have the values of Linux
+pub const S_IFIFO: u32 = 0o010000;
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFLNK: u32 = 0o120000;
+
+/// The size of what `stat` writes
+const STAT_SIZE: u64 = 40;
+
+/// The size of the addresses the socket system calls take and give, the `sockaddr_in` of Linux: the family, the port
+/// in big-endian, the address then 8 unused bytes
+const SOCKADDR_SIZE: u64 = 16;
+
+/// The longest path the system calls take
+const MAX_PATH_LENGTH: u64 = 4096;
+
+/// The most bytes of a buffer the system calls copy through the kernel at once, they go through a page of their own
+/// since the heap is too small (see [`UnmappedFrame`])
+const BOUNCE_SIZE: usize = 4096;
+
+/// A system call handler, it gets the registers of the caller and returns the value left in `rax`
+type SyscallHandler = fn(&SyscallFrame) -> Result<u64, KernelError>;
+
+/// How many entries [`SYSCALL_TABLE`] has, one more than the highest system call number
+const SYSCALL_COUNT: usize = 36;
+
+/// The handlers of every system call, indexed by their number
+static SYSCALL_TABLE: [SyscallHandler; SYSCALL_COUNT] = {
+    let mut table: [SyscallHandler; SYSCALL_COUNT] = [sys_unknown; SYSCALL_COUNT];
+
+    table[SYS_EXIT as usize] = sys_exit;
+    table[SYS_WRITE as usize] = sys_write;
+    table[SYS_YIELD as usize] = sys_yield;
+    table[SYS_SLEEP_MS as usize] = sys_sleep_ms;
+    table[SYS_FORK as usize] = sys_fork;
+    table[SYS_EXEC as usize] = sys_exec;
+    table[SYS_SHM_CREATE as usize] = sys_shm_create;
+    table[SYS_SHM_MAP as usize] = sys_shm_map;
+    table[SYS_SHM_UNMAP as usize] = sys_shm_unmap;
+    table[SYS_KILL as usize] = sys_kill;
+    table[SYS_SIGACTION as usize] = sys_sigaction;
+    table[SYS_SIGRETURN as usize] = sys_sigreturn;
+    table[SYS_OPEN as usize] = sys_open;
+    table[SYS_CLOSE as usize] = sys_close;
+    table[SYS_READ as usize] = sys_read;
+    table[SYS_LSEEK as usize] = sys_lseek;
+    table[SYS_DUP as usize] = sys_dup;
+    table[SYS_CHDIR as usize] = sys_chdir;
+    table[SYS_READDIR as usize] = sys_readdir;
+    table[SYS_STAT as usize] = sys_stat;
+    table[SYS_FSTAT as usize] = sys_fstat;
+    table[SYS_GETUID as usize] = sys_getuid;
+    table[SYS_GETGID as usize] = sys_getgid;
+    table[SYS_SETUID as usize] = sys_setuid;
+    table[SYS_SETGID as usize] = sys_setgid;
+    table[SYS_CHMOD as usize] = sys_chmod;
+    table[SYS_CHOWN as usize] = sys_chown;
+    table[SYS_MMAP as usize] = sys_mmap;
+    table[SYS_MUNMAP as usize] = sys_munmap;
+    table[SYS_EXEC_FILE as usize] = sys_exec_file;
+    table[SYS_PIPE as usize] = sys_pipe;
+    table[SYS_SOCKET as usize] = sys_socket;
+    table[SYS_BIND as usize] = sys_bind;
+    table[SYS_CONNECT as usize] = sys_connect;
+    table[SYS_SEND as usize] = sys_send;
+    table[SYS_RECV as usize] = sys_recv;
+
+    table
+};
+
+/// The top of the kernel stack of the running thread, the entry switches to it before doing anything else
+#[no_mangle]
+static SYSCALL_KERNEL_STACK: AtomicU64 = AtomicU64::new(0);
+
+/// Where the entry keeps the user stack pointer until it's saved on the kernel stack,
+/// only touched while interrupts are disabled
+#[no_mangle]
+static SYSCALL_USER_STACK: AtomicU64 = AtomicU64::new(0);
+
+/// The registers saved by the entry, exactly in the order they are stored in the kernel stack
+#[repr(C)]
+struct SyscallFrame {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    rbp: u64,
+    rbx: u64,
+    r9: u64,
+    r8: u64,
+    r10: u64,
+    rdx: u64,
+    rsi: u64,
+    rdi: u64,
+    /// The number of the system call, and the result once it returns
+    rax: u64,
+    /// The user RFLAGS, saved by `syscall`
+    r11: u64,
+    /// The user return address, saved by `syscall`
+    rcx: u64,
+    user_rsp: u64
+}
+
+impl SyscallFrame {
+    /// The six arguments of the call, in order
+    fn arguments(&self) -> [u64; 6] {
+        return [self.rdi, self.rsi, self.rdx, self.r10, self.r8, self.r9];
+    }
+
+    /// The whole user state of the caller, as it is right after the `syscall` instruction
+    fn user_registers(&self) -> UserRegisters {
+        UserRegisters {
+            rax: self.rax,
+            rbx: self.rbx,
+            rcx: self.rcx,
+            rdx: self.rdx,
+            rsi: self.rsi,
+            rdi: self.rdi,
+            rbp: self.rbp,
+            r8: self.r8,
+            r9: self.r9,
+            r10: self.r10,
+            r11: self.r11,
+            r12: self.r12,
+            r13: self.r13,
+            r14: self.r14,
+            r15: self.r15,
+            rip: self.rcx,
+            rsp: self.user_rsp,
+            rflags: self.r11
+        }
+    }
+}
+
+// `syscall` doesn't switch stacks by itself, so the entry swaps the user stack for the kernel stack of the thread before
+// saving anything. Interrupts are disabled on entry (see `init`), which keeps the scratch slot safe until then.
+//
+// The arguments come in `rdi`, `rsi`, `rdx`, `r10`, `r8` and `r9` like on Linux. The callee-saved registers are saved
+// too, since `fork` needs the whole user state. The kernel stack top is page aligned, so after the 16 pushes
+// the stack is aligned to 16 bytes as required for the call
+global_asm!(r#"
+.global syscall_entry
+syscall_entry:
+    mov [rip + SYSCALL_USER_STACK], rsp
+    mov rsp, [rip + SYSCALL_KERNEL_STACK]
+
+    push qword ptr [rip + SYSCALL_USER_STACK]
+    push rcx
+    push r11
+    push rax
+    push rdi
+    push rsi
+    push rdx
+    push r10
+    push r8
+    push r9
+    push rbx
+    push rbp
+    push r12
+    push r13
+    push r14
+    push r15
+
+    mov rdi, rsp
+    call syscall_handler
+
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbp
+    pop rbx
+    pop r9
+    pop r8
+    pop r10
+    pop rdx
+    pop rsi
+    pop rdi
+    pop rax
+    pop r11
+    pop rcx
+    pop rsp
+    sysretq
+"#);
+
+extern "C" {
+    fn syscall_entry();
+}
+
+/// Enables the `syscall` instruction and points it to the entry, must be called after
+/// [`interrupt_manager::init`] since it needs the selectors of the GDT
+pub fn init() {
+    let (kernel_code, kernel_data) = interrupt_manager::kernel_selectors();
+    let (user_code, user_data) = interrupt_manager::user_selectors();
+
+    Star::write(user_code, user_data, kernel_code, kernel_data).expect("The GDT layout doesn't fit syscall/sysret");
+    LStar::write(VirtAddr::new(syscall_entry as *const () as u64));
+
+    // Flags cleared when entering the kernel, so the entry runs with interrupts disabled until it's on the kernel stack
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG);
+
+    unsafe {
+        Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS));
+    }
+}
+
+/// Sets the stack the entry switches to, called by [`interrupt_manager::set_kernel_stack`] on every context switch
+pub(crate) fn set_kernel_stack(stack_top: VirtAddr) {
+    SYSCALL_KERNEL_STACK.store(stack_top.as_u64(), Ordering::Relaxed);
+}
+
+/// Called by the entry with the registers of the user, the result is left in `rax`
+#[no_mangle]
+extern "C" fn syscall_handler(frame: &mut SyscallFrame) {
+    // The call runs on the kernel stack of the thread, so it can be preempted (or block) like any other kernel code
+    x86_64::instructions::interrupts::enable();
+
+    let result = match SYSCALL_TABLE.get(frame.rax as usize) {
+        Some(handler) => handler(frame),
+        None => Err(KernelError::Unsupported)
+    };
+
+    let result = match result {
+        Ok(value) => value,
+        Err(error) => error.code() as u64
+    };
+
+    // The signals are delivered on the way back to user mode, a handler needs every register to be restored later
+    // so the call returns through `iretq` instead of `sysret` in that case
+    let mut registers = frame.user_registers();
+    registers.rax = result;
+
+    if crate::process::signal::deliver(&mut registers) {
+        unsafe { crate::usermode::resume(&registers) };
+    }
+
+    // `sysret` faults in the kernel, on the stack of the user, when the address after the call isn't canonical. A
+    // program gets there by making the call from the very end of the lower half, `resume` terminates it instead
+    if !address_space::is_user_memory(frame.rcx, 1) {
+        unsafe { crate::usermode::resume(&registers) };
+    }
+
+    x86_64::instructions::interrupts::disable();
+
+    frame.rax = result;
+}
+
+/// Copies the bytes at `address` in the memory of the calling process to `buffer`, see
+/// [`crate::process::Process::copy_from_user`]. The kernel never touches user memory through the pointers it's given,
+/// another thread could unmap it meanwhile
+fn copy_from_user(address: u64, buffer: &mut [u8]) -> Result<(), KernelError> {
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+    return process.copy_from_user(address, buffer);
+}
+
+/// Copies `data` to `address` in the memory of the calling process, see [`crate::process::Process::copy_to_user`]
+fn copy_to_user(address: u64, data: &[u8]) -> Result<(), KernelError> {
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+    return process.copy_to_user(address, data);
+}
+
+/// Checks that the calling process can access the `length` bytes at `address` (and write to them if `write` is set),
+/// so a call fails before doing anything it can't undo (like taking a datagram) if a buffer is wrong
+fn check_user_range(address: u64, length: u64, write: bool) -> Result<(), KernelError> {
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+    return process.check_user_range(address, length, write);
+}
+
+/// Returns the path of `length` bytes at `address` in user memory
+fn user_path(address: u64, length: u64) -> Result<String, KernelError> {
+    if length > MAX_PATH_LENGTH {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let mut bytes = vec![0; length as usize];
+    copy_from_user(address, &mut bytes)?;
+
+    return String::from_utf8(bytes).map_err(|_| KernelError::InvalidArgument);
+}
+
+/// Returns the file the calling process has open at `fd`
+fn user_file(fd: u64) -> Result<Arc<dyn File>, KernelError> {
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+    let file = process.files().get(fd as usize).ok_or(KernelError::InvalidArgument)?;
+
+    return Ok(file);
+}
+
+/// Returns the socket open at `fd` in the calling process
+fn user_socket(fd: u64) -> Result<Arc<dyn File>, KernelError> {
+    let file = user_file(fd)?;
+
+    if !file.as_any().is::<Socket>() {
+        return Err(KernelError::NotSocket);
+    }
+
+    return Ok(file);
+}
+
+/// Reads the socket address of `length` bytes at `address` in user memory, see [`SOCKADDR_SIZE`]
+fn user_socket_address(address: u64, length: u64) -> Result<SocketAddress, KernelError> {
+    if length < SOCKADDR_SIZE {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let mut bytes = [0; SOCKADDR_SIZE as usize];
+    copy_from_user(address, &mut bytes)?;
+
+    if u16::from_le_bytes([bytes[0], bytes[1]]) != AF_INET {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    Ok(SocketAddress {
+        address: Ipv4Address(bytes[4..8].try_into().unwrap()),
+        port: u16::from_be_bytes([bytes[2], bytes[3]])
+    })
+}
+
+/// Used for the numbers without a system call
+fn sys_unknown(_frame: &SyscallFrame) -> Result<u64, KernelError> {
+    return Err(KernelError::Unsupported);
+}
+
+/// `exit(code)`: terminates the calling thread
+fn sys_exit(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    crate::task::exit(frame.arguments()[0] as i32);
+}
+
+/// `write(fd, buffer, length)`: writes the buffer to the file open at `fd`, returning how many bytes were written.
+/// The standard output of a program is the console, which takes UTF-8 text. The buffer goes through the kernel a
+/// page at a time (see [`BOUNCE_SIZE`]), a failure after the first page ends the call with what was written
+fn sys_write(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+    let (address, length) = (arguments[1], arguments[2]);
+
+    let file = user_file(arguments[0])?;
+    check_user_range(address, length, false)?;
+
+    let mut bounce = UnmappedFrame::new()?;
+    let mut written = 0;
+
+    while written < length {
+        let mut chunk = (length - written).min(BOUNCE_SIZE as u64) as usize;
+        let buffer = &mut bounce.bytes()[..chunk];
+
+        if let Err(error) = copy_from_user(address + written, buffer) {
+            return if written > 0 { Ok(written) } else { Err(error) };
+        }
+
+        // A character cut by the end of the page goes with the next one, the console only takes whole characters
+        if written + (chunk as u64) < length {
+            if let Err(error) = core::str::from_utf8(buffer) {
+                if error.error_len().is_none() && error.valid_up_to() > 0 {
+                    chunk = error.valid_up_to();
+                }
+            }
+        }
+
+        let count = match file.write(&buffer[..chunk]) {
+            Ok(count) => count,
+            Err(_) if written > 0 => break,
+            Err(error) => return Err(error)
+        };
+
+        written += count as u64;
+
+        if count < chunk {
+            break;
+        }
+    }
+
+    return Ok(written);
+}
+
+/// `read(fd, buffer, length)`: reads from the file open at `fd` into the buffer, returning how many bytes were read.
+/// 0 is returned at the end of the file. At most a page (see [`BOUNCE_SIZE`]) is read at a time
+fn sys_read(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+    let file = user_file(arguments[0])?;
+
+    if arguments[2] == 0 {
+        return Ok(0);
+    }
+
+    // The buffer is checked before reading, so the data isn't lost to a bad pointer
+    check_user_range(arguments[1], arguments[2], true)?;
+
+    let mut bounce = UnmappedFrame::new()?;
+    let buffer = &mut bounce.bytes()[..arguments[2].min(BOUNCE_SIZE as u64) as usize];
+
+    let count = file.read(buffer)?;
+    copy_to_user(arguments[1], &buffer[..count])?;
+
+    return Ok(count as u64);
+}
+
+/// `open(path, length, flags)`: opens the node at the path for reading ([`O_RDONLY`]), writing ([`O_WRONLY`]) or
+/// both ([`O_RDWR`]), returning the lowest free file descriptor. A relative path starts from the working directory
+/// of the process. With [`O_CREAT`] a file is created if there's none, with [`O_TRUNC`] the file is emptied and
+/// with [`O_APPEND`] every write goes to the end of the file (see [`OpenNode::appending`]). The permissions of the
+/// node must allow what it's opened for, a directory can only be opened for reading
+fn sys_open(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+
+    let path = &user_path(arguments[0], arguments[1])?;
+    let flags = arguments[2];
+
+    let access = match flags & O_ACCMODE {
+        O_RDONLY => ACCESS_READ,
+        O_WRONLY => ACCESS_WRITE,
+        O_RDWR => ACCESS_READ | ACCESS_WRITE,
+        _ => return Err(KernelError::InvalidArgument)
+    };
+
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+
+    // The file created is opened for whatever was asked, even if its permissions don't allow it
+    let (fs, node) = match crate::fs::vfs::resolve_with_fs(path) {
+        Err(KernelError::NotFound) if flags & O_CREAT != 0 => crate::fs::vfs::create_with_fs(path, NodeKind::File)?,
+        found => {
+            let (fs, node) = found?;
+            crate::fs::vfs::check_access(node.as_ref(), access)?;
+            (fs, node)
+        }
+    };
+
+    if node.kind() == NodeKind::Directory && access & ACCESS_WRITE != 0 {
+        return Err(KernelError::IsDirectory);
+    }
+
+    if flags & O_TRUNC != 0 && node.kind() == NodeKind::File {
+        if access & ACCESS_WRITE == 0 {
+            return Err(KernelError::PermissionDenied);
+        }
+
+        node.truncate(0)?;
+    }
+
+    let handle: Arc<dyn FileHandle> = match node.kind() {
+        NodeKind::Directory => Arc::new(crate::fs::vfs::open_dir(path)?),
+        _ if flags & O_APPEND != 0 => Arc::new(OpenNode::appending(node).in_filesystem(fs)),
+        _ => Arc::new(OpenNode::new(node).in_filesystem(fs))
+    };
+
+    let file = Arc::new(VfsFile::new(handle, access));
+
+    return Ok(process.files().insert(file)? as u64);
+}
+
+/// `readdir(fd, buffer, length)`: reads the next entries of the directory open at `fd` into the buffer, returning
+/// how many bytes were written, 0 once every entry was read. Every entry starts with the size of the node (8 bytes),
+/// the length of the entry (2 bytes) and its type ([`DT_REG`], [`DT_DIR`], [`DT_LNK`] or [`DT_CHR`]), followed by
+/// the name ended by a NUL. The entries are 8 bytes aligned, the length of an entry is where the next one starts.
+/// At most a page of entries (see [`BOUNCE_SIZE`]) is read at a time
+fn sys_readdir(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+    let file = user_file(arguments[0])?;
+
+    check_user_range(arguments[1], arguments[2], true)?;
+
+    let mut bounce = UnmappedFrame::new()?;
+    let buffer = &mut bounce.bytes()[..arguments[2].min(BOUNCE_SIZE as u64) as usize];
+
+    let (mut written, mut full) = (0, false);
+
+    file.read_entries(&mut |entry: &DirEntry| {
+        let name = entry.name.as_bytes();
+        let length = (DIRENT_HEADER_SIZE + name.len() + 1 + 7) & !7;
+
+        if written + length > buffer.len() {
+            full = true;
+            return false;
+        }
+
+        let record = &mut buffer[written..written + length];
+
+        record[0..8].copy_from_slice(&entry.size.to_le_bytes());
+        record[8..10].copy_from_slice(&(length as u16).to_le_bytes());
+        record[10] = match entry.kind {
+            NodeKind::File => DT_REG,
+            NodeKind::Directory => DT_DIR,
+            NodeKind::Symlink => DT_LNK,
+            NodeKind::Device => DT_CHR,
+            NodeKind::Pipe => DT_FIFO
+        };
+        record[DIRENT_HEADER_SIZE..DIRENT_HEADER_SIZE + name.len()].copy_from_slice(name);
+        record[DIRENT_HEADER_SIZE + name.len()..].fill(0);
+
+        written += length;
+        true
+    })?;
+
+    // The next entry doesn't even fit alone
+    if written == 0 && full {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    copy_to_user(arguments[1], &buffer[..written])?;
+    return Ok(written as u64);
+}
+
+/// Writes `metadata` to the [`STAT_SIZE`] bytes of user memory at `address`: the size (8 bytes), the mode (4 bytes,
+/// the type like [`S_IFREG`] with the permissions), 4 bytes of padding, then the times of the last read, of the last
+/// write and of the creation (8 bytes each, in seconds since the Unix epoch, 0 when the filesystem doesn't keep them)
+fn write_stat(metadata: &Metadata, address: u64) -> Result<u64, KernelError> {
+    let mut buffer = [0; STAT_SIZE as usize];
+
+    let kind = match metadata.kind {
+        NodeKind::File => S_IFREG,
+        NodeKind::Directory => S_IFDIR,
+        NodeKind::Symlink => S_IFLNK,
+        NodeKind::Device => S_IFCHR,
+        NodeKind::Pipe => S_IFIFO
+    };
+
+    buffer[0..8].copy_from_slice(&metadata.size.to_le_bytes());
+    buffer[8..12].copy_from_slice(&(kind | metadata.mode as u32).to_le_bytes());
+    buffer[12..16].fill(0);
+    buffer[16..24].copy_from_slice(&metadata.accessed.to_le_bytes());
+    buffer[24..32].copy_from_slice(&metadata.modified.to_le_bytes());
+    buffer[32..40].copy_from_slice(&metadata.created.to_le_bytes());
+
+    copy_to_user(address, &buffer)?;
+    return Ok(0);
+}
+
+/// `stat(path, length, buffer)`: writes the metadata of the node at the path to the buffer, following the symbolic
+/// links. See [`write_stat`] for what's written
+fn sys_stat(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+    let metadata = crate::fs::vfs::metadata(&user_path(arguments[0], arguments[1])?)?;
+
+    return write_stat(&metadata, arguments[2]);
+}
+
+/// `fstat(fd, buffer)`: writes the metadata of the file open at `fd` to the buffer, like [`sys_stat`]
+fn sys_fstat(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+    let metadata = user_file(arguments[0])?.metadata()?;
+
+    return write_stat(&metadata, arguments[1]);
+}
+
+/// `close(fd)`: closes the file descriptor, the file itself is closed once no descriptor refers to it
+fn sys_close(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+    let file = process.files().remove(frame.arguments()[0] as usize).ok_or(KernelError::InvalidArgument)?;
+
+    // Closing the file can block (like flushing it), so it's done with the table unlocked
+    drop(file);
+    return Ok(0);
+}
+
+/// `lseek(fd, offset, whence)`: moves the offset of the file open at `fd` to `offset` bytes from the start
+/// ([`SEEK_SET`]), from where it is ([`SEEK_CUR`]) or from the end ([`SEEK_END`]), returning the new offset
+fn sys_lseek(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+
+    let position = match arguments[2] {
+        SEEK_SET => SeekFrom::Start(arguments[1]),
+        SEEK_CUR => SeekFrom::Current(arguments[1] as i64),
+        SEEK_END => SeekFrom::End(arguments[1] as i64),
+        _ => return Err(KernelError::InvalidArgument)
+    };
+
+    return user_file(arguments[0])?.seek(position);
+}
+
+/// `dup(fd)`: opens the file open at `fd` again at the lowest free file descriptor, both share the same offset
+fn sys_dup(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let file = user_file(frame.arguments()[0])?;
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+
+    return Ok(process.files().insert(file)? as u64);
+}
+
+/// `pipe(fds)`: creates a pipe (see [`crate::fs::pipe::pair`]) and writes the file descriptor of its reading end
+/// then the one of its writing end to `fds`, as two 32-bit numbers. The ends are passed on by `fork`, a pipeline
+/// closes its standard output (or input) and `dup`s an end to take its place
+fn sys_pipe(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let address = frame.arguments()[0];
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+
+    check_user_range(address, 8, true)?;
+
+    let (reader, writer) = crate::fs::pipe::pair()?;
+    let reader: Arc<dyn File> = Arc::new(VfsFile::new(Arc::new(reader), ACCESS_READ));
+    let writer: Arc<dyn File> = Arc::new(VfsFile::new(Arc::new(writer), ACCESS_WRITE));
+
+    let mut files = process.files();
+    let read_fd = files.insert(reader)?;
+
+    let write_fd = match files.insert(writer) {
+        Ok(fd) => fd,
+        Err(error) => {
+            files.remove(read_fd);
+            return Err(error);
+        }
+    };
+
+    // Copying may block, the table can't stay locked
+    drop(files);
+
+    let mut fds = [0; 8];
+    fds[0..4].copy_from_slice(&(read_fd as u32).to_le_bytes());
+    fds[4..8].copy_from_slice(&(write_fd as u32).to_le_bytes());
+
+    if let Err(error) = copy_to_user(address, &fds) {
+        let mut files = process.files();
+        let ends = (files.remove(read_fd), files.remove(write_fd));
+
+        // Closing the ends can block too
+        drop(files);
+        drop(ends);
+
+        return Err(error);
+    }
+
+    return Ok(0);
+}
+
+/// `socket(domain, type, protocol)`: creates a socket (see [`Socket`]) and returns its file descriptor. The domain
+/// must be [`AF_INET`], and only the datagram sockets of UDP are there for now
+fn sys_socket(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+
+    if arguments[0] != AF_INET as u64 {
+        return Err(KernelError::Unsupported);
+    }
+
+    let socket: Arc<dyn File> = Arc::new(Socket::new(arguments[1], arguments[2])?);
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+
+    return Ok(process.files().insert(socket)? as u64);
+}
+
+/// `bind(fd, address, length)`: binds the socket to the address and port at `address`, a `sockaddr_in` (see
+/// [`SOCKADDR_SIZE`]). A port of 0 gets a free one
+fn sys_bind(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+
+    let file = user_socket(arguments[0])?;
+    let local = user_socket_address(arguments[1], arguments[2])?;
+
+    file.as_any().downcast_ref::<Socket>().unwrap().bind(local)?;
+    return Ok(0);
+}
+
+/// `connect(fd, address, length)`: makes the address at `address` the peer of the socket, where `send` sends by
+/// default and the only address `recv` receives from
+fn sys_connect(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+
+    let file = user_socket(arguments[0])?;
+    let peer = user_socket_address(arguments[1], arguments[2])?;
+
+    file.as_any().downcast_ref::<Socket>().unwrap().connect(peer)?;
+    return Ok(0);
+}
+
+/// `send(fd, buffer, length, address, address_length)`: sends the `length` bytes at `buffer` in a datagram to the
+/// address at `address`, or to the peer of the socket if `address` is 0. Returns how many bytes were sent
+fn sys_send(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+
+    let file = user_socket(arguments[0])?;
+
+    let destination = match arguments[3] {
+        0 => None,
+        address => Some(user_socket_address(address, arguments[4])?)
+    };
+
+    // A datagram is sent whole, so it can't be bigger than what goes through the kernel at once
+    if arguments[2] > BOUNCE_SIZE as u64 {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let mut bounce = UnmappedFrame::new()?;
+    let data = &mut bounce.bytes()[..arguments[2] as usize];
+    copy_from_user(arguments[1], data)?;
+
+    let sent = file.as_any().downcast_ref::<Socket>().unwrap().send_to(data, destination)?;
+    return Ok(sent as u64);
+}
+
+/// `recv(fd, buffer, length, address, address_length)`: receives the next datagram into the `length` bytes at
+/// `buffer`, blocking until there's one, and returns the size of its data. What doesn't fit in the buffer (or in a
+/// page, see [`BOUNCE_SIZE`]) is lost. The address it came from is written to `address` unless it's 0
+fn sys_recv(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+
+    let file = user_socket(arguments[0])?;
+
+    // The buffers are checked before blocking, so the datagram isn't lost to a bad pointer
+    check_user_range(arguments[1], arguments[2], true)?;
+
+    let source_address = match arguments[3] {
+        0 => None,
+        _ if arguments[4] < SOCKADDR_SIZE => return Err(KernelError::InvalidArgument),
+        address => {
+            check_user_range(address, SOCKADDR_SIZE, true)?;
+            Some(address)
+        }
+    };
+
+    let mut bounce = UnmappedFrame::new()?;
+    let buffer = &mut bounce.bytes()[..arguments[2].min(BOUNCE_SIZE as u64) as usize];
+
+    let (size, source) = file.as_any().downcast_ref::<Socket>().unwrap().receive_from(buffer)?;
+    copy_to_user(arguments[1], &buffer[..size.min(buffer.len())])?;
+
+    if let Some(address) = source_address {
+        let mut bytes = [0; SOCKADDR_SIZE as usize];
+        bytes[0..2].copy_from_slice(&AF_INET.to_le_bytes());
+        bytes[2..4].copy_from_slice(&source.port.to_be_bytes());
+        bytes[4..8].copy_from_slice(&source.address.0);
+
+        copy_to_user(address, &bytes)?;
+    }
+
+    return Ok(size as u64);
+}
+
+/// `chdir(path, length)`: makes the directory at the path the working directory of the calling process, which must
+/// be able to execute it
+fn sys_chdir(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+
+    let path = &user_path(arguments[0], arguments[1])?;
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+
+    crate::fs::vfs::check_access(crate::fs::vfs::resolve(path)?.as_ref(), ACCESS_EXECUTE)?;
+
+    process.set_working_directory(path)?;
+    return Ok(0);
+}
+
+/// `getuid()`: returns the ID of the user the calling process acts as
+fn sys_getuid(_frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+    return Ok(process.credentials().uid as u64);
+}
+
+/// `getgid()`: returns the ID of the group the calling process acts as
+fn sys_getgid(_frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+    return Ok(process.credentials().gid as u64);
+}
+
+/// `setuid(uid)`: makes the calling process act as the user `uid`. Only root can become another user, and it can't
+/// come back once it did
+fn sys_setuid(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+    let uid = u32::try_from(frame.arguments()[0]).map_err(|_| KernelError::InvalidArgument)?;
+    let credentials = process.credentials();
+
+    if !credentials.is_root() && credentials.uid != uid {
+        return Err(KernelError::NotPermitted);
+    }
+
+    process.set_credentials(Credentials { uid, ..credentials });
+    return Ok(0);
+}
+
+/// `setgid(gid)`: makes the calling process act as the group `gid`, only root can change it
+fn sys_setgid(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+    let gid = u32::try_from(frame.arguments()[0]).map_err(|_| KernelError::InvalidArgument)?;
+    let credentials = process.credentials();
+
+    if !credentials.is_root() && credentials.gid != gid {
+        return Err(KernelError::NotPermitted);
+    }
+
+    process.set_credentials(Credentials { gid, ..credentials });
+    return Ok(0);
+}
+
+/// `chmod(path, length, mode)`: changes the permissions of the node at the path to the low 12 bits of `mode`, only
+/// its owner and root can
+fn sys_chmod(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+    let path = &user_path(arguments[0], arguments[1])?;
+
+    crate::fs::vfs::chmod(path, (arguments[2] & 0o7777) as u16)?;
+    return Ok(0);
+}
+
+/// `chown(path, length, uid, gid)`: gives the node at the path to the user `uid` and the group `gid`, only root can
+fn sys_chown(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+    let path = &user_path(arguments[0], arguments[1])?;
+
+    let uid = u32::try_from(arguments[2]).map_err(|_| KernelError::InvalidArgument)?;
+    let gid = u32::try_from(arguments[3]).map_err(|_| KernelError::InvalidArgument)?;
+
+    crate::fs::vfs::chown(path, uid, gid)?;
+    return Ok(0);
+}
+
+/// `yield()`: gives up the rest of the time slice
+fn sys_yield(_frame: &SyscallFrame) -> Result<u64, KernelError> {
+    crate::sched::yield_now();
+    return Ok(0);
+}
+
+/// `sleep_ms(ms)`: blocks the calling thread for at least the given amount of milliseconds
+fn sys_sleep_ms(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    crate::task::sleep_ms(frame.arguments()[0]);
+    return Ok(0);
+}
+
+/// `fork()`: creates a copy of the calling process, returning the ID of the child to the parent and 0 to the child.
+/// The memory of both processes is shared until one of them writes to it
+fn sys_fork(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+    let child = process.fork(&frame.user_registers())?;
+
+    return Ok(child.id().as_u64());
+}
+
+/// `exec(image, length)`: replaces the program of the calling process with the ELF executable in the given buffer,
+/// only returns on error. The process must have a single thread
+fn sys_exec(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+
+    // The image is read in place, `exec` fails before touching it unless the calling thread is the only one of the
+    // process, so nothing can unmap it meanwhile
+    process.check_user_range(arguments[0], arguments[1], false)?;
+    let image = unsafe { core::slice::from_raw_parts(arguments[0] as *const u8, arguments[1] as usize) };
+
+    let (entry, stack) = process.exec(image)?;
+
+    // The process is kept alive by its thread, the reference of the call can't stay on a stack that's abandoned
+    drop(process);
+
+    unsafe { crate::usermode::enter(entry, stack) };
+}
+
+/// `exec_file(path, length)`: replaces the program of the calling process with the ELF executable in the file at the
+/// path, which must be executable by the process. The segments are mapped from the file (see `mmap`), only returns on
+/// error. The process must have a single thread
+fn sys_exec_file(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+    let path = &user_path(arguments[0], arguments[1])?;
+
+    let (entry, stack) = process.exec_file(path)?;
+
+    // Same as `exec`, the reference can't stay on the abandoned stack
+    drop(process);
+
+    unsafe { crate::usermode::enter(entry, stack) };
+}
+
+/// `mmap(address, length, protection, flags, fd, offset)`: maps `length` bytes of the file open at `fd` from `offset`
+/// (page aligned), returning the address of the mapping. The file must be open for reading, its pages are only read
+/// once they're touched. With [`MAP_FIXED`] the mapping is at `address` (page aligned), which must be free, otherwise
+/// the kernel picks an address. With [`MAP_PRIVATE`] the writes go to pages of the process, [`MAP_SHARED`] can only
+/// be used without [`PROT_WRITE`] since the pages are never written back to the file
+fn sys_mmap(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+    let (address, length, protection, flags) = (arguments[0], arguments[1], arguments[2], arguments[3]);
+
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+
+    if length == 0 || protection & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let shared = match flags & !MAP_FIXED {
+        MAP_SHARED => true,
+        MAP_PRIVATE => false,
+        _ => return Err(KernelError::InvalidArgument)
+    };
+
+    if shared && protection & PROT_WRITE != 0 {
+        return Err(KernelError::Unsupported);
+    }
+
+    let address = match flags & MAP_FIXED {
+        0 => None,
+        _ => Some(VirtAddr::try_new(address).map_err(|_| KernelError::InvalidArgument)?)
+    };
+
+    let file = user_file(arguments[4])?;
+    let pages = length.checked_add(4095).ok_or(KernelError::InvalidArgument)? / 4096;
+
+    let writable = protection & PROT_WRITE != 0;
+    let executable = protection & PROT_EXEC != 0;
+
+    let start = crate::process::mmap::map(&process, address, pages, file.as_ref(), arguments[5], writable, executable)?;
+    return Ok(start.as_u64());
+}
+
+/// `munmap(address, length)`: unmaps the pages of the file mappings covering `length` bytes from `address` (page
+/// aligned), the part of a mapping that's left stays mapped
+fn sys_munmap(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+
+    let address = VirtAddr::try_new(arguments[0]).map_err(|_| KernelError::InvalidArgument)?;
+    let pages = arguments[1].checked_add(4095).ok_or(KernelError::InvalidArgument)? / 4096;
+
+    crate::process::mmap::unmap(&process, address, pages)?;
+    return Ok(0);
+}
+
+/// `shm_create(size, writable)`: creates a shared memory region of at least `size` bytes, returning its ID.
+/// The region can only be mapped as writable if `writable` isn't 0, and it can be mapped until the caller exits
+fn sys_shm_create(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+
+    return crate::process::shm::create(&process, arguments[0], arguments[1] != 0);
+}
+
+/// `shm_map(id, address, writable)`: maps the whole shared memory region with the given ID at `address`
+fn sys_shm_map(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+    let address = VirtAddr::try_new(arguments[1]).map_err(|_| KernelError::InvalidArgument)?;
+
+    crate::process::shm::map(&process, arguments[0], address, arguments[2] != 0)?;
+    return Ok(0);
+}
+
+/// `shm_unmap(address, size)`: unmaps the shared memory covering `size` bytes from `address`
+fn sys_shm_unmap(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+    let address = VirtAddr::try_new(arguments[0]).map_err(|_| KernelError::InvalidArgument)?;
+
+    crate::process::shm::unmap(&process, address, arguments[1])?;
+    return Ok(0);
+}
+
+/// `kill(pid, signal)`: sends a signal to the process with the given ID, which must belong to the same user unless
+/// the caller is root
+fn sys_kill(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+    let signal = Signal::try_from(arguments[1]).map_err(|_| KernelError::InvalidArgument)?;
+    let process = crate::process::find(ProcessId::from(arguments[0])).ok_or(KernelError::InvalidArgument)?;
+    let credentials = crate::process::credentials::current();
+
+    if !credentials.is_root() && credentials.uid != process.credentials().uid {
+        return Err(KernelError::NotPermitted);
+    }
+
+    process.signals().send(signal)?;
+    return Ok(0);
+}
+
+/// `sigaction(signal, handler, trampoline)`: registers the handler of a signal, which is called with the signal as its
+/// argument and returns to the trampoline, that must call `sigreturn`. A handler of 0 restores the default action
+fn sys_sigaction(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let arguments = frame.arguments();
+    let process = crate::process::current().ok_or(KernelError::Unsupported)?;
+    let signal = Signal::try_from(arguments[0]).map_err(|_| KernelError::InvalidArgument)?;
+
+    process.signals().set_handler(signal, arguments[1], arguments[2])?;
+    return Ok(0);
+}
+
+/// `sigreturn()`: called by the signal trampoline once the handler returns, resumes the thread where the signal
+/// interrupted it. Only returns on error
+fn sys_sigreturn(frame: &SyscallFrame) -> Result<u64, KernelError> {
+    let mut registers = crate::process::signal::restore(frame.user_rsp)?;
+
+    // Other signals may have arrived while the handler was running
+    crate::process::signal::deliver(&mut registers);
+
+    unsafe { crate::usermode::resume(&registers) };
+}
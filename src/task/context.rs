@@ -0,0 +1,104 @@
+use core::arch::global_asm;
+
+/// The registers a thread must keep across a call to [`switch_to`], as defined by the System V ABI, plus its address space.
+///
+/// Every other register is either caller-saved (so the compiler already saved it before calling [`switch_to`])
+/// or saved by the interrupt handler that decided to switch, so this is all that has to be kept per thread.
+/// The return address isn't stored here, it stays on the stack of the thread and `rsp` points to it.
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct Context {
+    pub rbx: u64,
+    pub rbp: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rsp: u64,
+    /// The level 4 table of the thread, it never changes so it's only loaded (and only when it's different)
+    pub cr3: u64
+}
+
+extern "C" {
+    fn context_switch(old: *mut Context, new: *const Context);
+}
+
+// `rdi` holds the context of the thread that is leaving the CPU and `rsi` the context of the one that takes it.
+// The offsets must match the fields of `Context`. The `ret` at the end returns to wherever the new thread called
+// `switch_to`, or to `thread_entry` if it never ran before
+global_asm!(r#"
+.global context_switch
+context_switch:
+    mov [rdi + 0x00], rbx
+    mov [rdi + 0x08], rbp
+    mov [rdi + 0x10], r12
+    mov [rdi + 0x18], r13
+    mov [rdi + 0x20], r14
+    mov [rdi + 0x28], r15
+    mov [rdi + 0x30], rsp
+
+    mov rbx, [rsi + 0x00]
+    mov rbp, [rsi + 0x08]
+    mov r12, [rsi + 0x10]
+    mov r13, [rsi + 0x18]
+    mov r14, [rsi + 0x20]
+    mov r15, [rsi + 0x28]
+    mov rsp, [rsi + 0x30]
+
+    mov rax, [rsi + 0x38]
+    mov rdx, cr3
+    cmp rax, rdx
+    je 1f
+    mov cr3, rax
+1:
+    ret
+
+.global thread_entry
+thread_entry:
+    mov rdi, r12
+    jmp thread_trampoline
+"#);
+
+extern "C" {
+    /// Where a new thread starts, it moves the entry of the thread (kept in `r12` by [`Context::new_thread`])
+    /// to the first argument and jumps to the trampoline
+    fn thread_entry();
+}
+
+impl Context {
+    /// Creates the context of a thread that never ran, switching to it calls `trampoline(argument)`
+    /// on the stack that ends at `stack_top`, in the address space of the kernel
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that the stack ending at `stack_top`
+    /// is mapped, writable and not used by anything else
+    pub unsafe fn new_thread(stack_top: u64, argument: u64) -> Self {
+        // The System V ABI expects `rsp + 8` to be 16 bytes aligned when a function starts, since the trampoline isn't
+        // called the slot where the return address would be is left empty (so a stack trace stops there).
+        // Right below it goes the address `switch_to` returns to the first time
+        let entry_stack_pointer = (stack_top & !0xF) - 8;
+        let switch_stack_pointer = entry_stack_pointer - 8;
+
+        (entry_stack_pointer as *mut u64).write(0);
+        (switch_stack_pointer as *mut u64).write(thread_entry as *const () as u64);
+
+        Context {
+            r12: argument,
+            rsp: switch_stack_pointer,
+            cr3: crate::memory::kernel_cr3(),
+            ..Context::default()
+        }
+    }
+}
+
+/// Saves the registers of the running thread in `old` and resumes the thread whose registers are in `new`.
+/// This returns once some other thread switches back to `old`
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that `new` is the context of a thread that isn't
+/// running, and that interrupts are disabled (the thread that resumes is responsible for enabling them again)
+pub unsafe fn switch_to(old: &mut Context, new: &Context) {
+    context_switch(old, new);
+}
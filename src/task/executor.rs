@@ -0,0 +1,127 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::ptr::NonNull;
+use core::task::{Context, Poll, Waker};
+use crate::task::thread::Thread;
+use crate::task::{Task, TaskId};
+use crate::utils::queue::MpscQueue;
+
+/// How many tasks can be waiting to be polled at the same time
+const TASK_QUEUE_SIZE: usize = 100;
+
+/// The IDs of the tasks ready to be polled together with the thread that runs the executor, so a waker can
+/// wake the thread up when it's blocked waiting for work
+struct TaskQueue {
+    /// Lock-free since the wakers are called from interrupt handlers
+    ids: MpscQueue<TaskId, TASK_QUEUE_SIZE>,
+    thread: NonNull<Thread>
+}
+
+// The thread is only handed to the scheduler, which never frees it while the executor runs
+unsafe impl Send for TaskQueue {}
+unsafe impl Sync for TaskQueue {}
+
+/// A simple executor that polls the ready tasks in the order they were woken up (FIFO) and blocks the
+/// thread it runs on when there's nothing to do, letting other threads (or the idle thread) use the CPU
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    task_queue: Arc<TaskQueue>,
+    waker_cache: BTreeMap<TaskId, Waker>
+}
+
+impl Executor {
+    /// Creates an executor that runs in the current thread
+    pub fn new() -> Self {
+        Executor {
+            tasks: BTreeMap::new(),
+            task_queue: Arc::new(TaskQueue {
+                ids: MpscQueue::new(),
+                thread: crate::sched::current_thread()
+            }),
+            waker_cache: BTreeMap::new()
+        }
+    }
+
+    /// Adds a new task to the executor, the task is polled for the first time on the next iteration of [`Executor::run`]
+    pub fn spawn(&mut self, task: Task) {
+        let task_id = task.id;
+
+        if self.tasks.insert(task_id, task).is_some() {
+            panic!("Task with ID {:?} was already spawned", task_id);
+        }
+
+        self.task_queue.ids.push(task_id).expect("Task queue is full");
+    }
+
+    /// Runs all the tasks forever, must be called in the thread that created the executor
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    fn run_ready_tasks(&mut self) {
+        while let Some(task_id) = self.task_queue.ids.pop() {
+            // The task may have completed after being woken up more than once
+            let task = match self.tasks.get_mut(&task_id) {
+                Some(task) => task,
+                None => continue
+            };
+
+            let waker = self.waker_cache
+                .entry(task_id)
+                .or_insert_with(|| TaskWaker::new(task_id, self.task_queue.clone()));
+
+            let mut context = Context::from_waker(waker);
+
+            if let Poll::Ready(()) = task.poll(&mut context) {
+                self.tasks.remove(&task_id);
+                self.waker_cache.remove(&task_id);
+            }
+        }
+    }
+
+    fn sleep_if_idle(&self) {
+        // Interrupts are disabled while checking the queue so a wake up can't arrive between the check and blocking
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            if self.task_queue.ids.is_empty() {
+                crate::sched::block_current();
+                crate::sched::yield_now();
+            }
+        });
+    }
+}
+
+/// The waker given to the tasks, waking it up pushes the ID of the task to the queue of ready tasks
+/// and wakes up the thread of the executor in case it's blocked
+struct TaskWaker {
+    task_id: TaskId,
+    task_queue: Arc<TaskQueue>
+}
+
+impl TaskWaker {
+    fn new(task_id: TaskId, task_queue: Arc<TaskQueue>) -> Waker {
+        Waker::from(Arc::new(TaskWaker {
+            task_id,
+            task_queue
+        }))
+    }
+
+    fn wake_task(&self) {
+        // If the queue is full the executor is way behind, so there's nothing better to do than losing the wake up
+        let _ = self.task_queue.ids.push(self.task_id);
+        crate::sched::wake(self.task_queue.thread);
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
@@ -0,0 +1,103 @@
+use alloc::sync::Arc;
+use core::ptr::NonNull;
+use crate::task::thread::{Thread, ThreadId};
+use crate::utils::IrqCell;
+
+/// Where a thread leaves its exit code, shared between the thread and its [`JoinHandle`]
+pub(crate) struct ExitStatus {
+    state: IrqCell<ExitState>
+}
+
+struct ExitState {
+    code: Option<i32>,
+    /// The thread blocked in [`JoinHandle::join`], if any
+    joiner: Option<NonNull<Thread>>
+}
+
+// The joiner is only handed to the scheduler, it's blocked (so alive) until it's woken up
+unsafe impl Send for ExitStatus {}
+unsafe impl Sync for ExitStatus {}
+
+impl ExitStatus {
+    pub(crate) fn new() -> Self {
+        ExitStatus {
+            state: IrqCell::new(ExitState {
+                code: None,
+                joiner: None
+            })
+        }
+    }
+
+    /// Records the exit code of the thread and wakes up whoever is joining it
+    pub(crate) fn finish(&self, code: i32) {
+        let joiner = self.state.with(|state| {
+            state.code = Some(code);
+            state.joiner.take()
+        });
+
+        if let Some(joiner) = joiner {
+            crate::sched::wake(joiner);
+        }
+    }
+}
+
+/// An owned permission to wait for a kernel thread to exit, returned when the thread is spawned.
+///
+/// Dropping the handle detaches the thread, it keeps running and is still reclaimed once it exits
+pub struct JoinHandle {
+    id: ThreadId,
+    status: Arc<ExitStatus>
+}
+
+impl JoinHandle {
+    pub(crate) fn new(id: ThreadId, status: Arc<ExitStatus>) -> Self {
+        JoinHandle { id, status }
+    }
+
+    /// The ID of the thread this handle belongs to
+    #[allow(dead_code)]
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    /// Returns whatever the thread already exited
+    #[allow(dead_code)]
+    pub fn is_finished(&self) -> bool {
+        self.status.state.with(|state| state.code.is_some())
+    }
+
+    /// Blocks the current thread until the thread of this handle exits, returning its exit code
+    ///
+    /// ## Panics
+    ///
+    /// Panics if a thread tries to join itself, since it would never wake up
+    #[allow(dead_code)]
+    pub fn join(self) -> i32 {
+        let current = crate::sched::current_thread();
+        assert!(unsafe { current.as_ref().id() } != self.id, "A thread can't join itself");
+
+        loop {
+            // Interrupts stay disabled until the thread blocks, so the exit can't happen between the check and blocking
+            let code = x86_64::instructions::interrupts::without_interrupts(|| {
+                let code = self.status.state.with(|state| {
+                    if state.code.is_none() {
+                        state.joiner = Some(current);
+                    }
+
+                    state.code
+                });
+
+                if code.is_none() {
+                    crate::sched::block_current();
+                    crate::sched::yield_now();
+                }
+
+                code
+            });
+
+            if let Some(code) = code {
+                return code;
+            }
+        }
+    }
+}
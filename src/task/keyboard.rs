@@ -0,0 +1,184 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use crate::utils::IrqCell;
+use crate::utils::queue::SpscQueue;
+
+/// Scancodes read by the keyboard handler that are waiting to be processed outside of the interrupt
+static SCANCODE_QUEUE: SpscQueue<u8, 128> = SpscQueue::new();
+
+/// The waker of the task waiting for the next scancode, if there's any
+static WAKER: IrqCell<Option<Waker>> = IrqCell::new(None);
+
+/// Called by the keyboard interrupt handler to hand a scancode over to the keyboard task
+///
+/// ## Note
+///
+/// This function must not allocate or block since it runs inside an interrupt handler
+pub(crate) fn add_scancode(scancode: u8) {
+    // If the queue is full the scancode is dropped, there's nothing better to do inside an interrupt
+    if SCANCODE_QUEUE.push(scancode).is_err() {
+        return;
+    }
+
+    // `wake_by_ref` is used so the waker is never dropped (and deallocated) inside the interrupt
+    WAKER.with(|waker| {
+        if let Some(waker) = waker.as_ref() {
+            waker.wake_by_ref();
+        }
+    });
+}
+
+/// Returns a future that completes with the next scancode sent by the keyboard
+pub fn next_scancode() -> NextScancode {
+    NextScancode { _private: () }
+}
+
+pub struct NextScancode {
+    _private: ()
+}
+
+impl Future for NextScancode {
+    type Output = u8;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<u8> {
+        if let Some(scancode) = SCANCODE_QUEUE.pop() {
+            return Poll::Ready(scancode);
+        }
+
+        // The old waker is dropped only after interrupts are enabled again, since dropping it may deallocate
+        let old_waker = WAKER.with(|waker| waker.replace(context.waker().clone()));
+        drop(old_waker);
+
+        // A scancode may have arrived after the first check but before the waker was registered
+        match SCANCODE_QUEUE.pop() {
+            Some(scancode) => Poll::Ready(scancode),
+            None => Poll::Pending
+        }
+    }
+}
+
+/// Scancodes (set 1) of the keys that aren't characters, the release of a key has bit 7 set
+const SCANCODE_BACKSPACE: u8 = 0x0E;
+const SCANCODE_TAB: u8 = 0x0F;
+const SCANCODE_ENTER: u8 = 0x1C;
+const SCANCODE_CTRL: u8 = 0x1D;
+const SCANCODE_LEFT_SHIFT: u8 = 0x2A;
+const SCANCODE_RIGHT_SHIFT: u8 = 0x36;
+const SCANCODE_RELEASED: u8 = 0x80;
+
+/// Sent before the scancode of the keys added after the original keyboard, like the arrows
+const SCANCODE_EXTENDED: u8 = 0xE0;
+
+const SCANCODE_UP: u8 = 0x48;
+const SCANCODE_LEFT: u8 = 0x4B;
+const SCANCODE_RIGHT: u8 = 0x4D;
+const SCANCODE_DOWN: u8 = 0x50;
+
+/// The characters of the keys of a US layout, indexed by scancode starting at 0x02, without and with shift
+const CHARACTERS: [(char, char); 56] = [
+    ('1', '!'), ('2', '@'), ('3', '#'), ('4', '$'), ('5', '%'), ('6', '^'), ('7', '&'), ('8', '*'), ('9', '('), ('0', ')'),
+    ('-', '_'), ('=', '+'), ('\0', '\0'), ('\0', '\0'),
+    ('q', 'Q'), ('w', 'W'), ('e', 'E'), ('r', 'R'), ('t', 'T'), ('y', 'Y'), ('u', 'U'), ('i', 'I'), ('o', 'O'), ('p', 'P'),
+    ('[', '{'), (']', '}'), ('\0', '\0'), ('\0', '\0'),
+    ('a', 'A'), ('s', 'S'), ('d', 'D'), ('f', 'F'), ('g', 'G'), ('h', 'H'), ('j', 'J'), ('k', 'K'), ('l', 'L'),
+    (';', ':'), ('\'', '"'), ('`', '~'), ('\0', '\0'), ('\\', '|'),
+    ('z', 'Z'), ('x', 'X'), ('c', 'C'), ('v', 'V'), ('b', 'B'), ('n', 'N'), ('m', 'M'), (',', '<'), ('.', '>'), ('/', '?'),
+    ('\0', '\0'), ('*', '*'), ('\0', '\0'), (' ', ' ')
+];
+
+/// A key pressed by the user, as understood by [`Keyboard`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Key {
+    /// A key that types a character, already taking shift into account
+    Char(char),
+    /// A character key pressed while holding Ctrl, always lowercase
+    Ctrl(char),
+    Enter,
+    Backspace,
+    Tab,
+    Up,
+    Down,
+    Left,
+    Right
+}
+
+/// Turns the scancodes sent by the keyboard into [`Key`]s, keeping track of the modifiers held down
+pub struct Keyboard {
+    shift: bool,
+    ctrl: bool,
+    /// Whatever the last scancode was [`SCANCODE_EXTENDED`]
+    extended: bool
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Keyboard {
+            shift: false,
+            ctrl: false,
+            extended: false
+        }
+    }
+
+    /// Waits for the next key pressed, skipping the scancodes that don't make a key (like releases)
+    pub async fn next_key(&mut self) -> Key {
+        loop {
+            if let Some(key) = self.decode(next_scancode().await) {
+                return key;
+            }
+        }
+    }
+
+    /// Feeds a scancode to the decoder, returning the key it completes, if any
+    pub fn decode(&mut self, scancode: u8) -> Option<Key> {
+        if scancode == SCANCODE_EXTENDED {
+            self.extended = true;
+            return None;
+        }
+
+        let extended = core::mem::replace(&mut self.extended, false);
+        let released = scancode & SCANCODE_RELEASED != 0;
+        let code = scancode & !SCANCODE_RELEASED;
+
+        // Both Ctrl keys send the same scancode, the right one is just extended
+        match code {
+            SCANCODE_CTRL => self.ctrl = !released,
+            SCANCODE_LEFT_SHIFT | SCANCODE_RIGHT_SHIFT if !extended => self.shift = !released,
+            _ => {}
+        }
+
+        if released {
+            return None;
+        }
+
+        if extended {
+            return match code {
+                SCANCODE_UP => Some(Key::Up),
+                SCANCODE_DOWN => Some(Key::Down),
+                SCANCODE_LEFT => Some(Key::Left),
+                SCANCODE_RIGHT => Some(Key::Right),
+                SCANCODE_ENTER => Some(Key::Enter),
+                _ => None
+            };
+        }
+
+        match code {
+            SCANCODE_ENTER => return Some(Key::Enter),
+            SCANCODE_BACKSPACE => return Some(Key::Backspace),
+            SCANCODE_TAB => return Some(Key::Tab),
+            _ => {}
+        }
+
+        let (normal, shifted) = *CHARACTERS.get((code as usize).checked_sub(0x02)?)?;
+
+        if normal == '\0' {
+            return None;
+        }
+
+        if self.ctrl {
+            return Some(Key::Ctrl(normal));
+        }
+
+        Some(Key::Char(if self.shift { shifted } else { normal }))
+    }
+}
@@ -0,0 +1,66 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use core::any::Any;
+
+/// Declares statics whose value is different for every thread, each thread gets its own value the first time
+/// it accesses the static (created by the given expression) and the value lives as long as the thread.
+///
+/// Like the thread locals of `std` the values can only be borrowed immutably, so a [`core::cell::RefCell`]
+/// (or a [`core::cell::Cell`]) is needed to change them
+#[macro_export]
+macro_rules! task_local {
+    ($($(#[$attribute:meta])* $visibility:vis static $name:ident: $type:ty = $init:expr;)*) => {
+        $(
+            $(#[$attribute])*
+            $visibility static $name: $crate::task::local::LocalKey<$type> = $crate::task::local::LocalKey::new({
+                fn init() -> $type {
+                    $init
+                }
+
+                init
+            });
+        )*
+    };
+}
+
+/// The values of the task locals of a thread, indexed by the address of their [`LocalKey`]
+pub(crate) type TaskLocals = BTreeMap<usize, Box<dyn Any>>;
+
+/// A key to a task local value, declared with [`task_local!`]
+pub struct LocalKey<T: 'static> {
+    init: fn() -> T
+}
+
+#[allow(dead_code)]
+impl<T: 'static> LocalKey<T> {
+    #[doc(hidden)]
+    pub const fn new(init: fn() -> T) -> Self {
+        LocalKey { init }
+    }
+
+    /// Runs `f` with the value of the current thread, creating it first if this thread never accessed it
+    ///
+    /// ## Note
+    ///
+    /// Interrupt handlers must never use task locals, they would get the values of the thread they interrupted
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        let key = self as *const LocalKey<T> as usize;
+
+        // Only the thread itself ever touches its locals, and it's running
+        let locals = unsafe { &mut crate::sched::current_thread().as_mut().locals };
+
+        let value: *const T = match locals.get(&key) {
+            Some(value) => value.downcast_ref::<T>().unwrap(),
+            None => {
+                // The initializer may use other task locals, so the map can't stay borrowed while it runs
+                let value: Box<dyn Any> = Box::new((self.init)());
+
+                let locals = unsafe { &mut crate::sched::current_thread().as_mut().locals };
+                locals.entry(key).or_insert(value).downcast_ref::<T>().unwrap()
+            }
+        };
+
+        // The values are boxed, so they don't move when other values are added to the map
+        return f(unsafe { &*value });
+    }
+}
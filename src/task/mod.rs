@@ -0,0 +1,129 @@
+pub mod executor;
+pub mod keyboard;
+pub mod thread;
+pub mod context;
+pub mod timer;
+pub mod local;
+mod join;
+
+pub use join::JoinHandle;
+#[allow(unused_imports)]
+pub use crate::sched::{preempt_disable, PreemptGuard};
+
+use alloc::boxed::Box;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use crate::memory::stack;
+use crate::sched::{CpuSet, Priority};
+use crate::time;
+use crate::time::wheel::{TimerEntry, TimerTarget};
+use crate::task::thread::Thread;
+use crate::utils::error::KernelError;
+
+/// How many pages are allocated for the stack of each kernel thread
+const KTHREAD_STACK_PAGES: usize = 4;
+
+/// Gives up the rest of the time slice of the current thread, so long-running kernel loops can let other threads run.
+/// Does nothing while preemption is disabled (see [`preempt_disable`]), since the thread asked not to be switched out
+#[allow(dead_code)]
+pub fn yield_now() {
+    if crate::sched::is_preempt_disabled() {
+        return;
+    }
+
+    crate::sched::yield_now();
+}
+
+/// Blocks the current thread for at least `ms` milliseconds, letting other threads use the CPU meanwhile
+#[allow(dead_code)]
+pub fn sleep_ms(ms: u64) {
+    let deadline = time::ticks() + time::ms_to_ticks(ms).max(1);
+    let entry = TimerEntry::new(deadline, TimerTarget::Thread(crate::sched::current_thread()));
+
+    // Interrupts stay disabled until the thread is out of the CPU, so the timer can't fire before the thread blocks
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        time::wheel::insert(&entry);
+        crate::sched::block_current();
+        crate::sched::yield_now();
+    });
+}
+
+/// Creates a new kernel thread named `name` that runs `entry` with its own guard-paged stack,
+/// the thread starts running once the scheduler gives it its first time slice.
+///
+/// The thread exits when `entry` returns (with code 0) or when it calls [`exit`]
+#[allow(dead_code)]
+pub fn spawn_kthread(entry: fn(), name: &'static str) -> Result<JoinHandle, KernelError> {
+    return spawn_kthread_with_priority(entry, name, Priority::Normal);
+}
+
+/// Same as [`spawn_kthread`] but the thread is scheduled with the given priority
+pub fn spawn_kthread_with_priority(entry: fn(), name: &'static str, priority: Priority) -> Result<JoinHandle, KernelError> {
+    return spawn_kthread_with(entry, name, priority, CpuSet::all());
+}
+
+/// Same as [`spawn_kthread`] but the thread is scheduled with the given priority and only on the CPUs in `affinity`
+///
+/// ## Errors
+///
+/// Returns [`KernelError::InvalidArgument`] if none of the CPUs in `affinity` is online
+pub fn spawn_kthread_with(entry: fn(), name: &'static str, priority: Priority, affinity: CpuSet) -> Result<JoinHandle, KernelError> {
+    if affinity.intersection(crate::sched::online_cpus()).is_empty() {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let stack = stack::allocate_stack(KTHREAD_STACK_PAGES)?;
+    let mut thread = Thread::new(name, entry, stack, priority);
+    thread.affinity = affinity;
+
+    let handle = JoinHandle::new(thread.id(), thread.exit_status().clone());
+
+    crate::sched::add_thread(thread);
+
+    return Ok(handle);
+}
+
+/// Terminates the current thread with the given exit code, which is handed to whoever joins it.
+/// The stack of the thread is freed by the reaper after the thread leaves the CPU
+#[allow(dead_code)]
+pub fn exit(code: i32) -> ! {
+    let thread = crate::sched::current_thread();
+
+    // The thread can't be freed while it's still running
+    unsafe { thread.as_ref().exit_status().finish(code) };
+
+    crate::sched::exit_current();
+}
+
+/// A unique identifier for a [`Task`], used by the executor to know which task a waker belongs to
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// An asynchronous task, which is just a pinned future that is polled by the [`executor::Executor`]
+/// until it completes
+pub struct Task {
+    id: TaskId,
+    future: Pin<Box<dyn Future<Output = ()>>>
+}
+
+impl Task {
+    pub fn new(future: impl Future<Output = ()> + 'static) -> Self {
+        Task {
+            id: TaskId::new(),
+            future: Box::pin(future)
+        }
+    }
+
+    fn poll(&mut self, context: &mut Context) -> Poll<()> {
+        self.future.as_mut().poll(context)
+    }
+}
@@ -0,0 +1,220 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::memory::stack::KernelStack;
+use crate::process::Process;
+use crate::sched::{CpuId, CpuSet, Priority, ThreadStats};
+use crate::task::context::Context;
+use crate::task::join::ExitStatus;
+use crate::task::local::TaskLocals;
+use crate::usermode::UserRegisters;
+use crate::utils::list::{Link, Linked};
+
+/// A unique identifier for a kernel [`Thread`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Whatever a thread can be picked by the scheduler
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ThreadState {
+    /// The thread is running or waiting in a ready queue for its turn
+    Ready,
+    /// The thread is waiting for something (like a timer) and isn't in any ready queue
+    Blocked,
+    /// The thread called [`crate::task::exit`] and is waiting for the reaper to free it
+    Exited
+}
+
+/// A kernel thread, a flow of execution with its own stack that can be preempted at any point by the timer
+pub struct Thread {
+    id: ThreadId,
+    name: &'static str,
+    /// The registers of this thread, only meaningful while the thread isn't running
+    pub(crate) context: Context,
+    /// The stack owned by this thread, the thread that runs `kernel_main` uses the stack given by the bootloader
+    stack: Option<KernelStack>,
+    /// The priority the thread is scheduled with, higher than [`Thread::base_priority`] while it's boosted
+    pub(crate) priority: Priority,
+    /// The priority the thread was given, see [`crate::sched::boost`]
+    pub(crate) base_priority: Priority,
+    pub(crate) state: ThreadState,
+    /// The CPUs the thread is allowed to run on
+    pub(crate) affinity: CpuSet,
+    /// The CPU the thread is running (or waiting) on, or the last one it ran on while it's blocked
+    pub(crate) cpu: CpuId,
+    /// Where the exit code is left for whoever joins this thread
+    exit_status: Arc<ExitStatus>,
+    /// The process this thread runs code of, kernel threads don't belong to any
+    process: Option<Arc<Process>>,
+    /// The registers the thread enters user mode with, only for the threads of a process
+    user_start: Option<UserRegisters>,
+    /// Kept by the scheduler, shared with [`crate::sched::stats`] so they can be read without the scheduler
+    stats: Arc<ThreadStats>,
+    /// The values of the task locals (see [`crate::task_local`]) this thread used, only touched by the thread itself
+    pub(crate) locals: TaskLocals,
+    /// Links this thread to the scheduler queue it's currently in
+    link: Link<Thread>
+}
+
+// A thread is only ever accessed by the scheduler (with interrupts disabled) or by the thread itself
+unsafe impl Send for Thread {}
+
+unsafe impl Linked for Thread {
+    fn link(&self) -> &Link<Self> {
+        &self.link
+    }
+}
+
+impl Thread {
+    /// Creates a thread that starts running `entry` in the given stack the first time it's scheduled
+    pub fn new(name: &'static str, entry: fn(), stack: KernelStack, priority: Priority) -> Self {
+        // The stack is mapped and owned by this thread, which isn't running yet
+        let context = unsafe { Context::new_thread(stack.top().as_u64(), entry as usize as u64) };
+
+        Thread {
+            id: ThreadId::new(),
+            name,
+            context,
+            stack: Some(stack),
+            priority,
+            base_priority: priority,
+            state: ThreadState::Ready,
+            affinity: CpuSet::all(),
+            cpu: CpuId::BOOT,
+            exit_status: Arc::new(ExitStatus::new()),
+            process: None,
+            user_start: None,
+            stats: Arc::new(ThreadStats::new()),
+            locals: TaskLocals::new(),
+            link: Link::new()
+        }
+    }
+
+    /// Creates a thread of `process` that drops to user mode with the given registers, `kernel_stack` is used
+    /// whenever the thread enters the kernel through an interrupt or a system call
+    pub fn new_user(name: &'static str, process: Arc<Process>, registers: UserRegisters, kernel_stack: KernelStack) -> Self {
+        let mut thread = Thread::new(name, enter_user_mode, kernel_stack, Priority::Normal);
+
+        thread.context.cr3 = process.address_space_cr3();
+        thread.process = Some(process);
+        thread.user_start = Some(registers);
+
+        return thread;
+    }
+
+    /// Creates the thread that represents the flow of execution that is already running (the one that called
+    /// `kernel_main`), its context is filled the first time it's preempted
+    pub fn bootstrap() -> Self {
+        Thread {
+            id: ThreadId::new(),
+            name: "kernel_main",
+            context: Context {
+                cr3: crate::memory::kernel_cr3(),
+                ..Context::default()
+            },
+            stack: None,
+            priority: Priority::Normal,
+            base_priority: Priority::Normal,
+            state: ThreadState::Ready,
+            affinity: CpuSet::all(),
+            cpu: CpuId::BOOT,
+            exit_status: Arc::new(ExitStatus::new()),
+            process: None,
+            user_start: None,
+            stats: Arc::new(ThreadStats::new()),
+            locals: TaskLocals::new(),
+            link: Link::new()
+        }
+    }
+
+    /// Changes the address space the thread runs in, used by the process when its image is replaced
+    ///
+    /// ## Safety
+    ///
+    /// This method is unsafe because the caller must guarantee that `cr3` stays valid while the thread uses it
+    pub(crate) unsafe fn set_cr3(&mut self, cr3: u64) {
+        self.context.cr3 = cr3;
+    }
+
+    pub fn process(&self) -> Option<&Arc<Process>> {
+        self.process.as_ref()
+    }
+
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    #[allow(dead_code)]
+    pub fn affinity(&self) -> CpuSet {
+        self.affinity
+    }
+
+    #[allow(dead_code)]
+    pub fn cpu(&self) -> CpuId {
+        self.cpu
+    }
+
+    pub fn stack(&self) -> Option<&KernelStack> {
+        self.stack.as_ref()
+    }
+
+    pub(crate) fn exit_status(&self) -> &Arc<ExitStatus> {
+        &self.exit_status
+    }
+
+    pub(crate) fn stats(&self) -> &Arc<ThreadStats> {
+        &self.stats
+    }
+
+    /// Takes the stack out of the thread so it can be freed, the thread must never run again after this
+    pub(crate) fn take_stack(&mut self) -> Option<KernelStack> {
+        self.stack.take()
+    }
+
+    /// Returns whatever the thread overflowed its stack, the stack given by the bootloader has no canary
+    /// so the thread that runs `kernel_main` is never considered overflowed
+    pub fn stack_overflowed(&self) -> bool {
+        self.stack.as_ref().map_or(false, |stack| !stack.canary_intact())
+    }
+}
+
+/// The kernel side entry of the threads of a process, it just drops to user mode where the thread was told to start
+fn enter_user_mode() {
+    // The thread is running, so it's alive, and `user_start` never changes after the thread is created
+    let thread = unsafe { crate::sched::current_thread().as_ref() };
+    let registers = thread.user_start.as_ref().expect("Only threads of a process can enter user mode");
+
+    unsafe { crate::usermode::resume(registers) };
+}
+
+/// The first function that runs in every new thread, it just calls the entry of the thread
+/// which is given as the first argument and exits with code 0 once the entry returns
+#[no_mangle]
+extern "C" fn thread_trampoline(entry: usize) -> ! {
+    // Threads are always switched with interrupts disabled, the old thread enables them again when it resumes
+    // but a new thread never goes back through the code that disabled them
+    x86_64::instructions::interrupts::enable();
+
+    let entry: fn() = unsafe { core::mem::transmute(entry) };
+    entry();
+
+    crate::task::exit(0);
+}
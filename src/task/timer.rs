@@ -0,0 +1,156 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use crate::time;
+use crate::time::wheel::{TimerEntry, TimerTarget};
+use crate::utils::error::KernelError;
+
+/// A future that completes once a deadline is reached, the async counterpart of [`super::sleep_ms`]
+pub struct Delay {
+    entry: TimerEntry
+}
+
+#[allow(dead_code)]
+impl Delay {
+    /// Returns a delay that completes after at least `ms` milliseconds
+    pub fn after(ms: u64) -> Self {
+        return Delay::at(time::ticks() + time::ms_to_ticks(ms));
+    }
+
+    /// Returns a delay that completes once the timer tick count reaches `deadline`
+    pub fn at(deadline: u64) -> Self {
+        Delay {
+            entry: TimerEntry::new(deadline, TimerTarget::Waker(None))
+        }
+    }
+
+    /// The tick at which this delay completes
+    pub fn deadline(&self) -> u64 {
+        self.entry.deadline()
+    }
+
+    /// Moves the deadline so the delay completes after at least `ms` milliseconds from now, even if it already completed
+    pub fn reset(self: Pin<&mut Self>, ms: u64) {
+        let deadline = time::ticks() + time::ms_to_ticks(ms);
+
+        // The delay is pinned, so the entry won't move while it's on the wheel
+        unsafe { time::wheel::set_deadline(&self.entry, deadline) };
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        return poll_entry(&self.entry, context);
+    }
+}
+
+/// A stream of ticks, one every period, for periodic jobs. If the ticks aren't consumed in time the missed ones are
+/// skipped instead of completing all at once, so a slow consumer doesn't get a burst of ticks
+pub struct Interval {
+    /// The period in timer ticks
+    period: u64,
+    entry: TimerEntry
+}
+
+#[allow(dead_code)]
+impl Interval {
+    /// Returns an interval that ticks every `ms` milliseconds (rounded up to whole timer ticks),
+    /// the first tick comes after one period
+    pub fn every(ms: u64) -> Self {
+        let period = time::ms_to_ticks(ms).max(1);
+
+        Interval {
+            period,
+            entry: TimerEntry::new(time::ticks() + period, TimerTarget::Waker(None))
+        }
+    }
+
+    /// Returns a future that completes on the next tick of this interval
+    pub fn tick(self: Pin<&mut Self>) -> Tick<'_> {
+        Tick { interval: self }
+    }
+
+    /// Completes once the next tick is reached and schedules the one after it
+    pub fn poll_tick(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        if poll_entry(&self.entry, context).is_pending() {
+            return Poll::Pending;
+        }
+
+        let now = time::ticks();
+        let mut next = self.entry.deadline() + self.period;
+
+        if next <= now {
+            next = now + self.period;
+        }
+
+        // The interval is pinned, so the entry won't move while it's on the wheel
+        unsafe { time::wheel::set_deadline(&self.entry, next) };
+
+        return Poll::Ready(());
+    }
+}
+
+/// The future returned by [`Interval::tick`]
+pub struct Tick<'a> {
+    interval: Pin<&'a mut Interval>
+}
+
+impl Future for Tick<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        return self.interval.as_mut().poll_tick(context);
+    }
+}
+
+/// Runs `future` for at most `ms` milliseconds, completing with [`KernelError::Timeout`] if it takes longer
+#[allow(dead_code)]
+pub fn timeout<F: Future>(ms: u64, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        delay: Delay::after(ms)
+    }
+}
+
+/// The future returned by [`timeout`]
+pub struct Timeout<F> {
+    future: F,
+    delay: Delay
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, KernelError>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        // Both fields are pinned together with the timeout, neither of them is ever moved out of it
+        let (future, delay) = unsafe {
+            let this = self.get_unchecked_mut();
+            (Pin::new_unchecked(&mut this.future), Pin::new_unchecked(&mut this.delay))
+        };
+
+        if let Poll::Ready(output) = future.poll(context) {
+            return Poll::Ready(Ok(output));
+        }
+
+        return match delay.poll(context) {
+            Poll::Ready(()) => Poll::Ready(Err(KernelError::Timeout)),
+            Poll::Pending => Poll::Pending
+        };
+    }
+}
+
+/// Completes once `entry` expired, otherwise hangs it on the wheel so the task is woken up when it does
+fn poll_entry(entry: &TimerEntry, context: &mut Context) -> Poll<()> {
+    if entry.has_expired() {
+        return Poll::Ready(());
+    }
+
+    // The entry belongs to a pinned future, so it won't move until it's dropped (which takes it off the wheel).
+    // The old waker is dropped only after interrupts are enabled again, since dropping it may deallocate
+    let old_waker = unsafe { time::wheel::set_waker(entry, context.waker()) };
+    drop(old_waker);
+
+    return Poll::Pending;
+}
@@ -0,0 +1,69 @@
+use core::fmt;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A date and a time of the day in UTC, like the RTC and the filesystems keep them
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    /// From 1 to 12
+    pub month: u8,
+    /// From 1 to 31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8
+}
+
+impl DateTime {
+    /// Returns the date and time `seconds` after the Unix epoch (1970-01-01 00:00:00)
+    pub fn from_unix(seconds: u64) -> Self {
+        let (days, time) = (seconds / SECONDS_PER_DAY, seconds % SECONDS_PER_DAY);
+
+        // The years are counted from March, so the day added by a leap year is the last one of the year. An era is
+        // 400 years, after which the calendar repeats itself
+        let days = days + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+
+        DateTime {
+            year: (era * 400 + year_of_era + (month <= 2) as u64) as u16,
+            month: month as u8,
+            day: (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8
+        }
+    }
+
+    /// Returns how many seconds passed from the Unix epoch to this date, 0 for the dates before it
+    pub fn to_unix(self) -> u64 {
+        if self.year < 1970 {
+            return 0;
+        }
+
+        let (month, day) = (self.month.clamp(1, 12) as u64, self.day.max(1) as u64);
+        let year = self.year as u64 - (month <= 2) as u64;
+
+        let era = year / 400;
+        let year_of_era = year % 400;
+        let day_of_year = (153 * if month > 2 { month - 3 } else { month + 9 } + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = (era * 146_097 + day_of_era).saturating_sub(719_468);
+
+        return days * SECONDS_PER_DAY + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64;
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
@@ -0,0 +1,289 @@
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::VirtAddr;
+use crate::println;
+use crate::utils::error::KernelError;
+use crate::utils::IrqCell;
+
+/// The most sources that can be registered
+const MAX_SOURCES: usize = 4;
+
+/// The ratings of the sources registered by [`init`], the highest one that works is used. The tick only has the
+/// resolution of a tick, the HPET is precise but slow to read and the TSC is both unless it changes speed with the CPU
+const RATING_PIT: u32 = 50;
+const RATING_LAPIC: u32 = 60;
+const RATING_HPET: u32 = 250;
+const RATING_TSC: u32 = 300;
+const RATING_TSC_VARIABLE: u32 = 100;
+
+/// The registers of the HPET, as offsets from its base: the period of the counter is the upper half of the
+/// capabilities, in femtoseconds
+const HPET_CAPABILITIES: usize = 0x00;
+const HPET_CONFIGURATION: usize = 0x10;
+const HPET_COUNTER: usize = 0xF0;
+const HPET_REGISTERS_SIZE: usize = 0x400;
+
+/// Bit 0 of the configuration starts the main counter
+const HPET_CONFIGURATION_ENABLE: u64 = 1 << 0;
+
+const FEMTOSECONDS_PER_SECOND: u64 = 1_000_000_000_000_000;
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+
+/// How often the current source is compared with the tick
+const CHECK_INTERVAL_MS: u64 = 1000;
+
+/// How far (in percent of the time that passed) the current source can be from the tick before it's considered
+/// unstable. It's never less than [`MIN_SKEW_TICKS`], since the tick itself is only that precise
+const MAX_SKEW_PERCENT: u64 = 5;
+const MIN_SKEW_TICKS: u64 = 2;
+
+/// Where the registers of the HPET are mapped, once it's registered
+static HPET: AtomicU64 = AtomicU64::new(0);
+
+/// The sources registered and the one in use
+static STATE: IrqCell<State> = IrqCell::new(State {
+    sources: [None, None, None, None],
+    current: None,
+    base_ns: 0,
+    start: 0
+});
+
+/// A counter the time is read from
+#[derive(Debug, Copy, Clone)]
+pub struct ClockSource {
+    pub name: &'static str,
+    /// How good the source is, the highest rated one that works is used
+    pub rating: u32,
+    /// How many times the counter goes up in a second
+    pub frequency: u64,
+    /// The bits the counter has, it wraps around past them
+    pub mask: u64,
+    pub read: fn() -> u64
+}
+
+struct Registered {
+    source: ClockSource,
+    /// Set once the source was caught drifting, it's never used again
+    unstable: bool
+}
+
+struct State {
+    sources: [Option<Registered>; MAX_SOURCES],
+    /// The index of the source in use, the tick is used while there's none
+    current: Option<usize>,
+    /// The time when the current source was last read by [`State::rebase`], and the counter at that time
+    base_ns: u64,
+    start: u64
+}
+
+impl State {
+    fn current(&self) -> Option<&ClockSource> {
+        Some(&self.sources[self.current?].as_ref()?.source)
+    }
+
+    /// Returns the time since boot and the counter it was computed from
+    fn read(&self) -> (u64, u64) {
+        let source = match self.current() {
+            Some(source) => source,
+            None => return (super::ticks() * NANOSECONDS_PER_SECOND / super::TICKS_PER_SECOND, 0)
+        };
+
+        let counter = (source.read)();
+        let elapsed = counter.wrapping_sub(self.start) & source.mask;
+        let elapsed_ns = (elapsed as u128 * NANOSECONDS_PER_SECOND as u128 / source.frequency as u128) as u64;
+
+        return (self.base_ns + elapsed_ns, counter);
+    }
+
+    /// Moves the base to now, so the counter never goes around more than once between two reads. Returns the time
+    fn rebase(&mut self) -> u64 {
+        let (now, counter) = self.read();
+
+        self.base_ns = now;
+        self.start = counter;
+
+        return now;
+    }
+
+    /// Switches to the highest rated source that isn't unstable, the time goes on from where the old one was.
+    /// Returns the name of the new source
+    fn switch_to_best(&mut self) -> Option<&'static str> {
+        let now = self.rebase();
+
+        self.current = self.sources
+            .iter()
+            .enumerate()
+            .filter_map(|(index, registered)| Some((index, registered.as_ref()?)))
+            .filter(|(_, registered)| !registered.unstable)
+            .max_by_key(|(_, registered)| registered.source.rating)
+            .map(|(index, _)| index);
+
+        let (_, counter) = self.read();
+
+        self.base_ns = now;
+        self.start = counter;
+
+        return self.current().map(|source| source.name);
+    }
+}
+
+/// Adds a source, it's only used once [`select_best`] picks it
+///
+/// ## Errors
+///
+/// Returns [`KernelError::Busy`] if there are already [`MAX_SOURCES`] sources
+pub fn register(source: ClockSource) -> Result<(), KernelError> {
+    STATE.with(|state| {
+        let slot = state.sources.iter_mut().find(|slot| slot.is_none()).ok_or(KernelError::Busy)?;
+
+        *slot = Some(Registered { source, unstable: false });
+        Ok(())
+    })
+}
+
+/// Switches to the highest rated source, returning its name
+pub fn select_best() -> Option<&'static str> {
+    STATE.with(|state| state.switch_to_best())
+}
+
+/// Returns the source in use, if any
+pub fn current() -> Option<ClockSource> {
+    STATE.with(|state| state.current().copied())
+}
+
+/// Returns how many nanoseconds passed since the first source was selected, with the resolution of the source in
+/// use (or of a tick while there's none). The time never goes back when the source changes
+pub fn now_ns() -> u64 {
+    STATE.with(|state| state.read().0)
+}
+
+fn read_tick() -> u64 {
+    super::ticks()
+}
+
+fn read_hpet() -> u64 {
+    let counter = VirtAddr::new(HPET.load(Ordering::Relaxed) + HPET_COUNTER as u64);
+    unsafe { core::ptr::read_volatile(counter.as_ptr::<u64>()) }
+}
+
+fn read_tsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Maps the HPET described by the ACPI table and starts its main counter, returning it as a source
+///
+/// ## Errors
+///
+/// Returns [`KernelError::NoDevice`] if there's no HPET, or the error of mapping its registers
+fn hpet_source() -> Result<ClockSource, KernelError> {
+    let hpet = crate::acpi::hpet().ok_or(KernelError::NoDevice)?;
+    let registers = crate::memory::mmio::map(hpet.address, HPET_REGISTERS_SIZE)?;
+
+    let register = |offset: usize| (registers + offset).as_mut_ptr::<u64>();
+
+    let period = unsafe { core::ptr::read_volatile(register(HPET_CAPABILITIES)) } >> 32;
+
+    if period == 0 {
+        return Err(KernelError::Unsupported);
+    }
+
+    unsafe {
+        let configuration = core::ptr::read_volatile(register(HPET_CONFIGURATION));
+        core::ptr::write_volatile(register(HPET_CONFIGURATION), configuration | HPET_CONFIGURATION_ENABLE);
+    }
+
+    HPET.store(registers.as_u64(), Ordering::Relaxed);
+
+    Ok(ClockSource {
+        name: "hpet",
+        rating: RATING_HPET,
+        frequency: FEMTOSECONDS_PER_SECOND / period,
+        mask: if hpet.counter_64bit { u64::MAX } else { u32::MAX as u64 },
+        read: read_hpet
+    })
+}
+
+/// Returns the TSC as a source, rated below the HPET when its speed changes with the one of the CPU
+fn tsc_source() -> ClockSource {
+    // The invariant TSC is bit 8 of EDX of leaf 0x80000007, CPUID is only unsafe on older compilers
+    #[allow(unused_unsafe)]
+    let invariant = unsafe {
+        __cpuid(0x8000_0000).eax >= 0x8000_0007 && __cpuid(0x8000_0007).edx & (1 << 8) != 0
+    };
+
+    // The local APIC measured it already when it drives the tick
+    let frequency = super::lapic::tsc_frequency()
+        .unwrap_or_else(|| super::lapic::calibrate() * super::TICKS_PER_SECOND);
+
+    ClockSource {
+        name: "tsc",
+        rating: if invariant { RATING_TSC } else { RATING_TSC_VARIABLE },
+        frequency,
+        mask: u64::MAX,
+        read: read_tsc
+    }
+}
+
+/// Compares the source in use with the tick every [`CHECK_INTERVAL_MS`], and switches to the next best one if it
+/// drifted too far
+fn check_loop() {
+    let (mut last_ticks, mut last_ns) = (super::ticks(), now_ns());
+
+    loop {
+        crate::task::sleep_ms(CHECK_INTERVAL_MS);
+
+        let switched = STATE.with(|state| {
+            let (ticks, now) = (super::ticks(), state.rebase());
+
+            let expected = (ticks - last_ticks) * NANOSECONDS_PER_SECOND / super::TICKS_PER_SECOND;
+            let measured = now - last_ns;
+            let tolerance = (expected * MAX_SKEW_PERCENT / 100)
+                .max(MIN_SKEW_TICKS * NANOSECONDS_PER_SECOND / super::TICKS_PER_SECOND);
+
+            (last_ticks, last_ns) = (ticks, now);
+
+            if measured.abs_diff(expected) <= tolerance {
+                return None;
+            }
+
+            let current = state.current?;
+            let unstable = state.sources[current].as_mut()?;
+
+            unstable.unstable = true;
+            let name = unstable.source.name;
+
+            return Some((name, state.switch_to_best()));
+        });
+
+        if let Some((unstable, next)) = switched {
+            println!("Clock source: {} drifted from the tick, switching to {}", unstable, next.unwrap_or("the tick"));
+        }
+    }
+}
+
+/// Registers the tick (from the PIT or the local APIC), the HPET and the TSC as sources, selects the best one and
+/// starts the thread that checks it. Must be called once, after [`super::init_deadline_timer`] and the ACPI tables
+/// are parsed, before any process is created
+pub fn init() {
+    let (name, rating) = if super::lapic::is_active() { ("lapic", RATING_LAPIC) } else { ("pit", RATING_PIT) };
+
+    let sources = [
+        Ok(ClockSource { name, rating, frequency: super::TICKS_PER_SECOND, mask: u64::MAX, read: read_tick }),
+        hpet_source(),
+        Ok(tsc_source())
+    ];
+
+    for source in sources.into_iter().flatten() {
+        if let Err(error) = register(source) {
+            println!("Clock source: failed to register {} ({:?})", source.name, error);
+        }
+    }
+
+    if let Some(source) = select_best().and_then(|_| current()) {
+        println!("Clock source: {} ({} kHz)", source.name, source.frequency / 1000);
+    }
+
+    if let Err(error) = crate::task::spawn_kthread(check_loop, "clocksource") {
+        println!("Clock source: failed to start the thread that checks it ({:?})", error);
+    }
+}
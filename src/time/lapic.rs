@@ -0,0 +1,184 @@
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::registers::model_specific::Msr;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::utils::error::KernelError;
+
+/// The MSR with the physical address of the local APIC, and the one the TSC-deadline timer fires at
+const IA32_APIC_BASE: u32 = 0x1B;
+const IA32_TSC_DEADLINE: u32 = 0x6E0;
+
+/// The registers of the local APIC, as offsets from its base
+const REGISTER_END_OF_INTERRUPT: u64 = 0xB0;
+const REGISTER_SPURIOUS: u64 = 0xF0;
+const REGISTER_LVT_TIMER: u64 = 0x320;
+const REGISTER_LVT_LINT0: u64 = 0x350;
+
+/// The size of the registers of the local APIC
+const REGISTERS_SIZE: usize = 0x400;
+
+/// The vectors of the interrupts of the local APIC, above the ones of the PICs
+pub const TIMER_VECTOR: u8 = 0xF0;
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+/// Bit 8 of the spurious interrupt register enables the local APIC
+const SPURIOUS_APIC_ENABLED: u32 = 1 << 8;
+
+/// Bits 17 and 18 of the timer entry pick the mode, the timer fires once the TSC reaches the deadline MSR
+const LVT_TSC_DEADLINE: u32 = 0b10 << 17;
+
+/// Delivers what comes in through the pin as if the PIC was wired to the CPU directly
+const LVT_EXTINT: u32 = 0b111 << 8;
+
+/// How many ticks of the PIT the TSC is measured against
+const CALIBRATION_TICKS: u64 = 5;
+
+/// The most ticks the timer is stopped for while the CPU is idle, so a clock that drifts is corrected often enough
+const MAX_IDLE_TICKS: u64 = super::TICKS_PER_SECOND;
+
+/// Set once [`init`] moved the tick to the local APIC
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Where the registers of the local APIC are mapped
+static REGISTERS: AtomicU64 = AtomicU64::new(0);
+
+/// How many cycles of the TSC a tick lasts
+static CYCLES_PER_TICK: AtomicU64 = AtomicU64::new(0);
+
+/// The value of the TSC at the last tick counted, the next tick is [`CYCLES_PER_TICK`] later
+static LAST_TICK: AtomicU64 = AtomicU64::new(0);
+
+fn write_register(register: u64, value: u32) {
+    let address = VirtAddr::new(REGISTERS.load(Ordering::Relaxed) + register);
+    unsafe { core::ptr::write_volatile(address.as_mut_ptr::<u32>(), value) };
+}
+
+fn set_deadline(tsc: u64) {
+    unsafe { Msr::new(IA32_TSC_DEADLINE).write(tsc) };
+}
+
+/// Returns whatever the CPU has a local APIC whose timer can fire at a TSC deadline
+fn is_supported() -> bool {
+    // The local APIC is bit 9 of EDX of leaf 1 and the TSC-deadline mode bit 24 of ECX, CPUID is only unsafe on
+    // older compilers
+    #[allow(unused_unsafe)]
+    let features = unsafe { __cpuid(1) };
+
+    return features.edx & (1 << 9) != 0 && features.ecx & (1 << 24) != 0;
+}
+
+/// Measures how many cycles of the TSC a tick of the PIT lasts
+pub(super) fn calibrate() -> u64 {
+    // Starting right after a tick means whole ticks are measured
+    let start_tick = super::ticks() + 1;
+
+    while super::ticks() < start_tick {
+        core::hint::spin_loop();
+    }
+
+    let start = unsafe { _rdtsc() };
+
+    while super::ticks() < start_tick + CALIBRATION_TICKS {
+        core::hint::spin_loop();
+    }
+
+    return (unsafe { _rdtsc() } - start) / CALIBRATION_TICKS;
+}
+
+/// Moves the tick from the PIT to the timer of the local APIC in TSC-deadline mode, where every tick is a one-shot
+/// deadline armed by the one before it. Returns the frequency of the TSC in Hz. Must be called once, with the PIT
+/// running and before any process is created
+///
+/// ## Errors
+///
+/// Returns [`KernelError::Unsupported`] if the CPU doesn't have the TSC-deadline mode, or the error of mapping the
+/// registers of the local APIC
+pub fn init() -> Result<u64, KernelError> {
+    if !is_supported() {
+        return Err(KernelError::Unsupported);
+    }
+
+    // The address is in bits 12 and up of the MSR
+    let base = unsafe { Msr::new(IA32_APIC_BASE).read() } & 0x000F_FFFF_FFFF_F000;
+    let registers = crate::memory::mmio::map(PhysAddr::new(base), REGISTERS_SIZE)?;
+
+    REGISTERS.store(registers.as_u64(), Ordering::Relaxed);
+
+    let cycles_per_tick = calibrate();
+    CYCLES_PER_TICK.store(cycles_per_tick, Ordering::Relaxed);
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        // The interrupts of the PICs keep reaching the CPU through the first pin once the local APIC is enabled
+        write_register(REGISTER_LVT_LINT0, LVT_EXTINT);
+        write_register(REGISTER_SPURIOUS, SPURIOUS_APIC_ENABLED | SPURIOUS_VECTOR as u32);
+        write_register(REGISTER_LVT_TIMER, LVT_TSC_DEADLINE | TIMER_VECTOR as u32);
+
+        crate::interrupts::interrupt_manager::disable_irq(0);
+
+        let now = unsafe { _rdtsc() };
+        LAST_TICK.store(now, Ordering::Relaxed);
+        set_deadline(now + cycles_per_tick);
+
+        ACTIVE.store(true, Ordering::Release);
+    });
+
+    return Ok(cycles_per_tick * super::TICKS_PER_SECOND);
+}
+
+/// Returns whatever the tick comes from the local APIC
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Acquire)
+}
+
+/// Returns the frequency of the TSC in Hz, as measured by [`init`] when the tick comes from the local APIC
+pub fn tsc_frequency() -> Option<u64> {
+    is_active().then(|| CYCLES_PER_TICK.load(Ordering::Relaxed) * super::TICKS_PER_SECOND)
+}
+
+/// Called by the interrupt handler of the timer, it counts every tick that passed since the last one (more than one
+/// after the CPU was idle, see [`stop_tick`]) and arms the deadline of the next one
+///
+/// ## Note
+///
+/// This function must not allocate or block since it runs inside an interrupt handler
+pub(crate) fn handle_interrupt() {
+    write_register(REGISTER_END_OF_INTERRUPT, 0);
+
+    let cycles_per_tick = CYCLES_PER_TICK.load(Ordering::Relaxed);
+    let last_tick = LAST_TICK.load(Ordering::Relaxed);
+    let elapsed = (unsafe { _rdtsc() }.saturating_sub(last_tick)) / cycles_per_tick;
+
+    // The next tick keeps to the same beat, however late this interrupt was handled
+    let last_tick = last_tick + elapsed * cycles_per_tick;
+    LAST_TICK.store(last_tick, Ordering::Relaxed);
+    set_deadline(last_tick + cycles_per_tick);
+
+    for _ in 0..elapsed {
+        super::tick();
+    }
+}
+
+/// Lets the idle CPU sleep through the ticks until the next timer on the wheel (at most [`MAX_IDLE_TICKS`] ahead),
+/// instead of waking up on every one of them. Does nothing while the tick comes from the PIT. Must be called with
+/// interrupts disabled, right before the CPU halts, and followed by [`restart_tick`] once it wakes up
+pub(crate) fn stop_tick() {
+    if !is_active() {
+        return;
+    }
+
+    let now = super::ticks();
+    let next = super::wheel::next_deadline().unwrap_or(u64::MAX).clamp(now + 1, now + MAX_IDLE_TICKS);
+
+    let cycles_per_tick = CYCLES_PER_TICK.load(Ordering::Relaxed);
+    set_deadline(LAST_TICK.load(Ordering::Relaxed) + (next - now) * cycles_per_tick);
+}
+
+/// Arms the deadline of the next tick again after [`stop_tick`], a deadline that already passed fires right away
+/// and the interrupt counts the ticks slept through. Must be called with interrupts disabled
+pub(crate) fn restart_tick() {
+    if !is_active() {
+        return;
+    }
+
+    set_deadline(LAST_TICK.load(Ordering::Relaxed) + CYCLES_PER_TICK.load(Ordering::Relaxed));
+}
@@ -0,0 +1,61 @@
+pub mod calendar;
+pub mod clocksource;
+pub mod lapic;
+mod pit;
+pub mod wheel;
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::println;
+
+/// How many times a second the timer interrupt is raised
+pub const TICKS_PER_SECOND: u64 = 100;
+
+/// How many timer interrupts happened since the timer was initialized
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// The time the timer was initialized at, in seconds since the Unix epoch
+static BOOT_TIME: AtomicU64 = AtomicU64::new(0);
+
+/// Configures the PIT to raise the timer interrupt [`TICKS_PER_SECOND`] times a second and reads the date from the
+/// RTC, the wall clock then goes on with the tick
+pub fn init() {
+    BOOT_TIME.store(crate::cmos::read_clock().to_unix(), Ordering::Relaxed);
+    pit::set_frequency(TICKS_PER_SECOND as u32);
+}
+
+/// Moves the tick to the TSC-deadline timer of the local APIC when the CPU has one, so the idle CPU doesn't have to
+/// wake up on every tick. The PIT keeps raising the tick otherwise. Must be called once, after [`init`] and once the
+/// interrupts are enabled, before any process is created
+pub fn init_deadline_timer() {
+    match lapic::init() {
+        Ok(frequency) => println!("Timer: TSC-deadline, TSC at {} MHz", frequency / 1_000_000),
+        Err(error) => println!("Timer: PIT, no TSC-deadline timer ({:?})", error)
+    }
+}
+
+/// Called by the timer interrupt handler on every tick, this also fires the expired timers
+pub(crate) fn tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    wheel::advance(now);
+}
+
+/// Returns how many timer ticks happened since boot
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Converts milliseconds to timer ticks, rounding up so a timeout never expires earlier than requested
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    (ms * TICKS_PER_SECOND + 999) / 1000
+}
+
+/// Returns how many milliseconds passed since boot, with the resolution of a timer tick
+#[allow(dead_code)]
+pub fn uptime_ms() -> u64 {
+    ticks() * 1000 / TICKS_PER_SECOND
+}
+
+/// Returns the current time in seconds since the Unix epoch, as the RTC told at boot and counted by the tick since
+pub fn unix_time() -> u64 {
+    BOOT_TIME.load(Ordering::Relaxed) + ticks() / TICKS_PER_SECOND
+}
@@ -0,0 +1,27 @@
+use x86_64::instructions::port::Port;
+
+/// The frequency of the oscillator that drives the PIT (Programmable Interval Timer), in Hz
+pub const PIT_BASE_FREQUENCY: u32 = 1_193_182;
+
+const CHANNEL_0_DATA: u16 = 0x40;
+const COMMAND: u16 = 0x43;
+
+/// Selects channel 0, sends the divisor as low byte followed by the high byte and
+/// uses mode 3 (square wave generator) with a binary divisor
+const CHANNEL_0_SQUARE_WAVE: u8 = 0x36;
+
+/// Programs the channel 0 of the PIT, which is connected to the IRQ 0, to raise an interrupt `frequency` times a second
+///
+/// The divisor used by the PIT is only 16 bits wide, so frequencies lower than ~19 Hz are clamped
+pub fn set_frequency(frequency: u32) {
+    let divisor = (PIT_BASE_FREQUENCY / frequency.max(1)).clamp(1, u16::MAX as u32) as u16;
+
+    let mut command_port: Port<u8> = Port::new(COMMAND);
+    let mut data_port: Port<u8> = Port::new(CHANNEL_0_DATA);
+
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        command_port.write(CHANNEL_0_SQUARE_WAVE);
+        data_port.write((divisor & 0xFF) as u8);
+        data_port.write((divisor >> 8) as u8);
+    });
+}
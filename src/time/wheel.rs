@@ -0,0 +1,210 @@
+use core::cell::{Cell, UnsafeCell};
+use core::marker::PhantomPinned;
+use core::ptr::NonNull;
+use core::task::Waker;
+use crate::task::thread::Thread;
+use crate::utils::IrqCell;
+use crate::utils::list::{Link, Linked, List};
+use crate::workqueue::{WorkItem, WorkQueue};
+
+/// How many slots the timer wheel has, a timer whose deadline is further than this amount
+/// of ticks just stays in its slot for more than one turn of the wheel
+const WHEEL_SLOTS: usize = 64;
+
+static WHEEL: IrqCell<TimerWheel> = IrqCell::new(TimerWheel::new());
+
+/// What should be woken up once a timer expires
+pub enum TimerTarget {
+    /// An async task, through its waker
+    Waker(Option<Waker>),
+    /// A kernel thread blocked in [`crate::task::sleep_ms`]
+    Thread(NonNull<Thread>),
+    /// A work item, queued on the work queue, for timers that do more than an interrupt handler can (like taking
+    /// locks)
+    Work(&'static WorkQueue, &'static WorkItem)
+}
+
+/// A timer that can be hung on the timer wheel.
+///
+/// The entry is linked into the wheel intrusively, so arming a timer never allocates, but this also
+/// means the entry must not move while armed. It's removed from the wheel when dropped.
+pub struct TimerEntry {
+    link: Link<TimerEntry>,
+    /// The tick at which this timer expires
+    deadline: Cell<u64>,
+    /// Only accessed with the wheel held
+    target: UnsafeCell<TimerTarget>,
+    _pinned: PhantomPinned
+}
+
+// The entry is only touched with the wheel held (interrupts disabled) or by its owner before it's armed
+unsafe impl Send for TimerEntry {}
+unsafe impl Sync for TimerEntry {}
+
+unsafe impl Linked for TimerEntry {
+    fn link(&self) -> &Link<Self> {
+        &self.link
+    }
+}
+
+impl TimerEntry {
+    pub fn new(deadline: u64, target: TimerTarget) -> Self {
+        TimerEntry {
+            link: Link::new(),
+            deadline: Cell::new(deadline),
+            target: UnsafeCell::new(target),
+            _pinned: PhantomPinned
+        }
+    }
+
+    pub fn deadline(&self) -> u64 {
+        self.deadline.get()
+    }
+
+    pub fn has_expired(&self) -> bool {
+        super::ticks() >= self.deadline()
+    }
+}
+
+impl Drop for TimerEntry {
+    fn drop(&mut self) {
+        if self.link.is_linked() {
+            let entry = NonNull::from(&*self);
+            WHEEL.with(|wheel| unsafe { wheel.remove(entry) });
+        }
+    }
+}
+
+/// A hashed timing wheel: every timer lives in the slot given by its deadline modulo [`WHEEL_SLOTS`],
+/// so each tick only has to look at the timers of a single slot
+struct TimerWheel {
+    slots: [List<TimerEntry>; WHEEL_SLOTS]
+}
+
+impl TimerWheel {
+    const fn new() -> Self {
+        const EMPTY_SLOT: List<TimerEntry> = List::new();
+
+        TimerWheel {
+            slots: [EMPTY_SLOT; WHEEL_SLOTS]
+        }
+    }
+
+    unsafe fn insert(&mut self, entry: NonNull<TimerEntry>) {
+        let entry_ref = entry.as_ref();
+
+        // A deadline that already passed would only be seen after a whole turn of the wheel
+        let deadline = entry_ref.deadline().max(super::ticks() + 1);
+        entry_ref.deadline.set(deadline);
+
+        self.slots[deadline as usize % WHEEL_SLOTS].push_back(entry);
+    }
+
+    unsafe fn remove(&mut self, entry: NonNull<TimerEntry>) {
+        let slot = entry.as_ref().deadline() as usize % WHEEL_SLOTS;
+        self.slots[slot].remove(entry);
+    }
+
+    /// Returns the earliest deadline of the timers on the wheel
+    fn next_deadline(&self) -> Option<u64> {
+        self.slots.iter().flat_map(|slot| slot.iter()).map(|entry| entry.deadline()).min()
+    }
+
+    /// Fires every timer of the slot of `now` whose deadline is `now` or earlier
+    fn advance(&mut self, now: u64) {
+        let mut cursor = self.slots[now as usize % WHEEL_SLOTS].cursor_front_mut();
+
+        while let Some(entry) = cursor.current() {
+            let entry = unsafe { entry.as_ref() };
+
+            if entry.deadline() > now {
+                cursor.move_next();
+                continue;
+            }
+
+            cursor.remove_current();
+
+            // `wake_by_ref` is used so the waker is never dropped (and deallocated) inside the interrupt
+            match unsafe { &*entry.target.get() } {
+                TimerTarget::Waker(Some(waker)) => waker.wake_by_ref(),
+                TimerTarget::Waker(None) => {},
+                TimerTarget::Thread(thread) => crate::sched::wake(*thread),
+                TimerTarget::Work(queue, item) => {
+                    queue.enqueue(item);
+                }
+            }
+        }
+    }
+}
+
+/// Hangs `entry` on the timer wheel
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that `entry` doesn't move until it's dropped
+pub unsafe fn insert(entry: &TimerEntry) {
+    if entry.link.is_linked() {
+        return;
+    }
+
+    let entry = NonNull::from(entry);
+    WHEEL.with(|wheel| wheel.insert(entry));
+}
+
+/// Sets the waker woken up when `entry` expires, hanging the entry on the timer wheel if it isn't already.
+/// The old waker is returned so it can be dropped after interrupts are enabled again
+///
+/// ## Safety
+///
+/// Same as [`insert`]
+pub unsafe fn set_waker(entry: &TimerEntry, waker: &Waker) -> Option<Waker> {
+    let entry = NonNull::from(entry);
+
+    WHEEL.with(|wheel| {
+        let target = &mut *entry.as_ref().target.get();
+        let old_target = core::mem::replace(target, TimerTarget::Waker(Some(waker.clone())));
+
+        if !entry.as_ref().link.is_linked() {
+            wheel.insert(entry);
+        }
+
+        match old_target {
+            TimerTarget::Waker(old_waker) => old_waker,
+            TimerTarget::Thread(_) | TimerTarget::Work(..) => None
+        }
+    })
+}
+
+/// Moves `entry` to a new deadline, if the entry is on the timer wheel it stays there
+///
+/// ## Safety
+///
+/// Same as [`insert`]
+pub unsafe fn set_deadline(entry: &TimerEntry, deadline: u64) {
+    let entry = NonNull::from(entry);
+
+    WHEEL.with(|wheel| {
+        let linked = entry.as_ref().link.is_linked();
+
+        if linked {
+            wheel.remove(entry);
+        }
+
+        entry.as_ref().deadline.set(deadline);
+
+        if linked {
+            wheel.insert(entry);
+        }
+    });
+}
+
+/// Fires the expired timers, called on every tick
+pub(super) fn advance(now: u64) {
+    WHEEL.with(|wheel| wheel.advance(now));
+}
+
+/// Returns the tick the next timer on the wheel expires at, if there's any timer. Looks at every timer, so it's
+/// only meant for the idle CPU
+pub(super) fn next_deadline() -> Option<u64> {
+    WHEEL.with(|wheel| wheel.next_deadline())
+}
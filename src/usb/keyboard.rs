@@ -0,0 +1,98 @@
+use crate::task::keyboard::add_scancode;
+
+/// The size of a report of the boot protocol: the modifiers, a reserved byte and up to 6 keys held down
+pub const REPORT_SIZE: usize = 8;
+
+/// Sent in place of every key when too many of them are held down at once
+const ERROR_ROLL_OVER: u8 = 0x01;
+
+/// The first usage ID in [`SCANCODES`], the ones before it aren't keys
+const FIRST_USAGE: u8 = 0x04;
+
+/// Marks the scancodes of [`SCANCODES`] and [`MODIFIERS`] that are sent after the extended prefix, it's never part of
+/// a scancode of set 1 since that bit is the one of the releases
+const EXTENDED: u8 = 0x80;
+
+/// Sent before the scancode of an extended key
+const SCANCODE_EXTENDED_PREFIX: u8 = 0xE0;
+
+/// Set in the scancode of a key that is released
+const SCANCODE_RELEASED: u8 = 0x80;
+
+/// The scancodes (set 1) of the modifiers, in the order of the bits of the first byte of a report: left Ctrl,
+/// Shift, Alt and GUI and then the right ones
+const MODIFIERS: [u8; 8] = [0x1D, 0x2A, 0x38, EXTENDED | 0x5B, EXTENDED | 0x1D, 0x36, EXTENDED | 0x38, EXTENDED | 0x5C];
+
+/// The scancodes (set 1) of the usage IDs of the keys, starting at [`FIRST_USAGE`] (the A key) and going up to the
+/// up arrow. The keys without a single scancode (Print Screen and Pause) are 0
+const SCANCODES: [u8; 79] = [
+    0x1E, 0x30, 0x2E, 0x20, 0x12, 0x21, 0x22, 0x23, 0x17, 0x24, 0x25, 0x26, 0x32, 0x31, 0x18, 0x19, 0x10, 0x13, 0x1F,
+    0x14, 0x16, 0x2F, 0x11, 0x2D, 0x15, 0x2C,
+    0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B,
+    0x1C, 0x01, 0x0E, 0x0F, 0x39, 0x0C, 0x0D, 0x1A, 0x1B, 0x2B, 0x2B, 0x27, 0x28, 0x29, 0x33, 0x34, 0x35, 0x3A,
+    0x3B, 0x3C, 0x3D, 0x3E, 0x3F, 0x40, 0x41, 0x42, 0x43, 0x44, 0x57, 0x58,
+    0x00, 0x46, 0x00, EXTENDED | 0x52, EXTENDED | 0x47, EXTENDED | 0x49, EXTENDED | 0x53, EXTENDED | 0x4F,
+    EXTENDED | 0x51, EXTENDED | 0x4D, EXTENDED | 0x4B, EXTENDED | 0x50, EXTENDED | 0x48
+];
+
+/// Turns the reports of a keyboard speaking the boot protocol into the scancodes a PS/2 keyboard would send, so
+/// they go through the same [`crate::task::keyboard`] queue. A report tells which keys are held down, so the presses
+/// and releases come from comparing it with the previous one
+pub struct BootKeyboard {
+    previous: [u8; REPORT_SIZE]
+}
+
+impl BootKeyboard {
+    pub const fn new() -> Self {
+        BootKeyboard {
+            previous: [0; REPORT_SIZE]
+        }
+    }
+
+    /// Hands the scancodes of the keys pressed and released since the previous report over to the keyboard task
+    ///
+    /// ## Note
+    ///
+    /// This function doesn't allocate or block, so it can be called inside an interrupt handler
+    pub fn handle_report(&mut self, report: &[u8; REPORT_SIZE]) {
+        // The keys held down are unknown, they're considered the same as before
+        if report[2..].contains(&ERROR_ROLL_OVER) {
+            return;
+        }
+
+        let changed = self.previous[0] ^ report[0];
+
+        for (bit, &scancode) in MODIFIERS.iter().enumerate() {
+            if changed >> bit & 1 != 0 {
+                send(scancode, report[0] >> bit & 1 == 0);
+            }
+        }
+
+        for &usage in self.previous[2..].iter().filter(|&&usage| usage != 0 && !report[2..].contains(&usage)) {
+            send(scancode(usage), true);
+        }
+
+        for &usage in report[2..].iter().filter(|&&usage| usage != 0 && !self.previous[2..].contains(&usage)) {
+            send(scancode(usage), false);
+        }
+
+        self.previous = *report;
+    }
+}
+
+/// Returns the scancode of the key with the given usage ID, or 0 if it has none
+fn scancode(usage: u8) -> u8 {
+    usage.checked_sub(FIRST_USAGE).and_then(|index| SCANCODES.get(index as usize)).copied().unwrap_or(0)
+}
+
+fn send(scancode: u8, released: bool) {
+    if scancode == 0 {
+        return;
+    }
+
+    if scancode & EXTENDED != 0 {
+        add_scancode(SCANCODE_EXTENDED_PREFIX);
+    }
+
+    add_scancode(scancode & !EXTENDED | if released { SCANCODE_RELEASED } else { 0 });
+}
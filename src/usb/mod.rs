@@ -0,0 +1,149 @@
+mod keyboard;
+pub mod xhci;
+
+use crate::drivers::registry;
+
+/// The standard requests sent to the control endpoint of a device, and the ones of the HID class
+const REQUEST_GET_DESCRIPTOR: u8 = 6;
+const REQUEST_SET_CONFIGURATION: u8 = 9;
+const REQUEST_HID_SET_IDLE: u8 = 0x0A;
+const REQUEST_HID_SET_PROTOCOL: u8 = 0x0B;
+
+/// The request types of the requests above: to the device or to an interface, standard or class specific
+const REQUEST_TYPE_DEVICE_IN: u8 = 0x80;
+const REQUEST_TYPE_DEVICE_OUT: u8 = 0x00;
+const REQUEST_TYPE_CLASS_INTERFACE_OUT: u8 = 0x21;
+
+/// The types of the descriptors
+pub const DESCRIPTOR_DEVICE: u8 = 1;
+pub const DESCRIPTOR_CONFIGURATION: u8 = 2;
+const DESCRIPTOR_INTERFACE: u8 = 4;
+const DESCRIPTOR_ENDPOINT: u8 = 5;
+
+/// The class, the subclass and the protocol of the interface of a keyboard that speaks the boot protocol
+const BOOT_KEYBOARD: [u8; 3] = [0x03, 0x01, 0x01];
+
+/// The protocol set with [`SetupPacket::set_protocol`] to get the fixed reports of the boot protocol
+pub const PROTOCOL_BOOT: u16 = 0;
+
+/// The packet that starts every transfer on the control endpoint of a device
+#[derive(Debug, Copy, Clone)]
+pub struct SetupPacket {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    /// How many bytes the data stage has, 0 when there's none
+    pub length: u16
+}
+
+impl SetupPacket {
+    /// Reads the first `length` bytes of the descriptor of the given type (and index 0)
+    pub fn get_descriptor(kind: u8, length: u16) -> Self {
+        SetupPacket {
+            request_type: REQUEST_TYPE_DEVICE_IN,
+            request: REQUEST_GET_DESCRIPTOR,
+            value: (kind as u16) << 8,
+            index: 0,
+            length
+        }
+    }
+
+    pub fn set_configuration(configuration: u8) -> Self {
+        SetupPacket {
+            request_type: REQUEST_TYPE_DEVICE_OUT,
+            request: REQUEST_SET_CONFIGURATION,
+            value: configuration as u16,
+            index: 0,
+            length: 0
+        }
+    }
+
+    pub fn set_protocol(interface: u8, protocol: u16) -> Self {
+        SetupPacket {
+            request_type: REQUEST_TYPE_CLASS_INTERFACE_OUT,
+            request: REQUEST_HID_SET_PROTOCOL,
+            value: protocol,
+            index: interface as u16,
+            length: 0
+        }
+    }
+
+    /// Makes the HID device only send a report when something changes, instead of repeating the last one
+    pub fn set_idle(interface: u8) -> Self {
+        SetupPacket {
+            request_type: REQUEST_TYPE_CLASS_INTERFACE_OUT,
+            request: REQUEST_HID_SET_IDLE,
+            value: 0,
+            index: interface as u16,
+            length: 0
+        }
+    }
+
+    /// Returns the packet as the two words it's sent as, its fields are in little endian one after the other
+    pub fn to_words(self) -> [u32; 2] {
+        let first = self.request_type as u32 | (self.request as u32) << 8 | (self.value as u32) << 16;
+        return [first, self.index as u32 | (self.length as u32) << 16];
+    }
+}
+
+/// The interface of a keyboard speaking the boot protocol, found by [`find_boot_keyboard`]
+#[derive(Debug, Copy, Clone)]
+pub struct KeyboardInterface {
+    /// The value of the configuration the interface is part of, given to [`SetupPacket::set_configuration`]
+    pub configuration: u8,
+    pub interface: u8,
+    /// The address of the interrupt IN endpoint the reports come from
+    pub endpoint: u8,
+    pub max_packet: u16,
+    /// How often the endpoint is polled, in frames for the low and full speed devices and as an exponent otherwise
+    pub interval: u8
+}
+
+/// Looks for a boot keyboard in `configuration`, the configuration descriptor of a device followed by the
+/// descriptors of its interfaces and endpoints
+pub fn find_boot_keyboard(configuration: &[u8]) -> Option<KeyboardInterface> {
+    let value = *configuration.get(5)?;
+
+    let mut offset = 0;
+    let mut interface = None;
+
+    // Every descriptor starts with its length and its type, the endpoints follow the interface they belong to
+    while offset + 2 <= configuration.len() {
+        let length = configuration[offset] as usize;
+
+        if length < 2 || offset + length > configuration.len() {
+            break;
+        }
+
+        let descriptor = &configuration[offset..offset + length];
+
+        match descriptor[1] {
+            DESCRIPTOR_INTERFACE if length >= 9 => {
+                interface = (descriptor[5..8] == BOOT_KEYBOARD).then_some(descriptor[2]);
+            },
+            DESCRIPTOR_ENDPOINT if length >= 7 => {
+                // Bit 7 of the address is set for the IN endpoints, and 3 in the attributes is an interrupt one
+                if let Some(interface) = interface.filter(|_| descriptor[2] & 0x80 != 0 && descriptor[3] & 0b11 == 3) {
+                    return Some(KeyboardInterface {
+                        configuration: value,
+                        interface,
+                        endpoint: descriptor[2],
+                        max_packet: u16::from_le_bytes([descriptor[4], descriptor[5]]) & 0x7FF,
+                        interval: descriptor[6]
+                    });
+                }
+            },
+            _ => {}
+        }
+
+        offset += length;
+    }
+
+    return None;
+}
+
+/// Registers the probes of the USB host controllers. Must be called once, before [`registry::probe_all`]
+pub fn init() {
+    registry::register(xhci::probe);
+}
@@ -0,0 +1,855 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, AtomicBool, AtomicU16, Ordering};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::drivers::registry::Driver;
+use crate::memory::dma::DmaBuffer;
+use crate::pci::PciDevice;
+use crate::println;
+use crate::time;
+use crate::usb::keyboard::{BootKeyboard, REPORT_SIZE};
+use crate::usb::{self, SetupPacket};
+use crate::utils::error::KernelError;
+use crate::utils::IrqCell;
+
+/// The class, the subclass and the programming interface of an xHCI controller on the PCI bus
+const XHCI_CLASS: (u8, u8, u8) = (0x0C, 0x03, 0x30);
+
+const PCI_COMMAND: u16 = 0x04;
+const PCI_COMMAND_MEMORY: u32 = 1 << 1;
+const PCI_COMMAND_BUS_MASTER: u32 = 1 << 2;
+
+/// The BAR with the registers of the controller, always a 64 bits one
+const PCI_BAR0: u16 = 0x10;
+const PCI_BAR1: u16 = 0x14;
+
+/// The register with the IRQ line the firmware routed the interrupt of the device to, in its lowest byte
+const PCI_INTERRUPT_LINE: u16 = 0x3C;
+
+/// The capability registers, at the start of the BAR
+const CAPABILITY_LENGTH: usize = 0x00;
+const CAPABILITY_STRUCTURAL_1: usize = 0x04;
+const CAPABILITY_STRUCTURAL_2: usize = 0x08;
+const CAPABILITY_PARAMETERS_1: usize = 0x10;
+const CAPABILITY_DOORBELL_OFFSET: usize = 0x14;
+const CAPABILITY_RUNTIME_OFFSET: usize = 0x18;
+
+/// Set in the first capability parameters when the contexts are 64 bytes instead of 32
+const PARAMETERS_CONTEXT_64: u32 = 1 << 2;
+
+/// The operational registers, as offsets from the end of the capability registers
+const OPERATIONAL_COMMAND: usize = 0x00;
+const OPERATIONAL_STATUS: usize = 0x04;
+const OPERATIONAL_COMMAND_RING: usize = 0x18;
+const OPERATIONAL_CONTEXTS: usize = 0x30;
+const OPERATIONAL_CONFIGURE: usize = 0x38;
+const OPERATIONAL_PORTS: usize = 0x400;
+
+const COMMAND_RUN: u32 = 1 << 0;
+const COMMAND_RESET: u32 = 1 << 1;
+const COMMAND_INTERRUPTS: u32 = 1 << 2;
+
+/// The bits of the status, the event interrupt is cleared by writing 1 to it
+const STATUS_HALTED: u32 = 1 << 0;
+const STATUS_EVENT_INTERRUPT: u32 = 1 << 3;
+const STATUS_NOT_READY: u32 = 1 << 11;
+
+/// The bits of the status and control register of a port. The enabled bit is cleared (disabling the port) by writing
+/// 1 to it, like the changes
+const PORT_CONNECTED: u32 = 1 << 0;
+const PORT_ENABLED: u32 = 1 << 1;
+const PORT_RESET: u32 = 1 << 4;
+const PORT_POWER: u32 = 1 << 9;
+const PORT_RESET_CHANGE: u32 = 1 << 21;
+const PORT_CHANGES: u32 = 0x7F << 17;
+
+/// The registers of the first interrupter, as offsets from the runtime registers
+const INTERRUPTER_MANAGEMENT: usize = 0x20;
+const INTERRUPTER_TABLE_SIZE: usize = 0x28;
+const INTERRUPTER_TABLE: usize = 0x30;
+const INTERRUPTER_DEQUEUE: usize = 0x38;
+
+/// The pending bit is cleared by writing 1 to it
+const MANAGEMENT_PENDING: u32 = 1 << 0;
+const MANAGEMENT_ENABLE: u32 = 1 << 1;
+
+/// Set in the dequeue pointer of the event ring, it's cleared by writing 1 to it once the events are handled
+const DEQUEUE_HANDLER_BUSY: u64 = 1 << 3;
+
+/// The extended capability through which the firmware hands the controller over to the OS
+const EXTENDED_LEGACY_SUPPORT: u32 = 1;
+const LEGACY_BIOS_OWNED: u32 = 1 << 16;
+const LEGACY_OS_OWNED: u32 = 1 << 24;
+
+/// The types of the TRBs (Transfer Request Blocks), in bits 10 to 15 of their last word
+const TRB_NORMAL: u32 = 1;
+const TRB_SETUP: u32 = 2;
+const TRB_DATA: u32 = 3;
+const TRB_STATUS: u32 = 4;
+const TRB_LINK: u32 = 6;
+const TRB_ENABLE_SLOT: u32 = 9;
+const TRB_DISABLE_SLOT: u32 = 10;
+const TRB_ADDRESS_DEVICE: u32 = 11;
+const TRB_CONFIGURE_ENDPOINT: u32 = 12;
+const TRB_EVALUATE_CONTEXT: u32 = 13;
+const TRB_TRANSFER_EVENT: u32 = 32;
+const TRB_COMMAND_COMPLETION: u32 = 33;
+
+/// The bits of the last word of a TRB
+const TRB_CYCLE: u32 = 1 << 0;
+const TRB_TOGGLE_CYCLE: u32 = 1 << 1;
+const TRB_INTERRUPT_ON_SHORT: u32 = 1 << 2;
+const TRB_INTERRUPT_ON_COMPLETION: u32 = 1 << 5;
+const TRB_IMMEDIATE_DATA: u32 = 1 << 6;
+const TRB_DIRECTION_IN: u32 = 1 << 16;
+
+/// The transfer type of a setup TRB with a data stage going to the memory
+const TRB_TRANSFER_IN: u32 = 3 << 16;
+
+/// The completion codes of the events
+const COMPLETION_SUCCESS: u32 = 1;
+const COMPLETION_SHORT_PACKET: u32 = 13;
+
+/// How many TRBs a ring of a page has, the last one of a transfer or command ring is the link back to its start
+const TRBS_PER_RING: usize = 4096 / 16;
+
+/// The most device slots enabled, a slot is only kept for the keyboards
+const MAX_SLOTS: u32 = 16;
+
+/// The most controllers and the most keyboards per controller handled
+const MAX_CONTROLLERS: usize = 4;
+const MAX_KEYBOARDS: usize = 4;
+
+/// The types of the endpoints in their context
+const ENDPOINT_CONTROL: u32 = 4;
+const ENDPOINT_INTERRUPT_IN: u32 = 7;
+
+/// How many times an endpoint retries a failed transaction before it reports an error
+const ENDPOINT_ERROR_COUNT: u32 = 3;
+
+/// The index of the context of the default control endpoint, which is also its doorbell target
+const CONTROL_ENDPOINT: u32 = 1;
+
+/// The speeds of a port, as it reports them
+const SPEED_FULL: u32 = 1;
+const SPEED_LOW: u32 = 2;
+const SPEED_HIGH: u32 = 3;
+
+/// How long the controller and the ports can take to reset, and a command or a control transfer to complete
+const RESET_TIMEOUT_MS: u64 = 1000;
+const COMMAND_TIMEOUT_MS: u64 = 500;
+
+/// How often the controllers without an interrupt are checked for reports
+const POLL_MS: u64 = 10;
+
+/// The controllers found by [`probe`]
+static CONTROLLERS: IrqCell<[Option<Xhci>; MAX_CONTROLLERS]> = IrqCell::new([None, None, None, None]);
+
+/// The IRQ lines the interrupt handler is registered on, one bit each
+static IRQ_LINES: AtomicU16 = AtomicU16::new(0);
+
+/// Whatever the thread that polls the controllers without an interrupt was started
+static POLLING: AtomicBool = AtomicBool::new(false);
+
+fn read(base: VirtAddr, offset: usize) -> u32 {
+    unsafe { core::ptr::read_volatile((base + offset).as_ptr::<u32>()) }
+}
+
+fn write(base: VirtAddr, offset: usize, value: u32) {
+    unsafe { core::ptr::write_volatile((base + offset).as_mut_ptr::<u32>(), value) };
+}
+
+/// Writes a 64 bits register as two halves, low one first, since not every controller takes 64 bits accesses
+fn write_u64(base: VirtAddr, offset: usize, value: u64) {
+    write(base, offset, value as u32);
+    write(base, offset + 4, (value >> 32) as u32);
+}
+
+/// Polls `condition` until it's true, or fails with [`KernelError::Timeout`] after `ms` milliseconds
+fn wait_for(ms: u64, mut condition: impl FnMut() -> bool) -> Result<(), KernelError> {
+    let deadline = time::ticks() + time::ms_to_ticks(ms);
+
+    while !condition() {
+        if time::ticks() > deadline {
+            return Err(KernelError::Timeout);
+        }
+
+        core::hint::spin_loop();
+    }
+
+    Ok(())
+}
+
+/// A ring of TRBs the controller reads, for the commands or the transfers of an endpoint. The cycle bit of a TRB
+/// tells the controller whatever it was written in this lap around the ring
+struct Ring {
+    memory: DmaBuffer,
+    enqueue: usize,
+    cycle: bool
+}
+
+impl Ring {
+    fn new() -> Result<Self, KernelError> {
+        Ok(Ring { memory: DmaBuffer::new(1)?, enqueue: 0, cycle: true })
+    }
+
+    fn address(&self) -> PhysAddr {
+        self.memory.physical(0)
+    }
+
+    /// Writes a TRB, the word with the cycle bit last so the controller never sees half of it
+    fn write(&self, index: usize, trb: [u32; 4]) {
+        let words = unsafe { (self.memory.page(0) as *mut u32).add(index * 4) };
+
+        unsafe {
+            for (word, &value) in trb[..3].iter().enumerate() {
+                words.add(word).write_volatile(value);
+            }
+
+            fence(Ordering::Release);
+            words.add(3).write_volatile(trb[3]);
+        }
+    }
+
+    /// Adds a TRB to the ring, the cycle bit is set by the ring. Returns its physical address, which is how the
+    /// events refer to it
+    fn push(&mut self, trb: [u32; 4]) -> PhysAddr {
+        let address = self.address() + self.enqueue * 16;
+        let cycle = if self.cycle { TRB_CYCLE } else { 0 };
+
+        self.write(self.enqueue, [trb[0], trb[1], trb[2], trb[3] | cycle]);
+        self.enqueue += 1;
+
+        // The link TRB takes the controller back to the start and flips the cycle bit it expects
+        if self.enqueue == TRBS_PER_RING - 1 {
+            let start = self.address().as_u64();
+            let link = TRB_LINK << 10 | TRB_TOGGLE_CYCLE | cycle;
+
+            self.write(self.enqueue, [start as u32, (start >> 32) as u32, 0, link]);
+            self.enqueue = 0;
+            self.cycle = !self.cycle;
+        }
+
+        return address;
+    }
+}
+
+/// The ring the controller writes its events to, a single segment described by a table of one entry
+struct EventRing {
+    memory: DmaBuffer,
+    table: DmaBuffer,
+    dequeue: usize,
+    cycle: bool
+}
+
+impl EventRing {
+    fn new() -> Result<Self, KernelError> {
+        let ring = EventRing { memory: DmaBuffer::new(1)?, table: DmaBuffer::new(1)?, dequeue: 0, cycle: true };
+        let entry = ring.table.page(0);
+
+        unsafe {
+            (entry as *mut u64).write_volatile(ring.memory.physical(0).as_u64());
+            (entry.add(8) as *mut u32).write_volatile(TRBS_PER_RING as u32);
+        }
+
+        Ok(ring)
+    }
+
+    /// Takes the next event, if the controller wrote one
+    fn pop(&mut self) -> Option<[u32; 4]> {
+        let words = unsafe { (self.memory.page(0) as *const u32).add(self.dequeue * 4) };
+        let control = unsafe { words.add(3).read_volatile() };
+
+        if (control & TRB_CYCLE != 0) != self.cycle {
+            return None;
+        }
+
+        // The rest of the event is only valid once the cycle bit says so
+        fence(Ordering::Acquire);
+
+        let event = unsafe {
+            [words.read_volatile(), words.add(1).read_volatile(), words.add(2).read_volatile(), control]
+        };
+
+        self.dequeue += 1;
+
+        if self.dequeue == TRBS_PER_RING {
+            self.dequeue = 0;
+            self.cycle = !self.cycle;
+        }
+
+        return Some(event);
+    }
+
+    fn dequeue_address(&self) -> PhysAddr {
+        self.memory.physical(0) + self.dequeue * 16
+    }
+}
+
+/// Returns the type of a TRB
+fn trb_type(trb: &[u32; 4]) -> u32 {
+    trb[3] >> 10 & 0x3F
+}
+
+/// A device addressed by the controller, with the memory of its contexts and of its default control endpoint
+struct Device {
+    slot: u8,
+    /// The input context (what the commands give the controller) and the device context (what the controller keeps)
+    input: DmaBuffer,
+    output: DmaBuffer,
+    control: Ring,
+    /// The page the descriptors are read to, and then the reports of a keyboard
+    data: DmaBuffer
+}
+
+/// A keyboard whose reports come from an interrupt endpoint
+struct Keyboard {
+    device: Device,
+    /// The index of the context of the endpoint, which is also its doorbell target
+    endpoint: u32,
+    ring: Ring,
+    state: BootKeyboard
+}
+
+impl Keyboard {
+    /// Gives the endpoint a TRB for the next report
+    fn queue_report(&mut self) {
+        let buffer = self.device.data.physical(0).as_u64();
+        let control = TRB_NORMAL << 10 | TRB_INTERRUPT_ON_COMPLETION | TRB_INTERRUPT_ON_SHORT;
+
+        self.ring.push([buffer as u32, (buffer >> 32) as u32, REPORT_SIZE as u32, control]);
+    }
+}
+
+struct Xhci {
+    operational: VirtAddr,
+    runtime: VirtAddr,
+    doorbells: VirtAddr,
+    /// The size of a context, 32 or 64 bytes
+    context_size: usize,
+    ports: u8,
+    /// The device context base address array, with the address of the context of every slot
+    contexts: DmaBuffer,
+    /// The pages the controller keeps for itself and the array with their addresses, if it wants any
+    _scratchpad: Option<(DmaBuffer, DmaBuffer)>,
+    commands: Ring,
+    events: EventRing,
+    keyboards: Vec<Keyboard>
+}
+
+impl Xhci {
+    /// Hands the controller over from the firmware, resets it and sets up its rings. The controller is left
+    /// running with its interrupts disabled, the events are polled until [`Xhci::enable_interrupts`]
+    fn new(registers: VirtAddr) -> Result<Self, KernelError> {
+        let capability_length = read(registers, CAPABILITY_LENGTH) as u8 as usize;
+        let structural_1 = read(registers, CAPABILITY_STRUCTURAL_1);
+        let structural_2 = read(registers, CAPABILITY_STRUCTURAL_2);
+        let parameters_1 = read(registers, CAPABILITY_PARAMETERS_1);
+
+        let operational = registers + capability_length;
+        let runtime = registers + (read(registers, CAPABILITY_RUNTIME_OFFSET) & !0x1F) as usize;
+        let doorbells = registers + (read(registers, CAPABILITY_DOORBELL_OFFSET) & !0x3) as usize;
+
+        take_ownership(registers, parameters_1)?;
+
+        write(operational, OPERATIONAL_COMMAND, read(operational, OPERATIONAL_COMMAND) & !COMMAND_RUN);
+        wait_for(RESET_TIMEOUT_MS, || read(operational, OPERATIONAL_STATUS) & STATUS_HALTED != 0)?;
+
+        write(operational, OPERATIONAL_COMMAND, COMMAND_RESET);
+        wait_for(RESET_TIMEOUT_MS, || read(operational, OPERATIONAL_COMMAND) & COMMAND_RESET == 0)?;
+        wait_for(RESET_TIMEOUT_MS, || read(operational, OPERATIONAL_STATUS) & STATUS_NOT_READY == 0)?;
+
+        let slots = (structural_1 & 0xFF).min(MAX_SLOTS);
+        let ports = (structural_1 >> 24) as u8;
+
+        let contexts = DmaBuffer::new(1)?;
+
+        // The count of the scratchpad pages is split in two fields of the second structural parameters
+        let scratchpad_pages = ((structural_2 >> 21 & 0x1F) << 5 | structural_2 >> 27) as usize;
+
+        let scratchpad = match scratchpad_pages {
+            0 => None,
+            pages if pages * 8 > 4096 => return Err(KernelError::Unsupported),
+            pages => {
+                let (array, pages) = (DmaBuffer::new(1)?, DmaBuffer::new(pages)?);
+
+                for page in 0..pages.pages() {
+                    unsafe { (array.page(0) as *mut u64).add(page).write_volatile(pages.physical(page).as_u64()) };
+                }
+
+                // The first entry of the array of the contexts is the one of the scratchpad
+                unsafe { (contexts.page(0) as *mut u64).write_volatile(array.physical(0).as_u64()) };
+                Some((array, pages))
+            }
+        };
+
+        let controller = Xhci {
+            operational,
+            runtime,
+            doorbells,
+            context_size: if parameters_1 & PARAMETERS_CONTEXT_64 != 0 { 64 } else { 32 },
+            ports,
+            contexts,
+            _scratchpad: scratchpad,
+            commands: Ring::new()?,
+            events: EventRing::new()?,
+            keyboards: Vec::with_capacity(MAX_KEYBOARDS)
+        };
+
+        write(operational, OPERATIONAL_CONFIGURE, slots);
+        write_u64(operational, OPERATIONAL_CONTEXTS, controller.contexts.physical(0).as_u64());
+        write_u64(operational, OPERATIONAL_COMMAND_RING, controller.commands.address().as_u64() | TRB_CYCLE as u64);
+
+        // The table is written last, that's what makes the interrupter use the ring
+        write(runtime, INTERRUPTER_TABLE_SIZE, 1);
+        write_u64(runtime, INTERRUPTER_DEQUEUE, controller.events.dequeue_address().as_u64());
+        write_u64(runtime, INTERRUPTER_TABLE, controller.events.table.physical(0).as_u64());
+
+        write(operational, OPERATIONAL_COMMAND, COMMAND_RUN);
+        wait_for(RESET_TIMEOUT_MS, || read(operational, OPERATIONAL_STATUS) & STATUS_HALTED == 0)?;
+
+        Ok(controller)
+    }
+
+    fn ring_doorbell(&self, slot: u8, target: u32) {
+        fence(Ordering::SeqCst);
+        write(self.doorbells, slot as usize * 4, target);
+    }
+
+    /// Tells the controller the events up to the current one were handled
+    fn update_dequeue(&self) {
+        write_u64(self.runtime, INTERRUPTER_DEQUEUE, self.events.dequeue_address().as_u64() | DEQUEUE_HANDLER_BUSY);
+    }
+
+    /// Waits for the event of the given type about the TRB at `trb`, skipping the other ones (like the changes
+    /// of the ports). Returns the completion code of the event and the event
+    fn wait_event(&mut self, kind: u32, trb: PhysAddr) -> Result<(u32, [u32; 4]), KernelError> {
+        let deadline = time::ticks() + time::ms_to_ticks(COMMAND_TIMEOUT_MS);
+
+        while time::ticks() <= deadline {
+            let event = match self.events.pop() {
+                Some(event) => event,
+                None => {
+                    core::hint::spin_loop();
+                    continue;
+                }
+            };
+
+            self.update_dequeue();
+
+            if trb_type(&event) == kind && (event[0] as u64 | (event[1] as u64) << 32) == trb.as_u64() {
+                return Ok((event[2] >> 24, event));
+            }
+        }
+
+        return Err(KernelError::Timeout);
+    }
+
+    /// Sends a command and waits for it to complete, returning the slot of its completion event
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Timeout`] if the command doesn't complete and [`KernelError::Io`] if it fails
+    fn command(&mut self, trb: [u32; 4]) -> Result<u8, KernelError> {
+        let address = self.commands.push(trb);
+        self.ring_doorbell(0, 0);
+
+        match self.wait_event(TRB_COMMAND_COMPLETION, address)? {
+            (COMPLETION_SUCCESS, event) => Ok((event[3] >> 24) as u8),
+            _ => Err(KernelError::Io)
+        }
+    }
+
+    /// Sends `setup` to the default control endpoint of `device`, the data stage (if any) goes to the start of its
+    /// data page
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Timeout`] if the transfer doesn't complete and [`KernelError::Io`] if it fails, like
+    /// when the device doesn't know the request
+    fn control_transfer(&mut self, device: &mut Device, setup: SetupPacket) -> Result<(), KernelError> {
+        let [first, second] = setup.to_words();
+        let data_in = setup.length > 0;
+
+        let transfer = if data_in { TRB_TRANSFER_IN } else { 0 };
+        device.control.push([first, second, 8, TRB_SETUP << 10 | TRB_IMMEDIATE_DATA | transfer]);
+
+        if data_in {
+            let buffer = device.data.physical(0).as_u64();
+            let length = setup.length as u32;
+
+            device.control.push([buffer as u32, (buffer >> 32) as u32, length, TRB_DATA << 10 | TRB_DIRECTION_IN]);
+        }
+
+        // The status stage goes the other way than the data, or to the host when there's none
+        let direction = if data_in { 0 } else { TRB_DIRECTION_IN };
+        let status = device.control.push([0, 0, 0, TRB_STATUS << 10 | TRB_INTERRUPT_ON_COMPLETION | direction]);
+
+        self.ring_doorbell(device.slot, CONTROL_ENDPOINT);
+
+        match self.wait_event(TRB_TRANSFER_EVENT, status)?.0 {
+            COMPLETION_SUCCESS | COMPLETION_SHORT_PACKET => Ok(()),
+            _ => Err(KernelError::Io)
+        }
+    }
+
+    /// Returns a pointer to the context with the given index of the input context of `device`, the input control
+    /// context is the first one and the slot context the second
+    fn input_context(&self, device: &Device, index: usize) -> *mut u32 {
+        unsafe { device.input.page(0).add(index * self.context_size) as *mut u32 }
+    }
+
+    /// Clears the input context of `device` and sets the contexts the next command adds (or changes)
+    fn prepare_input(&self, device: &Device, added: u32) {
+        unsafe {
+            core::ptr::write_bytes(device.input.page(0), 0, 4096);
+            self.input_context(device, 0).add(1).write_volatile(added);
+        }
+    }
+
+    /// Fills the slot context of the input context of `device`, with the last endpoint context it has
+    fn write_slot_context(&self, device: &Device, port: u8, speed: u32, last_endpoint: u32) {
+        let slot = self.input_context(device, 1);
+
+        unsafe {
+            slot.write_volatile(speed << 20 | last_endpoint << 27);
+            slot.add(1).write_volatile((port as u32) << 16);
+        }
+    }
+
+    /// Resets the port with the given number (starting at 1), returning its speed if a device is there
+    fn reset_port(&self, port: u8) -> Option<u32> {
+        let register = OPERATIONAL_PORTS + (port as usize - 1) * 0x10;
+        let status = read(self.operational, register);
+
+        if status & PORT_CONNECTED == 0 {
+            return None;
+        }
+
+        // Only the power is kept, writing the changes back would clear them and writing enabled would disable it
+        write(self.operational, register, PORT_POWER | PORT_RESET);
+
+        wait_for(RESET_TIMEOUT_MS, || read(self.operational, register) & PORT_RESET_CHANGE != 0).ok()?;
+
+        let status = read(self.operational, register);
+        write(self.operational, register, PORT_POWER | (status & PORT_CHANGES));
+
+        return (status & PORT_ENABLED != 0).then_some(status >> 10 & 0xF);
+    }
+
+    /// Fills the context of the endpoint with the given index in the input context of `device`
+    fn write_endpoint_context(
+        &self, device: &Device, index: u32, kind: u32, max_packet: u32, interval: u32, ring: PhysAddr
+    ) {
+        let context = self.input_context(device, index as usize + 1);
+
+        unsafe {
+            context.write_volatile(interval << 16);
+            context.add(1).write_volatile(ENDPOINT_ERROR_COUNT << 1 | kind << 3 | max_packet << 16);
+
+            // The dequeue cycle state starts at 1, like the cycle of the ring
+            context.add(2).write_volatile(ring.as_u64() as u32 | TRB_CYCLE);
+            context.add(3).write_volatile((ring.as_u64() >> 32) as u32);
+
+            // The average length of the TRBs and the most bytes the endpoint moves per interval
+            context.add(4).write_volatile(8 | max_packet << 16);
+        }
+    }
+
+    /// Addresses the device on `port`, which has the given speed, and sets it up if it's a keyboard. The slot of the
+    /// device is only kept for the keyboards
+    ///
+    /// ## Errors
+    ///
+    /// Returns the error of the command or the transfer that failed
+    fn enumerate(&mut self, port: u8, speed: u32) -> Result<Option<Keyboard>, KernelError> {
+        let slot = self.command([0, 0, 0, TRB_ENABLE_SLOT << 10])?;
+        let result = self.set_up_keyboard(slot, port, speed);
+
+        if !matches!(result, Ok(Some(_))) {
+            let _ = self.command([0, 0, 0, TRB_DISABLE_SLOT << 10 | (slot as u32) << 24]);
+            unsafe { (self.contexts.page(0) as *mut u64).add(slot as usize).write_volatile(0) };
+        }
+
+        return result;
+    }
+
+    /// Gives the device in `slot` its address and reads its descriptors, then configures it and starts polling its
+    /// reports if it has a boot keyboard interface
+    fn set_up_keyboard(&mut self, slot: u8, port: u8, speed: u32) -> Result<Option<Keyboard>, KernelError> {
+        let mut device = Device {
+            slot,
+            input: DmaBuffer::new(1)?,
+            output: DmaBuffer::new(1)?,
+            control: Ring::new()?,
+            data: DmaBuffer::new(1)?
+        };
+
+        let output = device.output.physical(0).as_u64();
+        unsafe { (self.contexts.page(0) as *mut u64).add(slot as usize).write_volatile(output) };
+
+        // The full speed devices tell the size of their packets in their descriptor, 8 bytes are always fine to start
+        let max_packet = match speed {
+            SPEED_LOW | SPEED_FULL => 8,
+            SPEED_HIGH => 64,
+            _ => 512
+        };
+
+        self.prepare_input(&device, 0b11);
+        self.write_slot_context(&device, port, speed, CONTROL_ENDPOINT);
+        let ring = device.control.address();
+        self.write_endpoint_context(&device, CONTROL_ENDPOINT, ENDPOINT_CONTROL, max_packet, 0, ring);
+
+        let input = device.input.physical(0).as_u64();
+        let (input_low, input_high) = (input as u32, (input >> 32) as u32);
+
+        self.command([input_low, input_high, 0, TRB_ADDRESS_DEVICE << 10 | (slot as u32) << 24])?;
+        self.control_transfer(&mut device, SetupPacket::get_descriptor(usb::DESCRIPTOR_DEVICE, 8))?;
+
+        let data = device.data.page(0);
+        let actual_max_packet = unsafe { data.add(7).read_volatile() } as u32;
+
+        if speed == SPEED_FULL && actual_max_packet != max_packet {
+            let ring = device.control.address();
+
+            self.prepare_input(&device, 0b10);
+            self.write_endpoint_context(&device, CONTROL_ENDPOINT, ENDPOINT_CONTROL, actual_max_packet, 0, ring);
+            self.command([input_low, input_high, 0, TRB_EVALUATE_CONTEXT << 10 | (slot as u32) << 24])?;
+        }
+
+        // The configuration descriptor is read twice, first for its total length
+        self.control_transfer(&mut device, SetupPacket::get_descriptor(usb::DESCRIPTOR_CONFIGURATION, 9))?;
+
+        let total = unsafe { u16::from_le_bytes([data.add(2).read_volatile(), data.add(3).read_volatile()]) }.min(4096);
+        self.control_transfer(&mut device, SetupPacket::get_descriptor(usb::DESCRIPTOR_CONFIGURATION, total))?;
+
+        // The page is only written by the transfers, which are all done
+        let configuration = unsafe { core::slice::from_raw_parts(data, total as usize) };
+
+        let interface = match usb::find_boot_keyboard(configuration) {
+            Some(interface) => interface,
+            None => return Ok(None)
+        };
+
+        self.control_transfer(&mut device, SetupPacket::set_configuration(interface.configuration))?;
+        self.control_transfer(&mut device, SetupPacket::set_protocol(interface.interface, usb::PROTOCOL_BOOT))?;
+
+        // Not every keyboard takes it, it only saves the reports that don't change anything
+        let _ = self.control_transfer(&mut device, SetupPacket::set_idle(interface.interface));
+
+        let endpoint = (interface.endpoint as u32 & 0xF) * 2 + 1;
+
+        // The interval of the context is an exponent of 125 µs, the low and full speed devices give it in frames of
+        // 1 ms and the others as an exponent already
+        let interval = match speed {
+            SPEED_LOW | SPEED_FULL => (31 - (interface.interval.max(1) as u32 * 8).leading_zeros()).clamp(3, 10),
+            _ => (interface.interval as u32).clamp(1, 16) - 1
+        };
+
+        let mut keyboard = Keyboard { device, endpoint, ring: Ring::new()?, state: BootKeyboard::new() };
+        let (max_packet, ring) = (interface.max_packet as u32, keyboard.ring.address());
+
+        self.prepare_input(&keyboard.device, 1 << endpoint | 1);
+        self.write_slot_context(&keyboard.device, port, speed, endpoint);
+        self.write_endpoint_context(&keyboard.device, endpoint, ENDPOINT_INTERRUPT_IN, max_packet, interval, ring);
+
+        self.command([input_low, input_high, 0, TRB_CONFIGURE_ENDPOINT << 10 | (slot as u32) << 24])?;
+
+        keyboard.queue_report();
+        self.ring_doorbell(slot, endpoint);
+
+        return Ok(Some(keyboard));
+    }
+
+    /// Handles the events written since the last call: the reports of the keyboards are handed over and the next
+    /// report is asked for
+    ///
+    /// ## Note
+    ///
+    /// This function doesn't allocate or block, so it can be called inside an interrupt handler
+    fn handle_events(&mut self) {
+        while let Some(event) = self.events.pop() {
+            if trb_type(&event) != TRB_TRANSFER_EVENT {
+                continue;
+            }
+
+            let (slot, endpoint) = ((event[3] >> 24) as u8, event[3] >> 16 & 0x1F);
+
+            let keyboard = match self.keyboards.iter_mut().find(|keyboard| {
+                keyboard.device.slot == slot && keyboard.endpoint == endpoint
+            }) {
+                Some(keyboard) => keyboard,
+                None => continue
+            };
+
+            // After an error the endpoint is halted, the keyboard stops working until it's reset, which isn't supported
+            if !matches!(event[2] >> 24, COMPLETION_SUCCESS | COMPLETION_SHORT_PACKET) {
+                continue;
+            }
+
+            let mut report = [0u8; REPORT_SIZE];
+            unsafe { core::ptr::copy_nonoverlapping(keyboard.device.data.page(0), report.as_mut_ptr(), REPORT_SIZE) };
+
+            keyboard.state.handle_report(&report);
+            keyboard.queue_report();
+
+            let (slot, endpoint) = (keyboard.device.slot, keyboard.endpoint);
+            self.ring_doorbell(slot, endpoint);
+        }
+
+        self.update_dequeue();
+    }
+
+    /// Makes the controller raise its interrupt for every event
+    fn enable_interrupts(&self) {
+        write(self.runtime, INTERRUPTER_MANAGEMENT, MANAGEMENT_PENDING | MANAGEMENT_ENABLE);
+
+        let command = read(self.operational, OPERATIONAL_COMMAND);
+        write(self.operational, OPERATIONAL_COMMAND, command | COMMAND_INTERRUPTS);
+    }
+
+    /// Acknowledges the interrupt of the controller and handles its events
+    fn handle_interrupt(&mut self) {
+        write(self.operational, OPERATIONAL_STATUS, STATUS_EVENT_INTERRUPT);
+        write(self.runtime, INTERRUPTER_MANAGEMENT, MANAGEMENT_PENDING | MANAGEMENT_ENABLE);
+
+        self.handle_events();
+    }
+}
+
+/// Asks the firmware to hand the controller over if it still uses it, for the keyboard emulation of the BIOS
+///
+/// ## Errors
+///
+/// Returns [`KernelError::Timeout`] if the firmware never lets it go
+fn take_ownership(registers: VirtAddr, parameters_1: u32) -> Result<(), KernelError> {
+    // The extended capabilities are a list, the offsets of the first one and of the next ones are in words
+    let mut offset = (parameters_1 >> 16) as usize * 4;
+
+    while offset != 0 {
+        let capability = read(registers, offset);
+
+        if capability & 0xFF == EXTENDED_LEGACY_SUPPORT {
+            if capability & LEGACY_BIOS_OWNED == 0 {
+                return Ok(());
+            }
+
+            write(registers, offset, capability | LEGACY_OS_OWNED);
+            return wait_for(RESET_TIMEOUT_MS, || read(registers, offset) & LEGACY_BIOS_OWNED == 0);
+        }
+
+        offset = match (capability >> 8 & 0xFF) as usize {
+            0 => 0,
+            next => offset + next * 4
+        };
+    }
+
+    Ok(())
+}
+
+/// Called by the interrupt handler of the IRQ lines of the controllers, it handles the events of every controller
+///
+/// ## Note
+///
+/// This function must not allocate or block since it runs inside an interrupt handler
+fn handle_interrupt() {
+    CONTROLLERS.with(|controllers| {
+        for controller in controllers.iter_mut().flatten() {
+            controller.handle_interrupt();
+        }
+    });
+}
+
+/// The thread that checks the controllers without an interrupt for reports
+fn poll_loop() {
+    loop {
+        handle_interrupt();
+        crate::task::sleep_ms(POLL_MS);
+    }
+}
+
+/// The driver bound to an xHCI controller
+struct XhciDriver;
+
+impl Driver for XhciDriver {
+    fn name(&self) -> &'static str {
+        "xhci"
+    }
+}
+
+/// Binds to `pci` if it's an xHCI controller: resets it and sets up the keyboards speaking the boot protocol
+/// plugged into its ports. The devices behind hubs and the ones plugged in later aren't seen
+pub fn probe(pci: &PciDevice) -> Option<Box<dyn Driver>> {
+    if (pci.class, pci.subclass, pci.prog_if) != XHCI_CLASS {
+        return None;
+    }
+
+    let index = CONTROLLERS.with(|controllers| controllers.iter().position(|controller| controller.is_none()))?;
+
+    pci.write_config(PCI_COMMAND, pci.read_config(PCI_COMMAND) | PCI_COMMAND_MEMORY | PCI_COMMAND_BUS_MASTER);
+
+    let bar = (pci.read_config(PCI_BAR1) as u64) << 32 | (pci.read_config(PCI_BAR0) & !0xF) as u64;
+
+    // The capability registers tell how far the other ones go, the doorbells of the slots used are the last ones
+    let capabilities = crate::memory::mmio::map(PhysAddr::new(bar), 4096).ok()?;
+    let runtime_end = (read(capabilities, CAPABILITY_RUNTIME_OFFSET) & !0x1F) as usize + 0x40;
+    let doorbells_end = (read(capabilities, CAPABILITY_DOORBELL_OFFSET) & !0x3) as usize + (MAX_SLOTS as usize + 1) * 4;
+
+    let size = runtime_end.max(doorbells_end).max(OPERATIONAL_PORTS + 0x10 * 256);
+
+    let mut controller = match crate::memory::mmio::map(PhysAddr::new(bar), size).and_then(Xhci::new) {
+        Ok(controller) => controller,
+        Err(error) => {
+            println!("xHCI: failed to set up the controller: {:?}", error);
+            return None;
+        }
+    };
+
+    for port in 1..=controller.ports {
+        if controller.keyboards.len() == MAX_KEYBOARDS {
+            break;
+        }
+
+        let speed = match controller.reset_port(port) {
+            Some(speed) => speed,
+            None => continue
+        };
+
+        match controller.enumerate(port, speed) {
+            Ok(Some(keyboard)) => {
+                println!("xHCI: keyboard on port {}", port);
+                controller.keyboards.push(keyboard);
+            },
+            Ok(None) => {},
+            Err(error) => println!("xHCI: failed to set up the device on port {}: {:?}", port, error)
+        }
+    }
+
+    println!("xHCI: controller with {} ports, {} keyboards", controller.ports, controller.keyboards.len());
+
+    // 0xFF means the firmware didn't route the interrupt anywhere, then a thread polls the controller
+    let irq = pci.read_config(PCI_INTERRUPT_LINE) as u8;
+    let registered = IRQ_LINES.load(Ordering::Acquire);
+
+    let interrupts = irq < 16 && (registered & 1 << irq != 0
+        || crate::interrupts::interrupt_manager::register_irq(irq, handle_interrupt).is_ok());
+
+    // The controller is where the interrupt handler finds it before it raises its first interrupt
+    CONTROLLERS.with(|controllers| {
+        if interrupts {
+            controller.enable_interrupts();
+        }
+
+        controllers[index] = Some(controller);
+    });
+
+    if interrupts {
+        IRQ_LINES.fetch_or(1 << irq, Ordering::AcqRel);
+    } else if !POLLING.swap(true, Ordering::AcqRel) {
+        if let Err(error) = crate::task::spawn_kthread(poll_loop, "usb") {
+            println!("xHCI: failed to start the polling thread: {:?}", error);
+        }
+    }
+
+    return Some(Box::new(XhciDriver));
+}
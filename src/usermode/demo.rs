@@ -0,0 +1,15 @@
+use crate::println;
+use crate::process::Process;
+
+/// A tiny statically linked program that prints a message through `write` and exits, built from `user/hello.S` by
+/// `user/build.sh`. It's in the files built in the kernel (see [`crate::fs::romfs`]), so it's always there
+const HELLO_PATH: &str = "/rom/bin/init";
+
+/// Starts the demo program in a process of its own, a quick check at boot that the user segments, the system calls
+/// and the address spaces of the processes work. Must be called after [`crate::fs::init`]
+pub fn run() {
+    match Process::from_file("hello", HELLO_PATH) {
+        Ok(process) => println!("Started the demo program as process {}", process.id().as_u64()),
+        Err(error) => println!("Failed to start the demo program: {}", error)
+    }
+}
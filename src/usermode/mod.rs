@@ -0,0 +1,136 @@
+pub mod demo;
+
+use core::arch::global_asm;
+use x86_64::VirtAddr;
+use crate::interrupts::interrupt_manager;
+use crate::memory::address_space;
+use crate::println;
+
+/// RFLAGS used when entering user mode, only the interrupt flag (bit 9) and the always-one bit 1 are set,
+/// so user code can always be preempted
+const USER_RFLAGS: u64 = 0x202;
+
+/// The RFLAGS bits user code is allowed to control (the arithmetic flags and the direction flag),
+/// every other bit is taken from [`USER_RFLAGS`] when resuming user mode
+const USER_RFLAGS_MASK: u64 = 0xCD5;
+
+/// Every general purpose register of a thread running in user mode, plus where it is
+#[derive(Debug, Default, Clone)]
+#[repr(C)]
+pub struct UserRegisters {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rip: u64,
+    pub rsp: u64,
+    pub rflags: u64
+}
+
+impl UserRegisters {
+    /// The registers of a thread that starts running at `entry` with the stack pointer at `stack`
+    pub fn new(entry: VirtAddr, stack: VirtAddr) -> Self {
+        UserRegisters {
+            rip: entry.as_u64(),
+            rsp: stack.as_u64(),
+            rflags: USER_RFLAGS,
+            ..UserRegisters::default()
+        }
+    }
+
+    /// Returns whatever the thread can go back to user mode with these registers. The CPU faults in the kernel on an
+    /// instruction or stack pointer that isn't canonical, so both must be in the part of the address space left to
+    /// user mappings (see [`address_space::is_user_memory`]). The stack pointer may be right past the end of the
+    /// stack, it's the byte under it that's checked
+    pub fn is_resumable(&self) -> bool {
+        address_space::is_user_memory(self.rip, 1) && address_space::is_user_memory(self.rsp.wrapping_sub(1), 1)
+    }
+}
+
+// `rdi` points to the registers, `rsi` holds the user code selector and `rdx` the user data selector.
+// `iretq` pops the same frame the CPU pushes when an interrupt arrives in user mode, which is the only way
+// to lower the privilege level (besides `sysret`, which clobbers `rcx` and `r11`). The offsets must match `UserRegisters`
+global_asm!(r#"
+.global user_resume
+user_resume:
+    push rdx
+    push qword ptr [rdi + 0x80]
+    push qword ptr [rdi + 0x88]
+    push rsi
+    push qword ptr [rdi + 0x78]
+
+    mov ds, dx
+    mov es, dx
+
+    mov rax, [rdi + 0x00]
+    mov rbx, [rdi + 0x08]
+    mov rcx, [rdi + 0x10]
+    mov rdx, [rdi + 0x18]
+    mov rsi, [rdi + 0x20]
+    mov rbp, [rdi + 0x30]
+    mov r8, [rdi + 0x38]
+    mov r9, [rdi + 0x40]
+    mov r10, [rdi + 0x48]
+    mov r11, [rdi + 0x50]
+    mov r12, [rdi + 0x58]
+    mov r13, [rdi + 0x60]
+    mov r14, [rdi + 0x68]
+    mov r15, [rdi + 0x70]
+    mov rdi, [rdi + 0x28]
+    iretq
+"#);
+
+extern "C" {
+    fn user_resume(registers: *const UserRegisters, code_selector: u64, data_selector: u64) -> !;
+}
+
+/// Drops the current thread to ring 3, jumping to `entry` with the stack pointer set to `stack`.
+/// There's no coming back from this, the thread only enters the kernel again through interrupts and system calls
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that `entry` is mapped as user accessible and executable
+/// and that `stack` is the top of a user accessible, writable stack
+/// (see [`crate::memory::address_space::AddressSpace::map_user_pages`])
+pub unsafe fn enter(entry: VirtAddr, stack: VirtAddr) -> ! {
+    resume(&UserRegisters::new(entry, stack));
+}
+
+/// Drops the current thread to ring 3 with every register set as in `registers`, the flags that user code
+/// isn't allowed to control (like the interrupt flag) are forced to their user mode values. The thread is
+/// terminated instead if the registers can't be resumed (see [`UserRegisters::is_resumable`]), like it would be
+/// by a page fault
+///
+/// ## Safety
+///
+/// Same as [`enter`], with `rip` as the entry and `rsp` as the stack
+pub unsafe fn resume(registers: &UserRegisters) -> ! {
+    if !registers.is_resumable() {
+        println!("Invalid return to user mode at {:#x}, killing the thread", registers.rip);
+        crate::task::exit(-11);
+    }
+
+    let (code_selector, data_selector) = interrupt_manager::user_selectors();
+
+    let registers = UserRegisters {
+        rflags: (registers.rflags & USER_RFLAGS_MASK) | USER_RFLAGS,
+        ..registers.clone()
+    };
+
+    // Interrupts stay disabled until `iretq` loads the flags of the user, so nothing can
+    // use the kernel stack of the thread in between
+    x86_64::instructions::interrupts::disable();
+
+    user_resume(&registers, code_selector.0 as u64, data_selector.0 as u64);
+}
@@ -0,0 +1,120 @@
+use core::ops::Range;
+
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+/// A bitmap over a borrowed slice of words, where every bit represents whatever some resource
+/// (a physical frame, an interrupt vector, a disk block, ...) is used (`1`) or free (`0`).
+///
+/// The bitmap doesn't own its memory so it can live anywhere, including memory carved out
+/// of the physical memory before the heap exists
+pub struct Bitmap<'a> {
+    words: &'a mut [u64]
+}
+
+#[allow(dead_code)]
+impl<'a> Bitmap<'a> {
+    /// Creates a bitmap over `words`, the current content of the words is kept
+    pub fn new(words: &'a mut [u64]) -> Self {
+        Bitmap { words }
+    }
+
+    /// Returns how many words are needed to store a bitmap with `bits` bits
+    pub const fn words_for(bits: usize) -> usize {
+        (bits + BITS_PER_WORD - 1) / BITS_PER_WORD
+    }
+
+    /// The amount of bits in this bitmap
+    pub fn len(&self) -> usize {
+        self.words.len() * BITS_PER_WORD
+    }
+
+    pub fn test(&self, bit: usize) -> bool {
+        return self.words[bit / BITS_PER_WORD] & (1 << (bit % BITS_PER_WORD)) != 0;
+    }
+
+    pub fn set(&mut self, bit: usize) {
+        self.words[bit / BITS_PER_WORD] |= 1 << (bit % BITS_PER_WORD);
+    }
+
+    pub fn clear(&mut self, bit: usize) {
+        self.words[bit / BITS_PER_WORD] &= !(1 << (bit % BITS_PER_WORD));
+    }
+
+    /// Sets every bit in `range`
+    pub fn set_range(&mut self, range: Range<usize>) {
+        self.fill_range(range, true);
+    }
+
+    /// Clears every bit in `range`
+    pub fn clear_range(&mut self, range: Range<usize>) {
+        self.fill_range(range, false);
+    }
+
+    /// Sets or clears all the bits in `range`, whole words are written at once and only the bits
+    /// at the edges of the range are handled one by one
+    fn fill_range(&mut self, range: Range<usize>, value: bool) {
+        let mut bit = range.start;
+
+        while bit < range.end {
+            if bit % BITS_PER_WORD == 0 && bit + BITS_PER_WORD <= range.end {
+                self.words[bit / BITS_PER_WORD] = if value { u64::MAX } else { 0 };
+                bit += BITS_PER_WORD;
+                continue;
+            }
+
+            if value { self.set(bit) } else { self.clear(bit) }
+            bit += 1;
+        }
+    }
+
+    /// Returns the index of the first clear bit, or [`None`] if all bits are set
+    pub fn find_first_zero(&self) -> Option<usize> {
+        return self.find_first_zero_from(0);
+    }
+
+    /// Returns the index of the first clear bit at or after `start`, or [`None`] if all those bits are set
+    pub fn find_first_zero_from(&self, start: usize) -> Option<usize> {
+        if start >= self.len() {
+            return None;
+        }
+
+        let first_word = start / BITS_PER_WORD;
+
+        for (index, &word) in self.words.iter().enumerate().skip(first_word) {
+            // Pretend the bits before `start` are set, so they are skipped in the first word
+            let word = if index == first_word { word | ((1u64 << (start % BITS_PER_WORD)) - 1) } else { word };
+
+            if word != u64::MAX {
+                return Some(index * BITS_PER_WORD + (!word).trailing_zeros() as usize);
+            }
+        }
+
+        return None;
+    }
+
+    /// Returns the first index where `count` consecutive clear bits start, or [`None`] if there's no such run
+    pub fn find_zero_range(&self, count: usize) -> Option<usize> {
+        if count == 0 {
+            return Some(0);
+        }
+
+        let mut start = self.find_first_zero()?;
+
+        loop {
+            let end = start + count;
+            if end > self.len() {
+                return None;
+            }
+
+            match (start..end).find(|&bit| self.test(bit)) {
+                Some(used_bit) => start = self.find_first_zero_from(used_bit + 1)?,
+                None => return Some(start)
+            }
+        }
+    }
+
+    /// Returns how many bits are set
+    pub fn count_ones(&self) -> usize {
+        return self.words.iter().map(|word| word.count_ones() as usize).sum();
+    }
+}
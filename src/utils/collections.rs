@@ -0,0 +1,214 @@
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+
+/// A vector with a fixed capacity of `N` elements that lives entirely inline (on the stack or in a static).
+///
+/// It never touches the global allocator, so it can be used by the early boot code that runs before
+/// [`crate::memory::init_heap`] and by code that must not allocate, like interrupt handlers
+pub struct FixedVec<T, const N: usize> {
+    buffer: [MaybeUninit<T>; N],
+    len: usize
+}
+
+#[allow(dead_code)]
+impl<T, const N: usize> FixedVec<T, N> {
+    pub const fn new() -> Self {
+        FixedVec {
+            buffer: unsafe { MaybeUninit::<[MaybeUninit<T>; N]>::uninit().assume_init() },
+            len: 0
+        }
+    }
+
+    /// Appends `value` to the end of the vector, giving it back if the vector is full
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.len >= N {
+            return Err(value);
+        }
+
+        self.buffer[self.len].write(value);
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Removes the last element of the vector, returning [`None`] if it's empty
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        self.len -= 1;
+        return Some(unsafe { self.buffer[self.len].assume_init_read() });
+    }
+
+    /// Inserts `value` at `index`, shifting all the elements after it to the right
+    pub fn insert(&mut self, index: usize, value: T) -> Result<(), T> {
+        if self.len >= N || index > self.len {
+            return Err(value);
+        }
+
+        unsafe {
+            let slot = self.buffer.as_mut_ptr().add(index) as *mut T;
+            ptr::copy(slot, slot.add(1), self.len - index);
+            slot.write(value);
+        }
+
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes and returns the element at `index`, shifting all the elements after it to the left
+    pub fn remove(&mut self, index: usize) -> Option<T> {
+        if index >= self.len {
+            return None;
+        }
+
+        unsafe {
+            let slot = self.buffer.as_mut_ptr().add(index) as *mut T;
+            let value = slot.read();
+            ptr::copy(slot.add(1), slot, self.len - index - 1);
+
+            self.len -= 1;
+            return Some(value);
+        }
+    }
+
+    /// Drops every element of the vector
+    pub fn clear(&mut self) {
+        while self.pop().is_some() {}
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { core::slice::from_raw_parts(self.buffer.as_ptr() as *const T, self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { core::slice::from_raw_parts_mut(self.buffer.as_mut_ptr() as *mut T, self.len) }
+    }
+}
+
+impl<T, const N: usize> Deref for FixedVec<T, N> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T, const N: usize> DerefMut for FixedVec<T, N> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.as_mut_slice()
+    }
+}
+
+impl<T, const N: usize> Drop for FixedVec<T, N> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<T: fmt::Debug, const N: usize> fmt::Debug for FixedVec<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// An UTF-8 string with a fixed capacity of `N` bytes that lives entirely inline, see [`FixedVec`]
+///
+/// It implements [`fmt::Write`], so `write!` can be used to format text into it before the heap exists.
+/// Writing past the capacity fails without modifying the string
+pub struct FixedString<const N: usize> {
+    bytes: FixedVec<u8, N>
+}
+
+#[allow(dead_code)]
+impl<const N: usize> FixedString<N> {
+    pub const fn new() -> Self {
+        FixedString {
+            bytes: FixedVec::new()
+        }
+    }
+
+    /// Appends `s` to the end of the string, failing if there's not enough room for all of it
+    pub fn push_str(&mut self, s: &str) -> Result<(), ()> {
+        if self.bytes.len() + s.len() > N {
+            return Err(());
+        }
+
+        for &byte in s.as_bytes() {
+            let _ = self.bytes.push(byte);
+        }
+
+        Ok(())
+    }
+
+    pub fn push(&mut self, c: char) -> Result<(), ()> {
+        let mut buffer = [0u8; 4];
+        return self.push_str(c.encode_utf8(&mut buffer));
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only whole `&str`s and `char`s are ever pushed, so the bytes are always valid UTF-8
+        unsafe { core::str::from_utf8_unchecked(self.bytes.as_slice()) }
+    }
+}
+
+impl<const N: usize> Deref for FixedString<N> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<const N: usize> fmt::Write for FixedString<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s).map_err(|_| fmt::Error)
+    }
+}
+
+impl<const N: usize> fmt::Display for FixedString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<const N: usize> fmt::Debug for FixedString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
@@ -0,0 +1,76 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::sched::WaitQueue;
+use crate::utils::mutex::{MutexGuard, RawLock};
+
+/// A condition variable, used together with a [`super::Mutex`] to wait until some condition on the
+/// protected data becomes true without polling it in a busy loop.
+///
+/// A waiter releases the mutex and blocks until another context (a thread or an interrupt handler)
+/// calls [`CondVar::notify_one`] or [`CondVar::notify_all`]. Like every condition variable, waking up doesn't
+/// mean the condition is true, so the waiter must always check it again, [`CondVar::wait_while`] does this
+/// automatically.
+pub struct CondVar {
+    /// How many contexts are currently waiting on this condition variable
+    waiters: AtomicUsize,
+    /// How many waiters are allowed to wake up, each waiter that wakes up consumes one
+    permits: AtomicUsize,
+    /// Where the waiters are blocked
+    queue: WaitQueue
+}
+
+#[allow(dead_code)]
+impl CondVar {
+    pub const fn new() -> Self {
+        CondVar {
+            waiters: AtomicUsize::new(0),
+            permits: AtomicUsize::new(0),
+            queue: WaitQueue::new()
+        }
+    }
+
+    /// Releases the mutex held by `guard`, sleeps until this condition variable is notified and then
+    /// acquires the mutex again
+    pub fn wait<'a, T, L: RawLock>(&self, guard: MutexGuard<'a, T, L>) -> MutexGuard<'a, T, L> {
+        let mutex = MutexGuard::mutex(&guard);
+
+        // The waiter must be registered before the mutex is released, otherwise a notification
+        // sent right after the release would be missed
+        self.waiters.fetch_add(1, Ordering::AcqRel);
+        drop(guard);
+
+        self.queue.wait_until(|| self.take_permit());
+
+        self.waiters.fetch_sub(1, Ordering::AcqRel);
+        return mutex.lock();
+    }
+
+    /// Waits on this condition variable for as long as `condition` returns `true`
+    pub fn wait_while<'a, T, L: RawLock>(&self, mut guard: MutexGuard<'a, T, L>, mut condition: impl FnMut(&mut T) -> bool) -> MutexGuard<'a, T, L> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+
+        return guard;
+    }
+
+    /// Wakes up one of the contexts waiting on this condition variable, if there's any
+    pub fn notify_one(&self) {
+        let _ = self.permits.fetch_update(Ordering::AcqRel, Ordering::Acquire, |permits| {
+            if permits < self.waiters.load(Ordering::Acquire) { Some(permits + 1) } else { None }
+        });
+
+        self.queue.wake_one();
+    }
+
+    /// Wakes up every context waiting on this condition variable
+    pub fn notify_all(&self) {
+        self.permits.store(self.waiters.load(Ordering::Acquire), Ordering::Release);
+        self.queue.wake_all();
+    }
+
+    fn take_permit(&self) -> bool {
+        return self.permits
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |permits| permits.checked_sub(1))
+            .is_ok();
+    }
+}
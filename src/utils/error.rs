@@ -0,0 +1,125 @@
+use core::fmt;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::Size4KiB;
+
+/// The error type shared by every fallible path in the kernel.
+///
+/// Functions that can fail should return `Result<_, KernelError>` and let the caller decide what to do
+/// with the error, instead of printing it or panicking where the error happened
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum KernelError {
+    /// There's no memory (or physical frame) left to satisfy the request
+    OutOfMemory,
+    /// A page couldn't be mapped, the inner error tells why
+    MapError(MapToError<Size4KiB>),
+    /// The device needed by the operation isn't present
+    NoDevice,
+    /// The operation didn't finish in the expected time
+    Timeout,
+    /// One of the arguments given is invalid for the operation
+    InvalidArgument,
+    /// The operation isn't supported by the hardware or by the kernel
+    Unsupported,
+    /// The resource is being used by someone else
+    Busy,
+    /// The other end of a connection (like a channel) is gone
+    Closed,
+    /// A device reported an error while transferring data
+    Io,
+    /// There's no file or directory at the path given
+    NotFound,
+    /// A directory was expected, like for a component of a path that isn't the last one
+    NotDirectory,
+    /// The operation can't be done on a directory, like reading it as a file
+    IsDirectory,
+    /// There's a file or a directory with that name already
+    AlreadyExists,
+    /// There's no space left on the device
+    NoSpace,
+    /// The directory can't be removed since there are still entries in it
+    NotEmpty,
+    /// The operation can't go from one filesystem to another, like moving a file to another device
+    CrossDevice,
+    /// Too many symbolic links were followed while resolving a path, they probably point to each other
+    SymlinkLoop,
+    /// The permissions of the node don't let the process do that, like writing a file only its owner can write
+    PermissionDenied,
+    /// Only the owner of the node (or root) can do that, like changing its permissions
+    NotPermitted,
+    /// The file descriptor given isn't the one of a socket
+    NotSocket,
+    /// The socket has no peer to send to, or isn't bound to receive anything
+    NotConnected,
+    /// The network interface the operation goes through is down
+    NetworkDown
+}
+
+impl From<MapToError<Size4KiB>> for KernelError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        match error {
+            MapToError::FrameAllocationFailed => KernelError::OutOfMemory,
+            error => KernelError::MapError(error)
+        }
+    }
+}
+
+impl KernelError {
+    /// The negative number that represents this error where only an integer can be returned, like the result of
+    /// a system call. The numbers follow the ones used by Linux, so user programs can make sense of them
+    pub fn code(&self) -> i64 {
+        match self {
+            KernelError::OutOfMemory => -12,
+            KernelError::MapError(_) => -14,
+            KernelError::NoDevice => -19,
+            KernelError::Timeout => -110,
+            KernelError::InvalidArgument => -22,
+            KernelError::Unsupported => -38,
+            KernelError::Busy => -16,
+            KernelError::Closed => -32,
+            KernelError::Io => -5,
+            KernelError::NotFound => -2,
+            KernelError::NotDirectory => -20,
+            KernelError::IsDirectory => -21,
+            KernelError::AlreadyExists => -17,
+            KernelError::NoSpace => -28,
+            KernelError::NotEmpty => -39,
+            KernelError::CrossDevice => -18,
+            KernelError::SymlinkLoop => -40,
+            KernelError::PermissionDenied => -13,
+            KernelError::NotPermitted => -1,
+            KernelError::NotSocket => -88,
+            KernelError::NotConnected => -107,
+            KernelError::NetworkDown => -100
+        }
+    }
+}
+
+impl fmt::Display for KernelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KernelError::OutOfMemory => write!(f, "out of memory"),
+            KernelError::MapError(error) => write!(f, "failed to map page: {:?}", error),
+            KernelError::NoDevice => write!(f, "no such device"),
+            KernelError::Timeout => write!(f, "operation timed out"),
+            KernelError::InvalidArgument => write!(f, "invalid argument"),
+            KernelError::Unsupported => write!(f, "operation not supported"),
+            KernelError::Busy => write!(f, "resource busy"),
+            KernelError::Closed => write!(f, "the other end is closed"),
+            KernelError::Io => write!(f, "input/output error"),
+            KernelError::NotFound => write!(f, "no such file or directory"),
+            KernelError::NotDirectory => write!(f, "not a directory"),
+            KernelError::IsDirectory => write!(f, "is a directory"),
+            KernelError::AlreadyExists => write!(f, "file exists"),
+            KernelError::NoSpace => write!(f, "no space left on device"),
+            KernelError::NotEmpty => write!(f, "directory not empty"),
+            KernelError::CrossDevice => write!(f, "invalid cross-device link"),
+            KernelError::SymlinkLoop => write!(f, "too many levels of symbolic links"),
+            KernelError::PermissionDenied => write!(f, "permission denied"),
+            KernelError::NotPermitted => write!(f, "operation not permitted"),
+            KernelError::NotSocket => write!(f, "socket operation on non-socket"),
+            KernelError::NotConnected => write!(f, "transport endpoint is not connected"),
+            KernelError::NetworkDown => write!(f, "network is down")
+        }
+    }
+}
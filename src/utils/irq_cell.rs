@@ -0,0 +1,49 @@
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A cell for state that is only shared between the main flow of the kernel and interrupt handlers on a single CPU.
+///
+/// Instead of spinning like [`super::Mutex`] every access through [`IrqCell::with`] runs with interrupts disabled,
+/// so an interrupt handler can never find the state half-modified, and since there's only one CPU there's no one
+/// else to wait for. This makes it impossible to deadlock by taking the same lock in an interrupt handler
+/// that interrupted the lock holder.
+///
+/// ## Note
+///
+/// Nested calls to [`IrqCell::with`] on the same cell are a bug (they would create two `&mut` to the same value),
+/// so they are caught and cause a panic
+pub struct IrqCell<T> {
+    value: UnsafeCell<T>,
+    borrowed: AtomicBool
+}
+
+unsafe impl<T: Send> Sync for IrqCell<T> {}
+
+impl<T> IrqCell<T> {
+    pub const fn new(value: T) -> Self {
+        IrqCell {
+            value: UnsafeCell::new(value),
+            borrowed: AtomicBool::new(false)
+        }
+    }
+
+    /// Runs `f` with exclusive access to the value of this cell, with interrupts disabled for the whole call
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            if self.borrowed.swap(true, Ordering::Acquire) {
+                panic!("IrqCell accessed while already borrowed");
+            }
+
+            let result = f(unsafe { &mut *self.value.get() });
+            self.borrowed.store(false, Ordering::Release);
+
+            result
+        })
+    }
+
+    /// Returns a mutable reference to the value, this is safe since the `&mut self` guarantees no one else can access it
+    #[allow(dead_code)]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
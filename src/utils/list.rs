@@ -0,0 +1,264 @@
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+
+/// The links embedded in every element of an intrusive [`List`].
+///
+/// Since the links live inside the element itself, adding an element to a list never allocates, which
+/// makes these lists usable inside interrupt handlers, the scheduler and the allocators themselves
+pub struct Link<T> {
+    prev: Cell<Option<NonNull<T>>>,
+    next: Cell<Option<NonNull<T>>>,
+    linked: Cell<bool>
+}
+
+#[allow(dead_code)]
+impl<T> Link<T> {
+    pub const fn new() -> Self {
+        Link {
+            prev: Cell::new(None),
+            next: Cell::new(None),
+            linked: Cell::new(false)
+        }
+    }
+
+    /// Returns whatever the element that owns this link is currently in a list
+    pub fn is_linked(&self) -> bool {
+        self.linked.get()
+    }
+}
+
+/// Implemented by types that can be elements of a [`List`], by telling where their [`Link`] is
+///
+/// ## Safety
+///
+/// This trait is unsafe because [`Linked::link`] must always return the same link for the same element
+pub unsafe trait Linked: Sized {
+    fn link(&self) -> &Link<Self>;
+}
+
+/// An intrusive doubly-linked list, where each element carries its own [`Link`].
+///
+/// The list only stores pointers to the elements, it never owns them, so the caller is responsible for
+/// keeping every element alive (and not moving it) while it's in the list. An element can be in only one
+/// list at a time, trying to push an element that is already linked causes a panic.
+pub struct List<T: Linked> {
+    head: Option<NonNull<T>>,
+    tail: Option<NonNull<T>>,
+    len: usize
+}
+
+unsafe impl<T: Linked + Send> Send for List<T> {}
+
+#[allow(dead_code)]
+impl<T: Linked> List<T> {
+    pub const fn new() -> Self {
+        List {
+            head: None,
+            tail: None,
+            len: 0
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn front(&self) -> Option<NonNull<T>> {
+        self.head
+    }
+
+    pub fn back(&self) -> Option<NonNull<T>> {
+        self.tail
+    }
+
+    /// Adds `item` to the end of the list
+    ///
+    /// ## Safety
+    ///
+    /// This method is unsafe because the caller must guarantee that `item` stays valid and doesn't move
+    /// until it's removed from the list
+    pub unsafe fn push_back(&mut self, item: NonNull<T>) {
+        self.insert_between(item, self.tail, None);
+    }
+
+    /// Adds `item` to the start of the list
+    ///
+    /// ## Safety
+    ///
+    /// Same as [`List::push_back`]
+    pub unsafe fn push_front(&mut self, item: NonNull<T>) {
+        self.insert_between(item, None, self.head);
+    }
+
+    pub fn pop_front(&mut self) -> Option<NonNull<T>> {
+        let head = self.head?;
+        unsafe { self.unlink(head) };
+
+        return Some(head);
+    }
+
+    pub fn pop_back(&mut self) -> Option<NonNull<T>> {
+        let tail = self.tail?;
+        unsafe { self.unlink(tail) };
+
+        return Some(tail);
+    }
+
+    /// Removes `item` from the list
+    ///
+    /// ## Safety
+    ///
+    /// This method is unsafe because the caller must guarantee that `item` is an element of this list
+    pub unsafe fn remove(&mut self, item: NonNull<T>) {
+        self.unlink(item);
+    }
+
+    /// Returns a cursor pointing to the first element of the list
+    pub fn cursor_front_mut(&mut self) -> CursorMut<'_, T> {
+        CursorMut {
+            current: self.head,
+            list: self
+        }
+    }
+
+    /// Returns a cursor pointing to the last element of the list
+    pub fn cursor_back_mut(&mut self) -> CursorMut<'_, T> {
+        CursorMut {
+            current: self.tail,
+            list: self
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            next: self.head,
+            _list: PhantomData
+        }
+    }
+
+    unsafe fn insert_between(&mut self, item: NonNull<T>, prev: Option<NonNull<T>>, next: Option<NonNull<T>>) {
+        let link = item.as_ref().link();
+        assert!(!link.linked.replace(true), "Element is already in a list");
+
+        link.prev.set(prev);
+        link.next.set(next);
+
+        match prev {
+            Some(prev) => prev.as_ref().link().next.set(Some(item)),
+            None => self.head = Some(item)
+        }
+
+        match next {
+            Some(next) => next.as_ref().link().prev.set(Some(item)),
+            None => self.tail = Some(item)
+        }
+
+        self.len += 1;
+    }
+
+    unsafe fn unlink(&mut self, item: NonNull<T>) {
+        let link = item.as_ref().link();
+        let prev = link.prev.take();
+        let next = link.next.take();
+
+        match prev {
+            Some(prev) => prev.as_ref().link().next.set(next),
+            None => self.head = next
+        }
+
+        match next {
+            Some(next) => next.as_ref().link().prev.set(prev),
+            None => self.tail = prev
+        }
+
+        link.linked.set(false);
+        self.len -= 1;
+    }
+}
+
+/// A cursor over a [`List`] that can insert and remove elements around its position.
+///
+/// When the cursor moves past either end of the list it points to nothing, from there moving
+/// forward goes back to the first element and moving backwards goes to the last one
+pub struct CursorMut<'a, T: Linked> {
+    list: &'a mut List<T>,
+    current: Option<NonNull<T>>
+}
+
+#[allow(dead_code)]
+impl<'a, T: Linked> CursorMut<'a, T> {
+    pub fn current(&self) -> Option<NonNull<T>> {
+        self.current
+    }
+
+    pub fn move_next(&mut self) {
+        self.current = match self.current {
+            Some(current) => unsafe { current.as_ref().link().next.get() },
+            None => self.list.head
+        };
+    }
+
+    pub fn move_prev(&mut self) {
+        self.current = match self.current {
+            Some(current) => unsafe { current.as_ref().link().prev.get() },
+            None => self.list.tail
+        };
+    }
+
+    /// Inserts `item` before the current element, or at the end of the list if the cursor points to nothing
+    ///
+    /// ## Safety
+    ///
+    /// Same as [`List::push_back`]
+    pub unsafe fn insert_before(&mut self, item: NonNull<T>) {
+        match self.current {
+            Some(current) => self.list.insert_between(item, current.as_ref().link().prev.get(), Some(current)),
+            None => self.list.push_back(item)
+        }
+    }
+
+    /// Inserts `item` after the current element, or at the start of the list if the cursor points to nothing
+    ///
+    /// ## Safety
+    ///
+    /// Same as [`List::push_back`]
+    pub unsafe fn insert_after(&mut self, item: NonNull<T>) {
+        match self.current {
+            Some(current) => self.list.insert_between(item, Some(current), current.as_ref().link().next.get()),
+            None => self.list.push_front(item)
+        }
+    }
+
+    /// Removes the current element from the list and moves the cursor to the next one
+    pub fn remove_current(&mut self) -> Option<NonNull<T>> {
+        let current = self.current?;
+
+        unsafe {
+            self.current = current.as_ref().link().next.get();
+            self.list.unlink(current);
+        }
+
+        return Some(current);
+    }
+}
+
+pub struct Iter<'a, T: Linked> {
+    next: Option<NonNull<T>>,
+    _list: PhantomData<&'a List<T>>
+}
+
+impl<'a, T: Linked + 'a> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let current = unsafe { self.next?.as_ref() };
+        self.next = current.link().next.get();
+
+        return Some(current);
+    }
+}
@@ -1,17 +1,14 @@
-/// Since Rust doesn't allow `impl` in structs that doesn't belong to the current crate
-/// we create a "shadow" of the [`spin::Mutex`] so we can use `impl` freely
-pub struct Mutex<T> {
-    inner: spin::Mutex<T>
-}
+pub mod mutex;
+pub mod queue;
+pub mod error;
+pub mod collections;
+pub mod list;
+mod irq_cell;
+mod bitmap;
+mod condvar;
 
-impl<T> Mutex<T> {
-    pub const fn new(data: T) -> Self {
-        Mutex {
-            inner: spin::Mutex::new(data)
-        }
-    }
-
-    pub fn lock(&self) -> spin::MutexGuard<T> {
-        self.inner.lock()
-    }
-}
\ No newline at end of file
+pub use mutex::{Mutex, TicketMutex};
+pub use irq_cell::IrqCell;
+pub use bitmap::Bitmap;
+#[allow(unused_imports)]
+pub use condvar::CondVar;
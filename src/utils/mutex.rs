@@ -0,0 +1,321 @@
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+#[cfg(feature = "lock-stats")]
+use core::sync::atomic::AtomicU64;
+
+/// The maximum exponent used by [`Backoff`], after this the amount of spins per attempt stops growing
+/// (`2^MAX_BACKOFF_EXPONENT` calls to [`spin_loop`] per attempt)
+const MAX_BACKOFF_EXPONENT: u32 = 6;
+
+/// A mutual exclusion lock used across the whole kernel.
+///
+/// How the lock is actually acquired is decided by `L`, so each lock can pick the strategy that suits it:
+/// - [`SpinLock`] (the default) is the cheapest but gives no guarantee about who gets the lock next
+/// - [`TicketLock`] hands the lock out in the order it was requested, so no contender can be starved
+///
+/// Both back off exponentially while the lock is contended, so the cores (and the hyperthreads)
+/// fighting over a lock don't keep hammering the same cache line
+pub struct Mutex<T, L: RawLock = SpinLock> {
+    lock: L,
+    data: UnsafeCell<T>,
+    #[cfg(feature = "lock-stats")]
+    stats: RawLockStats
+}
+
+/// A [`Mutex`] that guarantees FIFO acquisition order
+pub type TicketMutex<T> = Mutex<T, TicketLock>;
+
+unsafe impl<T: Send, L: RawLock> Sync for Mutex<T, L> {}
+unsafe impl<T: Send, L: RawLock> Send for Mutex<T, L> {}
+
+impl<T, L: RawLock> Mutex<T, L> {
+    pub const fn new(data: T) -> Self {
+        Mutex {
+            lock: L::INIT,
+            data: UnsafeCell::new(data),
+            #[cfg(feature = "lock-stats")]
+            stats: RawLockStats::new()
+        }
+    }
+
+    /// Acquires the lock, spinning with an exponential backoff until it becomes available
+    pub fn lock(&self) -> MutexGuard<'_, T, L> {
+        let _spins = self.lock.lock();
+
+        #[cfg(feature = "lock-stats")]
+        self.stats.spins.fetch_add(_spins, Ordering::Relaxed);
+
+        return self.guard();
+    }
+
+    /// Tries to acquire the lock a single time, returning [`None`] if it's already held
+    #[allow(dead_code)]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T, L>> {
+        if !self.lock.try_lock() {
+            return None;
+        }
+
+        return Some(self.guard());
+    }
+
+    /// Returns whatever the lock is currently held by someone
+    #[allow(dead_code)]
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_locked()
+    }
+
+    /// Releases the lock without a guard, used by code that had to [`core::mem::forget`] its guard
+    /// (like a context switch that can't drop the guard before jumping to another stack)
+    ///
+    /// ## Safety
+    ///
+    /// This method is unsafe because the caller must guarantee the lock is held and that the
+    /// guard that acquired it was forgotten
+    #[allow(dead_code)]
+    pub unsafe fn force_unlock(&self) {
+        self.lock.unlock();
+    }
+
+    fn guard(&self) -> MutexGuard<'_, T, L> {
+        #[cfg(feature = "lock-stats")]
+        self.stats.acquisitions.fetch_add(1, Ordering::Relaxed);
+
+        MutexGuard {
+            mutex: self,
+            #[cfg(feature = "lock-stats")]
+            acquired_at: read_ticks()
+        }
+    }
+
+    /// Returns a snapshot of the contention counters of this lock
+    #[cfg(feature = "lock-stats")]
+    #[allow(dead_code)]
+    pub fn stats(&self) -> LockStats {
+        LockStats {
+            acquisitions: self.stats.acquisitions.load(Ordering::Relaxed),
+            spins: self.stats.spins.load(Ordering::Relaxed),
+            max_hold_ticks: self.stats.max_hold_ticks.load(Ordering::Relaxed)
+        }
+    }
+
+    /// Resets all the contention counters of this lock back to zero
+    #[cfg(feature = "lock-stats")]
+    #[allow(dead_code)]
+    pub fn reset_stats(&self) {
+        self.stats.acquisitions.store(0, Ordering::Relaxed);
+        self.stats.spins.store(0, Ordering::Relaxed);
+        self.stats.max_hold_ticks.store(0, Ordering::Relaxed);
+    }
+}
+
+/// The guard returned by [`Mutex::lock`], the lock is released once this guard is dropped
+pub struct MutexGuard<'a, T, L: RawLock = SpinLock> {
+    mutex: &'a Mutex<T, L>,
+    #[cfg(feature = "lock-stats")]
+    acquired_at: u64
+}
+
+impl<'a, T, L: RawLock> MutexGuard<'a, T, L> {
+    /// Returns the mutex that this guard is holding, this is an associated function so it doesn't
+    /// shadow a method of `T` with the same name
+    pub fn mutex(guard: &Self) -> &'a Mutex<T, L> {
+        guard.mutex
+    }
+}
+
+impl<T, L: RawLock> Deref for MutexGuard<'_, T, L> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T, L: RawLock> DerefMut for MutexGuard<'_, T, L> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T, L: RawLock> Drop for MutexGuard<'_, T, L> {
+    fn drop(&mut self) {
+        #[cfg(feature = "lock-stats")]
+        {
+            let held_for = read_ticks().wrapping_sub(self.acquired_at);
+            self.mutex.stats.max_hold_ticks.fetch_max(held_for, Ordering::Relaxed);
+        }
+
+        unsafe {
+            self.mutex.lock.unlock();
+        }
+    }
+}
+
+/// The strategy used by a [`Mutex`] to actually acquire and release the lock
+///
+/// ## Safety
+///
+/// This trait is unsafe because the implementation must guarantee that only one context holds the lock at a time
+pub unsafe trait RawLock {
+    /// An unlocked lock
+    const INIT: Self;
+
+    /// Acquires the lock, spinning until it's available, and returns how many times it had to back off
+    fn lock(&self) -> u64;
+
+    /// Tries to acquire the lock a single time, returning whatever it was acquired
+    fn try_lock(&self) -> bool;
+
+    fn is_locked(&self) -> bool;
+
+    /// Releases the lock
+    ///
+    /// ## Safety
+    ///
+    /// This method is unsafe because the caller must guarantee the lock is held by the current context
+    unsafe fn unlock(&self);
+}
+
+/// A test-and-test-and-set lock, cheap but unfair: whoever happens to see the lock free first takes it
+pub struct SpinLock {
+    locked: AtomicBool
+}
+
+unsafe impl RawLock for SpinLock {
+    const INIT: Self = SpinLock { locked: AtomicBool::new(false) };
+
+    fn lock(&self) -> u64 {
+        let mut backoff = Backoff::new();
+
+        while !self.try_lock() {
+            // Only read the lock while waiting, so the cache line isn't bounced between the cores
+            while self.is_locked() {
+                backoff.spin();
+            }
+        }
+
+        return backoff.spins;
+    }
+
+    fn try_lock(&self) -> bool {
+        return self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok();
+    }
+
+    fn is_locked(&self) -> bool {
+        return self.locked.load(Ordering::Relaxed);
+    }
+
+    unsafe fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+/// A ticket lock, every contender takes a ticket and waits until its number is served,
+/// so the lock is acquired in the same order it was requested (FIFO)
+pub struct TicketLock {
+    next_ticket: AtomicU32,
+    now_serving: AtomicU32
+}
+
+unsafe impl RawLock for TicketLock {
+    const INIT: Self = TicketLock {
+        next_ticket: AtomicU32::new(0),
+        now_serving: AtomicU32::new(0)
+    };
+
+    fn lock(&self) -> u64 {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        let mut backoff = Backoff::new();
+
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            backoff.spin();
+        }
+
+        return backoff.spins;
+    }
+
+    fn try_lock(&self) -> bool {
+        let serving = self.now_serving.load(Ordering::Relaxed);
+
+        // Only take a ticket if it would be served right away
+        return self.next_ticket
+            .compare_exchange(serving, serving.wrapping_add(1), Ordering::Acquire, Ordering::Relaxed)
+            .is_ok();
+    }
+
+    fn is_locked(&self) -> bool {
+        return self.next_ticket.load(Ordering::Relaxed) != self.now_serving.load(Ordering::Relaxed);
+    }
+
+    unsafe fn unlock(&self) {
+        self.now_serving.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// A snapshot of the contention counters of a [`Mutex`], only available with the `lock-stats` feature
+///
+/// The hold time is measured in TSC ticks, so it's only comparable between locks on the same machine
+#[cfg(feature = "lock-stats")]
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct LockStats {
+    /// How many times the lock was acquired
+    pub acquisitions: u64,
+    /// How many times a contender had to spin while waiting for the lock
+    pub spins: u64,
+    /// The longest time the lock was held, in TSC ticks
+    pub max_hold_ticks: u64
+}
+
+#[cfg(feature = "lock-stats")]
+struct RawLockStats {
+    acquisitions: AtomicU64,
+    spins: AtomicU64,
+    max_hold_ticks: AtomicU64
+}
+
+#[cfg(feature = "lock-stats")]
+impl RawLockStats {
+    const fn new() -> Self {
+        RawLockStats {
+            acquisitions: AtomicU64::new(0),
+            spins: AtomicU64::new(0),
+            max_hold_ticks: AtomicU64::new(0)
+        }
+    }
+}
+
+/// Exponential backoff used while waiting for a contended lock, each call to [`Backoff::spin`]
+/// doubles the amount of time spent spinning until [`MAX_BACKOFF_EXPONENT`] is reached
+pub struct Backoff {
+    exponent: u32,
+    spins: u64
+}
+
+impl Backoff {
+    pub const fn new() -> Self {
+        Backoff {
+            exponent: 0,
+            spins: 0
+        }
+    }
+
+    pub fn spin(&mut self) {
+        for _ in 0..(1u32 << self.exponent) {
+            spin_loop();
+        }
+
+        if self.exponent < MAX_BACKOFF_EXPONENT {
+            self.exponent += 1;
+        }
+
+        self.spins += 1;
+    }
+}
+
+#[cfg(feature = "lock-stats")]
+fn read_ticks() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
@@ -0,0 +1,210 @@
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A fixed-capacity lock-free queue with a single producer and a single consumer.
+///
+/// Neither side ever takes a lock, so this queue can be safely used to pass data from an interrupt
+/// handler to the rest of the kernel (or the other way around) without the risk of deadlocking
+/// when the interrupt fires while the lock is held.
+///
+/// ## Note
+///
+/// This queue is only correct while there's at most one context pushing and one context popping at
+/// a time, use [`MpscQueue`] when more than one producer exists
+pub struct SpscQueue<T, const N: usize> {
+    buffer: UnsafeCell<[MaybeUninit<T>; N]>,
+    /// Index of the next element to be popped, only written by the consumer
+    head: AtomicUsize,
+    /// Index of the next free slot, only written by the producer
+    tail: AtomicUsize
+}
+
+unsafe impl<T: Send, const N: usize> Sync for SpscQueue<T, N> {}
+
+#[allow(dead_code)]
+impl<T, const N: usize> SpscQueue<T, N> {
+    pub const fn new() -> Self {
+        SpscQueue {
+            buffer: UnsafeCell::new(unsafe { MaybeUninit::<[MaybeUninit<T>; N]>::uninit().assume_init() }),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0)
+        }
+    }
+
+    /// Pushes `value` to the end of the queue, giving it back if the queue is full
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+
+        if tail.wrapping_sub(head) >= N {
+            return Err(value);
+        }
+
+        unsafe {
+            (*self.buffer.get())[tail % N].write(value);
+        }
+
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Removes the first element of the queue, returning [`None`] if the queue is empty
+    pub fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+
+        if head == tail {
+            return None;
+        }
+
+        let value = unsafe { (*self.buffer.get())[head % N].assume_init_read() };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+
+        return Some(value);
+    }
+
+    pub fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        let head = self.head.load(Ordering::Acquire);
+
+        return tail.wrapping_sub(head);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+}
+
+impl<T, const N: usize> Drop for SpscQueue<T, N> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+/// A fixed-capacity lock-free queue that accepts any number of producers and a single consumer.
+///
+/// This is an implementation of the bounded queue described by Dmitry Vyukov, where every slot
+/// carries a sequence number telling producers and the consumer whatever the slot is free or filled.
+/// Producers race for a slot with a compare-and-swap on the tail, so several interrupt handlers (or
+/// tasks and interrupt handlers) can push to the same queue without taking any lock.
+///
+/// For more information refer to [this post](https://www.1024cores.net/home/lock-free-algorithms/queues/bounded-mpmc-queue)
+#[allow(dead_code)]
+pub struct MpscQueue<T, const N: usize> {
+    slots: [Slot<T>; N],
+    head: AtomicUsize,
+    tail: AtomicUsize
+}
+
+/// A single slot of a [`MpscQueue`]
+///
+/// Since atomics can't be initialized with different values in a `const fn` the sequence
+/// is stored relative to the index of the slot, so `sequence` is 0 for every slot of a new queue
+/// and the actual sequence number is `sequence + index`
+struct Slot<T> {
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>
+}
+
+impl<T> Slot<T> {
+    const fn empty() -> Self {
+        Slot {
+            sequence: AtomicUsize::new(0),
+            value: UnsafeCell::new(MaybeUninit::uninit())
+        }
+    }
+}
+
+unsafe impl<T: Send, const N: usize> Sync for MpscQueue<T, N> {}
+
+#[allow(dead_code)]
+impl<T, const N: usize> MpscQueue<T, N> {
+    pub const fn new() -> Self {
+        MpscQueue {
+            slots: [ const { Slot::empty() }; N ],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0)
+        }
+    }
+
+    /// Pushes `value` to the end of the queue, giving it back if the queue is full
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let mut position = self.tail.load(Ordering::Relaxed);
+
+        loop {
+            let index = position % N;
+            let slot = &self.slots[index];
+            let sequence = slot.sequence.load(Ordering::Acquire).wrapping_add(index);
+
+            if sequence == position {
+                // The slot is free, try to claim it before another producer does
+                match self.tail.compare_exchange_weak(position, position.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        unsafe {
+                            (*slot.value.get()).write(value);
+                        }
+
+                        slot.sequence.store(position.wrapping_add(1).wrapping_sub(index), Ordering::Release);
+                        return Ok(());
+                    },
+                    Err(current) => position = current
+                }
+            } else if (sequence as isize).wrapping_sub(position as isize) < 0 {
+                // The consumer still didn't pop the element that is in this slot, so the queue is full
+                return Err(value);
+            } else {
+                position = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Removes the first element of the queue, returning [`None`] if the queue is empty
+    ///
+    /// ## Note
+    ///
+    /// Only one context may pop from this queue at a time
+    pub fn pop(&self) -> Option<T> {
+        let position = self.head.load(Ordering::Relaxed);
+        let index = position % N;
+        let slot = &self.slots[index];
+        let sequence = slot.sequence.load(Ordering::Acquire).wrapping_add(index);
+
+        // If the producer that claimed this slot didn't finish writing it yet then we treat the queue as empty
+        if sequence != position.wrapping_add(1) {
+            return None;
+        }
+
+        self.head.store(position.wrapping_add(1), Ordering::Relaxed);
+
+        let value = unsafe { (*slot.value.get()).assume_init_read() };
+        slot.sequence.store(position.wrapping_add(N).wrapping_sub(index), Ordering::Release);
+
+        return Some(value);
+    }
+
+    pub fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        let head = self.head.load(Ordering::Acquire);
+
+        return tail.wrapping_sub(head).min(N);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+}
+
+impl<T, const N: usize> Drop for MpscQueue<T, N> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
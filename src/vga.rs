@@ -1,14 +1,23 @@
 use core::{fmt, ptr};
 use core::fmt::Write;
 use lazy_static::lazy_static;
+use crate::graphics::console::FramebufferConsole;
+use crate::graphics::Framebuffer;
+use crate::utils::TicketMutex;
 
 const VGA_BUFFER_PTR: usize = 0xb8000;
 
 const BUFFER_WIDTH: usize = 80;
 const BUFFER_HEIGHT: usize = 25;
 
+/// The colors of the text mode as `0x00RRGGBB`, for the framebuffer console
+const PALETTE: [u32; 16] = [
+    0x000000, 0x0000AA, 0x00AA00, 0x00AAAA, 0xAA0000, 0xAA00AA, 0xAA5500, 0xAAAAAA,
+    0x555555, 0x5555FF, 0x55FF55, 0x55FFFF, 0xFF5555, 0xFF55FF, 0xFFFF55, 0xFFFFFF
+];
+
 lazy_static! {
-    static ref WRITER: spin::Mutex<VGAWriter> = spin::Mutex::new(VGAWriter::new(ColorCode::new(Color::White, Color::Black)));
+    static ref WRITER: TicketMutex<VGAWriter> = TicketMutex::new(VGAWriter::new(ColorCode::new(Color::White, Color::Black)));
 }
 
 #[macro_export]
@@ -25,7 +34,42 @@ macro_rules! println {
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     x86_64::instructions::interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
+        let mut writer = WRITER.lock();
+
+        writer.write_fmt(args).unwrap();
+        writer.flush();
+        drop(writer);
+
+        crate::serial::console_print(args);
+        crate::debugcon::console_print(args);
+    });
+}
+
+/// Clears the whole screen, the next character is written at the start of the last row
+pub fn clear() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+
+        writer.clear();
+        writer.flush();
+    });
+}
+
+/// Moves the console from the text mode (or from the framebuffer it was using) to `framebuffer`. What was
+/// on the screen in text mode is written again, a framebuffer is only known as pixels so it starts empty
+pub fn use_framebuffer(framebuffer: Framebuffer) {
+    let color = x86_64::instructions::interrupts::without_interrupts(|| WRITER.lock().default_color);
+
+    // Drawing the whole framebuffer takes a while, so it's done before taking the lock
+    let mut console = FramebufferConsole::new(framebuffer, color.foreground(), color.background());
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+
+        writer.replay(&mut console);
+        console.flush();
+
+        writer.framebuffer = Some(console);
     });
 }
 
@@ -59,6 +103,14 @@ impl ColorCode {
     pub fn new(foregound: Color, background: Color) -> Self {
         ColorCode((background as u8) << 4 | (foregound as u8))
     }
+
+    fn foreground(&self) -> u32 {
+        PALETTE[(self.0 & 0xF) as usize]
+    }
+
+    fn background(&self) -> u32 {
+        PALETTE[(self.0 >> 4) as usize]
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -75,7 +127,9 @@ struct VGABuffer {
 struct VGAWriter {
     cursor_x: usize,
     buffer: &'static mut VGABuffer,
-    default_color: ColorCode
+    default_color: ColorCode,
+    /// The console everything goes to instead of the text mode buffer, once the screen is in a graphics mode
+    framebuffer: Option<FramebufferConsole>
 }
 
 impl VGAWriter {
@@ -83,13 +137,20 @@ impl VGAWriter {
         VGAWriter {
             cursor_x: 0,
             buffer: unsafe { &mut *(VGA_BUFFER_PTR as *mut VGABuffer) },
-            default_color
+            default_color,
+            framebuffer: None
         }
     }
 
     pub fn write_char(&mut self, c: char, color: ColorCode) {
+        if let Some(console) = &mut self.framebuffer {
+            console.write_byte(c as u8);
+            return;
+        }
+
         match c {
             '\n' => self.new_line(),
+            '\x08' => self.backspace(),
             character => {
                 if self.cursor_x >= BUFFER_WIDTH {
                     self.new_line();
@@ -111,7 +172,7 @@ impl VGAWriter {
     pub fn write_string(&mut self, s: &str) {
         for byte in s.bytes() {
             match byte {
-                0x20..=0x7e | b'\n' => self.write_char(byte as char, self.default_color),
+                0x20..=0x7e | b'\n' | 0x08 => self.write_char(byte as char, self.default_color),
                 _ => self.write_char(0xfe as char, self.default_color)
             }
         }
@@ -129,6 +190,64 @@ impl VGAWriter {
         self.cursor_x = 0;
     }
 
+    /// Erases the character before the cursor, the cursor never goes back to the previous row
+    /// since that row may have been written by someone else
+    fn backspace(&mut self) {
+        if self.cursor_x == 0 {
+            return;
+        }
+
+        self.cursor_x -= 1;
+
+        self.buffer.chars[BUFFER_HEIGHT - 1][self.cursor_x] = VGAChar {
+            character: b' ',
+            color: self.default_color
+        };
+    }
+
+    fn clear(&mut self) {
+        if let Some(console) = &mut self.framebuffer {
+            console.clear();
+            return;
+        }
+
+        for row in 0..BUFFER_HEIGHT {
+            self.clear_row(row);
+        }
+
+        self.cursor_x = 0;
+    }
+
+    /// Shows what was written on the framebuffer console, if there's one
+    fn flush(&mut self) {
+        if let Some(console) = &mut self.framebuffer {
+            console.flush();
+        }
+    }
+
+    /// Writes what's on the text mode screen to `console`, so nothing is lost when switching to it. The rows are
+    /// written from the top, the last one only up to the cursor since it's the one still being written
+    fn replay(&self, console: &mut FramebufferConsole) {
+        if self.framebuffer.is_some() {
+            return;
+        }
+
+        for (index, row) in self.buffer.chars.iter().enumerate() {
+            // The cleared cells hold zeros
+            let character = |c: &VGAChar| if c.character == 0 { b' ' } else { c.character };
+
+            if index == BUFFER_HEIGHT - 1 {
+                row[..self.cursor_x].iter().for_each(|c| console.write_byte(character(c)));
+                break;
+            }
+
+            let length = row.iter().rposition(|c| character(c) != b' ').map_or(0, |last| last + 1);
+
+            row[..length].iter().for_each(|c| console.write_byte(character(c)));
+            console.write_byte(b'\n');
+        }
+    }
+
     fn clear_row(&mut self, row: usize) {
         let vga_ptr = VGA_BUFFER_PTR as *mut u8;
 
@@ -0,0 +1,293 @@
+pub mod queue;
+
+use x86_64::{PhysAddr, VirtAddr};
+use crate::pci::PciDevice;
+use crate::utils::error::KernelError;
+use self::queue::Virtqueue;
+
+/// The vendor of every virtio device, the ID of a modern device is [`MODERN_DEVICE_ID_BASE`] plus its type
+const VIRTIO_VENDOR: u16 = 0x1AF4;
+const MODERN_DEVICE_ID_BASE: u16 = 0x1040;
+
+/// The IDs of the transitional devices, which speak the legacy interface and the modern one, with their type. QEMU
+/// makes most devices (like the network cards) transitional ones on the PCI bus
+const TRANSITIONAL_DEVICE_IDS: [(u16, u16); 7] = [
+    (0x1000, 1),
+    (0x1001, 2),
+    (0x1002, 5),
+    (0x1003, 3),
+    (0x1004, 8),
+    (0x1005, 4),
+    (0x1009, 9)
+];
+
+const PCI_COMMAND: u16 = 0x04;
+const PCI_COMMAND_MEMORY: u32 = 1 << 1;
+const PCI_COMMAND_BUS_MASTER: u32 = 1 << 2;
+
+/// The ID of the vendor specific PCI capabilities, which is how a modern device tells where its structures are
+const CAPABILITY_VENDOR: u8 = 0x09;
+
+/// The structures a capability can point to
+const CAPABILITY_COMMON: u8 = 1;
+const CAPABILITY_NOTIFY: u8 = 2;
+const CAPABILITY_ISR: u8 = 3;
+const CAPABILITY_DEVICE: u8 = 4;
+
+/// The registers of the common configuration structure, as offsets from its start
+const COMMON_DEVICE_FEATURE_SELECT: usize = 0x00;
+const COMMON_DEVICE_FEATURE: usize = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: usize = 0x08;
+const COMMON_DRIVER_FEATURE: usize = 0x0C;
+const COMMON_DEVICE_STATUS: usize = 0x14;
+const COMMON_QUEUE_SELECT: usize = 0x16;
+const COMMON_QUEUE_SIZE: usize = 0x18;
+const COMMON_QUEUE_ENABLE: usize = 0x1C;
+const COMMON_QUEUE_NOTIFY_OFFSET: usize = 0x1E;
+const COMMON_QUEUE_DESCRIPTORS: usize = 0x20;
+const COMMON_QUEUE_DRIVER: usize = 0x28;
+const COMMON_QUEUE_DEVICE: usize = 0x30;
+
+/// The bits of the device status, set one after the other while the driver initializes the device
+const STATUS_ACKNOWLEDGE: u8 = 1 << 0;
+const STATUS_DRIVER: u8 = 1 << 1;
+const STATUS_DRIVER_OK: u8 = 1 << 2;
+const STATUS_FEATURES_OK: u8 = 1 << 3;
+const STATUS_FAILED: u8 = 1 << 7;
+
+/// The feature every modern device has, without it the device only speaks the legacy interface
+const FEATURE_VERSION_1: u64 = 1 << 32;
+
+/// How many times the status is read while waiting for the reset to finish
+const RESET_ATTEMPTS: usize = 100_000;
+
+/// Returns whatever `pci` is a virtio device of the given type (like 16 for a GPU), either a modern one or a
+/// transitional one. Only the modern interface is used either way
+pub fn is_device(pci: &PciDevice, device_type: u16) -> bool {
+    if pci.vendor_id != VIRTIO_VENDOR {
+        return false;
+    }
+
+    pci.device_id == MODERN_DEVICE_ID_BASE + device_type
+        || TRANSITIONAL_DEVICE_IDS.contains(&(pci.device_id, device_type))
+}
+
+/// A virtio device reached through the modern PCI transport, where the configuration structures are in the BARs
+pub struct VirtioDevice {
+    common: VirtAddr,
+    notify: VirtAddr,
+    /// The offset of a queue from [`VirtioDevice::notify`] is its notify offset times this
+    notify_multiplier: u32,
+    isr: VirtAddr,
+    device_config: Option<VirtAddr>,
+    irq: Option<u8>
+}
+
+#[allow(dead_code)]
+impl VirtioDevice {
+    /// Maps the configuration structures of `pci`, after letting it answer to its memory and start transfers on its
+    /// own. Must be called before any process is created, like [`crate::memory::mmio::map`]
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Unsupported`] if the device doesn't have the structures of the modern interface
+    pub fn new(pci: PciDevice) -> Result<Self, KernelError> {
+        pci.write_config(PCI_COMMAND, pci.read_config(PCI_COMMAND) | PCI_COMMAND_MEMORY | PCI_COMMAND_BUS_MASTER);
+
+        let (mut common, mut notify, mut isr, mut device_config) = (None, None, None, None);
+        let mut notify_multiplier = 0;
+
+        for (id, offset) in pci.capabilities() {
+            if id != CAPABILITY_VENDOR {
+                continue;
+            }
+
+            // The type and the BAR are bytes 3 and 4, followed by the offset and the length in the BAR
+            let kind = (pci.read_config(offset) >> 24) as u8;
+            let bar = pci.read_config(offset + 4) as u8;
+            let start = pci.read_config(offset + 8) as u64;
+            let length = pci.read_config(offset + 12) as usize;
+
+            let address = match bar_address(&pci, bar) {
+                Some(address) if length > 0 => address,
+                _ => continue
+            };
+
+            // There can be more than one capability of a type, the first one is the preferred one
+            let slot = match kind {
+                CAPABILITY_COMMON => &mut common,
+                CAPABILITY_NOTIFY => &mut notify,
+                CAPABILITY_ISR => &mut isr,
+                CAPABILITY_DEVICE => &mut device_config,
+                _ => continue
+            };
+
+            if slot.is_some() {
+                continue;
+            }
+
+            if kind == CAPABILITY_NOTIFY {
+                notify_multiplier = pci.read_config(offset + 16);
+            }
+
+            *slot = Some(crate::memory::mmio::map(PhysAddr::new(address + start), length)?);
+        }
+
+        let (common, notify, isr) = match (common, notify, isr) {
+            (Some(common), Some(notify), Some(isr)) => (common, notify, isr),
+            _ => return Err(KernelError::Unsupported)
+        };
+
+        // 0xFF means the firmware didn't route the interrupt anywhere
+        let irq = pci.read_config(0x3C) as u8;
+
+        Ok(VirtioDevice { common, notify, notify_multiplier, isr, device_config, irq: (irq < 16).then_some(irq) })
+    }
+
+    fn read_common<T>(&self, offset: usize) -> T {
+        unsafe { core::ptr::read_volatile((self.common + offset).as_ptr::<T>()) }
+    }
+
+    fn write_common<T>(&self, offset: usize, value: T) {
+        unsafe { core::ptr::write_volatile((self.common + offset).as_mut_ptr::<T>(), value) };
+    }
+
+    fn set_status(&self, status: u8) {
+        let current: u8 = self.read_common(COMMON_DEVICE_STATUS);
+        self.write_common(COMMON_DEVICE_STATUS, current | status);
+    }
+
+    /// Resets the device and agrees on the features to use, the ones in `wanted` the device has plus
+    /// [`FEATURE_VERSION_1`]. Returns the features agreed on
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Timeout`] if the device doesn't finish its reset and [`KernelError::Unsupported`]
+    /// if it only has the legacy interface or refuses the features
+    pub fn negotiate(&self, wanted: u64) -> Result<u64, KernelError> {
+        self.write_common(COMMON_DEVICE_STATUS, 0u8);
+
+        if !(0..RESET_ATTEMPTS).any(|_| self.read_common::<u8>(COMMON_DEVICE_STATUS) == 0) {
+            return Err(KernelError::Timeout);
+        }
+
+        self.set_status(STATUS_ACKNOWLEDGE);
+        self.set_status(STATUS_DRIVER);
+
+        // The features are read and written 32 bits at a time, the select register picks which half
+        let mut offered = 0u64;
+
+        for half in 0..2u32 {
+            self.write_common(COMMON_DEVICE_FEATURE_SELECT, half);
+            offered |= (self.read_common::<u32>(COMMON_DEVICE_FEATURE) as u64) << (half * 32);
+        }
+
+        if offered & FEATURE_VERSION_1 == 0 {
+            self.set_status(STATUS_FAILED);
+            return Err(KernelError::Unsupported);
+        }
+
+        let accepted = offered & (wanted | FEATURE_VERSION_1);
+
+        for half in 0..2u32 {
+            self.write_common(COMMON_DRIVER_FEATURE_SELECT, half);
+            self.write_common(COMMON_DRIVER_FEATURE, (accepted >> (half * 32)) as u32);
+        }
+
+        self.set_status(STATUS_FEATURES_OK);
+
+        if self.read_common::<u8>(COMMON_DEVICE_STATUS) & STATUS_FEATURES_OK == 0 {
+            self.set_status(STATUS_FAILED);
+            return Err(KernelError::Unsupported);
+        }
+
+        return Ok(accepted);
+    }
+
+    /// Allocates the queue with the given index and hands it to the device, must be called after
+    /// [`VirtioDevice::negotiate`] and before [`VirtioDevice::start`]
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if the device doesn't have that queue, or
+    /// [`KernelError::OutOfMemory`] if there's no memory left for it
+    pub fn setup_queue(&self, index: u16) -> Result<Virtqueue, KernelError> {
+        self.write_common(COMMON_QUEUE_SELECT, index);
+
+        let size: u16 = self.read_common(COMMON_QUEUE_SIZE);
+
+        if size == 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let notify_offset: u16 = self.read_common(COMMON_QUEUE_NOTIFY_OFFSET);
+        let notify = self.notify + notify_offset as u64 * self.notify_multiplier as u64;
+
+        let queue = Virtqueue::new(index, size, notify)?;
+        let (descriptors, driver, device) = queue.addresses();
+
+        // The queue sizes are powers of 2, so the smaller size of the queue still is one
+        self.write_common(COMMON_QUEUE_SIZE, size.min(queue::MAX_QUEUE_SIZE));
+
+        let registers = [
+            (COMMON_QUEUE_DESCRIPTORS, descriptors),
+            (COMMON_QUEUE_DRIVER, driver),
+            (COMMON_QUEUE_DEVICE, device)
+        ];
+
+        // The 64 bits registers can be written as two halves, low one first
+        for (offset, address) in registers {
+            self.write_common(offset, address.as_u64() as u32);
+            self.write_common(offset + 4, (address.as_u64() >> 32) as u32);
+        }
+
+        self.write_common(COMMON_QUEUE_ENABLE, 1u16);
+        return Ok(queue);
+    }
+
+    /// Tells the device the driver is ready, the queues can be used from now on
+    pub fn start(&self) {
+        self.set_status(STATUS_DRIVER_OK);
+    }
+
+    /// Reads a register of the configuration structure of the device type, at `offset` from its start
+    ///
+    /// ## Panics
+    ///
+    /// Panics if the device has no such structure
+    pub fn read_config<T>(&self, offset: usize) -> T {
+        let config = self.device_config.expect("The virtio device has no device configuration");
+        unsafe { core::ptr::read_volatile((config + offset).as_ptr::<T>()) }
+    }
+
+    /// Reads (and so acknowledges) the interrupt status, bit 0 is set for a queue interrupt and bit 1 for a
+    /// configuration change
+    pub fn interrupt_status(&self) -> u8 {
+        unsafe { core::ptr::read_volatile(self.isr.as_ptr::<u8>()) }
+    }
+
+    /// The IRQ line of the device, if it has one the PIC can deliver
+    pub fn irq(&self) -> Option<u8> {
+        self.irq
+    }
+}
+
+/// Returns the physical address of a memory BAR of `pci`, or `None` for the BARs with I/O ports or without an address
+fn bar_address(pci: &PciDevice, bar: u8) -> Option<u64> {
+    if bar > 5 {
+        return None;
+    }
+
+    let register = 0x10 + bar as u16 * 4;
+    let low = pci.read_config(register);
+
+    // Bit 0 tells whatever the BAR has I/O ports, bits 1 and 2 whatever it's a 64 bits one
+    if low & 1 != 0 {
+        return None;
+    }
+
+    let high = if low >> 1 & 0b11 == 0b10 { pci.read_config(register + 4) as u64 } else { 0 };
+    let address = high << 32 | (low & !0xF) as u64;
+
+    return (address != 0).then_some(address);
+}
@@ -0,0 +1,162 @@
+use core::sync::atomic::{fence, Ordering};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::memory::dma::DmaBuffer;
+use crate::utils::error::KernelError;
+
+/// The most descriptors a queue gets, so the whole queue fits in a page and the free ones fit in a `u64`
+pub const MAX_QUEUE_SIZE: u16 = 64;
+
+/// Where the rings are in the page of the queue, the descriptor table is at its start
+const AVAILABLE_RING_OFFSET: usize = 0x400;
+const USED_RING_OFFSET: usize = 0x800;
+
+/// The flags of a descriptor: the chain goes on with the `next` descriptor, the device writes the buffer
+const DESCRIPTOR_NEXT: u16 = 1 << 0;
+const DESCRIPTOR_WRITE: u16 = 1 << 1;
+
+/// A buffer given to the device as part of a chain
+#[derive(Debug, Copy, Clone)]
+pub struct Buffer {
+    pub address: PhysAddr,
+    pub length: u32,
+    /// Whatever the device writes to the buffer instead of reading it, these must come after the ones it reads
+    pub writable: bool
+}
+
+/// A split virtqueue: the driver puts chains of buffers in the available ring and the device gives them back
+/// through the used ring once it's done with them
+pub struct Virtqueue {
+    index: u16,
+    size: u16,
+    /// The descriptor table and both rings
+    memory: DmaBuffer,
+    /// Where the index of the queue is written to tell the device there are new buffers
+    notify: VirtAddr,
+    /// The descriptors that aren't part of a chain owned by the device, one bit each
+    free: u64,
+    next_available: u16,
+    last_used: u16
+}
+
+impl Virtqueue {
+    /// Allocates a queue of `size` descriptors, at most [`MAX_QUEUE_SIZE`]
+    pub(super) fn new(index: u16, size: u16, notify: VirtAddr) -> Result<Self, KernelError> {
+        let size = size.min(MAX_QUEUE_SIZE);
+
+        Ok(Virtqueue {
+            index,
+            size,
+            memory: DmaBuffer::new(1)?,
+            notify,
+            free: u64::MAX >> (64 - size),
+            next_available: 0,
+            last_used: 0
+        })
+    }
+
+    /// Returns the physical addresses of the descriptor table, the available ring and the used ring
+    pub(super) fn addresses(&self) -> (PhysAddr, PhysAddr, PhysAddr) {
+        let base = self.memory.physical(0);
+        return (base, base + AVAILABLE_RING_OFFSET, base + USED_RING_OFFSET);
+    }
+
+    fn write<T>(&self, offset: usize, value: T) {
+        // The page is mapped for as long as the queue exists, and the device reads it on its own
+        unsafe { core::ptr::write_volatile(self.memory.page(0).add(offset) as *mut T, value) };
+    }
+
+    fn read<T>(&self, offset: usize) -> T {
+        unsafe { core::ptr::read_volatile(self.memory.page(0).add(offset) as *const T) }
+    }
+
+    /// Gives a chain made of `buffers` to the device, returning the ID of the chain that [`Virtqueue::pop_used`]
+    /// returns once the device is done with it. The device is only told with [`Virtqueue::notify`]
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::InvalidArgument`] if `buffers` is empty and [`KernelError::Busy`] if there aren't
+    /// enough free descriptors for it
+    pub fn push(&mut self, buffers: &[Buffer]) -> Result<u16, KernelError> {
+        if buffers.is_empty() {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        if buffers.len() > self.free.count_ones() as usize {
+            return Err(KernelError::Busy);
+        }
+
+        let mut descriptors = [0u16; MAX_QUEUE_SIZE as usize];
+
+        for descriptor in &mut descriptors[..buffers.len()] {
+            *descriptor = self.free.trailing_zeros() as u16;
+            self.free &= !(1 << *descriptor);
+        }
+
+        for (position, buffer) in buffers.iter().enumerate() {
+            let offset = descriptors[position] as usize * 16;
+            let next = (position + 1 < buffers.len()).then(|| descriptors[position + 1]);
+
+            let mut flags = if buffer.writable { DESCRIPTOR_WRITE } else { 0 };
+
+            if next.is_some() {
+                flags |= DESCRIPTOR_NEXT;
+            }
+
+            self.write(offset, buffer.address.as_u64());
+            self.write(offset + 8, buffer.length);
+            self.write(offset + 12, flags);
+            self.write(offset + 14, next.unwrap_or(0));
+        }
+
+        let head = descriptors[0];
+        let slot = (self.next_available % self.size) as usize;
+
+        self.write(AVAILABLE_RING_OFFSET + 4 + slot * 2, head);
+        self.next_available = self.next_available.wrapping_add(1);
+
+        // The device must see the chain before the index that hands it over
+        fence(Ordering::Release);
+        self.write(AVAILABLE_RING_OFFSET + 2, self.next_available);
+
+        return Ok(head);
+    }
+
+    /// Tells the device there are new chains in the available ring
+    pub fn notify(&self) {
+        fence(Ordering::SeqCst);
+        unsafe { core::ptr::write_volatile(self.notify.as_mut_ptr::<u16>(), self.index) };
+    }
+
+    /// Takes the next chain the device is done with, returning its ID and how many bytes the device wrote to it.
+    /// Its descriptors are free again
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if self.read::<u16>(USED_RING_OFFSET + 2) == self.last_used {
+            return None;
+        }
+
+        // The entry is only valid once the index says so
+        fence(Ordering::Acquire);
+
+        let entry = USED_RING_OFFSET + 4 + (self.last_used % self.size) as usize * 8;
+        let head = (self.read::<u32>(entry) % self.size as u32) as u16;
+        let written = self.read::<u32>(entry + 4);
+
+        self.last_used = self.last_used.wrapping_add(1);
+
+        let mut descriptor = head;
+
+        loop {
+            self.free |= 1 << descriptor;
+
+            let offset = descriptor as usize * 16;
+
+            if self.read::<u16>(offset + 12) & DESCRIPTOR_NEXT == 0 {
+                break;
+            }
+
+            descriptor = self.read::<u16>(offset + 14) % self.size;
+        }
+
+        return Some((head, written));
+    }
+}
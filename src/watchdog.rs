@@ -0,0 +1,92 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::structures::idt::InterruptStackFrame;
+use crate::println;
+use crate::time;
+
+/// How long the kernel can go without petting the watchdog before the machine is restarted
+pub const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+
+/// How many times per timeout the thread of the watchdog pets it, so a late wake up doesn't restart the machine
+const PETS_PER_TIMEOUT: u64 = 4;
+
+/// The tick the watchdog fires at unless it's pet before, 0 while it's disabled
+static DEADLINE: AtomicU64 = AtomicU64::new(0);
+
+/// The timeout set by [`enable`], in ticks
+static TIMEOUT_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Starts the countdown, the machine is restarted if [`pet`] isn't called for `timeout_ms` milliseconds
+pub fn enable(timeout_ms: u64) {
+    let timeout = time::ms_to_ticks(timeout_ms).max(1);
+
+    TIMEOUT_TICKS.store(timeout, Ordering::Relaxed);
+    DEADLINE.store(time::ticks() + timeout, Ordering::Release);
+}
+
+/// Stops the countdown, until the next call to [`enable`]
+#[allow(dead_code)]
+pub fn disable() {
+    DEADLINE.store(0, Ordering::Release);
+}
+
+/// Starts the countdown over, telling the watchdog the kernel still makes progress. Does nothing while the
+/// watchdog is disabled
+pub fn pet() {
+    let timeout = TIMEOUT_TICKS.load(Ordering::Relaxed);
+
+    // Disabling the watchdog between the load and the store can't be undone by a pet
+    let _ = DEADLINE.fetch_update(Ordering::AcqRel, Ordering::Acquire, |deadline| {
+        (deadline != 0).then(|| time::ticks() + timeout)
+    });
+}
+
+/// Called by the timer interrupt handlers after counting the tick, it prints what the CPU was doing and restarts
+/// the machine once the countdown runs out
+///
+/// ## Note
+///
+/// This function must not allocate or block since it runs inside an interrupt handler
+pub(crate) fn check(interrupt_stack_frame: &InterruptStackFrame) {
+    let deadline = DEADLINE.load(Ordering::Acquire);
+
+    if deadline == 0 || time::ticks() < deadline {
+        return;
+    }
+
+    let thread = unsafe { crate::sched::current_thread().as_ref() };
+    let timeout_ms = TIMEOUT_TICKS.load(Ordering::Relaxed) * 1000 / time::TICKS_PER_SECOND;
+    let frame = interrupt_stack_frame;
+
+    println!("Watchdog: no progress for {} ms, restarting", timeout_ms);
+    println!("Interrupted at {:?} (CS {:#x})", frame.instruction_pointer, frame.code_segment);
+    println!("Running thread {:?} ({}) on {:?}", thread.id(), thread.name(), crate::sched::current_cpu());
+    println!(
+        "Uptime {} ms, {} context switches, {} ticks idle",
+        time::uptime_ms(), crate::sched::context_switches(), crate::sched::idle_ticks()
+    );
+
+    // The output may still be buffered for the interrupt of the serial port, which won't come anymore
+    crate::serial::flush();
+    crate::power::reboot();
+}
+
+/// The thread that pets the watchdog, as long as it gets to run the scheduler and the timers work
+fn pet_loop() {
+    loop {
+        pet();
+
+        let timeout_ms = TIMEOUT_TICKS.load(Ordering::Relaxed) * 1000 / time::TICKS_PER_SECOND;
+        crate::task::sleep_ms((timeout_ms / PETS_PER_TIMEOUT).max(1));
+    }
+}
+
+/// Enables the watchdog with `timeout_ms` and starts the thread that pets it. Must be called once, after the
+/// scheduler and the timer are running
+pub fn init(timeout_ms: u64) {
+    enable(timeout_ms);
+
+    if let Err(error) = crate::task::spawn_kthread(pet_loop, "watchdog") {
+        disable();
+        println!("Watchdog: failed to start its thread ({:?})", error);
+    }
+}
@@ -0,0 +1,193 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use crate::sched::WaitQueue;
+use crate::utils::error::KernelError;
+use crate::utils::{IrqCell, Mutex};
+use crate::utils::list::{Link, Linked, List};
+
+/// The name of the queue created by [`init`], used by [`schedule`] and [`schedule_fn`]
+const SYSTEM_QUEUE_NAME: &str = "events";
+
+/// Every work queue created so far, the worker of each queue finds its queue here by its name
+static QUEUES: Mutex<Vec<&'static WorkQueue>> = Mutex::new(Vec::new());
+
+/// A closure queued with [`WorkQueue::enqueue_fn`]
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A piece of work that can be queued from any context, interrupt handlers included, since queueing it
+/// never allocates. Meant to be a `static`, queueing it again before it runs does nothing
+pub struct WorkItem {
+    link: Link<WorkItem>,
+    function: fn(),
+    queued: AtomicBool
+}
+
+// The link is only touched with the queue held
+unsafe impl Send for WorkItem {}
+unsafe impl Sync for WorkItem {}
+
+unsafe impl Linked for WorkItem {
+    fn link(&self) -> &Link<Self> {
+        &self.link
+    }
+}
+
+#[allow(dead_code)]
+impl WorkItem {
+    pub const fn new(function: fn()) -> Self {
+        WorkItem {
+            link: Link::new(),
+            function,
+            queued: AtomicBool::new(false)
+        }
+    }
+
+    /// Returns whatever the item is waiting in a queue to run
+    pub fn is_queued(&self) -> bool {
+        self.queued.load(Ordering::Acquire)
+    }
+}
+
+/// A named queue of work run in order by a kernel thread of its own (named after the queue), used to move slow
+/// operations out of interrupt handlers or to run them without holding up the thread that asked for them.
+///
+/// Queues live forever once created
+pub struct WorkQueue {
+    name: &'static str,
+    items: IrqCell<List<WorkItem>>,
+    /// Only touched by threads, so it can allocate
+    jobs: Mutex<VecDeque<Job>>,
+    /// How many jobs are in [`WorkQueue::jobs`], so the worker can check it without taking the lock
+    pending_jobs: AtomicUsize,
+    /// Where the worker waits for work
+    worker: WaitQueue
+}
+
+#[allow(dead_code)]
+impl WorkQueue {
+    /// Creates a queue and starts its worker thread, which is called `name`
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`KernelError::Busy`] if there's already a queue with that name, or the error of spawning the thread
+    pub fn create(name: &'static str) -> Result<&'static WorkQueue, KernelError> {
+        let mut queues = QUEUES.lock();
+
+        if queues.iter().any(|queue| queue.name == name) {
+            return Err(KernelError::Busy);
+        }
+
+        let queue: &'static WorkQueue = Box::leak(Box::new(WorkQueue {
+            name,
+            items: IrqCell::new(List::new()),
+            jobs: Mutex::new(VecDeque::new()),
+            pending_jobs: AtomicUsize::new(0),
+            worker: WaitQueue::new()
+        }));
+
+        // The worker looks for its queue as soon as it starts, so the queue has to be registered first
+        queues.push(queue);
+        drop(queues);
+
+        if let Err(error) = crate::task::spawn_kthread(worker_loop, name) {
+            QUEUES.lock().retain(|other| !core::ptr::eq(*other, queue));
+            return Err(error);
+        }
+
+        return Ok(queue);
+    }
+
+    /// Returns the queue with the given name, if it was created
+    pub fn find(name: &str) -> Option<&'static WorkQueue> {
+        QUEUES.lock().iter().find(|queue| queue.name == name).copied()
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Queues `item` to run on the worker of this queue, returning `false` if it was already queued (in any queue).
+    /// Never blocks nor allocates, so it can be called from interrupt handlers
+    pub fn enqueue(&self, item: &'static WorkItem) -> bool {
+        if item.queued.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+
+        self.items.with(|items| unsafe { items.push_back(item.into()) });
+        self.worker.wake_one();
+
+        return true;
+    }
+
+    /// Queues `job` to run on the worker of this queue. Since the job is moved to the heap this can't be
+    /// called from interrupt handlers, use [`WorkQueue::enqueue`] there
+    pub fn enqueue_fn(&self, job: impl FnOnce() + Send + 'static) {
+        let job: Job = Box::new(job);
+
+        self.jobs.lock().push_back(job);
+        self.pending_jobs.fetch_add(1, Ordering::AcqRel);
+        self.worker.wake_one();
+    }
+
+    /// Runs every item and job queued so far, the items first since they usually come from interrupt handlers
+    fn run_pending(&self) {
+        while let Some(item) = self.items.with(|items| items.pop_front()) {
+            let item = unsafe { item.as_ref() };
+
+            // Cleared before running so the item can queue itself again
+            item.queued.store(false, Ordering::Release);
+            (item.function)();
+        }
+
+        loop {
+            let job = self.jobs.lock().pop_front();
+
+            match job {
+                Some(job) => {
+                    self.pending_jobs.fetch_sub(1, Ordering::AcqRel);
+                    job();
+                },
+                None => return
+            }
+        }
+    }
+
+    /// Never takes a lock, since the worker checks it with interrupts disabled
+    fn has_work(&self) -> bool {
+        self.pending_jobs.load(Ordering::Acquire) > 0 || self.items.with(|items| !items.is_empty())
+    }
+}
+
+/// Creates the queue used by [`schedule`] and [`schedule_fn`], must be called after the scheduler is initialized
+pub fn init() {
+    WorkQueue::create(SYSTEM_QUEUE_NAME).expect("Failed to create the system work queue");
+}
+
+/// Queues `item` on the system queue, see [`WorkQueue::enqueue`]
+#[allow(dead_code)]
+pub fn schedule(item: &'static WorkItem) -> bool {
+    return system().enqueue(item);
+}
+
+/// Queues `job` on the system queue, see [`WorkQueue::enqueue_fn`]
+#[allow(dead_code)]
+pub fn schedule_fn(job: impl FnOnce() + Send + 'static) {
+    system().enqueue_fn(job);
+}
+
+fn system() -> &'static WorkQueue {
+    WorkQueue::find(SYSTEM_QUEUE_NAME).expect("The system work queue wasn't created yet")
+}
+
+/// The entry of the worker thread of every queue, the thread is named after its queue
+fn worker_loop() {
+    let name = unsafe { crate::sched::current_thread().as_ref().name() };
+    let queue = WorkQueue::find(name).expect("A worker thread started without its queue");
+
+    loop {
+        queue.worker.wait_until(|| queue.has_work());
+        queue.run_pending();
+    }
+}
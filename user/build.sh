@@ -0,0 +1,10 @@
+#!/bin/sh
+# Builds the user programs embedded in the kernel, needs the GNU assembler and linker for x86_64.
+# The resulting ELFs are committed, so the kernel can be built without them
+set -e
+
+cd "$(dirname "$0")"
+
+as --64 -o hello.o hello.S
+ld -static -nostdlib --build-id=none -z max-page-size=4096 -z noexecstack -T link.ld -o hello.elf hello.o
+rm hello.o